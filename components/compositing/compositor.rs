@@ -4,28 +4,35 @@
 
 use compositor_layer::{CompositorData, CompositorLayer, WantsScrollEventsFlag};
 use compositor_task::{CompositorEventListener, CompositorProxy, CompositorReceiver};
-use compositor_task::{CompositorTask, LayerProperties, Msg};
+use compositor_task::{CompositorTask, LayerProperties, Msg, discard_shared_tiles};
 use constellation::SendableFrameTree;
+use debug_overlay::{self, OutlinedLayer};
+use direct_frame::DirectFrameRenderer;
+use frame_timer;
+use layer_animation::{self, AnimatedValue};
 use pipeline::CompositionPipeline;
 use scrolling::ScrollingTimerProxy;
 use windowing;
 use windowing::{MouseWindowEvent, WindowEvent, WindowMethods, WindowNavigateMsg};
 
+use geom::length::Length;
 use geom::point::{Point2D, TypedPoint2D};
 use geom::rect::{Rect, TypedRect};
 use geom::scale_factor::ScaleFactor;
 use geom::size::{Size2D, TypedSize2D};
 use gfx::color;
 use gfx::paint_task::Msg as PaintMsg;
-use gfx::paint_task::PaintRequest;
-use gleam::gl::types::{GLint, GLsizei};
+use gfx::paint_task::{DirectPaintRequest, PaintRequest, WorkerPoolConfig};
+use gleam::gl::types::{GLint, GLsizei, GLuint};
 use gleam::gl;
+use layers::color::Color;
 use layers::geometry::{DevicePixel, LayerPixel};
 use layers::layers::{BufferRequest, Layer, LayerBuffer, LayerBufferSet};
 use layers::rendergl::RenderContext;
 use layers::rendergl;
 use layers::scene::Scene;
-use msg::compositor_msg::{Epoch, LayerId};
+use msg::compositor_msg::{DirectFrame, Epoch, ExternalTextureFrame, ExternalTextureId};
+use msg::compositor_msg::{LayerAnimation, LayerId, LayerKind};
 use msg::compositor_msg::{ReadyState, PaintState, ScrollPolicy};
 use msg::constellation_msg::Msg as ConstellationMsg;
 use msg::constellation_msg::{ConstellationChan, NavigationDirection};
@@ -45,7 +52,7 @@ use std::slice::bytes::copy_memory;
 use std::sync::mpsc::Sender;
 use time::{precise_time_ns, precise_time_s};
 use url::Url;
-use util::geometry::{PagePx, ScreenPx, ViewportPx};
+use util::geometry::{Au, PagePx, ScreenPx, ViewportPx};
 use util::opts;
 
 /// NB: Never block on the constellation, because sometimes the constellation blocks on us.
@@ -66,6 +73,10 @@ pub struct IOCompositor<Window: WindowMethods> {
     /// Tracks details about each active pipeline that the compositor knows about.
     pipeline_details: HashMap<PipelineId, PipelineDetails>,
 
+    /// The threads to paint tiles on that the window asked for, if it changed them from those
+    /// given on the command line. Pipelines that come along later are given them too.
+    paint_worker_config: Option<WorkerPoolConfig>,
+
     /// The canvas to paint a page.
     scene: Scene<CompositorData>,
 
@@ -129,10 +140,90 @@ pub struct IOCompositor<Window: WindowMethods> {
     /// Pending scroll events.
     pending_scroll_events: Vec<ScrollEvent>,
 
+    /// Tiles that opaque layers hid, or that were outside the display ports of their layers, when
+    /// they were to be asked for.
+    deferred_tiles: Vec<DeferredTile>,
+
+    /// The direction that the last scroll events moved the viewport in, which the painters paint
+    /// the tiles along first.
+    scroll_direction: TypedPoint2D<LayerPixel, f32>,
+
+    /// Areas of layers that are to be repainted when the animated images in them show their next
+    /// frames.
+    pending_frame_repaints: Vec<FrameRepaint>,
+
+    /// The earliest time at which a frame repaint timer is due to fire, if one is running.
+    frame_timer_deadline: Option<u64>,
+
+    /// The animations of the transforms and opacities of layers that the compositor runs, with
+    /// the pipelines of the layers.
+    layer_animations: Vec<(PipelineId, LayerAnimation)>,
+
+    /// Where the painters have put external textures in their layers.
+    external_texture_placements: Vec<ExternalTexturePlacement>,
+
+    /// The latest frame of each external texture.
+    external_texture_frames: HashMap<ExternalTextureId, ExternalTextureFrame>,
+
+    /// Frames of external textures that have been replaced, which go back to their producers once
+    /// the next composite has been presented, since the last one may still be drawing them.
+    superseded_texture_frames: Vec<ExternalTextureFrame>,
+
+    /// The last frame painted for the window as a whole, with the pipeline it shows and the epoch
+    /// of the display list it was painted from.
+    direct_frame: Option<(PipelineId, Epoch, DirectFrame)>,
+
+    /// The frame last asked for from a painter that hasn't arrived yet, if any.
+    pending_direct_paint: Option<DirectPaintRequest>,
+
+    /// Draws frames painted for the window, or `None` if the GL context can't, in which case every
+    /// page is painted in tiles.
+    direct_frame_renderer: Option<DirectFrameRenderer>,
+
     /// Has a Quit event been seen?
     has_seen_quit_event: bool,
 }
 
+/// Where a layer is composited, in the coordinates of the root layer.
+struct CompositedLayer {
+    layer: Rc<Layer<CompositorData>>,
+    /// Where the top left of the content of the layer is, which its tiles are positioned from.
+    content_origin: TypedPoint2D<LayerPixel, f32>,
+    /// The part of the layer that shows, if any.
+    visible_rect: Option<TypedRect<LayerPixel, f32>>,
+    /// The part of the layer that its tiles are painted in, if any: what shows of it, and what
+    /// scrolling may bring into view soon.
+    display_port: Option<TypedRect<LayerPixel, f32>>,
+}
+
+/// A tile that wasn't asked for because opaque layers hid it or it was outside the display port.
+struct DeferredTile {
+    layer: Rc<Layer<CompositorData>>,
+    /// The epoch of the layer when the tile was to be asked for.
+    epoch: Epoch,
+    request: BufferRequest,
+}
+
+/// Where in a layer an external texture is drawn.
+struct ExternalTexturePlacement {
+    pipeline_id: PipelineId,
+    /// The epoch of the layer that the placement belongs to.
+    epoch: Epoch,
+    layer_id: LayerId,
+    texture: ExternalTextureId,
+    /// Where the texture goes, in px from the top left of the content of the layer.
+    rect: Rect<f32>,
+}
+
+/// A request from a painter to have an area of a layer repainted at a given time.
+struct FrameRepaint {
+    pipeline_id: PipelineId,
+    epoch: Epoch,
+    layer_id: LayerId,
+    rect: Rect<f32>,
+    time: u64,
+}
+
 pub struct ScrollEvent {
     delta: TypedPoint2D<DevicePixel,f32>,
     cursor: TypedPoint2D<DevicePixel,i32>,
@@ -169,6 +260,10 @@ struct PipelineDetails {
 
     /// Whether animations are running.
     animations_running: bool,
+
+    /// The epoch of the last display list that the painter found simple enough to be painted
+    /// straight into the window, if the very last one was.
+    direct_painting_epoch: Option<Epoch>,
 }
 
 impl PipelineDetails {
@@ -178,6 +273,7 @@ impl PipelineDetails {
             ready_state: ReadyState::Blank,
             paint_state: PaintState::Painting,
             animations_running: false,
+            direct_painting_epoch: None,
         }
     }
 }
@@ -202,6 +298,7 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             context: None,
             root_pipeline: None,
             pipeline_details: HashMap::new(),
+            paint_worker_config: None,
             scene: Scene::new(Rect {
                 origin: Point2D::zero(),
                 size: window_size.as_f32(),
@@ -212,6 +309,17 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             scrolling_timer: ScrollingTimerProxy::new(sender),
             composition_request: CompositionRequest::NoCompositingNecessary,
             pending_scroll_events: Vec::new(),
+            deferred_tiles: Vec::new(),
+            scroll_direction: TypedPoint2D(0.0, 0.0),
+            pending_frame_repaints: Vec::new(),
+            layer_animations: Vec::new(),
+            external_texture_placements: Vec::new(),
+            external_texture_frames: HashMap::new(),
+            superseded_texture_frames: Vec::new(),
+            frame_timer_deadline: None,
+            direct_frame: None,
+            pending_direct_paint: None,
+            direct_frame_renderer: None,
             shutdown_state: ShutdownState::NotShuttingDown,
             page_zoom: ScaleFactor::new(1.0),
             viewport_zoom: ScaleFactor::new(1.0),
@@ -293,6 +401,7 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             }
 
             (Msg::PaintMsgDiscarded, ShutdownState::NotShuttingDown) => {
+                self.pending_direct_paint = None;
                 self.remove_outstanding_paint_msg();
             }
 
@@ -332,6 +441,37 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 self.remove_outstanding_paint_msg();
             }
 
+            (Msg::AssignSharedTiles(pipeline_id, _, tiles), ShutdownState::NotShuttingDown) => {
+                for (layer_id, tiles) in tiles.into_iter() {
+                    self.window.shared_tiles_painted(pipeline_id, layer_id, tiles);
+                }
+            }
+
+            // The surfaces of shared tiles are ours, so they must go even if they aren't shown.
+            (Msg::AssignSharedTiles(_, _, tiles), ShutdownState::ShuttingDown) => {
+                discard_shared_tiles(tiles)
+            }
+
+            (Msg::SetDirectPainting(pipeline_id, epoch, direct),
+             ShutdownState::NotShuttingDown) => {
+                // The layers of the new display list follow, and ask for what to paint.
+                self.get_or_create_pipeline_details(pipeline_id).direct_painting_epoch =
+                    if direct { Some(epoch) } else { None };
+            }
+
+            (Msg::AssignDirectFrame(pipeline_id, epoch, mut frame),
+             ShutdownState::NotShuttingDown) => {
+                self.pending_direct_paint = None;
+                if let Some(ref mut renderer) = self.direct_frame_renderer {
+                    renderer.upload(&frame)
+                }
+                // The pixels are in the texture of the renderer now.
+                frame.pixels = Vec::new();
+                self.direct_frame = Some((pipeline_id, epoch, frame));
+                self.remove_outstanding_paint_msg();
+                self.composite_if_necessary(CompositingReason::NewPaintedBuffers);
+            }
+
             (Msg::ScrollFragmentPoint(pipeline_id, layer_id, point),
              ShutdownState::NotShuttingDown) => {
                 self.scroll_fragment_to_point(pipeline_id, layer_id, point);
@@ -369,6 +509,34 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                     CompositionRequest::CompositeNow(CompositingReason::ContinueScroll)
             }
 
+            (Msg::ScheduleFrameRepaint(pipeline_id, epoch, layer_id, rect, time),
+             ShutdownState::NotShuttingDown) => {
+                self.schedule_frame_repaint(FrameRepaint {
+                    pipeline_id: pipeline_id,
+                    epoch: epoch,
+                    layer_id: layer_id,
+                    rect: rect,
+                    time: time,
+                });
+            }
+
+            (Msg::SetLayerAnimations(pipeline_id, animations), ShutdownState::NotShuttingDown) => {
+                self.set_layer_animations(pipeline_id, animations);
+            }
+
+            (Msg::SetExternalTextures(pipeline_id, epoch, textures),
+             ShutdownState::NotShuttingDown) => {
+                self.set_external_textures(pipeline_id, epoch, textures);
+            }
+
+            (Msg::UpdateExternalTexture(texture, frame), ShutdownState::NotShuttingDown) => {
+                self.update_external_texture(texture, frame);
+            }
+
+            (Msg::FrameRepaintTimeout, ShutdownState::NotShuttingDown) => {
+                self.process_frame_repaints();
+            }
+
             (Msg::KeyEvent(key, state, modified), ShutdownState::NotShuttingDown) => {
                 if state == KeyState::Pressed {
                     self.window.handle_key(key, modified);
@@ -383,6 +551,10 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 if self.pipeline_details.remove(&pipeline_id).is_none() {
                     panic!("Saw PaintTaskExited message from an unknown pipeline!");
                 }
+                self.external_texture_placements.retain(|placement| {
+                    placement.pipeline_id != pipeline_id
+                });
+                self.release_unplaced_texture_frames();
             }
 
             // When we are shutting_down, we need to avoid performing operations
@@ -550,6 +722,9 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             rect: Rect::zero(),
             background_color: color::transparent(),
             scroll_policy: ScrollPolicy::Scrollable,
+            scroll: None,
+            kind: LayerKind::Content,
+            is_opaque: false,
         };
 
         let root_layer = CompositorData::new_layer(layer_properties,
@@ -557,6 +732,9 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                                                    opts::get().tile_size);
 
         self.get_or_create_pipeline_details(pipeline.id).pipeline = Some(pipeline.clone());
+        if let Some(ref config) = self.paint_worker_config {
+            let _ = pipeline.paint_chan.send_opt(PaintMsg::ConfigureWorkers(config.clone()));
+        }
 
         // All root layers mask to bounds.
         *root_layer.masks_to_bounds.borrow_mut() = true;
@@ -623,8 +801,12 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             // Add the base layer to the front of the child list, so that child
             // iframe layers are painted on top of the base layer. These iframe
             // layers were added previously when creating the layer tree
-            // skeleton in create_frame_tree_root_layers.
-            root_layer.children().insert(0, base_layer);
+            // skeleton in create_frame_tree_root_layers. Fixed background layers
+            // stay behind the base layer.
+            let index = root_layer.children().iter().take_while(|kid| {
+                kid.extra_data.borrow().scroll_policy == ScrollPolicy::FixedBackground
+            }).count();
+            root_layer.children().insert(index, base_layer);
         }
 
         self.scroll_layer_to_fragment_point_if_necessary(layer_properties.pipeline_id,
@@ -650,7 +832,17 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         let new_layer = CompositorData::new_layer(layer_properties,
                                                   WantsScrollEventsFlag::DoesntWantScrollEvents,
                                                   root_layer.tile_size);
-        root_layer.add_child(new_layer);
+        if layer_properties.scroll_policy == ScrollPolicy::FixedBackground {
+            root_layer.children().insert(0, new_layer);
+        } else if layer_properties.kind == LayerKind::Scrollbar {
+            root_layer.add_child(new_layer);
+        } else {
+            // Keep scrollbars above everything else.
+            let index = root_layer.children().iter().take_while(|kid| {
+                kid.extra_data.borrow().kind != LayerKind::Scrollbar
+            }).count();
+            root_layer.children().insert(index, new_layer);
+        }
     }
 
     fn send_window_size(&self) {
@@ -706,6 +898,92 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         self.composition_request = CompositionRequest::CompositeOnScrollTimeout(timestamp);
     }
 
+    fn schedule_frame_repaint(&mut self, frame_repaint: FrameRepaint) {
+        // Painting the same layer again before the frame is due asks for the same repaint.
+        let existing = self.pending_frame_repaints.iter().position(|pending| {
+            pending.pipeline_id == frame_repaint.pipeline_id &&
+                pending.layer_id == frame_repaint.layer_id &&
+                pending.rect == frame_repaint.rect
+        });
+        match existing {
+            Some(index) => {
+                let pending = &mut self.pending_frame_repaints[index];
+                pending.epoch = frame_repaint.epoch;
+                pending.time = cmp::min(pending.time, frame_repaint.time);
+            }
+            None => self.pending_frame_repaints.push(frame_repaint),
+        }
+        self.start_frame_timer_if_necessary();
+    }
+
+    /// Starts a timer for the earliest pending frame repaint, unless one that fires no later is
+    /// already running.
+    fn start_frame_timer_if_necessary(&mut self) {
+        let earliest = match self.pending_frame_repaints.iter().map(|pending| pending.time).min() {
+            Some(earliest) => earliest,
+            None => return,
+        };
+        match self.frame_timer_deadline {
+            Some(deadline) if deadline <= earliest => return,
+            Some(_) | None => {}
+        }
+        self.frame_timer_deadline = Some(earliest);
+        frame_timer::start(self.channel_to_self.clone_compositor_proxy(), earliest);
+    }
+
+    /// Has the tiles over the animated images that are due to show their next frames repainted.
+    fn process_frame_repaints(&mut self) {
+        let now = precise_time_ns();
+        if self.frame_timer_deadline.map_or(false, |deadline| deadline <= now) {
+            self.frame_timer_deadline = None;
+        }
+
+        let pending_frame_repaints = std_mem::replace(&mut self.pending_frame_repaints, vec!());
+        let (due, pending): (Vec<FrameRepaint>, Vec<FrameRepaint>) =
+            pending_frame_repaints.into_iter().partition(|pending| pending.time <= now);
+        self.pending_frame_repaints = pending;
+
+        let mut repaints: HashMap<PipelineId, (Epoch, Vec<(LayerId, Vec<Rect<Au>>)>)> =
+            HashMap::new();
+        for frame_repaint in due.iter() {
+            let layer = match self.find_layer_with_pipeline_and_layer_id(frame_repaint.pipeline_id,
+                                                                         frame_repaint.layer_id) {
+                Some(layer) => layer,
+                None => continue,
+            };
+
+            // A new epoch means that the layer has been repainted with a new display list since
+            // the request was made, and the painter will have asked again if it still needs to.
+            if layer.extra_data.borrow().epoch != frame_repaint.epoch {
+                continue
+            }
+
+            debug!("repainting {:?} of layer {:?} for the next frame of an animated image",
+                   frame_repaint.rect,
+                   frame_repaint.layer_id);
+            let rect = Rect(Point2D(Au::from_frac32_px(frame_repaint.rect.origin.x),
+                                    Au::from_frac32_px(frame_repaint.rect.origin.y)),
+                            Size2D(Au::from_frac32_px(frame_repaint.rect.size.width),
+                                   Au::from_frac32_px(frame_repaint.rect.size.height)));
+            let &mut (_, ref mut rects) = match repaints.entry(frame_repaint.pipeline_id) {
+                Occupied(entry) => entry.into_mut(),
+                Vacant(entry) => entry.insert((frame_repaint.epoch, Vec::new())),
+            };
+            match rects.iter().position(|&(layer_id, _)| layer_id == frame_repaint.layer_id) {
+                Some(index) => rects[index].1.push(rect),
+                None => rects.push((frame_repaint.layer_id, vec![rect])),
+            }
+        }
+
+        // The painters repaint only the tiles under the images, and the compositor keeps the
+        // others.
+        for (pipeline_id, (epoch, rects)) in repaints.into_iter() {
+            let message = PaintMsg::RepaintAnimatedImages(epoch, rects);
+            let _ = self.get_pipeline(pipeline_id).paint_chan.send_opt(message);
+        }
+        self.start_frame_timer_if_necessary();
+    }
+
     fn set_layer_rect(&mut self,
                       pipeline_id: PipelineId,
                       layer_id: LayerId,
@@ -813,6 +1091,10 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 self.on_key_event(key, state, modifiers);
             }
 
+            WindowEvent::ConfigurePaintWorkers(config) => {
+                self.on_configure_paint_workers_window_event(config);
+            }
+
             WindowEvent::Quit => {
                 if !self.has_seen_quit_event {
                     self.has_seen_quit_event = true;
@@ -825,6 +1107,15 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         }
     }
 
+    fn on_configure_paint_workers_window_event(&mut self, config: WorkerPoolConfig) {
+        for details in self.pipeline_details.values() {
+            if let Some(ref pipeline) = details.pipeline {
+                let _ = pipeline.paint_chan.send_opt(PaintMsg::ConfigureWorkers(config.clone()));
+            }
+        }
+        self.paint_worker_config = Some(config);
+    }
+
     fn on_resize_window_event(&mut self, new_size: TypedSize2D<DevicePixel, u32>) {
         debug!("compositor resizing to {:?}", new_size.to_untyped());
 
@@ -901,6 +1192,8 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 layer.handle_scroll_event(delta, cursor);
             }
 
+            // Scrolling moves the content by the delta, so the viewport moves the other way.
+            self.scroll_direction = -delta;
             self.perform_updates_after_scroll();
         }
 
@@ -909,14 +1202,12 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         }
     }
 
-    /// Performs buffer requests and starts the scrolling timer or schedules a recomposite as
-    /// necessary.
+    /// Asks for the tiles that scrolling brought into view, and schedules a recomposite that
+    /// moves what is painted already to where it has scrolled, without waiting for them.
     fn perform_updates_after_scroll(&mut self) {
-        if self.send_buffer_requests_for_all_layers() {
-            self.start_scrolling_timer_if_necessary();
-        } else {
-            self.channel_to_self.send(Msg::RecompositeAfterScroll);
-        }
+        let low_res_prepaint = opts::get().low_res_prepaint && !self.has_paint_msg_tracking();
+        self.send_buffer_requests(low_res_prepaint);
+        self.channel_to_self.send(Msg::RecompositeAfterScroll);
     }
 
     /// If there are any animations running, dispatches appropriate messages to the constellation.
@@ -927,6 +1218,115 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             }
             self.constellation_chan.0.send(ConstellationMsg::TickAnimation(*pipeline_id)).unwrap();
         }
+
+        if self.apply_layer_animations() {
+            self.composite_if_necessary(CompositingReason::Animation);
+        }
+    }
+
+    /// Replaces where the external textures of the pipeline go, as the painter found them in its
+    /// layers for the given epoch. Frames of textures that no layer shows any more go back.
+    fn set_external_textures(&mut self,
+                             pipeline_id: PipelineId,
+                             epoch: Epoch,
+                             textures: Vec<(LayerId, Vec<(ExternalTextureId, Rect<f32>)>)>) {
+        self.external_texture_placements.retain(|placement| placement.pipeline_id != pipeline_id);
+        for (layer_id, layer_textures) in textures.into_iter() {
+            for (texture, rect) in layer_textures.into_iter() {
+                self.external_texture_placements.push(ExternalTexturePlacement {
+                    pipeline_id: pipeline_id,
+                    epoch: epoch,
+                    layer_id: layer_id,
+                    texture: texture,
+                    rect: rect,
+                })
+            }
+        }
+        self.release_unplaced_texture_frames();
+        self.composite_if_necessary(CompositingReason::ExternalTextures);
+    }
+
+    fn update_external_texture(&mut self, texture: ExternalTextureId, frame: ExternalTextureFrame) {
+        let placed = self.external_texture_placements.iter().any(|placement| {
+            placement.texture == texture
+        });
+        if let Some(old_frame) = self.external_texture_frames.insert(texture, frame) {
+            self.superseded_texture_frames.push(old_frame)
+        }
+        // Composite even if the texture doesn't show, so that the frame it replaced goes back.
+        if placed || !self.superseded_texture_frames.is_empty() {
+            self.composite_if_necessary(CompositingReason::ExternalTextures);
+        }
+    }
+
+    /// Gives the frames of the external textures that no layer shows any more back to their
+    /// producers, once they are off the screen.
+    fn release_unplaced_texture_frames(&mut self) {
+        let unplaced: Vec<ExternalTextureId> = {
+            let placements = &self.external_texture_placements;
+            self.external_texture_frames.keys().filter(|&&texture| {
+                !placements.iter().any(|placement| placement.texture == texture)
+            }).cloned().collect()
+        };
+        for texture in unplaced.into_iter() {
+            if let Some(frame) = self.external_texture_frames.remove(&texture) {
+                self.superseded_texture_frames.push(frame)
+            }
+        }
+        if !self.superseded_texture_frames.is_empty() {
+            self.composite_if_necessary(CompositingReason::ExternalTextures);
+        }
+    }
+
+    /// Replaces the animations of the layers of the pipeline. Layers whose animations are gone
+    /// go back to how they were painted.
+    fn set_layer_animations(&mut self, pipeline_id: PipelineId, animations: Vec<LayerAnimation>) {
+        let old_animations = std_mem::replace(&mut self.layer_animations, Vec::new());
+        for (old_pipeline_id, animation) in old_animations.into_iter() {
+            if old_pipeline_id != pipeline_id {
+                self.layer_animations.push((old_pipeline_id, animation));
+                continue
+            }
+            if let Some(layer) = self.find_layer_with_pipeline_and_layer_id(pipeline_id,
+                                                                            animation.layer_id) {
+                layer.extra_data.borrow_mut().animated_transform = None;
+                *layer.opacity.borrow_mut() = 1.0;
+                layer.update_transform();
+            }
+        }
+        self.layer_animations.extend(animations.into_iter().map(|animation| {
+            (pipeline_id, animation)
+        }));
+
+        if self.apply_layer_animations() {
+            self.composite_if_necessary(CompositingReason::Animation);
+        }
+    }
+
+    /// Sets the layers that are animated to what their animations set them to now. Returns true
+    /// if any of the animations are still running. Those that have ended keep their layers as
+    /// they left them until the pipeline sends animations without them, since layout may not
+    /// have painted what they ended on yet.
+    fn apply_layer_animations(&mut self) -> bool {
+        let now = precise_time_s();
+        let mut running = false;
+        for &(pipeline_id, ref animation) in self.layer_animations.iter() {
+            running |= animation.end_time > now;
+            let layer = match self.find_layer_with_pipeline_and_layer_id(pipeline_id,
+                                                                         animation.layer_id) {
+                Some(layer) => layer,
+                None => continue,
+            };
+            match layer_animation::value_at(animation, now) {
+                Some(AnimatedValue::Transform(transform)) => {
+                    layer.extra_data.borrow_mut().animated_transform = Some(transform);
+                    layer.update_transform();
+                }
+                Some(AnimatedValue::Opacity(opacity)) => *layer.opacity.borrow_mut() = opacity,
+                None => {}
+            }
+        }
+        running
     }
 
     fn device_pixels_per_screen_px(&self) -> ScaleFactor<ScreenPx, DevicePixel, f32> {
@@ -939,6 +1339,9 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         }
     }
 
+    /// Returns how many device pixels a px of the page covers. Tiles and frames are asked for at
+    /// this resolution, which the painter puts at the root of the transform that it paints
+    /// stacking contexts with, so pinch zooming only takes painting again, and no layout.
     fn device_pixels_per_page_px(&self) -> ScaleFactor<PagePx, DevicePixel, f32> {
         self.viewport_zoom * self.page_zoom * self.device_pixels_per_screen_px()
     }
@@ -983,7 +1386,8 @@ impl<Window: WindowMethods> IOCompositor<Window> {
             None => { }
         }
 
-        self.send_viewport_rects_for_all_layers();
+        // Scale what is painted for now. Layout hears about the new viewport, and what is in view
+        // is painted again at the new resolution, once the zoom settles.
         self.composite_if_necessary(CompositingReason::Zoom);
     }
 
@@ -1003,9 +1407,12 @@ impl<Window: WindowMethods> IOCompositor<Window> {
 
     fn convert_buffer_requests_to_pipeline_requests_map(&self,
                                                         requests: Vec<(Rc<Layer<CompositorData>>,
-                                                                       Vec<BufferRequest>)>)
+                                                                       Vec<BufferRequest>)>,
+                                                        composited_layers: &[CompositedLayer],
+                                                        low_res_prepaint: bool)
                                                         -> HashMap<PipelineId, Vec<PaintRequest>> {
         let scale = self.device_pixels_per_page_px();
+        let empty_rect = Rect(Point2D(0.0, 0.0), Size2D(0.0, 0.0));
         let mut results: HashMap<PipelineId, Vec<PaintRequest>> = HashMap::new();
 
         for (layer, mut layer_requests) in requests.into_iter() {
@@ -1025,11 +1432,23 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 request.page_rect = request.page_rect / scale.get();
             }
 
+            // Layer pixels are page pixels, so the part of the layer in view is in page
+            // coordinates already, once it is relative to the content of the layer.
+            let viewport = composited_layers.iter().find(|composited_layer| {
+                same_layer(&composited_layer.layer, &layer)
+            }).and_then(|composited_layer| {
+                composited_layer.visible_rect
+                                .map(|rect| rect.translate(&-composited_layer.content_origin))
+            });
+
             vec.push(PaintRequest {
                 buffer_requests: layer_requests,
                 scale: scale.get(),
                 layer_id: layer.extra_data.borrow().id,
                 epoch: layer.extra_data.borrow().epoch,
+                viewport: viewport.map(|rect| rect.to_untyped()).unwrap_or(empty_rect),
+                scroll_direction: self.scroll_direction.to_untyped(),
+                low_res_prepaint: low_res_prepaint,
             });
         }
 
@@ -1068,8 +1487,114 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         }
     }
 
+    /// Returns the root layer if the scene is simple enough for its pipeline to be painted
+    /// straight into the window instead of into tiles: it is the only layer, the painter found its
+    /// current display list simple enough, and no animations are running in it. The GL context
+    /// must also be able to draw frames of the size that the window needs.
+    fn layer_to_paint_directly(&self) -> Option<Rc<Layer<CompositorData>>> {
+        match self.direct_frame_renderer {
+            Some(ref renderer) if renderer.can_draw(&self.direct_frame_size()) => {}
+            _ => return None,
+        }
+        let root = match self.scene.root {
+            Some(ref root) if root.children().is_empty() => root.clone(),
+            _ => return None,
+        };
+        let pipeline_id = root.extra_data.borrow().pipeline_id;
+        match self.pipeline_details.get(&pipeline_id) {
+            Some(details) if !details.animations_running &&
+                             details.direct_painting_epoch ==
+                                Some(root.extra_data.borrow().epoch) => Some(root),
+            _ => None,
+        }
+    }
+
+    /// Returns the part of the page of the layer that the window shows, in page coordinates.
+    fn direct_painting_viewport(&self, layer: &Layer<CompositorData>) -> Rect<f32> {
+        let scale = self.device_pixels_per_page_px().get();
+        let scroll_offset = layer.extra_data.borrow().scroll_offset.to_untyped();
+        Rect(Point2D(-scroll_offset.x / scale, -scroll_offset.y / scale),
+             Size2D(self.window_size.width.get() as f32 / scale,
+                    self.window_size.height.get() as f32 / scale))
+    }
+
+    /// Returns the margin that frames painted for the window reach past it on each side, in device
+    /// pixels.
+    fn direct_frame_margin(&self) -> f32 {
+        (self.window_size.height.get() as f32 * opts::get().display_port_margin).round()
+    }
+
+    /// Returns the size of the frames painted for the window, in device pixels.
+    fn direct_frame_size(&self) -> Size2D<usize> {
+        let margin = self.direct_frame_margin() as usize;
+        Size2D(self.window_size.width.get() as usize + margin * 2,
+               self.window_size.height.get() as usize + margin * 2)
+    }
+
+    /// Asks the painter of the layer for a frame around the part of its page in view, unless the
+    /// last frame it painted of its current display list, or the one it is painting, covers that
+    /// already. Returns true if it asked.
+    ///
+    /// Frames reach the display port margin past the window on each side, so that scrolling moves
+    /// the frame there is until the next one is needed. While the page is being pinch zoomed,
+    /// frames at any resolution do: they are scaled until the zoom settles, and painted again at
+    /// the new resolution then.
+    fn send_direct_paint_request(&mut self, layer: Rc<Layer<CompositorData>>) -> bool {
+        let scale = self.device_pixels_per_page_px().get();
+        let epoch = layer.extra_data.borrow().epoch;
+        let pipeline_id = layer.get_pipeline_id();
+        let viewport = self.direct_painting_viewport(&*layer);
+
+        let covered = match self.direct_frame {
+            Some((frame_pipeline_id, frame_epoch, ref frame)) => {
+                frame_pipeline_id == pipeline_id && frame_epoch == epoch &&
+                    (frame.resolution == scale || self.zoom_action) &&
+                    rect_contains_rect(&frame.page_rect, &viewport)
+            }
+            None => false,
+        };
+        let pending = match self.pending_direct_paint {
+            Some(ref request) => {
+                request.epoch == epoch && (request.scale == scale || self.zoom_action) &&
+                    rect_contains_rect(&request.page_rect, &viewport)
+            }
+            None => false,
+        };
+        if covered || pending {
+            return false
+        }
+
+        let margin = self.direct_frame_margin();
+        let size = self.direct_frame_size();
+        let request = DirectPaintRequest {
+            page_rect: Rect(Point2D(viewport.origin.x - margin / scale,
+                                    viewport.origin.y - margin / scale),
+                            Size2D(size.width as f32 / scale, size.height as f32 / scale)),
+            size: size,
+            scale: scale,
+            epoch: epoch,
+        };
+
+        let _ = self.get_pipeline(pipeline_id).paint_chan.send_opt(PaintMsg::PaintDirect(
+            request.clone()));
+        self.pending_direct_paint = Some(request);
+        self.add_outstanding_paint_msg(1);
+        true
+    }
+
     /// Returns true if any buffer requests were sent or false otherwise.
     fn send_buffer_requests_for_all_layers(&mut self) -> bool {
+        self.send_buffer_requests(false)
+    }
+
+    /// Asks for the tiles that all layers need. If `low_res_prepaint` is true, the painter sends
+    /// them at low resolution before painting them properly. Returns true if any buffer requests
+    /// were sent or false otherwise.
+    fn send_buffer_requests(&mut self, low_res_prepaint: bool) -> bool {
+        if let Some(layer) = self.layer_to_paint_directly() {
+            return self.send_direct_paint_request(layer)
+        }
+
         let mut layers_and_requests = Vec::new();
         let mut unused_buffers = Vec::new();
         self.scene.get_buffer_requests(&mut layers_and_requests, &mut unused_buffers);
@@ -1077,6 +1602,10 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         // Return unused tiles first, so that they can be reused by any new BufferRequests.
         self.send_back_unused_buffers(unused_buffers);
 
+        let composited_layers = self.composited_layers();
+        let layers_and_requests = self.defer_hidden_tiles(layers_and_requests,
+                                                          &composited_layers);
+
         if layers_and_requests.len() == 0 {
             return false;
         }
@@ -1084,7 +1613,9 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         // We want to batch requests for each pipeline to avoid race conditions
         // when handling the resulting BufferRequest responses.
         let pipeline_requests =
-            self.convert_buffer_requests_to_pipeline_requests_map(layers_and_requests);
+            self.convert_buffer_requests_to_pipeline_requests_map(layers_and_requests,
+                                                                  &composited_layers,
+                                                                  low_res_prepaint);
 
         let mut num_paint_msgs_sent = 0;
         for (pipeline_id, requests) in pipeline_requests.into_iter() {
@@ -1096,6 +1627,75 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         true
     }
 
+    /// Leaves out the requests for tiles that opaque layers composited over them hide completely,
+    /// and for those outside the display ports of their layers, so that they are neither painted
+    /// nor composited. They are kept, and asked for along with the next requests once they show,
+    /// unless their layers have new display lists or have gone by then.
+    fn defer_hidden_tiles(&mut self,
+                          layers_and_requests: Vec<(Rc<Layer<CompositorData>>,
+                                                    Vec<BufferRequest>)>,
+                          composited_layers: &[CompositedLayer])
+                          -> Vec<(Rc<Layer<CompositorData>>, Vec<BufferRequest>)> {
+        let mut layers_and_requests = layers_and_requests;
+        for tile in std_mem::replace(&mut self.deferred_tiles, Vec::new()).into_iter() {
+            if tile.layer.extra_data.borrow().epoch != tile.epoch ||
+                    !composited_layers.iter().any(|composited_layer| {
+                        same_layer(&composited_layer.layer, &tile.layer)
+                    }) {
+                continue
+            }
+            let index = layers_and_requests.iter().position(|&(ref layer, _)| {
+                same_layer(layer, &tile.layer)
+            });
+            match index {
+                Some(index) => {
+                    let requests = &mut layers_and_requests[index].1;
+                    if !requests.iter().any(|request| {
+                        request.screen_rect == tile.request.screen_rect
+                    }) {
+                        requests.push(tile.request)
+                    }
+                }
+                None => layers_and_requests.push((tile.layer, vec!(tile.request))),
+            }
+        }
+
+        // The requests are in device pixels.
+        let scale = self.device_pixels_per_page_px().get();
+        let mut result = Vec::new();
+        for (layer, requests) in layers_and_requests.into_iter() {
+            let occluding_rects: Vec<Rect<f32>> = occluding_rects(composited_layers, &layer)
+                .iter()
+                .map(|rect| *rect * scale)
+                .collect();
+            let display_port = display_port(composited_layers, &layer).map(|rect| rect * scale);
+            let epoch = layer.extra_data.borrow().epoch;
+            let mut shown_requests = Vec::new();
+            for request in requests.into_iter() {
+                let outside_display_port = match display_port {
+                    Some(ref display_port) => !display_port.intersects(&request.page_rect),
+                    None => true,
+                };
+                if outside_display_port ||
+                        occluding_rects.iter().any(|rect| {
+                            rect_contains_rect(rect, &request.page_rect)
+                        }) {
+                    self.deferred_tiles.push(DeferredTile {
+                        layer: layer.clone(),
+                        epoch: epoch,
+                        request: request,
+                    })
+                } else {
+                    shown_requests.push(request)
+                }
+            }
+            if !shown_requests.is_empty() {
+                result.push((layer, shown_requests))
+            }
+        }
+        result
+    }
+
     fn is_ready_to_paint_image_output(&self) -> bool {
         if !self.got_load_complete_message {
             return false;
@@ -1159,8 +1759,29 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 size: self.window_size.as_f32(),
             };
 
-            // Paint the scene.
-            if let Some(ref layer) = self.scene.root {
+            // Put a frame painted for the window straight into it if the scene is still simple
+            // enough for that, or paint the scene. The frame is moved by how far the page has
+            // scrolled since it was painted.
+            let direct_frame = match (self.layer_to_paint_directly(),
+                                      &self.direct_frame,
+                                      &self.direct_frame_renderer) {
+                (Some(ref layer), &Some((pipeline_id, _, ref frame)), &Some(ref renderer))
+                        if layer.get_pipeline_id() == pipeline_id => {
+                    Some((layer.clone(), frame, renderer))
+                }
+                _ => None,
+            };
+            if let Some((layer, frame, renderer)) = direct_frame {
+                let framebuffer = framebuffer_ids.get(0).cloned().unwrap_or(0);
+                let viewport = self.direct_painting_viewport(&*layer);
+                draw_direct_frame(renderer,
+                                  frame,
+                                  framebuffer,
+                                  &self.window_size,
+                                  &viewport,
+                                  self.device_pixels_per_page_px().get(),
+                                  &*layer.background_color.borrow());
+            } else if let Some(ref layer) = self.scene.root {
                 match self.context {
                     Some(context) => rendergl::render_scene(layer.clone(), context, &self.scene),
                     None => {
@@ -1169,6 +1790,10 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                 }
             }
         });
+        self.draw_external_textures(framebuffer_ids.get(0).cloned().unwrap_or(0));
+        if opts::get().show_debug_layer_borders {
+            self.draw_layer_borders(framebuffer_ids.get(0).cloned().unwrap_or(0));
+        }
 
         if output_image {
             let path = opts::get().output_file.as_ref().unwrap();
@@ -1208,6 +1833,9 @@ impl<Window: WindowMethods> IOCompositor<Window> {
 
         // Perform the page flip. This will likely block for a while.
         self.window.present();
+        for frame in std_mem::replace(&mut self.superseded_texture_frames, Vec::new()).into_iter() {
+            frame.release()
+        }
 
         self.last_composite_time = precise_time_ns();
 
@@ -1216,6 +1844,133 @@ impl<Window: WindowMethods> IOCompositor<Window> {
         self.process_animations();
     }
 
+    /// Returns the layers of the scene in the order that they are composited in.
+    fn composited_layers(&self) -> Vec<CompositedLayer> {
+        let mut composited_layers = Vec::new();
+        if let Some(ref root) = self.scene.root {
+            let root_bounds = *root.bounds.borrow();
+            collect_composited_layers(root,
+                                      &TypedPoint2D(0.0, 0.0),
+                                      &root_bounds,
+                                      &root_bounds,
+                                      &mut composited_layers);
+        }
+        composited_layers
+    }
+
+    /// Draws the latest frames of the external textures into the layers that show them, as if
+    /// each were composited with its layer: the opaque layers composited after it, which include
+    /// its descendants and the layers stacked over it, are left showing over the texture.
+    /// Translucent layers over it are still drawn under it. Textures are drawn upright, so the
+    /// transforms of the layers aren't taken into account, and are taken to be drawn with
+    /// OpenGL, with their bottom row first.
+    fn draw_external_textures(&self, framebuffer: GLuint) {
+        if self.external_texture_placements.is_empty() {
+            return
+        }
+
+        let composited_layers = self.composited_layers();
+        let scale = self.device_pixels_per_page_px().get();
+        let window_height = self.window_size.height.get() as GLint;
+        let read_framebuffers = gl::gen_framebuffers(1);
+        for placement in self.external_texture_placements.iter() {
+            let frame = match self.external_texture_frames.get(&placement.texture) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let index = composited_layers.iter().position(|composited_layer| {
+                let extra_data = composited_layer.layer.extra_data.borrow();
+                extra_data.pipeline_id == placement.pipeline_id &&
+                    extra_data.id == placement.layer_id &&
+                    extra_data.epoch == placement.epoch
+            });
+            let (index, content_origin, visible_rect) = match index {
+                Some(index) => match composited_layers[index] {
+                    CompositedLayer {
+                        content_origin,
+                        visible_rect: Some(visible_rect),
+                        ..
+                    } => (index, content_origin, visible_rect.to_untyped()),
+                    _ => continue,
+                },
+                None => continue,
+            };
+            let rect = placement.rect.translate(&Point2D(content_origin.x.get(),
+                                                         content_origin.y.get()));
+            let shown = match rect.intersection(&visible_rect) {
+                Some(shown) if !rect.is_empty() => shown,
+                _ => continue,
+            };
+
+            // Leave out what the opaque layers composited after the texture's layer cover.
+            let covering_rects = occluding_rects(&composited_layers[..],
+                                                 &composited_layers[index].layer);
+            let pieces = covering_rects.iter().fold(vec!(shown), |pieces, covering_rect| {
+                let covering_rect = covering_rect.translate(&Point2D(content_origin.x.get(),
+                                                                     content_origin.y.get()));
+                pieces.iter().flat_map(|piece| {
+                    subtract_rect(piece, &covering_rect).into_iter()
+                }).collect()
+            });
+
+            gl::bind_framebuffer(gl::READ_FRAMEBUFFER, read_framebuffers[0]);
+            gl::framebuffer_texture_2d(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0,
+                                       gl::TEXTURE_2D, frame.texture, 0);
+            gl::bind_framebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+            let (width, height) = (frame.size.width as f32, frame.size.height as f32);
+            for piece in pieces.iter() {
+                // The part of the texture under the piece, with rows going up from the bottom.
+                let source_left = (piece.origin.x - rect.origin.x) / rect.size.width * width;
+                let source_right = (piece.max_x() - rect.origin.x) / rect.size.width * width;
+                let source_bottom = (rect.max_y() - piece.max_y()) / rect.size.height * height;
+                let source_top = (rect.max_y() - piece.origin.y) / rect.size.height * height;
+                unsafe {
+                    gl::BlitFramebuffer(source_left.round() as GLint,
+                                        source_bottom.round() as GLint,
+                                        source_right.round() as GLint,
+                                        source_top.round() as GLint,
+                                        (piece.origin.x * scale).round() as GLint,
+                                        window_height - (piece.max_y() * scale).round() as GLint,
+                                        (piece.max_x() * scale).round() as GLint,
+                                        window_height - (piece.origin.y * scale).round() as GLint,
+                                        gl::COLOR_BUFFER_BIT,
+                                        gl::LINEAR);
+                }
+            }
+        }
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::delete_frame_buffers(&read_framebuffers);
+    }
+
+    /// Outlines the layers that show and their tiles over everything else.
+    fn draw_layer_borders(&self, framebuffer: GLuint) {
+        let scale = self.device_pixels_per_page_px().get();
+        let layer_painted_directly = self.layer_to_paint_directly();
+        let layers: Vec<OutlinedLayer> =
+            self.composited_layers().iter().filter_map(|composited_layer| {
+                let visible_rect = match composited_layer.visible_rect {
+                    Some(visible_rect) => visible_rect.to_untyped(),
+                    None => return None,
+                };
+                let layer = &composited_layer.layer;
+                let tiled = match layer_painted_directly {
+                    Some(ref layer_painted_directly) => !same_layer(layer, layer_painted_directly),
+                    None => true,
+                };
+                Some(OutlinedLayer {
+                    id: layer.extra_data.borrow().id,
+                    visible_rect: Rect(Point2D(visible_rect.origin.x * scale,
+                                               visible_rect.origin.y * scale),
+                                       Size2D(visible_rect.size.width * scale,
+                                              visible_rect.size.height * scale)),
+                    tile_origin: Point2D(composited_layer.content_origin.x.get() * scale,
+                                         composited_layer.content_origin.y.get() * scale),
+                    tile_size: if tiled { Some(layer.tile_size as f32) } else { None },
+                })
+            }).collect();
+        debug_overlay::draw(&layers, framebuffer, self.window_size.height.get() as i32);
+    }
+
     fn composite_if_necessary(&mut self, reason: CompositingReason) {
         if self.composition_request == CompositionRequest::NoCompositingNecessary {
             self.composition_request = CompositionRequest::CompositeNow(reason)
@@ -1225,7 +1980,8 @@ impl<Window: WindowMethods> IOCompositor<Window> {
     fn initialize_compositing(&mut self) {
         let context = CompositorTask::create_graphics_context(&self.window.native_metadata());
         let show_debug_borders = opts::get().show_debug_borders;
-        self.context = Some(rendergl::RenderContext::new(context, show_debug_borders))
+        self.context = Some(rendergl::RenderContext::new(context, show_debug_borders));
+        self.direct_frame_renderer = DirectFrameRenderer::new();
     }
 
     fn find_topmost_layer_at_point_for_layer(&self,
@@ -1233,6 +1989,11 @@ impl<Window: WindowMethods> IOCompositor<Window> {
                                              point: TypedPoint2D<LayerPixel, f32>,
                                              clip_rect: &TypedRect<LayerPixel, f32>)
                                              -> Option<HitTestResult> {
+        // Overlays let mouse events through to what is under them.
+        if layer.extra_data.borrow().kind == LayerKind::Overlay {
+            return None;
+        }
+
         let layer_bounds = *layer.bounds.borrow();
         let masks_to_bounds = *layer.masks_to_bounds.borrow();
         if layer_bounds.is_empty() && masks_to_bounds {
@@ -1300,6 +2061,143 @@ impl<Window: WindowMethods> IOCompositor<Window> {
     }
 }
 
+/// Draws the part of a frame painted for the window that the viewport, in page coordinates, shows
+/// into the framebuffer, at `scale` device pixels per px. What the frame doesn't reach is filled
+/// with the background color.
+fn draw_direct_frame(renderer: &DirectFrameRenderer,
+                     frame: &DirectFrame,
+                     framebuffer: GLuint,
+                     window_size: &TypedSize2D<DevicePixel, u32>,
+                     viewport: &Rect<f32>,
+                     scale: f32,
+                     background_color: &Color) {
+    // Where the frame goes in the window. While the page is being pinch zoomed, frames painted
+    // at the resolution before are scaled until they are painted at the new one.
+    let frame_scale = scale / frame.resolution;
+    let rect = Rect(Point2D(((frame.page_rect.origin.x - viewport.origin.x) * scale).round(),
+                            ((frame.page_rect.origin.y - viewport.origin.y) * scale).round()),
+                    Size2D((frame.size.width as f32 * frame_scale).round(),
+                           (frame.size.height as f32 * frame_scale).round()));
+    renderer.draw(framebuffer,
+                  &Size2D(window_size.width.get() as i32, window_size.height.get() as i32),
+                  &rect,
+                  background_color);
+}
+
+/// Returns true if `outer` contains all of `inner`.
+fn rect_contains_rect(outer: &Rect<f32>, inner: &Rect<f32>) -> bool {
+    outer.origin.x <= inner.origin.x && outer.origin.y <= inner.origin.y &&
+        outer.max_x() >= inner.max_x() && outer.max_y() >= inner.max_y()
+}
+
+/// Returns the parts of `rect` outside `hole`, as up to four rects: the bands above and below the
+/// hole across the whole of `rect`, and those to its left and right.
+fn subtract_rect(rect: &Rect<f32>, hole: &Rect<f32>) -> Vec<Rect<f32>> {
+    let hole = match rect.intersection(hole) {
+        Some(hole) if !hole.is_empty() => hole,
+        _ => return vec!(*rect),
+    };
+    let bands = [
+        Rect(rect.origin, Size2D(rect.size.width, hole.origin.y - rect.origin.y)),
+        Rect(Point2D(rect.origin.x, hole.max_y()),
+             Size2D(rect.size.width, rect.max_y() - hole.max_y())),
+        Rect(Point2D(rect.origin.x, hole.origin.y),
+             Size2D(hole.origin.x - rect.origin.x, hole.size.height)),
+        Rect(Point2D(hole.max_x(), hole.origin.y),
+             Size2D(rect.max_x() - hole.max_x(), hole.size.height)),
+    ];
+    bands.iter().filter(|band| !band.is_empty()).cloned().collect()
+}
+
+/// Adds the layer and its descendants to `composited_layers`, in the order that they are
+/// composited in. `content_origin` is where the content of the parent of the layer is, `clip_rect`
+/// what of it shows and `display_port_clip` what of it is painted, in the coordinates of the root
+/// layer.
+fn collect_composited_layers(layer: &Rc<Layer<CompositorData>>,
+                             content_origin: &TypedPoint2D<LayerPixel, f32>,
+                             clip_rect: &TypedRect<LayerPixel, f32>,
+                             display_port_clip: &TypedRect<LayerPixel, f32>,
+                             composited_layers: &mut Vec<CompositedLayer>) {
+    let layer_bounds = layer.bounds.borrow().translate(content_origin);
+    let visible_rect = clip_rect.intersection(&layer_bounds);
+    let content_origin = layer_bounds.origin + *layer.content_offset.borrow();
+
+    // Layers that mask to their bounds scroll their content through them, so their display ports
+    // reach past them, by a part of how much of them is painted.
+    let masks_to_bounds = *layer.masks_to_bounds.borrow();
+    let display_port = display_port_clip.intersection(&layer_bounds).map(|rect| {
+        if !masks_to_bounds {
+            return rect
+        }
+        let margin = Length::new(rect.size.height.get() * opts::get().display_port_margin);
+        Rect(rect.origin - Point2D(margin, margin),
+             rect.size + Size2D(margin + margin, margin + margin))
+    });
+
+    composited_layers.push(CompositedLayer {
+        layer: layer.clone(),
+        content_origin: content_origin,
+        visible_rect: visible_rect,
+        display_port: display_port,
+    });
+
+    let (clip_rect, display_port_clip) = if masks_to_bounds {
+        match (visible_rect, display_port) {
+            (Some(visible_rect), Some(display_port)) => (visible_rect, display_port),
+            (None, Some(display_port)) => {
+                (Rect(layer_bounds.origin, Size2D(Length::new(0.0), Length::new(0.0))),
+                 display_port)
+            }
+            (_, None) => return,
+        }
+    } else {
+        (*clip_rect, *display_port_clip)
+    };
+    for child in layer.children().iter() {
+        collect_composited_layers(child,
+                                  &content_origin,
+                                  &clip_rect,
+                                  &display_port_clip,
+                                  composited_layers);
+    }
+}
+
+/// Returns the display port of `layer`, relative to its content, if it has one.
+fn display_port(composited_layers: &[CompositedLayer], layer: &Rc<Layer<CompositorData>>)
+                -> Option<Rect<f32>> {
+    composited_layers.iter().find(|composited_layer| {
+        same_layer(&composited_layer.layer, layer)
+    }).and_then(|composited_layer| {
+        composited_layer.display_port.map(|rect| {
+            rect.translate(&-composited_layer.content_origin).to_untyped()
+        })
+    })
+}
+
+/// Returns the rects, relative to the content of `layer`, that opaque layers composited over it
+/// hide. Root layers of pipelines only take their background color from the layers of their
+/// pages, which may not cover them, so they hide nothing.
+fn occluding_rects(composited_layers: &[CompositedLayer], layer: &Rc<Layer<CompositorData>>)
+                   -> Vec<Rect<f32>> {
+    let index = match composited_layers.iter().position(|composited_layer| {
+        same_layer(&composited_layer.layer, layer)
+    }) {
+        Some(index) => index,
+        None => return Vec::new(),
+    };
+    let content_origin = composited_layers[index].content_origin;
+    composited_layers[index + 1..].iter().filter(|composited_layer| {
+        let data = composited_layer.layer.extra_data.borrow();
+        data.is_opaque && data.id != LayerId::null()
+    }).filter_map(|composited_layer| {
+        composited_layer.visible_rect.map(|rect| rect.translate(&-content_origin).to_untyped())
+    }).collect()
+}
+
+fn same_layer(a: &Rc<Layer<CompositorData>>, b: &Rc<Layer<CompositorData>>) -> bool {
+    &**a as *const Layer<CompositorData> == &**b as *const Layer<CompositorData>
+}
+
 fn find_layer_with_pipeline_and_layer_id_for_layer(layer: Rc<Layer<CompositorData>>,
                                                    pipeline_id: PipelineId,
                                                    layer_id: LayerId)
@@ -1350,6 +2248,7 @@ impl<Window> CompositorEventListener for IOCompositor<Window> where Window: Wind
             self.zoom_action = false;
             self.scene.mark_layer_contents_as_changed_recursively();
             self.send_buffer_requests_for_all_layers();
+            self.send_viewport_rects_for_all_layers();
         }
 
         match self.composition_request {
@@ -1435,5 +2334,7 @@ pub enum CompositingReason {
     NewPaintedBuffers,
     /// The window has been zoomed.
     Zoom,
+    /// External textures have new frames or have moved.
+    ExternalTextures,
 }
 
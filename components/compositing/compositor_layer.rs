@@ -4,11 +4,13 @@
 
 use compositor_task::LayerProperties;
 use compositor::IOCompositor;
+use layer_animation;
 use windowing::{MouseWindowEvent, WindowMethods};
 
 use azure::azure_hl;
 use geom::length::Length;
 use geom::matrix::identity;
+use geom::matrix2d::Matrix2D;
 use geom::point::{Point2D, TypedPoint2D};
 use geom::size::TypedSize2D;
 use geom::rect::Rect;
@@ -18,7 +20,7 @@ use layers::geometry::LayerPixel;
 use layers::layers::{Layer, LayerBufferSet};
 use script_traits::CompositorEvent::{ClickEvent, MouseDownEvent, MouseMoveEvent, MouseUpEvent};
 use script_traits::{ScriptControlChan, ConstellationControlMsg};
-use msg::compositor_msg::{Epoch, LayerId, ScrollPolicy};
+use msg::compositor_msg::{Epoch, LayerId, LayerKind, ScrollMetadata, ScrollPolicy};
 use msg::constellation_msg::PipelineId;
 use std::num::Float;
 use std::rc::Rc;
@@ -44,13 +46,37 @@ pub struct CompositorData {
     /// The scroll offset originating from this scrolling root. This allows scrolling roots
     /// to track their current scroll position even while their content_offset does not change.
     pub scroll_offset: TypedPoint2D<LayerPixel, f32>,
+
+    /// How this layer scrolls its own content, if it is a scroll layer. Scroll layers move their
+    /// content, as well as their children, by their scroll offset.
+    pub scroll: Option<ScrollMetadata>,
+
+    /// What this layer holds.
+    pub kind: LayerKind,
+
+    /// True if the painter paints fully opaque pixels over all of this layer, so that what is
+    /// behind it doesn't show.
+    pub is_opaque: bool,
+
+    /// How far scrolling the layers around this one has moved it.
+    pub scroll_translation: Point2D<f32>,
+
+    /// The transform that an animation of this layer applies on top of how it was painted, if
+    /// one is running.
+    pub animated_transform: Option<Matrix2D<f32>>,
 }
 
 impl CompositorData {
+    /// Creates a layer. Scroll layers always want scroll events, and mask to their bounds.
     pub fn new_layer(layer_properties: LayerProperties,
                      wants_scroll_events: WantsScrollEventsFlag,
                      tile_size: usize)
                      -> Rc<Layer<CompositorData>> {
+        let wants_scroll_events = if layer_properties.scroll.is_some() {
+            WantsScrollEventsFlag::WantsScrollEvents
+        } else {
+            wants_scroll_events
+        };
         let new_compositor_data = CompositorData {
             pipeline_id: layer_properties.pipeline_id,
             id: layer_properties.id,
@@ -58,12 +84,21 @@ impl CompositorData {
             scroll_policy: layer_properties.scroll_policy,
             epoch: layer_properties.epoch,
             scroll_offset: TypedPoint2D(0., 0.),
+            scroll: layer_properties.scroll,
+            kind: layer_properties.kind,
+            is_opaque: layer_properties.is_opaque,
+            scroll_translation: Point2D(0.0, 0.0),
+            animated_transform: None,
         };
 
-        Rc::new(Layer::new(Rect::from_untyped(&layer_properties.rect),
-                           tile_size,
-                           to_layers_color(&layer_properties.background_color),
-                           new_compositor_data))
+        let layer = Rc::new(Layer::new(Rect::from_untyped(&layer_properties.rect),
+                                       tile_size,
+                                       to_layers_color(&layer_properties.background_color),
+                                       new_compositor_data));
+        if layer_properties.scroll.is_some() {
+            *layer.masks_to_bounds.borrow_mut() = true;
+        }
+        layer
     }
 }
 
@@ -122,6 +157,10 @@ pub trait CompositorLayer {
                                             new_offset: TypedPoint2D<LayerPixel, f32>)
                                             -> ScrollEventResult;
 
+    /// Sets the transform of the layer to move it by how far it is scrolled, and by what an
+    /// animation of it applies.
+    fn update_transform(&self);
+
     fn scroll_layer_and_all_child_layers(&self,
                                          new_offset: TypedPoint2D<LayerPixel, f32>)
                                          -> bool;
@@ -180,6 +219,9 @@ impl CompositorLayer for Layer<CompositorData> {
     fn update_layer_except_bounds(&self, layer_properties: LayerProperties) {
         self.extra_data.borrow_mut().epoch = layer_properties.epoch;
         self.extra_data.borrow_mut().scroll_policy = layer_properties.scroll_policy;
+        self.extra_data.borrow_mut().scroll = layer_properties.scroll;
+        self.extra_data.borrow_mut().kind = layer_properties.kind;
+        self.extra_data.borrow_mut().is_opaque = layer_properties.is_opaque;
 
         *self.background_color.borrow_mut() = to_layers_color(&layer_properties.background_color);
 
@@ -288,10 +330,23 @@ impl CompositorLayer for Layer<CompositorData> {
         let scroll_offset = self.extra_data.borrow().scroll_offset;
         let new_cursor = cursor - scroll_offset;
         for child in self.children().iter() {
+            if child.extra_data.borrow().kind == LayerKind::Overlay {
+                continue
+            }
             let child_bounds = child.bounds.borrow();
             if child_bounds.contains(&new_cursor) {
                 let result = child.handle_scroll_event(delta, new_cursor - child_bounds.origin);
-                if result != ScrollEventResult::ScrollEventUnhandled {
+                if result == ScrollEventResult::ScrollEventUnhandled {
+                    continue
+                }
+
+                // Scroll layers that are at the end of their content leave the scroll to this
+                // layer, unless their overscroll behavior keeps it to themselves.
+                let chains = match child.extra_data.borrow().scroll {
+                    Some(ref scroll) => scroll.chains_scroll(&delta.to_untyped()),
+                    None => false,
+                };
+                if result != ScrollEventResult::ScrollPositionUnchanged || !chains {
                     return result;
                 }
             }
@@ -302,13 +357,26 @@ impl CompositorLayer for Layer<CompositorData> {
 
     fn clamp_scroll_offset_and_scroll_layer(&self, new_offset: TypedPoint2D<LayerPixel, f32>)
                                             -> ScrollEventResult {
-        let layer_size = self.bounds.borrow().size;
-        let content_size = calculate_content_size_for_layer(self);
-        let min_x = (layer_size.width - content_size.width).get().min(0.0);
-        let min_y = (layer_size.height - content_size.height).get().min(0.0);
+        // Scroll layers know how far their content can be scrolled. Other layers scroll the
+        // layers in them within their own bounds. Scroll offsets go the other way from how far
+        // content is scrolled.
+        let scroll = self.extra_data.borrow().scroll;
+        let (min_offset, max_offset) = match scroll {
+            Some(ref scroll) => {
+                (Point2D(-scroll.max_scroll_offset.x as f32, -scroll.max_scroll_offset.y as f32),
+                 Point2D(-scroll.min_scroll_offset.x as f32, -scroll.min_scroll_offset.y as f32))
+            }
+            None => {
+                let layer_size = self.bounds.borrow().size;
+                let content_size = calculate_content_size_for_layer(self);
+                (Point2D((layer_size.width - content_size.width).get().min(0.0),
+                         (layer_size.height - content_size.height).get().min(0.0)),
+                 Point2D(0.0, 0.0))
+            }
+        };
         let new_offset : TypedPoint2D<LayerPixel, f32> =
-            Point2D(Length::new(new_offset.x.get().clamp(&min_x, &0.0)),
-                    Length::new(new_offset.y.get().clamp(&min_y, &0.0)));
+            Point2D(Length::new(new_offset.x.get().clamp(&min_offset.x, &max_offset.x)),
+                    Length::new(new_offset.y.get().clamp(&min_offset.y, &max_offset.y)));
 
         if self.extra_data.borrow().scroll_offset == new_offset {
             return ScrollEventResult::ScrollPositionUnchanged;
@@ -316,9 +384,16 @@ impl CompositorLayer for Layer<CompositorData> {
 
         // The scroll offset is just a record of the scroll position of this scrolling root,
         // but scroll_layer_and_all_child_layers actually moves the child layers.
+        let old_offset = self.extra_data.borrow().scroll_offset;
         self.extra_data.borrow_mut().scroll_offset = new_offset;
 
+        // Scroll layers move their own content too, behind their bounds.
         let mut result = false;
+        if scroll.is_some() {
+            let content_offset = *self.content_offset.borrow() - old_offset + new_offset;
+            *self.content_offset.borrow_mut() = content_offset;
+            result = true
+        }
         for child in self.children().iter() {
             result |= child.scroll_layer_and_all_child_layers(new_offset);
         }
@@ -365,10 +440,15 @@ impl CompositorLayer for Layer<CompositorData> {
         let mut result = false;
 
         // Only scroll this layer if it's not fixed-positioned.
-        if self.extra_data.borrow().scroll_policy != ScrollPolicy::FixedPosition {
+        if self.extra_data.borrow().scroll_policy == ScrollPolicy::Scrollable {
             let new_offset = new_offset.to_untyped();
-            *self.transform.borrow_mut() = identity().translate(new_offset.x, new_offset.y, 0.0);
-            *self.content_offset.borrow_mut() = Point2D::from_untyped(&new_offset);
+            self.extra_data.borrow_mut().scroll_translation = new_offset;
+            self.update_transform();
+            let mut content_offset = Point2D::from_untyped(&new_offset);
+            if self.extra_data.borrow().scroll.is_some() {
+                content_offset = content_offset + self.extra_data.borrow().scroll_offset;
+            }
+            *self.content_offset.borrow_mut() = content_offset;
             result = true
         }
 
@@ -380,6 +460,19 @@ impl CompositorLayer for Layer<CompositorData> {
         return result;
     }
 
+    fn update_transform(&self) {
+        let data = self.extra_data.borrow();
+        let translation = identity().translate(data.scroll_translation.x,
+                                               data.scroll_translation.y,
+                                               0.0);
+        *self.transform.borrow_mut() = match data.animated_transform {
+            Some(ref animated_transform) => {
+                translation.mul(&layer_animation::to_matrix4(animated_transform))
+            }
+            None => translation,
+        };
+    }
+
     fn wants_scroll_events(&self) -> WantsScrollEventsFlag {
         self.extra_data.borrow().wants_scroll_events
     }
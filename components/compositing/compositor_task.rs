@@ -15,10 +15,13 @@ use azure::azure_hl::{SourceSurfaceMethods, Color};
 use geom::point::Point2D;
 use geom::rect::Rect;
 use geom::size::Size2D;
+use gfx::shared_surface::SharedSurface;
 use layers::platform::surface::{NativeCompositingGraphicsContext, NativeGraphicsMetadata};
 use layers::layers::LayerBufferSet;
-use msg::compositor_msg::{Epoch, LayerId, LayerMetadata, ReadyState};
-use msg::compositor_msg::{PaintListener, PaintState, ScriptListener, ScrollPolicy};
+use msg::compositor_msg::{DirectFrame, Epoch, ExternalTextureFrame, ExternalTextureId};
+use msg::compositor_msg::{LayerAnimation, LayerId, LayerMetadata};
+use msg::compositor_msg::{LayerKind, PaintListener, PaintState, ReadyState, ScriptListener};
+use msg::compositor_msg::{ScrollMetadata, ScrollPolicy, SharedTile};
 use msg::constellation_msg::{ConstellationChan, PipelineId};
 use msg::constellation_msg::{Key, KeyState, KeyModifiers};
 use profile_traits::mem;
@@ -103,6 +106,9 @@ pub struct LayerProperties {
     pub rect: Rect<f32>,
     pub background_color: Color,
     pub scroll_policy: ScrollPolicy,
+    pub scroll: Option<ScrollMetadata>,
+    pub kind: LayerKind,
+    pub is_opaque: bool,
 }
 
 impl LayerProperties {
@@ -117,6 +123,9 @@ impl LayerProperties {
                               metadata.position.size.height as f32)),
             background_color: metadata.background_color,
             scroll_policy: metadata.scroll_policy,
+            scroll: metadata.scroll,
+            kind: metadata.kind,
+            is_opaque: metadata.is_opaque,
         }
     }
 }
@@ -136,6 +145,21 @@ impl PaintListener for Box<CompositorProxy+'static+Send> {
         self.send(Msg::AssignPaintedBuffers(pipeline_id, epoch, replies));
     }
 
+    fn assign_shared_tiles(&mut self,
+                           pipeline_id: PipelineId,
+                           epoch: Epoch,
+                           tiles: Vec<(LayerId, Vec<SharedTile>)>) {
+        self.send(Msg::AssignSharedTiles(pipeline_id, epoch, tiles));
+    }
+
+    fn set_direct_painting(&mut self, pipeline_id: PipelineId, epoch: Epoch, direct: bool) {
+        self.send(Msg::SetDirectPainting(pipeline_id, epoch, direct));
+    }
+
+    fn assign_direct_frame(&mut self, pipeline_id: PipelineId, epoch: Epoch, frame: DirectFrame) {
+        self.send(Msg::AssignDirectFrame(pipeline_id, epoch, frame));
+    }
+
     fn initialize_layers_for_pipeline(&mut self,
                                       pipeline_id: PipelineId,
                                       metadata: Vec<LayerMetadata>,
@@ -162,6 +186,26 @@ impl PaintListener for Box<CompositorProxy+'static+Send> {
     fn set_paint_state(&mut self, pipeline_id: PipelineId, paint_state: PaintState) {
         self.send(Msg::ChangePaintState(pipeline_id, paint_state))
     }
+
+    fn schedule_frame_repaint(&mut self,
+                              pipeline_id: PipelineId,
+                              epoch: Epoch,
+                              layer_id: LayerId,
+                              rect: Rect<f32>,
+                              time: u64) {
+        self.send(Msg::ScheduleFrameRepaint(pipeline_id, epoch, layer_id, rect, time))
+    }
+
+    fn set_layer_animations(&mut self, pipeline_id: PipelineId, animations: Vec<LayerAnimation>) {
+        self.send(Msg::SetLayerAnimations(pipeline_id, animations))
+    }
+
+    fn set_external_textures(&mut self,
+                             pipeline_id: PipelineId,
+                             epoch: Epoch,
+                             textures: Vec<(LayerId, Vec<(ExternalTextureId, Rect<f32>)>)>) {
+        self.send(Msg::SetExternalTextures(pipeline_id, epoch, textures))
+    }
 }
 
 /// Messages from the painting task and the constellation task to the compositor task.
@@ -193,6 +237,13 @@ pub enum Msg {
     ScrollFragmentPoint(PipelineId, LayerId, Point2D<f32>),
     /// Requests that the compositor assign the painted buffers to the given layers.
     AssignPaintedBuffers(PipelineId, Epoch, Vec<(LayerId, Box<LayerBufferSet>)>),
+    /// Hands tiles that the painter painted into shared memory on to the embedder.
+    AssignSharedTiles(PipelineId, Epoch, Vec<(LayerId, Vec<SharedTile>)>),
+    /// Tells the compositor whether the pipeline can be painted straight into the window at the
+    /// given epoch.
+    SetDirectPainting(PipelineId, Epoch, bool),
+    /// Requests that the compositor put the frame painted for the window into it.
+    AssignDirectFrame(PipelineId, Epoch, DirectFrame),
     /// Alerts the compositor to the current status of page loading.
     ChangeReadyState(PipelineId, ReadyState),
     /// Alerts the compositor to the current status of painting.
@@ -213,6 +264,19 @@ pub enum Msg {
     /// composite should happen. (See the `scrolling` module.)
     ScrollTimeout(u64),
     RecompositeAfterScroll,
+    /// Requests that the given area of a layer be repainted at the given time, because an
+    /// animated image in it is due to show its next frame.
+    ScheduleFrameRepaint(PipelineId, Epoch, LayerId, Rect<f32>, u64),
+    /// Indicates that the earliest scheduled frame repaint is due. (See the `frame_timer`
+    /// module.)
+    FrameRepaintTimeout,
+    /// Replaces the animations of the layers of the given pipeline.
+    SetLayerAnimations(PipelineId, Vec<LayerAnimation>),
+    /// Replaces where in the layers of the given pipeline external textures are drawn.
+    SetExternalTextures(PipelineId, Epoch, Vec<(LayerId, Vec<(ExternalTextureId, Rect<f32>)>)>),
+    /// Hands the compositor a new frame of an external texture. Producers such as video decoders
+    /// and WebGL canvases send this through a compositor proxy of their own.
+    UpdateExternalTexture(ExternalTextureId, ExternalTextureFrame),
     /// Sends an unconsumed key event back to the compositor.
     KeyEvent(Key, KeyState, KeyModifiers),
     /// Changes the cursor.
@@ -232,6 +296,9 @@ impl Debug for Msg {
             Msg::SetLayerRect(..) => write!(f, "SetLayerRect"),
             Msg::ScrollFragmentPoint(..) => write!(f, "ScrollFragmentPoint"),
             Msg::AssignPaintedBuffers(..) => write!(f, "AssignPaintedBuffers"),
+            Msg::AssignSharedTiles(..) => write!(f, "AssignSharedTiles"),
+            Msg::SetDirectPainting(..) => write!(f, "SetDirectPainting"),
+            Msg::AssignDirectFrame(..) => write!(f, "AssignDirectFrame"),
             Msg::ChangeReadyState(..) => write!(f, "ChangeReadyState"),
             Msg::ChangePaintState(..) => write!(f, "ChangePaintState"),
             Msg::ChangeRunningAnimationsState(..) => write!(f, "ChangeRunningAnimationsState"),
@@ -242,6 +309,11 @@ impl Debug for Msg {
             Msg::LoadComplete => write!(f, "LoadComplete"),
             Msg::ScrollTimeout(..) => write!(f, "ScrollTimeout"),
             Msg::RecompositeAfterScroll => write!(f, "RecompositeAfterScroll"),
+            Msg::ScheduleFrameRepaint(..) => write!(f, "ScheduleFrameRepaint"),
+            Msg::FrameRepaintTimeout => write!(f, "FrameRepaintTimeout"),
+            Msg::SetLayerAnimations(..) => write!(f, "SetLayerAnimations"),
+            Msg::SetExternalTextures(..) => write!(f, "SetExternalTextures"),
+            Msg::UpdateExternalTexture(..) => write!(f, "UpdateExternalTexture"),
             Msg::KeyEvent(..) => write!(f, "KeyEvent"),
             Msg::SetCursor(..) => write!(f, "SetCursor"),
             Msg::PaintTaskExited(..) => write!(f, "PaintTaskExited"),
@@ -249,6 +321,16 @@ impl Debug for Msg {
     }
 }
 
+/// Removes the shared memory of tiles that won't be shown. Their receiver owns it, so it would
+/// otherwise stay behind.
+pub fn discard_shared_tiles(tiles: Vec<(LayerId, Vec<SharedTile>)>) {
+    for (_, tiles) in tiles.into_iter() {
+        for tile in tiles.iter() {
+            SharedSurface::discard(&tile.surface)
+        }
+    }
+}
+
 pub struct CompositorTask;
 
 impl CompositorTask {
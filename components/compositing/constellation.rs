@@ -16,7 +16,8 @@ use libc;
 use msg::compositor_msg::LayerId;
 use msg::constellation_msg::Msg as ConstellationMsg;
 use msg::constellation_msg::{FrameId, PipelineExitType, PipelineId};
-use msg::constellation_msg::{IFrameSandboxState, MozBrowserEvent, NavigationDirection};
+use msg::constellation_msg::{IFrameSandboxState, IframeMouseEvent, MozBrowserEvent};
+use msg::constellation_msg::NavigationDirection;
 use msg::constellation_msg::{Key, KeyState, KeyModifiers, LoadData};
 use msg::constellation_msg::{SubpageId, WindowSizeData};
 use msg::constellation_msg::{self, ConstellationChan, Failure};
@@ -417,6 +418,10 @@ impl<LTF: LayoutTaskFactory, STF: ScriptTaskFactory> Constellation<LTF, STF> {
                 self.handle_webdriver_command_msg(pipeline_id,
                                                   command);
             }
+            ConstellationMsg::ForwardMouseEvent(containing_pipeline_id, subpage_id, event) => {
+                debug!("constellation got forwarded mouse event message");
+                self.handle_forward_mouse_event_msg(containing_pipeline_id, subpage_id, event);
+            }
         }
         true
     }
@@ -731,6 +736,32 @@ impl<LTF: LayoutTaskFactory, STF: ScriptTaskFactory> Constellation<LTF, STF> {
         pipeline.trigger_mozbrowser_event(subpage_id, event);
     }
 
+    fn handle_forward_mouse_event_msg(&mut self,
+                                      containing_pipeline_id: PipelineId,
+                                      subpage_id: SubpageId,
+                                      event: IframeMouseEvent) {
+        // The iframe may have gone away since the event hit it.
+        let pipeline = match self.subpage_map.get(&(containing_pipeline_id, subpage_id)) {
+            Some(pipeline_id) => match self.pipelines.get(pipeline_id) {
+                Some(pipeline) => pipeline,
+                None => return,
+            },
+            None => return,
+        };
+        let event = match event {
+            IframeMouseEvent::Click(button, point) => CompositorEvent::ClickEvent(button, point),
+            IframeMouseEvent::MouseDown(button, point) => {
+                CompositorEvent::MouseDownEvent(button, point)
+            }
+            IframeMouseEvent::MouseUp(button, point) => {
+                CompositorEvent::MouseUpEvent(button, point)
+            }
+            IframeMouseEvent::MouseMove(point) => CompositorEvent::MouseMoveEvent(point),
+        };
+        let ScriptControlChan(ref chan) = pipeline.script_chan;
+        let _ = chan.send(ConstellationControlMsg::SendEvent(pipeline.id, event));
+    }
+
     fn handle_get_root_pipeline(&mut self, resp_chan: Sender<Option<PipelineId>>) {
         let pipeline_id = self.root_frame_id.map(|frame_id| {
             let frame = self.frames.get(&frame_id).unwrap();
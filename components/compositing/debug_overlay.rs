@@ -0,0 +1,165 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Outlines of layers and tiles that the compositor draws over everything else with
+//! `-Z show-layer-borders`, so that how a page is split into layers and tiles can be watched
+//! while it scrolls. Each layer gets a color of its own and a label with its ID.
+//!
+//! Everything is drawn by clearing scissor rects, so no shaders are needed, and labels use a
+//! tiny built-in font of digits.
+
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gleam::gl::types::GLuint;
+use gleam::gl;
+use msg::compositor_msg::LayerId;
+use std::num::Float;
+
+/// The colors that layers are outlined in, in turn.
+static LAYER_COLORS: [[f32; 3]; 6] = [
+    [1.0, 0.0, 0.0],
+    [0.0, 0.8, 0.0],
+    [0.0, 0.4, 1.0],
+    [1.0, 0.6, 0.0],
+    [0.8, 0.0, 0.8],
+    [0.0, 0.8, 0.8],
+];
+
+/// How wide the outlines of layers are, in device pixels.
+static LAYER_BORDER_WIDTH: i32 = 2;
+
+/// How many device pixels each pixel of the font of labels covers.
+static LABEL_PIXEL_SIZE: i32 = 2;
+
+/// Glyphs of 3 by 5 pixels for the digits and the comma, a row to each byte.
+static DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+static COMMA_GLYPH: [u8; 5] = [0b000, 0b000, 0b000, 0b010, 0b100];
+
+/// A layer to outline. Everything is in device pixels of the window, from its top left.
+pub struct OutlinedLayer {
+    pub id: LayerId,
+    /// The part of the layer that shows.
+    pub visible_rect: Rect<f32>,
+    /// Where the grid of tiles of the layer starts, which is the top left of its content.
+    pub tile_origin: Point2D<f32>,
+    /// The length of the sides of the tiles, or `None` if the layer isn't painted in tiles.
+    pub tile_size: Option<f32>,
+}
+
+/// Outlines the layers and their tiles in the framebuffer, in the order that they are given in.
+pub fn draw(layers: &[OutlinedLayer], framebuffer: GLuint, window_height: i32) {
+    gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::enable(gl::SCISSOR_TEST);
+    for (index, layer) in layers.iter().enumerate() {
+        let color = LAYER_COLORS[index % LAYER_COLORS.len()];
+        let visible_rect = round_rect(&layer.visible_rect);
+        if let Some(tile_size) = layer.tile_size {
+            let tile_color = [color[0] * 0.5, color[1] * 0.5, color[2] * 0.5];
+            draw_tile_grid(&visible_rect, &layer.tile_origin, tile_size, &tile_color, window_height)
+        }
+        draw_outline(&visible_rect, LAYER_BORDER_WIDTH, &color, window_height);
+        draw_label(layer.id, &visible_rect.origin, &color, window_height);
+    }
+    gl::disable(gl::SCISSOR_TEST);
+}
+
+/// Draws the lines between the tiles that lie inside `clip`.
+fn draw_tile_grid(clip: &Rect<i32>,
+                  origin: &Point2D<f32>,
+                  tile_size: f32,
+                  color: &[f32; 3],
+                  window_height: i32) {
+    if tile_size < 1.0 {
+        return
+    }
+
+    let mut x = origin.x + ((clip.origin.x as f32 - origin.x) / tile_size).ceil() * tile_size;
+    while x < clip.max_x() as f32 {
+        fill(&Rect(Point2D(x.round() as i32, clip.origin.y), Size2D(1, clip.size.height)),
+             color,
+             window_height);
+        x += tile_size
+    }
+    let mut y = origin.y + ((clip.origin.y as f32 - origin.y) / tile_size).ceil() * tile_size;
+    while y < clip.max_y() as f32 {
+        fill(&Rect(Point2D(clip.origin.x, y.round() as i32), Size2D(clip.size.width, 1)),
+             color,
+             window_height);
+        y += tile_size
+    }
+}
+
+fn draw_outline(rect: &Rect<i32>, width: i32, color: &[f32; 3], window_height: i32) {
+    let (left, top, right, bottom) = (rect.origin.x, rect.origin.y, rect.max_x(), rect.max_y());
+    fill(&Rect(rect.origin, Size2D(rect.size.width, width)), color, window_height);
+    fill(&Rect(Point2D(left, bottom - width), Size2D(rect.size.width, width)),
+         color,
+         window_height);
+    fill(&Rect(rect.origin, Size2D(width, rect.size.height)), color, window_height);
+    fill(&Rect(Point2D(right - width, top), Size2D(width, rect.size.height)),
+         color,
+         window_height);
+}
+
+/// Draws the ID of the layer in black on its color, just inside the given top left corner.
+fn draw_label(id: LayerId, origin: &Point2D<i32>, color: &[f32; 3], window_height: i32) {
+    let LayerId(a, b) = id;
+    let label = format!("{},{}", a, b);
+    let advance = 4 * LABEL_PIXEL_SIZE;
+    let padding = LABEL_PIXEL_SIZE;
+    let origin = Point2D(origin.x + LAYER_BORDER_WIDTH, origin.y + LAYER_BORDER_WIDTH);
+    fill(&Rect(origin,
+               Size2D(label.len() as i32 * advance + padding,
+                      5 * LABEL_PIXEL_SIZE + padding * 2)),
+         color,
+         window_height);
+
+    for (index, character) in label.chars().enumerate() {
+        let glyph = match character.to_digit(10) {
+            Some(digit) => &DIGIT_GLYPHS[digit as usize],
+            None => &COMMA_GLYPH,
+        };
+        let glyph_origin = Point2D(origin.x + padding + index as i32 * advance, origin.y + padding);
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue
+                }
+                fill(&Rect(Point2D(glyph_origin.x + column as i32 * LABEL_PIXEL_SIZE,
+                                   glyph_origin.y + row as i32 * LABEL_PIXEL_SIZE),
+                           Size2D(LABEL_PIXEL_SIZE, LABEL_PIXEL_SIZE)),
+                     &[0.0, 0.0, 0.0],
+                     window_height);
+            }
+        }
+    }
+}
+
+/// Fills the rect, which is from the top left of the window, with the color.
+fn fill(rect: &Rect<i32>, color: &[f32; 3], window_height: i32) {
+    if rect.size.width <= 0 || rect.size.height <= 0 {
+        return
+    }
+    gl::scissor(rect.origin.x, window_height - rect.max_y(), rect.size.width, rect.size.height);
+    gl::clear_color(color[0], color[1], color[2], 1.0);
+    gl::clear(gl::COLOR_BUFFER_BIT);
+}
+
+fn round_rect(rect: &Rect<f32>) -> Rect<i32> {
+    let (left, top) = (rect.origin.x.round() as i32, rect.origin.y.round() as i32);
+    let (right, bottom) = (rect.max_x().round() as i32, rect.max_y().round() as i32);
+    Rect(Point2D(left, top), Size2D(right - left, bottom - top))
+}
@@ -0,0 +1,221 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Draws the frames that painters paint for the window as a whole, for pages simple enough not to
+//! need tiles.
+//!
+//! The pixels of a frame are uploaded into a texture once, when the frame arrives, and the
+//! texture is drawn on a quad at every composite. Only what OpenGL ES 2 has is used. If the
+//! renderer can't be created, or a frame would be larger than a texture can be, pages are
+//! painted in tiles instead.
+
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gleam::gl;
+use gleam::gl::types::{GLenum, GLint, GLsizei, GLuint};
+use layers::color::Color;
+use msg::compositor_msg::DirectFrame;
+
+static VERTEX_SHADER: &'static str = "
+    // The corner of the quad, from (0, 0) to (1, 1).
+    attribute vec2 aCorner;
+
+    // Where the frame goes, as (left, top, right, bottom) in normalized device coordinates.
+    uniform vec4 uRect;
+
+    varying vec2 vTexCoord;
+
+    void main() {
+        vTexCoord = aCorner;
+        gl_Position = vec4(mix(uRect.xy, uRect.zw, aCorner), 0.0, 1.0);
+    }
+";
+
+static FRAGMENT_SHADER: &'static str = "
+    #ifdef GL_ES
+    precision mediump float;
+    #endif
+
+    uniform sampler2D uFrame;
+
+    varying vec2 vTexCoord;
+
+    void main() {
+        // Frames are premultiplied BGRA, which is uploaded as RGBA since OpenGL ES 2 can't
+        // upload BGRA without an extension.
+        gl_FragColor = texture2D(uFrame, vTexCoord).bgra;
+    }
+";
+
+pub struct DirectFrameRenderer {
+    program: GLuint,
+    corner_buffer: GLuint,
+    texture: GLuint,
+    corner_attribute: GLuint,
+    rect_uniform: GLint,
+    frame_uniform: GLint,
+    max_texture_size: i32,
+    /// The size of the frame in the texture, or `None` if no frame has been uploaded yet.
+    frame_size: Option<Size2D<i32>>,
+}
+
+impl DirectFrameRenderer {
+    /// Creates a renderer for the current GL context, or returns `None` if its shaders can't be
+    /// built.
+    pub fn new() -> Option<DirectFrameRenderer> {
+        let program = match link_program() {
+            Some(program) => program,
+            None => return None,
+        };
+
+        let buffers = gl::gen_buffers(1);
+        gl::bind_buffer(gl::ARRAY_BUFFER, buffers[0]);
+        let corners = [0.0f32, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        gl::buffer_data(gl::ARRAY_BUFFER, &corners[..], gl::STATIC_DRAW);
+        gl::bind_buffer(gl::ARRAY_BUFFER, 0);
+
+        let textures = gl::gen_textures(1);
+        gl::bind_texture(gl::TEXTURE_2D, textures[0]);
+        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::bind_texture(gl::TEXTURE_2D, 0);
+
+        let mut max_texture_size = 0;
+        unsafe {
+            gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+        }
+
+        Some(DirectFrameRenderer {
+            program: program,
+            corner_buffer: buffers[0],
+            texture: textures[0],
+            corner_attribute: gl::get_attrib_location(program, "aCorner") as GLuint,
+            rect_uniform: gl::get_uniform_location(program, "uRect"),
+            frame_uniform: gl::get_uniform_location(program, "uFrame"),
+            max_texture_size: max_texture_size,
+            frame_size: None,
+        })
+    }
+
+    /// Returns true if frames of the given size fit in a texture.
+    pub fn can_draw(&self, size: &Size2D<usize>) -> bool {
+        let max_texture_size = self.max_texture_size as usize;
+        size.width <= max_texture_size && size.height <= max_texture_size
+    }
+
+    /// Uploads the pixels of the frame, which are drawn from then on.
+    pub fn upload(&mut self, frame: &DirectFrame) {
+        gl::bind_texture(gl::TEXTURE_2D, self.texture);
+        gl::pixel_store_i(gl::UNPACK_ALIGNMENT, 4);
+        gl::tex_image_2d(gl::TEXTURE_2D,
+                         0,
+                         gl::RGBA as GLint,
+                         frame.size.width as GLsizei,
+                         frame.size.height as GLsizei,
+                         0,
+                         gl::RGBA,
+                         gl::UNSIGNED_BYTE,
+                         Some(&frame.pixels[..]));
+        gl::bind_texture(gl::TEXTURE_2D, 0);
+        self.frame_size = Some(frame.size);
+    }
+
+    /// Fills the framebuffer, which is the size of the window, with the background color, then
+    /// draws the last frame uploaded over `rect`, in device pixels from the top left of the
+    /// window. Frames drawn at the size they were painted at are sampled without filtering.
+    pub fn draw(&self,
+                framebuffer: GLuint,
+                window_size: &Size2D<i32>,
+                rect: &Rect<f32>,
+                background_color: &Color) {
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::viewport(0, 0, window_size.width, window_size.height);
+        gl::clear_color(background_color.r, background_color.g, background_color.b,
+                        background_color.a);
+        gl::clear(gl::COLOR_BUFFER_BIT);
+
+        let frame_size = match self.frame_size {
+            Some(frame_size) => frame_size,
+            None => return,
+        };
+        let filter = if rect.size.width == frame_size.width as f32 &&
+                rect.size.height == frame_size.height as f32 {
+            gl::NEAREST
+        } else {
+            gl::LINEAR
+        };
+
+        gl::use_program(self.program);
+        let (width, height) = (window_size.width as f32, window_size.height as f32);
+        gl::uniform_4f(self.rect_uniform,
+                       rect.origin.x / width * 2.0 - 1.0,
+                       1.0 - rect.origin.y / height * 2.0,
+                       rect.max_x() / width * 2.0 - 1.0,
+                       1.0 - rect.max_y() / height * 2.0);
+        gl::uniform_1i(self.frame_uniform, 0);
+
+        gl::active_texture(gl::TEXTURE0);
+        gl::bind_texture(gl::TEXTURE_2D, self.texture);
+        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+
+        gl::disable(gl::BLEND);
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.corner_buffer);
+        gl::enable_vertex_attrib_array(self.corner_attribute);
+        gl::vertex_attrib_pointer_f32(self.corner_attribute, 2, false, 0, 0);
+        gl::draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+
+        gl::disable_vertex_attrib_array(self.corner_attribute);
+        gl::bind_buffer(gl::ARRAY_BUFFER, 0);
+        gl::bind_texture(gl::TEXTURE_2D, 0);
+        gl::use_program(0);
+    }
+}
+
+impl Drop for DirectFrameRenderer {
+    fn drop(&mut self) {
+        gl::delete_program(self.program);
+        gl::delete_buffers(&[self.corner_buffer]);
+        gl::delete_textures(&[self.texture]);
+    }
+}
+
+fn compile_shader(source: &str, shader_type: GLenum) -> Option<GLuint> {
+    let shader = gl::create_shader(shader_type);
+    gl::shader_source(shader, &[source.as_bytes()]);
+    gl::compile_shader(shader);
+    if gl::get_shader_iv(shader, gl::COMPILE_STATUS) == 0 {
+        debug!("compositor: failed to compile shader: {}", gl::get_shader_info_log(shader));
+        gl::delete_shader(shader);
+        return None
+    }
+    Some(shader)
+}
+
+fn link_program() -> Option<GLuint> {
+    let vertex_shader = match compile_shader(VERTEX_SHADER, gl::VERTEX_SHADER) {
+        Some(shader) => shader,
+        None => return None,
+    };
+    let fragment_shader = match compile_shader(FRAGMENT_SHADER, gl::FRAGMENT_SHADER) {
+        Some(shader) => shader,
+        None => {
+            gl::delete_shader(vertex_shader);
+            return None
+        }
+    };
+
+    let program = gl::create_program();
+    gl::attach_shader(program, vertex_shader);
+    gl::attach_shader(program, fragment_shader);
+    gl::link_program(program);
+    gl::delete_shader(vertex_shader);
+    gl::delete_shader(fragment_shader);
+    if gl::get_program_iv(program, gl::LINK_STATUS) == 0 {
+        debug!("compositor: failed to link direct frame program");
+        gl::delete_program(program);
+        return None
+    }
+    Some(program)
+}
@@ -0,0 +1,27 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Timers that wake the compositor up when an animated image is due to show its next frame, so
+//! that it can have the tiles covering the image repainted.
+
+use compositor_task::{CompositorProxy, Msg};
+
+use std::borrow::ToOwned;
+use std::old_io::timer;
+use std::thread::Builder;
+use std::time::duration::Duration;
+use time;
+
+/// Sends a `FrameRepaintTimeout` message to the compositor at `due_time`, in nanoseconds as
+/// returned by `time::precise_time_ns()`.
+pub fn start(compositor_proxy: Box<CompositorProxy+Send>, due_time: u64) {
+    Builder::new().name("FrameRepaintTimer".to_owned()).spawn(move || {
+        let mut compositor_proxy = compositor_proxy;
+        let delta = due_time as i64 - (time::precise_time_ns() as i64);
+        if delta > 0 {
+            timer::sleep(Duration::nanoseconds(delta));
+        }
+        compositor_proxy.send(Msg::FrameRepaintTimeout);
+    }).unwrap();
+}
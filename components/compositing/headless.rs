@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use compositor_task::{CompositorEventListener, CompositorReceiver, Msg};
+use compositor_task::{CompositorEventListener, CompositorReceiver, Msg, discard_shared_tiles};
 use windowing::WindowEvent;
 
 use geom::scale_factor::ScaleFactor;
@@ -88,6 +88,12 @@ impl CompositorEventListener for NullCompositor {
                 response_chan.send(()).unwrap();
             }
 
+            // Nothing is drawn, so the frame can go straight back to its producer.
+            Msg::UpdateExternalTexture(_, frame) => frame.release(),
+
+            // The surfaces of shared tiles are ours, so they must go even though nothing is shown.
+            Msg::AssignSharedTiles(_, _, tiles) => discard_shared_tiles(tiles),
+
             // Explicitly list ignored messages so that when we add a new one,
             // we'll notice and think about whether it needs a response, like
             // SetFrameTree.
@@ -96,6 +102,8 @@ impl CompositorEventListener for NullCompositor {
             Msg::CreateOrUpdateDescendantLayer(..) |
             Msg::SetLayerRect(..) |
             Msg::AssignPaintedBuffers(..) |
+            Msg::SetDirectPainting(..) |
+            Msg::AssignDirectFrame(..) |
             Msg::ChangeReadyState(..) |
             Msg::ChangePaintState(..) |
             Msg::ChangeRunningAnimationsState(..) |
@@ -104,6 +112,10 @@ impl CompositorEventListener for NullCompositor {
             Msg::PaintMsgDiscarded(..) |
             Msg::ScrollTimeout(..) |
             Msg::RecompositeAfterScroll |
+            Msg::ScheduleFrameRepaint(..) |
+            Msg::FrameRepaintTimeout |
+            Msg::SetLayerAnimations(..) |
+            Msg::SetExternalTextures(..) |
             Msg::ChangePageTitle(..) |
             Msg::ChangePageUrl(..) |
             Msg::KeyEvent(..) |
@@ -0,0 +1,89 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Animations of the transforms and opacities of layers, which the compositor runs by itself
+//! between the frames that layout builds.
+//!
+//! Transforms are interpolated component by component, which is what CSS does for transforms
+//! that are translations and scales. Layout sends keyframes close enough together for others.
+
+use geom::matrix::Matrix4;
+use geom::matrix2d::Matrix2D;
+use msg::compositor_msg::{AnimationTimingFunction, LayerAnimation, LayerKeyframes};
+use std::num::Float;
+use util::bezier::Bezier;
+
+/// What an animation sets a layer to at some point in its time.
+pub enum AnimatedValue {
+    Transform(Matrix2D<f32>),
+    Opacity(f32),
+}
+
+/// Returns what the animation sets its layer to at `time`, in seconds as returned by
+/// `time::precise_time_s()`, or `None` if it hasn't started yet. Animations that have ended keep
+/// their last keyframe.
+pub fn value_at(animation: &LayerAnimation, time: f64) -> Option<AnimatedValue> {
+    if time < animation.start_time {
+        return None
+    }
+
+    let duration = animation.end_time - animation.start_time;
+    let progress = if duration <= 0.0 {
+        1.0
+    } else {
+        ((time - animation.start_time) / duration).min(1.0)
+    };
+    let progress = match animation.timing_function {
+        AnimationTimingFunction::Linear => progress,
+        AnimationTimingFunction::CubicBezier(p1, p2) => {
+            // Precise enough to be off by less than one frame at 200 frames per second.
+            let epsilon = 1.0 / (200.0 * duration.max(0.001));
+            Bezier::new(p1, p2).solve(progress, epsilon)
+        }
+    } as f32;
+
+    match animation.keyframes {
+        LayerKeyframes::Transform(ref keyframes) => {
+            interpolate(keyframes, progress, |from, to, amount| {
+                Matrix2D::new(lerp(from.m11, to.m11, amount),
+                              lerp(from.m12, to.m12, amount),
+                              lerp(from.m21, to.m21, amount),
+                              lerp(from.m22, to.m22, amount),
+                              lerp(from.m31, to.m31, amount),
+                              lerp(from.m32, to.m32, amount))
+            }).map(AnimatedValue::Transform)
+        }
+        LayerKeyframes::Opacity(ref keyframes) => {
+            interpolate(keyframes, progress, |from, to, amount| lerp(*from, *to, amount))
+                .map(AnimatedValue::Opacity)
+        }
+    }
+}
+
+/// Returns the 3D transform that applies the given 2D one.
+pub fn to_matrix4(transform: &Matrix2D<f32>) -> Matrix4<f32> {
+    Matrix4::new(transform.m11, transform.m12, 0.0, 0.0,
+                 transform.m21, transform.m22, 0.0, 0.0,
+                 0.0,           0.0,           1.0, 0.0,
+                 transform.m31, transform.m32, 0.0, 1.0)
+}
+
+/// Returns the value between the keyframes around `progress`, or the first or last keyframe if
+/// `progress` is outside of them.
+fn interpolate<T, F>(keyframes: &[(f32, T)], progress: f32, lerp_values: F) -> Option<T>
+                     where T: Clone, F: Fn(&T, &T, f32) -> T {
+    let next = match keyframes.iter().position(|&(offset, _)| offset > progress) {
+        Some(0) => return keyframes.first().map(|&(_, ref value)| value.clone()),
+        Some(next) => next,
+        None => return keyframes.last().map(|&(_, ref value)| value.clone()),
+    };
+    let (from_offset, ref from) = keyframes[next - 1];
+    let (to_offset, ref to) = keyframes[next];
+    let amount = (progress - from_offset) / (to_offset - from_offset);
+    Some(lerp_values(from, to, amount))
+}
+
+fn lerp(from: f32, to: f32, amount: f32) -> f32 {
+    from + (to - from) * amount
+}
@@ -45,6 +45,10 @@ pub use constellation::Constellation;
 pub mod compositor_task;
 
 mod compositor_layer;
+mod debug_overlay;
+mod direct_frame;
+mod frame_timer;
+mod layer_animation;
 mod scrolling;
 
 mod compositor;
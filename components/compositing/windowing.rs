@@ -9,10 +9,12 @@ use compositor_task::{CompositorProxy, CompositorReceiver};
 use geom::point::TypedPoint2D;
 use geom::scale_factor::ScaleFactor;
 use geom::size::TypedSize2D;
+use gfx::paint_task::WorkerPoolConfig;
+use gfx::shared_surface::SharedSurface;
 use layers::geometry::DevicePixel;
 use layers::platform::surface::NativeGraphicsMetadata;
-use msg::compositor_msg::{PaintState, ReadyState};
-use msg::constellation_msg::{Key, KeyState, KeyModifiers};
+use msg::compositor_msg::{LayerId, PaintState, ReadyState, SharedTile};
+use msg::constellation_msg::{Key, KeyState, KeyModifiers, PipelineId};
 use script_traits::MouseButton;
 use url::Url;
 use util::cursor::Cursor;
@@ -70,6 +72,9 @@ pub enum WindowEvent {
     Quit,
     /// Sent when a key input state changes
     KeyEvent(Key, KeyState, KeyModifiers),
+    /// Sent to change the threads that tiles are painted on, for example to use fewer while on
+    /// battery.
+    ConfigurePaintWorkers(WorkerPoolConfig),
 }
 
 impl Debug for WindowEvent {
@@ -88,6 +93,7 @@ impl Debug for WindowEvent {
             WindowEvent::PinchZoom(..) => write!(f, "PinchZoom"),
             WindowEvent::Navigation(..) => write!(f, "Navigation"),
             WindowEvent::Quit => write!(f, "Quit"),
+            WindowEvent::ConfigurePaintWorkers(..) => write!(f, "ConfigurePaintWorkers"),
         }
     }
 }
@@ -135,4 +141,12 @@ pub trait WindowMethods {
 
     /// Process a key event.
     fn handle_key(&self, key: Key, mods: KeyModifiers);
+
+    /// Receives tiles of a layer that were painted into shared memory, with
+    /// `-Z shared-memory-tiles`. The window owns their surfaces; by default, it discards them.
+    fn shared_tiles_painted(&self, _: PipelineId, _: LayerId, tiles: Vec<SharedTile>) {
+        for tile in tiles.iter() {
+            SharedSurface::discard(&tile.surface)
+        }
+    }
 }
@@ -0,0 +1,305 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Generic ways to paint display items that a backend can't paint itself, or only slowly, out of
+//! what every backend can do. Display items pick these over the backend's own drawing according
+//! to its `BackendCapabilities`.
+//!
+//! The fallbacks paint images: gradients and wavy lines are rasterized at the resolution they are
+//! drawn at, and blurred shadows at one low enough for the blur to be cheap, then scaled up.
+
+use backends::raster;
+use backends::software::{Pixel, gradient_color, premultiply, repeat};
+use display_list::{BoxShadowClipMode, ClippingRegion, ImageOrientation};
+use paint_backend::{Color, GradientStop, PaintBackend};
+use paint_context::ToAzureRect;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::f32::consts::PI;
+use std::num::Float;
+use std::sync::Arc;
+use style::computed_values::image_rendering;
+use util::geometry::Au;
+
+/// The largest width or height, in pixels, of the images that fallbacks paint. Larger ones are
+/// painted at a lower resolution.
+static MAX_IMAGE_SIZE: f32 = 2048.0;
+
+/// The standard deviation, in image pixels, that approximate blurs are done with.
+static APPROXIMATE_STD_DEVIATION: f32 = 2.0;
+
+/// The number of standard deviations that blurs are taken to spread over.
+static BLUR_EXTENT: f32 = 3.0;
+
+/// The length of each wave of a wavy line, in thicknesses of the line.
+static WAVE_LENGTH: f32 = 6.0;
+
+/// The number of straight segments that each wave of a wavy line is flattened into.
+static WAVE_SEGMENTS: f32 = 8.0;
+
+/// Returns how many pixels of the backend's surface a px currently covers, on average.
+pub fn device_scale(paint_backend: &PaintBackend) -> f32 {
+    let size = paint_backend.device_size(&Rect(Point2D(0.0, 0.0), Size2D(1.0, 1.0)));
+    (size.width * size.height).sqrt()
+}
+
+/// Paints a linear gradient as an image of it.
+pub fn draw_linear_gradient(paint_backend: &mut PaintBackend,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool) {
+    if stops.is_empty() {
+        return
+    }
+    let rect = bounds.to_azure_rect();
+    let (width, height) = image_size(paint_backend, &rect, 1.0);
+    if width == 0 || height == 0 {
+        return
+    }
+
+    let start = Point2D(start_point.x.to_subpx() as f32, start_point.y.to_subpx() as f32);
+    let end = Point2D(end_point.x.to_subpx() as f32, end_point.y.to_subpx() as f32);
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    let pixels = gradient_pixels(&rect, width, height, stops, |point_x, point_y| {
+        let position = if length_squared > 0.0 {
+            ((point_x - start.x) * dx + (point_y - start.y) * dy) / length_squared
+        } else {
+            0.0
+        };
+        if repeating { repeat(position) } else { position }
+    });
+    draw_pixels(paint_backend, bounds, width, height, pixels)
+}
+
+/// Paints a radial gradient as an image of it.
+pub fn draw_radial_gradient(paint_backend: &mut PaintBackend,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]) {
+    if stops.is_empty() {
+        return
+    }
+    let rect = bounds.to_azure_rect();
+    let (width, height) = image_size(paint_backend, &rect, 1.0);
+    if width == 0 || height == 0 {
+        return
+    }
+
+    let to_unit_circle = match raster::invert(&raster::radial_gradient_transform(center, radius)) {
+        Some(to_unit_circle) => to_unit_circle,
+        None => return,
+    };
+    let pixels = gradient_pixels(&rect, width, height, stops, |point_x, point_y| {
+        let point = to_unit_circle.transform_point(&Point2D(point_x, point_y));
+        (point.x * point.x + point.y * point.y).sqrt()
+    });
+    draw_pixels(paint_backend, bounds, width, height, pixels)
+}
+
+/// Returns the pixels of an image of a gradient over `rect`, with the position along the gradient
+/// at each point given by `position`.
+fn gradient_pixels<F>(rect: &Rect<f32>,
+                      width: usize,
+                      height: usize,
+                      stops: &[GradientStop],
+                      position: F)
+                      -> Vec<u8>
+                      where F: Fn(f32, f32) -> f32 {
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let point_y = rect.origin.y + (y as f32 + 0.5) * rect.size.height / height as f32;
+        for x in 0..width {
+            let point_x = rect.origin.x +
+                (x as f32 + 0.5) * rect.size.width / width as f32;
+            push_pixel(&mut pixels, &gradient_color(stops, position(point_x, point_y)), 1.0)
+        }
+    }
+    pixels
+}
+
+/// Paints a box shadow with an approximate blur: the shadow is rasterized and blurred at a
+/// resolution at which the blur is `APPROXIMATE_STD_DEVIATION` pixels, or at that of the surface
+/// if that is lower, and painted scaled up.
+pub fn draw_box_shadow(paint_backend: &mut PaintBackend,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+    let shadow_bounds = box_bounds.translate(offset).inflate(spread_radius, spread_radius);
+    let margin = Au::from_frac32_px(blur_radius.to_frac32_px() * BLUR_EXTENT);
+    let region = if clip_mode == BoxShadowClipMode::Inset {
+        *box_bounds
+    } else {
+        shadow_bounds.inflate(margin, margin)
+    };
+    let region_rect = region.to_azure_rect();
+
+    let std_deviation = blur_radius.to_frac32_px();
+    let device_std_deviation = std_deviation * device_scale(paint_backend);
+    let resolution = if device_std_deviation > APPROXIMATE_STD_DEVIATION {
+        APPROXIMATE_STD_DEVIATION / device_std_deviation
+    } else {
+        1.0
+    };
+    let (width, height) = image_size(paint_backend, &region_rect, resolution);
+    if width == 0 || height == 0 {
+        return
+    }
+
+    // Rasterize in the pixels of the image, with room around it for the blur to pull from.
+    let (x_scale, y_scale) = (width as f32 / region_rect.size.width,
+                              height as f32 / region_rect.size.height);
+    let transform: Matrix2D<f32> = Matrix2D::identity();
+    let transform = transform.scale(x_scale, y_scale)
+                             .translate(-region_rect.origin.x, -region_rect.origin.y);
+    let image_std_deviation = std_deviation * (x_scale * y_scale).sqrt();
+    let image_margin = (image_std_deviation * BLUR_EXTENT).ceil() as i32;
+    let image_bounds = Rect(Point2D(0, 0), Size2D(width as i32, height as i32));
+    let mask_bounds = Rect(Point2D(-image_margin, -image_margin),
+                           Size2D(width as i32 + image_margin * 2,
+                                  height as i32 + image_margin * 2));
+
+    let shadow = [raster::rect_polygon(&shadow_bounds.to_azure_rect(), &transform)];
+    let mut shadow = raster::rasterize(&shadow, &mask_bounds);
+    if clip_mode == BoxShadowClipMode::Inset {
+        shadow = shadow.invert(&mask_bounds)
+    }
+    if image_std_deviation > 0.0 {
+        shadow.blur(image_std_deviation)
+    }
+    let box_mask = [raster::rect_polygon(&box_bounds.to_azure_rect(), &transform)];
+    let box_mask = raster::rasterize(&box_mask, &image_bounds);
+    let mask = match clip_mode {
+        BoxShadowClipMode::None => shadow,
+        BoxShadowClipMode::Outset => shadow.intersect(&box_mask.invert(&image_bounds)),
+        BoxShadowClipMode::Inset => shadow.intersect(&box_mask),
+    };
+
+    let color = premultiply(&color);
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..(height as i32) {
+        for x in 0..(width as i32) {
+            push_pixel(&mut pixels, &color, mask.get(x, y))
+        }
+    }
+
+    // Like the backends' own shadows, draw outside the clips, since shadows may reach beyond
+    // them. The next display item sets its own clip.
+    paint_backend.set_clip(&ClippingRegion::max());
+    draw_pixels(paint_backend, &region, width, height, pixels)
+}
+
+/// Paints a wavy line, such as a wavy underline, as an image of it. The wave runs along the middle
+/// of `rect`, down it if `vertical` and across it otherwise, as thick as `rect` is the other way,
+/// and its crests are that thickness beyond `rect`.
+pub fn draw_wavy_line(paint_backend: &mut PaintBackend,
+                      rect: &Rect<Au>,
+                      vertical: bool,
+                      color: Color) {
+    let region = if vertical {
+        rect.inflate(rect.size.width, Au(0))
+    } else {
+        rect.inflate(Au(0), rect.size.height)
+    };
+    let region_rect = region.to_azure_rect();
+    let (width, height) = image_size(paint_backend, &region_rect, 1.0);
+    if width == 0 || height == 0 {
+        return
+    }
+
+    let rect = rect.to_azure_rect();
+    let (length, thickness) = if vertical {
+        (rect.size.height, rect.size.width)
+    } else {
+        (rect.size.width, rect.size.height)
+    };
+    let transform: Matrix2D<f32> = Matrix2D::identity();
+    let transform = transform.scale(width as f32 / region_rect.size.width,
+                                    height as f32 / region_rect.size.height)
+                             .translate(-region_rect.origin.x, -region_rect.origin.y);
+    let point = |along: f32, across: f32| {
+        let point = if vertical {
+            Point2D(rect.origin.x + rect.size.width / 2.0 + across, rect.origin.y + along)
+        } else {
+            Point2D(rect.origin.x + along, rect.origin.y + rect.size.height / 2.0 + across)
+        };
+        transform.transform_point(&point)
+    };
+
+    // Go along one edge of the wave and back along the other.
+    let wave_length = thickness * WAVE_LENGTH;
+    let segments = (length / wave_length * WAVE_SEGMENTS).ceil().max(1.0) as usize;
+    let mut wave = Vec::with_capacity((segments + 1) * 2);
+    for segment in 0..(segments + 1) {
+        let along = length * segment as f32 / segments as f32;
+        let across = thickness * (along / wave_length * 2.0 * PI).sin();
+        wave.push(point(along, across - thickness / 2.0))
+    }
+    for segment in (0..(segments + 1)).rev() {
+        let along = length * segment as f32 / segments as f32;
+        let across = thickness * (along / wave_length * 2.0 * PI).sin();
+        wave.push(point(along, across + thickness / 2.0))
+    }
+
+    let image_bounds = Rect(Point2D(0, 0), Size2D(width as i32, height as i32));
+    let mask = raster::rasterize(&[wave], &image_bounds);
+    let color = premultiply(&color);
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..(height as i32) {
+        for x in 0..(width as i32) {
+            push_pixel(&mut pixels, &color, mask.get(x, y))
+        }
+    }
+    draw_pixels(paint_backend, &region, width, height, pixels)
+}
+
+/// Returns the size of an image that covers `rect`, in px, at `resolution` times the resolution
+/// of the surface, within `MAX_IMAGE_SIZE`.
+fn image_size(paint_backend: &PaintBackend, rect: &Rect<f32>, resolution: f32)
+              -> (usize, usize) {
+    if rect.size.width <= 0.0 || rect.size.height <= 0.0 {
+        return (0, 0)
+    }
+    let device_size = paint_backend.device_size(rect);
+    let size = |length: f32| (length * resolution).ceil().max(1.0).min(MAX_IMAGE_SIZE) as usize;
+    (size(device_size.width), size(device_size.height))
+}
+
+/// Paints a `width` by `height` image of premultiplied BGRA pixels, as decoded images have them,
+/// into `bounds`.
+fn draw_pixels(paint_backend: &mut PaintBackend,
+               bounds: &Rect<Au>,
+               width: usize,
+               height: usize,
+               pixels: Vec<u8>) {
+    let image = Image::new(width as u32, height as u32, PixelsByColorType::RGBA8(pixels));
+    paint_backend.draw_image(bounds,
+                             Arc::new(image),
+                             None,
+                             image_rendering::T::Auto,
+                             ImageOrientation::identity())
+}
+
+/// Appends the premultiplied RGBA color, scaled by `coverage`, as a BGRA pixel.
+fn push_pixel(pixels: &mut Vec<u8>, color: &Pixel, coverage: f32) {
+    pixels.push_all(&[to_byte(color[2] * coverage),
+                      to_byte(color[1] * coverage),
+                      to_byte(color[0] * coverage),
+                      to_byte(color[3] * coverage)])
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+}
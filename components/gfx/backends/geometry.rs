@@ -0,0 +1,235 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A painting backend that passes everything on to another one, and notes down where each display
+//! item ends up on the surface as it goes, for accessibility tools and overlays that need to know
+//! where things were painted without going over the display list again.
+
+use backends::raster;
+use display_list::{BorderRadii, BoxShadowClipMode, ClippingRegion, DisplayItem};
+use display_list::{ImageOrientation, OpaqueNode, TextDisplayItem, TextOrientation};
+use paint_backend::{BackendCapabilities, Color, GradientStop, PaintBackend};
+use paint_context::ToAzureRect;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use std::num::Float;
+use std::sync::Arc;
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ItemKind {
+    SolidColor,
+    Text,
+    Image,
+    Border,
+    Gradient,
+    Line,
+    BoxShadow,
+    ExternalTexture,
+    Iframe,
+}
+
+impl ItemKind {
+    /// Returns the kind of the display item.
+    pub fn of(item: &DisplayItem) -> ItemKind {
+        match *item {
+            DisplayItem::SolidColorClass(_) => ItemKind::SolidColor,
+            DisplayItem::TextClass(_) => ItemKind::Text,
+            DisplayItem::ImageClass(_) => ItemKind::Image,
+            DisplayItem::BorderClass(_) => ItemKind::Border,
+            DisplayItem::GradientClass(_) |
+            DisplayItem::RadialGradientClass(_) => ItemKind::Gradient,
+            DisplayItem::LineClass(_) => ItemKind::Line,
+            DisplayItem::BoxShadowClass(_) => ItemKind::BoxShadow,
+            DisplayItem::ExternalTextureClass(_) => ItemKind::ExternalTexture,
+            DisplayItem::IframeClass(_) => ItemKind::Iframe,
+        }
+    }
+}
+
+/// Where a display item was painted, in pixels of the surface.
+#[derive(Clone, Copy, Debug)]
+pub struct ItemGeometry {
+    /// The DOM node that the item came from.
+    pub node: OpaqueNode,
+    pub kind: ItemKind,
+    /// The smallest rect around the item.
+    pub bounds: Rect<f32>,
+    /// The part of `bounds` that the clip of the item lets through, or `None` if it hides all of
+    /// it. The clips of the stacking contexts around the item aren't taken into account.
+    pub visible_bounds: Option<Rect<f32>>,
+    /// For text, where its baseline starts and where it ends.
+    pub baseline: Option<(Point2D<f32>, Point2D<f32>)>,
+}
+
+pub struct GeometryBackend<'a> {
+    paint_backend: &'a mut (PaintBackend + 'a),
+    items: Vec<ItemGeometry>,
+}
+
+impl<'a> GeometryBackend<'a> {
+    pub fn new(paint_backend: &'a mut PaintBackend) -> GeometryBackend<'a> {
+        GeometryBackend {
+            paint_backend: paint_backend,
+            items: Vec::new(),
+        }
+    }
+
+    /// Returns where the display items were painted, in the order they were.
+    pub fn into_items(self) -> Vec<ItemGeometry> {
+        self.items
+    }
+}
+
+impl<'a> PaintBackend for GeometryBackend<'a> {
+    fn transform(&self) -> Matrix2D<f32> {
+        self.paint_backend.transform()
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        self.paint_backend.capabilities()
+    }
+
+    fn push_stacking_context(&mut self,
+                             tile_bounds: &Rect<f32>,
+                             transform: &Matrix2D<f32>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T) {
+        self.paint_backend.push_stacking_context(tile_bounds,
+                                                 transform,
+                                                 clip_rect,
+                                                 filters,
+                                                 blend_mode)
+    }
+
+    fn pop_stacking_context(&mut self) {
+        self.paint_backend.pop_stacking_context()
+    }
+
+    fn set_clip(&mut self, clip: &ClippingRegion) {
+        self.paint_backend.set_clip(clip)
+    }
+
+    fn clear(&mut self) {
+        self.paint_backend.clear()
+    }
+
+    fn begin_display_item(&mut self, item: &DisplayItem) {
+        let transform = self.paint_backend.transform();
+        let base = item.base();
+        let bounds = device_bounds(&base.bounds, &transform);
+        let visible_bounds = bounds.intersection(&device_bounds(&base.clip.main, &transform));
+        let baseline = match *item {
+            DisplayItem::TextClass(ref text) => Some(baseline(text, &transform)),
+            _ => None,
+        };
+        self.items.push(ItemGeometry {
+            node: base.metadata.node,
+            kind: ItemKind::of(item),
+            bounds: bounds,
+            visible_bounds: visible_bounds,
+            baseline: baseline,
+        });
+
+        self.paint_backend.begin_display_item(item)
+    }
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: Color) {
+        self.paint_backend.draw_solid_color(bounds, color)
+    }
+
+    fn draw_text(&mut self, text: &TextDisplayItem) {
+        self.paint_backend.draw_text(text)
+    }
+
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<f32>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation) {
+        self.paint_backend.draw_image(bounds, image, source_rect, image_rendering, orientation)
+    }
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<Color>,
+                   style: &SideOffsets2D<border_style::T>) {
+        self.paint_backend.draw_border(bounds, border, radius, color, style)
+    }
+
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool) {
+        self.paint_backend.draw_linear_gradient(bounds, start_point, end_point, stops, repeating)
+    }
+
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]) {
+        self.paint_backend.draw_radial_gradient(bounds, center, radius, stops)
+    }
+
+    fn draw_line(&mut self, bounds: &Rect<Au>, color: Color, style: border_style::T) {
+        self.paint_backend.draw_line(bounds, color, style)
+    }
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+        self.paint_backend.draw_box_shadow(box_bounds,
+                                           offset,
+                                           color,
+                                           blur_radius,
+                                           spread_radius,
+                                           clip_mode)
+    }
+}
+
+/// Returns the smallest rect of the surface that contains the rect after the transform.
+fn device_bounds(rect: &Rect<Au>, transform: &Matrix2D<f32>) -> Rect<f32> {
+    let polygon = raster::rect_polygon(&rect.to_azure_rect(), transform);
+    let (mut left, mut top) = (Float::infinity(), Float::infinity());
+    let (mut right, mut bottom) = (Float::neg_infinity(), Float::neg_infinity());
+    for point in polygon.iter() {
+        left = point.x.min(left);
+        top = point.y.min(top);
+        right = point.x.max(right);
+        bottom = point.y.max(bottom);
+    }
+    Rect(Point2D(left, top), Size2D(right - left, bottom - top))
+}
+
+/// Returns where the baseline of the text starts and ends on the surface. Sideways text runs up,
+/// or down, from its baseline origin.
+fn baseline(text: &TextDisplayItem, transform: &Matrix2D<f32>)
+            -> (Point2D<f32>, Point2D<f32>) {
+    let origin = Point2D(text.baseline_origin.x.to_subpx() as f32,
+                         text.baseline_origin.y.to_subpx() as f32);
+    let advance = text.text_run.advance_for_range(&text.range).to_subpx() as f32;
+    let end = match text.orientation {
+        TextOrientation::Upright => Point2D(origin.x + advance, origin.y),
+        TextOrientation::SidewaysLeft => Point2D(origin.x, origin.y - advance),
+        TextOrientation::SidewaysRight => Point2D(origin.x, origin.y + advance),
+    };
+    (transform.transform_point(&origin), transform.transform_point(&end))
+}
@@ -0,0 +1,408 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The outlines of glyphs, read from the `glyf` table of TrueType fonts, for backends that draw
+//! text without a font library.
+//!
+//! Outlines are neither hinted nor grid fitted, so glyphs come out a little blurrier than those
+//! that a font library draws at small sizes.
+//!
+//! TODO: Fonts with PostScript outlines, and system fonts whose files aren't loaded, have no
+//! outlines here, so their text isn't drawn.
+
+use backends::raster::Polygon;
+use platform::font_template::FontTemplateData;
+use text::glyph::GlyphId;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use std::collections::HashMap;
+use std::num::Float;
+use std::sync::Arc;
+
+/// The largest distance, in pixels, between a flattened curve and the true curve.
+static CURVE_TOLERANCE: f32 = 0.1;
+
+/// How deeply composite glyphs may nest, so that fonts whose glyphs refer to each other in a loop
+/// don't recurse forever.
+static MAX_COMPONENT_DEPTH: u32 = 8;
+
+// The flags of the points of simple glyphs.
+static ON_CURVE_POINT: u8 = 0x01;
+static X_SHORT_VECTOR: u8 = 0x02;
+static Y_SHORT_VECTOR: u8 = 0x04;
+static REPEAT_FLAG: u8 = 0x08;
+static X_IS_SAME_OR_POSITIVE: u8 = 0x10;
+static Y_IS_SAME_OR_POSITIVE: u8 = 0x20;
+
+// The flags of the components of composite glyphs.
+static ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+static ARGS_ARE_XY_VALUES: u16 = 0x0002;
+static WE_HAVE_A_SCALE: u16 = 0x0008;
+static MORE_COMPONENTS: u16 = 0x0020;
+static WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+static WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+/// A point of an outline, in ems from the origin of the glyph with y going up, and whether it is
+/// on the curve rather than the control point of a quadratic Bézier curve.
+#[derive(Clone, Copy, Debug)]
+pub struct OutlinePoint {
+    pub point: Point2D<f32>,
+    pub on_curve: bool,
+}
+
+/// A closed contour of an outline. It starts on the curve, and there is a point on the curve
+/// between any two control points.
+pub type Contour = Vec<OutlinePoint>;
+
+/// The outlines of the glyphs of a font, read as they are needed.
+pub struct GlyphOutlines {
+    template: Arc<FontTemplateData>,
+    units_per_em: f32,
+    /// Whether the `loca` table has 32-bit offsets rather than 16-bit ones.
+    long_offsets: bool,
+    /// The offsets and lengths of the `loca` and `glyf` tables in the font file.
+    loca: (usize, usize),
+    glyf: (usize, usize),
+    outlines: HashMap<GlyphId, Vec<Contour>>,
+}
+
+impl GlyphOutlines {
+    /// Reads the tables of the font that outlines are found with. Returns `None` if the font has
+    /// no TrueType outlines, or if its file isn't loaded.
+    pub fn new(template: Arc<FontTemplateData>) -> Option<GlyphOutlines> {
+        let (units_per_em, long_offsets, loca, glyf) = {
+            let data = match template.bytes() {
+                Some(data) => data,
+                None => return None,
+            };
+            let (head, loca, glyf) = match (find_table(data, b"head"),
+                                            find_table(data, b"loca"),
+                                            find_table(data, b"glyf")) {
+                (Some(head), Some(loca), Some(glyf)) if head.1 >= 54 => (head, loca, glyf),
+                _ => return None,
+            };
+            let units_per_em = read_u16(data, head.0 + 18);
+            if units_per_em == 0 {
+                return None
+            }
+            (units_per_em as f32, read_u16(data, head.0 + 50) != 0, loca, glyf)
+        };
+        Some(GlyphOutlines {
+            template: template,
+            units_per_em: units_per_em,
+            long_offsets: long_offsets,
+            loca: loca,
+            glyf: glyf,
+            outlines: HashMap::new(),
+        })
+    }
+
+    /// Returns the contours of the glyph, which are empty for glyphs that draw nothing, such as
+    /// spaces, and for glyphs that the font doesn't have.
+    pub fn outline(&mut self, glyph: GlyphId) -> &[Contour] {
+        if !self.outlines.contains_key(&glyph) {
+            let mut contours = Vec::new();
+            if let Some(data) = self.template.bytes() {
+                let to_ems = Matrix2D::new(1.0 / self.units_per_em, 0.0,
+                                           0.0, 1.0 / self.units_per_em,
+                                           0.0, 0.0);
+                self.read_glyph(data, glyph, 0, &to_ems, &mut contours)
+            }
+            self.outlines.insert(glyph, contours);
+        }
+        &self.outlines[&glyph]
+    }
+
+    /// Returns the data of the glyph in the `glyf` table, or `None` if it is empty.
+    fn glyph_data<'a>(&self, data: &'a [u8], glyph: GlyphId) -> Option<&'a [u8]> {
+        let (loca_offset, loca_length) = self.loca;
+        let index = glyph as usize;
+        let (start, end) = if self.long_offsets {
+            if (index + 2) * 4 > loca_length {
+                return None
+            }
+            (read_u32(data, loca_offset + index * 4) as usize,
+             read_u32(data, loca_offset + index * 4 + 4) as usize)
+        } else {
+            if (index + 2) * 2 > loca_length {
+                return None
+            }
+            (read_u16(data, loca_offset + index * 2) as usize * 2,
+             read_u16(data, loca_offset + index * 2 + 2) as usize * 2)
+        };
+        let (glyf_offset, glyf_length) = self.glyf;
+        if start >= end || end > glyf_length {
+            return None
+        }
+        Some(&data[glyf_offset + start..glyf_offset + end])
+    }
+
+    /// Adds the contours of the glyph, in font units, mapped by `transform`, to `contours`.
+    fn read_glyph(&self,
+                  data: &[u8],
+                  glyph: GlyphId,
+                  depth: u32,
+                  transform: &Matrix2D<f32>,
+                  contours: &mut Vec<Contour>) {
+        let glyph_data = match self.glyph_data(data, glyph) {
+            Some(glyph_data) => glyph_data,
+            None => return,
+        };
+        let contour_count = read_u16(glyph_data, 0) as i16;
+        if contour_count >= 0 {
+            read_simple_glyph(glyph_data, contour_count as usize, transform, contours)
+        } else if depth < MAX_COMPONENT_DEPTH {
+            self.read_composite_glyph(data, glyph_data, depth, transform, contours)
+        }
+    }
+
+    /// Adds the contours of each component of a composite glyph, placed as the glyph places it.
+    fn read_composite_glyph(&self,
+                            data: &[u8],
+                            glyph_data: &[u8],
+                            depth: u32,
+                            transform: &Matrix2D<f32>,
+                            contours: &mut Vec<Contour>) {
+        let mut offset = 10;
+        loop {
+            let flags = read_u16(glyph_data, offset);
+            let component = read_u16(glyph_data, offset + 2) as GlyphId;
+            offset += 4;
+
+            let (x, y) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                offset += 4;
+                (read_u16(glyph_data, offset - 4) as i16 as f32,
+                 read_u16(glyph_data, offset - 2) as i16 as f32)
+            } else {
+                offset += 2;
+                (read_u8(glyph_data, offset - 2) as i8 as f32,
+                 read_u8(glyph_data, offset - 1) as i8 as f32)
+            };
+            // Components that are placed by matching points are left at the origin.
+            let (x, y) = if flags & ARGS_ARE_XY_VALUES != 0 { (x, y) } else { (0.0, 0.0) };
+
+            let (m11, m12, m21, m22) = if flags & WE_HAVE_A_SCALE != 0 {
+                offset += 2;
+                let scale = read_f2dot14(glyph_data, offset - 2);
+                (scale, 0.0, 0.0, scale)
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                offset += 4;
+                (read_f2dot14(glyph_data, offset - 4),
+                 0.0,
+                 0.0,
+                 read_f2dot14(glyph_data, offset - 2))
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                offset += 8;
+                (read_f2dot14(glyph_data, offset - 8),
+                 read_f2dot14(glyph_data, offset - 6),
+                 read_f2dot14(glyph_data, offset - 4),
+                 read_f2dot14(glyph_data, offset - 2))
+            } else {
+                (1.0, 0.0, 0.0, 1.0)
+            };
+
+            let component_transform = Matrix2D::new(m11, m12, m21, m22, x, y);
+            self.read_glyph(data,
+                            component,
+                            depth + 1,
+                            &transform.mul(&component_transform),
+                            contours);
+            if flags & MORE_COMPONENTS == 0 {
+                break
+            }
+        }
+    }
+}
+
+/// Adds the contours of a glyph that has its own points, mapped by `transform`, to `contours`.
+fn read_simple_glyph(glyph_data: &[u8],
+                     contour_count: usize,
+                     transform: &Matrix2D<f32>,
+                     contours: &mut Vec<Contour>) {
+    let end_points: Vec<usize> = (0..contour_count).map(|index| {
+        read_u16(glyph_data, 10 + index * 2) as usize
+    }).collect();
+    let point_count = match end_points.last() {
+        Some(&end_point) => end_point + 1,
+        None => return,
+    };
+    let instruction_length = read_u16(glyph_data, 10 + contour_count * 2) as usize;
+    let mut offset = 12 + contour_count * 2 + instruction_length;
+
+    let mut flags = Vec::with_capacity(point_count);
+    while flags.len() < point_count {
+        let flag = read_u8(glyph_data, offset);
+        offset += 1;
+        flags.push(flag);
+        if flag & REPEAT_FLAG != 0 {
+            let repeat_count = read_u8(glyph_data, offset);
+            offset += 1;
+            for _ in 0..repeat_count {
+                if flags.len() < point_count {
+                    flags.push(flag)
+                }
+            }
+        }
+    }
+
+    // Coordinates are stored as the differences from the point before, all of the x coordinates
+    // first.
+    let mut read_coordinates = |short_flag: u8, same_or_positive_flag: u8| {
+        let mut coordinate = 0;
+        flags.iter().map(|&flag| {
+            if flag & short_flag != 0 {
+                let delta = read_u8(glyph_data, offset) as i32;
+                offset += 1;
+                coordinate += if flag & same_or_positive_flag != 0 { delta } else { -delta }
+            } else if flag & same_or_positive_flag == 0 {
+                coordinate += read_u16(glyph_data, offset) as i16 as i32;
+                offset += 2
+            }
+            coordinate as f32
+        }).collect::<Vec<f32>>()
+    };
+    let xs = read_coordinates(X_SHORT_VECTOR, X_IS_SAME_OR_POSITIVE);
+    let ys = read_coordinates(Y_SHORT_VECTOR, Y_IS_SAME_OR_POSITIVE);
+
+    let points: Vec<OutlinePoint> = flags.iter().enumerate().map(|(index, &flag)| {
+        OutlinePoint {
+            point: transform.transform_point(&Point2D(xs[index], ys[index])),
+            on_curve: flag & ON_CURVE_POINT != 0,
+        }
+    }).collect();
+    let mut start = 0;
+    for &end_point in end_points.iter() {
+        if end_point < start || end_point >= point_count {
+            break
+        }
+        push_contour(&points[start..end_point + 1], contours);
+        start = end_point + 1
+    }
+}
+
+/// Adds the contour with the given points to `contours`, starting it on the curve and putting
+/// the points on the curve that TrueType leaves out between control points.
+fn push_contour(points: &[OutlinePoint], contours: &mut Vec<Contour>) {
+    if points.len() < 2 {
+        return
+    }
+    let (start, rest): (OutlinePoint, Vec<OutlinePoint>) =
+            match points.iter().position(|point| point.on_curve) {
+        Some(index) => {
+            (points[index], points[index + 1..].iter().chain(points[..index].iter())
+                                                      .cloned()
+                                                      .collect())
+        }
+        None => {
+            let start = OutlinePoint {
+                point: midpoint(&points[points.len() - 1].point, &points[0].point),
+                on_curve: true,
+            };
+            (start, points.to_vec())
+        }
+    };
+
+    let mut contour = Vec::with_capacity(points.len() * 2);
+    contour.push(start);
+    for point in rest.iter() {
+        let last = contour[contour.len() - 1];
+        if !last.on_curve && !point.on_curve {
+            contour.push(OutlinePoint {
+                point: midpoint(&last.point, &point.point),
+                on_curve: true,
+            })
+        }
+        contour.push(*point)
+    }
+    contours.push(contour)
+}
+
+/// Returns the polygon that the contour, mapped by `transform` into device pixels, is flattened
+/// into.
+pub fn flatten(contour: &Contour, transform: &Matrix2D<f32>) -> Polygon {
+    let mut polygon = Vec::with_capacity(contour.len() * 2);
+    let start = transform.transform_point(&contour[0].point);
+    polygon.push(start);
+    let mut control_point = None;
+    for point in contour[1..].iter().chain(contour[..1].iter()) {
+        let device_point = transform.transform_point(&point.point);
+        if !point.on_curve {
+            control_point = Some(device_point);
+            continue
+        }
+        if let Some(control_point) = control_point.take() {
+            let from = polygon[polygon.len() - 1];
+            push_quadratic_curve(&mut polygon, &from, &control_point, &device_point)
+        } else {
+            polygon.push(device_point)
+        }
+    }
+    polygon
+}
+
+/// Adds the points of the curve from `from` to `to` after `from` to the polygon, closely enough
+/// that no part of the curve is more than `CURVE_TOLERANCE` from them.
+fn push_quadratic_curve(polygon: &mut Polygon,
+                        from: &Point2D<f32>,
+                        control_point: &Point2D<f32>,
+                        to: &Point2D<f32>) {
+    // The curve is at most a quarter of this far from its chord, and that distance shrinks with
+    // the square of the number of segments.
+    let deviation = Point2D(from.x - 2.0 * control_point.x + to.x,
+                            from.y - 2.0 * control_point.y + to.y);
+    let deviation = deviation.x.hypot(deviation.y) / 4.0;
+    let segment_count = (deviation / CURVE_TOLERANCE).sqrt().ceil().max(1.0) as usize;
+    for index in 1..segment_count + 1 {
+        let t = index as f32 / segment_count as f32;
+        let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * t * (1.0 - t), t * t);
+        polygon.push(Point2D(a * from.x + b * control_point.x + c * to.x,
+                             a * from.y + b * control_point.y + c * to.y))
+    }
+}
+
+fn midpoint(a: &Point2D<f32>, b: &Point2D<f32>) -> Point2D<f32> {
+    Point2D((a.x + b.x) / 2.0, (a.y + b.y) / 2.0)
+}
+
+/// Returns the offset and length of the table with the given tag, or `None` if the font doesn't
+/// have it.
+fn find_table(data: &[u8], tag: &[u8]) -> Option<(usize, usize)> {
+    let table_count = read_u16(data, 4) as usize;
+    for index in 0..table_count {
+        let record = 12 + index * 16;
+        if record + 16 > data.len() {
+            break
+        }
+        if &data[record..record + 4] == tag {
+            let offset = read_u32(data, record + 8) as usize;
+            let length = read_u32(data, record + 12) as usize;
+            if offset > data.len() || length > data.len() - offset {
+                return None
+            }
+            return Some((offset, length))
+        }
+    }
+    None
+}
+
+// Values past the end of the data read as zero, so that broken fonts draw wrong glyphs rather than
+// crash.
+
+fn read_u8(data: &[u8], offset: usize) -> u8 {
+    if offset < data.len() { data[offset] } else { 0 }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    (read_u8(data, offset) as u16) << 8 | read_u8(data, offset + 1) as u16
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    (read_u16(data, offset) as u32) << 16 | read_u16(data, offset + 2) as u32
+}
+
+/// Reads a signed 2.14 fixed point number.
+fn read_f2dot14(data: &[u8], offset: usize) -> f32 {
+    read_u16(data, offset) as i16 as f32 / 16384.0
+}
@@ -0,0 +1,16 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Painting backends other than the Azure one in `paint_context`.
+
+pub mod fallback;
+pub mod geometry;
+pub mod glyph_outline;
+pub mod pdf;
+pub mod quad_batch;
+pub mod quad_renderer;
+pub mod raster;
+pub mod recording;
+pub mod software;
+pub mod svg;
@@ -0,0 +1,1152 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A painting backend that records display lists as PDF documents, for printing.
+//!
+//! Each page is a content stream of PDF drawing operators. Shapes are written as vector paths in
+//! the coordinates of the page, after the current transform. Text is written as the glyphs that
+//! it was shaped into, in the fonts it was shaped with, which are embedded. Text in system fonts
+//! whose data can't be read is written as its characters in Helvetica instead. Images are embedded
+//! as they are. Stacking contexts with opacity or a blend mode become transparency groups, and
+//! blurred box shadows are embedded as images of their masks.
+//!
+//! TODO: Filters other than `opacity`, text blur, synthetic bold and upright glyphs in sideways
+//! text aren't drawn. Gradient stops lose their alpha, and 3D border styles are drawn solid.
+//! Nothing is compressed.
+
+use backends::raster::{self, Polygon};
+use color::to_paint_color;
+use display_list::TextOrientation::{SidewaysLeft, SidewaysRight, Upright};
+use display_list::{self, BorderRadii, BoxShadowClipMode, ClippingRegion, ImageOrientation};
+use display_list::TextDisplayItem;
+use filters;
+use font::{SYNTHETIC_OBLIQUE_FONT_FLAG, SYNTHETIC_OBLIQUE_SKEW};
+use paint_backend::{BackendCapabilities, BlurCost, Color, GradientStop, PaintBackend, ToRadiiPx};
+use paint_backend::ToSideOffsetsPx;
+use paint_context::ToAzureRect;
+use platform::font_template::FontTemplateData;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::borrow::ToOwned;
+use std::collections::BTreeMap;
+use std::mem;
+use std::num::Float;
+use std::sync::Arc;
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+use util::opts::ColorSpace;
+
+/// The size of a CSS pixel in PDF points.
+static POINTS_PER_PX: f32 = 0.75;
+
+/// The largest coordinate that is written. PDF readers only have to handle numbers up to this
+/// size, and clip rects can be much larger.
+static MAX_COORDINATE: f32 = 32767.0;
+
+/// The number of standard deviations of a blur that shadow masks leave room for.
+static BLUR_EXTENT: f32 = 3.0;
+
+/// The most copies of their stops that repeating gradients are drawn with. Ones that would take
+/// more are drawn without repeating.
+static MAX_GRADIENT_REPEATS: usize = 1000;
+
+/// The objects whose numbers are known up front.
+static CATALOG_OBJECT: usize = 1;
+static PAGES_OBJECT: usize = 2;
+/// All pages and transparency groups share one resource dictionary.
+static RESOURCES_OBJECT: usize = 3;
+
+pub struct PdfBackend {
+    /// The bodies of the objects written so far. Object `n` is at index `n - 1`. Objects that
+    /// can only be written once the document is finished are `None` until then.
+    objects: Vec<Option<Vec<u8>>>,
+    /// The object numbers of the finished pages.
+    pages: Vec<usize>,
+    /// The size of the current page in px.
+    page_size: Size2D<f32>,
+    /// The content stream of the current page, followed by those of the transparency groups
+    /// being painted.
+    content: Vec<String>,
+    transform: Matrix2D<f32>,
+    /// The clips of the stacking contexts being painted, in page coordinates.
+    clip: Vec<Polygon>,
+    /// The clip that `set_clip()` set last, and the polygons it was turned into.
+    transient_clip: Option<(ClippingRegion, Vec<Polygon>)>,
+    saved_states: Vec<SavedState>,
+    fonts: Vec<EmbeddedFont>,
+    /// The images embedded so far, with whether they are interpolated and their names.
+    images: Vec<(Arc<Image>, bool, String)>,
+    /// The names and object numbers of the images and transparency groups.
+    xobjects: Vec<(String, usize)>,
+    /// The opacities and blend modes of the graphics states made so far, with their names and
+    /// object numbers.
+    graphics_states: Vec<(f32, mix_blend_mode::T, String, usize)>,
+    /// The names and object numbers of the gradients.
+    shadings: Vec<(String, usize)>,
+}
+
+/// The state that `push_stacking_context()` saves and `pop_stacking_context()` restores.
+struct SavedState {
+    transform: Matrix2D<f32>,
+    clip: Vec<Polygon>,
+    transient_clip: Option<(ClippingRegion, Vec<Polygon>)>,
+    /// The opacity and blend mode to paint the stacking context's transparency group with, if it
+    /// was given one.
+    group: Option<(f32, mix_blend_mode::T)>,
+}
+
+/// A font that text has been written in.
+struct EmbeddedFont {
+    template: Arc<FontTemplateData>,
+    name: String,
+    /// The number of the font's object, which is written when the document is finished.
+    object: usize,
+    /// The ascent and descent of the font, in thousandths of its size.
+    ascent: f32,
+    descent: f32,
+    /// The characters that the glyphs written so far stand for, so that readers can copy text.
+    characters: BTreeMap<u16, char>,
+    /// True if the font's data can't be read, so the text is written as its characters in a
+    /// standard font rather than as glyphs of this one.
+    standard: bool,
+}
+
+/// The clips that a drawing operation is clipped to.
+#[derive(Clone, Copy, PartialEq)]
+enum Clips {
+    None,
+    StackingContext,
+    All,
+}
+
+impl PdfBackend {
+    pub fn new() -> PdfBackend {
+        PdfBackend {
+            objects: vec![None, None, None],
+            pages: Vec::new(),
+            page_size: Size2D(0.0, 0.0),
+            content: Vec::new(),
+            transform: Matrix2D::identity(),
+            clip: Vec::new(),
+            transient_clip: None,
+            saved_states: Vec::new(),
+            fonts: Vec::new(),
+            images: Vec::new(),
+            xobjects: Vec::new(),
+            graphics_states: Vec::new(),
+            shadings: Vec::new(),
+        }
+    }
+
+    /// Starts a new page of the given size. Until `end_page()`, the untransformed coordinates of
+    /// drawing are px from the top left of the page.
+    pub fn begin_page(&mut self, size: &Size2D<Au>) {
+        assert!(self.content.is_empty(), "the last page wasn't ended");
+        self.page_size = Size2D(size.width.to_frac32_px(), size.height.to_frac32_px());
+        self.transform = Matrix2D::identity();
+        self.clip = Vec::new();
+        self.transient_clip = None;
+
+        // Flip the page so that y goes down, and scale it from points to px.
+        self.content.push(format!("{} 0 0 {} 0 {} cm\n",
+                                  number(POINTS_PER_PX),
+                                  number(-POINTS_PER_PX),
+                                  number(self.page_size.height * POINTS_PER_PX)));
+    }
+
+    pub fn end_page(&mut self) {
+        assert!(self.saved_states.is_empty(), "a stacking context wasn't popped");
+        let content = self.content.pop().expect("no page to end");
+        assert!(self.content.is_empty());
+
+        let contents = self.add_stream("", content.as_bytes());
+        let page = format!("<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] \
+                            /Resources {} 0 R /Contents {} 0 R >>",
+                           PAGES_OBJECT,
+                           number(self.page_size.width * POINTS_PER_PX),
+                           number(self.page_size.height * POINTS_PER_PX),
+                           RESOURCES_OBJECT,
+                           contents);
+        let page = self.add_object(page.into_bytes());
+        self.pages.push(page)
+    }
+
+    /// Returns the finished document.
+    pub fn finish(mut self) -> Vec<u8> {
+        assert!(self.content.is_empty(), "the last page wasn't ended");
+
+        let fonts = mem::replace(&mut self.fonts, Vec::new());
+        let mut font_resources = String::new();
+        for font in fonts.iter() {
+            self.write_font(font);
+            font_resources.push_str(&format!("/{} {} 0 R ", font.name, font.object));
+        }
+        let mut xobject_resources = String::new();
+        for &(ref name, object) in self.xobjects.iter() {
+            xobject_resources.push_str(&format!("/{} {} 0 R ", name, object));
+        }
+        let mut graphics_state_resources = String::new();
+        for &(_, _, ref name, object) in self.graphics_states.iter() {
+            graphics_state_resources.push_str(&format!("/{} {} 0 R ", name, object));
+        }
+        let mut shading_resources = String::new();
+        for &(ref name, object) in self.shadings.iter() {
+            shading_resources.push_str(&format!("/{} {} 0 R ", name, object));
+        }
+        let resources = format!("<< /Font << {}>> /XObject << {}>> /ExtGState << {}>> \
+                                 /Shading << {}>> >>",
+                                font_resources,
+                                xobject_resources,
+                                graphics_state_resources,
+                                shading_resources);
+        self.objects[RESOURCES_OBJECT - 1] = Some(resources.into_bytes());
+
+        let kids: Vec<String> = self.pages.iter().map(|page| format!("{} 0 R", page)).collect();
+        let pages = format!("<< /Type /Pages /Kids [{}] /Count {} >>",
+                            kids.connect(" "),
+                            self.pages.len());
+        self.objects[PAGES_OBJECT - 1] = Some(pages.into_bytes());
+        let catalog = format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES_OBJECT);
+        self.objects[CATALOG_OBJECT - 1] = Some(catalog.into_bytes());
+
+        // The binary comment tells readers that the file contains binary data.
+        let mut result = b"%PDF-1.6\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (index, object) in self.objects.iter().enumerate() {
+            offsets.push(result.len());
+            result.push_all(format!("{} 0 obj\n", index + 1).as_bytes());
+            result.push_all(&object.as_ref().expect("object wasn't written")[..]);
+            result.push_all(b"\nendobj\n");
+        }
+        let cross_reference_table = result.len();
+        result.push_all(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+        for offset in offsets.iter() {
+            result.push_all(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        result.push_all(format!("trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+                                offsets.len() + 1,
+                                CATALOG_OBJECT,
+                                cross_reference_table).as_bytes());
+        result
+    }
+
+    fn add_object(&mut self, body: Vec<u8>) -> usize {
+        self.objects.push(Some(body));
+        self.objects.len()
+    }
+
+    /// Returns the number of an object that will be written later.
+    fn reserve_object(&mut self) -> usize {
+        self.objects.push(None);
+        self.objects.len()
+    }
+
+    /// Adds a stream object with the given entries in its dictionary besides its length.
+    fn add_stream(&mut self, dictionary: &str, data: &[u8]) -> usize {
+        let mut body =
+            format!("<< {} /Length {} >>\nstream\n", dictionary, data.len()).into_bytes();
+        body.push_all(data);
+        body.push_all(b"\nendstream");
+        self.add_object(body)
+    }
+
+    /// Returns the name of a graphics state with the given opacity and blend mode.
+    fn graphics_state(&mut self, alpha: f32, blend_mode: mix_blend_mode::T) -> String {
+        for &(state_alpha, state_blend_mode, ref name, _) in self.graphics_states.iter() {
+            if state_alpha == alpha && state_blend_mode == blend_mode {
+                return name.clone()
+            }
+        }
+        let name = format!("GS{}", self.graphics_states.len());
+        let state = format!("<< /Type /ExtGState /ca {} /CA {} /BM /{} >>",
+                            number(alpha),
+                            number(alpha),
+                            blend_mode_name(blend_mode));
+        let object = self.add_object(state.into_bytes());
+        self.graphics_states.push((alpha, blend_mode, name.clone(), object));
+        name
+    }
+
+    /// Saves the graphics state and clips the drawing that follows to the given clips, until
+    /// `end_drawing()`.
+    fn begin_drawing(&mut self, clips: Clips) {
+        let content = self.content.last_mut().expect("drawing outside of a page");
+        content.push_str("q\n");
+        if clips == Clips::None {
+            return
+        }
+        for polygon in self.clip.iter() {
+            write_polygon(content, polygon);
+            content.push_str("W n\n");
+        }
+        if clips == Clips::All {
+            if let Some((_, ref polygons)) = self.transient_clip {
+                for polygon in polygons.iter() {
+                    write_polygon(content, polygon);
+                    content.push_str("W n\n");
+                }
+            }
+        }
+    }
+
+    fn end_drawing(&mut self) {
+        self.content().push_str("Q\n")
+    }
+
+    fn content(&mut self) -> &mut String {
+        self.content.last_mut().expect("drawing outside of a page")
+    }
+
+    /// Sets the color of fills, and of strokes if `stroke` is true.
+    fn set_color(&mut self, color: &Color, stroke: bool) {
+        if color.a < 1.0 {
+            let graphics_state = self.graphics_state(color.a, mix_blend_mode::T::normal);
+            self.content().push_str(&format!("/{} gs\n", graphics_state));
+        }
+        let components = format!("{} {} {}", number(color.r), number(color.g), number(color.b));
+        self.content().push_str(&format!("{} rg\n", components));
+        if stroke {
+            self.content().push_str(&format!("{} RG\n", components));
+        }
+    }
+
+    /// Fills the polygons with the color, clipped to all the clips.
+    fn fill_polygons(&mut self, polygons: &[Polygon], color: &Color) {
+        self.begin_drawing(Clips::All);
+        self.set_color(color, false);
+        for polygon in polygons.iter() {
+            write_polygon(self.content(), polygon)
+        }
+        self.content().push_str("f\n");
+        self.end_drawing()
+    }
+
+    /// Returns the index of the font that the text run was shaped with, adding it if it is new.
+    fn font(&mut self, text: &TextDisplayItem) -> usize {
+        let run = &text.text_run;
+        if let Some(index) = self.fonts.iter().position(|font| {
+            font.template.identifier == run.font_template.identifier
+        }) {
+            return index
+        }
+
+        let size = run.actual_pt_size.to_subpx() as f32;
+        let (ascent, descent) = if size > 0.0 {
+            (run.font_metrics.ascent.to_subpx() as f32 / size * 1000.0,
+             run.font_metrics.descent.to_subpx() as f32 / size * 1000.0)
+        } else {
+            (1000.0, 0.0)
+        };
+        let object = self.reserve_object();
+        self.fonts.push(EmbeddedFont {
+            template: run.font_template.clone(),
+            name: format!("F{}", self.fonts.len()),
+            object: object,
+            ascent: ascent,
+            descent: descent,
+            characters: BTreeMap::new(),
+            standard: run.font_template.bytes().is_none(),
+        });
+        self.fonts.len() - 1
+    }
+
+    /// Writes the objects of an embedded font: a composite font whose character codes are glyph
+    /// ids, which refers to the font file. Fonts without data are written as Helvetica, whose
+    /// character codes are those of the Windows Latin 1 encoding.
+    fn write_font(&mut self, font: &EmbeddedFont) {
+        let bytes = match font.template.bytes() {
+            Some(bytes) => bytes,
+            None => {
+                let standard = "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica \
+                                /Encoding /WinAnsiEncoding >>";
+                self.objects[font.object - 1] = Some(standard.as_bytes().to_vec());
+                return
+            }
+        };
+        let base_font = base_font_name(&font.template.identifier);
+
+        // OpenType fonts with PostScript outlines need a different kind of font and font file
+        // from TrueType ones.
+        let postscript_outlines = bytes.starts_with(&b"OTTO"[..]);
+        let (font_file, font_file_key, font_subtype) = if postscript_outlines {
+            (self.add_stream("/Subtype /OpenType", bytes), "FontFile3", "CIDFontType0")
+        } else {
+            (self.add_stream(&format!("/Length1 {}", bytes.len()), bytes),
+             "FontFile2",
+             "CIDFontType2")
+        };
+
+        let descriptor = format!("<< /Type /FontDescriptor /FontName /{} /Flags 4 \
+                                  /FontBBox [0 {} 1000 {}] /ItalicAngle 0 /Ascent {} \
+                                  /Descent {} /CapHeight {} /StemV 80 /{} {} 0 R >>",
+                                 base_font,
+                                 number(-font.descent),
+                                 number(font.ascent),
+                                 number(font.ascent),
+                                 number(-font.descent),
+                                 number(font.ascent),
+                                 font_file_key,
+                                 font_file);
+        let descriptor = self.add_object(descriptor.into_bytes());
+        let glyph_map = if postscript_outlines { "" } else { " /CIDToGIDMap /Identity" };
+        let descendant = format!("<< /Type /Font /Subtype /{} /BaseFont /{} \
+                                  /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) \
+                                  /Supplement 0 >> /FontDescriptor {} 0 R{} >>",
+                                 font_subtype,
+                                 base_font,
+                                 descriptor,
+                                 glyph_map);
+        let descendant = self.add_object(descendant.into_bytes());
+        let to_unicode = to_unicode_cmap(&font.characters);
+        let to_unicode = self.add_stream("", to_unicode.as_bytes());
+
+        let type0 = format!("<< /Type /Font /Subtype /Type0 /BaseFont /{} /Encoding /Identity-H \
+                             /DescendantFonts [{} 0 R] /ToUnicode {} 0 R >>",
+                            base_font,
+                            descendant,
+                            to_unicode);
+        self.objects[font.object - 1] = Some(type0.into_bytes());
+    }
+
+    /// Returns the name of the image, embedding it if it is new. Returns `None` for empty images.
+    fn image(&mut self, image: &Arc<Image>, interpolate: bool) -> Option<String> {
+        if image.width == 0 || image.height == 0 {
+            return None
+        }
+        for &(ref embedded_image, embedded_interpolate, ref name) in self.images.iter() {
+            if &**embedded_image as *const Image == &**image as *const Image &&
+                    embedded_interpolate == interpolate {
+                return Some(name.clone())
+            }
+        }
+
+        let (colors, alphas) = image_data(&**image);
+        let soft_mask = if image.is_opaque {
+            String::new()
+        } else {
+            let dictionary = format!("/Type /XObject /Subtype /Image /Width {} /Height {} \
+                                      /ColorSpace /DeviceGray /BitsPerComponent 8",
+                                     image.width,
+                                     image.height);
+            format!(" /SMask {} 0 R", self.add_stream(&dictionary, &alphas[..]))
+        };
+        let dictionary = format!("/Type /XObject /Subtype /Image /Width {} /Height {} \
+                                  /ColorSpace /DeviceRGB /BitsPerComponent 8 /Interpolate {}{}",
+                                 image.width,
+                                 image.height,
+                                 interpolate,
+                                 soft_mask);
+        let object = self.add_stream(&dictionary, &colors[..]);
+        let name = format!("Im{}", self.xobjects.len());
+        self.xobjects.push((name.clone(), object));
+        self.images.push((image.clone(), interpolate, name.clone()));
+        Some(name)
+    }
+
+    /// Returns how much the current transform scales lengths, on average.
+    fn scale(&self) -> f32 {
+        let size = self.device_size(&Rect(Point2D(0.0, 0.0), Size2D(1.0, 1.0)));
+        (size.width * size.height).sqrt()
+    }
+}
+
+impl PaintBackend for PdfBackend {
+    fn transform(&self) -> Matrix2D<f32> {
+        self.transform
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        // The colors that PDF documents are written with are sRGB.
+        ColorSpace::Srgb
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        // PDF can't blur, so blurs are done on a mask of the shadow at the resolution of the page.
+        BackendCapabilities {
+            blur: BlurCost::Expensive,
+            ..BackendCapabilities::all()
+        }
+    }
+
+    fn push_stacking_context(&mut self,
+                             _: &Rect<f32>,
+                             transform: &Matrix2D<f32>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T) {
+        let group = if filters::temporary_draw_target_needed_for_style_filters(filters) ||
+                blend_mode != mix_blend_mode::T::normal {
+            let mut opacity = 1.0;
+            for style_filter in filters.filters.iter() {
+                match *style_filter {
+                    filter::Filter::Opacity(amount) => opacity *= amount as f32,
+                    _ => debug!("PDF backend: not applying filter {:?}", style_filter),
+                }
+            }
+            self.content.push(String::new());
+            Some((opacity, blend_mode))
+        } else {
+            None
+        };
+
+        let transform = mem::replace(&mut self.transform, *transform);
+        let mut clip = self.clip.clone();
+        if let Some(clip_rect) = clip_rect {
+            clip.push(raster::rect_polygon(&clip_rect.to_azure_rect(), &self.transform))
+        }
+        self.saved_states.push(SavedState {
+            transform: transform,
+            clip: mem::replace(&mut self.clip, clip),
+            transient_clip: self.transient_clip.take(),
+            group: group,
+        });
+    }
+
+    fn pop_stacking_context(&mut self) {
+        let saved_state = self.saved_states.pop().expect("no stacking context to pop");
+        self.transform = saved_state.transform;
+        self.clip = saved_state.clip;
+        self.transient_clip = saved_state.transient_clip;
+        if let Some((opacity, blend_mode)) = saved_state.group {
+            let content = self.content.pop().unwrap();
+            let dictionary = format!("/Type /XObject /Subtype /Form /BBox [0 0 {} {}] \
+                                      /Group << /S /Transparency >> /Resources {} 0 R",
+                                     number(self.page_size.width),
+                                     number(self.page_size.height),
+                                     RESOURCES_OBJECT);
+            let object = self.add_stream(&dictionary, content.as_bytes());
+            let name = format!("Fm{}", self.xobjects.len());
+            self.xobjects.push((name.clone(), object));
+
+            let graphics_state = self.graphics_state(opacity, blend_mode);
+            self.begin_drawing(Clips::StackingContext);
+            self.content().push_str(&format!("/{} gs /{} Do\n", graphics_state, name));
+            self.end_drawing()
+        }
+    }
+
+    fn set_clip(&mut self, clip: &ClippingRegion) {
+        if let Some((ref transient_clip, _)) = self.transient_clip {
+            if transient_clip == clip {
+                return
+            }
+        }
+
+        let mut polygons = vec![raster::rect_polygon(&clip.main.to_azure_rect(), &self.transform)];
+        for complex_region in clip.complex.iter() {
+            polygons.push(raster::rounded_rect_polygon(&complex_region.rect.to_azure_rect(),
+                                                       &complex_region.radii.to_radii_px(),
+                                                       &self.transform))
+        }
+        self.transient_clip = Some((clip.clone(), polygons))
+    }
+
+    fn clear(&mut self) {
+        // Pages start out blank, and there is nothing underneath them.
+    }
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: Color) {
+        let polygon = raster::rect_polygon(&bounds.to_azure_rect(), &self.transform);
+        self.fill_polygons(&[polygon], &color)
+    }
+
+    fn draw_text(&mut self, text: &TextDisplayItem) {
+        let font = self.font(text);
+        let standard = self.fonts[font].standard;
+
+        // Rotate sideways text about the start of its baseline, as the Azure backend does.
+        let run = &text.text_run;
+        let baseline_x = text.baseline_origin.x.to_subpx() as f32;
+        let baseline_y = text.baseline_origin.y.to_subpx() as f32;
+        let (transform, mut origin) = match text.orientation {
+            Upright => (self.transform, text.baseline_origin),
+            SidewaysLeft => {
+                (self.transform.mul(&Matrix2D::new(0., -1., 1., 0., baseline_x, baseline_y)),
+                 Point2D::zero())
+            }
+            SidewaysRight => {
+                (self.transform.mul(&Matrix2D::new(0., 1., -1., 0., baseline_x, baseline_y)),
+                 Point2D::zero())
+            }
+        };
+        let size = run.actual_pt_size.to_subpx() as f32;
+        let skew = if run.synthesis.contains(SYNTHETIC_OBLIQUE_FONT_FLAG) {
+            SYNTHETIC_OBLIQUE_SKEW as f32
+        } else {
+            0.0
+        };
+
+        // Glyphs are stored in logical order. Write right-to-left runs starting from their last
+        // glyph, so that the origin always moves from left to right.
+        let mut glyphs = vec!();
+        for slice in run.natural_word_slices_in_range(&text.range) {
+            for (index, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+                glyphs.push((slice.offset + index, glyph.id(), glyph.advance(), glyph.offset()))
+            }
+        }
+        if run.is_rtl() {
+            glyphs.reverse()
+        }
+
+        let characters: Vec<char> = run.text.chars().collect();
+        let mut operators = String::new();
+        for &(char_index, glyph_id, glyph_advance, glyph_offset) in glyphs.iter() {
+            let glyph_offset = glyph_offset.unwrap_or(Point2D::zero());
+            let glyph_origin = Point2D(origin.x + glyph_offset.x, origin.y + glyph_offset.y);
+            origin = Point2D(origin.x + glyph_advance, origin.y);
+            let code = if standard {
+                // Characters that the encoding doesn't share with Latin 1 are written as question
+                // marks. Ligatures are written as their first character.
+                match characters.get(char_index.to_usize()) {
+                    Some(&character) if (character as u32) < 0x80 ||
+                            (character as u32) >= 0xa0 && (character as u32) < 0x100 => {
+                        format!("<{:02X}>", character as u32)
+                    }
+                    Some(_) => "<3F>".to_owned(),
+                    None => continue,
+                }
+            } else {
+                if glyph_id > 0xffff {
+                    continue
+                }
+                if let Some(&character) = characters.get(char_index.to_usize()) {
+                    let characters = &mut self.fonts[font].characters;
+                    if !characters.contains_key(&(glyph_id as u16)) {
+                        characters.insert(glyph_id as u16, character);
+                    }
+                }
+                format!("<{:04X}>", glyph_id)
+            };
+
+            // Glyphs are drawn at size 1 in text space, with y going up.
+            let text_matrix = transform.mul(&Matrix2D::new(size,
+                                                           0.0,
+                                                           skew * size,
+                                                           -size,
+                                                           glyph_origin.x.to_subpx() as f32,
+                                                           glyph_origin.y.to_subpx() as f32));
+            operators.push_str(&format!("{} Tm {} Tj\n", matrix(&text_matrix), code));
+        }
+        if operators.is_empty() {
+            return
+        }
+
+        self.begin_drawing(Clips::All);
+        self.set_color(&to_paint_color(text.text_color), false);
+        let name = self.fonts[font].name.clone();
+        self.content().push_str(&format!("BT\n/{} 1 Tf\n{}ET\n", name, operators));
+        self.end_drawing()
+    }
+
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<f32>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation) {
+        let interpolate = match image_rendering {
+            image_rendering::T::Auto => true,
+            image_rendering::T::CrispEdges | image_rendering::T::Pixelated => false,
+        };
+        let name = match self.image(&image, interpolate) {
+            Some(name) => name,
+            None => return,
+        };
+
+        let source_rect = source_rect.unwrap_or(
+            Rect(Point2D(0.0, 0.0), Size2D(image.width as f32, image.height as f32)));
+        let mut dest_rect = bounds.to_azure_rect();
+        let mut transform = self.transform;
+        if orientation != ImageOrientation::identity() {
+            transform = transform.mul(&orientation.transform(&dest_rect));
+            dest_rect.origin = Point2D(0.0, 0.0);
+            if orientation.swaps_dimensions() {
+                dest_rect.size = Size2D(dest_rect.size.height, dest_rect.size.width);
+            }
+        }
+        if source_rect.size.width <= 0.0 || source_rect.size.height <= 0.0 {
+            return
+        }
+
+        // Images fill the unit square, with their first row at the top. Draw the whole image,
+        // scaled and moved so that the source rect lands on the destination rect, and clip it.
+        let x_scale = dest_rect.size.width / source_rect.size.width;
+        let y_scale = dest_rect.size.height / source_rect.size.height;
+        let image_width = image.width as f32 * x_scale;
+        let image_height = image.height as f32 * y_scale;
+        let image_transform =
+            transform.mul(&Matrix2D::new(image_width,
+                                         0.0,
+                                         0.0,
+                                         -image_height,
+                                         dest_rect.origin.x - source_rect.origin.x * x_scale,
+                                         dest_rect.origin.y - source_rect.origin.y * y_scale +
+                                            image_height));
+        let dest_polygon = raster::rect_polygon(&dest_rect, &transform);
+
+        self.begin_drawing(Clips::All);
+        write_polygon(self.content(), &dest_polygon);
+        self.content().push_str(&format!("W n\n{} cm /{} Do\n", matrix(&image_transform), name));
+        self.end_drawing()
+    }
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<Color>,
+                   style: &SideOffsets2D<border_style::T>) {
+        let widths = border.to_float_px();
+        let (ring, wedges) = raster::border_polygons(&bounds.to_azure_rect(),
+                                                     &widths,
+                                                     &radius.to_radii_px(),
+                                                     &self.transform);
+        let sides = [(widths.top, color.top, style.top),
+                     (widths.right, color.right, style.right),
+                     (widths.bottom, color.bottom, style.bottom),
+                     (widths.left, color.left, style.left)];
+        for (&(width, color, style), wedge) in sides.iter().zip(wedges.iter()) {
+            if width <= 0.0 || style == border_style::T::none || style == border_style::T::hidden {
+                continue
+            }
+            self.begin_drawing(Clips::All);
+            self.set_color(&color, false);
+            write_polygon(self.content(), wedge);
+            self.content().push_str("W n\n");
+            for polygon in ring.iter() {
+                write_polygon(self.content(), polygon)
+            }
+            self.content().push_str("f\n");
+            self.end_drawing()
+        }
+    }
+
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool) {
+        if stops.is_empty() {
+            return
+        }
+
+        let mut start = Point2D(start_point.x.to_subpx() as f32,
+                                start_point.y.to_subpx() as f32);
+        let mut end = Point2D(end_point.x.to_subpx() as f32, end_point.y.to_subpx() as f32);
+        let mut function = gradient_function(stops);
+        let (dx, dy) = (end.x - start.x, end.y - start.y);
+        let length_squared = dx * dx + dy * dy;
+        if repeating && length_squared > 0.0 {
+            // Shadings can't repeat, so stretch this one over as many copies of the stops as it
+            // takes to cover the bounds.
+            let rect = bounds.to_azure_rect();
+            let corners = [rect.origin,
+                           Point2D(rect.max_x(), rect.origin.y),
+                           Point2D(rect.origin.x, rect.max_y()),
+                           Point2D(rect.max_x(), rect.max_y())];
+            let (mut first, mut last): (f32, f32) =
+                (Float::infinity(), Float::neg_infinity());
+            for corner in corners.iter() {
+                let position =
+                    ((corner.x - start.x) * dx + (corner.y - start.y) * dy) / length_squared;
+                first = first.min(position);
+                last = last.max(position);
+            }
+            let first = first.floor();
+            let count = (last.ceil() - first).max(1.0) as usize;
+            if count <= MAX_GRADIENT_REPEATS {
+                function = repeated_function(&function, count);
+                end = Point2D(start.x + dx * (first + count as f32),
+                              start.y + dy * (first + count as f32));
+                start = Point2D(start.x + dx * first, start.y + dy * first);
+            }
+        }
+
+        let shading = format!("<< /ShadingType 2 /ColorSpace /DeviceRGB /Coords [{} {} {} {}] \
+                               /Function {} /Extend [true true] >>",
+                              number(start.x),
+                              number(start.y),
+                              number(end.x),
+                              number(end.y),
+                              function);
+        let object = self.add_object(shading.into_bytes());
+        let name = format!("Sh{}", self.shadings.len());
+        self.shadings.push((name.clone(), object));
+
+        let polygon = raster::rect_polygon(&bounds.to_azure_rect(), &self.transform);
+        let transform = self.transform;
+        self.begin_drawing(Clips::All);
+        write_polygon(self.content(), &polygon);
+        self.content().push_str(&format!("W n\n{} cm /{} sh\n", matrix(&transform), name));
+        self.end_drawing()
+    }
+
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]) {
+        if stops.is_empty() {
+            return
+        }
+
+        // The shading is a unit circle, which the second matrix stretches into the ellipse.
+        let shading = format!("<< /ShadingType 3 /ColorSpace /DeviceRGB /Coords [0 0 0 0 0 1] \
+                               /Function {} /Extend [true true] >>",
+                              gradient_function(stops));
+        let object = self.add_object(shading.into_bytes());
+        let name = format!("Sh{}", self.shadings.len());
+        self.shadings.push((name.clone(), object));
+
+        let polygon = raster::rect_polygon(&bounds.to_azure_rect(), &self.transform);
+        let transform = self.transform;
+        self.begin_drawing(Clips::All);
+        write_polygon(self.content(), &polygon);
+        self.content().push_str(&format!("W n\n{} cm {} cm /{} sh\n",
+                                         matrix(&transform),
+                                         matrix(&raster::radial_gradient_transform(center,
+                                                                                   radius)),
+                                         name));
+        self.end_drawing()
+    }
+
+    fn draw_line(&mut self, bounds: &Rect<Au>, color: Color, style: border_style::T) {
+        let dash_length = match style {
+            border_style::T::dotted => 1.0,
+            border_style::T::dashed => 3.0,
+            _ => return self.draw_solid_color(bounds, color),
+        };
+
+        // Stroke the middle of the line, with dashes as long as it is thick.
+        let rect = bounds.to_azure_rect();
+        let (start, end, thickness) = if rect.size.width >= rect.size.height {
+            let y = rect.origin.y + rect.size.height / 2.0;
+            (Point2D(rect.origin.x, y), Point2D(rect.max_x(), y), rect.size.height)
+        } else {
+            let x = rect.origin.x + rect.size.width / 2.0;
+            (Point2D(x, rect.origin.y), Point2D(x, rect.max_y()), rect.size.width)
+        };
+        let transform = self.transform;
+        self.begin_drawing(Clips::All);
+        self.set_color(&color, true);
+        self.content().push_str(&format!("{} cm {} w [{} {}] 0 d {} {} m {} {} l S\n",
+                                         matrix(&transform),
+                                         number(thickness),
+                                         number(thickness * dash_length),
+                                         number(thickness * dash_length),
+                                         number(start.x),
+                                         number(start.y),
+                                         number(end.x),
+                                         number(end.y)));
+        self.end_drawing()
+    }
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+        // Like the Azure backend, draw outside the clips, since shadows may reach beyond them.
+        let shadow_bounds = box_bounds.translate(offset).inflate(spread_radius, spread_radius);
+        let shadow = raster::rect_polygon(&shadow_bounds.to_azure_rect(), &self.transform);
+        let box_polygon = raster::rect_polygon(&box_bounds.to_azure_rect(), &self.transform);
+        let everything = vec![Point2D(-MAX_COORDINATE, -MAX_COORDINATE),
+                              Point2D(MAX_COORDINATE, -MAX_COORDINATE),
+                              Point2D(MAX_COORDINATE, MAX_COORDINATE),
+                              Point2D(-MAX_COORDINATE, MAX_COORDINATE)];
+
+        self.begin_drawing(Clips::None);
+        match clip_mode {
+            BoxShadowClipMode::None => {}
+            BoxShadowClipMode::Outset => {
+                write_polygon(self.content(), &everything);
+                write_polygon(self.content(), &box_polygon);
+                self.content().push_str("W* n\n");
+            }
+            BoxShadowClipMode::Inset => {
+                write_polygon(self.content(), &box_polygon);
+                self.content().push_str("W n\n");
+            }
+        }
+
+        let std_deviation = blur_radius.to_frac32_px() * self.scale();
+        if std_deviation <= 0.0 {
+            self.set_color(&color, false);
+            if clip_mode == BoxShadowClipMode::Inset {
+                write_polygon(self.content(), &everything);
+                write_polygon(self.content(), &shadow);
+                self.content().push_str("f*\n");
+            } else {
+                write_polygon(self.content(), &shadow);
+                self.content().push_str("f\n");
+            }
+            return self.end_drawing()
+        }
+
+        // PDF can't blur, so blur a mask of the shadow in page pixels and draw the color through
+        // it as an image.
+        let page_bounds = Rect(Point2D(0, 0),
+                               Size2D(self.page_size.width.ceil() as i32,
+                                      self.page_size.height.ceil() as i32));
+        let margin = (std_deviation * BLUR_EXTENT).ceil() as i32;
+        let bounds = if clip_mode == BoxShadowClipMode::Inset {
+            raster::polygon_bounds(&[box_polygon])
+        } else {
+            let bounds = raster::polygon_bounds(&[shadow.clone()]);
+            Rect(Point2D(bounds.origin.x - margin, bounds.origin.y - margin),
+                 Size2D(bounds.size.width + margin * 2, bounds.size.height + margin * 2))
+        };
+        let bounds = match bounds.intersection(&page_bounds) {
+            Some(bounds) if bounds.size.width > 0 && bounds.size.height > 0 => bounds,
+            _ => return self.end_drawing(),
+        };
+        let mask_bounds = Rect(Point2D(bounds.origin.x - margin, bounds.origin.y - margin),
+                               Size2D(bounds.size.width + margin * 2,
+                                      bounds.size.height + margin * 2));
+        let mut mask = raster::rasterize(&[shadow], &mask_bounds);
+        if clip_mode == BoxShadowClipMode::Inset {
+            mask = mask.invert(&mask_bounds)
+        }
+        mask.blur(std_deviation);
+
+        let (width, height) = (bounds.size.width as usize, bounds.size.height as usize);
+        let mut alphas = Vec::with_capacity(width * height);
+        for y in bounds.origin.y..bounds.max_y() {
+            for x in bounds.origin.x..bounds.max_x() {
+                alphas.push(to_byte(mask.get(x, y) * color.a))
+            }
+        }
+        let mut colors = Vec::with_capacity(width * height * 3);
+        for _ in 0..(width * height) {
+            colors.push_all(&[to_byte(color.r), to_byte(color.g), to_byte(color.b)]);
+        }
+        let dictionary = format!("/Type /XObject /Subtype /Image /Width {} /Height {} \
+                                  /BitsPerComponent 8",
+                                 width,
+                                 height);
+        let soft_mask = self.add_stream(&format!("{} /ColorSpace /DeviceGray", dictionary),
+                                        &alphas[..]);
+        let image = self.add_stream(&format!("{} /ColorSpace /DeviceRGB /SMask {} 0 R",
+                                             dictionary,
+                                             soft_mask),
+                                    &colors[..]);
+        let name = format!("Im{}", self.xobjects.len());
+        self.xobjects.push((name.clone(), image));
+
+        self.content().push_str(&format!("{} 0 0 {} {} {} cm /{} Do\n",
+                                         width,
+                                         -(height as i32),
+                                         bounds.origin.x,
+                                         bounds.max_y(),
+                                         name));
+        self.end_drawing()
+    }
+}
+
+/// Formats a number for a content stream, without needless digits.
+fn number(value: f32) -> String {
+    let value = value.max(-MAX_COORDINATE).min(MAX_COORDINATE);
+    let mut string = format!("{:.3}", value);
+    while string.ends_with("0") {
+        string.pop();
+    }
+    if string.ends_with(".") {
+        string.pop();
+    }
+    if string == "-0" {
+        string = "0".to_owned()
+    }
+    string
+}
+
+/// Formats a transform as the six numbers of a PDF matrix.
+fn matrix(transform: &Matrix2D<f32>) -> String {
+    format!("{} {} {} {} {} {}",
+            number(transform.m11),
+            number(transform.m12),
+            number(transform.m21),
+            number(transform.m22),
+            number(transform.m31),
+            number(transform.m32))
+}
+
+fn write_polygon(content: &mut String, polygon: &Polygon) {
+    for (index, point) in polygon.iter().enumerate() {
+        let operator = if index == 0 { "m" } else { "l" };
+        content.push_str(&format!("{} {} {}\n", number(point.x), number(point.y), operator));
+    }
+    if !polygon.is_empty() {
+        content.push_str("h\n")
+    }
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+}
+
+/// Returns the unpremultiplied RGB and the alpha bytes of the image.
+pub fn image_data(image: &Image) -> (Vec<u8>, Vec<u8>) {
+    let pixel_count = (image.width * image.height) as usize;
+    let mut colors = Vec::with_capacity(pixel_count * 3);
+    let mut alphas = Vec::with_capacity(pixel_count);
+
+    // Decoded images have their color channels in BGR order.
+    match image.pixels {
+        PixelsByColorType::RGBA8(ref pixels) => {
+            for pixel in pixels.chunks(4) {
+                let alpha = pixel[3] as u32;
+                for &channel in [pixel[2], pixel[1], pixel[0]].iter() {
+                    colors.push(if alpha == 0 {
+                        0
+                    } else {
+                        ((channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8
+                    })
+                }
+                alphas.push(pixel[3])
+            }
+        }
+        PixelsByColorType::RGB8(ref pixels) => {
+            for pixel in pixels.chunks(3) {
+                colors.push_all(&[pixel[2], pixel[1], pixel[0]]);
+                alphas.push(0xff)
+            }
+        }
+        // Like the Azure backend, draw grayscale images as alpha masks.
+        PixelsByColorType::K8(ref pixels) => {
+            for &pixel in pixels.iter() {
+                colors.push_all(&[0, 0, 0]);
+                alphas.push(pixel)
+            }
+        }
+        PixelsByColorType::KA8(ref pixels) => {
+            for pixel in pixels.chunks(2) {
+                colors.push_all(&[pixel[0], pixel[0], pixel[0]]);
+                alphas.push(pixel[1])
+            }
+        }
+    }
+    (colors, alphas)
+}
+
+/// Returns a PDF function that maps positions along a gradient to the colors of its stops.
+fn gradient_function(stops: &[GradientStop]) -> String {
+    // Repeat the end stops at the ends, since the pieces of the function cover it entirely.
+    let mut stops = stops.to_vec();
+    if stops[0].offset > 0.0 {
+        let first = GradientStop { offset: 0.0, color: stops[0].color };
+        stops.insert(0, first)
+    }
+    if stops[stops.len() - 1].offset < 1.0 {
+        let last = GradientStop { offset: 1.0, color: stops[stops.len() - 1].color };
+        stops.push(last)
+    }
+    let color = |color: &Color| {
+        format!("{} {} {}", number(color.r), number(color.g), number(color.b))
+    };
+    let piece = |before: &GradientStop, after: &GradientStop| {
+        format!("<< /FunctionType 2 /Domain [0 1] /C0 [{}] /C1 [{}] /N 1 >>",
+                color(&before.color),
+                color(&after.color))
+    };
+    if stops.len() == 1 {
+        return piece(&stops[0], &stops[0])
+    }
+
+    let pieces: Vec<String> = stops.windows(2).map(|pair| piece(&pair[0], &pair[1])).collect();
+    let bounds: Vec<String> =
+        stops[1..stops.len() - 1].iter().map(|stop| number(stop.offset)).collect();
+    let encode: Vec<&str> = pieces.iter().map(|_| "0 1").collect();
+    format!("<< /FunctionType 3 /Domain [0 1] /Functions [{}] /Bounds [{}] /Encode [{}] >>",
+            pieces.connect(" "),
+            bounds.connect(" "),
+            encode.connect(" "))
+}
+
+/// Returns a function that goes through the given function `count` times over its domain.
+fn repeated_function(function: &str, count: usize) -> String {
+    if count == 1 {
+        return function.to_owned()
+    }
+    let functions: Vec<&str> = (0..count).map(|_| function).collect();
+    let bounds: Vec<String> =
+        (1..count).map(|index| number(index as f32 / count as f32)).collect();
+    let encode: Vec<&str> = (0..count).map(|_| "0 1").collect();
+    format!("<< /FunctionType 3 /Domain [0 1] /Functions [{}] /Bounds [{}] /Encode [{}] >>",
+            functions.connect(" "),
+            bounds.connect(" "),
+            encode.connect(" "))
+}
+
+/// Returns a CMap that maps glyph ids to the characters they stand for.
+fn to_unicode_cmap(characters: &BTreeMap<u16, char>) -> String {
+    let mut cmap = "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+                    /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+                    /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+                    1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n".to_owned();
+
+    // Each block may only have 100 entries.
+    let characters: Vec<(&u16, &char)> = characters.iter().collect();
+    for block in characters.chunks(100) {
+        cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+        for &(glyph_id, character) in block.iter() {
+            // Characters outside the BMP take a surrogate pair.
+            let code_point = *character as u32;
+            let code_units = if code_point < 0x10000 {
+                format!("{:04X}", code_point)
+            } else {
+                let code_point = code_point - 0x10000;
+                format!("{:04X}{:04X}", 0xd800 + (code_point >> 10), 0xdc00 + (code_point & 0x3ff))
+            };
+            cmap.push_str(&format!("<{:04X}> <{}>\n", glyph_id, code_units));
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    cmap
+}
+
+/// Returns a PDF name for a font from its identifier, which may be a path or a URL.
+fn base_font_name(identifier: &str) -> String {
+    let file_name = identifier.split('/').last().unwrap_or(identifier);
+    let stem = file_name.split('.').next().unwrap_or(file_name);
+    let name: String = stem.chars().filter(|character| {
+        (*character as u32) < 0x80 && character.is_alphanumeric() || *character == '-'
+    }).collect();
+    if name.is_empty() {
+        "Font".to_owned()
+    } else {
+        name
+    }
+}
+
+/// The name of the blend mode in PDF graphics states.
+fn blend_mode_name(blend_mode: mix_blend_mode::T) -> &'static str {
+    match blend_mode {
+        mix_blend_mode::T::normal => "Normal",
+        mix_blend_mode::T::multiply => "Multiply",
+        mix_blend_mode::T::screen => "Screen",
+        mix_blend_mode::T::overlay => "Overlay",
+        mix_blend_mode::T::darken => "Darken",
+        mix_blend_mode::T::lighten => "Lighten",
+        mix_blend_mode::T::color_dodge => "ColorDodge",
+        mix_blend_mode::T::color_burn => "ColorBurn",
+        mix_blend_mode::T::hard_light => "HardLight",
+        mix_blend_mode::T::soft_light => "SoftLight",
+        mix_blend_mode::T::difference => "Difference",
+        mix_blend_mode::T::exclusion => "Exclusion",
+        mix_blend_mode::T::hue => "Hue",
+        mix_blend_mode::T::saturation => "Saturation",
+        mix_blend_mode::T::color => "Color",
+        mix_blend_mode::T::luminosity => "Luminosity",
+    }
+}
@@ -0,0 +1,416 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Groups recorded paint commands into batches of quads that the GPU can draw with one instanced
+//! draw call each, and runs of commands that have to be painted as usual.
+//!
+//! Solid colors, images, borders without rounded corners or fancy styles, and two-stop linear
+//! gradients are quads whenever they are drawn with a transform that only scales and translates,
+//! and are clipped by rects alone. Everything else, and everything drawn inside stacking contexts
+//! that need a temporary draw target for their filters or blend mode, falls back to the backend.
+
+use backends::recording::{DrawCommand, DrawOperation, PaintCommand};
+use display_list::{ClippingRegion, ImageOrientation};
+use filters;
+use paint_backend::{Color, ToSideOffsetsPx};
+use paint_context::ToAzureRect;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::mem;
+use std::sync::Arc;
+use style::computed_values::{border_style, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+
+/// How much an image may be shrunk when it is drawn as a quad. Painting shrinks images further
+/// than this with mipmaps, which quads don't have.
+const MAX_IMAGE_DOWNSCALE: f32 = 2.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum QuadFill {
+    Color,
+    LinearGradient,
+    Image,
+}
+
+/// A quad, in the pixels of the surface.
+#[derive(Clone, Copy)]
+pub struct Quad {
+    pub rect: Rect<f32>,
+    /// The rect that the quad is clipped to.
+    pub clip: Rect<f32>,
+    pub fill: QuadFill,
+    /// The color of quads filled with a color, or the colors that a gradient goes between.
+    pub start_color: Color,
+    pub end_color: Color,
+    /// Where a gradient has its start and end colors.
+    pub gradient_start: Point2D<f32>,
+    pub gradient_end: Point2D<f32>,
+    /// The part of the image of the batch that an image quad shows, in texture coordinates.
+    pub texture_rect: Rect<f32>,
+}
+
+pub struct QuadBatch {
+    /// The image that the image quads of the batch show, and whether it is sampled without
+    /// filtering.
+    pub image: Option<(Arc<Image>, bool)>,
+    pub quads: Vec<Quad>,
+}
+
+pub enum Batch {
+    Quads(QuadBatch),
+    /// Commands to paint with a backend, in order.
+    Commands(Vec<PaintCommand>),
+}
+
+/// Batches recorded commands for a surface of the given size. Painting the batches in order
+/// paints the same as replaying the commands.
+pub fn batch_commands(commands: &[PaintCommand], surface_size: &Size2D<i32>) -> Vec<Batch> {
+    let surface_rect = Rect(Point2D(0.0, 0.0),
+                            Size2D(surface_size.width as f32, surface_size.height as f32));
+    let mut batcher = Batcher {
+        batches: Vec::new(),
+        deferred_commands: Vec::new(),
+        transform: Matrix2D::identity(),
+        clip: Some(surface_rect),
+        transient_clip: None,
+        in_group: false,
+        saved_states: Vec::new(),
+    };
+    for command in commands.iter() {
+        batcher.add(command)
+    }
+
+    let deferred_commands = mem::replace(&mut batcher.deferred_commands, Vec::new());
+    batcher.push_commands(deferred_commands);
+    batcher.batches
+}
+
+struct Batcher {
+    batches: Vec<Batch>,
+    /// Commands that only change the state of the backend, which can wait until the backend
+    /// paints something, so that they don't split up batches of quads.
+    deferred_commands: Vec<PaintCommand>,
+    transform: Matrix2D<f32>,
+    /// The clip of the current stacking context, or `None` if it isn't a rect on the surface.
+    clip: Option<Rect<f32>>,
+    transient_clip: Option<ClippingRegion>,
+    /// Whether the current stacking context, or one around it, draws into a temporary draw target.
+    in_group: bool,
+    saved_states: Vec<SavedState>,
+}
+
+struct SavedState {
+    transform: Matrix2D<f32>,
+    clip: Option<Rect<f32>>,
+    transient_clip: Option<ClippingRegion>,
+    in_group: bool,
+    /// Whether the stacking context draws into a temporary draw target of its own.
+    is_group: bool,
+}
+
+impl Batcher {
+    fn add(&mut self, command: &PaintCommand) {
+        match *command {
+            PaintCommand::PushStackingContext {
+                ref transform,
+                ref clip_rect,
+                ref filters,
+                blend_mode,
+                ..
+            } => {
+                let is_group = filters::temporary_draw_target_needed_for_style_filters(filters) ||
+                    blend_mode != mix_blend_mode::T::normal;
+                let clip = match (self.clip, clip_rect) {
+                    (clip, &None) => clip,
+                    (Some(ref clip), &Some(ref clip_rect)) => {
+                        device_rect(transform, &clip_rect.to_azure_rect()).map(|clip_rect| {
+                            intersect(clip, &clip_rect)
+                        })
+                    }
+                    (None, &Some(_)) => None,
+                };
+                self.saved_states.push(SavedState {
+                    transform: mem::replace(&mut self.transform, *transform),
+                    clip: mem::replace(&mut self.clip, clip),
+                    transient_clip: self.transient_clip.take(),
+                    in_group: self.in_group,
+                    is_group: is_group,
+                });
+                self.in_group = self.in_group || is_group;
+                self.deferred_commands.push(command.clone())
+            }
+            PaintCommand::PopStackingContext => {
+                let saved_state = self.saved_states.pop().expect("no stacking context to pop");
+                self.transform = saved_state.transform;
+                self.clip = saved_state.clip;
+                self.transient_clip = saved_state.transient_clip;
+                self.in_group = saved_state.in_group;
+
+                // Popping a group paints it, which has to happen before any quads after it.
+                self.deferred_commands.push(command.clone());
+                if saved_state.is_group {
+                    let deferred_commands = mem::replace(&mut self.deferred_commands, Vec::new());
+                    self.push_commands(deferred_commands)
+                }
+            }
+            PaintCommand::SetClip(ref clip) => {
+                self.transient_clip = Some(clip.clone());
+                self.deferred_commands.push(command.clone())
+            }
+            PaintCommand::Clear => {
+                let mut commands = mem::replace(&mut self.deferred_commands, Vec::new());
+                commands.push(command.clone());
+                self.push_commands(commands)
+            }
+            PaintCommand::Draw(ref draw_command) => {
+                let mut quads = Vec::new();
+                match self.quads_for(draw_command, &mut quads) {
+                    Some(image) => self.push_quads(quads, image),
+                    None => {
+                        let mut commands = mem::replace(&mut self.deferred_commands, Vec::new());
+                        commands.push(command.clone());
+                        self.push_commands(commands)
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_commands(&mut self, commands: Vec<PaintCommand>) {
+        if commands.is_empty() {
+            return
+        }
+        if let Some(&mut Batch::Commands(ref mut batch_commands)) = self.batches.last_mut() {
+            batch_commands.push_all(&commands[..]);
+            return
+        }
+        self.batches.push(Batch::Commands(commands))
+    }
+
+    fn push_quads(&mut self, quads: Vec<Quad>, image: Option<(Arc<Image>, bool)>) {
+        if quads.is_empty() {
+            return
+        }
+        if let Some(&mut Batch::Quads(ref mut batch)) = self.batches.last_mut() {
+            let same_image = match (&batch.image, &image) {
+                (&Some((ref batch_image, batch_nearest)), &Some((ref image, nearest))) => {
+                    &**batch_image as *const Image == &**image as *const Image &&
+                        batch_nearest == nearest
+                }
+                _ => true,
+            };
+            if same_image {
+                if batch.image.is_none() {
+                    batch.image = image
+                }
+                batch.quads.push_all(&quads[..]);
+                return
+            }
+        }
+        self.batches.push(Batch::Quads(QuadBatch {
+            image: image,
+            quads: quads,
+        }))
+    }
+
+    /// Adds the quads that draw the command to `quads`, and returns the image they show, if any.
+    /// Returns `None` if the command can't be drawn with quads.
+    fn quads_for(&self, draw_command: &DrawCommand, quads: &mut Vec<Quad>)
+                 -> Option<Option<(Arc<Image>, bool)>> {
+        if self.in_group {
+            return None
+        }
+        let transform = &draw_command.transform;
+        let mut clip = match self.clip {
+            Some(clip) => clip,
+            None => return None,
+        };
+        if let Some(ref transient_clip) = self.transient_clip {
+            if !transient_clip.complex.is_empty() {
+                return None
+            }
+            clip = match device_rect(transform, &transient_clip.main.to_azure_rect()) {
+                Some(main) => intersect(&clip, &main),
+                None => return None,
+            }
+        }
+
+        match draw_command.operation {
+            DrawOperation::SolidColor(ref bounds, color) => {
+                let rect = match device_rect(transform, &bounds.to_azure_rect()) {
+                    Some(rect) => rect,
+                    None => return None,
+                };
+                push_quad(quads, color_quad(&rect, &clip, color));
+                Some(None)
+            }
+            DrawOperation::Image(ref bounds,
+                                 ref image,
+                                 source_rect,
+                                 image_rendering,
+                                 orientation) => {
+                if orientation != ImageOrientation::identity() {
+                    return None
+                }
+                match image.pixels {
+                    PixelsByColorType::RGBA8(_) => {}
+                    _ => return None,
+                }
+                let rect = match device_rect(transform, &bounds.to_azure_rect()) {
+                    Some(rect) => rect,
+                    None => return None,
+                };
+                let image_size = Size2D(image.width as f32, image.height as f32);
+                let source_rect = source_rect.unwrap_or(Rect(Point2D(0.0, 0.0), image_size));
+                if source_rect.size.width > rect.size.width * MAX_IMAGE_DOWNSCALE ||
+                        source_rect.size.height > rect.size.height * MAX_IMAGE_DOWNSCALE {
+                    return None
+                }
+                let nearest = match image_rendering {
+                    image_rendering::T::Auto => false,
+                    image_rendering::T::CrispEdges | image_rendering::T::Pixelated => true,
+                };
+                push_quad(quads, Quad {
+                    fill: QuadFill::Image,
+                    texture_rect: Rect(Point2D(source_rect.origin.x / image_size.width,
+                                               source_rect.origin.y / image_size.height),
+                                       Size2D(source_rect.size.width / image_size.width,
+                                              source_rect.size.height / image_size.height)),
+                    ..color_quad(&rect, &clip, Color::new(0.0, 0.0, 0.0, 0.0))
+                });
+                Some(Some((image.clone(), nearest)))
+            }
+            DrawOperation::Border(ref bounds, ref border, ref radius, ref color, ref style) => {
+                if !radius.is_square() {
+                    return None
+                }
+                let widths = border.to_float_px();
+                let sides = [(widths.top, color.top, style.top),
+                             (widths.right, color.right, style.right),
+                             (widths.bottom, color.bottom, style.bottom),
+                             (widths.left, color.left, style.left)];
+                let mut side_color = None;
+                for &(width, color, style) in sides.iter() {
+                    match style {
+                        _ if width <= 0.0 => continue,
+                        border_style::T::none | border_style::T::hidden => continue,
+                        border_style::T::solid => {}
+                        _ => return None,
+                    }
+                    // Sides of different colors meet at a diagonal, which quads can't draw.
+                    match side_color {
+                        Some(side_color) if side_color != color => return None,
+                        _ => side_color = Some(color),
+                    }
+                }
+                let side_color = match side_color {
+                    Some(side_color) => side_color,
+                    None => return Some(None),
+                };
+
+                let rect = bounds.to_azure_rect();
+                let visible_width = |&(width, _, style): &(f32, Color, border_style::T)| {
+                    match style {
+                        border_style::T::none | border_style::T::hidden => 0.0,
+                        _ => width,
+                    }
+                };
+                let (top, right) = (visible_width(&sides[0]), visible_width(&sides[1]));
+                let (bottom, left) = (visible_width(&sides[2]), visible_width(&sides[3]));
+                let inner_height = rect.size.height - top - bottom;
+                let side_rects = [
+                    Rect(rect.origin, Size2D(rect.size.width, top)),
+                    Rect(Point2D(rect.origin.x, rect.max_y() - bottom),
+                         Size2D(rect.size.width, bottom)),
+                    Rect(Point2D(rect.origin.x, rect.origin.y + top), Size2D(left, inner_height)),
+                    Rect(Point2D(rect.max_x() - right, rect.origin.y + top),
+                         Size2D(right, inner_height)),
+                ];
+                for side_rect in side_rects.iter() {
+                    if side_rect.size.width <= 0.0 || side_rect.size.height <= 0.0 {
+                        continue
+                    }
+                    let side_rect = match device_rect(transform, side_rect) {
+                        Some(side_rect) => side_rect,
+                        None => return None,
+                    };
+                    push_quad(quads, color_quad(&side_rect, &clip, side_color))
+                }
+                Some(None)
+            }
+            DrawOperation::LinearGradient(ref bounds,
+                                          ref start_point,
+                                          ref end_point,
+                                          ref stops) => {
+                // Scaling one axis more than the other would skew the lines of equal color.
+                if stops.len() != 2 || transform.m11 != transform.m22 {
+                    return None
+                }
+                let rect = match device_rect(transform, &bounds.to_azure_rect()) {
+                    Some(rect) => rect,
+                    None => return None,
+                };
+                let start = transform.transform_point(&Point2D(start_point.x.to_frac32_px(),
+                                                               start_point.y.to_frac32_px()));
+                let end = transform.transform_point(&Point2D(end_point.x.to_frac32_px(),
+                                                             end_point.y.to_frac32_px()));
+                let point_at = |offset: f32| {
+                    Point2D(start.x + (end.x - start.x) * offset,
+                            start.y + (end.y - start.y) * offset)
+                };
+                push_quad(quads, Quad {
+                    fill: QuadFill::LinearGradient,
+                    end_color: stops[1].color,
+                    gradient_start: point_at(stops[0].offset),
+                    gradient_end: point_at(stops[1].offset),
+                    ..color_quad(&rect, &clip, stops[0].color)
+                });
+                Some(None)
+            }
+            DrawOperation::Text(_) |
+            DrawOperation::Line(..) |
+            DrawOperation::BoxShadow(..) => None,
+        }
+    }
+}
+
+/// Returns the rect on the surface that the transform puts the rect at, or `None` if the
+/// transform does more than scale and translate.
+fn device_rect(transform: &Matrix2D<f32>, rect: &Rect<f32>) -> Option<Rect<f32>> {
+    if transform.m12 != 0.0 || transform.m21 != 0.0 || transform.m11 <= 0.0 ||
+            transform.m22 <= 0.0 {
+        return None
+    }
+    let origin = transform.transform_point(&rect.origin);
+    Some(Rect(origin, Size2D(rect.size.width * transform.m11, rect.size.height * transform.m22)))
+}
+
+fn intersect(a: &Rect<f32>, b: &Rect<f32>) -> Rect<f32> {
+    a.intersection(b).unwrap_or(Rect(a.origin, Size2D(0.0, 0.0)))
+}
+
+fn color_quad(rect: &Rect<f32>, clip: &Rect<f32>, color: Color) -> Quad {
+    Quad {
+        rect: *rect,
+        clip: *clip,
+        fill: QuadFill::Color,
+        start_color: color,
+        end_color: color,
+        gradient_start: rect.origin,
+        gradient_end: rect.origin,
+        texture_rect: Rect(Point2D(0.0, 0.0), Size2D(1.0, 1.0)),
+    }
+}
+
+/// Adds the quad unless its clip hides it.
+fn push_quad(quads: &mut Vec<Quad>, quad: Quad) {
+    if quad.rect.intersects(&quad.clip) {
+        quads.push(quad)
+    }
+}
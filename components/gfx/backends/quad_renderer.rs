@@ -0,0 +1,390 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Draws batches of quads from `quad_batch` into the framebuffer that Skia-GL paints into, with
+//! one instanced draw call per batch.
+//!
+//! Skia caches the GL state, so the renderer keeps its vertex attributes in a vertex array object
+//! of its own and puts back every other piece of state that it changes.
+
+use backends::quad_batch::{Quad, QuadBatch, QuadFill};
+
+use geom::size::Size2D;
+use gleam::gl;
+use gleam::gl::types::{GLboolean, GLenum, GLint, GLsizei, GLuint};
+use png::PixelsByColorType;
+use std::mem;
+
+/// The number of floats that make up the attributes of a quad.
+const FLOATS_PER_QUAD: usize = 25;
+
+static VERTEX_SHADER: &'static str = "
+    #version 120
+
+    uniform vec2 uSurfaceSize;
+
+    // The corner of the quad, from (0, 0) to (1, 1).
+    attribute vec2 aCorner;
+
+    // Rects are given as (left, top, right, bottom), in pixels of the surface.
+    attribute vec4 aRect;
+    attribute vec4 aClip;
+    attribute float aFill;
+    attribute vec4 aStartColor;
+    attribute vec4 aEndColor;
+    attribute vec4 aGradientLine;
+    attribute vec4 aTextureRect;
+
+    varying vec2 vPosition;
+    varying vec4 vRect;
+    varying vec4 vClip;
+    varying float vFill;
+    varying vec4 vStartColor;
+    varying vec4 vEndColor;
+    varying vec4 vGradientLine;
+    varying vec4 vTextureRect;
+
+    void main() {
+        // Cover every pixel that the clipped quad touches, so that its edges can be antialiased.
+        vec4 bounds = vec4(max(aRect.xy, aClip.xy), min(aRect.zw, aClip.zw));
+        bounds = vec4(floor(bounds.xy), ceil(bounds.zw));
+        vPosition = mix(bounds.xy, bounds.zw, aCorner);
+
+        vRect = aRect;
+        vClip = aClip;
+        vFill = aFill;
+        vStartColor = aStartColor;
+        vEndColor = aEndColor;
+        vGradientLine = aGradientLine;
+        vTextureRect = aTextureRect;
+
+        // Like Skia, put the top of the surface at the top of the framebuffer.
+        vec2 position = vPosition / uSurfaceSize * vec2(2.0, -2.0) + vec2(-1.0, 1.0);
+        gl_Position = vec4(position, 0.0, 1.0);
+    }
+";
+
+static FRAGMENT_SHADER: &'static str = "
+    #version 120
+
+    uniform sampler2D uImage;
+
+    varying vec2 vPosition;
+    varying vec4 vRect;
+    varying vec4 vClip;
+    varying float vFill;
+    varying vec4 vStartColor;
+    varying vec4 vEndColor;
+    varying vec4 vGradientLine;
+    varying vec4 vTextureRect;
+
+    // Returns how much of the pixel is inside the rect.
+    float coverage(vec4 rect) {
+        vec2 extent = min(vPosition + 0.5, rect.zw) - max(vPosition - 0.5, rect.xy);
+        extent = clamp(extent, 0.0, 1.0);
+        return extent.x * extent.y;
+    }
+
+    void main() {
+        vec4 color;
+        if (vFill < 1.5) {
+            float t = 0.0;
+            if (vFill > 0.5) {
+                vec2 line = vGradientLine.zw - vGradientLine.xy;
+                t = clamp(dot(vPosition - vGradientLine.xy, line) / dot(line, line), 0.0, 1.0);
+            }
+            color = mix(vStartColor, vEndColor, t);
+            color.rgb *= color.a;
+        } else {
+            vec2 t = (vPosition - vRect.xy) / (vRect.zw - vRect.xy);
+            // Images are premultiplied BGRA.
+            color = texture2D(uImage, mix(vTextureRect.xy, vTextureRect.zw, t)).bgra;
+        }
+        gl_FragColor = color * coverage(vRect) * coverage(vClip);
+    }
+";
+
+/// The attributes of a quad after `aCorner`, with how many floats each has.
+static QUAD_ATTRIBUTES: [(&'static str, GLint); 7] = [
+    ("aRect", 4),
+    ("aClip", 4),
+    ("aFill", 1),
+    ("aStartColor", 4),
+    ("aEndColor", 4),
+    ("aGradientLine", 4),
+    ("aTextureRect", 4),
+];
+
+pub struct QuadRenderer {
+    program: GLuint,
+    vertex_array: GLuint,
+    corner_buffer: GLuint,
+    quad_buffer: GLuint,
+    surface_size_uniform: GLint,
+}
+
+impl QuadRenderer {
+    /// Creates a renderer for the current GL context, or returns `None` if the context can't draw
+    /// instanced quads.
+    pub fn new() -> Option<QuadRenderer> {
+        if !gl::DrawArraysInstanced::is_loaded() || !gl::VertexAttribDivisor::is_loaded() ||
+                !gl::GenVertexArrays::is_loaded() {
+            return None
+        }
+
+        let program = match link_program() {
+            Some(program) => program,
+            None => return None,
+        };
+
+        let saved_state = SavedGlState::save();
+        let mut vertex_array = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array);
+            gl::BindVertexArray(vertex_array);
+        }
+
+        let buffers = gl::gen_buffers(2);
+        let (corner_buffer, quad_buffer) = (buffers[0], buffers[1]);
+        let corner = gl::get_attrib_location(program, "aCorner");
+        gl::bind_buffer(gl::ARRAY_BUFFER, corner_buffer);
+        let corners = [0.0f32, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        gl::buffer_data(gl::ARRAY_BUFFER, &corners[..], gl::STATIC_DRAW);
+        gl::enable_vertex_attrib_array(corner as GLuint);
+        gl::vertex_attrib_pointer_f32(corner as GLuint, 2, false, 0, 0);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, quad_buffer);
+        let stride = (FLOATS_PER_QUAD * mem::size_of::<f32>()) as GLsizei;
+        let mut offset = 0;
+        for &(name, size) in QUAD_ATTRIBUTES.iter() {
+            let location = gl::get_attrib_location(program, name) as GLuint;
+            gl::enable_vertex_attrib_array(location);
+            gl::vertex_attrib_pointer_f32(location, size, false, stride, offset);
+            unsafe {
+                gl::VertexAttribDivisor(location, 1);
+            }
+            offset += size as u32 * mem::size_of::<f32>() as u32;
+        }
+        saved_state.restore();
+
+        Some(QuadRenderer {
+            program: program,
+            vertex_array: vertex_array,
+            corner_buffer: corner_buffer,
+            quad_buffer: quad_buffer,
+            surface_size_uniform: gl::get_uniform_location(program, "uSurfaceSize"),
+        })
+    }
+
+    /// Draws the batch into the framebuffer that is bound, which is the size of the surface. Skia
+    /// has to have flushed what it paints below the quads first.
+    pub fn draw(&mut self, batch: &QuadBatch, surface_size: &Size2D<i32>) {
+        let saved_state = SavedGlState::save();
+        unsafe {
+            gl::BindVertexArray(self.vertex_array);
+        }
+        gl::use_program(self.program);
+        gl::uniform_2f(self.surface_size_uniform,
+                       surface_size.width as f32,
+                       surface_size.height as f32);
+        gl::viewport(0, 0, surface_size.width, surface_size.height);
+        gl::disable(gl::SCISSOR_TEST);
+        gl::enable(gl::BLEND);
+        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+
+        let mut texture = None;
+        if let Some((ref image, nearest)) = batch.image {
+            if let PixelsByColorType::RGBA8(ref pixels) = image.pixels {
+                let textures = gl::gen_textures(1);
+                gl::active_texture(gl::TEXTURE0);
+                gl::bind_texture(gl::TEXTURE_2D, textures[0]);
+                let filter = if nearest { gl::NEAREST } else { gl::LINEAR };
+                gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+                gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+                gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+                gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+                gl::pixel_store_i(gl::UNPACK_ROW_LENGTH, 0);
+                gl::pixel_store_i(gl::UNPACK_ALIGNMENT, 4);
+                gl::tex_image_2d(gl::TEXTURE_2D,
+                                 0,
+                                 gl::RGBA as GLint,
+                                 image.width as GLsizei,
+                                 image.height as GLsizei,
+                                 0,
+                                 gl::RGBA,
+                                 gl::UNSIGNED_BYTE,
+                                 Some(&pixels[..]));
+                texture = Some(textures);
+            }
+        }
+
+        // FIXME: Keep the buffer around when the batch is no bigger than the last one, and keep
+        // textures of images that are drawn again.
+        let mut data = Vec::with_capacity(batch.quads.len() * FLOATS_PER_QUAD);
+        for quad in batch.quads.iter() {
+            push_quad_attributes(&mut data, quad)
+        }
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.quad_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, &data, gl::STREAM_DRAW);
+        unsafe {
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, batch.quads.len() as GLsizei);
+        }
+
+        saved_state.restore();
+        if let Some(textures) = texture {
+            gl::delete_textures(&textures);
+        }
+    }
+}
+
+impl Drop for QuadRenderer {
+    fn drop(&mut self) {
+        gl::delete_program(self.program);
+        gl::delete_buffers(&[self.corner_buffer, self.quad_buffer]);
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vertex_array);
+        }
+    }
+}
+
+fn push_quad_attributes(data: &mut Vec<f32>, quad: &Quad) {
+    let fill = match quad.fill {
+        QuadFill::Color => 0.0,
+        QuadFill::LinearGradient => 1.0,
+        QuadFill::Image => 2.0,
+    };
+    let (start, end) = (&quad.start_color, &quad.end_color);
+    let texture_rect = &quad.texture_rect;
+    data.push_all(&[
+        quad.rect.origin.x, quad.rect.origin.y, quad.rect.max_x(), quad.rect.max_y(),
+        quad.clip.origin.x, quad.clip.origin.y, quad.clip.max_x(), quad.clip.max_y(),
+        fill,
+        start.r, start.g, start.b, start.a,
+        end.r, end.g, end.b, end.a,
+        quad.gradient_start.x, quad.gradient_start.y, quad.gradient_end.x, quad.gradient_end.y,
+        texture_rect.origin.x, texture_rect.origin.y, texture_rect.max_x(), texture_rect.max_y(),
+    ])
+}
+
+fn compile_shader(source: &str, shader_type: GLenum) -> Option<GLuint> {
+    let shader = gl::create_shader(shader_type);
+    gl::shader_source(shader, &[source.as_bytes()]);
+    gl::compile_shader(shader);
+    if gl::get_shader_iv(shader, gl::COMPILE_STATUS) == 0 {
+        debug!("quad renderer: failed to compile shader: {}", gl::get_shader_info_log(shader));
+        gl::delete_shader(shader);
+        return None
+    }
+    Some(shader)
+}
+
+fn link_program() -> Option<GLuint> {
+    let vertex_shader = match compile_shader(VERTEX_SHADER, gl::VERTEX_SHADER) {
+        Some(shader) => shader,
+        None => return None,
+    };
+    let fragment_shader = match compile_shader(FRAGMENT_SHADER, gl::FRAGMENT_SHADER) {
+        Some(shader) => shader,
+        None => {
+            gl::delete_shader(vertex_shader);
+            return None
+        }
+    };
+
+    let program = gl::create_program();
+    gl::attach_shader(program, vertex_shader);
+    gl::attach_shader(program, fragment_shader);
+    gl::link_program(program);
+    gl::delete_shader(vertex_shader);
+    gl::delete_shader(fragment_shader);
+    if gl::get_program_iv(program, gl::LINK_STATUS) == 0 {
+        debug!("quad renderer: failed to link program");
+        gl::delete_program(program);
+        return None
+    }
+    Some(program)
+}
+
+/// The GL state that the renderer changes, which Skia expects to find as it left it.
+struct SavedGlState {
+    program: GLint,
+    vertex_array: GLint,
+    array_buffer: GLint,
+    active_texture: GLint,
+    texture: GLint,
+    unpack_row_length: GLint,
+    unpack_alignment: GLint,
+    viewport: [GLint; 4],
+    blend: GLboolean,
+    scissor_test: GLboolean,
+    blend_functions: [GLint; 4],
+}
+
+impl SavedGlState {
+    fn save() -> SavedGlState {
+        fn integer(name: GLenum) -> GLint {
+            let mut value = 0;
+            unsafe {
+                gl::GetIntegerv(name, &mut value);
+            }
+            value
+        }
+
+        let mut viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        let active_texture = integer(gl::ACTIVE_TEXTURE);
+        gl::active_texture(gl::TEXTURE0);
+        let texture = integer(gl::TEXTURE_BINDING_2D);
+        gl::active_texture(active_texture as GLenum);
+
+        SavedGlState {
+            program: integer(gl::CURRENT_PROGRAM),
+            vertex_array: integer(gl::VERTEX_ARRAY_BINDING),
+            array_buffer: integer(gl::ARRAY_BUFFER_BINDING),
+            active_texture: active_texture,
+            texture: texture,
+            unpack_row_length: integer(gl::UNPACK_ROW_LENGTH),
+            unpack_alignment: integer(gl::UNPACK_ALIGNMENT),
+            viewport: viewport,
+            blend: unsafe { gl::IsEnabled(gl::BLEND) },
+            scissor_test: unsafe { gl::IsEnabled(gl::SCISSOR_TEST) },
+            blend_functions: [integer(gl::BLEND_SRC_RGB),
+                              integer(gl::BLEND_DST_RGB),
+                              integer(gl::BLEND_SRC_ALPHA),
+                              integer(gl::BLEND_DST_ALPHA)],
+        }
+    }
+
+    fn restore(&self) {
+        fn set_enabled(capability: GLenum, enabled: GLboolean) {
+            if enabled != 0 {
+                gl::enable(capability)
+            } else {
+                gl::disable(capability)
+            }
+        }
+
+        gl::use_program(self.program as GLuint);
+        unsafe {
+            gl::BindVertexArray(self.vertex_array as GLuint);
+        }
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.array_buffer as GLuint);
+        gl::active_texture(gl::TEXTURE0);
+        gl::bind_texture(gl::TEXTURE_2D, self.texture as GLuint);
+        gl::active_texture(self.active_texture as GLenum);
+        gl::pixel_store_i(gl::UNPACK_ROW_LENGTH, self.unpack_row_length);
+        gl::pixel_store_i(gl::UNPACK_ALIGNMENT, self.unpack_alignment);
+        gl::viewport(self.viewport[0], self.viewport[1], self.viewport[2], self.viewport[3]);
+        set_enabled(gl::BLEND, self.blend);
+        set_enabled(gl::SCISSOR_TEST, self.scissor_test);
+        unsafe {
+            gl::BlendFuncSeparate(self.blend_functions[0] as GLenum,
+                                  self.blend_functions[1] as GLenum,
+                                  self.blend_functions[2] as GLenum,
+                                  self.blend_functions[3] as GLenum);
+        }
+    }
+}
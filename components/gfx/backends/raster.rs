@@ -0,0 +1,374 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Scan conversion of polygons into coverage masks, for the software backend.
+//!
+//! Polygons are filled with the nonzero winding rule. Each pixel row is sampled at
+//! `SUBSAMPLES` rows, and on each of those the spans between edge crossings are accumulated with
+//! their exact horizontal coverage, so edges are antialiased in both directions.
+
+use display_list::BorderRadii;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use std::cmp::{Ordering, max, min};
+use std::f32::consts::FRAC_PI_2;
+use std::num::Float;
+use util::geometry::Au;
+
+/// The number of rows at which each pixel row is sampled.
+static SUBSAMPLES: usize = 4;
+
+/// The largest distance, in pixels, between a flattened arc and the true arc.
+static ARC_TOLERANCE: f32 = 0.1;
+
+/// A closed polygon in device pixels.
+pub type Polygon = Vec<Point2D<f32>>;
+
+/// How much of each pixel in a box is covered, from 0 to 1. Pixels outside the box are not
+/// covered at all.
+#[derive(Clone)]
+pub struct Mask {
+    pub bounds: Rect<i32>,
+    coverage: Vec<f32>,
+}
+
+impl Mask {
+    /// Creates a mask that covers nothing in `bounds`.
+    pub fn new(bounds: &Rect<i32>) -> Mask {
+        Mask {
+            bounds: *bounds,
+            coverage: vec![0.0; (max(bounds.size.width, 0) * max(bounds.size.height, 0)) as usize],
+        }
+    }
+
+    /// Returns how much of the pixel at the given position is covered.
+    #[inline]
+    pub fn get(&self, x: i32, y: i32) -> f32 {
+        let (x, y) = (x - self.bounds.origin.x, y - self.bounds.origin.y);
+        if x < 0 || y < 0 || x >= self.bounds.size.width || y >= self.bounds.size.height {
+            return 0.0
+        }
+        self.coverage[(y * self.bounds.size.width + x) as usize]
+    }
+
+    /// Returns the mask that covers each pixel as much as both masks do.
+    pub fn intersect(&self, other: &Mask) -> Mask {
+        let left = max(self.bounds.origin.x, other.bounds.origin.x);
+        let top = max(self.bounds.origin.y, other.bounds.origin.y);
+        let right = min(self.bounds.max_x(), other.bounds.max_x());
+        let bottom = min(self.bounds.max_y(), other.bounds.max_y());
+        let mut result = Mask::new(&Rect(Point2D(left, top),
+                                         Size2D(max(right - left, 0), max(bottom - top, 0))));
+        for y in top..bottom {
+            for x in left..right {
+                let index = ((y - top) * result.bounds.size.width + (x - left)) as usize;
+                result.coverage[index] = self.get(x, y) * other.get(x, y);
+            }
+        }
+        result
+    }
+
+    /// Returns the mask that covers what this one doesn't within `bounds`.
+    pub fn invert(&self, bounds: &Rect<i32>) -> Mask {
+        let mut result = Mask::new(bounds);
+        for y in 0..bounds.size.height {
+            for x in 0..bounds.size.width {
+                let coverage = self.get(bounds.origin.x + x, bounds.origin.y + y);
+                result.coverage[(y * bounds.size.width + x) as usize] = 1.0 - coverage;
+            }
+        }
+        result
+    }
+
+    /// Blurs the mask with a Gaussian kernel of the given standard deviation, in pixels. The mask
+    /// must already have room around its contents for the blur to spread into.
+    pub fn blur(&mut self, std_deviation: f32) {
+        let (width, height) = (self.bounds.size.width as usize, self.bounds.size.height as usize);
+        blur(&mut self.coverage[..], width, height, 1, std_deviation)
+    }
+}
+
+/// Returns the transform from the space in which a radial gradient is the unit circle about the
+/// origin to the space of its center and radii, in px.
+pub fn radial_gradient_transform(center: &Point2D<Au>, radius: &Size2D<Au>)
+                                 -> Matrix2D<f32> {
+    let transform: Matrix2D<f32> = Matrix2D::identity();
+    transform.translate(center.x.to_subpx() as f32, center.y.to_subpx() as f32)
+             .scale(radius.width.to_subpx() as f32, radius.height.to_subpx() as f32)
+}
+
+/// Returns the polygon covering the given rect after the transform.
+pub fn rect_polygon(rect: &Rect<f32>, transform: &Matrix2D<f32>) -> Polygon {
+    vec![transform.transform_point(&rect.origin),
+         transform.transform_point(&Point2D(rect.max_x(), rect.origin.y)),
+         transform.transform_point(&Point2D(rect.max_x(), rect.max_y())),
+         transform.transform_point(&Point2D(rect.origin.x, rect.max_y()))]
+}
+
+/// Returns the polygon covering the given rect with rounded corners after the transform. Each
+/// radius is used for both axes of its corner.
+pub fn rounded_rect_polygon(rect: &Rect<f32>,
+                            radii: &BorderRadii<f32>,
+                            transform: &Matrix2D<f32>)
+                            -> Polygon {
+    let (left, top, right, bottom) = (rect.origin.x, rect.origin.y, rect.max_x(), rect.max_y());
+    let mut points = Vec::new();
+    arc(&mut points, &Point2D(left + radii.top_left, top + radii.top_left), radii.top_left, 2.0);
+    arc(&mut points,
+        &Point2D(right - radii.top_right, top + radii.top_right),
+        radii.top_right,
+        3.0);
+    arc(&mut points,
+        &Point2D(right - radii.bottom_right, bottom - radii.bottom_right),
+        radii.bottom_right,
+        0.0);
+    arc(&mut points,
+        &Point2D(left + radii.bottom_left, bottom - radii.bottom_left),
+        radii.bottom_left,
+        1.0);
+    points.iter().map(|point| transform.transform_point(point)).collect()
+}
+
+/// Returns the ring between the outer and the inner edge of a border after the transform, and the
+/// wedges that divide it into its top, right, bottom and left sides.
+///
+/// The ring is made of the outer edge and the inner edge going the other way round, so that the
+/// inner edge makes a hole. The wedges are bounded by lines from the outer corners through the
+/// inner ones, which go far enough in to take in rounded corners.
+pub fn border_polygons(rect: &Rect<f32>,
+                       widths: &SideOffsets2D<f32>,
+                       radii: &BorderRadii<f32>,
+                       transform: &Matrix2D<f32>)
+                       -> (Vec<Polygon>, Vec<Polygon>) {
+    let inner_rect = Rect(Point2D(rect.origin.x + widths.left, rect.origin.y + widths.top),
+                          Size2D((rect.size.width - widths.left - widths.right).max(0.0),
+                                 (rect.size.height - widths.top - widths.bottom).max(0.0)));
+    let inner_radii = BorderRadii {
+        top_left: (radii.top_left - widths.left.max(widths.top)).max(0.0),
+        top_right: (radii.top_right - widths.right.max(widths.top)).max(0.0),
+        bottom_right: (radii.bottom_right - widths.right.max(widths.bottom)).max(0.0),
+        bottom_left: (radii.bottom_left - widths.left.max(widths.bottom)).max(0.0),
+    };
+    let outer = rounded_rect_polygon(rect, radii, transform);
+    let mut inner = rounded_rect_polygon(&inner_rect, &inner_radii, transform);
+    inner.reverse();
+
+    let (left, top, right, bottom) = (rect.origin.x, rect.origin.y, rect.max_x(), rect.max_y());
+    let corner = |x: f32, y: f32, dx: f32, dy: f32, radius: f32| {
+        let reach = (radius / dx.abs().max(dy.abs())).max(1.0);
+        let reach = if reach.is_finite() { reach } else { 1.0 };
+        Point2D(x + dx * reach, y + dy * reach)
+    };
+    let top_left = corner(left, top, widths.left, widths.top, radii.top_left);
+    let top_right = corner(right, top, -widths.right, widths.top, radii.top_right);
+    let bottom_right = corner(right, bottom, -widths.right, -widths.bottom, radii.bottom_right);
+    let bottom_left = corner(left, bottom, widths.left, -widths.bottom, radii.bottom_left);
+    let wedges = vec![
+        vec![Point2D(left, top), Point2D(right, top), top_right, top_left],
+        vec![Point2D(right, top), Point2D(right, bottom), bottom_right, top_right],
+        vec![Point2D(right, bottom), Point2D(left, bottom), bottom_left, bottom_right],
+        vec![Point2D(left, bottom), Point2D(left, top), top_left, bottom_left],
+    ];
+    let wedges = wedges.into_iter().map(|wedge| {
+        wedge.iter().map(|point| transform.transform_point(point)).collect()
+    }).collect();
+    (vec![outer, inner], wedges)
+}
+
+/// Appends a quarter circle around `center`, starting `quarter` quarter turns clockwise from the
+/// positive x axis.
+fn arc(points: &mut Polygon, center: &Point2D<f32>, radius: f32, quarter: f32) {
+    if radius <= 0.0 {
+        points.push(*center);
+        return
+    }
+
+    // Segments of angle `a` stray `r (1 - cos(a / 2))` from the arc.
+    let max_angle = 2.0 * (1.0 - ARC_TOLERANCE / radius).max(-1.0).acos();
+    let segments = (FRAC_PI_2 / max_angle).ceil().max(1.0) as usize;
+    for segment in 0..(segments + 1) {
+        let angle = FRAC_PI_2 * (quarter + segment as f32 / segments as f32);
+        points.push(Point2D(center.x + radius * angle.cos(), center.y + radius * angle.sin()))
+    }
+}
+
+/// The smallest box of whole pixels that contains the polygons.
+pub fn polygon_bounds(polygons: &[Polygon]) -> Rect<i32> {
+    let (mut left, mut top) = (Float::infinity(), Float::infinity());
+    let (mut right, mut bottom) = (Float::neg_infinity(), Float::neg_infinity());
+    for point in polygons.iter().flat_map(|polygon| polygon.iter()) {
+        left = point.x.min(left);
+        top = point.y.min(top);
+        right = point.x.max(right);
+        bottom = point.y.max(bottom);
+    }
+    if !(left <= right && top <= bottom) {
+        return Rect(Point2D(0, 0), Size2D(0, 0))
+    }
+    let (left, top) = (left.floor() as i32, top.floor() as i32);
+    let (right, bottom) = (right.ceil() as i32, bottom.ceil() as i32);
+    Rect(Point2D(left, top), Size2D(right - left, bottom - top))
+}
+
+/// Fills the polygons into a mask over `bounds`. Whatever lies outside `bounds` is left out.
+pub fn rasterize(polygons: &[Polygon], bounds: &Rect<i32>) -> Mask {
+    let mut mask = Mask::new(bounds);
+    let (width, height) = (bounds.size.width, bounds.size.height);
+    if width <= 0 || height <= 0 {
+        return mask
+    }
+
+    // The edges, in coordinates relative to `bounds`, with their direction.
+    let mut edges = Vec::new();
+    for polygon in polygons.iter() {
+        for index in 0..polygon.len() {
+            let (from, to) = (polygon[index], polygon[(index + 1) % polygon.len()]);
+            if from.y == to.y {
+                continue
+            }
+            let (from, to) = (Point2D(from.x - bounds.origin.x as f32,
+                                      from.y - bounds.origin.y as f32),
+                              Point2D(to.x - bounds.origin.x as f32,
+                                      to.y - bounds.origin.y as f32));
+            edges.push(if from.y < to.y { (from, to, 1) } else { (to, from, -1) });
+        }
+    }
+
+    let mut row = vec![0.0; width as usize];
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    for y in 0..height {
+        let mut row_is_empty = true;
+        for subsample in 0..SUBSAMPLES {
+            let sample_y = y as f32 + (subsample as f32 + 0.5) / SUBSAMPLES as f32;
+            crossings.clear();
+            for &(top, bottom, winding) in edges.iter() {
+                if top.y <= sample_y && sample_y < bottom.y {
+                    let x = top.x + (sample_y - top.y) * (bottom.x - top.x) / (bottom.y - top.y);
+                    crossings.push((x, winding))
+                }
+            }
+            if crossings.is_empty() {
+                continue
+            }
+            crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+            let mut winding = 0;
+            let mut span_start = 0.0;
+            for &(x, crossing_winding) in crossings.iter() {
+                let was_inside = winding != 0;
+                winding += crossing_winding;
+                if !was_inside && winding != 0 {
+                    span_start = x
+                } else if was_inside && winding == 0 {
+                    add_span(&mut row[..], span_start, x, 1.0 / SUBSAMPLES as f32);
+                    row_is_empty = false
+                }
+            }
+        }
+
+        if row_is_empty {
+            continue
+        }
+        for x in 0..width {
+            mask.coverage[(y * width + x) as usize] = row[x as usize].min(1.0);
+            row[x as usize] = 0.0
+        }
+    }
+    mask
+}
+
+/// Adds `weight` times the coverage of the span from `start` to `end` to each pixel of the row.
+fn add_span(row: &mut [f32], start: f32, end: f32, weight: f32) {
+    let width = row.len() as f32;
+    let (start, end) = (start.max(0.0).min(width), end.max(0.0).min(width));
+    if start >= end {
+        return
+    }
+
+    let (first, last) = (start.floor() as usize, end.floor() as usize);
+    if first == last {
+        row[first] += (end - start) * weight;
+        return
+    }
+    row[first] += (first as f32 + 1.0 - start) * weight;
+    for pixel in row[first + 1..last].iter_mut() {
+        *pixel += weight
+    }
+    if last < row.len() {
+        row[last] += (end - last as f32) * weight
+    }
+}
+
+/// Blurs `channels` interleaved channels of a `width` by `height` image with three box blurs,
+/// which together are close to a Gaussian blur of the given standard deviation.
+pub fn blur(data: &mut [f32],
+            width: usize,
+            height: usize,
+            channels: usize,
+            std_deviation: f32) {
+    // Three box blurs of radius `r` have a variance of `((2r + 1)^2 - 1) / 4`.
+    let radius = (((4.0 * std_deviation * std_deviation + 1.0).sqrt() - 1.0) / 2.0).round();
+    if radius < 1.0 || width == 0 || height == 0 {
+        return
+    }
+    let radius = radius as usize;
+
+    let mut line = Vec::new();
+    for _ in 0..3 {
+        for y in 0..height {
+            for channel in 0..channels {
+                line.clear();
+                line.extend((0..width).map(|x| data[(y * width + x) * channels + channel]));
+                box_blur_line(&line[..], radius, |x, value| {
+                    data[(y * width + x) * channels + channel] = value
+                })
+            }
+        }
+        for x in 0..width {
+            for channel in 0..channels {
+                line.clear();
+                line.extend((0..height).map(|y| data[(y * width + x) * channels + channel]));
+                box_blur_line(&line[..], radius, |y, value| {
+                    data[(y * width + x) * channels + channel] = value
+                })
+            }
+        }
+    }
+}
+
+/// Averages each value of the line with the `radius` values on each side of it, taking values
+/// past the ends to be zero, and passes the results to `output`.
+fn box_blur_line<F>(line: &[f32], radius: usize, mut output: F) where F: FnMut(usize, f32) {
+    let scale = 1.0 / (2 * radius + 1) as f32;
+    let mut sum: f32 = line.iter().take(radius).fold(0.0, |sum, value| sum + *value);
+    for index in 0..line.len() {
+        if index + radius < line.len() {
+            sum += line[index + radius]
+        }
+        output(index, sum * scale);
+        if index >= radius {
+            sum -= line[index - radius]
+        }
+    }
+}
+
+/// Returns the inverse of the transform, or `None` if it has none.
+pub fn invert(transform: &Matrix2D<f32>) -> Option<Matrix2D<f32>> {
+    let determinant = transform.m11 * transform.m22 - transform.m12 * transform.m21;
+    if determinant == 0.0 || !determinant.is_finite() {
+        return None
+    }
+    let (m11, m12) = (transform.m22 / determinant, -transform.m12 / determinant);
+    let (m21, m22) = (-transform.m21 / determinant, transform.m11 / determinant);
+    Some(Matrix2D::new(m11,
+                       m12,
+                       m21,
+                       m22,
+                       -(transform.m31 * m11 + transform.m32 * m21),
+                       -(transform.m31 * m12 + transform.m32 * m22)))
+}
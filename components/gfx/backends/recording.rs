@@ -0,0 +1,415 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A painting backend that doesn't paint, but records what it is asked to paint as a flat list of
+//! commands, which `replay()` can paint with any other backend later.
+//!
+//! The commands are what is left after the display list optimizer and the clips: drawing that
+//! the clips hide entirely isn't recorded, nor are clips that nothing is drawn in or stacking
+//! contexts that draw nothing. Each drawing command carries the transform it is drawn with and a
+//! bound on the pixels it touches, so that a GPU batcher or a cache can use commands without
+//! keeping track of the state of the stream.
+
+use backends::raster;
+use display_list::{BLUR_INFLATION_FACTOR, BorderRadii, BoxShadowClipMode, ClippingRegion};
+use display_list::{ImageOrientation, TextDisplayItem};
+use image_generator::{ImageGeneratorRegistry, ImageGenerators};
+use paint_backend::{Color, GradientStop, PaintBackend};
+use paint_context::ToAzureRect;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use std::mem;
+use std::num::Float;
+use std::sync::{Arc, Mutex};
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+
+/// One call to a `PaintBackend`.
+#[derive(Clone)]
+pub enum PaintCommand {
+    PushStackingContext {
+        tile_bounds: Rect<f32>,
+        transform: Matrix2D<f32>,
+        clip_rect: Option<Rect<Au>>,
+        filters: filter::T,
+        blend_mode: mix_blend_mode::T,
+    },
+    PopStackingContext,
+    SetClip(ClippingRegion),
+    Clear,
+    Draw(DrawCommand),
+}
+
+/// A drawing operation, with where it draws.
+#[derive(Clone)]
+pub struct DrawCommand {
+    /// The transform that the operation is drawn with, which is that of the stacking context
+    /// around it.
+    pub transform: Matrix2D<f32>,
+    /// A bound on the pixels of the surface that the operation draws, within the clips.
+    pub device_bounds: Rect<f32>,
+    pub operation: DrawOperation,
+}
+
+/// The arguments of one of the drawing methods of `PaintBackend`.
+#[derive(Clone)]
+pub enum DrawOperation {
+    SolidColor(Rect<Au>, Color),
+    Text(Box<TextDisplayItem>),
+    Image(Rect<Au>, Arc<Image>, Option<Rect<f32>>, image_rendering::T, ImageOrientation),
+    Border(Rect<Au>,
+           SideOffsets2D<Au>,
+           BorderRadii<Au>,
+           SideOffsets2D<Color>,
+           SideOffsets2D<border_style::T>),
+    LinearGradient(Rect<Au>, Point2D<Au>, Point2D<Au>, Vec<GradientStop>, bool),
+    RadialGradient(Rect<Au>, Point2D<Au>, Size2D<Au>, Vec<GradientStop>),
+    Line(Rect<Au>, Color, border_style::T),
+    BoxShadow(Rect<Au>, Point2D<Au>, Color, Au, Au, BoxShadowClipMode),
+}
+
+pub struct RecordingBackend {
+    commands: Vec<PaintCommand>,
+    transform: Matrix2D<f32>,
+    /// A bound on the pixels that the clip of the current stacking context lets through, or
+    /// `None` if it has no clip.
+    clip_bounds: Option<Rect<f32>>,
+    /// The clip that `set_clip()` set last, with a bound on the pixels it lets through.
+    transient_clip: Option<(ClippingRegion, Rect<f32>)>,
+    /// The clip of the last `SetClip` command recorded in the current stacking context.
+    recorded_clip: Option<ClippingRegion>,
+    saved_states: Vec<SavedState>,
+    /// The generators that generated images are drawn with before they are recorded, if any.
+    image_generators: Option<ImageGenerators>,
+}
+
+/// The state that `push_stacking_context()` saves and `pop_stacking_context()` restores.
+struct SavedState {
+    transform: Matrix2D<f32>,
+    clip_bounds: Option<Rect<f32>>,
+    transient_clip: Option<(ClippingRegion, Rect<f32>)>,
+    recorded_clip: Option<ClippingRegion>,
+}
+
+impl RecordingBackend {
+    pub fn new(image_generators: Option<ImageGenerators>) -> RecordingBackend {
+        RecordingBackend {
+            commands: Vec::new(),
+            transform: Matrix2D::identity(),
+            clip_bounds: None,
+            transient_clip: None,
+            recorded_clip: None,
+            saved_states: Vec::new(),
+            image_generators: image_generators,
+        }
+    }
+
+    pub fn commands(&self) -> &[PaintCommand] {
+        &self.commands[..]
+    }
+
+    pub fn into_commands(self) -> Vec<PaintCommand> {
+        self.commands
+    }
+
+    /// Returns the smallest rect of the surface that contains the rect after the transform.
+    fn device_bounds(&self, rect: &Rect<Au>) -> Rect<f32> {
+        let polygon = raster::rect_polygon(&rect.to_azure_rect(), &self.transform);
+        let (mut left, mut top) = (Float::infinity(), Float::infinity());
+        let (mut right, mut bottom) = (Float::neg_infinity(), Float::neg_infinity());
+        for point in polygon.iter() {
+            left = point.x.min(left);
+            top = point.y.min(top);
+            right = point.x.max(right);
+            bottom = point.y.max(bottom);
+        }
+        Rect(Point2D(left, top), Size2D(right - left, bottom - top))
+    }
+
+    /// Records the operation, which draws within `device_bounds`, unless the clips hide it. Box
+    /// shadows, which are drawn outside of the clips, aren't `clipped`.
+    fn record(&mut self, device_bounds: Rect<f32>, operation: DrawOperation, clipped: bool) {
+        let mut device_bounds = device_bounds;
+        if clipped {
+            let clips = [self.clip_bounds, self.transient_clip.as_ref().map(|&(_, bounds)| bounds)];
+            for clip_bounds in clips.iter() {
+                if let Some(ref clip_bounds) = *clip_bounds {
+                    device_bounds = match device_bounds.intersection(clip_bounds) {
+                        Some(device_bounds) => device_bounds,
+                        None => return,
+                    }
+                }
+            }
+
+            if let Some((ref clip, _)) = self.transient_clip {
+                if self.recorded_clip.as_ref() != Some(clip) {
+                    self.commands.push(PaintCommand::SetClip(clip.clone()));
+                    self.recorded_clip = Some(clip.clone())
+                }
+            }
+        }
+
+        self.commands.push(PaintCommand::Draw(DrawCommand {
+            transform: self.transform,
+            device_bounds: device_bounds,
+            operation: operation,
+        }))
+    }
+}
+
+impl PaintBackend for RecordingBackend {
+    fn transform(&self) -> Matrix2D<f32> {
+        self.transform
+    }
+
+    fn image_generators(&self) -> Option<&Mutex<ImageGeneratorRegistry>> {
+        self.image_generators.as_ref().map(|image_generators| &**image_generators)
+    }
+
+    fn push_stacking_context(&mut self,
+                             tile_bounds: &Rect<f32>,
+                             transform: &Matrix2D<f32>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T) {
+        self.commands.push(PaintCommand::PushStackingContext {
+            tile_bounds: *tile_bounds,
+            transform: *transform,
+            clip_rect: clip_rect.map(|clip_rect| *clip_rect),
+            filters: filters.clone(),
+            blend_mode: blend_mode,
+        });
+
+        let transform = mem::replace(&mut self.transform, *transform);
+        let clip_bounds = match clip_rect {
+            None => self.clip_bounds,
+            Some(clip_rect) => {
+                let bounds = self.device_bounds(clip_rect);
+                match self.clip_bounds {
+                    None => Some(bounds),
+                    Some(ref clip_bounds) => {
+                        Some(clip_bounds.intersection(&bounds).unwrap_or(Rect(Point2D(0.0, 0.0),
+                                                                              Size2D(0.0, 0.0))))
+                    }
+                }
+            }
+        };
+        self.saved_states.push(SavedState {
+            transform: transform,
+            clip_bounds: mem::replace(&mut self.clip_bounds, clip_bounds),
+            transient_clip: self.transient_clip.take(),
+            recorded_clip: self.recorded_clip.take(),
+        });
+    }
+
+    fn pop_stacking_context(&mut self) {
+        let saved_state = self.saved_states.pop().expect("no stacking context to pop");
+        self.transform = saved_state.transform;
+        self.clip_bounds = saved_state.clip_bounds;
+        self.transient_clip = saved_state.transient_clip;
+        self.recorded_clip = saved_state.recorded_clip;
+
+        // Leave out stacking contexts that turned out to draw nothing.
+        if let Some(&PaintCommand::PushStackingContext { .. }) = self.commands.last() {
+            self.commands.pop();
+            return
+        }
+        self.commands.push(PaintCommand::PopStackingContext)
+    }
+
+    fn set_clip(&mut self, clip: &ClippingRegion) {
+        if let Some((ref transient_clip, _)) = self.transient_clip {
+            if transient_clip == clip {
+                return
+            }
+        }
+        let bounds = self.device_bounds(&clip.main);
+        self.transient_clip = Some((clip.clone(), bounds))
+    }
+
+    fn clear(&mut self) {
+        self.commands.push(PaintCommand::Clear)
+    }
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: Color) {
+        let device_bounds = self.device_bounds(bounds);
+        self.record(device_bounds, DrawOperation::SolidColor(*bounds, color), true)
+    }
+
+    fn draw_text(&mut self, text: &TextDisplayItem) {
+        let inflation = text.blur_radius * BLUR_INFLATION_FACTOR;
+        let device_bounds = self.device_bounds(&text.base.bounds.inflate(inflation, inflation));
+        self.record(device_bounds, DrawOperation::Text(box text.clone()), true)
+    }
+
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<f32>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation) {
+        let device_bounds = self.device_bounds(bounds);
+        self.record(device_bounds,
+                    DrawOperation::Image(*bounds, image, source_rect, image_rendering, orientation),
+                    true)
+    }
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<Color>,
+                   style: &SideOffsets2D<border_style::T>) {
+        let device_bounds = self.device_bounds(bounds);
+        self.record(device_bounds,
+                    DrawOperation::Border(*bounds,
+                                          border.clone(),
+                                          *radius,
+                                          color.clone(),
+                                          style.clone()),
+                    true)
+    }
+
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool) {
+        let device_bounds = self.device_bounds(bounds);
+        self.record(device_bounds,
+                    DrawOperation::LinearGradient(*bounds,
+                                                  *start_point,
+                                                  *end_point,
+                                                  stops.to_vec(),
+                                                  repeating),
+                    true)
+    }
+
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]) {
+        let device_bounds = self.device_bounds(bounds);
+        self.record(device_bounds,
+                    DrawOperation::RadialGradient(*bounds, *center, *radius, stops.to_vec()),
+                    true)
+    }
+
+    fn draw_line(&mut self, bounds: &Rect<Au>, color: Color, style: border_style::T) {
+        let device_bounds = self.device_bounds(bounds);
+        self.record(device_bounds, DrawOperation::Line(*bounds, color, style), true)
+    }
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+        // Inset shadows stay inside the box, and others may reach as far as their blur does.
+        let extent = match clip_mode {
+            BoxShadowClipMode::Inset => *box_bounds,
+            BoxShadowClipMode::None | BoxShadowClipMode::Outset => {
+                let inflation = spread_radius + blur_radius * BLUR_INFLATION_FACTOR;
+                box_bounds.translate(offset).inflate(inflation, inflation)
+            }
+        };
+        let device_bounds = self.device_bounds(&extent);
+        self.record(device_bounds,
+                    DrawOperation::BoxShadow(*box_bounds,
+                                             *offset,
+                                             color,
+                                             blur_radius,
+                                             spread_radius,
+                                             clip_mode),
+                    false)
+    }
+}
+
+/// Paints recorded commands with the given backend.
+pub fn replay(commands: &[PaintCommand], paint_backend: &mut PaintBackend) {
+    for command in commands.iter() {
+        match *command {
+            PaintCommand::PushStackingContext {
+                ref tile_bounds,
+                ref transform,
+                ref clip_rect,
+                ref filters,
+                blend_mode,
+            } => {
+                paint_backend.push_stacking_context(tile_bounds,
+                                                    transform,
+                                                    clip_rect.as_ref(),
+                                                    filters,
+                                                    blend_mode)
+            }
+            PaintCommand::PopStackingContext => paint_backend.pop_stacking_context(),
+            PaintCommand::SetClip(ref clip) => paint_backend.set_clip(clip),
+            PaintCommand::Clear => paint_backend.clear(),
+            PaintCommand::Draw(ref draw_command) => replay_operation(&draw_command.operation,
+                                                                     paint_backend),
+        }
+    }
+}
+
+fn replay_operation(operation: &DrawOperation, paint_backend: &mut PaintBackend) {
+    match *operation {
+        DrawOperation::SolidColor(ref bounds, color) => {
+            paint_backend.draw_solid_color(bounds, color)
+        }
+        DrawOperation::Text(ref text) => paint_backend.draw_text(&**text),
+        DrawOperation::Image(ref bounds,
+                             ref image,
+                             source_rect,
+                             image_rendering,
+                             orientation) => {
+            paint_backend.draw_image(bounds,
+                                     image.clone(),
+                                     source_rect,
+                                     image_rendering,
+                                     orientation)
+        }
+        DrawOperation::Border(ref bounds, ref border, ref radius, ref color, ref style) => {
+            paint_backend.draw_border(bounds, border, radius, color, style)
+        }
+        DrawOperation::LinearGradient(ref bounds,
+                                      ref start_point,
+                                      ref end_point,
+                                      ref stops,
+                                      repeating) => {
+            paint_backend.draw_linear_gradient(bounds,
+                                               start_point,
+                                               end_point,
+                                               &stops[..],
+                                               repeating)
+        }
+        DrawOperation::RadialGradient(ref bounds, ref center, ref radius, ref stops) => {
+            paint_backend.draw_radial_gradient(bounds, center, radius, &stops[..])
+        }
+        DrawOperation::Line(ref bounds, color, style) => {
+            paint_backend.draw_line(bounds, color, style)
+        }
+        DrawOperation::BoxShadow(ref box_bounds,
+                                 ref offset,
+                                 color,
+                                 blur_radius,
+                                 spread_radius,
+                                 clip_mode) => {
+            paint_backend.draw_box_shadow(box_bounds,
+                                          offset,
+                                          color,
+                                          blur_radius,
+                                          spread_radius,
+                                          clip_mode)
+        }
+    }
+}
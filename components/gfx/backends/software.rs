@@ -0,0 +1,1108 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A painting backend written in plain Rust, which paints into a buffer in memory without Azure
+//! or Skia. It is slower and draws less than the Azure backend, but it runs anywhere, which makes
+//! it useful for headless testing and fuzzing.
+//!
+//! Every shape is turned into polygons and scan converted into a coverage mask (see `raster`),
+//! which is filled with a color, a gradient or an image. Pixels are kept as premultiplied
+//! floating point RGBA until they are read out.
+//!
+//! Text is drawn from the outlines of its glyphs (see `glyph_outline`).
+//!
+//! TODO: Dotted, dashed and 3D borders are drawn solid. Glyphs that `text-orientation: mixed`
+//! keeps upright in sideways text are drawn sideways.
+
+use backends::glyph_outline::{self, GlyphOutlines};
+use backends::raster::{self, Mask, Polygon};
+use color::to_paint_color;
+use color_matrix;
+use display_list::TextOrientation::{SidewaysLeft, SidewaysRight, Upright};
+use display_list::{BorderRadii, BoxShadowClipMode, ClippingRegion, ImageOrientation};
+use display_list::TextDisplayItem;
+use filters;
+use font::{SYNTHETIC_OBLIQUE_FONT_FLAG, SYNTHETIC_OBLIQUE_SKEW};
+use paint_backend::{BackendCapabilities, BlurCost, Color, GradientStop, PaintBackend, ToRadiiPx};
+use paint_backend::ToSideOffsetsPx;
+use paint_context::ToAzureRect;
+use platform::font_template::FontTemplateData;
+use svg_filter::{self, CompositeOperator, FilterInput, Primitive, SvgFilter};
+use text::glyph::CharIndex;
+use text::text_run::TextRun;
+
+use azure::azure_hl::Matrix5x4;
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::collections::HashMap;
+use std::mem;
+use std::num::Float;
+use std::sync::Arc;
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+use util::opts::PixelFormat;
+use util::range::Range;
+
+/// The number of standard deviations of a blur that masks leave room for around their shapes.
+static BLUR_EXTENT: f32 = 3.0;
+
+/// A premultiplied RGBA color.
+pub type Pixel = [f32; 4];
+
+pub struct SoftwareBackend {
+    width: usize,
+    height: usize,
+    /// The surface being painted into, after those of the enclosing stacking contexts that paint
+    /// into surfaces of their own. The first one is the result.
+    surfaces: Vec<Vec<Pixel>>,
+    transform: Matrix2D<f32>,
+    /// The clip of the stacking context being painted, combined with those around it.
+    clip: Option<Mask>,
+    /// The clip that `set_clip()` set last, and the mask it was turned into.
+    transient_clip: Option<(ClippingRegion, Mask)>,
+    saved_states: Vec<SavedState>,
+    /// The outlines of the fonts that text has been drawn in, by font identifier, or `None` for
+    /// fonts that have none.
+    fonts: HashMap<String, Option<GlyphOutlines>>,
+}
+
+/// The state that `push_stacking_context()` saves and `pop_stacking_context()` restores.
+struct SavedState {
+    transform: Matrix2D<f32>,
+    clip: Option<Mask>,
+    transient_clip: Option<(ClippingRegion, Mask)>,
+    /// The filters and blend mode to composite the surface of the stacking context with, if it
+    /// was given one.
+    composite: Option<(filter::T, mix_blend_mode::T)>,
+}
+
+/// What a mask is filled with.
+enum Paint<'a> {
+    Color(Pixel),
+    /// A linear gradient from `start` to `end`, in device pixels, and whether it repeats.
+    LinearGradient(Point2D<f32>, Point2D<f32>, &'a [GradientStop], bool),
+    /// A radial gradient, with the transform from device pixels to the space in which the ellipse
+    /// of its stop at offset 1 is the unit circle about the origin.
+    RadialGradient(Matrix2D<f32>, &'a [GradientStop]),
+    /// An image, with the transform from device pixels to its pixels and whether to sample the
+    /// nearest pixel rather than interpolate.
+    Image(&'a Image, Matrix2D<f32>, bool),
+}
+
+impl SoftwareBackend {
+    /// Creates a backend that paints into a transparent surface of the given size in pixels.
+    pub fn new(width: usize, height: usize) -> SoftwareBackend {
+        SoftwareBackend {
+            width: width,
+            height: height,
+            surfaces: vec![vec![[0.0; 4]; width * height]],
+            transform: Matrix2D::identity(),
+            clip: None,
+            transient_clip: None,
+            saved_states: Vec::new(),
+            fonts: HashMap::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the painted pixels as premultiplied B8G8R8A8, the format of Azure draw targets.
+    pub fn to_bgra(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.width * self.height * 4);
+        for pixel in self.surfaces[0].iter() {
+            result.push(to_byte(pixel[2]));
+            result.push(to_byte(pixel[1]));
+            result.push(to_byte(pixel[0]));
+            result.push(to_byte(pixel[3]));
+        }
+        result
+    }
+
+    /// Writes the painted pixels into `data` in the given format, in rows `stride` bytes apart.
+    /// Colors are only clamped as far as the format needs.
+    pub fn write_pixels(&self, format: PixelFormat, data: &mut [u8], stride: usize) {
+        let row_length = self.width * format.bytes_per_pixel();
+        for (y, row) in self.surfaces[0].chunks(self.width).enumerate() {
+            let destination = &mut data[y * stride..y * stride + row_length];
+            for (pixel, destination) in row.iter()
+                                           .zip(destination.chunks_mut(format.bytes_per_pixel())) {
+                match format {
+                    PixelFormat::Bgra8 => {
+                        destination[0] = to_byte(pixel[2]);
+                        destination[1] = to_byte(pixel[1]);
+                        destination[2] = to_byte(pixel[0]);
+                        destination[3] = to_byte(pixel[3]);
+                    }
+                    PixelFormat::Rgb10A2 => {
+                        let channel = |value: f32, max: f32| {
+                            (value.max(0.0).min(1.0) * max + 0.5) as u32
+                        };
+                        let word = channel(pixel[0], 1023.0) |
+                                   channel(pixel[1], 1023.0) << 10 |
+                                   channel(pixel[2], 1023.0) << 20 |
+                                   channel(pixel[3], 3.0) << 30;
+                        for byte in 0..4 {
+                            destination[byte] = (word >> (byte * 8)) as u8
+                        }
+                    }
+                    PixelFormat::Rgba16F => {
+                        for channel in 0..4 {
+                            let half = to_half(pixel[channel]);
+                            destination[channel * 2] = half as u8;
+                            destination[channel * 2 + 1] = (half >> 8) as u8;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn surface_bounds(&self) -> Rect<i32> {
+        Rect(Point2D(0, 0), Size2D(self.width as i32, self.height as i32))
+    }
+
+    /// Fills the polygons, clipped to the current clips.
+    fn fill_polygons(&mut self, polygons: &[Polygon], paint: &Paint) {
+        let bounds = match raster::polygon_bounds(polygons).intersection(&self.surface_bounds()) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let mask = raster::rasterize(polygons, &bounds);
+        self.fill(&mask, paint, true)
+    }
+
+    /// Composites `paint` onto the current surface where the mask covers it, and also where the
+    /// current clips do if `clipped` is true.
+    fn fill(&mut self, mask: &Mask, paint: &Paint, clipped: bool) {
+        let bounds = match mask.bounds.intersection(&self.surface_bounds()) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let width = self.width;
+        let (clip, transient_clip) = if clipped {
+            (self.clip.as_ref(), self.transient_clip.as_ref().map(|&(_, ref mask)| mask))
+        } else {
+            (None, None)
+        };
+        let surface = self.surfaces.last_mut().unwrap();
+        for y in bounds.origin.y..bounds.max_y() {
+            for x in bounds.origin.x..bounds.max_x() {
+                let mut coverage = mask.get(x, y);
+                if let Some(clip) = clip {
+                    coverage *= clip.get(x, y)
+                }
+                if let Some(transient_clip) = transient_clip {
+                    coverage *= transient_clip.get(x, y)
+                }
+                if coverage <= 0.0 {
+                    continue
+                }
+
+                let source = paint.sample(x as f32 + 0.5, y as f32 + 0.5);
+                let destination = &mut surface[y as usize * width + x as usize];
+                let inverse_alpha = 1.0 - source[3] * coverage;
+                for channel in 0..4 {
+                    destination[channel] =
+                        source[channel] * coverage + destination[channel] * inverse_alpha
+                }
+            }
+        }
+    }
+
+    /// Fills the polygons with the color, blurred by `blur_radius`, clipped to the current clips.
+    fn fill_blurred(&mut self, polygons: &[Polygon], color: &Color, blur_radius: Au) {
+        if polygons.is_empty() {
+            return
+        }
+        let paint = Paint::Color(premultiply(color));
+        if blur_radius == Au(0) {
+            return self.fill_polygons(polygons, &paint)
+        }
+
+        let std_deviation = blur_radius.to_frac32_px() * self.scale();
+        let margin = (std_deviation * BLUR_EXTENT).ceil() as i32;
+        let bounds = raster::polygon_bounds(polygons).inflate(margin, margin);
+        let bounds = match bounds.intersection(&self.surface_bounds().inflate(margin, margin)) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let mut mask = raster::rasterize(polygons, &bounds);
+        mask.blur(std_deviation);
+        self.fill(&mask, &paint, true)
+    }
+
+    /// Returns the outlines of the font, reading its tables the first time it is drawn with.
+    fn outlines(&mut self, template: &Arc<FontTemplateData>) -> Option<&mut GlyphOutlines> {
+        if !self.fonts.contains_key(&template.identifier) {
+            self.fonts.insert(template.identifier.clone(), GlyphOutlines::new(template.clone()));
+        }
+        self.fonts.get_mut(&template.identifier).unwrap().as_mut()
+    }
+
+    /// Returns the mask of the rect in app units under the current transform, over the whole
+    /// surface.
+    fn rect_mask(&self, rect: &Rect<Au>) -> Mask {
+        raster::rasterize(&[raster::rect_polygon(&rect.to_azure_rect(), &self.transform)],
+                          &self.surface_bounds())
+    }
+
+    /// Returns how much the current transform scales lengths, on average.
+    fn scale(&self) -> f32 {
+        let size = self.device_size(&Rect(Point2D(0.0, 0.0), Size2D(1.0, 1.0)));
+        (size.width * size.height).sqrt()
+    }
+
+    /// Applies the filters to the surface and composites it onto the surface beneath it with the
+    /// blend mode, clipped to the current clip.
+    fn composite(&mut self,
+                 mut surface: Vec<Pixel>,
+                 filters: &filter::T,
+                 blend_mode: mix_blend_mode::T) {
+        let mut opacity = 1.0;
+        for style_filter in filters.filters.iter() {
+            match *style_filter {
+                filter::Filter::Opacity(amount) => opacity *= amount as f32,
+                filter::Filter::Blur(amount) => {
+                    let std_deviation = amount.to_frac32_px() * self.scale();
+                    blur(&mut surface[..], self.width, self.height, std_deviation)
+                }
+                filter::Filter::HueRotate(angle) => {
+                    apply_color_matrix(&mut surface[..],
+                                       &color_matrix::hue_rotate(angle.radians() as f32))
+                }
+                filter::Filter::Saturate(amount) => {
+                    apply_color_matrix(&mut surface[..], &color_matrix::saturate(amount as f32))
+                }
+                filter::Filter::Sepia(amount) => {
+                    apply_color_matrix(&mut surface[..], &color_matrix::sepia(amount as f32))
+                }
+                filter::Filter::Grayscale(amount) => {
+                    apply_color_matrix(&mut surface[..], &color_matrix::grayscale(amount as f32))
+                }
+                filter::Filter::Invert(amount) => {
+                    let amount = amount as f32;
+                    apply_transfer(&mut surface[..], |value| {
+                        amount * (1.0 - value) + (1.0 - amount) * value
+                    })
+                }
+                filter::Filter::Brightness(amount) => {
+                    let amount = amount as f32;
+                    apply_transfer(&mut surface[..], |value| value * amount)
+                }
+                filter::Filter::Contrast(amount) => {
+                    let amount = amount as f32;
+                    apply_transfer(&mut surface[..], |value| (value - 0.5) * amount + 0.5)
+                }
+                filter::Filter::Url(ref url) => {
+                    if let Some(svg_filter) = svg_filter::get(url) {
+                        let (width, height, scale) = (self.width, self.height, self.scale());
+                        surface = apply_svg_filter(surface, width, height, &*svg_filter, scale)
+                    }
+                }
+                filter::Filter::DropShadow(ref shadow) => {
+                    let scale = self.scale();
+                    surface = drop_shadow(&surface[..], self.width, self.height, shadow, scale)
+                }
+            }
+        }
+
+        let width = self.width;
+        let clip = self.clip.as_ref();
+        let destination_surface = self.surfaces.last_mut().unwrap();
+        for (index, source) in surface.iter().enumerate() {
+            let mut alpha = opacity;
+            if let Some(clip) = clip {
+                alpha *= clip.get((index % width) as i32, (index / width) as i32)
+            }
+            if alpha <= 0.0 || source[3] <= 0.0 {
+                continue
+            }
+            let source =
+                [source[0] * alpha, source[1] * alpha, source[2] * alpha, source[3] * alpha];
+            let destination = &mut destination_surface[index];
+            *destination = blend(&source, destination, blend_mode)
+        }
+    }
+}
+
+impl PaintBackend for SoftwareBackend {
+    fn transform(&self) -> Matrix2D<f32> {
+        self.transform
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        // Blurs are done on every pixel they cover, on the CPU.
+        BackendCapabilities {
+            blur: BlurCost::Expensive,
+            ..BackendCapabilities::all()
+        }
+    }
+
+    fn push_stacking_context(&mut self,
+                             _: &Rect<f32>,
+                             transform: &Matrix2D<f32>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T) {
+        let composite = if filters::temporary_draw_target_needed_for_style_filters(filters) ||
+                blend_mode != mix_blend_mode::T::normal {
+            self.surfaces.push(vec![[0.0; 4]; self.width * self.height]);
+            Some((filters.clone(), blend_mode))
+        } else {
+            None
+        };
+
+        let transform = mem::replace(&mut self.transform, *transform);
+        let clip = match clip_rect {
+            None => self.clip.clone(),
+            Some(clip_rect) => {
+                let mask = self.rect_mask(clip_rect);
+                Some(match self.clip {
+                    None => mask,
+                    Some(ref clip) => clip.intersect(&mask),
+                })
+            }
+        };
+
+        self.saved_states.push(SavedState {
+            transform: transform,
+            clip: mem::replace(&mut self.clip, clip),
+            transient_clip: self.transient_clip.take(),
+            composite: composite,
+        });
+    }
+
+    fn pop_stacking_context(&mut self) {
+        let saved_state = self.saved_states.pop().expect("no stacking context to pop");
+        self.transform = saved_state.transform;
+        self.clip = saved_state.clip;
+        self.transient_clip = saved_state.transient_clip;
+        if let Some((filters, blend_mode)) = saved_state.composite {
+            let surface = self.surfaces.pop().unwrap();
+            self.composite(surface, &filters, blend_mode)
+        }
+    }
+
+    fn set_clip(&mut self, clip: &ClippingRegion) {
+        if let Some((ref transient_clip, _)) = self.transient_clip {
+            if transient_clip == clip {
+                return
+            }
+        }
+
+        let mut mask = self.rect_mask(&clip.main);
+        for complex_region in clip.complex.iter() {
+            let polygon = raster::rounded_rect_polygon(&complex_region.rect.to_azure_rect(),
+                                                       &complex_region.radii.to_radii_px(),
+                                                       &self.transform);
+            let bounds = mask.bounds;
+            mask = mask.intersect(&raster::rasterize(&[polygon], &bounds));
+        }
+        self.transient_clip = Some((clip.clone(), mask))
+    }
+
+    fn clear(&mut self) {
+        for pixel in self.surfaces.last_mut().unwrap().iter_mut() {
+            *pixel = [0.0; 4]
+        }
+    }
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: Color) {
+        let polygon = raster::rect_polygon(&bounds.to_azure_rect(), &self.transform);
+        self.fill_polygons(&[polygon], &Paint::Color(premultiply(&color)))
+    }
+
+    fn draw_text(&mut self, text: &TextDisplayItem) {
+        // Rotate sideways text about the start of its baseline, as the Azure backend does.
+        let baseline_x = text.baseline_origin.x.to_subpx() as f32;
+        let baseline_y = text.baseline_origin.y.to_subpx() as f32;
+        let (transform, origin) = match text.orientation {
+            Upright => (self.transform, text.baseline_origin),
+            SidewaysLeft => {
+                (self.transform.mul(&Matrix2D::new(0., -1., 1., 0., baseline_x, baseline_y)),
+                 Point2D::zero())
+            }
+            SidewaysRight => {
+                (self.transform.mul(&Matrix2D::new(0., 1., -1., 0., baseline_x, baseline_y)),
+                 Point2D::zero())
+            }
+        };
+
+        let run = &**text.text_run;
+        let mut polygons = vec!();
+        if let Some(outlines) = self.outlines(&run.font_template) {
+            polygons = glyph_polygons(outlines, run, &text.range, origin, &transform);
+
+            // Synthetic bold strikes the glyphs a second time, slightly offset.
+            let synthetic_bold_offset = run.synthetic_bold_offset();
+            if synthetic_bold_offset != Au(0) {
+                let origin = Point2D(origin.x + synthetic_bold_offset, origin.y);
+                polygons.extend(glyph_polygons(outlines, run, &text.range, origin, &transform)
+                                    .into_iter())
+            }
+        }
+        self.fill_blurred(&polygons[..], &to_paint_color(text.text_color), text.blur_radius);
+
+        // Emphasis marks are painted in their own font, one for each character of the base text
+        // that isn't whitespace.
+        if let Some(ref marks) = text.emphasis_marks {
+            let mut polygons = vec!();
+            if let Some(outlines) = self.outlines(&marks.text_run.font_template) {
+                for offset in marks.baseline_offsets(run, &text.range).iter() {
+                    polygons.extend(glyph_polygons(outlines,
+                                                   &**marks.text_run,
+                                                   &marks.range,
+                                                   origin + *offset,
+                                                   &transform).into_iter())
+                }
+            }
+            self.fill_blurred(&polygons[..], &to_paint_color(marks.text_color), text.blur_radius)
+        }
+
+        // Ruby annotations are painted in their own font, beside the base text.
+        if let Some(ref annotation) = text.ruby_annotation {
+            let origin = origin + annotation.baseline_offset(run, &text.range);
+            let polygons = match self.outlines(&annotation.text_run.font_template) {
+                Some(outlines) => {
+                    glyph_polygons(outlines,
+                                   &**annotation.text_run,
+                                   &annotation.range,
+                                   origin,
+                                   &transform)
+                }
+                None => return,
+            };
+            self.fill_blurred(&polygons[..],
+                              &to_paint_color(annotation.text_color),
+                              text.blur_radius)
+        }
+    }
+
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<f32>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation) {
+        let source_rect = source_rect.unwrap_or(
+            Rect(Point2D(0.0, 0.0), Size2D(image.width as f32, image.height as f32)));
+        let mut dest_rect = bounds.to_azure_rect();
+        let mut transform = self.transform;
+        if orientation != ImageOrientation::identity() {
+            transform = transform.mul(&orientation.transform(&dest_rect));
+            dest_rect.origin = Point2D(0.0, 0.0);
+            if orientation.swaps_dimensions() {
+                dest_rect.size = Size2D(dest_rect.size.height, dest_rect.size.width);
+            }
+        }
+        if dest_rect.size.width <= 0.0 || dest_rect.size.height <= 0.0 {
+            return
+        }
+
+        // Map the destination rect onto the source rect, then the transform onto the surface.
+        let image_to_device =
+            transform.mul(&Matrix2D::identity()
+                               .translate(dest_rect.origin.x, dest_rect.origin.y)
+                               .scale(dest_rect.size.width / source_rect.size.width,
+                                      dest_rect.size.height / source_rect.size.height)
+                               .translate(-source_rect.origin.x, -source_rect.origin.y));
+        let device_to_image = match raster::invert(&image_to_device) {
+            Some(device_to_image) => device_to_image,
+            None => return,
+        };
+        let nearest = match image_rendering {
+            image_rendering::T::Auto => false,
+            image_rendering::T::CrispEdges | image_rendering::T::Pixelated => true,
+        };
+        let polygon = raster::rect_polygon(&dest_rect, &transform);
+        self.fill_polygons(&[polygon], &Paint::Image(&*image, device_to_image, nearest))
+    }
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<Color>,
+                   style: &SideOffsets2D<border_style::T>) {
+        let (ring, wedges) = raster::border_polygons(&bounds.to_azure_rect(),
+                                                     &border.to_float_px(),
+                                                     &radius.to_radii_px(),
+                                                     &self.transform);
+        let bounds = match raster::polygon_bounds(&ring).intersection(&self.surface_bounds()) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let ring = raster::rasterize(&ring, &bounds);
+
+        let widths = border.to_float_px();
+        let sides = [(widths.top, color.top, style.top),
+                     (widths.right, color.right, style.right),
+                     (widths.bottom, color.bottom, style.bottom),
+                     (widths.left, color.left, style.left)];
+        for (&(width, color, style), wedge) in sides.iter().zip(wedges.into_iter()) {
+            if width <= 0.0 || style == border_style::T::none || style == border_style::T::hidden {
+                continue
+            }
+            let mask = ring.intersect(&raster::rasterize(&[wedge], &bounds));
+            self.fill(&mask, &Paint::Color(premultiply(&color)), true)
+        }
+    }
+
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool) {
+        if stops.is_empty() {
+            return
+        }
+        let start = self.transform.transform_point(&Point2D(start_point.x.to_subpx() as f32,
+                                                            start_point.y.to_subpx() as f32));
+        let end = self.transform.transform_point(&Point2D(end_point.x.to_subpx() as f32,
+                                                          end_point.y.to_subpx() as f32));
+        let polygon = raster::rect_polygon(&bounds.to_azure_rect(), &self.transform);
+        self.fill_polygons(&[polygon], &Paint::LinearGradient(start, end, stops, repeating))
+    }
+
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]) {
+        if stops.is_empty() {
+            return
+        }
+        let unit_circle_to_device =
+            self.transform.mul(&raster::radial_gradient_transform(center, radius));
+        let device_to_unit_circle = match raster::invert(&unit_circle_to_device) {
+            Some(device_to_unit_circle) => device_to_unit_circle,
+            None => return,
+        };
+        let polygon = raster::rect_polygon(&bounds.to_azure_rect(), &self.transform);
+        self.fill_polygons(&[polygon], &Paint::RadialGradient(device_to_unit_circle, stops))
+    }
+
+    fn draw_line(&mut self, bounds: &Rect<Au>, color: Color, _: border_style::T) {
+        self.draw_solid_color(bounds, color)
+    }
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+        // Like the Azure backend, draw outside the clips, since shadows may reach beyond them.
+        let shadow_bounds = box_bounds.translate(offset).inflate(spread_radius, spread_radius);
+        let std_deviation = blur_radius.to_frac32_px() * self.scale();
+        let margin = (std_deviation * BLUR_EXTENT).ceil() as i32;
+        let surface_bounds = self.surface_bounds();
+        let bounds = Rect(Point2D(-margin, -margin),
+                          Size2D(surface_bounds.size.width + margin * 2,
+                                 surface_bounds.size.height + margin * 2));
+
+        let shadow = [raster::rect_polygon(&shadow_bounds.to_azure_rect(), &self.transform)];
+        let mut shadow = raster::rasterize(&shadow, &bounds);
+        if clip_mode == BoxShadowClipMode::Inset {
+            shadow = shadow.invert(&bounds)
+        }
+        if std_deviation > 0.0 {
+            shadow.blur(std_deviation)
+        }
+
+        let box_mask = self.rect_mask(box_bounds);
+        let mask = match clip_mode {
+            BoxShadowClipMode::None => shadow,
+            BoxShadowClipMode::Outset => shadow.intersect(&box_mask.invert(&surface_bounds)),
+            BoxShadowClipMode::Inset => shadow.intersect(&box_mask),
+        };
+        self.fill(&mask, &Paint::Color(premultiply(&color)), false)
+    }
+}
+
+/// Returns the polygons of the glyphs of `range` of the run, set from `origin` on the baseline
+/// and mapped into device pixels by `transform`.
+fn glyph_polygons(outlines: &mut GlyphOutlines,
+                  run: &TextRun,
+                  range: &Range<CharIndex>,
+                  mut origin: Point2D<Au>,
+                  transform: &Matrix2D<f32>)
+                  -> Vec<Polygon> {
+    let size = run.actual_pt_size.to_subpx() as f32;
+    let skew = if run.synthesis.contains(SYNTHETIC_OBLIQUE_FONT_FLAG) {
+        SYNTHETIC_OBLIQUE_SKEW as f32
+    } else {
+        0.0
+    };
+
+    // Glyphs are stored in logical order. Set right-to-left runs starting from their last glyph,
+    // so that the origin always moves from left to right.
+    let mut glyphs = vec!();
+    for slice in run.natural_word_slices_in_range(range) {
+        for (_, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+            glyphs.push((glyph.id(), glyph.advance(), glyph.offset()))
+        }
+    }
+    if run.is_rtl() {
+        glyphs.reverse()
+    }
+
+    let mut polygons = vec!();
+    for &(glyph_id, glyph_advance, glyph_offset) in glyphs.iter() {
+        let glyph_offset = glyph_offset.unwrap_or(Point2D::zero());
+        let glyph_origin = Point2D(origin.x + glyph_offset.x, origin.y + glyph_offset.y);
+        origin = Point2D(origin.x + glyph_advance, origin.y);
+
+        // Outlines are in ems, with y going up.
+        let glyph_to_device = transform.mul(&Matrix2D::new(size,
+                                                           0.0,
+                                                           skew * size,
+                                                           -size,
+                                                           glyph_origin.x.to_subpx() as f32,
+                                                           glyph_origin.y.to_subpx() as f32));
+        for contour in outlines.outline(glyph_id).iter() {
+            polygons.push(glyph_outline::flatten(contour, &glyph_to_device))
+        }
+    }
+    polygons
+}
+
+impl<'a> Paint<'a> {
+    /// Returns the color of the paint at the given point in device pixels.
+    fn sample(&self, x: f32, y: f32) -> Pixel {
+        match *self {
+            Paint::Color(color) => color,
+            Paint::LinearGradient(start, end, stops, repeating) => {
+                let (dx, dy) = (end.x - start.x, end.y - start.y);
+                let length_squared = dx * dx + dy * dy;
+                let position = if length_squared > 0.0 {
+                    ((x - start.x) * dx + (y - start.y) * dy) / length_squared
+                } else {
+                    0.0
+                };
+                gradient_color(stops, if repeating { repeat(position) } else { position })
+            }
+            Paint::RadialGradient(ref device_to_unit_circle, stops) => {
+                let point = device_to_unit_circle.transform_point(&Point2D(x, y));
+                gradient_color(stops, (point.x * point.x + point.y * point.y).sqrt())
+            }
+            Paint::Image(image, ref device_to_image, nearest) => {
+                let point = device_to_image.transform_point(&Point2D(x, y));
+                if nearest {
+                    image_pixel(image, point.x.floor() as i32, point.y.floor() as i32)
+                } else {
+                    // Interpolate between the centers of the four nearest pixels.
+                    let (x, y) = (point.x - 0.5, point.y - 0.5);
+                    let (left, top) = (x.floor(), y.floor());
+                    let (fx, fy) = (x - left, y - top);
+                    let (left, top) = (left as i32, top as i32);
+                    let samples = [(image_pixel(image, left, top), (1.0 - fx) * (1.0 - fy)),
+                                   (image_pixel(image, left + 1, top), fx * (1.0 - fy)),
+                                   (image_pixel(image, left, top + 1), (1.0 - fx) * fy),
+                                   (image_pixel(image, left + 1, top + 1), fx * fy)];
+                    let mut result = [0.0; 4];
+                    for &(pixel, weight) in samples.iter() {
+                        for channel in 0..4 {
+                            result[channel] += pixel[channel] * weight
+                        }
+                    }
+                    result
+                }
+            }
+        }
+    }
+}
+
+/// Returns the premultiplied color of the image at the given pixel, clamping to its edges.
+fn image_pixel(image: &Image, x: i32, y: i32) -> Pixel {
+    if image.width == 0 || image.height == 0 {
+        return [0.0; 4]
+    }
+    let x = x.max(0).min(image.width as i32 - 1) as usize;
+    let y = y.max(0).min(image.height as i32 - 1) as usize;
+    let index = y * image.width as usize + x;
+    let value = |byte: u8| byte as f32 / 255.0;
+
+    // Decoded images have their color channels in BGR order.
+    match image.pixels {
+        PixelsByColorType::RGBA8(ref pixels) => {
+            let pixel = &pixels[index * 4..index * 4 + 4];
+            [value(pixel[2]), value(pixel[1]), value(pixel[0]), value(pixel[3])]
+        }
+        PixelsByColorType::RGB8(ref pixels) => {
+            let pixel = &pixels[index * 3..index * 3 + 3];
+            [value(pixel[2]), value(pixel[1]), value(pixel[0]), 1.0]
+        }
+        // Like the Azure backend, draw grayscale images as alpha masks.
+        PixelsByColorType::K8(ref pixels) => [0.0, 0.0, 0.0, value(pixels[index])],
+        PixelsByColorType::KA8(ref pixels) => {
+            let alpha = value(pixels[index * 2 + 1]);
+            let gray = value(pixels[index * 2]) * alpha;
+            [gray, gray, gray, alpha]
+        }
+    }
+}
+
+/// Returns the premultiplied color of the gradient at the given position, where 0 is the start
+/// point and 1 the end point.
+pub fn gradient_color(stops: &[GradientStop], position: f32) -> Pixel {
+    let first = &stops[0];
+    if position <= first.offset {
+        return premultiply(&first.color)
+    }
+    for pair in stops.windows(2) {
+        let (before, after) = (&pair[0], &pair[1]);
+        if position <= after.offset {
+            let span = after.offset - before.offset;
+            let fraction = if span > 0.0 { (position - before.offset) / span } else { 1.0 };
+            let (before, after) = (premultiply(&before.color), premultiply(&after.color));
+            let mut result = [0.0; 4];
+            for channel in 0..4 {
+                result[channel] = before[channel] + (after[channel] - before[channel]) * fraction
+            }
+            return result
+        }
+    }
+    premultiply(&stops[stops.len() - 1].color)
+}
+
+/// Returns where in the stops of a repeating gradient a position along it falls.
+pub fn repeat(position: f32) -> f32 {
+    position - position.floor()
+}
+
+pub fn premultiply(color: &Color) -> Pixel {
+    [color.r * color.a, color.g * color.a, color.b * color.a, color.a]
+}
+
+fn unpremultiply(pixel: &Pixel) -> [f32; 3] {
+    if pixel[3] <= 0.0 {
+        return [0.0; 3]
+    }
+    [pixel[0] / pixel[3], pixel[1] / pixel[3], pixel[2] / pixel[3]]
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+}
+
+/// Returns the bits of the half float nearest to the value. Values too large for a half float
+/// become infinities, and ones too small zeroes.
+fn to_half(value: f32) -> u16 {
+    if value.is_nan() {
+        return 0x7e00
+    }
+    let sign = if value.is_sign_negative() { 0x8000 } else { 0 };
+    let magnitude = value.abs();
+    if magnitude >= 65520.0 {
+        return sign | 0x7c00
+    }
+    if magnitude < 6.103515625e-5 {
+        // Subnormal, in units of 2^-24.
+        return sign | (magnitude * 16777216.0 + 0.5) as u16
+    }
+    let exponent = magnitude.log2().floor();
+    let mut mantissa = ((magnitude / exponent.exp2() - 1.0) * 1024.0 + 0.5) as u16;
+    let mut exponent = (exponent + 15.0) as u16;
+    if mantissa == 1024 {
+        mantissa = 0;
+        exponent += 1;
+    }
+    sign | exponent << 10 | mantissa
+}
+
+/// Applies a color matrix, as the Azure color matrix filter does, to unpremultiplied colors.
+fn apply_color_matrix(surface: &mut [Pixel], matrix: &Matrix5x4) {
+    for pixel in surface.iter_mut() {
+        if pixel[3] <= 0.0 {
+            continue
+        }
+        let color = unpremultiply(pixel);
+        let (r, g, b, alpha) = (color[0], color[1], color[2], pixel[3]);
+        let output = [
+            r * matrix.m11 + g * matrix.m21 + b * matrix.m31 + alpha * matrix.m41 + matrix.m51,
+            r * matrix.m12 + g * matrix.m22 + b * matrix.m32 + alpha * matrix.m42 + matrix.m52,
+            r * matrix.m13 + g * matrix.m23 + b * matrix.m33 + alpha * matrix.m43 + matrix.m53,
+        ];
+        let alpha = (r * matrix.m14 + g * matrix.m24 + b * matrix.m34 + alpha * matrix.m44 +
+                     matrix.m54).max(0.0).min(1.0);
+        for channel in 0..3 {
+            pixel[channel] = output[channel].max(0.0).min(1.0) * alpha
+        }
+        pixel[3] = alpha
+    }
+}
+
+/// Blurs the surface with the standard deviation, in pixels.
+fn blur(surface: &mut [Pixel], width: usize, height: usize, std_deviation: f32) {
+    let data: &mut [f32] = unsafe {
+        ::std::slice::from_raw_parts_mut(surface.as_mut_ptr() as *mut f32, surface.len() * 4)
+    };
+    raster::blur(data, width, height, 4, std_deviation)
+}
+
+/// Returns the surface with the primitives of the SVG filter applied to it.
+fn apply_svg_filter(surface: Vec<Pixel>,
+                    width: usize,
+                    height: usize,
+                    svg_filter: &SvgFilter,
+                    scale: f32)
+                    -> Vec<Pixel> {
+    let source_alpha: Vec<Pixel> =
+        surface.iter().map(|pixel| [0.0, 0.0, 0.0, pixel[3]]).collect();
+    let mut results: Vec<Vec<Pixel>> = Vec::new();
+    let mut names = HashMap::new();
+    for primitive in svg_filter.primitives.iter() {
+        let result = {
+            let resolve = |input: &FilterInput| -> Vec<Pixel> {
+                match *input {
+                    FilterInput::SourceGraphic => surface.clone(),
+                    FilterInput::SourceAlpha => source_alpha.clone(),
+                    FilterInput::Named(ref name) if names.contains_key(name) => {
+                        results[*names.get(name).unwrap()].clone()
+                    }
+                    FilterInput::Previous | FilterInput::Named(_) => {
+                        results.last().unwrap_or(&surface).clone()
+                    }
+                }
+            };
+            let mut result = resolve(&primitive.input);
+            match primitive.primitive {
+                Primitive::GaussianBlur(std_deviation) => {
+                    blur(&mut result[..], width, height, std_deviation * scale)
+                }
+                Primitive::ColorMatrix(ref matrix) => apply_color_matrix(&mut result[..], matrix),
+                Primitive::Composite(ref input2, operator) => {
+                    for (source, destination) in result.iter_mut().zip(resolve(input2).iter()) {
+                        *source = porter_duff(source, destination, operator)
+                    }
+                }
+            }
+            result
+        };
+        if let Some(ref name) = primitive.result {
+            names.insert(name.clone(), results.len());
+        }
+        results.push(result)
+    }
+    results.pop().unwrap_or(surface)
+}
+
+/// Composites `source` onto `destination` with a Porter-Duff operator.
+fn porter_duff(source: &Pixel, destination: &Pixel, operator: CompositeOperator) -> Pixel {
+    let (source_alpha, destination_alpha) = (source[3], destination[3]);
+    let (source_factor, destination_factor) = match operator {
+        CompositeOperator::Over => (1.0, 1.0 - source_alpha),
+        CompositeOperator::In => (destination_alpha, 0.0),
+        CompositeOperator::Out => (1.0 - destination_alpha, 0.0),
+        CompositeOperator::Atop => (destination_alpha, 1.0 - source_alpha),
+        CompositeOperator::Xor => (1.0 - destination_alpha, 1.0 - source_alpha),
+    };
+    let mut result = [0.0; 4];
+    for channel in 0..4 {
+        result[channel] =
+            source[channel] * source_factor + destination[channel] * destination_factor
+    }
+    result
+}
+
+/// Returns the surface painted over its drop shadow, per CSS-FILTERS § 5. The shadow is moved by
+/// whole pixels.
+fn drop_shadow(surface: &[Pixel],
+               width: usize,
+               height: usize,
+               shadow: &filter::DropShadow,
+               scale: f32)
+               -> Vec<Pixel> {
+    let mut alpha: Vec<f32> = surface.iter().map(|pixel| pixel[3]).collect();
+    raster::blur(&mut alpha[..], width, height, 1, shadow.blur_radius.to_frac32_px() * scale);
+    let offset_x = (shadow.offset_x.to_frac32_px() * scale).round() as isize;
+    let offset_y = (shadow.offset_y.to_frac32_px() * scale).round() as isize;
+    let color = &shadow.color;
+    let mut result = Vec::with_capacity(surface.len());
+    for (index, source) in surface.iter().enumerate() {
+        let x = (index % width) as isize - offset_x;
+        let y = (index / width) as isize - offset_y;
+        let shadow_alpha = if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            alpha[y as usize * width + x as usize] * color.alpha as f32
+        } else {
+            0.0
+        };
+        let shadow = [color.red as f32 * shadow_alpha,
+                      color.green as f32 * shadow_alpha,
+                      color.blue as f32 * shadow_alpha,
+                      shadow_alpha];
+        result.push(blend(source, &shadow, mix_blend_mode::T::normal))
+    }
+    result
+}
+
+/// Applies the function to each unpremultiplied color channel.
+fn apply_transfer<F>(surface: &mut [Pixel], function: F) where F: Fn(f32) -> f32 {
+    for pixel in surface.iter_mut() {
+        if pixel[3] <= 0.0 {
+            continue
+        }
+        let color = unpremultiply(pixel);
+        for channel in 0..3 {
+            pixel[channel] = function(color[channel]).max(0.0).min(1.0) * pixel[3]
+        }
+    }
+}
+
+/// Composites `source` onto `destination` with the blend mode, following COMPOSITING-1 § 5 and
+/// § 10 for the source-over operator. Other backends use it for the blend modes that they lack.
+pub fn blend(source: &Pixel, destination: &Pixel, blend_mode: mix_blend_mode::T) -> Pixel {
+    let (source_alpha, destination_alpha) = (source[3], destination[3]);
+    let mixed = if blend_mode == mix_blend_mode::T::normal {
+        unpremultiply(source)
+    } else {
+        mix(&unpremultiply(destination), &unpremultiply(source), blend_mode)
+    };
+
+    let mut result = [0.0; 4];
+    for channel in 0..3 {
+        result[channel] = source[channel] * (1.0 - destination_alpha) +
+            destination[channel] * (1.0 - source_alpha) +
+            source_alpha * destination_alpha * mixed[channel]
+    }
+    result[3] = source_alpha + destination_alpha * (1.0 - source_alpha);
+    result
+}
+
+/// Mixes an unpremultiplied backdrop color and source color per COMPOSITING-1 § 10.
+fn mix(backdrop: &[f32; 3], source: &[f32; 3], blend_mode: mix_blend_mode::T)
+       -> [f32; 3] {
+    let separable = |function: &Fn(f32, f32) -> f32| {
+        [function(backdrop[0], source[0]),
+         function(backdrop[1], source[1]),
+         function(backdrop[2], source[2])]
+    };
+    let hard_light = |backdrop: f32, source: f32| {
+        if source <= 0.5 {
+            backdrop * 2.0 * source
+        } else {
+            let source = 2.0 * source - 1.0;
+            backdrop + source - backdrop * source
+        }
+    };
+
+    match blend_mode {
+        mix_blend_mode::T::normal => *source,
+        mix_blend_mode::T::multiply => separable(&|backdrop, source| backdrop * source),
+        mix_blend_mode::T::screen => {
+            separable(&|backdrop, source| backdrop + source - backdrop * source)
+        }
+        mix_blend_mode::T::overlay => {
+            separable(&|backdrop, source| hard_light(source, backdrop))
+        }
+        mix_blend_mode::T::darken => separable(&|backdrop, source| backdrop.min(source)),
+        mix_blend_mode::T::lighten => separable(&|backdrop, source| backdrop.max(source)),
+        mix_blend_mode::T::color_dodge => {
+            separable(&|backdrop, source| {
+                if backdrop == 0.0 {
+                    0.0
+                } else if source >= 1.0 {
+                    1.0
+                } else {
+                    (backdrop / (1.0 - source)).min(1.0)
+                }
+            })
+        }
+        mix_blend_mode::T::color_burn => {
+            separable(&|backdrop, source| {
+                if backdrop >= 1.0 {
+                    1.0
+                } else if source <= 0.0 {
+                    0.0
+                } else {
+                    1.0 - ((1.0 - backdrop) / source).min(1.0)
+                }
+            })
+        }
+        mix_blend_mode::T::hard_light => separable(&hard_light),
+        mix_blend_mode::T::soft_light => {
+            separable(&|backdrop, source| {
+                if source <= 0.5 {
+                    backdrop - (1.0 - 2.0 * source) * backdrop * (1.0 - backdrop)
+                } else {
+                    let d = if backdrop <= 0.25 {
+                        ((16.0 * backdrop - 12.0) * backdrop + 4.0) * backdrop
+                    } else {
+                        backdrop.sqrt()
+                    };
+                    backdrop + (2.0 * source - 1.0) * (d - backdrop)
+                }
+            })
+        }
+        mix_blend_mode::T::difference => {
+            separable(&|backdrop, source| (backdrop - source).abs())
+        }
+        mix_blend_mode::T::exclusion => {
+            separable(&|backdrop, source| backdrop + source - 2.0 * backdrop * source)
+        }
+        mix_blend_mode::T::hue => {
+            set_luminosity(&set_saturation(source, saturation(backdrop)), luminosity(backdrop))
+        }
+        mix_blend_mode::T::saturation => {
+            set_luminosity(&set_saturation(backdrop, saturation(source)), luminosity(backdrop))
+        }
+        mix_blend_mode::T::color => set_luminosity(source, luminosity(backdrop)),
+        mix_blend_mode::T::luminosity => set_luminosity(backdrop, luminosity(source)),
+    }
+}
+
+fn luminosity(color: &[f32; 3]) -> f32 {
+    0.3 * color[0] + 0.59 * color[1] + 0.11 * color[2]
+}
+
+fn set_luminosity(color: &[f32; 3], luminosity: f32) -> [f32; 3] {
+    let delta = luminosity - self::luminosity(color);
+    let color = [color[0] + delta, color[1] + delta, color[2] + delta];
+
+    // Clip the color back into range while keeping its luminosity.
+    let luminosity = self::luminosity(&color);
+    let lowest = color[0].min(color[1]).min(color[2]);
+    let highest = color[0].max(color[1]).max(color[2]);
+    let mut result = color;
+    for channel in 0..3 {
+        if lowest < 0.0 {
+            result[channel] = luminosity +
+                (result[channel] - luminosity) * luminosity / (luminosity - lowest)
+        }
+        if highest > 1.0 {
+            result[channel] = luminosity +
+                (result[channel] - luminosity) * (1.0 - luminosity) / (highest - luminosity)
+        }
+    }
+    result
+}
+
+fn saturation(color: &[f32; 3]) -> f32 {
+    color[0].max(color[1]).max(color[2]) - color[0].min(color[1]).min(color[2])
+}
+
+fn set_saturation(color: &[f32; 3], saturation: f32) -> [f32; 3] {
+    let lowest = color[0].min(color[1]).min(color[2]);
+    let highest = color[0].max(color[1]).max(color[2]);
+    if highest <= lowest {
+        return [0.0; 3]
+    }
+    [(color[0] - lowest) * saturation / (highest - lowest),
+     (color[1] - lowest) * saturation / (highest - lowest),
+     (color[2] - lowest) * saturation / (highest - lowest)]
+}
@@ -0,0 +1,901 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A painting backend that writes display lists out as SVG documents, for exporting pages to
+//! design tools and for comparing display lists outside the browser.
+//!
+//! Every element is written in the coordinates of the document with its own transform, so that
+//! clips, which are in document coordinates too, can be shared between elements. Stacking
+//! contexts become groups, with their filters written as SVG filter effects. Fonts and images are
+//! embedded as data URLs.
+//!
+//! TODO: Upright glyphs in sideways text are written sideways, and dotted, dashed and 3D border
+//! styles are written solid.
+
+use backends::pdf;
+use backends::raster::{self, Polygon};
+use color::to_paint_color;
+use display_list::TextOrientation::{SidewaysLeft, SidewaysRight, Upright};
+use display_list::{BorderRadii, BoxShadowClipMode, ClippingRegion, ImageOrientation};
+use display_list::{StackingContext, TextDisplayItem};
+use display_list::transform3d;
+use color_matrix;
+use font::SYNTHETIC_OBLIQUE_FONT_FLAG;
+use paint_backend::{Color, GradientStop, PaintBackend, ToRadiiPx, ToSideOffsetsPx};
+use paint_context::ToAzureRect;
+use platform::font_template::FontTemplateData;
+use svg_filter::{self, CompositeOperator, FilterInput, Primitive, SvgFilter};
+
+use azure::azure_hl::Matrix5x4;
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use libc::funcs::posix88::unistd::getpid;
+use net_traits::image::base::Image;
+use png::{self, PixelsByColorType};
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use std::borrow::ToOwned;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::num::Float;
+use std::sync::Arc;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+use util::opts::ColorSpace;
+
+/// How many images have been encoded, which numbers the temporary files they are encoded in.
+static ENCODED_IMAGE_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub struct SvgBackend {
+    /// The size of the document in px.
+    size: Size2D<f32>,
+    /// The clip paths, gradients, filters and images that the body refers to.
+    definitions: String,
+    body: String,
+    /// The number to give the next definition's id.
+    next_id: usize,
+    transform: Matrix2D<f32>,
+    /// The clip that `set_clip()` set last, which the group that is open in the body clips to.
+    transient_clip: Option<ClippingRegion>,
+    /// The transforms from before each of the stacking contexts being painted.
+    saved_transforms: Vec<Matrix2D<f32>>,
+    /// The fonts declared so far, with the names of their families.
+    fonts: Vec<(Arc<FontTemplateData>, String)>,
+    /// The images defined so far, with their ids.
+    images: Vec<(Arc<Image>, String)>,
+}
+
+impl SvgBackend {
+    /// Creates a backend that writes a document of the given size. Untransformed coordinates are
+    /// px from its top left.
+    pub fn new(size: &Size2D<Au>) -> SvgBackend {
+        SvgBackend {
+            size: Size2D(size.width.to_frac32_px(), size.height.to_frac32_px()),
+            definitions: String::new(),
+            body: String::new(),
+            next_id: 0,
+            transform: Matrix2D::identity(),
+            transient_clip: None,
+            saved_transforms: Vec::new(),
+            fonts: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    /// Returns the finished document.
+    pub fn finish(mut self) -> String {
+        assert!(self.saved_transforms.is_empty(), "a stacking context wasn't popped");
+        self.close_clip_group();
+        format!("<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                 xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{}\" height=\"{}\" \
+                 viewBox=\"0 0 {} {}\">\n<defs>\n{}</defs>\n{}</svg>\n",
+                number(self.size.width),
+                number(self.size.height),
+                number(self.size.width),
+                number(self.size.height),
+                self.definitions,
+                self.body)
+    }
+
+    fn new_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{}", prefix, self.next_id)
+    }
+
+    /// Fills the rect with the paint server of the given id.
+    fn fill_rect_with(&mut self, bounds: &Rect<Au>, id: &str) {
+        let rect = bounds.to_azure_rect();
+        self.body.push_str(&format!("<rect transform=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" \
+                                     height=\"{}\" fill=\"url(#{})\"/>\n",
+                                    matrix(&self.transform),
+                                    number(rect.origin.x),
+                                    number(rect.origin.y),
+                                    number(rect.size.width),
+                                    number(rect.size.height),
+                                    id));
+    }
+
+    /// Defines a clip path of the polygons, whose union it covers, and returns its id. If
+    /// `clip` is given, the clip path is also clipped to that one.
+    fn define_clip_path(&mut self, polygons: &[Polygon], even_odd: bool, clip: Option<&str>)
+                        -> String {
+        let id = self.new_id("clip");
+        let clip = match clip {
+            Some(clip) => format!(" clip-path=\"url(#{})\"", clip),
+            None => String::new(),
+        };
+        let clip_rule = if even_odd { " clip-rule=\"evenodd\"" } else { "" };
+        self.definitions.push_str(&format!("<clipPath id=\"{}\"{}><path d=\"{}\"{}/></clipPath>\n",
+                                           id,
+                                           clip,
+                                           path_data(polygons),
+                                           clip_rule));
+        id
+    }
+
+    /// Ends the group that clips drawing to the transient clip, if there is one.
+    fn close_clip_group(&mut self) {
+        if self.transient_clip.take().is_some() {
+            self.body.push_str("</g>\n")
+        }
+    }
+
+    /// Returns the family name to write the text run's font in, declaring the font if it is new.
+    fn font_family(&mut self, template: &Arc<FontTemplateData>) -> String {
+        for &(ref font, ref family) in self.fonts.iter() {
+            if font.identifier == template.identifier {
+                return family.clone()
+            }
+        }
+
+        let family = match template.bytes() {
+            Some(bytes) => {
+                let family = format!("font{}", self.fonts.len());
+                self.definitions.push_str(&format!("<style>@font-face {{ font-family: \"{}\"; \
+                                                    src: url(data:application/font-sfnt;base64,\
+                                                    {}); }}</style>\n",
+                                                   family,
+                                                   bytes.to_base64(STANDARD)));
+                family
+            }
+            // Without the font's data, the best that can be done is to name the font.
+            None => {
+                let name = template.identifier.split('/').last().unwrap_or("");
+                escape(name.split('.').next().unwrap_or(""))
+            }
+        };
+        self.fonts.push((template.clone(), family.clone()));
+        family
+    }
+
+    /// Returns the id of the definition of the image, adding it if it is new, or `None` if it
+    /// couldn't be encoded.
+    fn image_id(&mut self, image: &Arc<Image>) -> Option<String> {
+        for &(ref defined_image, ref id) in self.images.iter() {
+            if &**defined_image as *const Image == &**image as *const Image {
+                return Some(id.clone())
+            }
+        }
+
+        let (colors, alphas) = pdf::image_data(&**image);
+        let mut pixels = Vec::with_capacity(alphas.len() * 4);
+        for (color, &alpha) in colors.chunks(3).zip(alphas.iter()) {
+            pixels.push_all(color);
+            pixels.push(alpha)
+        }
+        let png = match encode_png(image.width, image.height, pixels) {
+            Some(png) => png,
+            None => return None,
+        };
+        let id = self.new_id("image");
+        self.definitions.push_str(&format!("<image id=\"{}\" width=\"{}\" height=\"{}\" \
+                                            xlink:href=\"data:image/png;base64,{}\"/>\n",
+                                           id,
+                                           image.width,
+                                           image.height,
+                                           png.to_base64(STANDARD)));
+        self.images.push((image.clone(), id.clone()));
+        Some(id)
+    }
+
+    /// Defines a filter effect that applies the filters and returns its id, or returns `None` if
+    /// there are none.
+    fn define_filter(&mut self, style_filters: &filter::T) -> Option<String> {
+        if style_filters.filters.is_empty() {
+            return None
+        }
+
+        let scale = self.scale();
+        let mut primitives = String::new();
+        for style_filter in style_filters.filters.iter() {
+            let primitive = match *style_filter {
+                filter::Filter::Blur(amount) => {
+                    format!("<feGaussianBlur stdDeviation=\"{}\"/>",
+                            number(amount.to_frac32_px() * scale))
+                }
+                filter::Filter::Brightness(amount) => {
+                    color_transfer(&format!("type=\"linear\" slope=\"{}\"",
+                                            number(amount as f32)))
+                }
+                filter::Filter::Contrast(amount) => {
+                    let amount = amount as f32;
+                    color_transfer(&format!("type=\"linear\" slope=\"{}\" intercept=\"{}\"",
+                                            number(amount),
+                                            number(0.5 - 0.5 * amount)))
+                }
+                filter::Filter::DropShadow(ref shadow) => {
+                    // Name what the filters before made, to paint it over its shadow.
+                    let source = self.new_id("source");
+                    let shadow_result = self.new_id("shadow");
+                    format!("<feOffset result=\"{0}\"/>\
+                             <feGaussianBlur in=\"{0}\" stdDeviation=\"{1}\"/>\
+                             <feOffset dx=\"{2}\" dy=\"{3}\"/>{4}\
+                             <feOffset result=\"{5}\"/>\
+                             <feMerge><feMergeNode in=\"{5}\"/><feMergeNode in=\"{0}\"/></feMerge>",
+                            source,
+                            number(shadow.blur_radius.to_frac32_px() * scale),
+                            number(shadow.offset_x.to_frac32_px() * scale),
+                            number(shadow.offset_y.to_frac32_px() * scale),
+                            fe_color_matrix(&color_matrix::drop_shadow_color(shadow)),
+                            shadow_result)
+                }
+                filter::Filter::Invert(amount) => {
+                    let amount = amount as f32;
+                    color_transfer(&format!("type=\"table\" tableValues=\"{} {}\"",
+                                            number(amount),
+                                            number(1.0 - amount)))
+                }
+                filter::Filter::Opacity(amount) => {
+                    format!("<feComponentTransfer><feFuncA type=\"linear\" slope=\"{}\"/>\
+                             </feComponentTransfer>",
+                            number(amount as f32))
+                }
+                filter::Filter::Grayscale(amount) => {
+                    fe_color_matrix(&color_matrix::grayscale(amount as f32))
+                }
+                filter::Filter::HueRotate(angle) => {
+                    fe_color_matrix(&color_matrix::hue_rotate(angle.radians() as f32))
+                }
+                filter::Filter::Saturate(amount) => {
+                    fe_color_matrix(&color_matrix::saturate(amount as f32))
+                }
+                filter::Filter::Sepia(amount) => {
+                    fe_color_matrix(&color_matrix::sepia(amount as f32))
+                }
+                filter::Filter::Url(ref url) => {
+                    match svg_filter::get(url) {
+                        Some(svg_filter) => {
+                            let prefix = self.new_id("svgfilter");
+                            svg_filter_primitives(&*svg_filter, &prefix, scale)
+                        }
+                        None => String::new(),
+                    }
+                }
+            };
+            primitives.push_str(&primitive);
+        }
+
+        // Like Azure, filter in sRGB, and let blurs reach anywhere in the document.
+        let id = self.new_id("filter");
+        self.definitions.push_str(&format!("<filter id=\"{}\" filterUnits=\"userSpaceOnUse\" \
+                                            x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" \
+                                            color-interpolation-filters=\"sRGB\">{}</filter>\n",
+                                           id,
+                                           number(self.size.width),
+                                           number(self.size.height),
+                                           primitives));
+        Some(id)
+    }
+
+    /// Returns how much the current transform scales lengths, on average.
+    fn scale(&self) -> f32 {
+        let size = self.device_size(&Rect(Point2D(0.0, 0.0), Size2D(1.0, 1.0)));
+        (size.width * size.height).sqrt()
+    }
+}
+
+impl PaintBackend for SvgBackend {
+    fn transform(&self) -> Matrix2D<f32> {
+        self.transform
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        // The colors that SVG documents are written with are sRGB.
+        ColorSpace::Srgb
+    }
+
+    fn push_stacking_context(&mut self,
+                             _: &Rect<f32>,
+                             transform: &Matrix2D<f32>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T) {
+        // Groups have to nest, so the stacking context's group can't be inside the clip group.
+        self.close_clip_group();
+        self.saved_transforms.push(self.transform);
+        self.transform = *transform;
+
+        let mut attributes = String::new();
+        if let Some(clip_rect) = clip_rect {
+            let polygon = raster::rect_polygon(&clip_rect.to_azure_rect(), &self.transform);
+            let clip = self.define_clip_path(&[polygon], false, None);
+            attributes.push_str(&format!(" clip-path=\"url(#{})\"", clip));
+        }
+        if let Some(filter) = self.define_filter(filters) {
+            attributes.push_str(&format!(" filter=\"url(#{})\"", filter));
+        }
+        if blend_mode != mix_blend_mode::T::normal {
+            attributes.push_str(&format!(" style=\"mix-blend-mode: {}\"",
+                                         blend_mode_name(blend_mode)));
+        }
+        self.body.push_str(&format!("<g{}>\n", attributes));
+    }
+
+    fn pop_stacking_context(&mut self) {
+        self.close_clip_group();
+        self.transform = self.saved_transforms.pop().expect("no stacking context to pop");
+        self.body.push_str("</g>\n");
+    }
+
+    fn set_clip(&mut self, clip: &ClippingRegion) {
+        if self.transient_clip.as_ref() == Some(clip) {
+            return
+        }
+        self.close_clip_group();
+
+        let main = raster::rect_polygon(&clip.main.to_azure_rect(), &self.transform);
+        let mut id = self.define_clip_path(&[main], false, None);
+        for complex_region in clip.complex.iter() {
+            let polygon = raster::rounded_rect_polygon(&complex_region.rect.to_azure_rect(),
+                                                       &complex_region.radii.to_radii_px(),
+                                                       &self.transform);
+            id = self.define_clip_path(&[polygon], false, Some(&id));
+        }
+        self.body.push_str(&format!("<g clip-path=\"url(#{})\">\n", id));
+        self.transient_clip = Some(clip.clone())
+    }
+
+    fn clear(&mut self) {
+        // Documents start out transparent, and there is nothing underneath them.
+    }
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: Color) {
+        let rect = bounds.to_azure_rect();
+        self.body.push_str(&format!("<rect transform=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" \
+                                     height=\"{}\"{}/>\n",
+                                    matrix(&self.transform),
+                                    number(rect.origin.x),
+                                    number(rect.origin.y),
+                                    number(rect.size.width),
+                                    number(rect.size.height),
+                                    fill(&color)));
+    }
+
+    fn draw_text(&mut self, text: &TextDisplayItem) {
+        // Rotate sideways text about the start of its baseline, as the Azure backend does.
+        let run = &text.text_run;
+        let baseline_x = text.baseline_origin.x.to_subpx() as f32;
+        let baseline_y = text.baseline_origin.y.to_subpx() as f32;
+        let (transform, mut origin) = match text.orientation {
+            Upright => (self.transform, text.baseline_origin),
+            SidewaysLeft => {
+                (self.transform.mul(&Matrix2D::new(0., -1., 1., 0., baseline_x, baseline_y)),
+                 Point2D::zero())
+            }
+            SidewaysRight => {
+                (self.transform.mul(&Matrix2D::new(0., 1., -1., 0., baseline_x, baseline_y)),
+                 Point2D::zero())
+            }
+        };
+
+        let mut glyphs = vec!();
+        for slice in run.natural_word_slices_in_range(&text.range) {
+            for (index, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+                glyphs.push((slice.offset + index, glyph.advance(), glyph.offset()))
+            }
+        }
+
+        // Each glyph stands for the characters up to the next glyph of a later character, or
+        // none if an earlier glyph stands for its character already.
+        let characters: Vec<char> = run.text.chars().collect();
+        let mut clusters = Vec::with_capacity(glyphs.len());
+        for (index, &(char_index, advance, offset)) in glyphs.iter().enumerate() {
+            let is_first = index == 0 || glyphs[index - 1].0 != char_index;
+            let end = glyphs[index + 1..].iter()
+                                        .map(|&(later_index, _, _)| later_index)
+                                        .find(|&later_index| later_index > char_index)
+                                        .unwrap_or(text.range.end());
+            let cluster: String = if is_first {
+                characters[char_index.to_usize()..end.to_usize()].iter().cloned().collect()
+            } else {
+                String::new()
+            };
+            clusters.push((cluster, advance, offset))
+        }
+
+        // Glyphs are stored in logical order. Right-to-left runs are written starting from their
+        // last glyph, so that the origin always moves from left to right.
+        if run.is_rtl() {
+            clusters.reverse()
+        }
+        let mut spans = String::new();
+        for &(ref cluster, advance, offset) in clusters.iter() {
+            let offset = offset.unwrap_or(Point2D::zero());
+            if !cluster.is_empty() {
+                spans.push_str(&format!("<tspan x=\"{}\" y=\"{}\">{}</tspan>",
+                                        number((origin.x + offset.x).to_subpx() as f32),
+                                        number((origin.y + offset.y).to_subpx() as f32),
+                                        escape(cluster)));
+            }
+            origin = Point2D(origin.x + advance, origin.y);
+        }
+        if spans.is_empty() {
+            return
+        }
+
+        let family = self.font_family(&run.font_template);
+        let style = if run.synthesis.contains(SYNTHETIC_OBLIQUE_FONT_FLAG) {
+            " font-style=\"oblique\""
+        } else {
+            ""
+        };
+        let filter = if text.blur_radius > Au(0) {
+            let id = self.new_id("filter");
+            self.definitions.push_str(&format!("<filter id=\"{}\"><feGaussianBlur \
+                                                stdDeviation=\"{}\"/></filter>\n",
+                                               id,
+                                               number(text.blur_radius.to_frac32_px())));
+            format!(" filter=\"url(#{})\"", id)
+        } else {
+            String::new()
+        };
+        self.body.push_str(&format!("<text transform=\"{}\" font-family=\"{}\" \
+                                     font-size=\"{}\"{}{}{} xml:space=\"preserve\">{}</text>\n",
+                                    matrix(&transform),
+                                    family,
+                                    number(run.actual_pt_size.to_subpx() as f32),
+                                    style,
+                                    fill(&to_paint_color(text.text_color)),
+                                    filter,
+                                    spans));
+    }
+
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<f32>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation) {
+        if image.width == 0 || image.height == 0 {
+            return
+        }
+        let source_rect = source_rect.unwrap_or(
+            Rect(Point2D(0.0, 0.0), Size2D(image.width as f32, image.height as f32)));
+        let mut dest_rect = bounds.to_azure_rect();
+        let mut transform = self.transform;
+        if orientation != ImageOrientation::identity() {
+            transform = transform.mul(&orientation.transform(&dest_rect));
+            dest_rect.origin = Point2D(0.0, 0.0);
+            if orientation.swaps_dimensions() {
+                dest_rect.size = Size2D(dest_rect.size.height, dest_rect.size.width);
+            }
+        }
+
+        // A nested viewport shows the source rect of the image, stretched over the destination.
+        let rendering = match image_rendering {
+            image_rendering::T::Auto => "",
+            image_rendering::T::CrispEdges => " image-rendering=\"optimizeSpeed\"",
+            image_rendering::T::Pixelated => " style=\"image-rendering: pixelated\"",
+        };
+        let id = match self.image_id(&image) {
+            Some(id) => id,
+            None => return,
+        };
+        self.body.push_str(&format!("<g transform=\"{}\"><svg x=\"{}\" y=\"{}\" width=\"{}\" \
+                                     height=\"{}\" viewBox=\"{} {} {} {}\" \
+                                     preserveAspectRatio=\"none\"><use xlink:href=\"#{}\"{}/>\
+                                     </svg></g>\n",
+                                    matrix(&transform),
+                                    number(dest_rect.origin.x),
+                                    number(dest_rect.origin.y),
+                                    number(dest_rect.size.width),
+                                    number(dest_rect.size.height),
+                                    number(source_rect.origin.x),
+                                    number(source_rect.origin.y),
+                                    number(source_rect.size.width),
+                                    number(source_rect.size.height),
+                                    id,
+                                    rendering));
+    }
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<Color>,
+                   style: &SideOffsets2D<border_style::T>) {
+        let widths = border.to_float_px();
+        let (ring, wedges) = raster::border_polygons(&bounds.to_azure_rect(),
+                                                     &widths,
+                                                     &radius.to_radii_px(),
+                                                     &self.transform);
+        let ring = path_data(&ring);
+
+        // Draw sides of the same color in one go.
+        let sides = [(widths.top, color.top, style.top),
+                     (widths.right, color.right, style.right),
+                     (widths.bottom, color.bottom, style.bottom),
+                     (widths.left, color.left, style.left)];
+        let mut done = [false; 4];
+        for index in 0..4 {
+            let (width, side_color, side_style) = sides[index];
+            if done[index] || width <= 0.0 || side_style == border_style::T::none ||
+                    side_style == border_style::T::hidden {
+                continue
+            }
+            let mut side_wedges = Vec::new();
+            for other_index in index..4 {
+                let (other_width, other_color, other_style) = sides[other_index];
+                if other_width > 0.0 && other_color == side_color &&
+                        other_style != border_style::T::none &&
+                        other_style != border_style::T::hidden {
+                    side_wedges.push(wedges[other_index].clone());
+                    done[other_index] = true
+                }
+            }
+            let clip = self.define_clip_path(&side_wedges[..], false, None);
+            self.body.push_str(&format!("<path d=\"{}\" clip-path=\"url(#{})\"{}/>\n",
+                                        ring,
+                                        clip,
+                                        fill(&side_color)));
+        }
+    }
+
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool) {
+        let id = self.new_id("gradient");
+        self.definitions.push_str(&format!("<linearGradient id=\"{}\" \
+                                            gradientUnits=\"userSpaceOnUse\" spreadMethod=\"{}\" \
+                                            x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\">{}\
+                                            </linearGradient>\n",
+                                           id,
+                                           if repeating { "repeat" } else { "pad" },
+                                           number(start_point.x.to_subpx() as f32),
+                                           number(start_point.y.to_subpx() as f32),
+                                           number(end_point.x.to_subpx() as f32),
+                                           number(end_point.y.to_subpx() as f32),
+                                           stop_elements(stops)));
+        self.fill_rect_with(bounds, &id)
+    }
+
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]) {
+        // The gradient is a unit circle that its transform stretches into the ellipse.
+        let id = self.new_id("gradient");
+        self.definitions.push_str(&format!("<radialGradient id=\"{}\" \
+                                            gradientUnits=\"userSpaceOnUse\" cx=\"0\" cy=\"0\" \
+                                            r=\"1\" gradientTransform=\"{}\">{}</radialGradient>\n",
+                                           id,
+                                           matrix(&raster::radial_gradient_transform(center,
+                                                                                     radius)),
+                                           stop_elements(stops)));
+        self.fill_rect_with(bounds, &id)
+    }
+
+    fn draw_line(&mut self, bounds: &Rect<Au>, color: Color, style: border_style::T) {
+        let dash_length = match style {
+            border_style::T::dotted => 1.0,
+            border_style::T::dashed => 3.0,
+            _ => return self.draw_solid_color(bounds, color),
+        };
+
+        // Stroke the middle of the line, with dashes as long as it is thick.
+        let rect = bounds.to_azure_rect();
+        let (start, end, thickness) = if rect.size.width >= rect.size.height {
+            let y = rect.origin.y + rect.size.height / 2.0;
+            (Point2D(rect.origin.x, y), Point2D(rect.max_x(), y), rect.size.height)
+        } else {
+            let x = rect.origin.x + rect.size.width / 2.0;
+            (Point2D(x, rect.origin.y), Point2D(x, rect.max_y()), rect.size.width)
+        };
+        self.body.push_str(&format!("<line transform=\"{}\" x1=\"{}\" y1=\"{}\" x2=\"{}\" \
+                                     y2=\"{}\" stroke=\"{}\" stroke-opacity=\"{}\" \
+                                     stroke-width=\"{}\" stroke-dasharray=\"{}\"/>\n",
+                                    matrix(&self.transform),
+                                    number(start.x),
+                                    number(start.y),
+                                    number(end.x),
+                                    number(end.y),
+                                    rgb(&color),
+                                    number(color.a),
+                                    number(thickness),
+                                    number(thickness * dash_length)));
+    }
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+        // Like the Azure backend, draw outside the clips, since shadows may reach beyond them.
+        let shadow_bounds = box_bounds.translate(offset).inflate(spread_radius, spread_radius);
+        let shadow = raster::rect_polygon(&shadow_bounds.to_azure_rect(), &self.transform);
+        let box_polygon = raster::rect_polygon(&box_bounds.to_azure_rect(), &self.transform);
+        let document = raster::rect_polygon(&Rect(Point2D(0.0, 0.0), self.size),
+                                            &Matrix2D::identity());
+
+        let (shape, clip) = match clip_mode {
+            BoxShadowClipMode::None => (format!("d=\"{}\"", path_data(&[shadow])), None),
+            BoxShadowClipMode::Outset => {
+                let clip = self.define_clip_path(&[document, box_polygon], true, None);
+                (format!("d=\"{}\"", path_data(&[shadow])), Some(clip))
+            }
+            BoxShadowClipMode::Inset => {
+                let clip = self.define_clip_path(&[box_polygon], false, None);
+                (format!("d=\"{}\" fill-rule=\"evenodd\"", path_data(&[document, shadow])),
+                 Some(clip))
+            }
+        };
+        let std_deviation = blur_radius.to_frac32_px() * self.scale();
+        let filter = if std_deviation > 0.0 {
+            let id = self.new_id("filter");
+            self.definitions.push_str(&format!("<filter id=\"{}\" filterUnits=\"userSpaceOnUse\" \
+                                                x=\"0\" y=\"0\" width=\"{}\" height=\"{}\">\
+                                                <feGaussianBlur stdDeviation=\"{}\"/></filter>\n",
+                                               id,
+                                               number(self.size.width),
+                                               number(self.size.height),
+                                               number(std_deviation)));
+            format!(" filter=\"url(#{})\"", id)
+        } else {
+            String::new()
+        };
+
+        // The clip has to apply after the blur, so it goes on a group around the shape.
+        let (open, close) = match clip {
+            Some(clip) => (format!("<g clip-path=\"url(#{})\">", clip), "</g>"),
+            None => (String::new(), ""),
+        };
+        self.body.push_str(&format!("{}<path {}{}{}/>{}\n",
+                                    open,
+                                    shape,
+                                    fill(&color),
+                                    filter,
+                                    close));
+    }
+}
+
+/// Writes the stacking context and its descendants out as an SVG document the size of its
+/// overflow area.
+pub fn stacking_context_to_svg(stacking_context: &StackingContext) -> String {
+    let overflow = stacking_context.overflow;
+    let mut svg_backend = SvgBackend::new(&overflow.size);
+    let tile_bounds = overflow.to_azure_rect();
+    let transform = Matrix2D::identity().translate(-tile_bounds.origin.x, -tile_bounds.origin.y);
+    stacking_context.optimize_and_draw_into_context(&mut svg_backend,
+                                                    &tile_bounds,
+                                                    &transform3d::from_2d(&transform),
+                                                    None);
+    svg_backend.finish()
+}
+
+/// Formats a number for an attribute, without needless digits.
+fn number(value: f32) -> String {
+    if !value.is_finite() {
+        return "0".to_owned()
+    }
+    let mut string = format!("{:.3}", value);
+    while string.ends_with("0") {
+        string.pop();
+    }
+    if string.ends_with(".") {
+        string.pop();
+    }
+    if string == "-0" {
+        string = "0".to_owned()
+    }
+    string
+}
+
+/// Returns the `<stop>` elements of a gradient.
+fn stop_elements(stops: &[GradientStop]) -> String {
+    let mut elements = String::new();
+    for stop in stops.iter() {
+        elements.push_str(&format!("<stop offset=\"{}\" stop-color=\"{}\" stop-opacity=\"{}\"/>",
+                                   number(stop.offset),
+                                   rgb(&stop.color),
+                                   number(stop.color.a)));
+    }
+    elements
+}
+
+fn matrix(transform: &Matrix2D<f32>) -> String {
+    format!("matrix({} {} {} {} {} {})",
+            number(transform.m11),
+            number(transform.m12),
+            number(transform.m21),
+            number(transform.m22),
+            number(transform.m31),
+            number(transform.m32))
+}
+
+fn rgb(color: &Color) -> String {
+    let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+    format!("rgb({},{},{})", channel(color.r), channel(color.g), channel(color.b))
+}
+
+/// Returns the attributes that fill a shape with the color.
+fn fill(color: &Color) -> String {
+    if color.a < 1.0 {
+        format!(" fill=\"{}\" fill-opacity=\"{}\"", rgb(color), number(color.a))
+    } else {
+        format!(" fill=\"{}\"", rgb(color))
+    }
+}
+
+fn path_data(polygons: &[Polygon]) -> String {
+    let mut data = String::new();
+    for polygon in polygons.iter() {
+        for (index, point) in polygon.iter().enumerate() {
+            let command = if index == 0 { "M" } else { "L" };
+            data.push_str(&format!("{}{} {}", command, number(point.x), number(point.y)));
+        }
+        if !polygon.is_empty() {
+            data.push_str("Z")
+        }
+    }
+    data
+}
+
+fn escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+/// Returns a filter primitive that applies the transfer function to the color channels.
+fn color_transfer(function: &str) -> String {
+    format!("<feComponentTransfer><feFuncR {}/><feFuncG {}/><feFuncB {}/></feComponentTransfer>",
+            function,
+            function,
+            function)
+}
+
+/// Returns a filter primitive that applies the color matrix. Azure matrices have a row for each
+/// input channel, and SVG ones a row for each output channel.
+fn fe_color_matrix(color_matrix: &Matrix5x4) -> String {
+    format!("<feColorMatrix type=\"matrix\" values=\"{}\"/>", color_matrix_values(color_matrix))
+}
+
+fn color_matrix_values(color_matrix: &Matrix5x4) -> String {
+    let m = color_matrix;
+    let values = [m.m11, m.m21, m.m31, m.m41, m.m51,
+                  m.m12, m.m22, m.m32, m.m42, m.m52,
+                  m.m13, m.m23, m.m33, m.m43, m.m53,
+                  m.m14, m.m24, m.m34, m.m44, m.m54];
+    let values: Vec<String> = values.iter().map(|&value| number(value)).collect();
+    values.connect(" ")
+}
+
+/// Returns the primitives of an SVG filter, to follow those of the filters before it. Every
+/// result is named, starting with `prefix`, so that none of them are mixed up with others.
+fn svg_filter_primitives(svg_filter: &SvgFilter, prefix: &str, scale: f32) -> String {
+    let source = format!("{}-source", prefix);
+    let source_alpha = format!("{}-alpha", prefix);
+    let mut primitives = format!("<feOffset result=\"{0}\"/>\
+                                  <feColorMatrix in=\"{0}\" type=\"matrix\" \
+                                  values=\"0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 1 0\" \
+                                  result=\"{1}\"/>",
+                                 source,
+                                 source_alpha);
+    let mut previous = source.clone();
+    for (index, primitive) in svg_filter.primitives.iter().enumerate() {
+        let result = match primitive.result {
+            Some(ref result) => format!("{}-{}", prefix, result),
+            None => format!("{}-{}", prefix, index),
+        };
+        let attributes = format!(" in=\"{}\" result=\"{}\"",
+                                 input_name(&primitive.input, prefix, &previous),
+                                 result);
+        let primitive = match primitive.primitive {
+            Primitive::GaussianBlur(std_deviation) => {
+                format!("<feGaussianBlur{} stdDeviation=\"{}\"/>",
+                        attributes,
+                        number(std_deviation * scale))
+            }
+            Primitive::ColorMatrix(ref matrix) => {
+                format!("<feColorMatrix{} type=\"matrix\" values=\"{}\"/>",
+                        attributes,
+                        color_matrix_values(matrix))
+            }
+            Primitive::Composite(ref input2, operator) => {
+                let operator = match operator {
+                    CompositeOperator::Over => "over",
+                    CompositeOperator::In => "in",
+                    CompositeOperator::Out => "out",
+                    CompositeOperator::Atop => "atop",
+                    CompositeOperator::Xor => "xor",
+                };
+                format!("<feComposite{} in2=\"{}\" operator=\"{}\"/>",
+                        attributes,
+                        input_name(input2, prefix, &previous),
+                        operator)
+            }
+        };
+        primitives.push_str(&primitive);
+        previous = result
+    }
+    primitives
+}
+
+/// Returns the name of the result that a primitive of an SVG filter uses, as
+/// `svg_filter_primitives()` names them.
+fn input_name(input: &FilterInput, prefix: &str, previous: &str) -> String {
+    match *input {
+        FilterInput::SourceGraphic => format!("{}-source", prefix),
+        FilterInput::SourceAlpha => format!("{}-alpha", prefix),
+        FilterInput::Previous => previous.to_owned(),
+        FilterInput::Named(ref name) => format!("{}-{}", prefix, name),
+    }
+}
+
+/// Encodes RGBA8 pixels as a PNG image. The png crate only writes images to files, so the image
+/// goes through a temporary one. Returns `None` if that fails.
+fn encode_png(width: u32, height: u32, pixels: Vec<u8>) -> Option<Vec<u8>> {
+    let mut image = png::Image {
+        width: width,
+        height: height,
+        pixels: PixelsByColorType::RGBA8(pixels),
+    };
+    let index = ENCODED_IMAGE_COUNT.fetch_add(1, Ordering::SeqCst);
+    let path = env::temp_dir().join(format!("servo-svg-{}-{}.png", unsafe { getpid() }, index));
+    if let Err(error) = png::store_png(&mut image, &path) {
+        debug!("SvgBackend: couldn't encode an image: {}", error);
+        return None
+    }
+
+    let mut png = Vec::new();
+    let result = File::open(&path).and_then(|mut file| file.read_to_end(&mut png));
+    let _ = fs::remove_file(&path);
+    match result {
+        Ok(_) => Some(png),
+        Err(error) => {
+            debug!("SvgBackend: couldn't read an encoded image: {}", error);
+            None
+        }
+    }
+}
+
+/// The name of the blend mode in CSS.
+fn blend_mode_name(blend_mode: mix_blend_mode::T) -> &'static str {
+    match blend_mode {
+        mix_blend_mode::T::normal => "normal",
+        mix_blend_mode::T::multiply => "multiply",
+        mix_blend_mode::T::screen => "screen",
+        mix_blend_mode::T::overlay => "overlay",
+        mix_blend_mode::T::darken => "darken",
+        mix_blend_mode::T::lighten => "lighten",
+        mix_blend_mode::T::color_dodge => "color-dodge",
+        mix_blend_mode::T::color_burn => "color-burn",
+        mix_blend_mode::T::hard_light => "hard-light",
+        mix_blend_mode::T::soft_light => "soft-light",
+        mix_blend_mode::T::difference => "difference",
+        mix_blend_mode::T::exclusion => "exclusion",
+        mix_blend_mode::T::hue => "hue",
+        mix_blend_mode::T::saturation => "saturation",
+        mix_blend_mode::T::color => "color",
+        mix_blend_mode::T::luminosity => "luminosity",
+    }
+}
@@ -4,6 +4,9 @@
 
 use azure::AzFloat;
 use azure::azure::AzColor;
+use paint_backend;
+use std::num::Float;
+use util::opts::ColorSpace;
 
 #[inline]
 pub fn new(r: AzFloat, g: AzFloat, b: AzFloat, a: AzFloat) -> AzColor {
@@ -39,3 +42,70 @@ pub fn transparent() -> AzColor {
 pub fn white() -> AzColor {
     AzColor { r: 1.0, g: 1.0, b: 1.0, a: 1.0 }
 }
+
+/// Returns the color as backends take it.
+#[inline]
+pub fn to_paint_color(color: AzColor) -> paint_backend::Color {
+    paint_backend::Color::new(color.r, color.g, color.b, color.a)
+}
+
+/// Converts linear Display P3 to linear sRGB, a row for each of red, green and blue.
+static P3_TO_SRGB: [[AzFloat; 3]; 3] = [
+    [1.2249401, -0.2249404, 0.0],
+    [-0.0420569, 1.0420571, 0.0],
+    [-0.0196376, -0.0786361, 1.0982735],
+];
+
+/// Converts linear sRGB to linear Display P3, a row for each of red, green and blue.
+static SRGB_TO_P3: [[AzFloat; 3]; 3] = [
+    [0.8224621, 0.1775380, 0.0],
+    [0.0331941, 0.9668058, 0.0],
+    [0.0170827, 0.0723974, 0.9105199],
+];
+
+/// Converts a color in the color space `from` to the color space `to`.
+///
+/// Colors that are outside of the gamut of `to` get components below 0 or above 1, rather than
+/// being clamped, so that surfaces that keep such colors can show them.
+pub fn convert(color: AzColor, from: ColorSpace, to: ColorSpace) -> AzColor {
+    let matrix = match (from, to) {
+        (ColorSpace::Srgb, ColorSpace::Srgb) | (ColorSpace::DisplayP3, ColorSpace::DisplayP3) => {
+            return color
+        }
+        (ColorSpace::DisplayP3, ColorSpace::Srgb) => &P3_TO_SRGB,
+        (ColorSpace::Srgb, ColorSpace::DisplayP3) => &SRGB_TO_P3,
+    };
+    let linear = [to_linear(color.r), to_linear(color.g), to_linear(color.b)];
+    let component = |row: &[AzFloat; 3]| {
+        from_linear(row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2])
+    };
+    AzColor {
+        r: component(&matrix[0]),
+        g: component(&matrix[1]),
+        b: component(&matrix[2]),
+        a: color.a,
+    }
+}
+
+/// Undoes the sRGB transfer function, which Display P3 shares. Negative components are mirrored,
+/// as in extended sRGB.
+fn to_linear(component: AzFloat) -> AzFloat {
+    let magnitude = component.abs();
+    let linear = if magnitude <= 0.04045 {
+        magnitude / 12.92
+    } else {
+        ((magnitude + 0.055) / 1.055).powf(2.4)
+    };
+    if component < 0.0 { -linear } else { linear }
+}
+
+/// Applies the sRGB transfer function.
+fn from_linear(component: AzFloat) -> AzFloat {
+    let magnitude = component.abs();
+    let encoded = if magnitude <= 0.0031308 {
+        magnitude * 12.92
+    } else {
+        1.055 * magnitude.powf(1.0 / 2.4) - 0.055
+    };
+    if component < 0.0 { -encoded } else { encoded }
+}
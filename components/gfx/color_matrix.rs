@@ -0,0 +1,112 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The color matrices of CSS filters, which the Azure filter pipeline and the backends that
+//! apply filters themselves share.
+
+use azure::AzFloat;
+use azure::azure_hl::Matrix5x4;
+use std::num::Float;
+use style::computed_values::filter;
+
+/// Creates a 5x4 color matrix that paints everything in the color of the drop shadow, keeping its
+/// alpha, per CSS-FILTERS § 5.
+pub fn drop_shadow_color(shadow: &filter::DropShadow) -> Matrix5x4 {
+    let color = &shadow.color;
+    Matrix5x4 {
+        m11: 0.0, m21: 0.0, m31: 0.0, m41: 0.0, m51: color.red as AzFloat,
+        m12: 0.0, m22: 0.0, m32: 0.0, m42: 0.0, m52: color.green as AzFloat,
+        m13: 0.0, m23: 0.0, m33: 0.0, m43: 0.0, m53: color.blue as AzFloat,
+        m14: 0.0, m24: 0.0, m34: 0.0, m44: color.alpha as AzFloat, m54: 0.0,
+    }
+}
+
+/// Creates a grayscale 5x4 color matrix per CSS-FILTERS § 12.1.1.
+pub fn grayscale(amount: AzFloat) -> Matrix5x4 {
+    Matrix5x4 {
+        m11: 0.2126 + 0.7874 * (1.0 - amount),
+            m21: 0.7152 - 0.7152 * (1.0 - amount),
+            m31: 0.0722 - 0.0722 * (1.0 - amount),
+            m41: 0.0,
+            m51: 0.0,
+        m12: 0.2126 - 0.2126 * (1.0 - amount),
+            m22: 0.7152 + 0.2848 * (1.0 - amount),
+            m32: 0.0722 - 0.0722 * (1.0 - amount),
+            m42: 0.0,
+            m52: 0.0,
+        m13: 0.2126 - 0.2126 * (1.0 - amount),
+            m23: 0.7152 - 0.7152 * (1.0 - amount),
+            m33: 0.0722 + 0.9278 * (1.0 - amount),
+            m43: 0.0,
+            m53: 0.0,
+        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
+    }
+}
+
+/// Creates a 5x4 hue rotation color matrix per CSS-FILTERS § 8.5.
+pub fn hue_rotate(angle: AzFloat) -> Matrix5x4 {
+    let (c, s) = (angle.cos(), angle.sin());
+    Matrix5x4 {
+        m11: 0.213 + c * 0.787 + s * -0.213,
+            m21: 0.715 + c * -0.715 + s * -0.715,
+            m31: 0.072 + c * -0.072 + s * 0.928,
+            m41: 0.0,
+            m51: 0.0,
+        m12: 0.213 + c * -0.213 + s * 0.143,
+            m22: 0.715 + c * 0.285 + s * 0.140,
+            m32: 0.072 + c * -0.072 + s * -0.283,
+            m42: 0.0,
+            m52: 0.0,
+        m13: 0.213 + c * -0.213 + s * -0.787,
+            m23: 0.715 + c * -0.715 + s * 0.715,
+            m33: 0.072 + c * 0.928 + s * 0.072,
+            m43: 0.0,
+            m53: 0.0,
+        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
+    }
+}
+
+/// Creates a 5x4 saturation color matrix per CSS-FILTERS § 8.5.
+pub fn saturate(amount: AzFloat) -> Matrix5x4 {
+    Matrix5x4 {
+        m11: 0.213 + 0.787 * amount,
+            m21: 0.715 - 0.715 * amount,
+            m31: 0.072 - 0.072 * amount,
+            m41: 0.0,
+            m51: 0.0,
+        m12: 0.213 - 0.213 * amount,
+            m22: 0.715 + 0.285 * amount,
+            m32: 0.072 - 0.072 * amount,
+            m42: 0.0,
+            m52: 0.0,
+        m13: 0.213 - 0.213 * amount,
+            m23: 0.715 - 0.715 * amount,
+            m33: 0.072 + 0.928 * amount,
+            m43: 0.0,
+            m53: 0.0,
+        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
+    }
+}
+
+/// Creates a sepia 5x4 color matrix per CSS-FILTERS § 12.1.1.
+pub fn sepia(amount: AzFloat) -> Matrix5x4 {
+    Matrix5x4 {
+        m11: 0.393 + 0.607 * (1.0 - amount),
+            m21: 0.769 - 0.769 * (1.0 - amount),
+            m31: 0.189 - 0.189 * (1.0 - amount),
+            m41: 0.0,
+            m51: 0.0,
+        m12: 0.349 - 0.349 * (1.0 - amount),
+            m22: 0.686 + 0.314 * (1.0 - amount),
+            m32: 0.168 - 0.168 * (1.0 - amount),
+            m42: 0.0,
+            m52: 0.0,
+        m13: 0.272 - 0.272 * (1.0 - amount),
+            m23: 0.534 - 0.534 * (1.0 - amount),
+            m33: 0.131 + 0.869 * (1.0 - amount),
+            m43: 0.0,
+            m53: 0.0,
+        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
+    }
+}
@@ -0,0 +1,1515 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Encodes display lists into bytes and decodes them again, so that they can be sent to a paint
+//! backend in another process.
+//!
+//! Values are written one after another, little-endian, without names or padding, so both sides
+//! must be built from the same source. Values that display lists share through `Arc`s, such as
+//! text runs, fonts, glyphs and images, are written once and referred to after that, so they are
+//! shared again once decoded. Generated images are written as the name of their generator and
+//! its arguments, and are drawn by the generators registered in the process that paints them.
+
+use display_list::{BaseDisplayItem, BorderDisplayItem, BorderRadii, BoxShadowClipMode};
+use display_list::{BoxShadowDisplayItem, ClippingRegion, ComplexClippingRegion, CursorRegion};
+use display_list::{DISPLAY_LIST_SECTIONS, DisplayItem, DisplayItemId, DisplayItemMetadata};
+use display_list::{DisplayList, EmphasisMarks, ExternalTextureDisplayItem, GradientDisplayItem};
+use display_list::{IframeDisplayItem, ImageAnimation, ImageDisplayItem, ImageFrame};
+use display_list::{ImageOrientation, ImageRepeat, ImageSource, LineDisplayItem, OpaqueNode};
+use display_list::{RadialGradientDisplayItem, RubyAnnotation, RubyPosition};
+use display_list::{SolidColorDisplayItem, StackingContext, TextDecoration, TextDecorationLine};
+use display_list::{TextDecorationStyle, TextDisplayItem, TextOrientation, TextShadow};
+use display_list::transform3d;
+use font::{FontMetrics, FontSynthesisFlags, FontVariation, FontVariations};
+use font_instance::FontInstanceKey;
+use image_generator::GeneratedImage;
+use paint_task::{LayerScrolling, PaintLayer};
+use platform::font_template::FontTemplateData;
+use text::glyph::{CharIndex, GlyphId, GlyphStore, GlyphStoreData};
+use text::text_run::{GlyphRun, TextRun};
+
+use azure::AzFloat;
+use azure::azure_hl::{Color, GradientStop};
+use geom::{Point2D, Rect, SideOffsets2D, Size2D};
+use geom::matrix::Matrix4;
+use geom::matrix2d::Matrix2D;
+use libc::uintptr_t;
+use msg::compositor_msg::{AnimationTimingFunction, ExternalTextureId, LayerAnimation, LayerId};
+use msg::compositor_msg::{LayerKeyframes, LayerKind, OverscrollBehavior, OverscrollMode};
+use msg::compositor_msg::{ScrollId, ScrollPolicy};
+use msg::constellation_msg::{PipelineId, SubpageId};
+use net_traits::image::base::{DecodedImages, Image, LazyImage, LazyImageCache};
+use net_traits::image::base::MAX_DECODED_SIZE;
+use png::PixelsByColorType;
+use std::collections::HashMap;
+use std::mem;
+use std::{isize, usize};
+use std::num::{Float, FromPrimitive};
+use std::sync::{Arc, Mutex};
+use style::computed_values::{border_style, filter, image_rendering};
+use style::values::RGBA;
+use style::values::specified::Angle;
+use url::Url;
+use util::cursor::Cursor;
+use util::geometry::Au;
+use util::opts::{ColorSpace, FontAutohint, FontHinting};
+use util::range::Range;
+
+/// Why bytes couldn't be decoded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    /// The bytes end in the middle of a value, or go on after the last one.
+    WrongLength,
+    /// A value that can't have been encoded, such as an unknown kind of display item.
+    InvalidValue,
+    /// A reference to a shared value that hasn't been decoded yet, or that is of another type.
+    InvalidReference,
+}
+
+/// Encodes the stacking context and everything in it.
+pub fn encode_stacking_context(stacking_context: &StackingContext) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    write_stacking_context(&mut encoder, stacking_context);
+    encoder.into_bytes()
+}
+
+/// Decodes a stacking context that `encode_stacking_context()` encoded.
+pub fn decode_stacking_context(bytes: &[u8]) -> Result<StackingContext, DecodeError> {
+    let mut decoder = Decoder::new(bytes);
+    let stacking_context = try!(read_stacking_context(&mut decoder));
+    try!(decoder.finish());
+    Ok(stacking_context)
+}
+
+/// Encodes the display list and the stacking contexts in it.
+pub fn encode_display_list(display_list: &DisplayList) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    write_display_list(&mut encoder, display_list);
+    encoder.into_bytes()
+}
+
+/// Decodes a display list that `encode_display_list()` encoded.
+pub fn decode_display_list(bytes: &[u8]) -> Result<DisplayList, DecodeError> {
+    let mut decoder = Decoder::new(bytes);
+    let display_list = try!(read_display_list(&mut decoder));
+    try!(decoder.finish());
+    Ok(display_list)
+}
+
+/// Writes values into bytes.
+struct Encoder {
+    bytes: Vec<u8>,
+    /// The index of each shared value that has been written, by its address.
+    shared: HashMap<usize, u32>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            bytes: Vec::new(),
+            shared: HashMap::new(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value)
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8)
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.push(value as u8);
+        self.bytes.push((value >> 8) as u8);
+        self.bytes.push((value >> 16) as u8);
+        self.bytes.push((value >> 24) as u8)
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.write_u32(value as u32)
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_u32(value as u32);
+        self.write_u32((value >> 32) as u32)
+    }
+
+    #[allow(unsafe_code)]
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_u32(unsafe {
+            mem::transmute(value)
+        })
+    }
+
+    #[allow(unsafe_code)]
+    pub fn write_f64(&mut self, value: f64) {
+        self.write_u64(unsafe {
+            mem::transmute(value)
+        })
+    }
+
+    /// Writes the number of bytes and then the bytes.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.bytes.push_all(bytes)
+    }
+
+    pub fn write_str(&mut self, string: &str) {
+        self.write_bytes(string.as_bytes())
+    }
+
+    pub fn write_au(&mut self, length: Au) {
+        self.write_i32(length.0)
+    }
+
+    /// Writes the shared value at `address` with `write`, unless it has been written already, in
+    /// which case it is referred to instead.
+    fn write_shared<F>(&mut self, address: usize, write: F) where F: FnOnce(&mut Encoder) {
+        match self.shared.get(&address).map(|index| *index) {
+            Some(index) => return self.write_u32(index + 1),
+            None => self.write_u32(0),
+        }
+        write(self);
+        // The decoder numbers values once it is done with them, after those that they contain.
+        let index = self.shared.len() as u32;
+        self.shared.insert(address, index);
+    }
+}
+
+/// Reads values out of bytes that an `Encoder` wrote.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// The shared values that have been read, in the order in which they were finished.
+    shared: Vec<SharedValue>,
+    /// The cache that the images that are decoded when painted keep their pixels in.
+    lazy_images: LazyImageCache,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            bytes: bytes,
+            position: 0,
+            shared: Vec::new(),
+            lazy_images: Arc::new(Mutex::new(DecodedImages::new(MAX_DECODED_SIZE))),
+        }
+    }
+
+    /// Checks that all of the bytes have been read.
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(DecodeError::WrongLength)
+        }
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self.bytes;
+        if bytes.len() - self.position < length {
+            return Err(DecodeError::WrongLength)
+        }
+        self.position += length;
+        Ok(&bytes[self.position - length..self.position])
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(try!(self.read_slice(1))[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, DecodeError> {
+        match try!(self.read_u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = try!(self.read_slice(4));
+        Ok(bytes.iter().rev().fold(0, |value, byte| (value << 8) | *byte as u32))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(try!(self.read_u32()) as i32)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        let low = try!(self.read_u32()) as u64;
+        let high = try!(self.read_u32()) as u64;
+        Ok(low | (high << 32))
+    }
+
+    #[allow(unsafe_code)]
+    pub fn read_f32(&mut self) -> Result<f32, DecodeError> {
+        let bits = try!(self.read_u32());
+        Ok(unsafe {
+            mem::transmute(bits)
+        })
+    }
+
+    #[allow(unsafe_code)]
+    pub fn read_f64(&mut self) -> Result<f64, DecodeError> {
+        let bits = try!(self.read_u64());
+        Ok(unsafe {
+            mem::transmute(bits)
+        })
+    }
+
+    /// Reads bytes that `Encoder::write_bytes()` wrote.
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let length = try!(self.read_u32()) as usize;
+        self.read_slice(length)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, DecodeError> {
+        let bytes = try!(self.read_bytes());
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidValue)
+    }
+
+    pub fn read_au(&mut self) -> Result<Au, DecodeError> {
+        Ok(Au(try!(self.read_i32())))
+    }
+
+    /// Reads a value that `Encoder::write_shared()` wrote, with `read` if it was written there
+    /// and from the values read before if it was referred to.
+    fn read_shared<T, F>(&mut self, read: F) -> Result<T, DecodeError>
+                         where T: Shared, F: FnOnce(&mut Decoder<'a>) -> Result<T, DecodeError> {
+        let reference = try!(self.read_u32()) as usize;
+        if reference == 0 {
+            let value = try!(read(self));
+            self.shared.push(value.to_shared());
+            return Ok(value)
+        }
+        self.shared
+            .get(reference - 1)
+            .and_then(|value| Shared::from_shared(value))
+            .ok_or(DecodeError::InvalidReference)
+    }
+}
+
+/// A value that display lists share through an `Arc`.
+trait Shared: Sized {
+    fn to_shared(&self) -> SharedValue;
+    fn from_shared(value: &SharedValue) -> Option<Self>;
+}
+
+macro_rules! shared_values {
+    ($($variant: ident($shared_type: ty)),+) => {
+        /// A shared value that has been read, as the decoder keeps it to be referred to again.
+        enum SharedValue {
+            $($variant($shared_type)),+
+        }
+
+        $(
+            impl Shared for $shared_type {
+                fn to_shared(&self) -> SharedValue {
+                    SharedValue::$variant(self.clone())
+                }
+
+                fn from_shared(value: &SharedValue) -> Option<$shared_type> {
+                    match *value {
+                        SharedValue::$variant(ref value) => Some(value.clone()),
+                        _ => None,
+                    }
+                }
+            }
+        )+
+    }
+}
+
+shared_values! {
+    Text(Arc<String>),
+    FontTemplate(Arc<FontTemplateData>),
+    TextRun(Arc<Box<TextRun>>),
+    GlyphRuns(Arc<Vec<GlyphRun>>),
+    GlyphStore(Arc<GlyphStore>),
+    GlyphDescents(Arc<HashMap<GlyphId, Au>>),
+    Image(Arc<Image>),
+    LazyImage(LazyImage),
+    Frames(Arc<Vec<ImageFrame>>)
+}
+
+/// Returns the address that a shared value is known by while it is written.
+fn address<T>(value: &T) -> usize {
+    value as *const T as usize
+}
+
+/// Defines functions that write a fieldless enum as the index of its value among `variants` and
+/// read it back.
+macro_rules! enum_codec {
+    ($write: ident, $read: ident, $enum_type: ty, [$($variant: path),+]) => {
+        fn $write(encoder: &mut Encoder, value: &$enum_type) {
+            let variants = [$($variant),+];
+            encoder.write_u8(variants.iter().position(|variant| variant == value).unwrap() as u8)
+        }
+
+        fn $read(decoder: &mut Decoder) -> Result<$enum_type, DecodeError> {
+            let variants = [$($variant),+];
+            let index = try!(decoder.read_u8()) as usize;
+            variants.get(index).map(|variant| variant.clone()).ok_or(DecodeError::InvalidValue)
+        }
+    }
+}
+
+enum_codec!(write_text_orientation, read_text_orientation, TextOrientation, [
+    TextOrientation::Upright,
+    TextOrientation::SidewaysLeft,
+    TextOrientation::SidewaysRight
+]);
+enum_codec!(write_decoration_line, read_decoration_line, TextDecorationLine, [
+    TextDecorationLine::Underline,
+    TextDecorationLine::Overline,
+    TextDecorationLine::LineThrough
+]);
+enum_codec!(write_decoration_style, read_decoration_style, TextDecorationStyle, [
+    TextDecorationStyle::Solid,
+    TextDecorationStyle::Double,
+    TextDecorationStyle::Dotted,
+    TextDecorationStyle::Dashed,
+    TextDecorationStyle::Wavy
+]);
+enum_codec!(write_ruby_position, read_ruby_position, RubyPosition, [
+    RubyPosition::Over,
+    RubyPosition::Under
+]);
+enum_codec!(write_font_hinting, read_font_hinting, FontHinting, [
+    FontHinting::None,
+    FontHinting::Slight,
+    FontHinting::Full
+]);
+enum_codec!(write_font_autohint, read_font_autohint, FontAutohint, [
+    FontAutohint::Default,
+    FontAutohint::Force,
+    FontAutohint::Disable
+]);
+enum_codec!(write_image_repeat, read_image_repeat, ImageRepeat, [
+    ImageRepeat::Repeat,
+    ImageRepeat::NoRepeat,
+    ImageRepeat::Round,
+    ImageRepeat::Space
+]);
+enum_codec!(write_image_rendering, read_image_rendering, image_rendering::T, [
+    image_rendering::T::Auto,
+    image_rendering::T::CrispEdges,
+    image_rendering::T::Pixelated
+]);
+enum_codec!(write_clip_mode, read_clip_mode, BoxShadowClipMode, [
+    BoxShadowClipMode::None,
+    BoxShadowClipMode::Outset,
+    BoxShadowClipMode::Inset
+]);
+enum_codec!(write_color_space, read_color_space, ColorSpace, [
+    ColorSpace::Srgb,
+    ColorSpace::DisplayP3
+]);
+enum_codec!(write_layer_kind, read_layer_kind, LayerKind, [
+    LayerKind::Content,
+    LayerKind::Mask,
+    LayerKind::Scrollbar,
+    LayerKind::Overlay
+]);
+enum_codec!(write_scroll_policy, read_scroll_policy, ScrollPolicy, [
+    ScrollPolicy::Scrollable,
+    ScrollPolicy::FixedPosition,
+    ScrollPolicy::FixedBackground
+]);
+enum_codec!(write_overscroll_mode, read_overscroll_mode, OverscrollMode, [
+    OverscrollMode::Auto,
+    OverscrollMode::Contain,
+    OverscrollMode::None
+]);
+
+fn write_option<T, F>(encoder: &mut Encoder, value: &Option<T>, write: F)
+                      where F: FnOnce(&mut Encoder, &T) {
+    match *value {
+        Some(ref value) => {
+            encoder.write_bool(true);
+            write(encoder, value)
+        }
+        None => encoder.write_bool(false),
+    }
+}
+
+fn read_option<T, F>(decoder: &mut Decoder, read: F) -> Result<Option<T>, DecodeError>
+                     where F: FnOnce(&mut Decoder) -> Result<T, DecodeError> {
+    if try!(decoder.read_bool()) {
+        read(decoder).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn write_vec<T, F>(encoder: &mut Encoder, values: &[T], mut write: F)
+                   where F: FnMut(&mut Encoder, &T) {
+    encoder.write_u32(values.len() as u32);
+    for value in values.iter() {
+        write(encoder, value)
+    }
+}
+
+fn read_vec<T, F>(decoder: &mut Decoder, mut read: F) -> Result<Vec<T>, DecodeError>
+                  where F: FnMut(&mut Decoder) -> Result<T, DecodeError> {
+    // The length isn't trusted to reserve space with, as the bytes may be wrong.
+    let length = try!(decoder.read_u32());
+    let mut values = Vec::new();
+    for _ in 0..length {
+        values.push(try!(read(decoder)))
+    }
+    Ok(values)
+}
+
+fn write_stacking_context(encoder: &mut Encoder, stacking_context: &StackingContext) {
+    write_display_list(encoder, &*stacking_context.display_list);
+
+    write_option(encoder, &stacking_context.layer, |encoder, layer| write_layer(encoder, &**layer));
+    write_rect(encoder, &stacking_context.bounds);
+    write_rect(encoder, &stacking_context.overflow);
+    encoder.write_i32(stacking_context.z_index);
+    write_vec(encoder, &stacking_context.filters.filters, write_filter);
+    encoder.write_u8(stacking_context.blend_mode as u8);
+    write_matrix(encoder, &stacking_context.transform);
+    write_matrix(encoder, &stacking_context.perspective);
+    encoder.write_bool(stacking_context.preserves_3d);
+    encoder.write_f32(stacking_context.device_pixels_per_px);
+}
+
+fn read_stacking_context(decoder: &mut Decoder) -> Result<StackingContext, DecodeError> {
+    let display_list = try!(read_display_list(decoder));
+    let layer = try!(read_option(decoder, |decoder| read_layer(decoder).map(Arc::new)));
+    let bounds = try!(read_rect(decoder));
+    let overflow = try!(read_rect(decoder));
+    let z_index = try!(decoder.read_i32());
+    let filters = filter::T::new(try!(read_vec(decoder, read_filter)));
+    let blend_mode = try!(FromPrimitive::from_u8(try!(decoder.read_u8())).ok_or(
+        DecodeError::InvalidValue));
+    let transform = try!(read_matrix(decoder));
+    let perspective = try!(read_matrix(decoder));
+    let preserves_3d = try!(decoder.read_bool());
+    let device_pixels_per_px = try!(decoder.read_f32());
+    Ok(StackingContext::new(box display_list,
+                            &bounds,
+                            &overflow,
+                            z_index,
+                            &transform,
+                            &perspective,
+                            preserves_3d,
+                            filters,
+                            blend_mode,
+                            layer,
+                            device_pixels_per_px))
+}
+
+fn write_layer(encoder: &mut Encoder, layer: &PaintLayer) {
+    encoder.write_u64(layer.id.0 as u64);
+    encoder.write_u32(layer.id.1);
+    write_color(encoder, &layer.background_color);
+    write_scroll_policy(encoder, &layer.scroll_policy);
+    write_option(encoder, &layer.scrolling, |encoder, scrolling| {
+        encoder.write_u64(scrolling.scroll_id.0 as u64);
+        write_size(encoder, &scrolling.content_size);
+        write_rect(encoder, &scrolling.viewport);
+        write_point(encoder, &scrolling.min_scroll_offset);
+        write_overscroll_mode(encoder, &scrolling.overscroll.x);
+        write_overscroll_mode(encoder, &scrolling.overscroll.y);
+    });
+    write_layer_kind(encoder, &layer.kind);
+    encoder.write_bool(layer.is_opaque);
+    write_vec(encoder, &layer.animations, write_layer_animation);
+}
+
+fn read_layer(decoder: &mut Decoder) -> Result<PaintLayer, DecodeError> {
+    let id = LayerId(try!(decoder.read_u64()) as usize, try!(decoder.read_u32()));
+    let background_color = try!(read_color(decoder));
+    let scroll_policy = try!(read_scroll_policy(decoder));
+    let scrolling = try!(read_option(decoder, |decoder| {
+        Ok(LayerScrolling {
+            scroll_id: ScrollId(try!(decoder.read_u64()) as usize),
+            content_size: try!(read_size(decoder)),
+            viewport: try!(read_rect(decoder)),
+            min_scroll_offset: try!(read_point(decoder)),
+            overscroll: OverscrollBehavior {
+                x: try!(read_overscroll_mode(decoder)),
+                y: try!(read_overscroll_mode(decoder)),
+            },
+        })
+    }));
+    Ok(PaintLayer {
+        id: id,
+        background_color: background_color,
+        scroll_policy: scroll_policy,
+        scrolling: scrolling,
+        kind: try!(read_layer_kind(decoder)),
+        is_opaque: try!(decoder.read_bool()),
+        animations: try!(read_vec(decoder, read_layer_animation)),
+    })
+}
+
+fn write_layer_animation(encoder: &mut Encoder, animation: &LayerAnimation) {
+    encoder.write_u64(animation.layer_id.0 as u64);
+    encoder.write_u32(animation.layer_id.1);
+    encoder.write_f64(animation.start_time);
+    encoder.write_f64(animation.end_time);
+    match animation.timing_function {
+        AnimationTimingFunction::Linear => encoder.write_u8(0),
+        AnimationTimingFunction::CubicBezier(first, second) => {
+            encoder.write_u8(1);
+            for value in [first.x, first.y, second.x, second.y].iter() {
+                encoder.write_f64(*value)
+            }
+        }
+    }
+    match animation.keyframes {
+        LayerKeyframes::Transform(ref keyframes) => {
+            encoder.write_u8(0);
+            write_vec(encoder, keyframes, |encoder, &(offset, ref transform)| {
+                encoder.write_f32(offset);
+                for value in [transform.m11, transform.m12, transform.m21, transform.m22,
+                              transform.m31, transform.m32].iter() {
+                    encoder.write_f32(*value)
+                }
+            })
+        }
+        LayerKeyframes::Opacity(ref keyframes) => {
+            encoder.write_u8(1);
+            write_vec(encoder, keyframes, |encoder, &(offset, opacity)| {
+                encoder.write_f32(offset);
+                encoder.write_f32(opacity)
+            })
+        }
+    }
+}
+
+fn read_layer_animation(decoder: &mut Decoder) -> Result<LayerAnimation, DecodeError> {
+    let layer_id = LayerId(try!(decoder.read_u64()) as usize, try!(decoder.read_u32()));
+    let start_time = try!(decoder.read_f64());
+    let end_time = try!(decoder.read_f64());
+    let timing_function = match try!(decoder.read_u8()) {
+        0 => AnimationTimingFunction::Linear,
+        1 => {
+            let first = Point2D(try!(decoder.read_f64()), try!(decoder.read_f64()));
+            let second = Point2D(try!(decoder.read_f64()), try!(decoder.read_f64()));
+            AnimationTimingFunction::CubicBezier(first, second)
+        }
+        _ => return Err(DecodeError::InvalidValue),
+    };
+    let keyframes = match try!(decoder.read_u8()) {
+        0 => LayerKeyframes::Transform(try!(read_vec(decoder, |decoder| {
+            let offset = try!(decoder.read_f32());
+            let mut values = [0.0; 6];
+            for value in values.iter_mut() {
+                *value = try!(decoder.read_f32())
+            }
+            Ok((offset, Matrix2D::new(values[0],
+                                      values[1],
+                                      values[2],
+                                      values[3],
+                                      values[4],
+                                      values[5])))
+        }))),
+        1 => LayerKeyframes::Opacity(try!(read_vec(decoder, |decoder| {
+            Ok((try!(decoder.read_f32()), try!(decoder.read_f32())))
+        }))),
+        _ => return Err(DecodeError::InvalidValue),
+    };
+    Ok(LayerAnimation {
+        layer_id: layer_id,
+        start_time: start_time,
+        end_time: end_time,
+        timing_function: timing_function,
+        keyframes: keyframes,
+    })
+}
+
+fn write_filter(encoder: &mut Encoder, style_filter: &filter::Filter) {
+    match *style_filter {
+        filter::Filter::Blur(radius) => {
+            encoder.write_u8(0);
+            encoder.write_au(radius)
+        }
+        filter::Filter::Brightness(amount) => {
+            encoder.write_u8(1);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::Contrast(amount) => {
+            encoder.write_u8(2);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::DropShadow(ref shadow) => {
+            encoder.write_u8(3);
+            encoder.write_au(shadow.offset_x);
+            encoder.write_au(shadow.offset_y);
+            encoder.write_au(shadow.blur_radius);
+            for component in [shadow.color.red,
+                              shadow.color.green,
+                              shadow.color.blue,
+                              shadow.color.alpha].iter() {
+                encoder.write_f32(*component)
+            }
+        }
+        filter::Filter::Grayscale(amount) => {
+            encoder.write_u8(4);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::HueRotate(angle) => {
+            encoder.write_u8(5);
+            encoder.write_f64(angle.radians())
+        }
+        filter::Filter::Invert(amount) => {
+            encoder.write_u8(6);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::Opacity(amount) => {
+            encoder.write_u8(7);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::Saturate(amount) => {
+            encoder.write_u8(8);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::Sepia(amount) => {
+            encoder.write_u8(9);
+            encoder.write_f64(amount)
+        }
+        filter::Filter::Url(ref url) => {
+            encoder.write_u8(10);
+            encoder.write_str(&url.serialize())
+        }
+    }
+}
+
+fn read_filter(decoder: &mut Decoder) -> Result<filter::Filter, DecodeError> {
+    Ok(match try!(decoder.read_u8()) {
+        0 => filter::Filter::Blur(try!(decoder.read_au())),
+        1 => filter::Filter::Brightness(try!(decoder.read_f64())),
+        2 => filter::Filter::Contrast(try!(decoder.read_f64())),
+        3 => {
+            filter::Filter::DropShadow(filter::DropShadow {
+                offset_x: try!(decoder.read_au()),
+                offset_y: try!(decoder.read_au()),
+                blur_radius: try!(decoder.read_au()),
+                color: RGBA {
+                    red: try!(decoder.read_f32()),
+                    green: try!(decoder.read_f32()),
+                    blue: try!(decoder.read_f32()),
+                    alpha: try!(decoder.read_f32()),
+                },
+            })
+        }
+        4 => filter::Filter::Grayscale(try!(decoder.read_f64())),
+        5 => filter::Filter::HueRotate(Angle(try!(decoder.read_f64()))),
+        6 => filter::Filter::Invert(try!(decoder.read_f64())),
+        7 => filter::Filter::Opacity(try!(decoder.read_f64())),
+        8 => filter::Filter::Saturate(try!(decoder.read_f64())),
+        9 => filter::Filter::Sepia(try!(decoder.read_f64())),
+        10 => {
+            let url = try!(decoder.read_string());
+            filter::Filter::Url(try!(Url::parse(&url).map_err(|_| DecodeError::InvalidValue)))
+        }
+        _ => return Err(DecodeError::InvalidValue),
+    })
+}
+
+fn write_display_list(encoder: &mut Encoder, display_list: &DisplayList) {
+    for section in DISPLAY_LIST_SECTIONS.iter() {
+        write_vec(encoder, display_list.get(*section), write_display_item)
+    }
+    write_vec(encoder, &display_list.children, |encoder, kid| {
+        write_stacking_context(encoder, &**kid)
+    })
+}
+
+fn read_display_list(decoder: &mut Decoder) -> Result<DisplayList, DecodeError> {
+    let mut display_list = DisplayList::new();
+    for section in DISPLAY_LIST_SECTIONS.iter() {
+        *display_list.get_mut(*section) = try!(read_vec(decoder, read_display_item));
+    }
+    display_list.children = try!(read_vec(decoder, |decoder| {
+        read_stacking_context(decoder).map(Arc::new)
+    }));
+    Ok(display_list)
+}
+
+fn write_display_item(encoder: &mut Encoder, item: &DisplayItem) {
+    match *item {
+        DisplayItem::SolidColorClass(ref solid_color) => {
+            encoder.write_u8(0);
+            write_base(encoder, &solid_color.base);
+            write_color(encoder, &solid_color.color)
+        }
+        DisplayItem::TextClass(ref text) => {
+            encoder.write_u8(1);
+            write_base(encoder, &text.base);
+            write_text_run(encoder, &text.text_run);
+            write_char_range(encoder, &text.range);
+            write_color(encoder, &text.text_color);
+            write_point(encoder, &text.baseline_origin);
+            write_text_orientation(encoder, &text.orientation);
+            encoder.write_au(text.blur_radius);
+            write_vec(encoder, &text.shadows, |encoder, shadow| {
+                write_point(encoder, &shadow.offset);
+                encoder.write_au(shadow.blur_radius);
+                write_color(encoder, &shadow.color)
+            });
+            write_vec(encoder, &text.decorations, |encoder, decoration| {
+                write_decoration_line(encoder, &decoration.line);
+                write_decoration_style(encoder, &decoration.style);
+                write_color(encoder, &decoration.color);
+                encoder.write_bool(decoration.skip_ink)
+            });
+            write_option(encoder, &text.ruby_annotation, |encoder, annotation| {
+                write_text_run(encoder, &annotation.text_run);
+                write_char_range(encoder, &annotation.range);
+                write_color(encoder, &annotation.text_color);
+                write_ruby_position(encoder, &annotation.position)
+            });
+            write_option(encoder, &text.emphasis_marks, |encoder, marks| {
+                write_text_run(encoder, &marks.text_run);
+                write_char_range(encoder, &marks.range);
+                write_color(encoder, &marks.text_color);
+                write_ruby_position(encoder, &marks.position)
+            })
+        }
+        DisplayItem::ImageClass(ref image) => {
+            encoder.write_u8(2);
+            write_base(encoder, &image.base);
+            write_image_source(encoder, &image.image);
+            write_option(encoder, &image.source_rect, |encoder, source_rect| {
+                for value in [source_rect.origin.x,
+                              source_rect.origin.y,
+                              source_rect.size.width,
+                              source_rect.size.height].iter() {
+                    encoder.write_f32(*value)
+                }
+            });
+            write_size(encoder, &image.stretch_size);
+            write_image_repeat(encoder, &image.horizontal_repeat);
+            write_image_repeat(encoder, &image.vertical_repeat);
+            write_image_rendering(encoder, &image.image_rendering);
+            write_option(encoder, &image.animation, |encoder, animation| {
+                let frames = &animation.frames;
+                encoder.write_shared(address(&**frames), |encoder| {
+                    write_vec(encoder, &**frames, |encoder, frame| {
+                        write_image(encoder, &frame.image);
+                        encoder.write_u32(frame.delay)
+                    })
+                });
+                encoder.write_u64(animation.start_time);
+                encoder.write_bool(animation.repeat)
+            });
+            encoder.write_u8(image.orientation.quarter_turns);
+            encoder.write_bool(image.orientation.flipped);
+            encoder.write_bool(image.is_opaque)
+        }
+        DisplayItem::BorderClass(ref border) => {
+            encoder.write_u8(3);
+            write_base(encoder, &border.base);
+            write_side_offsets(encoder, &border.border_widths, |encoder, width| {
+                encoder.write_au(*width)
+            });
+            write_side_offsets(encoder, &border.color, write_color);
+            write_side_offsets(encoder, &border.style, |encoder, style| {
+                encoder.write_i32(*style as i32)
+            });
+            write_radii(encoder, &border.radius)
+        }
+        DisplayItem::GradientClass(ref gradient) => {
+            encoder.write_u8(4);
+            write_base(encoder, &gradient.base);
+            write_point(encoder, &gradient.start_point);
+            write_point(encoder, &gradient.end_point);
+            write_vec(encoder, &gradient.stops, write_stop);
+            encoder.write_bool(gradient.repeating)
+        }
+        DisplayItem::RadialGradientClass(ref gradient) => {
+            encoder.write_u8(5);
+            write_base(encoder, &gradient.base);
+            write_point(encoder, &gradient.center);
+            write_size(encoder, &gradient.radius);
+            write_vec(encoder, &gradient.stops, write_stop)
+        }
+        DisplayItem::LineClass(ref line) => {
+            encoder.write_u8(6);
+            write_base(encoder, &line.base);
+            write_color(encoder, &line.color);
+            encoder.write_i32(line.style as i32)
+        }
+        DisplayItem::BoxShadowClass(ref box_shadow) => {
+            encoder.write_u8(7);
+            write_base(encoder, &box_shadow.base);
+            write_rect(encoder, &box_shadow.box_bounds);
+            write_point(encoder, &box_shadow.offset);
+            write_color(encoder, &box_shadow.color);
+            encoder.write_au(box_shadow.blur_radius);
+            encoder.write_au(box_shadow.spread_radius);
+            write_clip_mode(encoder, &box_shadow.clip_mode)
+        }
+        DisplayItem::ExternalTextureClass(ref texture) => {
+            encoder.write_u8(8);
+            write_base(encoder, &texture.base);
+            encoder.write_u64(texture.texture.0 as u64)
+        }
+        DisplayItem::IframeClass(ref iframe) => {
+            encoder.write_u8(9);
+            write_base(encoder, &iframe.base);
+            encoder.write_u32(iframe.pipeline_id.0);
+            encoder.write_u32(iframe.subpage_id.0)
+        }
+    }
+}
+
+fn read_display_item(decoder: &mut Decoder) -> Result<DisplayItem, DecodeError> {
+    let kind = try!(decoder.read_u8());
+    let base = try!(read_base(decoder));
+    Ok(match kind {
+        0 => {
+            DisplayItem::SolidColorClass(box SolidColorDisplayItem {
+                base: base,
+                color: try!(read_color(decoder)),
+            })
+        }
+        1 => {
+            let text_run = try!(read_text_run(decoder));
+            let range = try!(read_run_range(decoder, &**text_run));
+            DisplayItem::TextClass(box TextDisplayItem {
+                base: base,
+                text_run: text_run,
+                range: range,
+                text_color: try!(read_color(decoder)),
+                baseline_origin: try!(read_point(decoder)),
+                orientation: try!(read_text_orientation(decoder)),
+                blur_radius: try!(decoder.read_au()),
+                shadows: try!(read_vec(decoder, |decoder| {
+                    Ok(TextShadow {
+                        offset: try!(read_point(decoder)),
+                        blur_radius: try!(decoder.read_au()),
+                        color: try!(read_color(decoder)),
+                    })
+                })),
+                decorations: try!(read_vec(decoder, |decoder| {
+                    Ok(TextDecoration {
+                        line: try!(read_decoration_line(decoder)),
+                        style: try!(read_decoration_style(decoder)),
+                        color: try!(read_color(decoder)),
+                        skip_ink: try!(decoder.read_bool()),
+                    })
+                })),
+                ruby_annotation: try!(read_option(decoder, |decoder| {
+                    let text_run = try!(read_text_run(decoder));
+                    let range = try!(read_run_range(decoder, &**text_run));
+                    Ok(RubyAnnotation {
+                        text_run: text_run,
+                        range: range,
+                        text_color: try!(read_color(decoder)),
+                        position: try!(read_ruby_position(decoder)),
+                    })
+                })),
+                emphasis_marks: try!(read_option(decoder, |decoder| {
+                    let text_run = try!(read_text_run(decoder));
+                    let range = try!(read_run_range(decoder, &**text_run));
+                    Ok(EmphasisMarks {
+                        text_run: text_run,
+                        range: range,
+                        text_color: try!(read_color(decoder)),
+                        position: try!(read_ruby_position(decoder)),
+                    })
+                })),
+            })
+        }
+        2 => {
+            DisplayItem::ImageClass(box ImageDisplayItem {
+                base: base,
+                image: try!(read_image_source(decoder)),
+                source_rect: try!(read_option(decoder, |decoder| {
+                    Ok(Rect(Point2D(try!(decoder.read_f32()), try!(decoder.read_f32())),
+                            Size2D(try!(decoder.read_f32()), try!(decoder.read_f32()))))
+                })),
+                stretch_size: try!(read_size(decoder)),
+                horizontal_repeat: try!(read_image_repeat(decoder)),
+                vertical_repeat: try!(read_image_repeat(decoder)),
+                image_rendering: try!(read_image_rendering(decoder)),
+                animation: try!(read_option(decoder, |decoder| {
+                    let frames = try!(decoder.read_shared(|decoder| {
+                        let frames = try!(read_vec(decoder, |decoder| {
+                            Ok(ImageFrame {
+                                image: try!(read_image(decoder)),
+                                delay: try!(decoder.read_u32()),
+                            })
+                        }));
+                        // Painting picks one of the frames, so there must be one.
+                        if frames.is_empty() {
+                            return Err(DecodeError::InvalidValue)
+                        }
+                        Ok(Arc::new(frames))
+                    }));
+                    Ok(ImageAnimation {
+                        frames: frames,
+                        start_time: try!(decoder.read_u64()),
+                        repeat: try!(decoder.read_bool()),
+                    })
+                })),
+                orientation: try!(read_image_orientation(decoder)),
+                is_opaque: try!(decoder.read_bool()),
+            })
+        }
+        3 => {
+            DisplayItem::BorderClass(box BorderDisplayItem {
+                base: base,
+                border_widths: try!(read_side_offsets(decoder, |decoder| decoder.read_au())),
+                color: try!(read_side_offsets(decoder, read_color)),
+                style: try!(read_side_offsets(decoder, read_border_style)),
+                radius: try!(read_radii(decoder)),
+            })
+        }
+        4 => {
+            DisplayItem::GradientClass(box GradientDisplayItem {
+                base: base,
+                start_point: try!(read_point(decoder)),
+                end_point: try!(read_point(decoder)),
+                stops: try!(read_vec(decoder, read_stop)),
+                repeating: try!(decoder.read_bool()),
+            })
+        }
+        5 => {
+            DisplayItem::RadialGradientClass(box RadialGradientDisplayItem {
+                base: base,
+                center: try!(read_point(decoder)),
+                radius: try!(read_size(decoder)),
+                stops: try!(read_vec(decoder, read_stop)),
+            })
+        }
+        6 => {
+            DisplayItem::LineClass(box LineDisplayItem {
+                base: base,
+                color: try!(read_color(decoder)),
+                style: try!(read_border_style(decoder)),
+            })
+        }
+        7 => {
+            DisplayItem::BoxShadowClass(box BoxShadowDisplayItem {
+                base: base,
+                box_bounds: try!(read_rect(decoder)),
+                offset: try!(read_point(decoder)),
+                color: try!(read_color(decoder)),
+                blur_radius: try!(decoder.read_au()),
+                spread_radius: try!(decoder.read_au()),
+                clip_mode: try!(read_clip_mode(decoder)),
+            })
+        }
+        8 => {
+            DisplayItem::ExternalTextureClass(box ExternalTextureDisplayItem {
+                base: base,
+                texture: ExternalTextureId(try!(decoder.read_u64()) as usize),
+            })
+        }
+        9 => {
+            DisplayItem::IframeClass(box IframeDisplayItem {
+                base: base,
+                pipeline_id: PipelineId(try!(decoder.read_u32())),
+                subpage_id: SubpageId(try!(decoder.read_u32())),
+            })
+        }
+        _ => return Err(DecodeError::InvalidValue),
+    })
+}
+
+fn write_base(encoder: &mut Encoder, base: &BaseDisplayItem) {
+    write_rect(encoder, &base.bounds);
+    encoder.write_u64(base.metadata.node.id() as u64);
+    write_option(encoder, &base.metadata.pointing, |encoder, cursor| {
+        encoder.write_u8(*cursor as u8)
+    });
+    encoder.write_u64(base.metadata.tag);
+    write_clip(encoder, &base.clip);
+    encoder.write_u64(base.id.node.id() as u64);
+    encoder.write_u32(base.id.index);
+    write_vec(encoder, &base.cursor_regions, |encoder, region| {
+        write_rect(encoder, &region.rect);
+        encoder.write_u8(region.cursor as u8)
+    });
+    write_color_space(encoder, &base.color_space)
+}
+
+fn read_base(decoder: &mut Decoder) -> Result<BaseDisplayItem, DecodeError> {
+    Ok(BaseDisplayItem {
+        bounds: try!(read_rect(decoder)),
+        metadata: DisplayItemMetadata {
+            node: OpaqueNode(try!(decoder.read_u64()) as uintptr_t),
+            pointing: try!(read_option(decoder, read_cursor)),
+            tag: try!(decoder.read_u64()),
+        },
+        clip: try!(read_clip(decoder)),
+        id: DisplayItemId {
+            node: OpaqueNode(try!(decoder.read_u64()) as uintptr_t),
+            index: try!(decoder.read_u32()),
+        },
+        cursor_regions: try!(read_vec(decoder, |decoder| {
+            Ok(CursorRegion {
+                rect: try!(read_rect(decoder)),
+                cursor: try!(read_cursor(decoder)),
+            })
+        })),
+        color_space: try!(read_color_space(decoder)),
+    })
+}
+
+fn read_cursor(decoder: &mut Decoder) -> Result<Cursor, DecodeError> {
+    FromPrimitive::from_u8(try!(decoder.read_u8())).ok_or(DecodeError::InvalidValue)
+}
+
+fn read_border_style(decoder: &mut Decoder) -> Result<border_style::T, DecodeError> {
+    FromPrimitive::from_i32(try!(decoder.read_i32())).ok_or(DecodeError::InvalidValue)
+}
+
+fn write_clip(encoder: &mut Encoder, clip: &ClippingRegion) {
+    write_rect(encoder, &clip.main);
+    write_vec(encoder, &clip.complex, |encoder, complex| {
+        write_rect(encoder, &complex.rect);
+        write_radii(encoder, &complex.radii)
+    })
+}
+
+fn read_clip(decoder: &mut Decoder) -> Result<ClippingRegion, DecodeError> {
+    Ok(ClippingRegion {
+        main: try!(read_rect(decoder)),
+        complex: try!(read_vec(decoder, |decoder| {
+            Ok(ComplexClippingRegion {
+                rect: try!(read_rect(decoder)),
+                radii: try!(read_radii(decoder)),
+            })
+        })),
+    })
+}
+
+fn write_radii(encoder: &mut Encoder, radii: &BorderRadii<Au>) {
+    for radius in [radii.top_left, radii.top_right, radii.bottom_right, radii.bottom_left].iter() {
+        encoder.write_au(*radius)
+    }
+}
+
+fn read_radii(decoder: &mut Decoder) -> Result<BorderRadii<Au>, DecodeError> {
+    Ok(BorderRadii {
+        top_left: try!(decoder.read_au()),
+        top_right: try!(decoder.read_au()),
+        bottom_right: try!(decoder.read_au()),
+        bottom_left: try!(decoder.read_au()),
+    })
+}
+
+fn write_side_offsets<T, F>(encoder: &mut Encoder, offsets: &SideOffsets2D<T>, mut write: F)
+                            where F: FnMut(&mut Encoder, &T) {
+    write(encoder, &offsets.top);
+    write(encoder, &offsets.right);
+    write(encoder, &offsets.bottom);
+    write(encoder, &offsets.left)
+}
+
+fn read_side_offsets<T, F>(decoder: &mut Decoder, mut read: F)
+                           -> Result<SideOffsets2D<T>, DecodeError>
+                           where F: FnMut(&mut Decoder) -> Result<T, DecodeError> {
+    Ok(SideOffsets2D {
+        top: try!(read(decoder)),
+        right: try!(read(decoder)),
+        bottom: try!(read(decoder)),
+        left: try!(read(decoder)),
+    })
+}
+
+fn write_text_run(encoder: &mut Encoder, text_run: &Arc<Box<TextRun>>) {
+    encoder.write_shared(address(&**text_run), |encoder| {
+        encoder.write_shared(address(&*text_run.text), |encoder| {
+            encoder.write_str(&text_run.text)
+        });
+        let font_template = &text_run.font_template;
+        encoder.write_shared(address(&**font_template), |encoder| {
+            encoder.write_str(&font_template.identifier);
+            write_option(encoder, &font_template.bytes(), |encoder, bytes| {
+                encoder.write_bytes(*bytes)
+            })
+        });
+        let font_key = &text_run.font_key;
+        encoder.write_str(&font_key.identifier);
+        encoder.write_au(font_key.pt_size);
+        write_option(encoder, &font_key.variations.named_instance, |encoder, index| {
+            encoder.write_u32(*index as u32)
+        });
+        write_vec(encoder, &font_key.variations.axes, |encoder, axis| {
+            encoder.write_u32(axis.tag);
+            encoder.write_i32(axis.value)
+        });
+        write_font_hinting(encoder, &font_key.hinting);
+        write_font_autohint(encoder, &font_key.autohint);
+        encoder.write_au(text_run.actual_pt_size);
+        let metrics = &text_run.font_metrics;
+        for metric in [metrics.underline_size,
+                       metrics.underline_offset,
+                       metrics.strikeout_size,
+                       metrics.strikeout_offset,
+                       metrics.leading,
+                       metrics.x_height,
+                       metrics.em_size,
+                       metrics.ascent,
+                       metrics.descent,
+                       metrics.max_advance,
+                       metrics.average_advance,
+                       metrics.line_gap].iter() {
+            encoder.write_au(*metric)
+        }
+        encoder.write_u8(text_run.synthesis.bits());
+        encoder.write_shared(address(&*text_run.glyphs), |encoder| {
+            write_vec(encoder, &*text_run.glyphs, |encoder, glyph_run| {
+                let glyph_store = &glyph_run.glyph_store;
+                encoder.write_shared(address(&**glyph_store), |encoder| {
+                    write_glyph_store(encoder, glyph_store)
+                });
+                write_char_range(encoder, &glyph_run.range);
+                encoder.write_bool(glyph_run.upright)
+            })
+        });
+        encoder.write_shared(address(&*text_run.glyph_descents), |encoder| {
+            let descents: Vec<_> = text_run.glyph_descents.iter().collect();
+            write_vec(encoder, &descents, |encoder, &(glyph, descent)| {
+                encoder.write_u32(*glyph);
+                encoder.write_au(*descent)
+            })
+        });
+        encoder.write_u8(text_run.bidi_level)
+    })
+}
+
+fn read_text_run(decoder: &mut Decoder) -> Result<Arc<Box<TextRun>>, DecodeError> {
+    decoder.read_shared(|decoder| {
+        let text = try!(decoder.read_shared(|decoder| decoder.read_string().map(Arc::new)));
+        let font_template = try!(decoder.read_shared(|decoder| {
+            let identifier = try!(decoder.read_string());
+            let bytes = try!(read_option(decoder, |decoder| {
+                decoder.read_bytes().map(|bytes| bytes.to_vec())
+            }));
+            Ok(Arc::new(FontTemplateData::new(&identifier, bytes)))
+        }));
+        let font_key = FontInstanceKey {
+            identifier: try!(decoder.read_string()),
+            pt_size: try!(decoder.read_au()),
+            variations: FontVariations {
+                named_instance: try!(read_option(decoder, |decoder| {
+                    decoder.read_u32().map(|index| index as u16)
+                })),
+                axes: try!(read_vec(decoder, |decoder| {
+                    Ok(FontVariation {
+                        tag: try!(decoder.read_u32()),
+                        value: try!(decoder.read_i32()),
+                    })
+                })),
+            },
+            hinting: try!(read_font_hinting(decoder)),
+            autohint: try!(read_font_autohint(decoder)),
+        };
+        let actual_pt_size = try!(decoder.read_au());
+        let font_metrics = FontMetrics {
+            underline_size: try!(decoder.read_au()),
+            underline_offset: try!(decoder.read_au()),
+            strikeout_size: try!(decoder.read_au()),
+            strikeout_offset: try!(decoder.read_au()),
+            leading: try!(decoder.read_au()),
+            x_height: try!(decoder.read_au()),
+            em_size: try!(decoder.read_au()),
+            ascent: try!(decoder.read_au()),
+            descent: try!(decoder.read_au()),
+            max_advance: try!(decoder.read_au()),
+            average_advance: try!(decoder.read_au()),
+            line_gap: try!(decoder.read_au()),
+        };
+        let synthesis = try!(FontSynthesisFlags::from_bits(try!(decoder.read_u8())).ok_or(
+            DecodeError::InvalidValue));
+        let glyphs = try!(decoder.read_shared(|decoder| {
+            // Each run of glyphs covers the characters after the one before it.
+            let mut run_start = CharIndex(0);
+            read_vec(decoder, |decoder| {
+                let glyph_store: Arc<GlyphStore> = try!(decoder.read_shared(|decoder| {
+                    read_glyph_store(decoder).map(Arc::new)
+                }));
+                let range = try!(read_char_range(decoder));
+                if range.begin() != run_start || range.length() != glyph_store.char_len() {
+                    return Err(DecodeError::InvalidValue)
+                }
+                run_start = range.end();
+                Ok(GlyphRun {
+                    glyph_store: glyph_store,
+                    range: range,
+                    upright: try!(decoder.read_bool()),
+                })
+            }).map(Arc::new)
+        }));
+        let glyph_descents: Arc<HashMap<_, _>> = try!(decoder.read_shared(|decoder| {
+            read_vec(decoder, |decoder| {
+                Ok((try!(decoder.read_u32()), try!(decoder.read_au())))
+            }).map(|descents| Arc::new(descents.into_iter().collect()))
+        }));
+        Ok(Arc::new(box TextRun {
+            text: text,
+            font_template: font_template,
+            font_key: font_key,
+            actual_pt_size: actual_pt_size,
+            font_metrics: font_metrics,
+            synthesis: synthesis,
+            glyphs: glyphs,
+            glyph_descents: glyph_descents,
+            bidi_level: try!(decoder.read_u8()),
+        }))
+    })
+}
+
+fn write_char_range(encoder: &mut Encoder, range: &Range<CharIndex>) {
+    encoder.write_u64(range.begin().0 as u64);
+    encoder.write_u64(range.length().0 as u64)
+}
+
+fn read_char_range(decoder: &mut Decoder) -> Result<Range<CharIndex>, DecodeError> {
+    let begin = try!(decoder.read_u64());
+    let length = try!(decoder.read_u64());
+    match begin.checked_add(length) {
+        Some(end) if end <= isize::MAX as u64 => {
+            Ok(Range::new(CharIndex(begin as isize), CharIndex(length as isize)))
+        }
+        _ => Err(DecodeError::InvalidValue),
+    }
+}
+
+/// Reads a range of the characters of the text run, which must be in it.
+fn read_run_range(decoder: &mut Decoder, text_run: &TextRun)
+                  -> Result<Range<CharIndex>, DecodeError> {
+    let range = try!(read_char_range(decoder));
+    if range.end() > text_run.char_len() {
+        return Err(DecodeError::InvalidValue)
+    }
+    Ok(range)
+}
+
+fn write_glyph_store(encoder: &mut Encoder, glyph_store: &GlyphStore) {
+    let data = glyph_store.to_data();
+    write_vec(encoder, &data.entries, |encoder, entry| encoder.write_u32(*entry));
+    write_vec(encoder, &data.detailed_glyphs, |encoder, &(id, advance, ref offset)| {
+        encoder.write_u32(id);
+        encoder.write_au(advance);
+        write_point(encoder, offset)
+    });
+    write_vec(encoder, &data.detail_lookup, |encoder, &(entry_offset, detail_offset)| {
+        encoder.write_u64(entry_offset.0 as u64);
+        encoder.write_u64(detail_offset as u64)
+    });
+    encoder.write_bool(data.is_whitespace)
+}
+
+fn read_glyph_store(decoder: &mut Decoder) -> Result<GlyphStore, DecodeError> {
+    let data = GlyphStoreData {
+        entries: try!(read_vec(decoder, |decoder| decoder.read_u32())),
+        detailed_glyphs: try!(read_vec(decoder, |decoder| {
+            Ok((try!(decoder.read_u32()), try!(decoder.read_au()), try!(read_point(decoder))))
+        })),
+        detail_lookup: try!(read_vec(decoder, |decoder| {
+            let entry_offset = try!(decoder.read_u64());
+            let detail_offset = try!(decoder.read_u64());
+            if entry_offset > isize::MAX as u64 || detail_offset > usize::MAX as u64 {
+                return Err(DecodeError::InvalidValue)
+            }
+            Ok((CharIndex(entry_offset as isize), detail_offset as usize))
+        })),
+        is_whitespace: try!(decoder.read_bool()),
+    };
+    GlyphStore::from_data(data).ok_or(DecodeError::InvalidValue)
+}
+
+fn write_image_source(encoder: &mut Encoder, source: &ImageSource) {
+    match *source {
+        ImageSource::Decoded(ref image) => {
+            encoder.write_u8(0);
+            write_image(encoder, image)
+        }
+        ImageSource::Lazy(ref lazy_image) => {
+            encoder.write_u8(1);
+            encoder.write_shared(lazy_image.data().as_ptr() as usize, |encoder| {
+                encoder.write_u32(lazy_image.width);
+                encoder.write_u32(lazy_image.height);
+                encoder.write_bool(lazy_image.is_opaque);
+                encoder.write_bytes(lazy_image.data())
+            })
+        }
+        ImageSource::Generated(ref generated_image) => {
+            encoder.write_u8(2);
+            encoder.write_str(&generated_image.name);
+            write_vec(encoder, &generated_image.arguments, |encoder, argument| {
+                encoder.write_str(argument)
+            })
+        }
+    }
+}
+
+fn read_image_source(decoder: &mut Decoder) -> Result<ImageSource, DecodeError> {
+    Ok(match try!(decoder.read_u8()) {
+        0 => ImageSource::Decoded(try!(read_image(decoder))),
+        1 => {
+            ImageSource::Lazy(try!(decoder.read_shared(|decoder| {
+                let width = try!(decoder.read_u32());
+                let height = try!(decoder.read_u32());
+                let is_opaque = try!(decoder.read_bool());
+                let data = try!(decoder.read_bytes()).to_vec();
+                let mut lazy_image = LazyImage::new(data, width, height, &decoder.lazy_images);
+                lazy_image.is_opaque = is_opaque;
+                Ok(lazy_image)
+            })))
+        }
+        2 => {
+            ImageSource::Generated(GeneratedImage {
+                name: try!(decoder.read_string()),
+                arguments: try!(read_vec(decoder, |decoder| decoder.read_string())),
+            })
+        }
+        _ => return Err(DecodeError::InvalidValue),
+    })
+}
+
+fn write_image(encoder: &mut Encoder, image: &Arc<Image>) {
+    encoder.write_shared(address(&**image), |encoder| write_image_pixels(encoder, &**image))
+}
+
+fn read_image(decoder: &mut Decoder) -> Result<Arc<Image>, DecodeError> {
+    decoder.read_shared(|decoder| read_image_pixels(decoder).map(Arc::new))
+}
+
+fn write_image_pixels(encoder: &mut Encoder, image: &Image) {
+    encoder.write_u32(image.width);
+    encoder.write_u32(image.height);
+    match image.pixels {
+        PixelsByColorType::K8(ref pixels) => {
+            encoder.write_u8(0);
+            encoder.write_bytes(pixels)
+        }
+        PixelsByColorType::KA8(ref pixels) => {
+            encoder.write_u8(1);
+            encoder.write_bytes(pixels)
+        }
+        PixelsByColorType::RGB8(ref pixels) => {
+            encoder.write_u8(2);
+            encoder.write_bytes(pixels)
+        }
+        PixelsByColorType::RGBA8(ref pixels) => {
+            encoder.write_u8(3);
+            encoder.write_bytes(pixels)
+        }
+    }
+}
+
+fn read_image_pixels(decoder: &mut Decoder) -> Result<Image, DecodeError> {
+    let width = try!(decoder.read_u32());
+    let height = try!(decoder.read_u32());
+    let kind = try!(decoder.read_u8());
+    let bytes_per_pixel = match kind {
+        0 => 1,
+        1 => 2,
+        2 => 3,
+        3 => 4,
+        _ => return Err(DecodeError::InvalidValue),
+    };
+    let pixels = try!(decoder.read_bytes()).to_vec();
+    // Painting reads as many pixels as the size says there are.
+    if pixels.len() as u64 != width as u64 * height as u64 * bytes_per_pixel {
+        return Err(DecodeError::InvalidValue)
+    }
+    let pixels = match kind {
+        0 => PixelsByColorType::K8(pixels),
+        1 => PixelsByColorType::KA8(pixels),
+        2 => PixelsByColorType::RGB8(pixels),
+        _ => PixelsByColorType::RGBA8(pixels),
+    };
+    Ok(Image::new(width, height, pixels))
+}
+
+fn read_image_orientation(decoder: &mut Decoder) -> Result<ImageOrientation, DecodeError> {
+    let quarter_turns = try!(decoder.read_u8());
+    if quarter_turns >= 4 {
+        return Err(DecodeError::InvalidValue)
+    }
+    Ok(ImageOrientation {
+        quarter_turns: quarter_turns,
+        flipped: try!(decoder.read_bool()),
+    })
+}
+
+fn write_stop(encoder: &mut Encoder, stop: &GradientStop) {
+    encoder.write_f32(stop.offset);
+    write_color(encoder, &stop.color)
+}
+
+fn read_stop(decoder: &mut Decoder) -> Result<GradientStop, DecodeError> {
+    Ok(GradientStop {
+        offset: try!(decoder.read_f32()),
+        color: try!(read_color(decoder)),
+    })
+}
+
+fn write_color(encoder: &mut Encoder, color: &Color) {
+    for component in [color.r, color.g, color.b, color.a].iter() {
+        encoder.write_f32(*component)
+    }
+}
+
+fn read_color(decoder: &mut Decoder) -> Result<Color, DecodeError> {
+    Ok(Color {
+        r: try!(decoder.read_f32()),
+        g: try!(decoder.read_f32()),
+        b: try!(decoder.read_f32()),
+        a: try!(decoder.read_f32()),
+    })
+}
+
+/// Writes the entries of the matrix a row at a time.
+fn write_matrix(encoder: &mut Encoder, transform: &Matrix4<AzFloat>) {
+    for value in transform3d::entries(transform).iter() {
+        encoder.write_f32(*value)
+    }
+}
+
+fn read_matrix(decoder: &mut Decoder) -> Result<Matrix4<AzFloat>, DecodeError> {
+    let mut m = [0.0; 16];
+    for value in m.iter_mut() {
+        *value = try!(decoder.read_f32())
+    }
+    Ok(Matrix4::new(m[0], m[1], m[2], m[3],
+                    m[4], m[5], m[6], m[7],
+                    m[8], m[9], m[10], m[11],
+                    m[12], m[13], m[14], m[15]))
+}
+
+fn write_rect(encoder: &mut Encoder, rect: &Rect<Au>) {
+    write_point(encoder, &rect.origin);
+    write_size(encoder, &rect.size)
+}
+
+fn read_rect(decoder: &mut Decoder) -> Result<Rect<Au>, DecodeError> {
+    Ok(Rect(try!(read_point(decoder)), try!(read_size(decoder))))
+}
+
+fn write_point(encoder: &mut Encoder, point: &Point2D<Au>) {
+    encoder.write_au(point.x);
+    encoder.write_au(point.y)
+}
+
+fn read_point(decoder: &mut Decoder) -> Result<Point2D<Au>, DecodeError> {
+    Ok(Point2D(try!(decoder.read_au()), try!(decoder.read_au())))
+}
+
+fn write_size(encoder: &mut Encoder, size: &Size2D<Au>) {
+    encoder.write_au(size.width);
+    encoder.write_au(size.height)
+}
+
+fn read_size(decoder: &mut Decoder) -> Result<Size2D<Au>, DecodeError> {
+    Ok(Size2D(try!(decoder.read_au()), try!(decoder.read_au())))
+}
@@ -0,0 +1,165 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Works out which parts of which layers a new display list paints differently from the one
+//! before it, so that only the tiles over them need painting again.
+//!
+//! Display lists are compared item by item, in order. An item that is added, removed or moved
+//! changes both where it was and where it is. Anything that would change the layers themselves,
+//! or that moves content in a way that the rects can't follow, makes all of them change.
+
+use display_list::{DISPLAY_LIST_SECTIONS, DisplayItem, StackingContext};
+use display_list::transform3d;
+use paint_task::PaintLayer;
+
+use geom::point::Point2D;
+use geom::rect::Rect;
+use msg::compositor_msg::LayerId;
+use util::geometry::{Au, ZERO_POINT};
+
+/// Returns the rects of each layer that `new` paints differently from `old`, relative to the top
+/// left of the layer. Returns `None` if the layers themselves differ, in which case all of them
+/// have to be painted again.
+pub fn changed_rects(old: &StackingContext, new: &StackingContext)
+                     -> Option<Vec<(LayerId, Vec<Rect<Au>>)>> {
+    let mut changes = Vec::new();
+    if diff_layer(old, new, &mut changes) {
+        Some(changes)
+    } else {
+        None
+    }
+}
+
+/// Compares two stacking contexts that have layers. Returns false if the layers differ.
+fn diff_layer(old: &StackingContext,
+              new: &StackingContext,
+              changes: &mut Vec<(LayerId, Vec<Rect<Au>>)>)
+              -> bool {
+    let (old_layer, new_layer) = match (old.compositor_layer(), new.compositor_layer()) {
+        (Some(old_layer), Some(new_layer)) => (old_layer, new_layer),
+        _ => return false,
+    };
+    if !same_layer(old_layer, new_layer) || !same_placement(old, new) {
+        return false
+    }
+
+    let mut rects = Vec::new();
+    if !diff_contents(old, new, &ZERO_POINT, &mut rects, changes) {
+        return false
+    }
+    if !rects.is_empty() {
+        let layer_origin = -new.overflow.origin;
+        changes.push((new_layer.id,
+                      rects.iter().map(|rect| rect.translate(&layer_origin)).collect()))
+    }
+    true
+}
+
+/// Adds the rects that the items of the stacking contexts, and those of the stacking contexts in
+/// them that don't have layers of their own, paint differently to `rects`. `offset` is where the
+/// stacking contexts are in the one that has the layer. Returns false if the layers differ.
+fn diff_contents(old: &StackingContext,
+                 new: &StackingContext,
+                 offset: &Point2D<Au>,
+                 rects: &mut Vec<Rect<Au>>,
+                 changes: &mut Vec<(LayerId, Vec<Rect<Au>>)>)
+                 -> bool {
+    let (old_list, new_list) = (&old.display_list, &new.display_list);
+    for section in DISPLAY_LIST_SECTIONS.iter() {
+        diff_items(old_list.get(*section), new_list.get(*section), offset, rects)
+    }
+
+    if old_list.children.len() != new_list.children.len() {
+        return false
+    }
+    for (old_kid, new_kid) in old_list.children.iter().zip(new_list.children.iter()) {
+        match (old_kid.compositor_layer(), new_kid.compositor_layer()) {
+            (Some(_), Some(_)) => {
+                if !diff_layer(&**old_kid, &**new_kid, changes) {
+                    return false
+                }
+                continue
+            }
+            (None, None) => {}
+            _ => return false,
+        }
+
+        // Content moved by a transform or seen with perspective can't be followed, so it only may
+        // stay as it is.
+        let transformed = [&old_kid.transform,
+                           &new_kid.transform,
+                           &old_kid.perspective,
+                           &new_kid.perspective].iter().any(|transform| {
+            !transform3d::is_identity(*transform)
+        });
+        if !same_placement(&**old_kid, &**new_kid) {
+            if transformed {
+                return false
+            }
+            rects.push(old_kid.overflow.translate(&(*offset + old_kid.bounds.origin)));
+            rects.push(new_kid.overflow.translate(&(*offset + new_kid.bounds.origin)));
+            continue
+        }
+
+        let kid_offset = *offset + new_kid.bounds.origin;
+        let mut kid_rects = Vec::new();
+        if !diff_contents(&**old_kid, &**new_kid, &kid_offset, &mut kid_rects, changes) {
+            return false
+        }
+        if transformed && !kid_rects.is_empty() {
+            return false
+        }
+        rects.extend(kid_rects.into_iter());
+    }
+    true
+}
+
+/// Adds the rects of the items of one section of a display list that differ to `rects`.
+fn diff_items(old_items: &[DisplayItem],
+              new_items: &[DisplayItem],
+              offset: &Point2D<Au>,
+              rects: &mut Vec<Rect<Au>>) {
+    let mut old_items = old_items.iter();
+    let mut new_items = new_items.iter();
+    loop {
+        match (old_items.next(), new_items.next()) {
+            (Some(old_item), Some(new_item)) => {
+                if !old_item.paints_same_as(new_item) {
+                    rects.push(old_item.ink_bounds().translate(offset));
+                    rects.push(new_item.ink_bounds().translate(offset));
+                }
+            }
+            (Some(item), None) | (None, Some(item)) => {
+                rects.push(item.ink_bounds().translate(offset))
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+/// Returns true if the stacking contexts are placed and composited the same way.
+fn same_placement(old: &StackingContext, new: &StackingContext) -> bool {
+    old.bounds == new.bounds &&
+        old.overflow == new.overflow &&
+        old.z_index == new.z_index &&
+        old.transform == new.transform &&
+        old.perspective == new.perspective &&
+        old.preserves_3d == new.preserves_3d &&
+        old.filters == new.filters &&
+        old.blend_mode == new.blend_mode
+}
+
+/// Returns true if the compositor needn't be told anything new about the layer.
+fn same_layer(old: &PaintLayer, new: &PaintLayer) -> bool {
+    let (old_color, new_color) = (&old.background_color, &new.background_color);
+    old.id == new.id &&
+        old.kind == new.kind &&
+        old.scroll_policy == new.scroll_policy &&
+        old.scrolling == new.scrolling &&
+        old.is_opaque == new.is_opaque &&
+        old_color.r == new_color.r &&
+        old_color.g == new_color.g &&
+        old_color.b == new_color.b &&
+        old_color.a == new_color.a
+}
@@ -0,0 +1,263 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Writes display lists out as JSON, for `--dump-display-list`, so that those of long sessions
+//! can be kept in files and looked through with other tools.
+//!
+//! Lengths are in px. Items keep the fields that tell what they paint, but not the glyphs of text
+//! or the pixels of images.
+
+use display_list::{ClippingRegion, DISPLAY_LIST_SECTIONS, DisplayItem, DisplayList};
+use display_list::StackingContext;
+use display_list::transform3d;
+use display_list::optimizer::DisplayListOptimizer;
+
+use azure::AzFloat;
+use azure::azure_hl::{Color, GradientStop};
+use collections::BTreeMap;
+use geom::matrix::Matrix4;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use rustc_serialize::json::Json;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use util::geometry::Au;
+
+/// Writes the stacking context and everything in it to the file at `path`.
+pub fn write_stacking_context(stacking_context: &StackingContext, path: &Path) -> io::Result<()> {
+    write(&stacking_context_to_json(stacking_context), path)
+}
+
+/// Writes the display list, without the stacking contexts in it, to the file at `path`.
+pub fn write_display_list(display_list: &DisplayList, path: &Path) -> io::Result<()> {
+    write(&display_list_to_json(display_list), path)
+}
+
+/// Writes the display list of the stacking context, optimized for the tile that shows
+/// `tile_bounds` of it, with the stacking contexts in it that the tile shows, to the file at
+/// `path`.
+pub fn write_for_tile(stacking_context: &StackingContext, tile_bounds: &Rect<AzFloat>, path: &Path)
+                      -> io::Result<()> {
+    let optimizer = DisplayListOptimizer::new(tile_bounds);
+    let display_list = optimizer.optimize(&*stacking_context.display_list);
+    let mut object = match display_list_to_json(&display_list) {
+        Json::Object(object) => object,
+        _ => unreachable!(),
+    };
+    object.insert("tile_bounds".to_owned(),
+                  Json::Array(vec![tile_bounds.origin.x,
+                                   tile_bounds.origin.y,
+                                   tile_bounds.size.width,
+                                   tile_bounds.size.height].into_iter().map(|value| {
+                      Json::F64(value as f64)
+                  }).collect()));
+    object.insert("children".to_owned(),
+                  Json::Array(display_list.children.iter().map(|kid| {
+                      stacking_context_to_json(&**kid)
+                  }).collect()));
+    write(&Json::Object(object), path)
+}
+
+fn write(json: &Json, path: &Path) -> io::Result<()> {
+    let mut file = try!(File::create(path));
+    write!(&mut file, "{}\n", json.pretty())
+}
+
+pub fn stacking_context_to_json(stacking_context: &StackingContext) -> Json {
+    let mut object = match display_list_to_json(&*stacking_context.display_list) {
+        Json::Object(object) => object,
+        _ => unreachable!(),
+    };
+    object.insert("bounds".to_owned(), rect_to_json(&stacking_context.bounds));
+    object.insert("overflow".to_owned(), rect_to_json(&stacking_context.overflow));
+    object.insert("z_index".to_owned(), Json::I64(stacking_context.z_index as i64));
+    object.insert("transform".to_owned(), matrix_to_json(&stacking_context.transform));
+    object.insert("perspective".to_owned(), matrix_to_json(&stacking_context.perspective));
+    object.insert("preserves_3d".to_owned(), Json::Boolean(stacking_context.preserves_3d));
+    object.insert("filters".to_owned(), Json::String(format!("{:?}", stacking_context.filters)));
+    object.insert("blend_mode".to_owned(),
+                  Json::String(format!("{:?}", stacking_context.blend_mode)));
+    object.insert("layer".to_owned(), match stacking_context.layer {
+        Some(ref layer) => {
+            let mut layer_object = BTreeMap::new();
+            layer_object.insert("id".to_owned(), Json::String(format!("{:?}", layer.id)));
+            layer_object.insert("kind".to_owned(), Json::String(format!("{:?}", layer.kind)));
+            layer_object.insert("background_color".to_owned(),
+                                color_to_json(&layer.background_color));
+            Json::Object(layer_object)
+        }
+        None => Json::Null,
+    });
+    object.insert("children".to_owned(),
+                  Json::Array(stacking_context.display_list.children.iter().map(|kid| {
+                      stacking_context_to_json(&**kid)
+                  }).collect()));
+    Json::Object(object)
+}
+
+fn display_list_to_json(display_list: &DisplayList) -> Json {
+    let mut object = BTreeMap::new();
+    for section in DISPLAY_LIST_SECTIONS.iter() {
+        object.insert(section.name().to_owned(), items_to_json(display_list.get(*section)));
+    }
+    Json::Object(object)
+}
+
+fn items_to_json(items: &[DisplayItem]) -> Json {
+    Json::Array(items.iter().map(item_to_json).collect())
+}
+
+fn item_to_json(item: &DisplayItem) -> Json {
+    let mut object = BTreeMap::new();
+    let base = item.base();
+    object.insert("bounds".to_owned(), rect_to_json(&base.bounds));
+    object.insert("clip".to_owned(), clip_to_json(&base.clip));
+    object.insert("color_space".to_owned(), Json::String(format!("{:?}", base.color_space)));
+    object.insert("node".to_owned(), Json::String(format!("{:x}", base.metadata.node.id())));
+    object.insert("node_index".to_owned(), Json::U64(base.id.index as u64));
+    object.insert("tag".to_owned(), Json::U64(base.metadata.tag));
+
+    let kind = match *item {
+        DisplayItem::SolidColorClass(ref solid_color) => {
+            object.insert("color".to_owned(), color_to_json(&solid_color.color));
+            "SolidColor"
+        }
+        DisplayItem::TextClass(ref text) => {
+            let range = &text.range;
+            let string = text.text_run.text.slice_chars(range.begin().to_usize(),
+                                                        range.end().to_usize());
+            object.insert("text".to_owned(), Json::String(string.to_owned()));
+            object.insert("color".to_owned(), color_to_json(&text.text_color));
+            object.insert("baseline_origin".to_owned(), point_to_json(&text.baseline_origin));
+            object.insert("orientation".to_owned(),
+                          Json::String(format!("{:?}", text.orientation)));
+            object.insert("blur_radius".to_owned(), au_to_json(text.blur_radius));
+            object.insert("shadows".to_owned(), Json::Array(text.shadows.iter().map(|shadow| {
+                let mut object = BTreeMap::new();
+                object.insert("offset".to_owned(), point_to_json(&shadow.offset));
+                object.insert("blur_radius".to_owned(), au_to_json(shadow.blur_radius));
+                object.insert("color".to_owned(), color_to_json(&shadow.color));
+                Json::Object(object)
+            }).collect()));
+            object.insert("decorations".to_owned(),
+                          Json::Array(text.decorations.iter().map(|decoration| {
+                let mut object = BTreeMap::new();
+                object.insert("line".to_owned(), Json::String(format!("{:?}", decoration.line)));
+                object.insert("style".to_owned(),
+                              Json::String(format!("{:?}", decoration.style)));
+                object.insert("color".to_owned(), color_to_json(&decoration.color));
+                object.insert("skip_ink".to_owned(), Json::Boolean(decoration.skip_ink));
+                Json::Object(object)
+            }).collect()));
+            "Text"
+        }
+        DisplayItem::ImageClass(ref image) => {
+            object.insert("stretch_size".to_owned(),
+                          Json::Array(vec![au_to_json(image.stretch_size.width),
+                                           au_to_json(image.stretch_size.height)]));
+            object.insert("animated".to_owned(), Json::Boolean(image.animation.is_some()));
+            "Image"
+        }
+        DisplayItem::BorderClass(ref border) => {
+            let widths = &border.border_widths;
+            object.insert("widths".to_owned(),
+                          Json::Array(vec![au_to_json(widths.top),
+                                           au_to_json(widths.right),
+                                           au_to_json(widths.bottom),
+                                           au_to_json(widths.left)]));
+            object.insert("colors".to_owned(),
+                          Json::Array(vec![color_to_json(&border.color.top),
+                                           color_to_json(&border.color.right),
+                                           color_to_json(&border.color.bottom),
+                                           color_to_json(&border.color.left)]));
+            object.insert("style".to_owned(), Json::String(format!("{:?}", border.style)));
+            "Border"
+        }
+        DisplayItem::GradientClass(ref gradient) => {
+            object.insert("start_point".to_owned(), point_to_json(&gradient.start_point));
+            object.insert("end_point".to_owned(), point_to_json(&gradient.end_point));
+            object.insert("stops".to_owned(), stops_to_json(&gradient.stops));
+            object.insert("repeating".to_owned(), Json::Boolean(gradient.repeating));
+            "Gradient"
+        }
+        DisplayItem::RadialGradientClass(ref gradient) => {
+            object.insert("center".to_owned(), point_to_json(&gradient.center));
+            let radius = vec![au_to_json(gradient.radius.width),
+                              au_to_json(gradient.radius.height)];
+            object.insert("radius".to_owned(), Json::Array(radius));
+            object.insert("stops".to_owned(), stops_to_json(&gradient.stops));
+            "RadialGradient"
+        }
+        DisplayItem::LineClass(ref line) => {
+            object.insert("color".to_owned(), color_to_json(&line.color));
+            object.insert("style".to_owned(), Json::String(format!("{:?}", line.style)));
+            "Line"
+        }
+        DisplayItem::BoxShadowClass(ref box_shadow) => {
+            object.insert("box_bounds".to_owned(), rect_to_json(&box_shadow.box_bounds));
+            object.insert("offset".to_owned(), point_to_json(&box_shadow.offset));
+            object.insert("color".to_owned(), color_to_json(&box_shadow.color));
+            object.insert("blur_radius".to_owned(), au_to_json(box_shadow.blur_radius));
+            object.insert("spread_radius".to_owned(), au_to_json(box_shadow.spread_radius));
+            object.insert("clip_mode".to_owned(),
+                          Json::String(format!("{:?}", box_shadow.clip_mode)));
+            "BoxShadow"
+        }
+        DisplayItem::ExternalTextureClass(ref texture) => {
+            object.insert("texture".to_owned(), Json::U64(texture.texture.0 as u64));
+            "ExternalTexture"
+        }
+        DisplayItem::IframeClass(ref iframe) => {
+            object.insert("pipeline".to_owned(), Json::U64(iframe.pipeline_id.0 as u64));
+            object.insert("subpage".to_owned(), Json::U64(iframe.subpage_id.0 as u64));
+            "Iframe"
+        }
+    };
+    object.insert("kind".to_owned(), Json::String(kind.to_owned()));
+    Json::Object(object)
+}
+
+fn clip_to_json(clip: &ClippingRegion) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("main".to_owned(), rect_to_json(&clip.main));
+    object.insert("complex".to_owned(), Json::Array(clip.complex.iter().map(|complex| {
+        rect_to_json(&complex.rect)
+    }).collect()));
+    Json::Object(object)
+}
+
+fn rect_to_json(rect: &Rect<Au>) -> Json {
+    Json::Array(vec![au_to_json(rect.origin.x),
+                     au_to_json(rect.origin.y),
+                     au_to_json(rect.size.width),
+                     au_to_json(rect.size.height)])
+}
+
+/// Writes the entries of the matrix a row at a time.
+fn matrix_to_json(transform: &Matrix4<AzFloat>) -> Json {
+    Json::Array(transform3d::entries(transform).iter().map(|value| {
+        Json::F64(*value as f64)
+    }).collect())
+}
+
+fn stops_to_json(stops: &[GradientStop]) -> Json {
+    Json::Array(stops.iter().map(|stop| {
+        Json::Array(vec![Json::F64(stop.offset as f64), color_to_json(&stop.color)])
+    }).collect())
+}
+
+fn point_to_json(point: &Point2D<Au>) -> Json {
+    Json::Array(vec![au_to_json(point.x), au_to_json(point.y)])
+}
+
+fn au_to_json(length: Au) -> Json {
+    Json::F64(length.to_subpx())
+}
+
+fn color_to_json(color: &Color) -> Json {
+    Json::Array(vec![color.r, color.g, color.b, color.a].into_iter().map(|component| {
+        Json::F64(component as f64)
+    }).collect())
+}
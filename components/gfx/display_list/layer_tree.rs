@@ -0,0 +1,129 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The tree of layers that a tree of stacking contexts is painted into, for devtools, for
+//! `-Z dump-layer-tree`, and for tests of which stacking contexts get layers.
+//!
+//! Each layer sits under the layer of the nearest stacking context around it that has one, in the
+//! order that the compositor is told about them in.
+
+use display_list::StackingContext;
+use display_list::transform3d;
+use paint_task::LayerScrolling;
+
+use azure::AzFloat;
+use azure::azure_hl::Color;
+use geom::matrix::Matrix4;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use msg::compositor_msg::{LayerId, LayerKind, ScrollPolicy};
+use std::cmp;
+use util::geometry::{Au, ZERO_POINT};
+
+/// A layer, and the layers in it.
+#[derive(Clone)]
+pub struct LayerTreeNode {
+    pub id: LayerId,
+    pub kind: LayerKind,
+    pub scroll_policy: ScrollPolicy,
+    /// The position and size of the layer on the page, which starts at the top left of the
+    /// overflow of its stacking context.
+    pub bounds: Rect<Au>,
+    /// The transform of the stacking context of the layer, relative to the one it is in.
+    pub transform: Matrix4<AzFloat>,
+    /// How the layer scrolls its own content, if it is a scroll layer.
+    pub scrolling: Option<LayerScrolling>,
+    pub background_color: Color,
+    pub is_opaque: bool,
+    /// The number of bytes that tiles covering all of the layer at one device pixel per px take.
+    pub memory: usize,
+    pub children: Vec<LayerTreeNode>,
+}
+
+impl LayerTreeNode {
+    /// Returns the layer with the given ID among this one and its descendants.
+    pub fn find(&self, id: LayerId) -> Option<&LayerTreeNode> {
+        if self.id == id {
+            return Some(self)
+        }
+        self.children.iter().filter_map(|child| child.find(id)).next()
+    }
+
+    /// Returns the number of bytes that this layer and its descendants take.
+    pub fn total_memory(&self) -> usize {
+        self.children.iter().fold(self.memory, |memory, child| memory + child.total_memory())
+    }
+
+    pub fn print(&self, indentation: String) {
+        println!("{} {:?} {:?}: bounds {:?}, memory {}, opaque {}, {:?}{}",
+                 indentation,
+                 self.kind,
+                 self.id,
+                 self.bounds,
+                 self.memory,
+                 self.is_opaque,
+                 self.scroll_policy,
+                 if transform3d::is_identity(&self.transform) { "" } else { ", transformed" });
+        if let Some(ref scrolling) = self.scrolling {
+            println!("{}   scrolls {:?}: content size {:?}, viewport {:?}",
+                     indentation,
+                     scrolling.scroll_id,
+                     scrolling.content_size,
+                     scrolling.viewport);
+        }
+
+        let indentation = format!("{}  ", indentation);
+        for child in self.children.iter() {
+            child.print(indentation.clone())
+        }
+    }
+}
+
+/// Returns the layers of the tree of stacking contexts, outermost first.
+pub fn layer_tree(root: &StackingContext) -> Vec<LayerTreeNode> {
+    let mut layers = Vec::new();
+    build(&mut layers, root, &ZERO_POINT);
+    layers
+}
+
+fn build(layers: &mut Vec<LayerTreeNode>,
+         stacking_context: &StackingContext,
+         page_position: &Point2D<Au>) {
+    let page_position = stacking_context.bounds.origin + *page_position;
+    let paint_layer = match stacking_context.compositor_layer() {
+        Some(paint_layer) => paint_layer,
+        None => {
+            for kid in stacking_context.display_list.children.iter() {
+                build(layers, &**kid, &page_position)
+            }
+            return
+        }
+    };
+
+    let overflow = stacking_context.overflow;
+    let mut children = Vec::new();
+    for kid in stacking_context.display_list.children.iter() {
+        build(&mut children, &**kid, &page_position)
+    }
+    layers.push(LayerTreeNode {
+        id: paint_layer.id,
+        kind: paint_layer.kind,
+        scroll_policy: paint_layer.scroll_policy,
+        bounds: Rect(page_position + overflow.origin, overflow.size),
+        transform: stacking_context.transform,
+        scrolling: paint_layer.scrolling,
+        background_color: paint_layer.background_color,
+        is_opaque: paint_layer.is_opaque,
+        memory: memory(&overflow.size),
+        children: children,
+    })
+}
+
+/// Returns the number of bytes of BGRA pixels that cover an area of the given size.
+fn memory(size: &Size2D<Au>) -> usize {
+    let width = cmp::max(size.width.to_nearest_px(), 0) as usize;
+    let height = cmp::max(size.height.to_nearest_px(), 0) as usize;
+    width * height * 4
+}
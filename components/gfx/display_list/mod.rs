@@ -16,8 +16,17 @@
 
 #![deny(unsafe_code)]
 
+use backends::fallback;
+use color;
 use display_list::optimizer::DisplayListOptimizer;
-use paint_context::{PaintContext, ToAzureRect};
+use filters;
+use image_generator::{self, GeneratedImage, ImageGeneratorRegistry};
+use item_costs;
+use paint_backend::{self, PaintBackend};
+use paint_markers;
+use paint_order;
+use skipped_painting;
+use paint_context::ToAzureRect;
 use self::DisplayItem::*;
 use self::DisplayItemIterator::*;
 use text::glyph::CharIndex;
@@ -26,24 +35,39 @@ use text::TextRun;
 use azure::azure::AzFloat;
 use azure::azure_hl::{Color};
 
-use collections::linked_list::{self, LinkedList};
-use geom::{Point2D, Rect, SideOffsets2D, Size2D, Matrix2D};
+use geom::{Point2D, Rect, SideOffsets2D, Size2D};
+use geom::matrix::{self, Matrix4};
+use geom::matrix2d::Matrix2D;
 use geom::approxeq::ApproxEq;
 use geom::num::Zero;
 use libc::uintptr_t;
 use paint_task::PaintLayer;
-use msg::compositor_msg::LayerId;
-use net_traits::image::base::Image;
-use util::opts;
+use msg::compositor_msg::{ExternalTextureId, LayerId, LayerKind, ScrollPolicy};
+use msg::constellation_msg::{PipelineId, SubpageId};
+use net_traits::image::base::{Image, ImageFrames, LazyImage};
+use profile_traits::time::ProfilerCategory;
+use util::opts::{self, ColorSpace, PaintingWork};
 use util::cursor::Cursor;
-use util::linked_list::prepend_from;
-use util::geometry::{self, Au, MAX_RECT, ZERO_RECT};
+use util::geometry::{self, Au, MAX_RECT, ZERO_POINT, ZERO_RECT};
 use util::mem::HeapSizeOf;
 use util::range::Range;
 use util::smallvec::{SmallVec, SmallVec8};
+use std::cmp::max;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::fmt;
-use std::slice::Iter;
-use std::sync::Arc;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::iter::{FromIterator, IntoIterator, repeat};
+use std::mem;
+use std::num::Float;
+use std::path::Path;
+use std::slice::{self, Iter};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::u32;
+use std::usize;
+use std::vec;
+use time;
 use style::computed_values::{border_style, cursor, filter, image_rendering, mix_blend_mode};
 use style::computed_values::{pointer_events};
 use style::properties::ComputedValues;
@@ -52,7 +76,17 @@ use style::properties::ComputedValues;
 // layout to use.
 pub use azure::azure_hl::GradientStop;
 
+// Frames are decoded with the images they belong to.
+pub use net_traits::image::base::ImageFrame;
+
+pub mod binary;
+pub mod invalidation;
+pub mod json;
+pub mod layer_tree;
 pub mod optimizer;
+pub mod squashing;
+pub mod transform3d;
+pub mod visitor;
 
 /// The factor that we multiply the blur radius by in order to inflate the boundaries of display
 /// items that involve a blur. This ensures that the display item boundaries include all the ink.
@@ -64,7 +98,7 @@ pub static BLUR_INFLATION_FACTOR: i32 = 3;
 /// Because the script task's GC does not trace layout, node data cannot be safely stored in layout
 /// data structures. Also, layout code tends to be faster when the DOM is not being accessed, for
 /// locality reasons. Using `OpaqueNode` enforces this invariant.
-#[derive(Clone, PartialEq, Copy, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Copy, Debug)]
 pub struct OpaqueNode(pub uintptr_t);
 
 impl OpaqueNode {
@@ -75,24 +109,53 @@ impl OpaqueNode {
     }
 }
 
-/// Display items that make up a stacking context. "Steps" here refer to the steps in CSS 2.1
-/// Appendix E.
-///
-/// TODO(pcwalton): We could reduce the size of this structure with a more "skip list"-like
-/// structure, omitting several pointers and lengths.
-pub struct DisplayList {
-    /// The border and backgrounds for the root of this stacking context: steps 1 and 2.
-    pub background_and_borders: LinkedList<DisplayItem>,
+/// The sections that the display items of a stacking context are kept in. "Steps" here refer to
+/// the steps in CSS 2.1 Appendix E.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisplayListSection {
+    /// The border and backgrounds for the root of the stacking context: steps 1 and 2.
+    BackgroundAndBorders,
     /// Borders and backgrounds for block-level descendants: step 4.
-    pub block_backgrounds_and_borders: LinkedList<DisplayItem>,
+    BlockBackgroundsAndBorders,
     /// Floats: step 5. These are treated as pseudo-stacking contexts.
-    pub floats: LinkedList<DisplayItem>,
+    Floats,
     /// All other content.
-    pub content: LinkedList<DisplayItem>,
+    Content,
     /// Outlines: step 10.
-    pub outlines: LinkedList<DisplayItem>,
+    Outlines,
+}
+
+/// All of the sections of a display list, in the order that they are painted in.
+pub static DISPLAY_LIST_SECTIONS: [DisplayListSection; 5] = [
+    DisplayListSection::BackgroundAndBorders,
+    DisplayListSection::BlockBackgroundsAndBorders,
+    DisplayListSection::Floats,
+    DisplayListSection::Content,
+    DisplayListSection::Outlines,
+];
+
+impl DisplayListSection {
+    /// Returns the name of the section, for debugging output.
+    pub fn name(self) -> &'static str {
+        match self {
+            DisplayListSection::BackgroundAndBorders => "background_and_borders",
+            DisplayListSection::BlockBackgroundsAndBorders => "block_backgrounds_and_borders",
+            DisplayListSection::Floats => "floats",
+            DisplayListSection::Content => "content",
+            DisplayListSection::Outlines => "outlines",
+        }
+    }
+}
+
+/// Display items that make up a stacking context.
+///
+/// The items of each section are kept one after the other, so an item keeps its index in its
+/// section for as long as the items before it stay.
+pub struct DisplayList {
+    /// The items of each section, in the order of `DISPLAY_LIST_SECTIONS`.
+    sections: [Vec<DisplayItem>; 5],
     /// Child stacking contexts.
-    pub children: LinkedList<Arc<StackingContext>>,
+    pub children: Vec<Arc<StackingContext>>,
 }
 
 impl DisplayList {
@@ -100,126 +163,327 @@ impl DisplayList {
     #[inline]
     pub fn new() -> DisplayList {
         DisplayList {
-            background_and_borders: LinkedList::new(),
-            block_backgrounds_and_borders: LinkedList::new(),
-            floats: LinkedList::new(),
-            content: LinkedList::new(),
-            outlines: LinkedList::new(),
-            children: LinkedList::new(),
+            sections: [Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns a display list with copies of the items of this one and with the given stacking
+    /// contexts in it.
+    pub fn clone_with_children(&self, children: Vec<Arc<StackingContext>>)
+                               -> DisplayList {
+        DisplayList {
+            sections: [
+                self.sections[0].clone(),
+                self.sections[1].clone(),
+                self.sections[2].clone(),
+                self.sections[3].clone(),
+                self.sections[4].clone(),
+            ],
+            children: children,
         }
     }
 
+    /// Returns the items of one section of this display list.
+    #[inline]
+    pub fn get(&self, section: DisplayListSection) -> &[DisplayItem] {
+        &self.sections[section as usize]
+    }
+
+    /// Returns the items of one section of this display list, so that they can be changed.
+    #[inline]
+    pub fn get_mut(&mut self, section: DisplayListSection) -> &mut Vec<DisplayItem> {
+        &mut self.sections[section as usize]
+    }
+
     /// Appends all display items from `other` into `self`, preserving stacking order and emptying
     /// `other` in the process.
     #[inline]
     pub fn append_from(&mut self, other: &mut DisplayList) {
-        self.background_and_borders.append(&mut other.background_and_borders);
-        self.block_backgrounds_and_borders.append(&mut other.block_backgrounds_and_borders);
-        self.floats.append(&mut other.floats);
-        self.content.append(&mut other.content);
-        self.outlines.append(&mut other.outlines);
+        for (section, other_section) in self.sections.iter_mut().zip(other.sections.iter_mut()) {
+            section.append(other_section)
+        }
         self.children.append(&mut other.children);
     }
 
-    /// Merges all display items from all non-float stacking levels to the `float` stacking level.
-    #[inline]
-    pub fn form_float_pseudo_stacking_context(&mut self) {
-        prepend_from(&mut self.floats, &mut self.outlines);
-        prepend_from(&mut self.floats, &mut self.content);
-        prepend_from(&mut self.floats, &mut self.block_backgrounds_and_borders);
-        prepend_from(&mut self.floats, &mut self.background_and_borders);
+    /// Returns an iterator over the items of this display list, section by section in the order
+    /// that they are painted in. The items of the stacking contexts in it aren't included.
+    pub fn iter<'a>(&'a self) -> DisplayItems<'a> {
+        // The sections are taken from the end.
+        DisplayItems {
+            sections: self.sections.iter().rev().map(|section| section.iter()).collect(),
+        }
     }
 
-    /// Returns a list of all items in this display list concatenated together. This is extremely
-    /// inefficient and should only be used for debugging.
-    pub fn all_display_items(&self) -> Vec<DisplayItem> {
-        let mut result = Vec::new();
-        for display_item in self.background_and_borders.iter() {
-            result.push((*display_item).clone())
-        }
-        for display_item in self.block_backgrounds_and_borders.iter() {
-            result.push((*display_item).clone())
-        }
-        for display_item in self.floats.iter() {
-            result.push((*display_item).clone())
+    /// Returns an iterator over the items of this display list that lets them be changed, section
+    /// by section in the order that they are painted in. The items of the stacking contexts in it
+    /// aren't included.
+    pub fn iter_mut<'a>(&'a mut self) -> DisplayItemsMut<'a> {
+        // The sections are taken from the end.
+        DisplayItemsMut {
+            sections: self.sections.iter_mut().rev().map(|section| section.iter_mut()).collect(),
         }
-        for display_item in self.content.iter() {
-            result.push((*display_item).clone())
-        }
-        for display_item in self.outlines.iter() {
-            result.push((*display_item).clone())
-        }
-        result
     }
 
-    // Print the display list. Only makes sense to call it after performing reflow.
-    pub fn print_items(&self, mut indentation: String) {
-        let min_length = 4;
-        // We cover the case of an empty string.
-        if indentation.len() == 0 {
-            indentation = String::from_str("####");
-        }
+    /// Returns the items of this display list that may paint some of `rect`, in the order that they
+    /// are painted in. The items of the stacking contexts in it aren't included.
+    pub fn items_intersecting<'a>(&'a self, rect: &Rect<Au>) -> Vec<&'a DisplayItem> {
+        self.iter().filter(|item| item.might_intersect_rect(rect)).collect()
+    }
 
-        // We grow the indentation by 4 characters if needed.
-        // I wish to push it all as a slice, but it won't work if the string is a single char.
-        while indentation.len() < min_length {
-            let c = indentation.char_at(0);
-            indentation.push(c);
+    /// Calls `callback` with each item of this display list, in the order of `iter_mut()`.
+    pub fn for_each_item_mut<F>(&mut self, mut callback: F) where F: FnMut(&mut DisplayItem) {
+        for item in self.iter_mut() {
+            callback(item)
         }
+    }
 
-        // Closures are so nice!
-        let doit = |items: &Vec<DisplayItem>| {
-            for item in items.iter() {
-                match *item {
-                    DisplayItem::SolidColorClass(ref solid_color) => {
-                        println!("{:?} SolidColor. {:?}", indentation, solid_color.base.bounds)
-                    }
-                    DisplayItem::TextClass(ref text) => {
-                        println!("{:?} Text. {:?}", indentation, text.base.bounds)
-                    }
-                    DisplayItem::ImageClass(ref image) => {
-                        println!("{:?} Image. {:?}", indentation, image.base.bounds)
-                    }
-                    DisplayItem::BorderClass(ref border) => {
-                        println!("{:?} Border. {:?}", indentation, border.base.bounds)
-                    }
-                    DisplayItem::GradientClass(ref gradient) => {
-                        println!("{:?} Gradient. {:?}", indentation, gradient.base.bounds)
-                    }
-                    DisplayItem::LineClass(ref line) => {
-                        println!("{:?} Line. {:?}", indentation, line.base.bounds)
-                    }
-                    DisplayItem::BoxShadowClass(ref box_shadow) => {
-                        println!("{:?} Box_shadow. {:?}", indentation, box_shadow.base.bounds)
-                    }
-                }
+    /// Returns the union of the rects that the items of this display list and of the stacking
+    /// contexts in it may paint in, in the coordinate system of the stacking context of this list,
+    /// or the zero rect if they paint nothing. Unlike the overflow that layout works out, this
+    /// comes from the items themselves.
+    pub fn bounds(&self) -> Rect<Au> {
+        self.ink_bounds().unwrap_or(ZERO_RECT)
+    }
+
+    fn ink_bounds(&self) -> Option<Rect<Au>> {
+        let item_bounds = self.iter().map(|item| item.ink_bounds());
+        let kid_bounds = self.children.iter().filter_map(|kid| {
+            kid.display_list.ink_bounds().and_then(|kid_bounds| {
+                // Stacking contexts that are partly behind the viewer are left out.
+                let kid_bounds = filters::inflate_rect(&kid.filters, &kid_bounds);
+                transform3d::transform_rect(&kid.transform, &kid_bounds.to_subpx_azure_rect())
+            }).map(|kid_bounds| {
+                geometry::f32_rect_to_au_rect(kid_bounds).translate(&kid.bounds.origin)
+            })
+        });
+        item_bounds.chain(kid_bounds).fold(None, |bounds, rect| {
+            match bounds {
+                Some(bounds) => Some(rect.union(&bounds)),
+                None => Some(rect),
             }
-            println!("\n");
-        };
+        })
+    }
+
+    /// Moves everything in this display list by `delta`: the bounds, clips and points of its items
+    /// and the bounds of the stacking contexts in it, whose contents are relative to them.
+    pub fn translate(&mut self, delta: &Point2D<Au>) {
+        for item in self.iter_mut() {
+            item.translate(delta)
+        }
+        self.children = self.children.iter().map(|kid| {
+            let kid_list = &kid.display_list;
+            let display_list = box kid_list.clone_with_children(kid_list.children.clone());
+            Arc::new(StackingContext::new(display_list,
+                                          &kid.bounds.translate(delta),
+                                          &kid.overflow,
+                                          kid.z_index,
+                                          &kid.transform,
+                                          &kid.perspective,
+                                          kid.preserves_3d,
+                                          kid.filters.clone(),
+                                          kid.blend_mode,
+                                          kid.layer.clone(),
+                                          kid.device_pixels_per_px))
+        }).collect()
+    }
 
-        doit(&(self.all_display_items()));
-        if self.children.len() != 0 {
-            println!("{} Children stacking contexts list length: {}",
-                     indentation,
-                     self.children.len());
-            for sublist in self.children.iter() {
-                sublist.display_list.print_items(indentation.clone()+&indentation[0..min_length]);
+    /// Merges all display items from all non-float stacking levels to the `float` stacking level.
+    #[inline]
+    pub fn form_float_pseudo_stacking_context(&mut self) {
+        let mut floats = Vec::new();
+        for section in DISPLAY_LIST_SECTIONS.iter() {
+            if *section != DisplayListSection::Floats {
+                floats.append(self.get_mut(*section))
             }
         }
+        floats.append(self.get_mut(DisplayListSection::Floats));
+        *self.get_mut(DisplayListSection::Floats) = floats
+    }
+
+    /// Returns a list of all items in this display list concatenated together. This should only be
+    /// used for debugging.
+    pub fn all_display_items<'a>(&'a self) -> Vec<&'a DisplayItem> {
+        self.iter().collect()
+    }
+
+    /// Returns something whose `Debug` output is this display list, with the stacking contexts
+    /// in it shown at most `max_depth` deep.
+    pub fn tree<'a>(&'a self, max_depth: usize) -> DisplayListTree<'a> {
+        DisplayListTree {
+            display_list: self,
+            max_depth: max_depth,
+        }
     }
 }
 
 impl HeapSizeOf for DisplayList {
     fn heap_size_of_children(&self) -> usize {
-        self.background_and_borders.heap_size_of_children() +
-            self.block_backgrounds_and_borders.heap_size_of_children() +
-            self.floats.heap_size_of_children() +
-            self.content.heap_size_of_children() +
-            self.outlines.heap_size_of_children() +
+        self.sections.iter().fold(0, |size, section| size + section.heap_size_of_children()) +
             self.children.heap_size_of_children()
     }
 }
 
+/// Adds each item to the end of the section that it is paired with.
+impl Extend<(DisplayListSection, DisplayItem)> for DisplayList {
+    fn extend<T>(&mut self, items: T)
+                 where T: IntoIterator<Item=(DisplayListSection, DisplayItem)> {
+        for (section, item) in items.into_iter() {
+            self.get_mut(section).push(item)
+        }
+    }
+}
+
+impl FromIterator<(DisplayListSection, DisplayItem)> for DisplayList {
+    fn from_iter<T>(items: T) -> DisplayList
+                    where T: IntoIterator<Item=(DisplayListSection, DisplayItem)> {
+        let mut display_list = DisplayList::new();
+        display_list.extend(items);
+        display_list
+    }
+}
+
+/// Adds the stacking contexts to the end of the children.
+impl Extend<Arc<StackingContext>> for DisplayList {
+    fn extend<T>(&mut self, children: T) where T: IntoIterator<Item=Arc<StackingContext>> {
+        self.children.extend(children.into_iter())
+    }
+}
+
+/// Takes the display list apart into its items and the stacking contexts in it, in the order of
+/// the steps of CSS 2.1 § E.2, with the stacking contexts in the order of their z-indices, so that
+/// it can be turned into something else without copying the items.
+impl IntoIterator for DisplayList {
+    type Item = PaintOrderEntry;
+    type IntoIter = IntoPaintOrder;
+
+    fn into_iter(self) -> IntoPaintOrder {
+        let DisplayList {
+            mut sections,
+            children,
+        } = self;
+        let mut children = children;
+        children.sort_by(|this, other| this.z_index.cmp(&other.z_index));
+        let (negative_children, positive_children): (Vec<_>, Vec<_>) =
+            children.into_iter().partition(|kid| kid.z_index < 0);
+        let mut take = |section: DisplayListSection| {
+            PaintOrderStage::Items(mem::replace(&mut sections[section as usize],
+                                                Vec::new()).into_iter())
+        };
+
+        // The stages are taken from the end.
+        let stages = vec![
+            take(DisplayListSection::Outlines),
+            PaintOrderStage::StackingContexts(positive_children.into_iter()),
+            take(DisplayListSection::Content),
+            take(DisplayListSection::Floats),
+            take(DisplayListSection::BlockBackgroundsAndBorders),
+            PaintOrderStage::StackingContexts(negative_children.into_iter()),
+            take(DisplayListSection::BackgroundAndBorders),
+        ];
+        IntoPaintOrder {
+            stages: stages,
+        }
+    }
+}
+
+/// Shows a display list as an indented tree, as far as a depth of stacking contexts.
+pub struct DisplayListTree<'a> {
+    display_list: &'a DisplayList,
+    max_depth: usize,
+}
+
+impl<'a> fmt::Debug for DisplayListTree<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_display_list(f, self.display_list, 0, self.max_depth)
+    }
+}
+
+impl fmt::Debug for DisplayList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_display_list(f, self, 0, usize::MAX)
+    }
+}
+
+/// Writes the sections of the display list that have items, then the stacking contexts in it,
+/// each indented by `depth`.
+fn write_display_list(f: &mut fmt::Formatter,
+                      display_list: &DisplayList,
+                      depth: usize,
+                      max_depth: usize)
+                      -> fmt::Result {
+    let indentation: String = repeat("    ").take(depth).collect();
+    for section in DISPLAY_LIST_SECTIONS.iter() {
+        let items = display_list.get(*section);
+        if items.is_empty() {
+            continue
+        }
+        try!(write!(f, "{}{}:\n", indentation, section.name()));
+        for item in items.iter() {
+            try!(write!(f, "{}  {:?}\n", indentation, item));
+        }
+    }
+
+    if display_list.children.is_empty() {
+        return Ok(())
+    }
+    if depth >= max_depth {
+        return write!(f,
+                      "{}({} stacking contexts not shown)\n",
+                      indentation,
+                      display_list.children.len())
+    }
+    for kid in display_list.children.iter() {
+        try!(write!(f, "{}{:?}\n", indentation, StackingContextSummary(&**kid)));
+        try!(write_display_list(f, &kid.display_list, depth + 1, max_depth));
+    }
+    Ok(())
+}
+
+/// Shows the placement and compositing of a stacking context, without its items.
+struct StackingContextSummary<'a>(&'a StackingContext);
+
+impl<'a> fmt::Debug for StackingContextSummary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stacking_context = self.0;
+        try!(write!(f,
+                    "StackingContext @ {:?}, overflow {:?}, z-index {}",
+                    stacking_context.bounds,
+                    stacking_context.overflow,
+                    stacking_context.z_index));
+        if !transform3d::is_identity(&stacking_context.transform) {
+            try!(write!(f,
+                        ", transform {:?}",
+                        &transform3d::entries(&stacking_context.transform)[..]));
+        }
+        if !transform3d::is_identity(&stacking_context.perspective) {
+            try!(write!(f,
+                        ", perspective {:?}",
+                        &transform3d::entries(&stacking_context.perspective)[..]));
+        }
+        if stacking_context.preserves_3d {
+            try!(write!(f, ", preserves 3D"));
+        }
+        if !stacking_context.filters.filters.is_empty() {
+            try!(write!(f, ", filters {:?}", stacking_context.filters.filters));
+        }
+        if stacking_context.blend_mode != mix_blend_mode::T::normal {
+            try!(write!(f, ", blend mode {:?}", stacking_context.blend_mode));
+        }
+        if let Some(ref layer) = stacking_context.layer {
+            try!(write!(f, ", layer {:?}", layer.id));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for StackingContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:?}\n", StackingContextSummary(self)));
+        write_display_list(f, &self.display_list, 1, usize::MAX)
+    }
+}
+
 /// Represents one CSS stacking context, which may or may not have a hardware layer.
 pub struct StackingContext {
     /// The display items that make up this stacking context.
@@ -243,9 +507,32 @@ pub struct StackingContext {
     pub blend_mode: mix_blend_mode::T,
 
     /// A transform to be applied to this stacking context.
-    ///
-    /// TODO(pcwalton): 3D transforms.
-    pub transform: Matrix2D<AzFloat>,
+    pub transform: Matrix4<AzFloat>,
+
+    /// The perspective that the stacking contexts in this one are seen with, applied after their
+    /// own transforms and relative to the top left of this one.
+    pub perspective: Matrix4<AzFloat>,
+
+    /// Whether the stacking contexts in this one share its 3D space (`transform-style:
+    /// preserve-3d`), rather than being flattened into its plane.
+    pub preserves_3d: bool,
+
+    /// How many device pixels a px covers at the page zoom, which layout lays the page out for,
+    /// but without the pinch zoom, which only magnifies what is painted. The edges of the items
+    /// are snapped to these pixels.
+    pub device_pixels_per_px: f32,
+
+    /// The items of the display list of this stacking context that each node made, so that they
+    /// can be found without going through all of them.
+    node_items: HashMap<OpaqueNode, NodeItems>,
+}
+
+/// The items of the display list of a stacking context that one node made.
+struct NodeItems {
+    /// The places of the items in `DisplayList::iter()`, in order.
+    indices: Vec<usize>,
+    /// The union of the bounds of the items.
+    bounds: Rect<Au>,
 }
 
 impl StackingContext {
@@ -255,158 +542,297 @@ impl StackingContext {
                bounds: &Rect<Au>,
                overflow: &Rect<Au>,
                z_index: i32,
-               transform: &Matrix2D<AzFloat>,
+               transform: &Matrix4<AzFloat>,
+               perspective: &Matrix4<AzFloat>,
+               preserves_3d: bool,
                filters: filter::T,
                blend_mode: mix_blend_mode::T,
-               layer: Option<Arc<PaintLayer>>)
+               layer: Option<Arc<PaintLayer>>,
+               device_pixels_per_px: f32)
                -> StackingContext {
-        StackingContext {
+        // The compositor draws the document in an iframe in a layer of its own over this one, so
+        // whatever is painted after the iframe goes in a layer over that.
+        let mut display_list = display_list;
+        if let Some((overlay_list, overlay_id)) = split_after_first_iframe(&mut *display_list) {
+            let scroll_policy = match layer {
+                Some(ref layer) => layer.scroll_policy,
+                None => ScrollPolicy::Scrollable,
+            };
+            let overlay_layer = PaintLayer::new(overlay_id, color::transparent(), scroll_policy);
+            let z_index = display_list.children.iter().fold(0, |z, kid| max(z, kid.z_index));
+            let overlay = StackingContext::new(overlay_list,
+                                               &Rect(ZERO_POINT, bounds.size),
+                                               overflow,
+                                               z_index,
+                                               &matrix::identity(),
+                                               &matrix::identity(),
+                                               false,
+                                               filter::T::new(Vec::new()),
+                                               mix_blend_mode::T::normal,
+                                               Some(Arc::new(overlay_layer)),
+                                               device_pixels_per_px);
+            display_list.children.push(Arc::new(overlay))
+        }
+
+        // The items are snapped relative to the origin, so the origin is snapped within the
+        // parent too. Then, as long as nothing above is transformed, the items land on device
+        // pixels wherever the stacking context is nested.
+        let bounds = Rect(Point2D(snap(bounds.origin.x, device_pixels_per_px),
+                                  snap(bounds.origin.y, device_pixels_per_px)),
+                          bounds.size);
+        let mut stacking_context = StackingContext {
             display_list: display_list,
-            layer: layer,
-            bounds: *bounds,
+            layer: None,
+            bounds: bounds,
             overflow: *overflow,
             z_index: z_index,
             transform: *transform,
+            perspective: *perspective,
+            preserves_3d: preserves_3d,
             filters: filters,
             blend_mode: blend_mode,
+            device_pixels_per_px: device_pixels_per_px,
+            node_items: HashMap::new(),
+        };
+        // Items drawn through a transform land between pixels whatever is done to them here.
+        if transform3d::is_identity(&stacking_context.transform) {
+            for item in stacking_context.display_list.iter_mut() {
+                let snapping = item.pixel_snapping();
+                item.snap_to_pixels(snapping, device_pixels_per_px)
+            }
+        }
+        stacking_context.index_node_items();
+
+        // The layer is opaque if its background color is, or if the display list paints opaque
+        // pixels over all of it, as opaque images do. The compositor doesn't ask for the tiles of
+        // the layers under an opaque layer.
+        stacking_context.layer = layer.map(|layer| {
+            let mut layer = (*layer).clone();
+            layer.is_opaque = layer.background_color.a == 1.0 ||
+                stacking_context.paints_opaque_over(overflow);
+            Arc::new(layer)
+        });
+        stacking_context
+    }
+
+    /// Notes which node made each item of the display list, now that the display list won't change,
+    /// and numbers the items of each node to give them their ids.
+    fn index_node_items(&mut self) {
+        for (index, item) in self.display_list.iter_mut().enumerate() {
+            let base = item.mut_base();
+            let node = base.metadata.node;
+            let node_index = match self.node_items.entry(node) {
+                Occupied(mut entry) => {
+                    let node_items = entry.get_mut();
+                    node_items.indices.push(index);
+                    node_items.bounds = node_items.bounds.union(&base.bounds);
+                    node_items.indices.len() - 1
+                }
+                Vacant(entry) => {
+                    entry.insert(NodeItems {
+                        indices: vec![index],
+                        bounds: base.bounds,
+                    });
+                    0
+                }
+            };
+            base.id = DisplayItemId {
+                node: node,
+                index: node_index as u32,
+            }
+        }
+    }
+
+    /// Returns the items of the display list of this stacking context, but not of those in it,
+    /// that the node made, in the order that they are painted in.
+    pub fn items_for_node<'a>(&'a self, node: OpaqueNode) -> Vec<&'a DisplayItem> {
+        let indices = match self.node_items.get(&node) {
+            Some(node_items) => &node_items.indices,
+            None => return Vec::new(),
+        };
+        let mut result = Vec::with_capacity(indices.len());
+        let mut items = self.display_list.iter();
+        let mut next_index = 0;
+        for &index in indices.iter() {
+            result.push(items.nth(index - next_index).unwrap());
+            next_index = index + 1;
         }
+        result
+    }
+
+    /// Returns the union of the bounds of the items of the display list of this stacking context
+    /// that the node made, in its coordinate system, or `None` if the node made none of them.
+    pub fn node_bounds(&self, node: OpaqueNode) -> Option<Rect<Au>> {
+        self.node_items.get(&node).map(|node_items| node_items.bounds)
+    }
+
+    /// Returns the rect that this stacking context and those in it may paint in, in its coordinate
+    /// system, worked out from its display items. It can be used to check `overflow`.
+    pub fn ink_overflow(&self) -> Rect<Au> {
+        self.display_list.bounds()
+    }
+
+    /// Returns the transform that the stacking contexts in this one are placed through, given the
+    /// one that this stacking context is drawn through: its perspective, after flattening them
+    /// into its plane unless it preserves 3D.
+    pub fn children_transform(&self, transform: &Matrix4<AzFloat>) -> Matrix4<AzFloat> {
+        let transform = if self.preserves_3d {
+            *transform
+        } else {
+            transform.mul(&transform3d::flattening())
+        };
+        transform.mul(&self.perspective)
     }
 
     /// Draws the stacking context in the proper order according to the steps in CSS 2.1 § E.2.
     pub fn optimize_and_draw_into_context(&self,
-                                          paint_context: &mut PaintContext,
+                                          paint_backend: &mut PaintBackend,
                                           tile_bounds: &Rect<AzFloat>,
-                                          transform: &Matrix2D<AzFloat>,
+                                          transform: &Matrix4<AzFloat>,
                                           clip_rect: Option<&Rect<Au>>) {
         let transform = transform.mul(&self.transform);
-        let temporary_draw_target =
-            paint_context.get_or_create_temporary_draw_target(&self.filters, self.blend_mode);
-        {
-            let mut paint_subcontext = PaintContext {
-                draw_target: temporary_draw_target.clone(),
-                font_context: &mut *paint_context.font_context,
-                page_rect: *tile_bounds,
-                screen_rect: paint_context.screen_rect,
-                clip_rect: clip_rect.map(|clip_rect| *clip_rect),
-                transient_clip: None,
-            };
-
-            // Optimize the display list to throw out out-of-bounds display items and so forth.
-            let display_list =
-                DisplayListOptimizer::new(tile_bounds).optimize(&*self.display_list);
-
-            if opts::get().dump_display_list_optimized {
-                println!("**** optimized display list. Tile bounds: {:?}", tile_bounds);
-                display_list.print_items(String::from_str("*"));
-            }
+        let kid_transform = self.children_transform(&transform);
 
-            // Sort positioned children according to z-index.
-            let mut positioned_children = SmallVec8::new();
-            for kid in display_list.children.iter() {
-                positioned_children.push((*kid).clone());
-            }
-            positioned_children.as_slice_mut()
-                               .sort_by(|this, other| this.z_index.cmp(&other.z_index));
+        // Backends paint through 2D transforms, so use the one that is right for this tile. If
+        // all of the tile is behind the viewer, nothing in it can be seen.
+        let transform_2d = match transform3d::to_2d_within(&transform, tile_bounds) {
+            Some(transform_2d) => transform_2d,
+            None => return,
+        };
+        let _item_costs_scope = item_costs::enter_stacking_context(self);
+        let _paint_order_scope = paint_order::enter_stacking_context();
+
+        // Optimize the display list to throw out out-of-bounds display items and so forth. When
+        // items are labeled with their places in the paint order, all of them are kept, so that
+        // they are numbered the same way in every tile.
+        let display_list = paint_markers::record(ProfilerCategory::PaintingOptimization, || {
+            let optimizer = if paint_order::is_enabled() {
+                DisplayListOptimizer::keeping_everything()
+            } else {
+                DisplayListOptimizer::new(tile_bounds)
+            };
+            optimizer.optimize(&*self.display_list)
+        });
 
-            // Set up our clip rect and transform.
-            let old_transform = paint_subcontext.draw_target.get_transform();
-            paint_subcontext.draw_target.set_transform(&transform);
-            paint_subcontext.push_clip_if_applicable();
+        if opts::get().dump_display_list_optimized {
+            dump_optimized_display_list(&display_list, tile_bounds);
+        }
 
-            // Steps 1 and 2: Borders and background for the root.
-            for display_item in display_list.background_and_borders.iter() {
-                display_item.draw_into_context(&mut paint_subcontext)
-            }
+        // Sort positioned children according to z-index.
+        let mut positioned_children = SmallVec8::new();
+        for kid in display_list.children.iter() {
+            positioned_children.push((*kid).clone());
+        }
+        positioned_children.as_slice_mut()
+                           .sort_by(|this, other| this.z_index.cmp(&other.z_index));
+
+        // Set up our clip rect, transform, filters and blend mode.
+        let no_filters = filter::T::new(Vec::new());
+        let filters = if skipped_painting::is_skipped(PaintingWork::Filters) {
+            &no_filters
+        } else {
+            &self.filters
+        };
+        paint_backend.push_stacking_context(tile_bounds,
+                                            &transform_2d,
+                                            clip_rect,
+                                            filters,
+                                            self.painted_blend_mode());
+
+        // Steps 1 and 2: Borders and background for the root.
+        paint_markers::record(ProfilerCategory::PaintingBackgroundsAndBorders, || {
+            draw_items(display_list.get(DisplayListSection::BackgroundAndBorders), paint_backend)
+        });
 
-            // Step 3: Positioned descendants with negative z-indices.
+        // Step 3: Positioned descendants with negative z-indices.
+        paint_markers::record(ProfilerCategory::PaintingNegativeZIndexDescendants, || {
             for positioned_kid in positioned_children.iter() {
                 if positioned_kid.z_index >= 0 {
                     break
                 }
-                if positioned_kid.layer.is_none() {
+                if positioned_kid.compositor_layer().is_none() {
                     let new_transform =
-                        transform.translate(positioned_kid.bounds
-                                                          .origin
-                                                          .x
-                                                          .to_nearest_px() as AzFloat,
-                                            positioned_kid.bounds
-                                                          .origin
-                                                          .y
-                                                          .to_nearest_px() as AzFloat);
+                        kid_transform.translate(positioned_kid.bounds
+                                                              .origin
+                                                              .x
+                                                              .to_nearest_px() as AzFloat,
+                                                positioned_kid.bounds
+                                                              .origin
+                                                              .y
+                                                              .to_nearest_px() as AzFloat,
+                                                0.0);
                     let new_tile_rect =
                         self.compute_tile_rect_for_child_stacking_context(tile_bounds,
                                                                           &**positioned_kid);
-                    positioned_kid.optimize_and_draw_into_context(&mut paint_subcontext,
+                    positioned_kid.optimize_and_draw_into_context(paint_backend,
                                                                   &new_tile_rect,
                                                                   &new_transform,
                                                                   Some(&positioned_kid.overflow))
                 }
             }
+        });
 
-            // Step 4: Block backgrounds and borders.
-            for display_item in display_list.block_backgrounds_and_borders.iter() {
-                display_item.draw_into_context(&mut paint_subcontext)
-            }
+        // Step 4: Block backgrounds and borders.
+        paint_markers::record(ProfilerCategory::PaintingBlockBackgroundsAndBorders, || {
+            draw_items(display_list.get(DisplayListSection::BlockBackgroundsAndBorders),
+                       paint_backend)
+        });
 
-            // Step 5: Floats.
-            for display_item in display_list.floats.iter() {
-                display_item.draw_into_context(&mut paint_subcontext)
-            }
+        // Step 5: Floats.
+        paint_markers::record(ProfilerCategory::PaintingFloats, || {
+            draw_items(display_list.get(DisplayListSection::Floats), paint_backend)
+        });
 
-            // TODO(pcwalton): Step 6: Inlines that generate stacking contexts.
+        // TODO(pcwalton): Step 6: Inlines that generate stacking contexts.
 
-            // Step 7: Content.
-            for display_item in display_list.content.iter() {
-                display_item.draw_into_context(&mut paint_subcontext)
-            }
+        // Step 7: Content.
+        paint_markers::record(ProfilerCategory::PaintingContent, || {
+            draw_items(display_list.get(DisplayListSection::Content), paint_backend)
+        });
 
-            // Steps 8 and 9: Positioned descendants with nonnegative z-indices.
+        // Steps 8 and 9: Positioned descendants with nonnegative z-indices.
+        paint_markers::record(ProfilerCategory::PaintingPositionedDescendants, || {
             for positioned_kid in positioned_children.iter() {
                 if positioned_kid.z_index < 0 {
                     continue
                 }
 
-                if positioned_kid.layer.is_none() {
+                if positioned_kid.compositor_layer().is_none() {
                     let new_transform =
-                        transform.translate(positioned_kid.bounds
-                                                          .origin
-                                                          .x
-                                                          .to_nearest_px() as AzFloat,
-                                            positioned_kid.bounds
-                                                          .origin
-                                                          .y
-                                                          .to_nearest_px() as AzFloat);
+                        kid_transform.translate(positioned_kid.bounds
+                                                              .origin
+                                                              .x
+                                                              .to_nearest_px() as AzFloat,
+                                                positioned_kid.bounds
+                                                              .origin
+                                                              .y
+                                                              .to_nearest_px() as AzFloat,
+                                                0.0);
                     let new_tile_rect =
                         self.compute_tile_rect_for_child_stacking_context(tile_bounds,
                                                                           &**positioned_kid);
-                    positioned_kid.optimize_and_draw_into_context(&mut paint_subcontext,
+                    positioned_kid.optimize_and_draw_into_context(paint_backend,
                                                                   &new_tile_rect,
                                                                   &new_transform,
                                                                   Some(&positioned_kid.overflow))
                 }
             }
+        });
 
-            // Step 10: Outlines.
-            for display_item in display_list.outlines.iter() {
-                display_item.draw_into_context(&mut paint_subcontext)
-            }
-
-            // Undo our clipping and transform.
-            paint_subcontext.remove_transient_clip_if_applicable();
-            paint_subcontext.pop_clip_if_applicable();
-            paint_subcontext.draw_target.set_transform(&old_transform)
-        }
+        // Step 10: Outlines.
+        paint_markers::record(ProfilerCategory::PaintingOutlines, || {
+            draw_items(display_list.get(DisplayListSection::Outlines), paint_backend)
+        });
 
-        paint_context.draw_temporary_draw_target_if_necessary(&temporary_draw_target,
-                                                              &self.filters,
-                                                              self.blend_mode)
+        // Undo our clipping, transform, filters and blend mode.
+        paint_backend.pop_stacking_context()
     }
 
     /// Translate the given tile rect into the coordinate system of a child stacking context.
-    fn compute_tile_rect_for_child_stacking_context(&self,
-                                                    tile_bounds: &Rect<AzFloat>,
-                                                    child_stacking_context: &StackingContext)
-                                                    -> Rect<AzFloat> {
+    pub fn compute_tile_rect_for_child_stacking_context(&self,
+                                                        tile_bounds: &Rect<AzFloat>,
+                                                        child_stacking_context: &StackingContext)
+                                                        -> Rect<AzFloat> {
         static ZERO_AZURE_RECT: Rect<f32> = Rect {
             origin: Point2D {
                 x: 0.0,
@@ -435,12 +861,31 @@ impl StackingContext {
     /// Places all nodes containing the point of interest into `result`, topmost first. Respects
     /// the `pointer-events` CSS property If `topmost_only` is true, stops after placing one node
     /// into the list. `result` must be empty upon entry to this function.
+    ///
+    /// If the topmost node is an iframe, returns where in the iframe the point is, so that the
+    /// caller can hand the hit over to the pipeline of the document in it.
     pub fn hit_test(&self,
-                    mut point: Point2D<Au>,
+                    point: Point2D<Au>,
                     result: &mut Vec<DisplayItemMetadata>,
-                    topmost_only: bool) {
+                    topmost_only: bool)
+                    -> Option<IframeHit> {
+        let point = Point2D(point.x.to_frac32_px(), point.y.to_frac32_px());
+        let mut iframe_hit = None;
+        self.hit_test_through(&point, &matrix::identity(), result, &mut iframe_hit, topmost_only);
+        iframe_hit
+    }
+
+    /// As `hit_test()`, where `transform` is the one from the coordinate system of the stacking
+    /// context that this one is in to that of the point.
+    fn hit_test_through(&self,
+                        original_point: &Point2D<AzFloat>,
+                        transform: &Matrix4<AzFloat>,
+                        result: &mut Vec<DisplayItemMetadata>,
+                        iframe_hit: &mut Option<IframeHit>,
+                        topmost_only: bool) {
         fn hit_test_in_list<'a,I>(point: Point2D<Au>,
                                   result: &mut Vec<DisplayItemMetadata>,
+                                  iframe_hit: &mut Option<IframeHit>,
                                   topmost_only: bool,
                                   iterator: I)
                                   where I: Iterator<Item=&'a DisplayItem> {
@@ -478,27 +923,51 @@ impl StackingContext {
                     _ => {}
                 }
 
-                // We found a hit!
-                result.push(item.base().metadata);
+                // We found a hit! If it is the topmost one and an iframe, the document in the
+                // iframe is what was hit.
+                if let DisplayItem::IframeClass(ref iframe) = *item {
+                    if result.is_empty() {
+                        *iframe_hit = Some(IframeHit {
+                            pipeline_id: iframe.pipeline_id,
+                            subpage_id: iframe.subpage_id,
+                            point: point - iframe.base.bounds.origin,
+                        })
+                    }
+                }
+
+                // Parts of the item may override the cursor of its node.
+                let mut metadata = item.base().metadata;
+                metadata.pointing = item.base().cursor_at(&point);
+                result.push(metadata);
                 if topmost_only {
                     return
                 }
             }
         }
 
-        // Convert the point into stacking context local space
-        point = point - self.bounds.origin;
-
+        // Convert the point into stacking context local space, undoing all of the transforms
+        // between it and the original point, perspective included. Parts of the stacking context
+        // that are behind the viewer can't be hit.
         debug_assert!(!topmost_only || result.is_empty());
-        let frac_point = self.transform.transform_point(&Point2D(point.x.to_frac32_px(),
-                                                                 point.y.to_frac32_px()));
-        point = Point2D(Au::from_frac32_px(frac_point.x), Au::from_frac32_px(frac_point.y));
+        let transform = transform.translate(self.bounds.origin.x.to_frac32_px(),
+                                            self.bounds.origin.y.to_frac32_px(),
+                                            0.0)
+                                 .mul(&self.transform);
+        let point = match transform3d::unproject_point(&transform, original_point) {
+            Some(point) => Point2D(Au::from_frac32_px(point.x), Au::from_frac32_px(point.y)),
+            None => return,
+        };
+        let kid_transform = self.children_transform(&transform);
 
         // Iterate through display items in reverse stacking order. Steps here refer to the
         // painting steps in CSS 2.1 Appendix E.
         //
         // Step 10: Outlines.
-        hit_test_in_list(point, result, topmost_only, self.display_list.outlines.iter().rev());
+        hit_test_in_list(point,
+                         result,
+                         iframe_hit,
+                         topmost_only,
+                         self.display_list.get(DisplayListSection::Outlines).iter().rev());
         if topmost_only && !result.is_empty() {
             return
         }
@@ -508,7 +977,11 @@ impl StackingContext {
             if kid.z_index < 0 {
                 continue
             }
-            kid.hit_test(point, result, topmost_only);
+            kid.hit_test_through(original_point,
+                                 &kid_transform,
+                                 result,
+                                 iframe_hit,
+                                 topmost_only);
             if topmost_only && !result.is_empty() {
                 return
             }
@@ -517,12 +990,16 @@ impl StackingContext {
         // Steps 7, 5, and 4: Content, floats, and block backgrounds and borders.
         //
         // TODO(pcwalton): Step 6: Inlines that generate stacking contexts.
-        for display_list in [
-            &self.display_list.content,
-            &self.display_list.floats,
-            &self.display_list.block_backgrounds_and_borders,
+        for section in [
+            DisplayListSection::Content,
+            DisplayListSection::Floats,
+            DisplayListSection::BlockBackgroundsAndBorders,
         ].iter() {
-            hit_test_in_list(point, result, topmost_only, display_list.iter().rev());
+            hit_test_in_list(point,
+                             result,
+                             iframe_hit,
+                             topmost_only,
+                             self.display_list.get(*section).iter().rev());
             if topmost_only && !result.is_empty() {
                 return
             }
@@ -533,7 +1010,11 @@ impl StackingContext {
             if kid.z_index >= 0 {
                 continue
             }
-            kid.hit_test(point, result, topmost_only);
+            kid.hit_test_through(original_point,
+                                 &kid_transform,
+                                 result,
+                                 iframe_hit,
+                                 topmost_only);
             if topmost_only && !result.is_empty() {
                 return
             }
@@ -542,8 +1023,135 @@ impl StackingContext {
         // Steps 2 and 1: Borders and background for the root.
         hit_test_in_list(point,
                          result,
+                         iframe_hit,
                          topmost_only,
-                         self.display_list.background_and_borders.iter().rev())
+                         self.display_list
+                             .get(DisplayListSection::BackgroundAndBorders)
+                             .iter()
+                             .rev())
+    }
+
+    /// Returns the bounds of the animated images that this stacking context paints, together with
+    /// the time at which each is due to show its next frame. Descendant stacking contexts that
+    /// paint into layers of their own are skipped, and the others are reported as a whole.
+    pub fn animated_images(&self, time: u64) -> Vec<(Rect<Au>, u64)> {
+        let mut result = vec!();
+        for item in self.display_list.iter() {
+            if let DisplayItem::ImageClass(ref image_item) = *item {
+                if let Some(ref animation) = image_item.animation {
+                    if let (_, Some(next_frame_time)) = animation.frame_at(time) {
+                        result.push((image_item.base.bounds, next_frame_time))
+                    }
+                }
+            }
+        }
+
+        for kid in self.display_list.children.iter() {
+            if kid.compositor_layer().is_some() {
+                continue
+            }
+            let next_frame_time = kid.animated_images(time)
+                                     .iter()
+                                     .map(|&(_, next_frame_time)| next_frame_time)
+                                     .min();
+            if let Some(next_frame_time) = next_frame_time {
+                result.push((kid.overflow.translate(&kid.bounds.origin), next_frame_time))
+            }
+        }
+        result
+    }
+
+    /// Returns the external textures that this stacking context shows, with where they go relative
+    /// to its top left. Descendant stacking contexts that paint into layers of their own are
+    /// skipped, as are transformed ones and those seen with perspective, since the compositor
+    /// only draws the textures upright.
+    pub fn external_textures(&self) -> Vec<(ExternalTextureId, Rect<Au>)> {
+        let mut result = vec!();
+        for item in self.display_list.iter() {
+            if let DisplayItem::ExternalTextureClass(ref texture_item) = *item {
+                if let Some(bounds) = texture_item.base
+                                                  .bounds
+                                                  .intersection(&texture_item.base.clip.main) {
+                    result.push((texture_item.texture, bounds))
+                }
+            }
+        }
+
+        for kid in self.display_list.children.iter() {
+            if kid.compositor_layer().is_some() || !transform3d::is_identity(&kid.transform) ||
+                    !transform3d::is_identity(&self.perspective) {
+                continue
+            }
+            result.extend(kid.external_textures().into_iter().map(|(texture, bounds)| {
+                (texture, bounds.translate(&kid.bounds.origin))
+            }))
+        }
+        result
+    }
+
+    /// Returns true if this stacking context is simple enough to be painted straight into the
+    /// window each time it is shown, instead of into tiles for the compositor: none of its
+    /// descendants has a layer of its own and none of its images is animated, so the compositor
+    /// has nothing to move or repaint in it by itself.
+    pub fn can_paint_directly(&self) -> bool {
+        for item in self.display_list.iter() {
+            if let DisplayItem::ImageClass(ref image_item) = *item {
+                if image_item.animation.is_some() {
+                    return false
+                }
+            }
+        }
+
+        self.display_list.children.iter().all(|kid| {
+            kid.compositor_layer().is_none() && kid.can_paint_directly()
+        })
+    }
+
+    /// Returns the layer of this stacking context if the compositor puts it together with the
+    /// others. Stacking contexts with mask layers are painted into the layer under them instead.
+    pub fn compositor_layer(&self) -> Option<&PaintLayer> {
+        match self.layer {
+            Some(ref layer) if layer.kind != LayerKind::Mask => Some(&**layer),
+            Some(_) | None => None,
+        }
+    }
+
+    /// Returns the blend mode that the stacking context is painted with. Masks multiply what is
+    /// under them.
+    fn painted_blend_mode(&self) -> mix_blend_mode::T {
+        match self.layer {
+            Some(ref layer) if layer.kind == LayerKind::Mask => mix_blend_mode::T::multiply,
+            Some(_) | None => self.blend_mode,
+        }
+    }
+
+    /// Returns rectangles, in the coordinate system of this stacking context and before its
+    /// transform, that it is sure to paint with fully opaque pixels. Only its own display items
+    /// are looked at, since descendants can only paint over them. The result is empty if filters
+    /// or a blend mode change what reaches the backdrop.
+    pub fn opaque_rects(&self) -> Vec<Rect<Au>> {
+        if filters::temporary_draw_target_needed_for_style_filters(&self.filters) ||
+                self.painted_blend_mode() != mix_blend_mode::T::normal {
+            return vec!()
+        }
+
+        self.display_list.iter().filter(|item| {
+            !skipped_painting::is_item_skipped(item)
+        }).filter_map(|item| item.opaque_rect()).collect()
+    }
+
+    /// Returns true if the stacking context is sure to paint fully opaque pixels over all of
+    /// `rect`, which is in its coordinate system.
+    pub fn paints_opaque_over(&self, rect: &Rect<Au>) -> bool {
+        // The opaque rects are before the transform of the stacking context.
+        if !transform3d::is_identity(&self.transform) {
+            return false
+        }
+
+        self.opaque_rects().iter().any(|opaque_rect| {
+            opaque_rect.origin.x <= rect.origin.x && opaque_rect.origin.y <= rect.origin.y &&
+                opaque_rect.max_x() >= rect.max_x() && opaque_rect.max_y() >= rect.max_y()
+        })
     }
 }
 
@@ -573,47 +1181,262 @@ pub fn find_stacking_context_with_layer_id(this: &Arc<StackingContext>, layer_id
     None
 }
 
-/// One drawing command in the list.
-#[derive(Clone)]
-pub enum DisplayItem {
-    SolidColorClass(Box<SolidColorDisplayItem>),
-    TextClass(Box<TextDisplayItem>),
-    ImageClass(Box<ImageDisplayItem>),
-    BorderClass(Box<BorderDisplayItem>),
-    GradientClass(Box<GradientDisplayItem>),
-    LineClass(Box<LineDisplayItem>),
-    BoxShadowClass(Box<BoxShadowDisplayItem>),
-}
+/// Prints the display list that was optimized for a tile, or writes it to the next numbered file
+/// in the directory given with `--dump-display-list`.
+fn dump_optimized_display_list(display_list: &DisplayList, tile_bounds: &Rect<AzFloat>) {
+    let directory = match opts::get().dump_display_list_dir {
+        Some(ref directory) => directory,
+        None => {
+            println!("**** optimized display list. Tile bounds: {:?}", tile_bounds);
+            println!("{:?}", display_list.tree(opts::get().dump_display_list_depth));
+            return
+        }
+    };
 
-/// Information common to all display items.
-#[derive(Clone)]
-pub struct BaseDisplayItem {
-    /// The boundaries of the display item, in layer coordinates.
-    pub bounds: Rect<Au>,
+    let index = OPTIMIZED_DUMP_COUNT.fetch_add(1, Ordering::SeqCst);
+    let path = Path::new(directory).join(format!("optimized-display-list-{}.json", index));
+    if let Err(error) = json::write_display_list(display_list, &path) {
+        println!("couldn't write the optimized display list to {:?}: {}", path, error)
+    }
+}
 
-    /// Metadata attached to this display item.
-    pub metadata: DisplayItemMetadata,
+/// How many optimized display lists have been written out, which numbers their files.
+static OPTIMIZED_DUMP_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
 
-    /// The region to clip to.
-    pub clip: ClippingRegion,
+/// Draws display items in order.
+fn draw_items(items: &[DisplayItem], paint_backend: &mut PaintBackend) {
+    for item in items.iter() {
+        item.draw_into_context(paint_backend)
+    }
 }
 
-impl BaseDisplayItem {
-    #[inline(always)]
-    pub fn new(bounds: Rect<Au>, metadata: DisplayItemMetadata, clip: ClippingRegion)
-               -> BaseDisplayItem {
-        BaseDisplayItem {
+/// The fragment index of the layer that what a stacking context paints after an iframe is moved
+/// into, less how many stacking contexts down from it the iframe is, so that each stacking context
+/// that a nested iframe splits gets a layer of its own.
+const IFRAME_OVERLAY_FRAGMENT_INDEX: u32 = u32::MAX;
+
+/// Finds the first iframe that a stacking context with the given display list paints, including
+/// in the stacking contexts it paints into itself, and moves everything that it paints after the
+/// iframe into a new display list. Returns that list and the id of the layer to put it in, or
+/// `None` if it paints no iframe or has been split already.
+///
+/// The children are left sorted by z-index, in the order that they are painted in.
+fn split_after_first_iframe(display_list: &mut DisplayList)
+                            -> Option<(Box<DisplayList>, LayerId)> {
+    display_list.children.sort_by(|this, other| this.z_index.cmp(&other.z_index));
+    let negative_children =
+        display_list.children.iter().take_while(|kid| kid.z_index < 0).count();
+
+    let mut iframe = None;
+    let mut rest = box DisplayList::new();
+    for section in DISPLAY_LIST_SECTIONS.iter() {
+        // Steps 3 and 8: the positioned descendants are painted before these sections.
+        let children = match *section {
+            DisplayListSection::BlockBackgroundsAndBorders => Some(0..negative_children),
+            DisplayListSection::Outlines => {
+                Some(negative_children..display_list.children.len())
+            }
+            _ => None,
+        };
+        if let (None, Some(children)) = (iframe, children) {
+            let kid_iframe = children.filter_map(|index| {
+                let kid = &display_list.children[index];
+                if kid.compositor_layer().is_some() {
+                    return None
+                }
+                first_iframe(&kid.display_list).map(|(node, depth)| (index, node, depth + 1))
+            }).next();
+            if let Some((index, node, depth)) = kid_iframe {
+                iframe = Some((node, depth));
+                rest.children = display_list.children.split_off(index + 1)
+            }
+        }
+
+        if iframe.is_some() {
+            rest.get_mut(*section).append(display_list.get_mut(*section));
+            continue
+        }
+        let position = display_list.get(*section).iter().position(|item| {
+            match *item {
+                DisplayItem::IframeClass(_) => true,
+                _ => false,
+            }
+        });
+        if let Some(position) = position {
+            {
+                let items = display_list.get_mut(*section);
+                iframe = Some((items[position].base().metadata.node, 0));
+                *rest.get_mut(*section) = items.split_off(position + 1);
+            }
+
+            // The children painted after this section go too.
+            let first_child_after = match *section {
+                DisplayListSection::BackgroundAndBorders => 0,
+                DisplayListSection::Outlines => display_list.children.len(),
+                _ => negative_children,
+            };
+            rest.children = display_list.children.split_off(first_child_after)
+        }
+    }
+    let (node, depth) = match iframe {
+        Some(iframe) => iframe,
+        None => return None,
+    };
+    let id = LayerId(node.id() as usize, IFRAME_OVERLAY_FRAGMENT_INDEX - depth);
+
+    // A display list that was split already, such as one decoded from one that was, has only the
+    // layer for what is painted after the iframe left after it.
+    let already_split = rest.iter().next().is_none() &&
+        rest.children.len() == 1 &&
+        rest.children[0].layer.as_ref().map(|layer| layer.id) == Some(id);
+    if already_split {
+        display_list.append_from(&mut *rest);
+        return None
+    }
+    Some((rest, id))
+}
+
+/// Returns the node of the first iframe that a stacking context with the given display list
+/// paints, including in the stacking contexts it paints into itself, and how many stacking
+/// contexts down it is.
+fn first_iframe(display_list: &DisplayList) -> Option<(OpaqueNode, u32)> {
+    for item in display_list.iter() {
+        if let DisplayItem::IframeClass(ref iframe) = *item {
+            return Some((iframe.base.metadata.node, 0))
+        }
+    }
+    display_list.children.iter().filter(|kid| kid.compositor_layer().is_none()).filter_map(|kid| {
+        first_iframe(&kid.display_list).map(|(node, depth)| (node, depth + 1))
+    }).next()
+}
+
+/// One drawing command in the list.
+#[derive(Clone)]
+pub enum DisplayItem {
+    SolidColorClass(Box<SolidColorDisplayItem>),
+    TextClass(Box<TextDisplayItem>),
+    ImageClass(Box<ImageDisplayItem>),
+    BorderClass(Box<BorderDisplayItem>),
+    GradientClass(Box<GradientDisplayItem>),
+    RadialGradientClass(Box<RadialGradientDisplayItem>),
+    LineClass(Box<LineDisplayItem>),
+    BoxShadowClass(Box<BoxShadowDisplayItem>),
+    ExternalTextureClass(Box<ExternalTextureDisplayItem>),
+    IframeClass(Box<IframeDisplayItem>),
+}
+
+/// Information common to all display items.
+#[derive(Clone)]
+pub struct BaseDisplayItem {
+    /// The boundaries of the display item, in layer coordinates.
+    pub bounds: Rect<Au>,
+
+    /// Metadata attached to this display item.
+    pub metadata: DisplayItemMetadata,
+
+    /// The region to clip to.
+    pub clip: ClippingRegion,
+
+    /// Names this item the same way in every display list that is built for the page, as long
+    /// as its node makes the same items. Set when the stacking context of the item is made.
+    pub id: DisplayItemId,
+
+    /// Parts of the item that show cursors of their own, instead of the one in `metadata`. Later
+    /// ones win where they overlap.
+    pub cursor_regions: Vec<CursorRegion>,
+
+    /// The color space that the colors of the item are in. They are converted to the color space
+    /// of the surface when the item is painted.
+    pub color_space: ColorSpace,
+}
+
+impl BaseDisplayItem {
+    #[inline(always)]
+    pub fn new(bounds: Rect<Au>, metadata: DisplayItemMetadata, clip: ClippingRegion)
+               -> BaseDisplayItem {
+        BaseDisplayItem {
             bounds: bounds,
             metadata: metadata,
             clip: clip,
+            id: DisplayItemId {
+                node: metadata.node,
+                index: 0,
+            },
+            cursor_regions: Vec::new(),
+            color_space: ColorSpace::Srgb,
         }
     }
+
+    /// Returns the cursor to show when the mouse is over `point` in this item, or `None` if the
+    /// item doesn't take pointer events.
+    pub fn cursor_at(&self, point: &Point2D<Au>) -> Option<Cursor> {
+        if self.metadata.pointing.is_none() {
+            return None
+        }
+        match self.cursor_regions.iter().rev().find(|region| {
+            geometry::rect_contains_point(region.rect, *point)
+        }) {
+            Some(region) => Some(region.cursor),
+            None => self.metadata.pointing,
+        }
+    }
+}
+
+/// A part of a display item over which the mouse shows a cursor other than the one that the
+/// `cursor` property of its node asks for, such as a resize handle or the boundary between table
+/// columns.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CursorRegion {
+    /// The part of the item, in the same coordinate system as its bounds.
+    pub rect: Rect<Au>,
+    /// The cursor to show over it.
+    pub cursor: Cursor,
+}
+
+/// How a display item is moved onto whole pixels when the stacking context that it is in is
+/// made, so that it is painted crisply. Painting then draws items where they are. Nothing in a
+/// transformed stacking context is snapped. Stacking context origins are snapped as well, so that
+/// what is snapped in one is on device pixels of the page too.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelSnapping {
+    /// Left where it is, for gradients, whose colors change smoothly anyway.
+    None,
+    /// Each edge is moved to the nearest pixel, for boxes, borders and images.
+    Both,
+    /// Only moved up or down to the nearest pixel, for text, whose glyphs are placed along the
+    /// line at subpixel offsets.
+    VerticalOnly,
+}
+
+/// Returns the length or coordinate rounded to the nearest device pixel, of which there are
+/// `device_pixels_per_px` in a px.
+fn snap(au: Au, device_pixels_per_px: f32) -> Au {
+    Au::from_frac32_px((au.to_frac32_px() * device_pixels_per_px).round() / device_pixels_per_px)
+}
+
+/// Returns the rect with each of its edges moved to the nearest device pixel.
+fn snap_rect(rect: &Rect<Au>, device_pixels_per_px: f32) -> Rect<Au> {
+    let snap = |au: Au| snap(au, device_pixels_per_px);
+    let origin = Point2D(snap(rect.origin.x), snap(rect.origin.y));
+    Rect(origin, Size2D(snap(rect.max_x()) - origin.x, snap(rect.max_y()) - origin.y))
+}
+
+/// An id of a display item that stays the same from one display list to the next, so that diffs,
+/// devtools and telemetry can refer to the same item across frames.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct DisplayItemId {
+    /// The node that made the item.
+    pub node: OpaqueNode,
+    /// Which of the items of the node in its stacking context this is, in the order that they are
+    /// painted in.
+    pub index: u32,
 }
 
 impl HeapSizeOf for BaseDisplayItem {
     fn heap_size_of_children(&self) -> usize {
         self.metadata.heap_size_of_children() +
-            self.clip.heap_size_of_children()
+            self.clip.heap_size_of_children() +
+            self.cursor_regions.heap_size_of_children()
     }
 }
 
@@ -740,6 +1563,25 @@ impl ClippingRegion {
             }).collect(),
         }
     }
+
+    /// Returns this clipping region with the edges of its rects moved to the nearest device
+    /// pixel, as those of the items that it clips are. A region that clips nothing stays as it
+    /// is.
+    pub fn snap_to_pixels(&self, device_pixels_per_px: f32) -> ClippingRegion {
+        ClippingRegion {
+            main: if self.main == MAX_RECT {
+                MAX_RECT
+            } else {
+                snap_rect(&self.main, device_pixels_per_px)
+            },
+            complex: self.complex.iter().map(|complex| {
+                ComplexClippingRegion {
+                    rect: snap_rect(&complex.rect, device_pixels_per_px),
+                    radii: complex.radii,
+                }
+            }).collect(),
+        }
+    }
 }
 
 impl HeapSizeOf for ClippingRegion {
@@ -764,6 +1606,10 @@ pub struct DisplayItemMetadata {
     /// The value of the `cursor` property when the mouse hovers over this display item. If `None`,
     /// this display item is ineligible for pointer events (`pointer-events: none`).
     pub pointing: Option<Cursor>,
+    /// A value that the code that builds the display item may set to tell it apart from the other
+    /// items of its node, such as which part of a scrollbar it is, and that hit testing hands back
+    /// with the node. It is zero unless set.
+    pub tag: u64,
 }
 
 impl DisplayItemMetadata {
@@ -781,10 +1627,17 @@ impl DisplayItemMetadata {
                 (pointer_events::T::auto, cursor::T::AutoCursor) => Some(default_cursor),
                 (pointer_events::T::auto, cursor::T::SpecifiedCursor(cursor)) => Some(cursor),
             },
+            tag: 0,
         }
     }
 }
 
+impl HeapSizeOf for CursorRegion {
+    fn heap_size_of_children(&self) -> usize {
+        0
+    }
+}
+
 impl HeapSizeOf for DisplayItemMetadata {
     fn heap_size_of_children(&self) -> usize {
         0
@@ -830,15 +1683,366 @@ pub struct TextDisplayItem {
 
     /// The blur radius for this text. If zero, this text is not blurred.
     pub blur_radius: Au,
+
+    /// The shadows of this text from `text-shadow`, in the order of that property, so the first
+    /// is painted on top. They are all painted behind the text.
+    pub shadows: Vec<TextShadow>,
+
+    /// The lines that decorate this text. Underlines and overlines are painted behind the text
+    /// and line-throughs over it, as CSS Text Decoration Level 3 § 2.4 says. Shadows of the text
+    /// have them too.
+    pub decorations: Vec<TextDecoration>,
+
+    /// A ruby annotation to paint over or under this text, if any.
+    pub ruby_annotation: Option<RubyAnnotation>,
+
+    /// The emphasis marks to paint over or under the characters of this text, if any.
+    pub emphasis_marks: Option<EmphasisMarks>,
+}
+
+/// A line that decorates the text of a `TextDisplayItem`, from `text-decoration`.
+#[derive(Clone, Copy)]
+pub struct TextDecoration {
+    /// Where the line is.
+    pub line: TextDecorationLine,
+
+    /// How the line is drawn.
+    pub style: TextDecorationStyle,
+
+    /// The color of the line.
+    pub color: Color,
+
+    /// True if an underline breaks where descenders cross it, from `text-decoration-skip-ink`.
+    pub skip_ink: bool,
+}
+
+/// Where a line that decorates text is, which the font says for underlines and line-throughs.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TextDecorationLine {
+    Underline,
+    /// At the top of the ascent.
+    Overline,
+    LineThrough,
+}
+
+/// How a line that decorates text is drawn.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TextDecorationStyle {
+    Solid,
+    /// Two strokes, a stroke's thickness apart.
+    Double,
+    Dotted,
+    Dashed,
+    /// A wave along the line, as thick as the stroke and as high as three of them.
+    Wavy,
+}
+
+/// A shadow of the text of a `TextDisplayItem`: the glyphs again in another color, offset and
+/// perhaps blurred.
+#[derive(Clone, Copy)]
+pub struct TextShadow {
+    /// How far the shadow is from the text.
+    pub offset: Point2D<Au>,
+
+    /// The blur radius of the shadow. If zero, the shadow is not blurred.
+    pub blur_radius: Au,
+
+    /// The color of the shadow.
+    pub color: Color,
+}
+
+/// A run of annotation text that is painted centered over or under the base text of a
+/// `TextDisplayItem`, as ruby is.
+#[derive(Clone)]
+pub struct RubyAnnotation {
+    /// The text run of the annotation. It is usually in a smaller font than the base text.
+    pub text_run: Arc<Box<TextRun>>,
+
+    /// The range of text within the annotation text run.
+    pub range: Range<CharIndex>,
+
+    /// The color of the annotation.
+    pub text_color: Color,
+
+    /// Which side of the base text the annotation goes on.
+    pub position: RubyPosition,
+}
+
+/// The side of the base text that a ruby annotation or emphasis marks are placed on.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RubyPosition {
+    /// Above the base text, in the line-over direction.
+    Over,
+    /// Below the base text, in the line-under direction.
+    Under,
+}
+
+impl RubyAnnotation {
+    /// Returns the start of the baseline of the annotation, relative to the start of the baseline
+    /// of the given base text. The annotation is centered on the base text, and its descent
+    /// (for `Over`) or ascent (for `Under`) touches the extent of the base font.
+    pub fn baseline_offset(&self, base_run: &TextRun, base_range: &Range<CharIndex>)
+                           -> Point2D<Au> {
+        let base_advance = base_run.advance_for_range(base_range);
+        let advance = self.text_run.advance_for_range(&self.range);
+        let (base_metrics, metrics) = (&base_run.font_metrics, &self.text_run.font_metrics);
+        let y = match self.position {
+            RubyPosition::Over => -base_metrics.ascent - metrics.descent,
+            RubyPosition::Under => base_metrics.descent + metrics.ascent,
+        };
+        Point2D((base_advance - advance) / 2, y)
+    }
+
+    /// Returns the bounds of the annotation, relative to the start of the baseline of the given
+    /// base text.
+    pub fn bounds(&self, base_run: &TextRun, base_range: &Range<CharIndex>) -> Rect<Au> {
+        let offset = self.baseline_offset(base_run, base_range);
+        let metrics = self.text_run.metrics_for_range(&self.range);
+        Rect(Point2D(offset.x, offset.y - metrics.ascent),
+             Size2D(metrics.advance_width, metrics.ascent + metrics.descent))
+    }
+}
+
+/// A mark that is painted centered over or under each character of the base text of a
+/// `TextDisplayItem` that isn't whitespace, from `text-emphasis`.
+#[derive(Clone)]
+pub struct EmphasisMarks {
+    /// The text run of the mark. It is usually in a font half the size of the base text.
+    pub text_run: Arc<Box<TextRun>>,
+
+    /// The range of the mark within its text run.
+    pub range: Range<CharIndex>,
+
+    /// The color of the marks.
+    pub text_color: Color,
+
+    /// Which side of the base text the marks go on.
+    pub position: RubyPosition,
+}
+
+impl EmphasisMarks {
+    /// Returns the start of the baseline of each mark, relative to the start of the baseline of
+    /// the given base text. As with ruby, the descent (for `Over`) or ascent (for `Under`) of
+    /// the marks touches the extent of the base font.
+    pub fn baseline_offsets(&self, base_run: &TextRun, base_range: &Range<CharIndex>)
+                            -> Vec<Point2D<Au>> {
+        let advance = self.text_run.advance_for_range(&self.range);
+        let y = self.baseline_y(base_run);
+        let characters: Vec<char> = base_run.text.chars()
+                                                 .skip(base_range.begin().to_usize())
+                                                 .take(base_range.length().to_usize())
+                                                 .collect();
+        base_run.character_intervals(base_range).into_iter().filter(|&(index, _, _)| {
+            !characters[(index - base_range.begin()).to_usize()].is_whitespace()
+        }).map(|(_, start, end)| Point2D((start + end - advance) / 2, y)).collect()
+    }
+
+    /// Returns bounds that take in all of the marks, relative to the start of the baseline of the
+    /// given base text.
+    pub fn bounds(&self, base_run: &TextRun, base_range: &Range<CharIndex>) -> Rect<Au> {
+        let base_advance = base_run.advance_for_range(base_range);
+        let metrics = self.text_run.metrics_for_range(&self.range);
+        let y = self.baseline_y(base_run);
+        Rect(Point2D(-metrics.advance_width / 2, y - metrics.ascent),
+             Size2D(base_advance + metrics.advance_width, metrics.ascent + metrics.descent))
+    }
+
+    fn baseline_y(&self, base_run: &TextRun) -> Au {
+        let (base_metrics, metrics) = (&base_run.font_metrics, &self.text_run.font_metrics);
+        match self.position {
+            RubyPosition::Over => -base_metrics.ascent - metrics.descent,
+            RubyPosition::Under => base_metrics.descent + metrics.ascent,
+        }
+    }
+}
+
+impl TextDisplayItem {
+    /// Returns a text item without shadows that paints the given shadow of this text, so that
+    /// backends paint shadows as they paint any text.
+    pub fn shadow_item(&self, shadow: &TextShadow) -> TextDisplayItem {
+        let mut base = self.base.clone();
+        base.bounds = base.bounds.translate(&shadow.offset);
+        TextDisplayItem {
+            base: base,
+            text_run: self.text_run.clone(),
+            range: self.range,
+            text_color: shadow.color,
+            baseline_origin: self.baseline_origin + shadow.offset,
+            orientation: self.orientation,
+            blur_radius: shadow.blur_radius,
+            shadows: Vec::new(),
+            decorations: self.decorations.iter().map(|decoration| {
+                TextDecoration {
+                    color: shadow.color,
+                    ..*decoration
+                }
+            }).collect(),
+            ruby_annotation: self.ruby_annotation.as_ref().map(|annotation| {
+                RubyAnnotation {
+                    text_color: shadow.color,
+                    ..(*annotation).clone()
+                }
+            }),
+            emphasis_marks: self.emphasis_marks.as_ref().map(|marks| {
+                EmphasisMarks {
+                    text_color: shadow.color,
+                    ..(*marks).clone()
+                }
+            }),
+        }
+    }
+
+    /// Returns the rect that this item may paint pixels in: its bounds and those of its
+    /// decorations, ruby annotation and emphasis marks, blurred, and the same for each of its
+    /// shadows.
+    pub fn ink_bounds(&self) -> Rect<Au> {
+        let mut text_bounds = self.base.bounds;
+        if let Some(ruby_annotation_bounds) = self.ruby_annotation_bounds() {
+            text_bounds = text_bounds.union(&ruby_annotation_bounds)
+        }
+        if let Some(ref marks) = self.emphasis_marks {
+            let marks_bounds = marks.bounds(&**self.text_run, &self.range);
+            text_bounds = text_bounds.union(&self.run_rect_to_item_rect(&marks_bounds))
+        }
+        for decoration in self.decorations.iter() {
+            // Inflating the strokes by their thickness takes in the crests of wavy lines.
+            for stroke in self.decoration_strokes(decoration).iter() {
+                let rect = self.run_rect_to_item_rect(stroke);
+                let thickness = stroke.size.height;
+                text_bounds = text_bounds.union(&rect.inflate(thickness, thickness))
+            }
+        }
+
+        let inflation = self.blur_radius * BLUR_INFLATION_FACTOR;
+        let mut ink_bounds = text_bounds.inflate(inflation, inflation);
+        for shadow in self.shadows.iter() {
+            let inflation = shadow.blur_radius * BLUR_INFLATION_FACTOR;
+            let shadow_bounds = text_bounds.translate(&shadow.offset)
+                                           .inflate(inflation, inflation);
+            ink_bounds = ink_bounds.union(&shadow_bounds)
+        }
+        ink_bounds
+    }
+
+    /// Returns the rects of the strokes of the given line decorating this text, in the same
+    /// coordinate system as the bounds of this item. Wavy lines wave about theirs. An underline
+    /// that skips ink is broken into pieces around the descenders that cross it.
+    pub fn decoration_rects(&self, decoration: &TextDecoration) -> Vec<Rect<Au>> {
+        let strokes = self.decoration_strokes(decoration);
+        let skips_ink = decoration.skip_ink && decoration.line == TextDecorationLine::Underline;
+        let mut rects = Vec::new();
+        for stroke in strokes.iter() {
+            if !skips_ink {
+                rects.push(self.run_rect_to_item_rect(stroke));
+                continue
+            }
+
+            // Keep a stroke's thickness of space around the ink.
+            let thickness = stroke.size.height;
+            let gaps: Vec<_> =
+                self.text_run
+                    .descender_intervals(&self.range, stroke.origin.y - thickness)
+                    .iter()
+                    .map(|&(start, end)| (start - thickness, end + thickness))
+                    .collect();
+            for &(start, end) in skip_intervals(stroke.origin.x, stroke.max_x(), &gaps).iter() {
+                let piece = Rect(Point2D(start, stroke.origin.y),
+                                 Size2D(end - start, thickness));
+                rects.push(self.run_rect_to_item_rect(&piece))
+            }
+        }
+        rects
+    }
+
+    /// Returns the strokes of the given line decorating this text, relative to the start of the
+    /// baseline with the x axis along it: two for `Double` lines and one otherwise.
+    fn decoration_strokes(&self, decoration: &TextDecoration) -> Vec<Rect<Au>> {
+        let metrics = &self.text_run.font_metrics;
+        let (top, thickness) = match decoration.line {
+            TextDecorationLine::Underline => (-metrics.underline_offset, metrics.underline_size),
+            TextDecorationLine::Overline => (-metrics.ascent, metrics.underline_size),
+            TextDecorationLine::LineThrough => {
+                (-metrics.strikeout_offset, metrics.strikeout_size)
+            }
+        };
+        let rect = Rect(Point2D(Au(0), top),
+                        Size2D(self.text_run.advance_for_range(&self.range), thickness));
+
+        if decoration.style == TextDecorationStyle::Double {
+            // The second stroke goes away from the text, or both straddle a line-through.
+            let (first, second) = match decoration.line {
+                TextDecorationLine::Underline => (Au(0), thickness * 2),
+                TextDecorationLine::Overline => (Au(0), -thickness * 2),
+                TextDecorationLine::LineThrough => (-thickness, thickness),
+            };
+            vec![rect.translate(&Point2D(Au(0), first)), rect.translate(&Point2D(Au(0), second))]
+        } else {
+            vec![rect]
+        }
+    }
+
+    /// Returns the union of the bounding rects of the glyphs for the characters in `range`, in the
+    /// same coordinate system as the bounds of this item. Characters outside the range of this
+    /// item are ignored.
+    pub fn glyph_bounds_for_range(&self, range: &Range<CharIndex>) -> Rect<Au> {
+        let bounds = self.text_run.glyph_bounds_for_range(&self.range, range);
+        self.run_rect_to_item_rect(&bounds)
+    }
+
+    /// Returns the bounds of the ruby annotation of this item, if it has one, in the same
+    /// coordinate system as the bounds of this item.
+    pub fn ruby_annotation_bounds(&self) -> Option<Rect<Au>> {
+        self.ruby_annotation.as_ref().map(|annotation| {
+            self.run_rect_to_item_rect(&annotation.bounds(&**self.text_run, &self.range))
+        })
+    }
+
+    /// Maps a rect relative to the start of the baseline of this text, with the x axis along the
+    /// baseline, into the coordinate system of the bounds of this item.
+    fn run_rect_to_item_rect(&self, bounds: &Rect<Au>) -> Rect<Au> {
+        let origin = self.baseline_origin;
+        match self.orientation {
+            TextOrientation::Upright => bounds.translate(&origin),
+            TextOrientation::SidewaysLeft => {
+                Rect(Point2D(origin.x + bounds.origin.y,
+                             origin.y - bounds.origin.x - bounds.size.width),
+                     Size2D(bounds.size.height, bounds.size.width))
+            }
+            TextOrientation::SidewaysRight => {
+                Rect(Point2D(origin.x - bounds.origin.y - bounds.size.height,
+                             origin.y + bounds.origin.x),
+                     Size2D(bounds.size.height, bounds.size.width))
+            }
+        }
+    }
+
+    /// Returns true if the text of this item is right-to-left.
+    #[inline]
+    pub fn is_rtl(&self) -> bool {
+        self.text_run.is_rtl()
+    }
 }
 
 impl HeapSizeOf for TextDisplayItem {
     fn heap_size_of_children(&self) -> usize {
-        self.base.heap_size_of_children()
+        self.base.heap_size_of_children() + self.shadows.heap_size_of_children() +
+            self.decorations.heap_size_of_children()
         // We exclude `text_run` because it is non-owning.
     }
 }
 
+impl HeapSizeOf for TextDecoration {
+    fn heap_size_of_children(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSizeOf for TextShadow {
+    fn heap_size_of_children(&self) -> usize {
+        0
+    }
+}
+
 #[derive(Clone, Eq, PartialEq)]
 pub enum TextOrientation {
     Upright,
@@ -850,16 +2054,113 @@ pub enum TextOrientation {
 #[derive(Clone)]
 pub struct ImageDisplayItem {
     pub base: BaseDisplayItem,
-    pub image: Arc<Image>,
+    pub image: ImageSource,
+
+    /// The part of the image that is drawn, in pixels of the image as it is stored, or `None` to
+    /// draw all of it. This lets sprites and `object-fit` show part of an image without layout
+    /// having to crop it into a new one.
+    pub source_rect: Option<Rect<AzFloat>>,
 
     /// The dimensions to which the image display item should be stretched. If this is smaller than
-    /// the bounds of this display item, then the image will be repeated in the appropriate
-    /// direction to tile the entire bounds.
+    /// the bounds of this display item, then the image will be repeated as `horizontal_repeat`
+    /// and `vertical_repeat` say, which may rescale it.
     pub stretch_size: Size2D<Au>,
 
+    /// How the image is repeated horizontally to fill the bounds.
+    pub horizontal_repeat: ImageRepeat,
+
+    /// How the image is repeated vertically to fill the bounds.
+    pub vertical_repeat: ImageRepeat,
+
     /// The algorithm we should use to stretch the image. See `image_rendering` in CSS-IMAGES-3 §
     /// 5.3.
     pub image_rendering: image_rendering::T,
+
+    /// The frames of the image if it is animated, in which case `image` is its first frame.
+    pub animation: Option<ImageAnimation>,
+
+    /// How the image is turned to be shown upright. `bounds` and `stretch_size` are in the
+    /// orientation in which the image is shown.
+    pub orientation: ImageOrientation,
+
+    /// Whether every pixel of the image, and of each of its frames if it is animated, is fully
+    /// opaque, as worked out when the image was decoded. This is false for images that aren't
+    /// decoded yet.
+    pub is_opaque: bool,
+}
+
+impl ImageDisplayItem {
+    /// Returns the part of the bounds of this item that it is sure to cover with fully opaque
+    /// pixels, if any.
+    fn opaque_rect(&self) -> Option<Rect<Au>> {
+        // `space` leaves gaps between the tiles, and an image that isn't repeated covers only
+        // its own size.
+        fn covers(repeat: ImageRepeat, extent: Au, tile_length: Au) -> bool {
+            match repeat {
+                ImageRepeat::Repeat | ImageRepeat::Round => true,
+                ImageRepeat::NoRepeat | ImageRepeat::Space => tile_length >= extent,
+            }
+        }
+
+        if !self.is_opaque ||
+                !covers(self.horizontal_repeat, self.base.bounds.size.width,
+                        self.stretch_size.width) ||
+                !covers(self.vertical_repeat, self.base.bounds.size.height,
+                        self.stretch_size.height) {
+            return None
+        }
+        Some(self.base.bounds)
+    }
+
+    /// Returns the frame of the image that is shown at `time`, or `None` if there is nothing to
+    /// draw. `device_size` is the size in device pixels at which each tile of the image is
+    /// drawn, and `generators` those that generated images are drawn with, if any.
+    pub fn image_at(&self,
+                    time: u64,
+                    device_size: &Size2D<u32>,
+                    generators: Option<&Mutex<ImageGeneratorRegistry>>)
+                    -> Option<Arc<Image>> {
+        match self.animation {
+            Some(ref animation) => Some(animation.frames[animation.frame_at(time).0].image.clone()),
+            None => self.image.image(device_size, generators),
+        }
+    }
+}
+
+/// Where the pixels of an image display item come from.
+#[derive(Clone)]
+pub enum ImageSource {
+    /// An image that has already been decoded.
+    Decoded(Arc<Image>),
+    /// An image that is decoded when a tile that shows the item is painted, unless its pixels
+    /// are still in the cache that it was made with. The cache drops the pixels of the images
+    /// that were used least recently, so images that aren't painted don't keep them in memory.
+    Lazy(LazyImage),
+    /// An image that is drawn at paint time, at the size at which it is shown, by a registered
+    /// generator.
+    Generated(GeneratedImage),
+}
+
+impl ImageSource {
+    /// Returns the image to draw at `device_size`, in device pixels, decoding it or generating
+    /// it with `generators` first if necessary. Returns `None` if there is nothing to draw.
+    pub fn image(&self,
+                 device_size: &Size2D<u32>,
+                 generators: Option<&Mutex<ImageGeneratorRegistry>>)
+                 -> Option<Arc<Image>> {
+        match *self {
+            ImageSource::Decoded(ref image) => Some(image.clone()),
+            ImageSource::Lazy(ref lazy_image) => lazy_image.decode(),
+            ImageSource::Generated(ref generated_image) => {
+                generators.and_then(|generators| {
+                    image_generator::generate(generators,
+                                              generated_image,
+                                              device_size.width,
+                                              device_size.height)
+                })
+            }
+        }
+    }
 }
 
 impl HeapSizeOf for ImageDisplayItem {
@@ -869,6 +2170,369 @@ impl HeapSizeOf for ImageDisplayItem {
     }
 }
 
+/// Shows a GPU texture that something other than the painter draws into, such as a video or a
+/// WebGL canvas. Nothing is painted for it; the compositor draws the latest frame of the texture
+/// over the layer instead, so its pixels are never read back.
+#[derive(Clone)]
+pub struct ExternalTextureDisplayItem {
+    pub base: BaseDisplayItem,
+    pub texture: ExternalTextureId,
+}
+
+impl HeapSizeOf for ExternalTextureDisplayItem {
+    fn heap_size_of_children(&self) -> usize {
+        self.base.heap_size_of_children()
+    }
+}
+
+/// Shows the document in an iframe, over the content box of the iframe. Nothing is painted for it;
+/// the compositor composites the layers of the pipeline of that document there instead, and hit
+/// testing hands hits on it over to that pipeline. What the stacking context paints after it is
+/// moved into a layer over those when the stacking context is created.
+#[derive(Clone)]
+pub struct IframeDisplayItem {
+    pub base: BaseDisplayItem,
+
+    /// The pipeline of the document that contains the iframe.
+    pub pipeline_id: PipelineId,
+
+    /// The subpage of that pipeline that the iframe shows, which the constellation maps to the
+    /// pipeline of the document in it.
+    pub subpage_id: SubpageId,
+}
+
+impl HeapSizeOf for IframeDisplayItem {
+    fn heap_size_of_children(&self) -> usize {
+        self.base.heap_size_of_children()
+    }
+}
+
+/// A hit test that found an iframe on top: the iframe, and the point in the coordinates of its
+/// content box, which are those of the document in it, so that the hit can be handed over to the
+/// pipeline of that document.
+#[derive(Clone, Copy, Debug)]
+pub struct IframeHit {
+    pub pipeline_id: PipelineId,
+    pub subpage_id: SubpageId,
+    pub point: Point2D<Au>,
+}
+
+/// How an image is repeated along one axis of the bounds of its display item. See
+/// `background-repeat` in CSS-BACKGROUNDS-3 § 3.4.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageRepeat {
+    /// The image is tiled from the start of the bounds and the last tile may be cut off.
+    Repeat,
+    /// The image is drawn once, at the start of the bounds.
+    NoRepeat,
+    /// The image is rescaled so that a whole number of tiles fills the bounds.
+    Round,
+    /// As many whole tiles as fit are drawn, spaced out so that the first and the last one touch
+    /// the ends of the bounds.
+    Space,
+}
+
+impl ImageRepeat {
+    /// Returns the offsets from the start of the bounds at which tiles are drawn along this axis,
+    /// and the length of each tile, given the length of the bounds and the stretched length of
+    /// the image.
+    pub fn tiles(&self, extent: Au, tile_length: Au) -> (Vec<Au>, Au) {
+        if tile_length <= Au(0) {
+            return (vec!(), tile_length)
+        }
+
+        match *self {
+            ImageRepeat::Repeat => {
+                let mut offsets = vec!();
+                let mut offset = Au(0);
+                while offset < extent {
+                    offsets.push(offset);
+                    offset = offset + tile_length;
+                }
+                (offsets, tile_length)
+            }
+            ImageRepeat::NoRepeat => (vec!(Au(0)), tile_length),
+            ImageRepeat::Round => {
+                let count = max(1, (extent.to_subpx() / tile_length.to_subpx()).round() as i32);
+                let tile_length = extent / count;
+                ((0..count).map(|index| tile_length * index).collect(), tile_length)
+            }
+            ImageRepeat::Space => {
+                let Au(extent_au) = extent;
+                let Au(tile_length_au) = tile_length;
+                let count = extent_au / tile_length_au;
+                if count < 2 {
+                    return (vec!(Au(0)), tile_length)
+                }
+                let gap = (extent - tile_length * count) / (count - 1);
+                ((0..count).map(|index| (tile_length + gap) * index).collect(), tile_length)
+            }
+        }
+    }
+}
+
+/// How an image is turned to be shown upright, as recorded for example in the EXIF data of
+/// photos. The image is rotated clockwise and then, if `flipped` is set, mirrored horizontally.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ImageOrientation {
+    /// The clockwise rotation, in quarter turns.
+    pub quarter_turns: u8,
+    pub flipped: bool,
+}
+
+impl ImageOrientation {
+    /// The orientation of images that are stored upright.
+    pub fn identity() -> ImageOrientation {
+        ImageOrientation {
+            quarter_turns: 0,
+            flipped: false,
+        }
+    }
+
+    /// Returns the orientation described by the value of an EXIF `Orientation` tag. Invalid
+    /// values describe upright images.
+    pub fn from_exif(value: u16) -> ImageOrientation {
+        let (quarter_turns, flipped) = match value {
+            2 => (0, true),
+            3 => (2, false),
+            4 => (2, true),
+            5 => (1, true),
+            6 => (1, false),
+            7 => (3, true),
+            8 => (3, false),
+            _ => (0, false),
+        };
+        ImageOrientation {
+            quarter_turns: quarter_turns,
+            flipped: flipped,
+        }
+    }
+
+    /// Whether the width and height of the image are swapped when it is shown.
+    pub fn swaps_dimensions(&self) -> bool {
+        self.quarter_turns % 2 == 1
+    }
+
+    /// Returns the transform that maps an image drawn at the origin in its stored orientation
+    /// onto `dest_rect`, in which it is shown upright.
+    pub fn transform(&self, dest_rect: &Rect<f32>) -> Matrix2D<f32> {
+        let (width, height) = (dest_rect.size.width, dest_rect.size.height);
+        let (m11, m12, m21, m22, m31, m32) = match self.quarter_turns % 4 {
+            0 => (1.0, 0.0, 0.0, 1.0, 0.0, 0.0),
+            1 => (0.0, 1.0, -1.0, 0.0, width, 0.0),
+            2 => (-1.0, 0.0, 0.0, -1.0, width, height),
+            _ => (0.0, -1.0, 1.0, 0.0, 0.0, height),
+        };
+
+        // Mirroring horizontally after the rotation maps `x` to `width - x`.
+        let (m11, m21, m31) = if self.flipped {
+            (-m11, -m21, width - m31)
+        } else {
+            (m11, m21, m31)
+        };
+        Matrix2D::new(m11, m12, m21, m22, m31 + dest_rect.origin.x, m32 + dest_rect.origin.y)
+    }
+}
+
+/// The frames of an animated image, such as an animated GIF or APNG, and when its animation
+/// started.
+#[derive(Clone)]
+pub struct ImageAnimation {
+    pub frames: Arc<Vec<ImageFrame>>,
+    /// The time at which the first frame was shown, in nanoseconds as returned by
+    /// `time::precise_time_ns()`.
+    pub start_time: u64,
+    /// Whether the animation loops forever instead of stopping at its last frame.
+    pub repeat: bool,
+}
+
+impl ImageAnimation {
+    /// Returns the animation of the image, which starts when the image was decoded, or `None` if
+    /// the image isn't animated.
+    pub fn from_image(image: &Image) -> Option<ImageAnimation> {
+        image.animation.as_ref().map(ImageAnimation::from_frames)
+    }
+
+    /// Returns the animation of the frames of an image, which starts when they were decoded.
+    pub fn from_frames(animation: &ImageFrames) -> ImageAnimation {
+        ImageAnimation {
+            frames: animation.frames.clone(),
+            start_time: animation.decode_time,
+            repeat: animation.repeat,
+        }
+    }
+
+    /// Returns the index of the frame shown at `time` and the time at which the frame after it is
+    /// due, or `None` if the animation has no more frames to show.
+    pub fn frame_at(&self, time: u64) -> (usize, Option<u64>) {
+        if self.frames.len() < 2 {
+            return (0, None)
+        }
+
+        let cycle = self.frames.iter().fold(0, |sum, frame| sum + frame_duration(frame));
+        let elapsed = if time > self.start_time { time - self.start_time } else { 0 };
+        if !self.repeat && elapsed >= cycle {
+            return (self.frames.len() - 1, None)
+        }
+
+        let mut frame_end = self.start_time + elapsed / cycle * cycle;
+        for (index, frame) in self.frames.iter().enumerate() {
+            frame_end += frame_duration(frame);
+            if time < frame_end {
+                if !self.repeat && index == self.frames.len() - 1 {
+                    return (index, None)
+                }
+                return (index, Some(frame_end))
+            }
+        }
+        (self.frames.len() - 1, None)
+    }
+}
+
+/// Returns how long the frame is shown, in nanoseconds. Like other browsers, we show frames with
+/// delays of 10ms or less for 100ms, since many images rely on that.
+fn frame_duration(frame: &ImageFrame) -> u64 {
+    let delay = if frame.delay <= 10 { 100 } else { frame.delay };
+    delay as u64 * 1_000_000
+}
+
+/// Paints text and the lines that decorate it, converting their colors with `convert`:
+/// underlines and overlines behind the text and line-throughs over it.
+fn draw_decorated_text(paint_backend: &mut PaintBackend,
+                       text: &TextDisplayItem,
+                       convert: &Fn(Color) -> Color) {
+    for decoration in text.decorations.iter() {
+        if decoration.line != TextDecorationLine::LineThrough {
+            draw_text_decoration(paint_backend,
+                                 text,
+                                 decoration,
+                                 color::to_paint_color(convert(decoration.color)))
+        }
+    }
+
+    let text_color = convert(text.text_color);
+    if same_color(&text_color, &text.text_color) {
+        paint_backend.draw_text(text)
+    } else {
+        let mut text = text.clone();
+        text.text_color = text_color;
+        if let Some(ref mut annotation) = text.ruby_annotation {
+            annotation.text_color = convert(annotation.text_color)
+        }
+        if let Some(ref mut marks) = text.emphasis_marks {
+            marks.text_color = convert(marks.text_color)
+        }
+        paint_backend.draw_text(&text)
+    }
+
+    for decoration in text.decorations.iter() {
+        if decoration.line == TextDecorationLine::LineThrough {
+            draw_text_decoration(paint_backend,
+                                 text,
+                                 decoration,
+                                 color::to_paint_color(convert(decoration.color)))
+        }
+    }
+}
+
+/// Paints a line that decorates text in the given color. Solid and double lines are blurred with
+/// the text when it is a blurred shadow, but dotted, dashed and wavy ones aren't.
+fn draw_text_decoration(paint_backend: &mut PaintBackend,
+                        text: &TextDisplayItem,
+                        decoration: &TextDecoration,
+                        color: paint_backend::Color) {
+    for rect in text.decoration_rects(decoration).iter() {
+        match decoration.style {
+            TextDecorationStyle::Solid | TextDecorationStyle::Double => {
+                if text.blur_radius == Au(0) {
+                    paint_backend.draw_solid_color(rect, color)
+                } else {
+                    draw_box_shadow(paint_backend,
+                                    rect,
+                                    &ZERO_POINT,
+                                    color,
+                                    text.blur_radius,
+                                    Au(0),
+                                    BoxShadowClipMode::None);
+                    // Shadows are drawn outside of the clip, which the text is drawn in.
+                    paint_backend.set_clip(&text.base.clip)
+                }
+            }
+            TextDecorationStyle::Dotted => {
+                paint_backend.draw_line(rect, color, border_style::T::dotted)
+            }
+            TextDecorationStyle::Dashed => {
+                paint_backend.draw_line(rect, color, border_style::T::dashed)
+            }
+            TextDecorationStyle::Wavy => {
+                let vertical = text.orientation != TextOrientation::Upright;
+                fallback::draw_wavy_line(paint_backend, rect, vertical, color)
+            }
+        }
+    }
+}
+
+/// Returns the pieces of the interval from `start` to `end` that are outside all of `gaps`, in
+/// order. The gaps may overlap and come in any order.
+pub fn skip_intervals(start: Au, end: Au, gaps: &[(Au, Au)]) -> Vec<(Au, Au)> {
+    let mut gaps = gaps.to_vec();
+    gaps.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut pieces = Vec::new();
+    let mut position = start;
+    for &(gap_start, gap_end) in gaps.iter() {
+        if gap_start >= end {
+            break
+        }
+        if gap_start > position {
+            pieces.push((position, gap_start))
+        }
+        position = max(position, gap_end)
+    }
+    if position < end {
+        pieces.push((position, end))
+    }
+    pieces
+}
+
+/// Paints a box shadow with the backend's own blur or, if the backend blurs that much slowly or
+/// not at all, an approximate one.
+fn draw_box_shadow(paint_backend: &mut PaintBackend,
+                   box_bounds: &Rect<Au>,
+                   offset: &Point2D<Au>,
+                   color: paint_backend::Color,
+                   blur_radius: Au,
+                   spread_radius: Au,
+                   clip_mode: BoxShadowClipMode) {
+    let std_deviation = blur_radius.to_frac32_px() * fallback::device_scale(paint_backend);
+    if paint_backend.capabilities().approximates_blur(std_deviation) {
+        fallback::draw_box_shadow(paint_backend,
+                                  box_bounds,
+                                  offset,
+                                  color,
+                                  blur_radius,
+                                  spread_radius,
+                                  clip_mode)
+    } else {
+        paint_backend.draw_box_shadow(box_bounds,
+                                      offset,
+                                      color,
+                                      blur_radius,
+                                      spread_radius,
+                                      clip_mode)
+    }
+}
+
+fn same_color(a: &Color, b: &Color) -> bool {
+    a.r == b.r && a.g == b.g && a.b == b.b && a.a == b.a
+}
+
+fn same_stops(a: &[GradientStop], b: &[GradientStop]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| {
+        a.offset == b.offset && same_color(&a.color, &b.color)
+    })
+}
+
 /// Paints a gradient.
 #[derive(Clone)]
 pub struct GradientDisplayItem {
@@ -883,6 +2547,11 @@ pub struct GradientDisplayItem {
 
     /// A list of color stops.
     pub stops: Vec<GradientStop>,
+
+    /// Whether the stops repeat along the whole gradient line, as for
+    /// `repeating-linear-gradient()`, rather than the end stops being extended beyond the start
+    /// and end points.
+    pub repeating: bool,
 }
 
 impl HeapSizeOf for GradientDisplayItem {
@@ -898,6 +2567,34 @@ impl HeapSizeOf for GradientDisplayItem {
     }
 }
 
+/// Paints a radial gradient, whose stops are on ellipses around a center.
+#[derive(Clone)]
+pub struct RadialGradientDisplayItem {
+    /// Fields common to all display items.
+    pub base: BaseDisplayItem,
+
+    /// The center of the gradient (computed during display list construction).
+    pub center: Point2D<Au>,
+
+    /// The horizontal and vertical radii of the ellipse that a stop at offset 1 is on. Stops at
+    /// other offsets are on the ellipse scaled by their offset.
+    pub radius: Size2D<Au>,
+
+    /// A list of color stops.
+    pub stops: Vec<GradientStop>,
+}
+
+impl HeapSizeOf for RadialGradientDisplayItem {
+    fn heap_size_of_children(&self) -> usize {
+        use libc::c_void;
+        use util::mem::heap_size_of;
+
+        // As for `GradientDisplayItem`, the stops are measured directly.
+        self.base.heap_size_of_children() +
+            heap_size_of(self.stops.as_ptr() as *const c_void)
+    }
+}
+
 
 /// Paints a border.
 #[derive(Clone)]
@@ -1022,7 +2719,93 @@ pub enum BoxShadowClipMode {
 
 pub enum DisplayItemIterator<'a> {
     Empty,
-    Parent(linked_list::Iter<'a,DisplayItem>),
+    Parent(slice::Iter<'a,DisplayItem>),
+}
+
+/// An iterator over the items of all of the sections of a display list.
+pub struct DisplayItems<'a> {
+    /// The iterators over the sections that are left, with the current one last.
+    sections: Vec<slice::Iter<'a, DisplayItem>>,
+}
+
+impl<'a> Iterator for DisplayItems<'a> {
+    type Item = &'a DisplayItem;
+    fn next(&mut self) -> Option<&'a DisplayItem> {
+        loop {
+            match self.sections.last_mut() {
+                Some(section) => {
+                    if let Some(item) = section.next() {
+                        return Some(item)
+                    }
+                }
+                None => return None,
+            }
+            self.sections.pop();
+        }
+    }
+}
+
+/// An iterator over the items of all of the sections of a display list that lets them be changed.
+pub struct DisplayItemsMut<'a> {
+    /// The iterators over the sections that are left, with the current one last.
+    sections: Vec<slice::IterMut<'a, DisplayItem>>,
+}
+
+impl<'a> Iterator for DisplayItemsMut<'a> {
+    type Item = &'a mut DisplayItem;
+    fn next(&mut self) -> Option<&'a mut DisplayItem> {
+        loop {
+            match self.sections.last_mut() {
+                Some(section) => {
+                    if let Some(item) = section.next() {
+                        return Some(item)
+                    }
+                }
+                None => return None,
+            }
+            self.sections.pop();
+        }
+    }
+}
+
+/// Something that a display list is painted from, as taken out of it by `into_iter()`.
+pub enum PaintOrderEntry {
+    /// A display item, painted in turn.
+    Item(DisplayItem),
+    /// A stacking context in the display list, whose contents are painted in turn.
+    StackingContext(Arc<StackingContext>),
+}
+
+/// An iterator that takes a display list apart in the order that it is painted in.
+pub struct IntoPaintOrder {
+    /// The stages that are left, with the current one last.
+    stages: Vec<PaintOrderStage>,
+}
+
+enum PaintOrderStage {
+    Items(vec::IntoIter<DisplayItem>),
+    StackingContexts(vec::IntoIter<Arc<StackingContext>>),
+}
+
+impl Iterator for IntoPaintOrder {
+    type Item = PaintOrderEntry;
+    fn next(&mut self) -> Option<PaintOrderEntry> {
+        loop {
+            let entry = match self.stages.last_mut() {
+                Some(&mut PaintOrderStage::Items(ref mut items)) => {
+                    items.next().map(PaintOrderEntry::Item)
+                }
+                Some(&mut PaintOrderStage::StackingContexts(ref mut children)) => {
+                    children.next().map(PaintOrderEntry::StackingContext)
+                }
+                None => return None,
+            };
+            if entry.is_some() {
+                return entry
+            }
+            self.stages.pop();
+        }
+    }
 }
 
 impl<'a> Iterator for DisplayItemIterator<'a> {
@@ -1037,26 +2820,52 @@ impl<'a> Iterator for DisplayItemIterator<'a> {
 }
 
 impl DisplayItem {
-    /// Paints this display item into the given painting context.
-    fn draw_into_context(&self, paint_context: &mut PaintContext) {
-        {
-            let this_clip = &self.base().clip;
-            match paint_context.transient_clip {
-                Some(ref transient_clip) if transient_clip == this_clip => {}
-                Some(_) | None => paint_context.push_transient_clip((*this_clip).clone()),
-            }
+    /// Paints this display item with the given painting backend, unless painting its kind of item
+    /// is skipped, timing it if the costs of items are being attributed, and labels it with its
+    /// place in the paint order if asked to.
+    fn draw_into_context(&self, paint_backend: &mut PaintBackend) {
+        if skipped_painting::is_item_skipped(self) {
+            return
         }
+        item_costs::record(self, || self.draw_untimed(paint_backend));
+        paint_order::label(self, paint_backend)
+    }
+
+    fn draw_untimed(&self, paint_backend: &mut PaintBackend) {
+        paint_backend.begin_display_item(self);
+        paint_backend.set_clip(&self.base().clip);
+
+        if opts::get().show_item_boundaries {
+            return self.draw_boundary(paint_backend)
+        }
+
+        let color_space = self.base().color_space;
+        let surface_color_space = paint_backend.color_space();
+        let convert = |color: Color| color::convert(color, color_space, surface_color_space);
+        let paint_color = |color: Color| color::to_paint_color(convert(color));
+        let convert_stops = |stops: &[GradientStop]| -> Vec<paint_backend::GradientStop> {
+            stops.iter().map(|stop| {
+                paint_backend::GradientStop {
+                    offset: stop.offset,
+                    color: paint_color(stop.color),
+                }
+            }).collect()
+        };
 
         match *self {
             DisplayItem::SolidColorClass(ref solid_color) => {
                 if !solid_color.color.a.approx_eq(&0.0) {
-                    paint_context.draw_solid_color(&solid_color.base.bounds, solid_color.color)
+                    paint_backend.draw_solid_color(&solid_color.base.bounds,
+                                                   paint_color(solid_color.color))
                 }
             }
 
             DisplayItem::TextClass(ref text) => {
                 debug!("Drawing text at {:?}.", text.base.bounds);
-                paint_context.draw_text(&**text);
+                for shadow in text.shadows.iter().rev() {
+                    draw_decorated_text(paint_backend, &text.shadow_item(shadow), &convert)
+                }
+                draw_decorated_text(paint_backend, &**text, &convert)
             }
 
             DisplayItem::ImageClass(ref image_item) => {
@@ -1064,53 +2873,135 @@ impl DisplayItem {
                 // brush instead.
                 debug!("Drawing image at {:?}.", image_item.base.bounds);
 
-                let mut y_offset = Au(0);
-                while y_offset < image_item.base.bounds.size.height {
-                    let mut x_offset = Au(0);
-                    while x_offset < image_item.base.bounds.size.width {
+                let (x_offsets, tile_width) =
+                    image_item.horizontal_repeat.tiles(image_item.base.bounds.size.width,
+                                                       image_item.stretch_size.width);
+                let (y_offsets, tile_height) =
+                    image_item.vertical_repeat.tiles(image_item.base.bounds.size.height,
+                                                     image_item.stretch_size.height);
+                let device_size = paint_backend.device_size(
+                    &Rect(ZERO_POINT, Size2D(tile_width, tile_height)).to_azure_rect());
+                let device_size = Size2D(device_size.width.ceil() as u32,
+                                         device_size.height.ceil() as u32);
+                let image = match image_item.image_at(time::precise_time_ns(),
+                                                      &device_size,
+                                                      paint_backend.image_generators()) {
+                    Some(image) => image,
+                    None => return,
+                };
+                for y_offset in y_offsets.iter() {
+                    for x_offset in x_offsets.iter() {
                         let mut bounds = image_item.base.bounds;
-                        bounds.origin.x = bounds.origin.x + x_offset;
-                        bounds.origin.y = bounds.origin.y + y_offset;
-                        bounds.size = image_item.stretch_size;
-
-                        paint_context.draw_image(&bounds,
-                                                 image_item.image.clone(),
-                                                 image_item.image_rendering.clone());
-
-                        x_offset = x_offset + image_item.stretch_size.width;
+                        bounds.origin.x = bounds.origin.x + *x_offset;
+                        bounds.origin.y = bounds.origin.y + *y_offset;
+                        bounds.size = Size2D(tile_width, tile_height);
+
+                        paint_backend.draw_image(&bounds,
+                                                 image.clone(),
+                                                 image_item.source_rect,
+                                                 image_item.image_rendering.clone(),
+                                                 image_item.orientation);
                     }
-
-                    y_offset = y_offset + image_item.stretch_size.height;
                 }
             }
 
             DisplayItem::BorderClass(ref border) => {
-                paint_context.draw_border(&border.base.bounds,
+                let color = SideOffsets2D::new(paint_color(border.color.top),
+                                               paint_color(border.color.right),
+                                               paint_color(border.color.bottom),
+                                               paint_color(border.color.left));
+                paint_backend.draw_border(&border.base.bounds,
                                           &border.border_widths,
                                           &border.radius,
-                                          &border.color,
+                                          &color,
                                           &border.style)
             }
 
             DisplayItem::GradientClass(ref gradient) => {
-                paint_context.draw_linear_gradient(&gradient.base.bounds,
+                let stops = convert_stops(&gradient.stops);
+                if paint_backend.capabilities().linear_gradients {
+                    paint_backend.draw_linear_gradient(&gradient.base.bounds,
+                                                       &gradient.start_point,
+                                                       &gradient.end_point,
+                                                       &stops,
+                                                       gradient.repeating);
+                } else {
+                    fallback::draw_linear_gradient(paint_backend,
+                                                   &gradient.base.bounds,
                                                    &gradient.start_point,
                                                    &gradient.end_point,
-                                                   &gradient.stops);
+                                                   &stops,
+                                                   gradient.repeating);
+                }
+            }
+
+            DisplayItem::RadialGradientClass(ref gradient) => {
+                let stops = convert_stops(&gradient.stops);
+                let radius = &gradient.radius;
+                if radius.width <= Au(0) || radius.height <= Au(0) {
+                    // A gradient with no area is painted with the color of its last stop.
+                    if let Some(last) = stops.last() {
+                        paint_backend.draw_solid_color(&gradient.base.bounds, last.color)
+                    }
+                } else if paint_backend.capabilities().radial_gradients {
+                    paint_backend.draw_radial_gradient(&gradient.base.bounds,
+                                                       &gradient.center,
+                                                       radius,
+                                                       &stops);
+                } else {
+                    fallback::draw_radial_gradient(paint_backend,
+                                                   &gradient.base.bounds,
+                                                   &gradient.center,
+                                                   radius,
+                                                   &stops);
+                }
             }
 
             DisplayItem::LineClass(ref line) => {
-                paint_context.draw_line(&line.base.bounds, line.color, line.style)
+                paint_backend.draw_line(&line.base.bounds, paint_color(line.color), line.style)
             }
 
             DisplayItem::BoxShadowClass(ref box_shadow) => {
-                paint_context.draw_box_shadow(&box_shadow.box_bounds,
-                                              &box_shadow.offset,
-                                              box_shadow.color,
-                                              box_shadow.blur_radius,
-                                              box_shadow.spread_radius,
-                                              box_shadow.clip_mode)
+                draw_box_shadow(paint_backend,
+                                &box_shadow.box_bounds,
+                                &box_shadow.offset,
+                                paint_color(box_shadow.color),
+                                box_shadow.blur_radius,
+                                box_shadow.spread_radius,
+                                box_shadow.clip_mode)
             }
+
+            // The compositor draws the texture itself.
+            DisplayItem::ExternalTextureClass(_) => {}
+
+            // The compositor draws the layers of the document in the iframe over it.
+            DisplayItem::IframeClass(_) => {}
+        }
+    }
+
+    /// Fills the bounds of the item with a color that comes from its node and outlines them,
+    /// instead of painting the item, for `-Z show-item-boundaries`. Each node keeps its color, so
+    /// the same page looks the same each time, and items that are painted later cover the ones
+    /// before them.
+    fn draw_boundary(&self, paint_backend: &mut PaintBackend) {
+        let mut hasher = SipHasher::new();
+        self.base().metadata.node.hash(&mut hasher);
+        let hash = hasher.finish();
+        let component = |shift: u64| ((hash >> shift) & 0xff) as AzFloat / 255.0;
+        let (r, g, b) = (component(0), component(8), component(16));
+        let bounds = &self.base().bounds;
+        paint_backend.draw_solid_color(bounds, paint_backend::Color::new(r, g, b, 1.0));
+
+        let width = Au::from_px(1);
+        let outline_color = paint_backend::Color::new(r * 0.5, g * 0.5, b * 0.5, 1.0);
+        let (left, top) = (bounds.origin.x, bounds.origin.y);
+        for side in [
+            Rect(bounds.origin, Size2D(bounds.size.width, width)),
+            Rect(Point2D(left, bounds.max_y() - width), Size2D(bounds.size.width, width)),
+            Rect(bounds.origin, Size2D(width, bounds.size.height)),
+            Rect(Point2D(bounds.max_x() - width, top), Size2D(width, bounds.size.height)),
+        ].iter() {
+            paint_backend.draw_solid_color(side, outline_color)
         }
     }
 
@@ -1121,8 +3012,11 @@ impl DisplayItem {
             DisplayItem::ImageClass(ref image_item) => &image_item.base,
             DisplayItem::BorderClass(ref border) => &border.base,
             DisplayItem::GradientClass(ref gradient) => &gradient.base,
+            DisplayItem::RadialGradientClass(ref gradient) => &gradient.base,
             DisplayItem::LineClass(ref line) => &line.base,
             DisplayItem::BoxShadowClass(ref box_shadow) => &box_shadow.base,
+            DisplayItem::ExternalTextureClass(ref texture) => &texture.base,
+            DisplayItem::IframeClass(ref iframe) => &iframe.base,
         }
     }
 
@@ -1133,8 +3027,11 @@ impl DisplayItem {
             DisplayItem::ImageClass(ref mut image_item) => &mut image_item.base,
             DisplayItem::BorderClass(ref mut border) => &mut border.base,
             DisplayItem::GradientClass(ref mut gradient) => &mut gradient.base,
+            DisplayItem::RadialGradientClass(ref mut gradient) => &mut gradient.base,
             DisplayItem::LineClass(ref mut line) => &mut line.base,
             DisplayItem::BoxShadowClass(ref mut box_shadow) => &mut box_shadow.base,
+            DisplayItem::ExternalTextureClass(ref mut texture) => &mut texture.base,
+            DisplayItem::IframeClass(ref mut iframe) => &mut iframe.base,
         }
     }
 
@@ -1142,6 +3039,237 @@ impl DisplayItem {
         self.base().bounds
     }
 
+    /// Returns the rect that this item may paint pixels in, which is more than its bounds when it
+    /// is blurred or is a shadow that is offset or spread from its box.
+    pub fn ink_bounds(&self) -> Rect<Au> {
+        match *self {
+            DisplayItem::TextClass(ref text) => text.ink_bounds(),
+            DisplayItem::BoxShadowClass(ref box_shadow) => {
+                match box_shadow.clip_mode {
+                    BoxShadowClipMode::Inset => box_shadow.base.bounds,
+                    BoxShadowClipMode::None | BoxShadowClipMode::Outset => {
+                        let inflation = box_shadow.spread_radius +
+                            box_shadow.blur_radius * BLUR_INFLATION_FACTOR;
+                        let extent = box_shadow.box_bounds
+                                               .translate(&box_shadow.offset)
+                                               .inflate(inflation, inflation);
+                        box_shadow.base.bounds.union(&extent)
+                    }
+                }
+            }
+            _ => self.base().bounds,
+        }
+    }
+
+    /// Returns how this item is moved onto whole pixels when its stacking context is made.
+    pub fn pixel_snapping(&self) -> PixelSnapping {
+        match *self {
+            DisplayItem::SolidColorClass(_) |
+            DisplayItem::ImageClass(_) |
+            DisplayItem::BorderClass(_) |
+            DisplayItem::LineClass(_) |
+            DisplayItem::BoxShadowClass(_) |
+            DisplayItem::ExternalTextureClass(_) |
+            DisplayItem::IframeClass(_) => PixelSnapping::Both,
+            DisplayItem::TextClass(_) => PixelSnapping::VerticalOnly,
+            DisplayItem::GradientClass(_) |
+            DisplayItem::RadialGradientClass(_) => PixelSnapping::None,
+        }
+    }
+
+    /// Moves the edges of this item onto whole device pixels, of which there are
+    /// `device_pixels_per_px` in a px, as the policy says.
+    pub fn snap_to_pixels(&mut self, snapping: PixelSnapping, device_pixels_per_px: f32) {
+        let snap = |au: Au| snap(au, device_pixels_per_px);
+        match snapping {
+            PixelSnapping::None => {}
+            PixelSnapping::Both => {
+                {
+                    let base = self.mut_base();
+                    base.bounds = snap_rect(&base.bounds, device_pixels_per_px);
+                    base.clip = base.clip.snap_to_pixels(device_pixels_per_px)
+                }
+                match *self {
+                    DisplayItem::BorderClass(ref mut border) => {
+                        // Thin borders stay one device pixel wide rather than vanishing.
+                        let device_pixel = Au::from_frac32_px(1.0 / device_pixels_per_px);
+                        let snap_width = |width: Au| {
+                            if width == Au(0) {
+                                width
+                            } else {
+                                max(snap(width), device_pixel)
+                            }
+                        };
+                        let widths = &mut border.border_widths;
+                        widths.top = snap_width(widths.top);
+                        widths.right = snap_width(widths.right);
+                        widths.bottom = snap_width(widths.bottom);
+                        widths.left = snap_width(widths.left);
+                    }
+                    DisplayItem::BoxShadowClass(ref mut box_shadow) => {
+                        box_shadow.box_bounds = snap_rect(&box_shadow.box_bounds,
+                                                          device_pixels_per_px)
+                    }
+                    _ => {}
+                }
+            }
+            PixelSnapping::VerticalOnly => {
+                // Text is moved by its baseline, and its bounds with it.
+                let delta = match *self {
+                    DisplayItem::TextClass(ref mut text) => {
+                        let y = text.baseline_origin.y;
+                        text.baseline_origin.y = snap(y);
+                        Some(text.baseline_origin.y - y)
+                    }
+                    _ => None,
+                };
+                let base = self.mut_base();
+                let y = base.bounds.origin.y;
+                base.bounds.origin.y = match delta {
+                    Some(delta) => y + delta,
+                    None => snap(y),
+                };
+                base.clip = base.clip.snap_to_pixels(device_pixels_per_px)
+            }
+        }
+    }
+
+    /// Moves this item by `delta`.
+    pub fn translate(&mut self, delta: &Point2D<Au>) {
+        {
+            let base = self.mut_base();
+            base.bounds = base.bounds.translate(delta);
+            base.clip = base.clip.translate(delta);
+            for region in base.cursor_regions.iter_mut() {
+                region.rect = region.rect.translate(delta)
+            }
+        }
+        match *self {
+            DisplayItem::TextClass(ref mut text) => {
+                text.baseline_origin = text.baseline_origin + *delta
+            }
+            DisplayItem::GradientClass(ref mut gradient) => {
+                gradient.start_point = gradient.start_point + *delta;
+                gradient.end_point = gradient.end_point + *delta;
+            }
+            DisplayItem::RadialGradientClass(ref mut gradient) => {
+                gradient.center = gradient.center + *delta
+            }
+            DisplayItem::BoxShadowClass(ref mut box_shadow) => {
+                box_shadow.box_bounds = box_shadow.box_bounds.translate(delta)
+            }
+            DisplayItem::SolidColorClass(_) |
+            DisplayItem::ImageClass(_) |
+            DisplayItem::BorderClass(_) |
+            DisplayItem::LineClass(_) |
+            DisplayItem::ExternalTextureClass(_) |
+            DisplayItem::IframeClass(_) => {}
+        }
+    }
+
+    /// Returns true if this item may paint some of `rect`, taking its clip into account.
+    pub fn might_intersect_rect(&self, rect: &Rect<Au>) -> bool {
+        rect.intersects(&self.ink_bounds()) && self.base().clip.might_intersect_rect(rect)
+    }
+
+    /// Returns true if this item is sure to paint the same pixels as `other`. Text is compared by
+    /// the characters and glyphs that it paints, not by which text run it comes from. Images, ruby
+    /// annotations and emphasis marks are never taken to be the same, since telling would cost
+    /// about as much as painting them.
+    pub fn paints_same_as(&self, other: &DisplayItem) -> bool {
+        if self.base().bounds != other.base().bounds || self.base().clip != other.base().clip ||
+                self.base().color_space != other.base().color_space {
+            return false
+        }
+
+        match (self, other) {
+            (&DisplayItem::SolidColorClass(ref a), &DisplayItem::SolidColorClass(ref b)) => {
+                same_color(&a.color, &b.color)
+            }
+            (&DisplayItem::TextClass(ref a), &DisplayItem::TextClass(ref b)) => {
+                a.range == b.range &&
+                    a.text_run.paints_same_as(&**b.text_run, &a.range) &&
+                    same_color(&a.text_color, &b.text_color) &&
+                    a.baseline_origin == b.baseline_origin &&
+                    a.orientation == b.orientation &&
+                    a.blur_radius == b.blur_radius &&
+                    a.shadows.len() == b.shadows.len() &&
+                    a.shadows.iter().zip(b.shadows.iter()).all(|(a, b)| {
+                        a.offset == b.offset && a.blur_radius == b.blur_radius &&
+                            same_color(&a.color, &b.color)
+                    }) &&
+                    a.decorations.len() == b.decorations.len() &&
+                    a.decorations.iter().zip(b.decorations.iter()).all(|(a, b)| {
+                        a.line == b.line && a.style == b.style &&
+                            same_color(&a.color, &b.color) && a.skip_ink == b.skip_ink
+                    }) &&
+                    a.ruby_annotation.is_none() &&
+                    b.ruby_annotation.is_none() &&
+                    a.emphasis_marks.is_none() &&
+                    b.emphasis_marks.is_none()
+            }
+            (&DisplayItem::BorderClass(ref a), &DisplayItem::BorderClass(ref b)) => {
+                a.border_widths == b.border_widths &&
+                    same_color(&a.color.top, &b.color.top) &&
+                    same_color(&a.color.right, &b.color.right) &&
+                    same_color(&a.color.bottom, &b.color.bottom) &&
+                    same_color(&a.color.left, &b.color.left) &&
+                    a.style == b.style &&
+                    a.radius == b.radius
+            }
+            (&DisplayItem::GradientClass(ref a), &DisplayItem::GradientClass(ref b)) => {
+                a.start_point == b.start_point &&
+                    a.end_point == b.end_point &&
+                    same_stops(&a.stops, &b.stops) &&
+                    a.repeating == b.repeating
+            }
+            (&DisplayItem::RadialGradientClass(ref a),
+             &DisplayItem::RadialGradientClass(ref b)) => {
+                a.center == b.center && a.radius == b.radius && same_stops(&a.stops, &b.stops)
+            }
+            (&DisplayItem::LineClass(ref a), &DisplayItem::LineClass(ref b)) => {
+                same_color(&a.color, &b.color) && a.style == b.style
+            }
+            (&DisplayItem::BoxShadowClass(ref a), &DisplayItem::BoxShadowClass(ref b)) => {
+                a.box_bounds == b.box_bounds &&
+                    a.offset == b.offset &&
+                    same_color(&a.color, &b.color) &&
+                    a.blur_radius == b.blur_radius &&
+                    a.spread_radius == b.spread_radius &&
+                    a.clip_mode == b.clip_mode
+            }
+            (&DisplayItem::ExternalTextureClass(ref a),
+             &DisplayItem::ExternalTextureClass(ref b)) => a.texture == b.texture,
+            (&DisplayItem::IframeClass(ref a), &DisplayItem::IframeClass(ref b)) => {
+                a.pipeline_id == b.pipeline_id && a.subpage_id == b.subpage_id
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the part of this item that it is sure to paint with fully opaque pixels, if any.
+    /// Items with rounded clips are never considered opaque.
+    pub fn opaque_rect(&self) -> Option<Rect<Au>> {
+        let base = self.base();
+        if !base.clip.complex.is_empty() {
+            return None
+        }
+
+        let opaque_rect = match *self {
+            DisplayItem::SolidColorClass(ref solid_color) if solid_color.color.a == 1.0 => {
+                base.bounds
+            }
+            DisplayItem::ImageClass(ref image_item) => {
+                match image_item.opaque_rect() {
+                    Some(opaque_rect) => opaque_rect,
+                    None => return None,
+                }
+            }
+            _ => return None,
+        };
+        opaque_rect.intersection(&base.clip.main)
+    }
+
     pub fn debug_with_level(&self, level: u32) {
         let mut indent = String::new();
         for _ in 0..level {
@@ -1153,19 +3281,100 @@ impl DisplayItem {
 
 impl fmt::Debug for DisplayItem {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} @ {:?} ({:x})",
+        try!(write!(f, "{} @ {:?} ({:x})",
             match *self {
                 DisplayItem::SolidColorClass(_) => "SolidColor",
                 DisplayItem::TextClass(_) => "Text",
                 DisplayItem::ImageClass(_) => "Image",
                 DisplayItem::BorderClass(_) => "Border",
                 DisplayItem::GradientClass(_) => "Gradient",
+                DisplayItem::RadialGradientClass(_) => "RadialGradient",
                 DisplayItem::LineClass(_) => "Line",
                 DisplayItem::BoxShadowClass(_) => "BoxShadow",
+                DisplayItem::ExternalTextureClass(_) => "ExternalTexture",
+                DisplayItem::IframeClass(_) => "Iframe",
             },
             self.base().bounds,
             self.base().metadata.node.id()
-        )
+        ));
+
+        match *self {
+            DisplayItem::SolidColorClass(ref solid_color) => {
+                try!(write!(f, " {:?}", ColorSummary(&solid_color.color)))
+            }
+            DisplayItem::TextClass(ref text) => {
+                let range = &text.range;
+                try!(write!(f,
+                            " {:?} {:?}",
+                            text.text_run.text.slice_chars(range.begin().to_usize(),
+                                                           range.end().to_usize()),
+                            ColorSummary(&text.text_color)))
+            }
+            DisplayItem::ImageClass(ref image) => {
+                try!(write!(f, " stretched to {:?}", image.stretch_size))
+            }
+            DisplayItem::BorderClass(ref border) => {
+                try!(write!(f,
+                            " {:?} {:?} {:?} {:?}, top {:?}",
+                            border.border_widths.top,
+                            border.border_widths.right,
+                            border.border_widths.bottom,
+                            border.border_widths.left,
+                            ColorSummary(&border.color.top)))
+            }
+            DisplayItem::GradientClass(ref gradient) => {
+                try!(write!(f,
+                            " from {:?} to {:?}, {} stops{}",
+                            gradient.start_point,
+                            gradient.end_point,
+                            gradient.stops.len(),
+                            if gradient.repeating { ", repeating" } else { "" }))
+            }
+            DisplayItem::RadialGradientClass(ref gradient) => {
+                try!(write!(f,
+                            " at {:?}, radii {:?}, {} stops",
+                            gradient.center,
+                            gradient.radius,
+                            gradient.stops.len()))
+            }
+            DisplayItem::LineClass(ref line) => {
+                try!(write!(f, " {:?} {:?}", line.style, ColorSummary(&line.color)))
+            }
+            DisplayItem::BoxShadowClass(ref box_shadow) => {
+                try!(write!(f,
+                            " {:?} offset {:?}, blur {:?}, spread {:?}, {:?}",
+                            ColorSummary(&box_shadow.color),
+                            box_shadow.offset,
+                            box_shadow.blur_radius,
+                            box_shadow.spread_radius,
+                            box_shadow.clip_mode))
+            }
+            DisplayItem::ExternalTextureClass(ref texture) => {
+                try!(write!(f, " texture {}", texture.texture.0))
+            }
+            DisplayItem::IframeClass(ref iframe) => {
+                try!(write!(f, " subpage {}", iframe.subpage_id.0))
+            }
+        }
+
+        let clip = &self.base().clip;
+        if clip.main != MAX_RECT {
+            try!(write!(f, ", clipped to {:?}", clip.main));
+        }
+        if !clip.complex.is_empty() {
+            try!(write!(f, " and {} rounded rects", clip.complex.len()));
+        }
+        Ok(())
+    }
+}
+
+/// Shows a color as its components.
+struct ColorSummary<'a>(&'a Color);
+
+impl<'a> fmt::Debug for ColorSummary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let color = self.0;
+        write!(f, "rgba({}, {}, {}, {})", color.r, color.g, color.b, color.a)
     }
 }
 
@@ -1177,8 +3386,11 @@ impl HeapSizeOf for DisplayItem {
             ImageClass(ref item)      => item.heap_size_of_children(),
             BorderClass(ref item)     => item.heap_size_of_children(),
             GradientClass(ref item)   => item.heap_size_of_children(),
+            RadialGradientClass(ref item) => item.heap_size_of_children(),
             LineClass(ref item)       => item.heap_size_of_children(),
             BoxShadowClass(ref item)  => item.heap_size_of_children(),
+            ExternalTextureClass(ref item) => item.heap_size_of_children(),
+            IframeClass(ref item)     => item.heap_size_of_children(),
         }
     }
 }
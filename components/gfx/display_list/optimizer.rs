@@ -4,11 +4,10 @@
 
 //! Transforms a display list to produce a visually-equivalent, but cheaper-to-paint, one.
 
-use display_list::{DisplayItem, DisplayList, StackingContext};
+use display_list::{DISPLAY_LIST_SECTIONS, DisplayItem, DisplayList, StackingContext};
 
-use collections::linked_list::LinkedList;
 use geom::rect::Rect;
-use util::geometry::{self, Au};
+use util::geometry::{self, Au, MAX_RECT};
 use std::sync::Arc;
 
 /// Transforms a display list to produce a visually-equivalent, but cheaper-to-paint, one.
@@ -26,42 +25,45 @@ impl DisplayListOptimizer {
         }
     }
 
+    /// Creates a display list optimizer that keeps every display item and stacking context.
+    pub fn keeping_everything() -> DisplayListOptimizer {
+        DisplayListOptimizer {
+            visible_rect: MAX_RECT,
+        }
+    }
+
     /// Optimizes the given display list, returning an equivalent, but cheaper-to-paint, one.
     pub fn optimize(self, display_list: &DisplayList) -> DisplayList {
         let mut result = DisplayList::new();
-        self.add_in_bounds_display_items(&mut result.background_and_borders,
-                                         display_list.background_and_borders.iter());
-        self.add_in_bounds_display_items(&mut result.block_backgrounds_and_borders,
-                                         display_list.block_backgrounds_and_borders.iter());
-        self.add_in_bounds_display_items(&mut result.floats, display_list.floats.iter());
-        self.add_in_bounds_display_items(&mut result.content, display_list.content.iter());
-        self.add_in_bounds_display_items(&mut result.outlines, display_list.outlines.iter());
+        for section in DISPLAY_LIST_SECTIONS.iter() {
+            self.add_in_bounds_display_items(result.get_mut(*section),
+                                             display_list.get(*section).iter());
+        }
         self.add_in_bounds_stacking_contexts(&mut result.children, display_list.children.iter());
         result
     }
 
     /// Adds display items that intersect the visible rect to `result_list`.
     fn add_in_bounds_display_items<'a,I>(&self,
-                                         result_list: &mut LinkedList<DisplayItem>,
+                                         result_list: &mut Vec<DisplayItem>,
                                          display_items: I)
                                          where I: Iterator<Item=&'a DisplayItem> {
         for display_item in display_items {
-            if self.visible_rect.intersects(&display_item.base().bounds) &&
-                    display_item.base().clip.might_intersect_rect(&self.visible_rect) {
-                result_list.push_back((*display_item).clone())
+            if display_item.might_intersect_rect(&self.visible_rect) {
+                result_list.push((*display_item).clone())
             }
         }
     }
 
     /// Adds child stacking contexts whose boundaries intersect the visible rect to `result_list`.
     fn add_in_bounds_stacking_contexts<'a,I>(&self,
-                                             result_list: &mut LinkedList<Arc<StackingContext>>,
+                                             result_list: &mut Vec<Arc<StackingContext>>,
                                              stacking_contexts: I)
                                              where I: Iterator<Item=&'a Arc<StackingContext>> {
         for stacking_context in stacking_contexts {
             let overflow = stacking_context.overflow.translate(&stacking_context.bounds.origin);
             if self.visible_rect.intersects(&overflow) {
-                result_list.push_back((*stacking_context).clone())
+                result_list.push((*stacking_context).clone())
             }
         }
     }
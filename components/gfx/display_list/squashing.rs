@@ -0,0 +1,121 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Squashes layers that aren't worth having back into the layers that they are in, so that pages
+//! which promote lots of stacking contexts to layers don't need a surface for each of them.
+//!
+//! A layer is squashed if it is small and other layers in the same stacking context hardly
+//! overlap it, as set with `--layer-squash-area` and `--layer-squash-overlap`. Only layers of
+//! page content that scroll with the page are squashed, since the compositor has to move the
+//! others by themselves. A squashed stacking context is painted along with the stacking context
+//! it is in, in the usual order, so what it looks like doesn't change.
+
+use display_list::StackingContext;
+use msg::compositor_msg::{LayerKind, ScrollPolicy};
+
+use geom::rect::Rect;
+use std::num::Float;
+use std::sync::Arc;
+use util::geometry::Au;
+use util::opts;
+
+/// Returns the tree of stacking contexts with the layers that aren't worth having taken out.
+/// Stacking contexts that nothing changes in are shared with the given tree.
+pub fn squash_layers(root: &Arc<StackingContext>) -> Arc<StackingContext> {
+    let thresholds = Thresholds {
+        max_area: opts::get().layer_squash_max_area as f32,
+        max_overlap: opts::get().layer_squash_max_overlap,
+    };
+    if thresholds.max_area <= 0.0 {
+        return root.clone()
+    }
+    squash(root, false, &thresholds)
+}
+
+struct Thresholds {
+    /// The largest area, in square px, of the layers to squash.
+    max_area: f32,
+    /// The largest part of the area of a layer that other layers may cover for it to be squashed.
+    max_overlap: f32,
+}
+
+/// Squashes the layers under the stacking context, and its own too if `squash_layer` is true.
+fn squash(stacking_context: &Arc<StackingContext>, squash_layer: bool, thresholds: &Thresholds)
+          -> Arc<StackingContext> {
+    // Where the kids with layers are, in the coordinates of this stacking context.
+    let layer_rects: Vec<Rect<Au>> =
+        stacking_context.display_list.children.iter().filter_map(|kid| {
+            kid.compositor_layer().map(|_| kid.overflow.translate(&kid.bounds.origin))
+        }).collect();
+
+    let mut changed = squash_layer;
+    let mut children = Vec::new();
+    let mut layer_index = 0;
+    for kid in stacking_context.display_list.children.iter() {
+        let squash_kid = if kid.compositor_layer().is_some() {
+            layer_index += 1;
+            can_squash(kid, layer_index - 1, &layer_rects, thresholds)
+        } else {
+            false
+        };
+        let new_kid = squash(kid, squash_kid, thresholds);
+        changed = changed || &*new_kid as *const StackingContext != &**kid as *const _;
+        children.push(new_kid)
+    }
+    if !changed {
+        return stacking_context.clone()
+    }
+
+    let display_list = box stacking_context.display_list.clone_with_children(children);
+    let layer = if squash_layer {
+        None
+    } else {
+        stacking_context.layer.clone()
+    };
+    Arc::new(StackingContext::new(display_list,
+                                  &stacking_context.bounds,
+                                  &stacking_context.overflow,
+                                  stacking_context.z_index,
+                                  &stacking_context.transform,
+                                  &stacking_context.perspective,
+                                  stacking_context.preserves_3d,
+                                  stacking_context.filters.clone(),
+                                  stacking_context.blend_mode,
+                                  layer,
+                                  stacking_context.device_pixels_per_px))
+}
+
+/// Returns true if the layer of the kid, the one at `layer_index` in `layer_rects`, is small
+/// enough and overlapped by the other layers little enough to be squashed.
+fn can_squash(kid: &StackingContext,
+              layer_index: usize,
+              layer_rects: &[Rect<Au>],
+              thresholds: &Thresholds)
+              -> bool {
+    match kid.layer {
+        Some(ref layer) if layer.kind == LayerKind::Content &&
+                           layer.scroll_policy == ScrollPolicy::Scrollable &&
+                           layer.scrolling.is_none() => {}
+        _ => return false,
+    }
+
+    let rect = &layer_rects[layer_index];
+    let layer_area = area(rect);
+    if layer_area > thresholds.max_area {
+        return false
+    }
+    if layer_area == 0.0 {
+        return true
+    }
+
+    let overlap = layer_rects.iter().enumerate().filter(|&(index, _)| index != layer_index)
+                                                .filter_map(|(_, other)| rect.intersection(other))
+                                                .fold(0.0, |overlap, part| overlap + area(&part));
+    overlap / layer_area <= thresholds.max_overlap
+}
+
+/// Returns the area of the rect in square px.
+fn area(rect: &Rect<Au>) -> f32 {
+    rect.size.width.to_frac32_px().max(0.0) * rect.size.height.to_frac32_px().max(0.0)
+}
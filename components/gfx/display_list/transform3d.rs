@@ -0,0 +1,200 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The 3D transforms of stacking contexts. Like `Matrix2D`, the matrices transform row vectors, so
+//! their translation is in `m41`, `m42` and `m43`, and `a.mul(&b)` applies `b` first.
+//!
+//! What a stacking context paints lies in its plane, z = 0, which a transform with perspective
+//! maps onto the page projectively. Backends only paint through affine transforms, so such a
+//! transform is painted through the affine one that matches it around the part being painted.
+//! Hit testing undoes the whole of it.
+
+use azure::AzFloat;
+use geom::matrix::{self, Matrix4};
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use std::num::Float;
+
+/// Returns the 3D transform that applies the given 2D one.
+pub fn from_2d(transform: &Matrix2D<AzFloat>) -> Matrix4<AzFloat> {
+    Matrix4::new(transform.m11, transform.m12, 0.0, 0.0,
+                 transform.m21, transform.m22, 0.0, 0.0,
+                 0.0,           0.0,           1.0, 0.0,
+                 transform.m31, transform.m32, 0.0, 1.0)
+}
+
+/// Returns the transform that drops the z of points, which flattens what is in a stacking context
+/// that doesn't preserve 3D into its plane.
+pub fn flattening() -> Matrix4<AzFloat> {
+    Matrix4::new(1.0, 0.0, 0.0, 0.0,
+                 0.0, 1.0, 0.0, 0.0,
+                 0.0, 0.0, 0.0, 0.0,
+                 0.0, 0.0, 0.0, 1.0)
+}
+
+/// Returns the transform that shows what is behind the plane z = 0 smaller and what is in front of
+/// it bigger, as it would look to a viewer `distance` in front of `origin`, as `perspective` does.
+pub fn perspective(distance: AzFloat, origin: &Point2D<AzFloat>) -> Matrix4<AzFloat> {
+    let projection = Matrix4::new(1.0, 0.0, 0.0, 0.0,
+                                  0.0, 1.0, 0.0, 0.0,
+                                  0.0, 0.0, 1.0, -1.0 / distance,
+                                  0.0, 0.0, 0.0, 1.0);
+    matrix::identity().translate(origin.x, origin.y, 0.0)
+                      .mul(&projection)
+                      .translate(-origin.x, -origin.y, 0.0)
+}
+
+pub fn is_identity(transform: &Matrix4<AzFloat>) -> bool {
+    *transform == matrix::identity()
+}
+
+/// Returns true if the transform maps the plane z = 0 affinely, so that painting through it is
+/// exact.
+pub fn is_affine(transform: &Matrix4<AzFloat>) -> bool {
+    transform.m14 == 0.0 && transform.m24 == 0.0 && transform.m44 == 1.0
+}
+
+/// Returns the entries of the matrix, a row at a time.
+pub fn entries(transform: &Matrix4<AzFloat>) -> [AzFloat; 16] {
+    [transform.m11, transform.m12, transform.m13, transform.m14,
+     transform.m21, transform.m22, transform.m23, transform.m24,
+     transform.m31, transform.m32, transform.m33, transform.m34,
+     transform.m41, transform.m42, transform.m43, transform.m44]
+}
+
+/// Returns where the transform puts the point of the plane z = 0, or `None` if it puts it behind
+/// the viewer.
+pub fn project_point(transform: &Matrix4<AzFloat>, point: &Point2D<AzFloat>)
+                     -> Option<Point2D<AzFloat>> {
+    let w = transform.m14 * point.x + transform.m24 * point.y + transform.m44;
+    if w <= 0.0 {
+        return None
+    }
+    Some(Point2D((transform.m11 * point.x + transform.m21 * point.y + transform.m41) / w,
+                 (transform.m12 * point.x + transform.m22 * point.y + transform.m42) / w))
+}
+
+/// Returns the point of the plane z = 0 that the transform puts at the given point, or `None` if
+/// there is none in front of the viewer.
+pub fn unproject_point(transform: &Matrix4<AzFloat>, point: &Point2D<AzFloat>)
+                       -> Option<Point2D<AzFloat>> {
+    // On the plane, the transform is the projective one of the rows and columns for x, y and w,
+    // so invert that. Its adjugate is its inverse up to a factor, which the division cancels.
+    let (a, b, c) = (transform.m11, transform.m12, transform.m14);
+    let (d, e, f) = (transform.m21, transform.m22, transform.m24);
+    let (g, h, i) = (transform.m41, transform.m42, transform.m44);
+    let determinant = a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g);
+    if determinant == 0.0 || !determinant.is_finite() {
+        return None
+    }
+    let x = point.x * (e * i - f * h) + point.y * (f * g - d * i) + (d * h - e * g);
+    let y = point.x * (c * h - b * i) + point.y * (a * i - c * g) + (b * g - a * h);
+    let w = point.x * (b * f - c * e) + point.y * (c * d - a * f) + (a * e - b * d);
+    if w == 0.0 {
+        return None
+    }
+    let result = Point2D(x / w, y / w);
+    project_point(transform, &result).map(|_| result)
+}
+
+/// Returns the bounds of where the transform puts the rect of the plane z = 0, or `None` if any of
+/// it goes behind the viewer.
+pub fn transform_rect(transform: &Matrix4<AzFloat>, rect: &Rect<AzFloat>)
+                      -> Option<Rect<AzFloat>> {
+    let corners = [rect.origin,
+                   Point2D(rect.max_x(), rect.origin.y),
+                   Point2D(rect.origin.x, rect.max_y()),
+                   Point2D(rect.max_x(), rect.max_y())];
+    let mut points = Vec::with_capacity(4);
+    for corner in corners.iter() {
+        match project_point(transform, corner) {
+            Some(point) => points.push(point),
+            None => return None,
+        }
+    }
+    let (mut left, mut top) = (points[0].x, points[0].y);
+    let (mut right, mut bottom) = (left, top);
+    for point in points.iter() {
+        left = left.min(point.x);
+        top = top.min(point.y);
+        right = right.max(point.x);
+        bottom = bottom.max(point.y);
+    }
+    Some(Rect(Point2D(left, top), Size2D(right - left, bottom - top)))
+}
+
+/// Returns the 2D transform to paint the plane z = 0 through, or `None` if the transform puts the
+/// part of it around `around` behind the viewer. For a transform with perspective, this is the
+/// affine transform that agrees with it at `around` and changes at the same rate there, so it is
+/// exact there and strays from it away from there.
+pub fn to_2d(transform: &Matrix4<AzFloat>, around: &Point2D<AzFloat>)
+             -> Option<Matrix2D<AzFloat>> {
+    if is_affine(transform) {
+        return Some(Matrix2D::new(transform.m11,
+                                  transform.m12,
+                                  transform.m21,
+                                  transform.m22,
+                                  transform.m41,
+                                  transform.m42))
+    }
+
+    let projected = match project_point(transform, around) {
+        Some(projected) => projected,
+        None => return None,
+    };
+    let w = transform.m14 * around.x + transform.m24 * around.y + transform.m44;
+    let m11 = (transform.m11 - projected.x * transform.m14) / w;
+    let m12 = (transform.m12 - projected.y * transform.m14) / w;
+    let m21 = (transform.m21 - projected.x * transform.m24) / w;
+    let m22 = (transform.m22 - projected.y * transform.m24) / w;
+    Some(Matrix2D::new(m11,
+                       m12,
+                       m21,
+                       m22,
+                       projected.x - m11 * around.x - m21 * around.y,
+                       projected.y - m12 * around.x - m22 * around.y))
+}
+
+/// Returns the 2D transform to paint the rect of the plane z = 0 through, as `to_2d` does around
+/// its centre. If the centre is behind the viewer, it is around the middle of the part of the rect
+/// that is in front instead. Returns `None` only if the whole rect is behind the viewer.
+pub fn to_2d_within(transform: &Matrix4<AzFloat>, rect: &Rect<AzFloat>)
+                    -> Option<Matrix2D<AzFloat>> {
+    let center = Point2D(rect.origin.x + rect.size.width / 2.0,
+                         rect.origin.y + rect.size.height / 2.0);
+    if let Some(transform_2d) = to_2d(transform, &center) {
+        return Some(transform_2d)
+    }
+
+    // The part in front is where w is positive, which is the rect cut by a line, so its corners
+    // are those of the rect in front and those where the line crosses the edges.
+    let w = |point: &Point2D<AzFloat>| {
+        transform.m14 * point.x + transform.m24 * point.y + transform.m44
+    };
+    let corners = [rect.origin,
+                   Point2D(rect.max_x(), rect.origin.y),
+                   Point2D(rect.max_x(), rect.max_y()),
+                   Point2D(rect.origin.x, rect.max_y())];
+    let (mut sum, mut count) = (Point2D(0.0, 0.0), 0);
+    for (index, corner) in corners.iter().enumerate() {
+        let next = &corners[(index + 1) % corners.len()];
+        let (w_corner, w_next) = (w(corner), w(next));
+        if w_corner > 0.0 {
+            sum = sum + *corner;
+            count += 1;
+        }
+        if (w_corner > 0.0) != (w_next > 0.0) {
+            let t = w_corner / (w_corner - w_next);
+            sum = sum + Point2D(corner.x + (next.x - corner.x) * t,
+                                corner.y + (next.y - corner.y) * t);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None
+    }
+    to_2d(transform, &Point2D(sum.x / count as AzFloat, sum.y / count as AzFloat))
+}
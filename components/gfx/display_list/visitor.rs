@@ -0,0 +1,122 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Going over the display items of display lists and stacking contexts in the order that they are
+//! painted in, so that code that looks at every item, such as serialization, statistics and
+//! validation, needn't know that order itself.
+
+use display_list::{BorderDisplayItem, BoxShadowDisplayItem, DisplayItem, DisplayList};
+use display_list::DisplayListSection;
+use display_list::{ExternalTextureDisplayItem, GradientDisplayItem, IframeDisplayItem};
+use display_list::{ImageDisplayItem, LineDisplayItem, RadialGradientDisplayItem};
+use display_list::SolidColorDisplayItem;
+use display_list::{StackingContext, TextDisplayItem};
+
+use std::sync::Arc;
+
+/// Something that is shown each display item in turn.
+///
+/// By default, `visit_item()` hands each item to the method for its kind, which does nothing, so
+/// implementations override either `visit_item()`, to see all items the same way, or the methods
+/// for the kinds of items that they care about.
+pub trait DisplayItemVisitor {
+    fn visit_item(&mut self, item: &DisplayItem) {
+        match *item {
+            DisplayItem::SolidColorClass(ref solid_color) => self.visit_solid_color(&**solid_color),
+            DisplayItem::TextClass(ref text) => self.visit_text(&**text),
+            DisplayItem::ImageClass(ref image) => self.visit_image(&**image),
+            DisplayItem::BorderClass(ref border) => self.visit_border(&**border),
+            DisplayItem::GradientClass(ref gradient) => self.visit_gradient(&**gradient),
+            DisplayItem::RadialGradientClass(ref gradient) => {
+                self.visit_radial_gradient(&**gradient)
+            }
+            DisplayItem::LineClass(ref line) => self.visit_line(&**line),
+            DisplayItem::BoxShadowClass(ref box_shadow) => self.visit_box_shadow(&**box_shadow),
+            DisplayItem::ExternalTextureClass(ref texture) => {
+                self.visit_external_texture(&**texture)
+            }
+            DisplayItem::IframeClass(ref iframe) => self.visit_iframe(&**iframe),
+        }
+    }
+
+    fn visit_solid_color(&mut self, _: &SolidColorDisplayItem) {}
+    fn visit_text(&mut self, _: &TextDisplayItem) {}
+    fn visit_image(&mut self, _: &ImageDisplayItem) {}
+    fn visit_border(&mut self, _: &BorderDisplayItem) {}
+    fn visit_gradient(&mut self, _: &GradientDisplayItem) {}
+    fn visit_radial_gradient(&mut self, _: &RadialGradientDisplayItem) {}
+    fn visit_line(&mut self, _: &LineDisplayItem) {}
+    fn visit_box_shadow(&mut self, _: &BoxShadowDisplayItem) {}
+    fn visit_external_texture(&mut self, _: &ExternalTextureDisplayItem) {}
+    fn visit_iframe(&mut self, _: &IframeDisplayItem) {}
+
+    /// Called before the items of a stacking context are visited. Returning false skips the
+    /// stacking context and everything in it, to cull it.
+    fn enter_stacking_context(&mut self, _: &StackingContext) -> bool {
+        true
+    }
+
+    /// Called once the items of a stacking context that was entered have been visited.
+    fn exit_stacking_context(&mut self, _: &StackingContext) {}
+}
+
+impl DisplayList {
+    /// Shows the items of this display list, but not those of the stacking contexts in it, to the
+    /// visitor, in the order that they are painted in.
+    pub fn visit(&self, visitor: &mut DisplayItemVisitor) {
+        for item in self.iter() {
+            visitor.visit_item(item)
+        }
+    }
+}
+
+impl StackingContext {
+    /// Shows the items of this stacking context and of all of the stacking contexts in it to the
+    /// visitor, in the order of the steps of CSS 2.1 § E.2, with the stacking contexts in the
+    /// order of their z-indices. Stacking contexts with layers of their own are visited where they
+    /// are in that order too.
+    pub fn visit_in_paint_order(&self, visitor: &mut DisplayItemVisitor) {
+        if !visitor.enter_stacking_context(self) {
+            return
+        }
+
+        let display_list = &self.display_list;
+        let mut positioned_children: Vec<&Arc<StackingContext>> =
+            display_list.children.iter().collect();
+        positioned_children.sort_by(|this, other| this.z_index.cmp(&other.z_index));
+
+        // Steps 1 and 2: Borders and background for the root.
+        for item in display_list.get(DisplayListSection::BackgroundAndBorders).iter() {
+            visitor.visit_item(item)
+        }
+
+        // Step 3: Positioned descendants with negative z-indices.
+        for positioned_kid in positioned_children.iter().filter(|kid| kid.z_index < 0) {
+            positioned_kid.visit_in_paint_order(visitor)
+        }
+
+        // Steps 4, 5 and 7: Block backgrounds and borders, floats and content.
+        for section in [
+            DisplayListSection::BlockBackgroundsAndBorders,
+            DisplayListSection::Floats,
+            DisplayListSection::Content,
+        ].iter() {
+            for item in display_list.get(*section).iter() {
+                visitor.visit_item(item)
+            }
+        }
+
+        // Steps 8 and 9: Positioned descendants with nonnegative z-indices.
+        for positioned_kid in positioned_children.iter().filter(|kid| kid.z_index >= 0) {
+            positioned_kid.visit_in_paint_order(visitor)
+        }
+
+        // Step 10: Outlines.
+        for item in display_list.get(DisplayListSection::Outlines).iter() {
+            visitor.visit_item(item)
+        }
+
+        visitor.exit_stacking_context(self)
+    }
+}
@@ -4,18 +4,27 @@
 
 //! CSS and SVG filter support.
 
+use color_matrix;
+use display_list::BLUR_INFLATION_FACTOR;
+use svg_filter;
+
 use azure::AzFloat;
 use azure::azure_hl::{ColorMatrixAttribute, ColorMatrixInput, CompositeInput, DrawTarget};
 use azure::azure_hl::{FilterNode, FilterType, LinearTransferAttribute, LinearTransferInput};
-use azure::azure_hl::{Matrix5x4, TableTransferAttribute, TableTransferInput};
-use azure::azure_hl::{GaussianBlurAttribute, GaussianBlurInput};
-
-use std::num::Float;
+use azure::azure_hl::{TableTransferAttribute, TableTransferInput};
+use azure::azure_hl::{DrawOptions, GaussianBlurAttribute, GaussianBlurInput};
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use std::cmp::max;
 use style::computed_values::filter;
 use util::geometry::Au;
 
 /// Creates a filter pipeline from a set of CSS filters. Returns the destination end of the filter
 /// pipeline and the opacity.
+///
+/// Each drop shadow is painted, under what the filters before it made, into a draw target of its
+/// own, since Azure can't move the shadow with a filter; the filters after it start from that.
 pub fn create_filters(draw_target: &DrawTarget,
                       temporary_draw_target: &DrawTarget,
                       style_filters: &filter::T,
@@ -28,7 +37,7 @@ pub fn create_filters(draw_target: &DrawTarget,
         match *style_filter {
             filter::Filter::HueRotate(angle) => {
                 let hue_rotate = draw_target.create_filter(FilterType::ColorMatrix);
-                let matrix = self::hue_rotate(angle.radians() as AzFloat);
+                let matrix = color_matrix::hue_rotate(angle.radians() as AzFloat);
                 hue_rotate.set_attribute(ColorMatrixAttribute::Matrix(matrix));
                 hue_rotate.set_input(ColorMatrixInput, &filter);
                 filter = hue_rotate
@@ -36,14 +45,14 @@ pub fn create_filters(draw_target: &DrawTarget,
             filter::Filter::Opacity(opacity_value) => opacity *= opacity_value as AzFloat,
             filter::Filter::Saturate(amount) => {
                 let saturate = draw_target.create_filter(FilterType::ColorMatrix);
-                let matrix = self::saturate(amount as AzFloat);
+                let matrix = color_matrix::saturate(amount as AzFloat);
                 saturate.set_attribute(ColorMatrixAttribute::Matrix(matrix));
                 saturate.set_input(ColorMatrixInput, &filter);
                 filter = saturate
             }
             filter::Filter::Sepia(amount) => {
                 let sepia = draw_target.create_filter(FilterType::ColorMatrix);
-                let matrix = self::sepia(amount as AzFloat);
+                let matrix = color_matrix::sepia(amount as AzFloat);
                 sepia.set_attribute(ColorMatrixAttribute::Matrix(matrix));
                 sepia.set_input(ColorMatrixInput, &filter);
                 filter = sepia
@@ -51,7 +60,8 @@ pub fn create_filters(draw_target: &DrawTarget,
             filter::Filter::Grayscale(amount) => {
                 let amount = amount as AzFloat;
                 let grayscale = draw_target.create_filter(FilterType::ColorMatrix);
-                grayscale.set_attribute(ColorMatrixAttribute::Matrix(self::grayscale(amount)));
+                let matrix = color_matrix::grayscale(amount);
+                grayscale.set_attribute(ColorMatrixAttribute::Matrix(matrix));
                 grayscale.set_input(ColorMatrixInput, &filter);
                 filter = grayscale
             }
@@ -102,6 +112,40 @@ pub fn create_filters(draw_target: &DrawTarget,
                 blur.set_input(GaussianBlurInput, &filter);
                 filter = blur
             }
+            filter::Filter::DropShadow(ref shadow) => {
+                *accumulated_blur_radius = *accumulated_blur_radius + drop_shadow_extent(shadow);
+                let blur = draw_target.create_filter(FilterType::GaussianBlur);
+                let std_deviation = shadow.blur_radius.to_frac32_px();
+                blur.set_attribute(GaussianBlurAttribute::StdDeviation(std_deviation));
+                blur.set_input(GaussianBlurInput, &filter);
+                let tint = draw_target.create_filter(FilterType::ColorMatrix);
+                let matrix = color_matrix::drop_shadow_color(shadow);
+                tint.set_attribute(ColorMatrixAttribute::Matrix(matrix));
+                tint.set_input(ColorMatrixInput, &blur);
+
+                let size = temporary_draw_target.get_size();
+                let size = Size2D(size.width, size.height);
+                let shadowed_draw_target =
+                    temporary_draw_target.create_similar_draw_target(&size,
+                                                                     draw_target.get_format());
+                let rect = Rect(Point2D(0.0, 0.0),
+                                Size2D(size.width as AzFloat, size.height as AzFloat));
+                let offset = Point2D(shadow.offset_x.to_frac32_px(),
+                                     shadow.offset_y.to_frac32_px());
+                shadowed_draw_target.draw_filter(&tint, &rect, &offset, DrawOptions::new(1.0, 0));
+                shadowed_draw_target.draw_filter(&filter,
+                                                 &rect,
+                                                 &rect.origin,
+                                                 DrawOptions::new(1.0, 0));
+                filter = draw_target.create_filter(FilterType::Composite);
+                filter.set_input(CompositeInput, &shadowed_draw_target.snapshot());
+            }
+            filter::Filter::Url(ref url) => {
+                if let Some(svg_filter) = svg_filter::get(url) {
+                    *accumulated_blur_radius = *accumulated_blur_radius + svg_filter.inflation();
+                    filter = svg_filter.create_filter(draw_target, temporary_draw_target, filter)
+                }
+            }
         }
     }
     (filter, opacity)
@@ -118,109 +162,61 @@ pub fn temporary_draw_target_needed_for_style_filters(filters: &filter::T) -> bo
     false
 }
 
-// If there is one or more blur filters, we need to know the blur ammount
-// to expand the draw target size.
-pub fn calculate_accumulated_blur(style_filters: &filter::T) -> Au {
-    let mut accum_blur = Au::new(0);
+/// Returns how far the filters may paint outside of what they are applied to, on each side, so
+/// that the draw target can be made big enough.
+pub fn calculate_inflation(style_filters: &filter::T) -> Au {
+    let mut inflation = Au::new(0);
     for style_filter in style_filters.filters.iter() {
         match *style_filter {
-            filter::Filter::Blur(amount) => {
-                accum_blur = accum_blur.clone() + amount;
+            filter::Filter::Blur(amount) => inflation = inflation + amount * BLUR_INFLATION_FACTOR,
+            filter::Filter::DropShadow(ref shadow) => {
+                inflation = inflation + shadow.blur_radius * BLUR_INFLATION_FACTOR +
+                    drop_shadow_offset(shadow)
+            }
+            filter::Filter::Url(ref url) => {
+                if let Some(svg_filter) = svg_filter::get(url) {
+                    inflation = inflation + svg_filter.inflation()
+                }
             }
             _ => continue,
         }
     }
-
-    accum_blur
+    inflation
 }
 
-
-/// Creates a grayscale 5x4 color matrix per CSS-FILTERS § 12.1.1.
-fn grayscale(amount: AzFloat) -> Matrix5x4 {
-    Matrix5x4 {
-        m11: 0.2126 + 0.7874 * (1.0 - amount),
-            m21: 0.7152 - 0.7152 * (1.0 - amount),
-            m31: 0.0722 - 0.0722 * (1.0 - amount),
-            m41: 0.0,
-            m51: 0.0,
-        m12: 0.2126 - 0.2126 * (1.0 - amount),
-            m22: 0.7152 + 0.2848 * (1.0 - amount),
-            m32: 0.0722 - 0.0722 * (1.0 - amount),
-            m42: 0.0,
-            m52: 0.0,
-        m13: 0.2126 - 0.2126 * (1.0 - amount),
-            m23: 0.7152 - 0.7152 * (1.0 - amount),
-            m33: 0.0722 + 0.9278 * (1.0 - amount),
-            m43: 0.0,
-            m53: 0.0,
-        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
-    }
-}
-
-/// Creates a 5x4 hue rotation color matrix per CSS-FILTERS § 8.5.
-fn hue_rotate(angle: AzFloat) -> Matrix5x4 {
-    let (c, s) = (angle.cos(), angle.sin());
-    Matrix5x4 {
-        m11: 0.213 + c * 0.787 + s * -0.213,
-            m21: 0.715 + c * -0.715 + s * -0.715,
-            m31: 0.072 + c * -0.072 + s * 0.928,
-            m41: 0.0,
-            m51: 0.0,
-        m12: 0.213 + c * -0.213 + s * 0.143,
-            m22: 0.715 + c * 0.285 + s * 0.140,
-            m32: 0.072 + c * -0.072 + s * -0.283,
-            m42: 0.0,
-            m52: 0.0,
-        m13: 0.213 + c * -0.213 + s * -0.787,
-            m23: 0.715 + c * -0.715 + s * 0.715,
-            m33: 0.072 + c * 0.928 + s * 0.072,
-            m43: 0.0,
-            m53: 0.0,
-        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
+/// Returns the rect that what is painted in `rect` may reach once the filters are applied to it.
+pub fn inflate_rect(style_filters: &filter::T, rect: &Rect<Au>) -> Rect<Au> {
+    let mut rect = *rect;
+    for style_filter in style_filters.filters.iter() {
+        match *style_filter {
+            filter::Filter::Blur(amount) => {
+                let inflation = amount * BLUR_INFLATION_FACTOR;
+                rect = rect.inflate(inflation, inflation)
+            }
+            filter::Filter::DropShadow(ref shadow) => {
+                let inflation = shadow.blur_radius * BLUR_INFLATION_FACTOR;
+                let offset = Point2D(shadow.offset_x, shadow.offset_y);
+                rect = rect.union(&rect.translate(&offset).inflate(inflation, inflation))
+            }
+            filter::Filter::Url(ref url) => {
+                if let Some(svg_filter) = svg_filter::get(url) {
+                    let inflation = svg_filter.inflation();
+                    rect = rect.inflate(inflation, inflation)
+                }
+            }
+            _ => continue,
+        }
     }
+    rect
 }
 
-/// Creates a 5x4 saturation color matrix per CSS-FILTERS § 8.5.
-fn saturate(amount: AzFloat) -> Matrix5x4 {
-    Matrix5x4 {
-        m11: 0.213 + 0.787 * amount,
-            m21: 0.715 - 0.715 * amount,
-            m31: 0.072 - 0.072 * amount,
-            m41: 0.0,
-            m51: 0.0,
-        m12: 0.213 - 0.213 * amount,
-            m22: 0.715 + 0.285 * amount,
-            m32: 0.072 - 0.072 * amount,
-            m42: 0.0,
-            m52: 0.0,
-        m13: 0.213 - 0.213 * amount,
-            m23: 0.715 - 0.715 * amount,
-            m33: 0.072 + 0.928 * amount,
-            m43: 0.0,
-            m53: 0.0,
-        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
-    }
+/// Returns how far a drop shadow reaches past what it is the shadow of, which blurs that come
+/// after it in the pipeline need to know.
+fn drop_shadow_extent(shadow: &filter::DropShadow) -> Au {
+    shadow.blur_radius + drop_shadow_offset(shadow)
 }
 
-/// Creates a sepia 5x4 color matrix per CSS-FILTERS § 12.1.1.
-fn sepia(amount: AzFloat) -> Matrix5x4 {
-    Matrix5x4 {
-        m11: 0.393 + 0.607 * (1.0 - amount),
-            m21: 0.769 - 0.769 * (1.0 - amount),
-            m31: 0.189 - 0.189 * (1.0 - amount),
-            m41: 0.0,
-            m51: 0.0,
-        m12: 0.349 - 0.349 * (1.0 - amount),
-            m22: 0.686 + 0.314 * (1.0 - amount),
-            m32: 0.168 - 0.168 * (1.0 - amount),
-            m42: 0.0,
-            m52: 0.0,
-        m13: 0.272 - 0.272 * (1.0 - amount),
-            m23: 0.534 - 0.534 * (1.0 - amount),
-            m33: 0.131 + 0.869 * (1.0 - amount),
-            m43: 0.0,
-            m53: 0.0,
-        m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
-    }
+/// Returns how far a drop shadow is moved along either axis, whichever is more.
+fn drop_shadow_offset(shadow: &filter::DropShadow) -> Au {
+    max(max(shadow.offset_x, -shadow.offset_x), max(shadow.offset_y, -shadow.offset_y))
 }
-
@@ -4,6 +4,7 @@
 
 use geom::{Point2D, Rect, Size2D};
 use std::borrow::ToOwned;
+use std::cmp::max;
 use std::mem;
 use std::slice;
 use std::rc::Rc;
@@ -11,15 +12,21 @@ use std::cell::RefCell;
 use util::cache::HashCache;
 use util::smallvec::{SmallVec, SmallVec8};
 use style::computed_values::{font_stretch, font_variant, font_weight};
+use style::font_face::{self, UnicodeRange};
 use style::properties::style_structs::Font as FontStyle;
 use std::sync::Arc;
 
 use platform::font_context::FontContextHandle;
 use platform::font::{FontHandle, FontTable};
 use util::geometry::Au;
-use text::glyph::{GlyphStore, GlyphId};
+use util::opts;
+use util::range::Range;
+use text::glyph::{CharIndex, GlyphStore, GlyphId};
+use text::shape_cache::{FontTemplateRef, ShapedTextCache, ShapedTextKey};
 use text::shaping::ShaperMethods;
+use text::util::Script;
 use text::{Shaper, TextRun};
+use font_instance::FontInstanceKey;
 use font_template::FontTemplateDescriptor;
 use platform::font_template::FontTemplateData;
 
@@ -37,10 +44,17 @@ pub trait FontHandleMethods {
     fn is_italic(&self) -> bool;
     fn boldness(&self) -> font_weight::T;
     fn stretchiness(&self) -> font_stretch::T;
+    /// Selects the outlines of a variable font. Axes that the face doesn't have are ignored.
+    /// Returns the tags of the axes whose coordinates were set, which is none of them if the face
+    /// isn't variable.
+    fn set_variations(&mut self, variations: &FontVariations) -> Vec<FontTableTag>;
 
     fn glyph_index(&self, codepoint: char) -> Option<GlyphId>;
     fn glyph_h_advance(&self, GlyphId) -> Option<FractionalPixel>;
     fn glyph_h_kerning(&self, GlyphId, GlyphId) -> FractionalPixel;
+    /// Returns how far the ink of the glyph reaches below the baseline. This is negative if the
+    /// glyph ends above the baseline.
+    fn glyph_descent(&self, GlyphId) -> Option<FractionalPixel>;
     fn get_metrics(&self) -> FontMetrics;
     fn get_table_for_tag(&self, FontTableTag) -> Option<FontTable>;
 }
@@ -72,8 +86,12 @@ pub trait FontTableMethods {
 #[derive(Clone, Debug)]
 pub struct FontMetrics {
     pub underline_size:   Au,
+    /// The distance from the baseline to the top of the underline, positive upwards, as in the
+    /// `post` table.
     pub underline_offset: Au,
     pub strikeout_size:   Au,
+    /// The distance from the baseline to the top of the strikeout stroke, positive upwards, as in
+    /// the `OS/2` table.
     pub strikeout_offset: Au,
     pub leading:          Au,
     pub x_height:         Au,
@@ -85,6 +103,93 @@ pub struct FontMetrics {
     pub line_gap:         Au,
 }
 
+impl FontMetrics {
+    /// Fills in decoration metrics that the font doesn't provide, the way other browsers do: an
+    /// underline one fourteenth of the em thick halfway down the descent, and a strikeout as
+    /// thick as the underline centered halfway up the x-height.
+    pub fn with_decoration_fallbacks(mut self) -> FontMetrics {
+        if self.underline_size == Au(0) {
+            self.underline_size = max(self.em_size / 14, Au::from_px(1));
+        }
+        if self.underline_offset == Au(0) {
+            self.underline_offset = -self.descent / 2;
+        }
+        if self.strikeout_size == Au(0) {
+            self.strikeout_size = self.underline_size;
+        }
+        if self.strikeout_offset == Au(0) {
+            let x_height = if self.x_height == Au(0) { self.ascent / 2 } else { self.x_height };
+            self.strikeout_offset = (x_height + self.strikeout_size) / 2;
+        }
+        self
+    }
+}
+
+/// A coordinate along one design axis of a variable font, such as `wght` or `wdth`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FontVariation {
+    /// The tag of the axis, as found in the `fvar` table.
+    pub tag: FontTableTag,
+    /// The coordinate in 16.16 fixed point, so that instance keys can be hashed and compared.
+    pub value: i32,
+}
+
+/// The axis coordinates that select the outlines of a variable font instance.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FontVariations {
+    /// The index of a named instance from the `fvar` table, if any. Explicit axis coordinates
+    /// take precedence over the coordinates of the named instance.
+    pub named_instance: Option<u16>,
+    /// Explicit axis coordinates.
+    pub axes: Vec<FontVariation>,
+}
+
+impl FontVariations {
+    pub fn new() -> FontVariations {
+        FontVariations {
+            named_instance: None,
+            axes: vec!(),
+        }
+    }
+
+    /// Returns the `wght` and `wdth` coordinates corresponding to the given style.
+    pub fn for_style(weight: font_weight::T, stretch: font_stretch::T) -> FontVariations {
+        let width = match stretch {
+            font_stretch::T::ultra_condensed => 50.0,
+            font_stretch::T::extra_condensed => 62.5,
+            font_stretch::T::condensed => 75.0,
+            font_stretch::T::semi_condensed => 87.5,
+            font_stretch::T::normal => 100.0,
+            font_stretch::T::semi_expanded => 112.5,
+            font_stretch::T::expanded => 125.0,
+            font_stretch::T::extra_expanded => 150.0,
+            font_stretch::T::ultra_expanded => 200.0,
+        };
+        FontVariations {
+            named_instance: None,
+            axes: vec!(
+                FontVariation {
+                    tag: WGHT_AXIS_TAG,
+                    value: (weight as i32) << 16,
+                },
+                FontVariation {
+                    tag: WDTH_AXIS_TAG,
+                    value: (width * 65536.0) as i32,
+                },
+            ),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.named_instance.is_none() && self.axes.is_empty()
+    }
+}
+
+/// The `wght` variation axis tag.
+pub static WGHT_AXIS_TAG: FontTableTag = 0x77676874;
+/// The `wdth` variation axis tag.
+pub static WDTH_AXIS_TAG: FontTableTag = 0x77647468;
+
 pub type SpecifiedFontStyle = FontStyle;
 pub type UsedFontStyle = FontStyle;
 
@@ -93,11 +198,60 @@ pub struct Font {
     pub metrics: FontMetrics,
     pub variant: font_variant::T,
     pub descriptor: FontTemplateDescriptor,
+    /// The variation axis coordinates this instance was created with. Instances with different
+    /// coordinates have distinct shape and advance caches.
+    pub variations: FontVariations,
     pub requested_pt_size: Au,
     pub actual_pt_size: Au,
     pub shaper: Option<Shaper>,
     pub shape_cache: HashCache<ShapeCacheEntry,Arc<GlyphStore>>,
     pub glyph_advance_cache: HashCache<u32,FractionalPixel>,
+    pub glyph_descent_cache: HashCache<u32,FractionalPixel>,
+    /// Styles that the face lacks and that must be faked at paint time.
+    pub synthesis: FontSynthesisFlags,
+    /// The characters that this face may be used for, from its `@font-face` rule. Empty if it
+    /// covers everything.
+    pub unicode_range: Vec<UnicodeRange>,
+    /// The cache of shaped text shared with the fonts of other threads.
+    pub shaped_text_cache: Arc<ShapedTextCache>,
+    /// The script that a generic family mapped to this font for, if any. Such fonts are only
+    /// used for characters of that script.
+    pub script: Option<Script>,
+}
+
+bitflags! {
+    flags FontSynthesisFlags: u8 {
+        #[doc="Set if bold was requested but the face is not bold. Glyphs are double-struck."]
+        const SYNTHETIC_BOLD_FONT_FLAG = 0x01,
+        #[doc="Set if italic or oblique was requested but the face is upright. Glyphs are \
+                sheared."]
+        const SYNTHETIC_OBLIQUE_FONT_FLAG = 0x02
+    }
+}
+
+/// The horizontal shear applied to glyphs of synthetic oblique faces: roughly 11°, matching
+/// Gecko and WebKit.
+pub static SYNTHETIC_OBLIQUE_SKEW: f64 = 0.2;
+
+impl FontSynthesisFlags {
+    /// Determines which styles must be synthesized in order to render a face with the given
+    /// characteristics as though it matched `descriptor`. `varied_axes` are the variation axes
+    /// that were set on the face; if its weight was set, the face already has the requested
+    /// weight, whatever the weight it declares.
+    pub fn for_face(descriptor: &FontTemplateDescriptor,
+                    face_is_bold: bool,
+                    face_is_italic: bool,
+                    varied_axes: &[FontTableTag])
+                    -> FontSynthesisFlags {
+        let mut flags = FontSynthesisFlags::empty();
+        if descriptor.weight.is_bold() && !face_is_bold && !varied_axes.contains(&WGHT_AXIS_TAG) {
+            flags.insert(SYNTHETIC_BOLD_FONT_FLAG)
+        }
+        if descriptor.italic && !face_is_italic {
+            flags.insert(SYNTHETIC_OBLIQUE_FONT_FLAG)
+        }
+        flags
+    }
 }
 
 bitflags! {
@@ -107,7 +261,12 @@ bitflags! {
         #[doc="Set if we are to ignore ligatures."]
         const IGNORE_LIGATURES_SHAPING_FLAG = 0x02,
         #[doc="Set if we are to disable kerning."]
-        const DISABLE_KERNING_SHAPING_FLAG = 0x04
+        const DISABLE_KERNING_SHAPING_FLAG = 0x04,
+        #[doc="Set if the text is right-to-left. Glyphs are still stored in logical order."]
+        const RTL_SHAPING_FLAG = 0x08,
+        #[doc="Set if the text is vertical and `text-orientation: mixed`, so that characters \
+                that are upright in vertical text get glyph runs of their own."]
+        const MIXED_ORIENTATION_SHAPING_FLAG = 0x10
     }
 }
 
@@ -119,6 +278,9 @@ pub struct ShapingOptions {
     pub letter_spacing: Option<Au>,
     /// Spacing to add between each word. Corresponds to the CSS 2.1 `word-spacing` property.
     pub word_spacing: Au,
+    /// The distance between tab stops in multiples of the advance of a space. Corresponds to the
+    /// CSS3 `tab-size` property.
+    pub tab_size: u32,
     /// Various flags.
     pub flags: ShapingFlags,
 }
@@ -145,18 +307,35 @@ impl Font {
             Some(glyphs) => return glyphs.clone(),
         }
 
-        let mut glyphs = GlyphStore::new(text.chars().count(),
-                                         options.flags.contains(IS_WHITESPACE_SHAPING_FLAG));
-        shaper.as_ref().unwrap().shape_text(text, options, &mut glyphs);
-
-        let glyphs = Arc::new(glyphs);
-        self.shape_cache.insert(ShapeCacheEntry {
+        // Another thread may have shaped the same text with the same font instance already.
+        let shared_key = ShapedTextKey {
+            template: FontTemplateRef(self.handle.get_template()),
+            font: self.instance_key(),
             text: text.to_owned(),
             options: *options,
-        }, glyphs.clone());
+        };
+        let glyphs = self.shaped_text_cache.find_or_shape(shared_key, || {
+            let mut glyphs = GlyphStore::new(text.chars().count(),
+                                             options.flags.contains(IS_WHITESPACE_SHAPING_FLAG));
+            shaper.as_ref().unwrap().shape_text(text, options, &mut glyphs);
+            glyphs
+        });
+
+        self.shape_cache.insert(lookup_key, glyphs.clone());
         glyphs
     }
 
+    /// Returns the key that identifies this font instance across threads.
+    pub fn instance_key(&self) -> FontInstanceKey {
+        FontInstanceKey {
+            identifier: self.handle.get_template().identifier.clone(),
+            pt_size: self.actual_pt_size,
+            variations: self.variations.clone(),
+            hinting: opts::get().font_hinting,
+            autohint: opts::get().font_autohint,
+        }
+    }
+
     fn make_shaper<'a>(&'a mut self, options: &ShapingOptions) -> &'a Shaper {
         // fast path: already created a shaper
         match self.shaper {
@@ -205,6 +384,16 @@ impl Font {
             }
         })
     }
+
+    /// Returns how far the ink of the glyph reaches below the baseline, or zero if the platform
+    /// can't tell.
+    pub fn glyph_descent(&mut self, glyph: GlyphId) -> Au {
+        let handle = &self.handle;
+        let descent = self.glyph_descent_cache.find_or_create(&glyph, |glyph| {
+            handle.glyph_descent(*glyph).unwrap_or(0.0)
+        });
+        Au::from_frac_px(descent)
+    }
 }
 
 pub struct FontGroup {
@@ -218,11 +407,69 @@ impl FontGroup {
         }
     }
 
-    pub fn create_textrun(&self, text: String, options: &ShapingOptions) -> TextRun {
+    /// Returns the index of the first font in the group whose `unicode-range` covers the
+    /// character and that isn't reserved for another script, or of the first font if none is.
+    pub fn font_index_for_char(&self, character: char) -> usize {
+        let script = Script::for_char(character);
+        self.fonts
+            .iter()
+            .position(|font| {
+                let font = font.borrow();
+                (font.script.is_none() || font.script == script) &&
+                    font_face::unicode_range_contains(&*font.unicode_range, character)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Splits the text into ranges of characters that are each rendered with one font of the
+    /// group. Characters that continue a cluster, such as combining marks, stay with the font of
+    /// the character before them so that the cluster is shaped as a whole.
+    pub fn font_ranges_for_text(&self, text: &str) -> Vec<(usize, Range<CharIndex>)> {
+        let mut font_ranges: Vec<(usize, Range<CharIndex>)> = vec!();
+        for (i, character) in text.chars().enumerate() {
+            let index = CharIndex(i as isize);
+            let font_index = match font_ranges.last() {
+                Some(&(last_font_index, _)) if continues_cluster(character) => last_font_index,
+                // Characters shared by all scripts, such as spaces and punctuation, stay with a
+                // font chosen for the script of the text around them.
+                Some(&(last_font_index, _)) if Script::for_char(character).is_none() &&
+                        self.fonts.get(last_font_index).borrow().script.is_some() => {
+                    last_font_index
+                }
+                _ if self.fonts.len() == 1 => 0,
+                _ => self.font_index_for_char(character),
+            };
+            match font_ranges.last_mut() {
+                Some(&mut (last_font_index, ref mut range)) if last_font_index == font_index => {
+                    range.extend_by(CharIndex(1));
+                    continue
+                }
+                _ => {}
+            }
+            font_ranges.push((font_index, Range::new(index, CharIndex(1))));
+        }
+        font_ranges
+    }
+
+    pub fn create_textrun(&self, text: String, options: &ShapingOptions, bidi_level: u8)
+                          -> TextRun {
         assert!(self.fonts.len() > 0);
 
         // TODO(Issue #177): Actually fall back through the FontGroup when a font is unsuitable.
-        TextRun::new(&mut *self.fonts.get(0).borrow_mut(), text.clone(), options)
+        TextRun::new(&mut *self.fonts.get(0).borrow_mut(), text.clone(), options, bidi_level)
+    }
+}
+
+/// Returns true if the character never begins a cluster of its own.
+fn continues_cluster(character: char) -> bool {
+    match character as u32 {
+        // Combining diacritical marks and their extensions and supplement.
+        0x0300...0x036f | 0x1ab0...0x1aff | 0x1dc0...0x1dff | 0x20d0...0x20ff |
+        // Zero width non-joiner and joiner.
+        0x200c...0x200d |
+        // Variation selectors and combining half marks.
+        0xfe00...0xfe0f | 0xfe20...0xfe2f | 0xe0100...0xe01ef => true,
+        _ => false,
     }
 }
 
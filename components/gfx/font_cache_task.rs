@@ -8,15 +8,19 @@ use platform::font_list::get_variations_for_family;
 use platform::font_list::get_last_resort_font_families;
 use platform::font_context::FontContextHandle;
 
-use font_template::{FontTemplate, FontTemplateDescriptor};
+use font_instance::FontInstanceTable;
+use font_template::{FontTemplate, FontTemplateDescriptor, MatchedFontTemplate};
 use net_traits::{ResourceTask, load_whole_resource};
 use platform::font_template::FontTemplateData;
 use std::borrow::ToOwned;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Sender, Receiver, channel};
 use string_cache::Atom;
-use style::font_face::Source;
+use style::font_face::{Source, UnicodeRange};
+use text::shape_cache::ShapedTextCache;
+use text::util::Script;
+use util::opts;
 use util::str::LowercaseString;
 use util::task::spawn_named;
 
@@ -32,52 +36,96 @@ impl FontFamily {
         }
     }
 
-    /// Find a font in this family that matches a given descriptor.
-    fn find_font_for_style<'a>(&'a mut self, desc: &FontTemplateDescriptor, fctx: &FontContextHandle)
-                               -> Option<Arc<FontTemplateData>> {
-        // TODO(Issue #189): optimize lookup for
-        // regular/bold/italic/bolditalic with fixed offsets and a
-        // static decision table for fallback between these values.
-
-        // TODO(Issue #190): if not in the fast path above, do
-        // expensive matching of weights, etc.
-        for template in self.templates.iter_mut() {
-            let maybe_template = template.get_if_matches(fctx, desc);
-            if maybe_template.is_some() {
-                return maybe_template;
+    /// Find the fonts in this family that match a given descriptor: one for each distinct
+    /// `unicode-range` among its faces, in the order that the faces were added. A composite
+    /// family, such as a Latin face plus a CJK face, thus yields a font for each part.
+    fn find_fonts_for_style(&mut self, desc: &FontTemplateDescriptor, fctx: &FontContextHandle)
+                            -> Vec<MatchedFontTemplate> {
+        let mut unicode_ranges: Vec<Vec<UnicodeRange>> = vec!();
+        for template in self.templates.iter() {
+            if !unicode_ranges.iter().any(|unicode_range| {
+                &**unicode_range == template.unicode_range()
+            }) {
+                unicode_ranges.push(template.unicode_range().to_vec());
+            }
+        }
+
+        let mut fonts = vec!();
+        for unicode_range in unicode_ranges.into_iter() {
+            if let Some(data) = self.find_font_for_style(desc, fctx, &*unicode_range) {
+                fonts.push(MatchedFontTemplate {
+                    data: data,
+                    unicode_range: unicode_range,
+                });
             }
         }
+        fonts
+    }
 
-        // If a request is made for a font family that exists,
-        // pick the first valid font in the family if we failed
-        // to find an exact match for the descriptor.
-        for template in self.templates.iter_mut() {
-            let maybe_template = template.get();
-            if maybe_template.is_some() {
-                return maybe_template;
+    /// Find the font among the faces of this family with the given `unicode-range` that best
+    /// matches a given descriptor, using the CSS font matching algorithm.
+    fn find_font_for_style<'a>(&'a mut self,
+                               desc: &FontTemplateDescriptor,
+                               fctx: &FontContextHandle,
+                               unicode_range: &[UnicodeRange])
+                               -> Option<Arc<FontTemplateData>> {
+        let mut best_template = None;
+        for (index, template) in self.templates.iter_mut().enumerate() {
+            if template.unicode_range() != unicode_range {
+                continue
+            }
+            let actual_desc = match template.descriptor(fctx) {
+                Some(actual_desc) => actual_desc,
+                None => continue,
+            };
+            let distance = desc.distance_to(&actual_desc);
+            match best_template {
+                Some((best_distance, _)) if best_distance <= distance => {}
+                _ => best_template = Some((distance, index)),
             }
         }
 
-        None
+        match best_template {
+            Some((_, index)) => self.templates[index].get(),
+            None => None,
+        }
     }
 
-    fn add_template(&mut self, identifier: &str, maybe_data: Option<Vec<u8>>) {
+    /// Adds a template with the given identifier unless there is one already. Returns true if
+    /// it was added.
+    fn add_template(&mut self,
+                    identifier: &str,
+                    maybe_data: Option<Vec<u8>>,
+                    unicode_range: Vec<UnicodeRange>)
+                    -> bool {
         for template in self.templates.iter() {
             if template.identifier() == identifier {
-                return;
+                return false;
             }
         }
 
-        let template = FontTemplate::new(identifier, maybe_data);
+        let template = FontTemplate::new(identifier, maybe_data, unicode_range);
         self.templates.push(template);
+        true
+    }
+
+    /// Removes the template with the given identifier. Returns true if there was one.
+    fn remove_template(&mut self, identifier: &str) -> bool {
+        let template_count = self.templates.len();
+        self.templates.retain(|template| template.identifier() != identifier);
+        self.templates.len() != template_count
     }
 }
 
 /// Commands that the FontContext sends to the font cache task.
 pub enum Command {
-    GetFontTemplate(String, FontTemplateDescriptor, Sender<Reply>),
+    GetFontTemplates(String, FontTemplateDescriptor, Sender<Reply>),
     GetLastResortFontTemplate(FontTemplateDescriptor, Sender<Reply>),
-    AddWebFont(Atom, Source, Sender<()>),
+    GetScriptFontTemplate(Script, String, FontTemplateDescriptor, Sender<Reply>),
+    AddWebFont(Atom, Source, Vec<UnicodeRange>, Sender<()>),
+    AddWebFontData(Atom, String, Vec<u8>, Vec<UnicodeRange>, Sender<()>),
+    RemoveWebFont(Atom, String, Sender<()>),
+    AddWebFontListener(Sender<Atom>),
     Exit(Sender<()>),
 }
 
@@ -86,6 +134,7 @@ unsafe impl Send for Command {}
 /// Reply messages sent from the font cache task to the FontContext caller.
 pub enum Reply {
     GetFontTemplateReply(Option<Arc<FontTemplateData>>),
+    GetFontTemplatesReply(Vec<MatchedFontTemplate>),
 }
 
 unsafe impl Send for Reply {}
@@ -95,8 +144,16 @@ unsafe impl Send for Reply {}
 struct FontCache {
     port: Receiver<Command>,
     generic_fonts: HashMap<LowercaseString, LowercaseString>,
+    /// The families that generic families map to for particular scripts.
+    script_fonts: HashMap<(Script, LowercaseString), LowercaseString>,
     local_families: HashMap<LowercaseString, FontFamily>,
     web_families: HashMap<LowercaseString, FontFamily>,
+    /// The channels that are notified with the family name whenever a web font face becomes
+    /// available, so that text using that family can be rebuilt.
+    web_font_listeners: Vec<Sender<Atom>>,
+    /// How many times each web font face has been registered, by family and identifier. Several
+    /// documents may register the same face, and it is evicted when the last of them removes it.
+    web_font_registrations: HashMap<(LowercaseString, String), usize>,
     font_context: FontContextHandle,
     resource_task: ResourceTask,
 }
@@ -117,16 +174,23 @@ impl FontCache {
             let msg = self.port.recv().unwrap();
 
             match msg {
-                Command::GetFontTemplate(family, descriptor, result) => {
+                Command::GetFontTemplates(family, descriptor, result) => {
                     let family = LowercaseString::new(&family);
-                    let maybe_font_template = self.get_font_template(&family, &descriptor);
-                    result.send(Reply::GetFontTemplateReply(maybe_font_template)).unwrap();
+                    let font_templates = self.get_font_templates(&family, &descriptor);
+                    result.send(Reply::GetFontTemplatesReply(font_templates)).unwrap();
                 }
                 Command::GetLastResortFontTemplate(descriptor, result) => {
                     let font_template = self.get_last_resort_font_template(&descriptor);
                     result.send(Reply::GetFontTemplateReply(Some(font_template))).unwrap();
                 }
-                Command::AddWebFont(family_name, src, result) => {
+                Command::GetScriptFontTemplate(script, generic_family, descriptor, result) => {
+                    let generic_family = LowercaseString::new(&generic_family);
+                    let font_template = self.get_script_font_template(script,
+                                                                      generic_family,
+                                                                      &descriptor);
+                    result.send(Reply::GetFontTemplateReply(font_template)).unwrap();
+                }
+                Command::AddWebFont(family_name, src, unicode_range, result) => {
                     let family_name = LowercaseString::new(&family_name);
                     if !self.web_families.contains_key(&family_name) {
                         let family = FontFamily::new();
@@ -136,11 +200,18 @@ impl FontCache {
                     match src {
                         Source::Url(ref url_source) => {
                             let url = &url_source.url;
+                            self.register_web_font(&family_name, &url.to_string());
                             let maybe_resource = load_whole_resource(&self.resource_task, url.clone());
                             match maybe_resource {
                                 Ok((_, bytes)) => {
-                                    let family = &mut self.web_families.get_mut(&family_name).unwrap();
-                                    family.add_template(&url.to_string(), Some(bytes));
+                                    let added = {
+                                        let family = &mut self.web_families.get_mut(&family_name).unwrap();
+                                        family.add_template(&url.to_string(), Some(bytes), unicode_range)
+                                    };
+                                    // A face that was already there changes nothing.
+                                    if added {
+                                        self.notify_web_font_listeners(&family_name);
+                                    }
                                 },
                                 Err(_) => {
                                     debug!("Failed to load web font: family={:?} url={}", family_name, url);
@@ -150,12 +221,54 @@ impl FontCache {
                         Source::Local(ref local_family_name) => {
                             let family = &mut self.web_families.get_mut(&family_name).unwrap();
                             get_variations_for_family(&local_family_name, |path| {
-                                family.add_template(&path, None);
+                                family.add_template(&path, None, unicode_range.clone());
                             });
                         }
                     }
                     result.send(()).unwrap();
                 }
+                Command::AddWebFontData(family_name, identifier, bytes, unicode_range, result) => {
+                    let family_name = LowercaseString::new(&family_name);
+                    if !self.web_families.contains_key(&family_name) {
+                        let family = FontFamily::new();
+                        self.web_families.insert(family_name.clone(), family);
+                    }
+
+                    self.register_web_font(&family_name, &identifier);
+                    let added = {
+                        let family = &mut self.web_families.get_mut(&family_name).unwrap();
+                        family.add_template(&identifier, Some(bytes), unicode_range)
+                    };
+                    if added {
+                        self.notify_web_font_listeners(&family_name);
+                    }
+                    result.send(()).unwrap();
+                }
+                Command::RemoveWebFont(family_name, identifier, result) => {
+                    let family_name = LowercaseString::new(&family_name);
+                    if !self.unregister_web_font(&family_name, &identifier) {
+                        result.send(()).unwrap();
+                        continue
+                    }
+                    let family_is_empty = match self.web_families.get_mut(&family_name) {
+                        Some(family) => {
+                            if !family.remove_template(&identifier) {
+                                debug!("No web font to evict: family={:?} identifier={}",
+                                       family_name,
+                                       identifier);
+                            }
+                            family.templates.is_empty()
+                        }
+                        None => false,
+                    };
+                    if family_is_empty {
+                        self.web_families.remove(&family_name);
+                    }
+                    result.send(()).unwrap();
+                }
+                Command::AddWebFontListener(listener) => {
+                    self.web_font_listeners.push(listener);
+                }
                 Command::Exit(result) => {
                     result.send(()).unwrap();
                     break;
@@ -164,6 +277,36 @@ impl FontCache {
         }
     }
 
+    /// Counts a registration of the web font face with the given identifier.
+    fn register_web_font(&mut self, family_name: &LowercaseString, identifier: &str) {
+        let key = (family_name.clone(), identifier.to_owned());
+        let count = self.web_font_registrations.get(&key).map(|count| *count).unwrap_or(0);
+        self.web_font_registrations.insert(key, count + 1);
+    }
+
+    /// Drops a registration of the web font face with the given identifier. Returns true if it
+    /// was the last one, so that the face should be evicted.
+    fn unregister_web_font(&mut self, family_name: &LowercaseString, identifier: &str) -> bool {
+        let key = (family_name.clone(), identifier.to_owned());
+        let count = match self.web_font_registrations.get(&key) {
+            Some(&count) => count,
+            None => return true,
+        };
+        if count > 1 {
+            self.web_font_registrations.insert(key, count - 1);
+            return false
+        }
+        self.web_font_registrations.remove(&key);
+        true
+    }
+
+    /// Tells every listener that a face in the given web font family is now available. Listeners
+    /// that have hung up are dropped.
+    fn notify_web_font_listeners(&mut self, family_name: &LowercaseString) {
+        let family_name = Atom::from_slice(&**family_name);
+        self.web_font_listeners.retain(|listener| listener.send(family_name.clone()).is_ok());
+    }
+
     fn refresh_local_families(&mut self) {
         self.local_families.clear();
         get_available_families(|family_name| {
@@ -192,13 +335,13 @@ impl FontCache {
 
             if s.templates.len() == 0 {
                 get_variations_for_family(&family_name, |path| {
-                    s.add_template(&path, None);
+                    s.add_template(&path, None, vec!());
                 });
             }
 
             // TODO(Issue #192: handle generic font families, like 'serif' and 'sans-serif'.
             // if such family exists, try to match style to a font
-            let result = s.find_font_for_style(desc, &self.font_context);
+            let result = s.find_font_for_style(desc, &self.font_context, &[]);
             if result.is_some() {
                 return result;
             }
@@ -210,25 +353,44 @@ impl FontCache {
         }
     }
 
-    fn find_font_in_web_family<'a>(&'a mut self, family_name: &LowercaseString, desc: &FontTemplateDescriptor)
-                                -> Option<Arc<FontTemplateData>> {
+    fn find_fonts_in_web_family<'a>(&'a mut self, family_name: &LowercaseString, desc: &FontTemplateDescriptor)
+                                -> Vec<MatchedFontTemplate> {
         if self.web_families.contains_key(family_name) {
             let family = self.web_families.get_mut(family_name).unwrap();
-            let maybe_font = family.find_font_for_style(desc, &self.font_context);
-            maybe_font
+            family.find_fonts_for_style(desc, &self.font_context)
         } else {
-            None
+            vec!()
         }
     }
 
-    fn get_font_template(&mut self, family: &LowercaseString, desc: &FontTemplateDescriptor)
-                            -> Option<Arc<FontTemplateData>> {
+    fn get_font_templates(&mut self, family: &LowercaseString, desc: &FontTemplateDescriptor)
+                             -> Vec<MatchedFontTemplate> {
         let transformed_family_name = self.transform_family(family);
-        let mut maybe_template = self.find_font_in_web_family(&transformed_family_name, desc);
-        if maybe_template.is_none() {
-            maybe_template = self.find_font_in_local_family(&transformed_family_name, desc);
+        let templates = self.find_fonts_in_web_family(&transformed_family_name, desc);
+        if !templates.is_empty() {
+            return templates
+        }
+        match self.find_font_in_local_family(&transformed_family_name, desc) {
+            Some(data) => {
+                vec!(MatchedFontTemplate {
+                    data: data,
+                    unicode_range: vec!(),
+                })
+            }
+            None => vec!(),
         }
-        maybe_template
+    }
+
+    fn get_script_font_template(&mut self,
+                                script: Script,
+                                generic_family: LowercaseString,
+                                desc: &FontTemplateDescriptor)
+                                -> Option<Arc<FontTemplateData>> {
+        let family = match self.script_fonts.get(&(script, generic_family)) {
+            Some(family) => family.clone(),
+            None => return None,
+        };
+        self.find_font_in_local_family(&family, desc)
     }
 
     fn get_last_resort_font_template(&mut self, desc: &FontTemplateDescriptor)
@@ -252,6 +414,10 @@ impl FontCache {
 #[derive(Clone)]
 pub struct FontCacheTask {
     chan: Sender<Command>,
+    /// Shaped text shared by the fonts of all threads that use this font cache.
+    shaped_text_cache: Arc<ShapedTextCache>,
+    /// The platform font objects shared by all threads that use this font cache.
+    font_instances: Arc<Mutex<FontInstanceTable>>,
 }
 
 impl FontCacheTask {
@@ -267,11 +433,26 @@ impl FontCacheTask {
             add_generic_font(&mut generic_fonts, "fantasy", "Papyrus");
             add_generic_font(&mut generic_fonts, "monospace", "Menlo");
 
+            let mut script_fonts = HashMap::new();
+            for script_font in opts::get().script_fonts.iter() {
+                match Script::from_name(&script_font.script) {
+                    Some(script) => {
+                        let generic_family = LowercaseString::new(&script_font.generic_family);
+                        script_fonts.insert((script, generic_family),
+                                            LowercaseString::new(&script_font.family));
+                    }
+                    None => debug!("Unknown script for script font: {}", script_font.script),
+                }
+            }
+
             let mut cache = FontCache {
                 port: port,
                 generic_fonts: generic_fonts,
+                script_fonts: script_fonts,
                 local_families: HashMap::new(),
                 web_families: HashMap::new(),
+                web_font_listeners: vec!(),
+                web_font_registrations: HashMap::new(),
                 font_context: FontContextHandle::new(),
                 resource_task: resource_task,
             };
@@ -282,21 +463,26 @@ impl FontCacheTask {
 
         FontCacheTask {
             chan: chan,
+            shaped_text_cache: Arc::new(ShapedTextCache::new()),
+            font_instances: Arc::new(Mutex::new(FontInstanceTable::new())),
         }
     }
 
-    pub fn get_font_template(&self, family: String, desc: FontTemplateDescriptor)
-                                                -> Option<Arc<FontTemplateData>> {
+    /// Returns the faces of the family that match the descriptor, one per distinct
+    /// `unicode-range`. The result is empty if there is no such family.
+    pub fn get_font_templates(&self, family: String, desc: FontTemplateDescriptor)
+                                                -> Vec<MatchedFontTemplate> {
 
         let (response_chan, response_port) = channel();
-        self.chan.send(Command::GetFontTemplate(family, desc, response_chan)).unwrap();
+        self.chan.send(Command::GetFontTemplates(family, desc, response_chan)).unwrap();
 
         let reply = response_port.recv().unwrap();
 
         match reply {
-            Reply::GetFontTemplateReply(data) => {
-                data
+            Reply::GetFontTemplatesReply(templates) => {
+                templates
             }
+            Reply::GetFontTemplateReply(_) => panic!("Unexpected reply to GetFontTemplates"),
         }
     }
 
@@ -312,15 +498,82 @@ impl FontCacheTask {
             Reply::GetFontTemplateReply(data) => {
                 data.unwrap()
             }
+            Reply::GetFontTemplatesReply(_) => {
+                panic!("Unexpected reply to GetLastResortFontTemplate")
+            }
         }
     }
 
-    pub fn add_web_font(&self, family: Atom, src: Source) {
+    /// Returns the face of the family that the generic family maps to for the script, if one
+    /// was configured with `--script-font` and is installed.
+    pub fn get_script_font_template(&self,
+                                    script: Script,
+                                    generic_family: String,
+                                    desc: FontTemplateDescriptor)
+                                    -> Option<Arc<FontTemplateData>> {
         let (response_chan, response_port) = channel();
-        self.chan.send(Command::AddWebFont(family, src, response_chan)).unwrap();
+        self.chan.send(Command::GetScriptFontTemplate(script,
+                                                      generic_family,
+                                                      desc,
+                                                      response_chan)).unwrap();
+
+        let reply = response_port.recv().unwrap();
+
+        match reply {
+            Reply::GetFontTemplateReply(data) => data,
+            Reply::GetFontTemplatesReply(_) => {
+                panic!("Unexpected reply to GetScriptFontTemplate")
+            }
+        }
+    }
+
+    pub fn add_web_font(&self, family: Atom, src: Source, unicode_range: Vec<UnicodeRange>) {
+        let (response_chan, response_port) = channel();
+        self.chan.send(Command::AddWebFont(family, src, unicode_range, response_chan)).unwrap();
+        response_port.recv().unwrap();
+    }
+
+    /// Registers already-decoded font data as a face of the given web font family. The
+    /// identifier distinguishes the face from the others in the family and is used to evict it
+    /// again; adding the same identifier twice has no effect. An empty `unicode_range` means that
+    /// the face may be used for any character.
+    pub fn add_web_font_data(&self,
+                             family: Atom,
+                             identifier: String,
+                             bytes: Vec<u8>,
+                             unicode_range: Vec<UnicodeRange>) {
+        let (response_chan, response_port) = channel();
+        self.chan.send(Command::AddWebFontData(family,
+                                               identifier,
+                                               bytes,
+                                               unicode_range,
+                                               response_chan)).unwrap();
+        response_port.recv().unwrap();
+    }
+
+    /// Removes a registration of the face with the given identifier, which is the URL of faces
+    /// added by `add_web_font`, and evicts the face from its family once every registration of it
+    /// is removed. Fonts that are already in use keep their data alive until they are dropped.
+    pub fn remove_web_font(&self, family: Atom, identifier: String) {
+        let (response_chan, response_port) = channel();
+        self.chan.send(Command::RemoveWebFont(family, identifier, response_chan)).unwrap();
         response_port.recv().unwrap();
     }
 
+    /// Asks to be sent the family name whenever a face of a web font family becomes available.
+    pub fn add_web_font_listener(&self, listener: Sender<Atom>) {
+        self.chan.send(Command::AddWebFontListener(listener)).unwrap();
+    }
+
+    pub fn shaped_text_cache(&self) -> Arc<ShapedTextCache> {
+        self.shaped_text_cache.clone()
+    }
+
+    /// Returns the table of font instances shared by the threads that use this font cache.
+    pub fn font_instances(&self) -> Arc<Mutex<FontInstanceTable>> {
+        self.font_instances.clone()
+    }
+
     pub fn exit(&self) {
         let (response_chan, response_port) = channel();
         self.chan.send(Command::Exit(response_chan)).unwrap();
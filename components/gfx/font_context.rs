@@ -2,21 +2,25 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use font::{Font, FontGroup};
+use font::{Font, FontGroup, FontSynthesisFlags, FontVariations};
 use font::SpecifiedFontStyle;
 use platform::font_context::FontContextHandle;
 use style::computed_values::{font_style, font_variant};
 
 use font::FontHandleMethods;
 use font_cache_task::FontCacheTask;
+use font_instance::{FontInstanceKey, SharedScaledFont};
 use font_template::FontTemplateDescriptor;
 use platform::font::FontHandle;
 use platform::font_template::FontTemplateData;
+use text::util::Script;
 use util::cache::HashCache;
 use util::fnv::FnvHasher;
 use util::geometry::Au;
+use util::opts;
 use util::smallvec::{SmallVec, SmallVec8};
 
+use std::ascii::AsciiExt;
 use std::borrow::{self, ToOwned};
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -34,17 +38,39 @@ use azure::scaled_font::ScaledFont;
 use azure::scaled_font::FontInfo;
 
 #[cfg(any(target_os="linux", target_os = "android"))]
-fn create_scaled_font(template: &Arc<FontTemplateData>, pt_size: Au) -> ScaledFont {
-    ScaledFont::new(BackendType::Skia, FontInfo::FontData(&template.bytes),
+fn create_scaled_font(handle: &FontHandle, pt_size: Au) -> ScaledFont {
+    ScaledFont::new(BackendType::Skia, FontInfo::NativeFont(handle.face),
                     pt_size.to_subpx() as AzFloat)
 }
 
 #[cfg(target_os="macos")]
-fn create_scaled_font(template: &Arc<FontTemplateData>, pt_size: Au) -> ScaledFont {
-    let cgfont = template.ctfont.as_ref().unwrap().copy_to_CGFont();
+fn create_scaled_font(handle: &FontHandle, pt_size: Au) -> ScaledFont {
+    // The graphics font of a Core Text font keeps its variation coordinates.
+    let cgfont = handle.ctfont.copy_to_CGFont();
     ScaledFont::new(BackendType::Skia, &cgfont, pt_size.to_subpx() as AzFloat)
 }
 
+/// Creates the scaled font of an instance from a platform font of its own, with the variation
+/// coordinates and the hinting of the instance applied.
+fn create_shared_scaled_font(template: &Arc<FontTemplateData>, key: &FontInstanceKey)
+                             -> Option<SharedScaledFont> {
+    let platform_handle = FontContextHandle::new_with_hinting(key.hinting, key.autohint);
+    let mut handle = match FontHandle::new_from_template(&platform_handle,
+                                                         template.clone(),
+                                                         Some(key.pt_size)) {
+        Ok(handle) => handle,
+        Err(()) => {
+            debug!("Unable to create a paint font for {}", key.identifier);
+            return None
+        }
+    };
+    if !key.variations.is_empty() {
+        handle.set_variations(&key.variations);
+    }
+    let scaled_font = create_scaled_font(&handle, key.pt_size);
+    Some(SharedScaledFont::new(scaled_font, handle))
+}
+
 static SMALL_CAPS_SCALE_FACTOR: f64 = 0.8;      // Matches FireFox (see gfxFont.h)
 
 struct LayoutFontCacheEntry {
@@ -56,12 +82,22 @@ struct FallbackFontCacheEntry {
     font: Rc<RefCell<Font>>,
 }
 
+/// A font that a generic family maps to for a script, or `None` if it isn't installed.
+struct ScriptFontCacheEntry {
+    generic_family: String,
+    script: Script,
+    font: Option<Rc<RefCell<Font>>>,
+}
+
+/// The generic family whose script fonts are used when none of the requested families exist,
+/// matching the initial value of `font-family`.
+static DEFAULT_GENERIC_FAMILY: &'static str = "serif";
+
 /// A cached azure font (per paint task) that
 /// can be shared by multiple text runs.
 struct PaintFontCacheEntry {
-    pt_size: Au,
-    identifier: String,
-    font: Rc<RefCell<ScaledFont>>,
+    key: FontInstanceKey,
+    font: Arc<SharedScaledFont>,
 }
 
 /// The FontContext represents the per-thread/task state necessary for
@@ -75,6 +111,7 @@ pub struct FontContext {
     /// TODO: See bug https://github.com/servo/servo/issues/3300.
     layout_font_cache: Vec<LayoutFontCacheEntry>,
     fallback_font_cache: Vec<FallbackFontCacheEntry>,
+    script_font_cache: Vec<ScriptFontCacheEntry>,
 
     /// Strong reference as the paint FontContext is (for now) recycled
     /// per frame. TODO: Make this weak when incremental redraw is done.
@@ -92,6 +129,7 @@ impl FontContext {
             font_cache_task: font_cache_task,
             layout_font_cache: vec!(),
             fallback_font_cache: vec!(),
+            script_font_cache: vec!(),
             paint_font_cache: vec!(),
             layout_font_group_cache: HashMap::with_hash_state(Default::default()),
         }
@@ -99,8 +137,8 @@ impl FontContext {
 
     /// Create a font for use in layout calculations.
     fn create_layout_font(&self, template: Arc<FontTemplateData>,
-                            descriptor: FontTemplateDescriptor, pt_size: Au,
-                            variant: font_variant::T) -> Result<Font, ()> {
+                            descriptor: FontTemplateDescriptor, variations: FontVariations,
+                            pt_size: Au, variant: font_variant::T) -> Result<Font, ()> {
         // TODO: (Bug #3463): Currently we only support fake small-caps
         // painting. We should also support true small-caps (where the
         // font supports it) in the future.
@@ -113,19 +151,34 @@ impl FontContext {
             FontHandleMethods::new_from_template(&self.platform_handle, template,
                                                  Some(actual_pt_size));
 
-        handle.map(|handle| {
+        handle.map(|mut handle| {
+            let varied_axes = if variations.is_empty() {
+                vec!()
+            } else {
+                handle.set_variations(&variations)
+            };
             let metrics = handle.get_metrics();
+            let synthesis = FontSynthesisFlags::for_face(&descriptor,
+                                                         handle.boldness().is_bold(),
+                                                         handle.is_italic(),
+                                                         &varied_axes);
 
             Font {
                 handle: handle,
                 shaper: None,
                 variant: variant,
                 descriptor: descriptor,
+                variations: variations,
                 requested_pt_size: pt_size,
                 actual_pt_size: actual_pt_size,
                 metrics: metrics,
                 shape_cache: HashCache::new(),
                 glyph_advance_cache: HashCache::new(),
+                glyph_descent_cache: HashCache::new(),
+                synthesis: synthesis,
+                unicode_range: vec!(),
+                shaped_text_cache: self.font_cache_task.shaped_text_cache(),
+                script: None,
             }
         })
     }
@@ -157,10 +210,15 @@ impl FontContext {
                                                style.font_stretch,
                                                style.font_style == font_style::T::italic ||
                                                 style.font_style == font_style::T::oblique);
+        let variations = FontVariations::for_style(style.font_weight, style.font_stretch);
 
         let mut fonts = SmallVec8::new();
 
         for family in style.font_family.iter() {
+            // The fonts that a generic family maps to for particular scripts come before its
+            // default family.
+            self.add_script_fonts(&mut fonts, family.name(), &desc, &variations, &*style);
+
             // GWTODO: Check on real pages if this is faster as Vec() or HashMap().
             let mut cache_hit = false;
             for cached_font_entry in self.layout_font_cache.iter() {
@@ -172,12 +230,14 @@ impl FontContext {
                         }
                         Some(ref cached_font_ref) => {
                             let cached_font = (*cached_font_ref).borrow();
+                            // Keep looking after a hit, since a family with faces for several
+                            // `unicode-range`s has an entry for each of them.
                             if cached_font.descriptor == desc &&
+                               cached_font.variations == variations &&
                                cached_font.requested_pt_size == style.font_size &&
                                cached_font.variant == style.font_variant {
                                 fonts.push((*cached_font_ref).clone());
                                 cache_hit = true;
-                                break;
                             }
                         }
                     }
@@ -185,47 +245,53 @@ impl FontContext {
             }
 
             if !cache_hit {
-                let font_template = self.font_cache_task.get_font_template(family.name()
-                                                                                 .to_owned(),
-                                                                           desc.clone());
-                match font_template {
-                    Some(font_template) => {
-                        let layout_font = self.create_layout_font(font_template,
-                                                                  desc.clone(),
-                                                                  style.font_size,
-                                                                  style.font_variant);
-                        let font = match layout_font {
-                            Ok(layout_font) => {
-                                let layout_font = Rc::new(RefCell::new(layout_font));
-                                fonts.push(layout_font.clone());
-
-                                Some(layout_font)
-                            }
-                            Err(_) => None
-                        };
+                let font_templates = self.font_cache_task.get_font_templates(family.name()
+                                                                                   .to_owned(),
+                                                                             desc.clone());
+                if font_templates.is_empty() {
+                    self.layout_font_cache.push(LayoutFontCacheEntry {
+                        family: family.name().to_owned(),
+                        font: None,
+                    });
+                }
+                for font_template in font_templates.into_iter() {
+                    let layout_font = self.create_layout_font(font_template.data,
+                                                              desc.clone(),
+                                                              variations.clone(),
+                                                              style.font_size,
+                                                              style.font_variant);
+                    let font = match layout_font {
+                        Ok(mut layout_font) => {
+                            layout_font.unicode_range = font_template.unicode_range;
+                            let layout_font = Rc::new(RefCell::new(layout_font));
+                            fonts.push(layout_font.clone());
+
+                            Some(layout_font)
+                        }
+                        Err(_) => None
+                    };
 
-                        self.layout_font_cache.push(LayoutFontCacheEntry {
-                            family: family.name().to_owned(),
-                            font: font
-                        });
-                    }
-                    None => {
-                        self.layout_font_cache.push(LayoutFontCacheEntry {
-                            family: family.name().to_owned(),
-                            font: None,
-                        });
-                    }
+                    self.layout_font_cache.push(LayoutFontCacheEntry {
+                        family: family.name().to_owned(),
+                        font: font
+                    });
                 }
             }
         }
 
-        // If unable to create any of the specified fonts, create one from the
-        // list of last resort fonts for this platform.
-        if fonts.len() == 0 {
+        // If unable to create any of the specified fonts, or only fonts for particular scripts,
+        // create one from the list of last resort fonts for this platform.
+        if !fonts.iter().any(|font| font.borrow().script.is_none()) {
+            if fonts.len() == 0 {
+                self.add_script_fonts(&mut fonts, DEFAULT_GENERIC_FAMILY, &desc, &variations,
+                                      &*style);
+            }
+
             let mut cache_hit = false;
             for cached_font_entry in self.fallback_font_cache.iter() {
                 let cached_font = cached_font_entry.font.borrow();
                 if cached_font.descriptor == desc &&
+                            cached_font.variations == variations &&
                             cached_font.requested_pt_size == style.font_size &&
                             cached_font.variant == style.font_variant {
                     fonts.push(cached_font_entry.font.clone());
@@ -238,6 +304,7 @@ impl FontContext {
                 let font_template = self.font_cache_task.get_last_resort_font_template(desc.clone());
                 let layout_font = self.create_layout_font(font_template,
                                                           desc.clone(),
+                                                          variations.clone(),
                                                           style.font_size,
                                                           style.font_variant);
                 match layout_font {
@@ -258,25 +325,111 @@ impl FontContext {
         font_group
     }
 
-    /// Create a paint font for use with azure. May return a cached
-    /// reference if already used by this font context.
+    /// Adds the fonts that the family maps to for particular scripts, if it is a generic family
+    /// that has any (see `--script-font`). Each of them is only used for characters of its
+    /// script.
+    fn add_script_fonts(&mut self,
+                        fonts: &mut SmallVec8<Rc<RefCell<Font>>>,
+                        family: &str,
+                        desc: &FontTemplateDescriptor,
+                        variations: &FontVariations,
+                        style: &SpecifiedFontStyle) {
+        let family = family.to_ascii_lowercase();
+        for script_font in opts::get().script_fonts.iter() {
+            if script_font.generic_family != family {
+                continue
+            }
+            let script = match Script::from_name(&script_font.script) {
+                Some(script) => script,
+                None => continue,
+            };
+
+            let mut cache_hit = false;
+            for cached_font_entry in self.script_font_cache.iter() {
+                if cached_font_entry.generic_family != family ||
+                        cached_font_entry.script != script {
+                    continue
+                }
+                match cached_font_entry.font {
+                    None => {
+                        cache_hit = true;
+                        break;
+                    }
+                    Some(ref cached_font_ref) => {
+                        let cached_font = cached_font_ref.borrow();
+                        if cached_font.descriptor == *desc &&
+                                cached_font.variations == *variations &&
+                                cached_font.requested_pt_size == style.font_size &&
+                                cached_font.variant == style.font_variant {
+                            fonts.push(cached_font_ref.clone());
+                            cache_hit = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            if cache_hit {
+                continue
+            }
+
+            let font_template = self.font_cache_task.get_script_font_template(script,
+                                                                              family.clone(),
+                                                                              desc.clone());
+            let layout_font = match font_template {
+                Some(font_template) => {
+                    self.create_layout_font(font_template,
+                                            desc.clone(),
+                                            variations.clone(),
+                                            style.font_size,
+                                            style.font_variant).ok()
+                }
+                None => None,
+            };
+            let font = layout_font.map(|mut layout_font| {
+                layout_font.script = Some(script);
+                let layout_font = Rc::new(RefCell::new(layout_font));
+                fonts.push(layout_font.clone());
+                layout_font
+            });
+            self.script_font_cache.push(ScriptFontCacheEntry {
+                generic_family: family.clone(),
+                script: script,
+                font: font,
+            });
+        }
+    }
+
+    /// Create a paint font for use with azure, for painting at `scale` device pixels per px. May
+    /// return a cached reference if already used by this font context, or the font of another
+    /// thread that uses the same instance. Returns `None` if the font can't be created.
     pub fn get_paint_font_from_template(&mut self,
                                          template: &Arc<FontTemplateData>,
-                                         pt_size: Au)
-                                         -> Rc<RefCell<ScaledFont>> {
+                                         key: &FontInstanceKey,
+                                         scale: f32)
+                                         -> Option<Arc<SharedScaledFont>> {
+        // The font is made at the size that it has in device pixels, so each scale that text is
+        // painted at has fonts of its own, and the same text at 2x and at twice the size at 1x
+        // share one.
+        let key = &FontInstanceKey {
+            pt_size: key.pt_size.scale_by(scale as f64),
+            ..key.clone()
+        };
         for cached_font in self.paint_font_cache.iter() {
-            if cached_font.pt_size == pt_size &&
-               cached_font.identifier == template.identifier {
-                return cached_font.font.clone();
+            if cached_font.key == *key {
+                return Some(cached_font.font.clone());
             }
         }
 
-        let paint_font = Rc::new(RefCell::new(create_scaled_font(template, pt_size)));
-        self.paint_font_cache.push(PaintFontCacheEntry{
-            font: paint_font.clone(),
-            pt_size: pt_size,
-            identifier: template.identifier.clone(),
+        let font_instances = self.font_cache_task.font_instances();
+        let paint_font = font_instances.lock().unwrap().find_or_create_scaled_font(key, || {
+            create_shared_scaled_font(template, key)
         });
+        if let Some(ref paint_font) = paint_font {
+            self.paint_font_cache.push(PaintFontCacheEntry{
+                key: key.clone(),
+                font: paint_font.clone(),
+            });
+        }
         paint_font
     }
 
@@ -0,0 +1,101 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Font instances that are shared between threads.
+//!
+//! Every paint task used to create its own platform font object for each face and size it drew
+//! with, so identical fonts were loaded once per thread. The font cache task now owns a table of
+//! the instances in use, keyed by `FontInstanceKey`, and hands out shared references to them.
+
+use font::FontVariations;
+use platform::font::FontHandle;
+
+use azure::scaled_font::ScaledFont;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use util::geometry::Au;
+use util::opts::{FontAutohint, FontHinting};
+
+/// Identifies a font instance: a face at a size with particular variation coordinates and
+/// hinting. Fonts with equal keys are interchangeable.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FontInstanceKey {
+    /// The identifier of the font template.
+    pub identifier: String,
+    pub pt_size: Au,
+    pub variations: FontVariations,
+    /// How the glyphs of the instance are fitted to the pixel grid.
+    pub hinting: FontHinting,
+    pub autohint: FontAutohint,
+}
+
+/// An Azure scaled font that may be drawn with from several paint threads, one at a time.
+pub struct SharedScaledFont {
+    font: Mutex<ScaledFont>,
+    /// The platform font that the scaled font draws with. It has a FreeType library of its own,
+    /// so no other font is affected by the threads that use this one. It is dropped after the
+    /// scaled font.
+    _handle: FontHandle,
+}
+
+// A FreeType face must not be used by two threads at once, so the scaled font and the face under
+// it are only reached through the lock. The platform font and its library belong to this
+// instance alone, and are only touched again when the last reference to it drops.
+unsafe impl Send for SharedScaledFont {}
+unsafe impl Sync for SharedScaledFont {}
+
+impl SharedScaledFont {
+    pub fn new(font: ScaledFont, handle: FontHandle) -> SharedScaledFont {
+        SharedScaledFont {
+            font: Mutex::new(font),
+            _handle: handle,
+        }
+    }
+
+    /// Locks the scaled font so that this thread can draw with it.
+    pub fn lock(&self) -> MutexGuard<ScaledFont> {
+        self.font.lock().unwrap()
+    }
+}
+
+/// The platform font objects of the instances that are in use by any thread. Entries are weak,
+/// so an instance is released once no thread uses it any more.
+pub struct FontInstanceTable {
+    scaled_fonts: HashMap<FontInstanceKey, Weak<SharedScaledFont>>,
+}
+
+impl FontInstanceTable {
+    pub fn new() -> FontInstanceTable {
+        FontInstanceTable {
+            scaled_fonts: HashMap::new(),
+        }
+    }
+
+    /// Returns the scaled font of the instance, creating it if no thread is using it. Returns
+    /// `None` if it can't be created.
+    pub fn find_or_create_scaled_font<F>(&mut self, key: &FontInstanceKey, create: F)
+                                         -> Option<Arc<SharedScaledFont>>
+                                         where F: FnOnce() -> Option<SharedScaledFont> {
+        if let Some(scaled_font) = self.scaled_fonts.get(key).and_then(|font| font.upgrade()) {
+            return Some(scaled_font)
+        }
+
+        // Drop the entries of instances that have been released before adding another one.
+        let released: Vec<FontInstanceKey> = self.scaled_fonts
+                                                 .iter()
+                                                 .filter(|&(_, font)| font.upgrade().is_none())
+                                                 .map(|(key, _)| key.clone())
+                                                 .collect();
+        for key in released.iter() {
+            self.scaled_fonts.remove(key);
+        }
+
+        let scaled_font = match create() {
+            Some(scaled_font) => Arc::new(scaled_font),
+            None => return None,
+        };
+        self.scaled_fonts.insert(key.clone(), scaled_font.downgrade());
+        Some(scaled_font)
+    }
+}
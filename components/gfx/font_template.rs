@@ -10,12 +10,14 @@ use platform::font_template::FontTemplateData;
 use std::borrow::ToOwned;
 use std::sync::{Arc, Weak};
 use style::computed_values::{font_stretch, font_weight};
+use style::font_face::UnicodeRange;
 
 /// Describes how to select a font from a given family. This is very basic at the moment and needs
 /// to be expanded or refactored when we support more of the font styling parameters.
 ///
-/// NB: If you change this, you will need to update `style::properties::compute_font_hash()`.
-#[derive(Clone, Copy, Eq, Hash)]
+/// NB: If you change this, you will need to update `style::properties::compute_font_hash()`,
+/// which must hash every field that is compared here.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FontTemplateDescriptor {
     pub weight: font_weight::T,
     pub stretch: font_stretch::T,
@@ -32,13 +34,66 @@ impl FontTemplateDescriptor {
             italic: italic,
         }
     }
+
+    /// Returns how far a face with the given style is from this requested style, following the
+    /// font matching algorithm of CSS Fonts § 5.2. Faces with smaller distances are preferred.
+    /// Stretch is compared first, then style, then weight; the tuple orders them accordingly.
+    pub fn distance_to(&self, actual: &FontTemplateDescriptor) -> (u32, u32, u32) {
+        (stretch_distance(self.stretch, actual.stretch),
+         if self.italic == actual.italic { 0 } else { 1 },
+         weight_distance(self.weight, actual.weight))
+    }
+}
+
+/// The number of `font-stretch` and `font-weight` values, used to rank all faces on the
+/// requested side ahead of those on the other side.
+static VALUE_COUNT: u32 = 9;
+
+/// Returns the position of the stretch value from narrowest to widest.
+fn stretch_ordinal(stretch: font_stretch::T) -> u32 {
+    match stretch {
+        font_stretch::T::ultra_condensed => 1,
+        font_stretch::T::extra_condensed => 2,
+        font_stretch::T::condensed => 3,
+        font_stretch::T::semi_condensed => 4,
+        font_stretch::T::normal => 5,
+        font_stretch::T::semi_expanded => 6,
+        font_stretch::T::expanded => 7,
+        font_stretch::T::extra_expanded => 8,
+        font_stretch::T::ultra_expanded => 9,
+    }
+}
+
+/// Ranks a face's stretch: if the requested value is normal or narrower, narrower faces are
+/// checked first, closest first, and then wider ones; otherwise the other way around.
+fn stretch_distance(requested: font_stretch::T, actual: font_stretch::T) -> u32 {
+    let (requested, actual) = (stretch_ordinal(requested), stretch_ordinal(actual));
+    let prefer_narrower = requested <= stretch_ordinal(font_stretch::T::normal);
+    directional_distance(requested, actual, prefer_narrower)
+}
+
+/// Ranks a face's weight. Weights below 400 prefer lighter faces, closest first, and then
+/// heavier ones; weights above 500 prefer heavier faces first. 400 checks 500 first and 500
+/// checks 400 first, after which both follow the rule for weights below 400.
+fn weight_distance(requested: font_weight::T, actual: font_weight::T) -> u32 {
+    let (requested, actual) = (requested as u32 / 100, actual as u32 / 100);
+    match (requested, actual) {
+        (4, 5) | (5, 4) => 1,
+        (4, _) | (5, _) if actual == requested => 0,
+        (4, _) | (5, _) => 1 + directional_distance(4, actual, true),
+        _ => directional_distance(requested, actual, requested < 4),
+    }
 }
 
-impl PartialEq for FontTemplateDescriptor {
-    fn eq(&self, other: &FontTemplateDescriptor) -> bool {
-        self.weight.is_bold() == other.weight.is_bold() &&
-            self.stretch == other.stretch &&
-            self.italic == other.italic
+/// Ranks `actual` by its distance from `requested`, putting every value on the preferred side
+/// (below if `prefer_lower`, above otherwise) ahead of every value on the other side.
+fn directional_distance(requested: u32, actual: u32, prefer_lower: bool) -> u32 {
+    if actual == requested {
+        0
+    } else if (actual < requested) == prefer_lower {
+        if actual < requested { requested - actual } else { actual - requested }
+    } else {
+        VALUE_COUNT + if actual < requested { requested - actual } else { actual - requested }
     }
 }
 
@@ -52,13 +107,23 @@ pub struct FontTemplate {
     // GWTODO: Add code path to unset the strong_ref for web fonts!
     strong_ref: Option<Arc<FontTemplateData>>,
     is_valid: bool,
+    /// The characters that this face may be used for. Empty if it covers everything.
+    unicode_range: Vec<UnicodeRange>,
+}
+
+/// A font template that was chosen for a style, along with the characters it may be used for.
+#[derive(Clone)]
+pub struct MatchedFontTemplate {
+    pub data: Arc<FontTemplateData>,
+    pub unicode_range: Vec<UnicodeRange>,
 }
 
 /// Holds all of the template information for a font that
 /// is common, regardless of the number of instances of
 /// this font handle per thread.
 impl FontTemplate {
-    pub fn new(identifier: &str, maybe_bytes: Option<Vec<u8>>) -> FontTemplate {
+    pub fn new(identifier: &str, maybe_bytes: Option<Vec<u8>>, unicode_range: Vec<UnicodeRange>)
+               -> FontTemplate {
         let maybe_data = match maybe_bytes {
             Some(_) => Some(FontTemplateData::new(identifier, maybe_bytes)),
             None => None,
@@ -80,6 +145,7 @@ impl FontTemplate {
             weak_ref: maybe_weak_ref,
             strong_ref: maybe_strong_ref,
             is_valid: true,
+            unicode_range: unicode_range,
         }
     }
 
@@ -87,42 +153,31 @@ impl FontTemplate {
         &*self.identifier
     }
 
-    /// Get the data for creating a font if it matches a given descriptor.
-    pub fn get_if_matches(&mut self,
-                          fctx: &FontContextHandle,
-                          requested_desc: &FontTemplateDescriptor)
-                          -> Option<Arc<FontTemplateData>> {
+    pub fn unicode_range<'a>(&'a self) -> &'a [UnicodeRange] {
+        &*self.unicode_range
+    }
+
+    /// Get the style of this face, loading it if it has not been determined yet. Returns `None`
+    /// if the face cannot be loaded.
+    pub fn descriptor(&mut self, fctx: &FontContextHandle) -> Option<FontTemplateDescriptor> {
         // The font template data can be unloaded when nothing is referencing
         // it (via the Weak reference to the Arc above). However, if we have
         // already loaded a font, store the style information about it separately,
         // so that we can do font matching against it again in the future
-        // without having to reload the font (unless it is an actual match).
+        // without having to reload the font.
         match self.descriptor {
-            Some(actual_desc) => {
-                if *requested_desc == actual_desc {
-                    Some(self.get_data())
-                } else {
-                    None
-                }
-            },
+            Some(actual_desc) => Some(actual_desc),
             None if self.is_valid => {
                 let data = self.get_data();
                 let handle: Result<FontHandle, ()> =
-                    FontHandleMethods::new_from_template(fctx, data.clone(), None);
+                    FontHandleMethods::new_from_template(fctx, data, None);
                 match handle {
                     Ok(handle) => {
                         let actual_desc = FontTemplateDescriptor::new(handle.boldness(),
                                                                       handle.stretchiness(),
                                                                       handle.is_italic());
-                        let desc_match = actual_desc == *requested_desc;
-
                         self.descriptor = Some(actual_desc);
-                        self.is_valid = true;
-                        if desc_match {
-                            Some(data)
-                        } else {
-                            None
-                        }
+                        Some(actual_desc)
                     }
                     Err(()) => {
                         self.is_valid = false;
@@ -0,0 +1,264 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Painting display lists without a window, a compositor or a paint task: into memory, for
+//! embedders, rendering on servers and pixel tests, and page by page, for paged media.
+//!
+//! Without a compositor to put layers together, the stacking contexts that have layers are
+//! painted into the same surface as the rest, where the compositor would put them.
+
+use backends::geometry::{GeometryBackend, ItemGeometry};
+use display_list::StackingContext;
+use display_list::transform3d;
+use font_cache_task::FontCacheTask;
+use font_context::FontContext;
+use mipmap_cache::{MAX_MIPMAP_CACHE_SIZE, MipmapCache};
+use paint_backend::PaintBackend;
+use paint_context::{PaintContext, ToAzureRect};
+
+use azure::AzFloat;
+use azure::azure_hl::{BackendType, DrawTarget, SurfaceFormat};
+use geom::matrix::{self, Matrix4};
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use msg::compositor_msg::ScrollPolicy;
+use std::cmp;
+use std::num::Float;
+use style::computed_values::{filter, mix_blend_mode};
+use util::geometry::Au;
+
+/// Paints the part of the stacking context that the viewport, in the coordinates of the stacking
+/// context, covers, at `scale` device pixels per px. Returns the pixels as tightly packed rows of
+/// RGBA, from the top, with the color unpremultiplied.
+///
+/// Text is painted with fonts from the given font cache task.
+pub fn render_display_list(root: &StackingContext,
+                           viewport: &Rect<Au>,
+                           scale: f32,
+                           font_cache_task: FontCacheTask)
+                           -> Vec<u8> {
+    render(root, viewport, scale, font_cache_task, None)
+}
+
+/// Like `render_display_list()`, but also returns where each display item was painted, in the
+/// same pass over the display list.
+pub fn render_display_list_with_geometry(root: &StackingContext,
+                                         viewport: &Rect<Au>,
+                                         scale: f32,
+                                         font_cache_task: FontCacheTask)
+                                         -> (Vec<u8>, Vec<ItemGeometry>) {
+    let mut geometry = Vec::new();
+    let pixels = render(root, viewport, scale, font_cache_task, Some(&mut geometry));
+    (pixels, geometry)
+}
+
+fn render(root: &StackingContext,
+          viewport: &Rect<Au>,
+          scale: f32,
+          font_cache_task: FontCacheTask,
+          geometry: Option<&mut Vec<ItemGeometry>>)
+          -> Vec<u8> {
+    let page_rect = Rect(Point2D(viewport.origin.x.to_frac32_px(),
+                                 viewport.origin.y.to_frac32_px()),
+                         Size2D(viewport.size.width.to_frac32_px(),
+                                viewport.size.height.to_frac32_px()));
+    let width = (page_rect.size.width * scale).ceil().max(0.0) as usize;
+    let height = (page_rect.size.height * scale).ceil().max(0.0) as usize;
+    if width == 0 || height == 0 {
+        return Vec::new()
+    }
+
+    let draw_target = DrawTarget::new(BackendType::Skia,
+                                      Size2D(width as i32, height as i32),
+                                      SurfaceFormat::B8G8R8A8);
+    let mut font_context = box FontContext::new(font_cache_task);
+    let mut mipmap_cache = MipmapCache::new(MAX_MIPMAP_CACHE_SIZE);
+    {
+        let mut paint_context = PaintContext {
+            draw_target: draw_target.clone(),
+            font_context: &mut font_context,
+            mipmap_cache: &mut mipmap_cache,
+            image_generators: None,
+            page_rect: page_rect,
+            screen_rect: Rect(Point2D(0, 0), Size2D(width, height)),
+            scale: scale,
+            clip_rect: None,
+            transient_clip: None,
+            saved_states: Vec::new(),
+        };
+
+        let matrix: Matrix2D<AzFloat> = Matrix2D::identity();
+        let matrix = matrix.scale(scale as AzFloat, scale as AzFloat);
+        let matrix = matrix.translate(-page_rect.origin.x as AzFloat,
+                                      -page_rect.origin.y as AzFloat);
+        let matrix = transform3d::from_2d(&matrix);
+
+        paint_context.clear();
+        match geometry {
+            None => draw_with_layers(root, &mut paint_context, &page_rect, &matrix, None),
+            Some(geometry) => {
+                let mut geometry_backend = GeometryBackend::new(&mut paint_context);
+                draw_with_layers(root, &mut geometry_backend, &page_rect, &matrix, None);
+                *geometry = geometry_backend.into_items()
+            }
+        }
+        paint_context.draw_target.flush();
+    }
+
+    let data_surface = draw_target.snapshot().get_data_surface();
+    let stride = data_surface.stride() as usize;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    data_surface.with_data(|data| {
+        for row in 0..height {
+            let row = &data[row * stride..row * stride + width * 4];
+            for bgra in row.chunks(4) {
+                let alpha = bgra[3];
+                let unpremultiply = |value: u8| {
+                    if alpha == 0 {
+                        0
+                    } else {
+                        cmp::min((value as u32 * 255 + alpha as u32 / 2) / alpha as u32, 255) as u8
+                    }
+                };
+                pixels.push(unpremultiply(bgra[2]));
+                pixels.push(unpremultiply(bgra[1]));
+                pixels.push(unpremultiply(bgra[0]));
+                pixels.push(alpha);
+            }
+        }
+    });
+    pixels
+}
+
+/// Returns how many pages it takes to paint all of the root stacking context, with pages of the
+/// size of `page_rect`, the first of which is at `page_rect`.
+pub fn page_count(root: &StackingContext, page_rect: &Rect<Au>) -> usize {
+    if page_rect.size.height <= Au(0) {
+        return 0
+    }
+    let height = root.overflow.max_y() - page_rect.origin.y;
+    if height <= Au(0) {
+        return 1
+    }
+    let page_height = page_rect.size.height.0 as i64;
+    ((height.0 as i64 + page_height - 1) / page_height) as usize
+}
+
+/// Paints page `page_index` of the root stacking context with the backend. The first page shows
+/// `page_rect` of the stacking context, and each one after it shows what is below the one before.
+/// Page coordinates start at the top left of the page, in px.
+///
+/// Items that straddle the boundary between two pages are clipped to both, so each page shows
+/// its part of them. Content fixed to the viewport isn't painted where it is in the document
+/// but, as CSS 2.1 has it for paged media, on every page, where it is relative to the viewport.
+pub fn paint_page(root: &StackingContext,
+                  page_rect: &Rect<Au>,
+                  page_index: usize,
+                  paint_backend: &mut PaintBackend) {
+    let page_offset = page_rect.size.height * page_index as i32;
+    let document_rect = page_rect.translate(&Point2D(Au(0), page_offset));
+    let tile_bounds = document_rect.to_azure_rect();
+
+    // Clip everything to the page, including the layers that `draw_with_layers()` paints
+    // outside the clips of the stacking contexts around them.
+    let page_matrix: Matrix2D<AzFloat> = Matrix2D::identity();
+    let page_matrix = page_matrix.translate(-tile_bounds.origin.x, -tile_bounds.origin.y);
+    paint_backend.push_stacking_context(&tile_bounds,
+                                        &page_matrix,
+                                        Some(&document_rect),
+                                        &filter::T::new(Vec::new()),
+                                        mix_blend_mode::T::normal);
+    draw_with_layers(root,
+                     paint_backend,
+                     &tile_bounds,
+                     &transform3d::from_2d(&page_matrix),
+                     Some(Point2D(0.0, page_offset.to_frac32_px())));
+    paint_backend.pop_stacking_context()
+}
+
+/// A stacking context with a layer, with what to paint it with.
+struct Layer<'a> {
+    stacking_context: &'a StackingContext,
+    tile_bounds: Rect<AzFloat>,
+    transform: Matrix4<AzFloat>,
+    clip_rect: Option<&'a Rect<Au>>,
+    scroll_policy: ScrollPolicy,
+}
+
+/// Paints the stacking context and all of the stacking contexts with layers in it, in the order
+/// that the compositor would put them together: fixed backgrounds behind the rest, then the
+/// others in tree order.
+///
+/// If `fixed_offset` isn't `None`, layers fixed to the viewport, and what is in them, are moved by
+/// it on the surface.
+fn draw_with_layers(root: &StackingContext,
+                    paint_backend: &mut PaintBackend,
+                    tile_bounds: &Rect<AzFloat>,
+                    transform: &Matrix4<AzFloat>,
+                    fixed_offset: Option<Point2D<AzFloat>>) {
+    let mut layers = vec!(Layer {
+        stacking_context: root,
+        tile_bounds: *tile_bounds,
+        transform: *transform,
+        clip_rect: None,
+        scroll_policy: ScrollPolicy::Scrollable,
+    });
+    find_layers(root, tile_bounds, transform, fixed_offset, &mut layers);
+
+    let (fixed_backgrounds, others): (Vec<Layer>, Vec<Layer>) =
+        layers.into_iter().partition(|layer| layer.scroll_policy == ScrollPolicy::FixedBackground);
+    for layer in fixed_backgrounds.iter().chain(others.iter()) {
+        layer.stacking_context.optimize_and_draw_into_context(paint_backend,
+                                                              &layer.tile_bounds,
+                                                              &layer.transform,
+                                                              layer.clip_rect)
+    }
+}
+
+/// Adds the stacking contexts with layers under the given one to `layers`, in tree order. The
+/// tile bounds and transform are those that the stacking context is drawn with.
+fn find_layers<'a>(stacking_context: &'a StackingContext,
+                   tile_bounds: &Rect<AzFloat>,
+                   transform: &Matrix4<AzFloat>,
+                   fixed_offset: Option<Point2D<AzFloat>>,
+                   layers: &mut Vec<Layer<'a>>) {
+    // This mirrors how `optimize_and_draw_into_context()` places the stacking contexts in it.
+    let transform = transform.mul(&stacking_context.transform);
+    let transform = stacking_context.children_transform(&transform);
+    for kid in stacking_context.display_list.children.iter() {
+        let mut kid_transform = transform.translate(kid.bounds.origin.x.to_nearest_px() as AzFloat,
+                                                    kid.bounds.origin.y.to_nearest_px() as AzFloat,
+                                                    0.0);
+        let mut kid_tile_bounds =
+            stacking_context.compute_tile_rect_for_child_stacking_context(tile_bounds, &**kid);
+        let mut kid_fixed_offset = fixed_offset;
+
+        if let Some(paint_layer) = kid.compositor_layer() {
+            match (fixed_offset, paint_layer.scroll_policy) {
+                (None, _) | (_, ScrollPolicy::Scrollable) => {}
+                (Some(offset), ScrollPolicy::FixedPosition) |
+                (Some(offset), ScrollPolicy::FixedBackground) => {
+                    // The tile bounds are where the layer would be without the offset, so paint
+                    // all of it, and leave it to the clips to keep it in the surface.
+                    let offset_matrix: Matrix4<AzFloat> = matrix::identity();
+                    kid_transform = offset_matrix.translate(offset.x, offset.y, 0.0)
+                                                 .mul(&kid_transform);
+                    kid_tile_bounds = kid.overflow.to_azure_rect();
+                    kid_fixed_offset = None;
+                }
+            }
+            layers.push(Layer {
+                stacking_context: &**kid,
+                tile_bounds: kid_tile_bounds,
+                transform: kid_transform,
+                clip_rect: Some(&kid.overflow),
+                scroll_policy: paint_layer.scroll_policy,
+            })
+        }
+
+        find_layers(&**kid, &kid_tile_bounds, &kid_transform, kid_fixed_offset, layers)
+    }
+}
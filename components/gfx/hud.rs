@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A heads-up display of painting statistics over the top left of the page (`-Z show-hud`): the
+//! frames painted in the last second, and the display items, layers and memory of the display
+//! list.
+//!
+//! The paint task adds it to each display list that it gets as a stacking context of ordinary
+//! text and solid color items, so every paint backend paints it and the compositor needn't know
+//! that it is there. The numbers change when the display list does.
+
+use color;
+use display_list::{BaseDisplayItem, ClippingRegion, DisplayItem, DisplayItemMetadata};
+use display_list::{DisplayList, DisplayListSection, OpaqueNode, SolidColorDisplayItem};
+use display_list::{StackingContext, TextDisplayItem, TextOrientation};
+use display_list::layer_tree;
+use display_list::visitor::DisplayItemVisitor;
+use font::{ShapingFlags, ShapingOptions};
+use font_cache_task::FontCacheTask;
+use font_context::FontContext;
+use text::glyph::CharIndex;
+
+use geom::matrix;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::i32;
+use std::sync::Arc;
+use style::computed_values::{filter, mix_blend_mode};
+use style::properties::INITIAL_VALUES;
+use time::precise_time_ns;
+use util::geometry::{Au, ZERO_POINT};
+use util::mem::HeapSizeOf;
+use util::range::Range;
+
+/// How far the display is from the top left of the page, and its text from its edges, in px.
+static PADDING: isize = 8;
+
+/// How long frames are counted for, in nanoseconds.
+static FRAME_WINDOW: u64 = 1000000000;
+
+pub struct Hud {
+    /// Shapes the text of the display.
+    font_context: Box<FontContext>,
+    /// When the frames painted in the last second were done, in nanoseconds.
+    frame_times: VecDeque<u64>,
+}
+
+impl Hud {
+    pub fn new(font_cache_task: FontCacheTask) -> Hud {
+        Hud {
+            font_context: box FontContext::new(font_cache_task),
+            frame_times: VecDeque::new(),
+        }
+    }
+
+    /// Counts a frame as painted.
+    pub fn frame_painted(&mut self) {
+        let now = precise_time_ns();
+        self.frame_times.push_back(now);
+        self.forget_old_frames(now)
+    }
+
+    fn forget_old_frames(&mut self, now: u64) {
+        while self.frame_times.front().map_or(false, |&time| now - time > FRAME_WINDOW) {
+            self.frame_times.pop_front();
+        }
+    }
+
+    /// Returns the root stacking context with the display over the top left of it.
+    pub fn add_to(&mut self, root: &Arc<StackingContext>) -> Arc<StackingContext> {
+        self.forget_old_frames(precise_time_ns());
+        let lines = vec![
+            format!("FPS: {}", self.frame_times.len()),
+            format!("Items: {}", item_count(&**root)),
+            format!("Layers: {}", layer_tree::layer_tree(&**root).len()),
+            format!("Display list: {} KiB", root.heap_size_of_children() / 1024),
+        ];
+
+        let font_group = self.font_context.get_layout_font_group_for_style(
+            INITIAL_VALUES.get_font_arc());
+        let options = ShapingOptions {
+            letter_spacing: None,
+            word_spacing: Au(0),
+            tab_size: 8,
+            flags: ShapingFlags::empty(),
+        };
+        let padding = Au::from_px(PADDING);
+        let mut display_list = box DisplayList::new();
+        let mut width = Au(0);
+        let mut line_top = padding;
+        for line in lines.into_iter() {
+            let text_run = Arc::new(box font_group.create_textrun(line, &options, 0));
+            let range = Range::new(CharIndex(0), text_run.char_len());
+            let advance = text_run.advance_for_range(&range);
+            let line_height = text_run.ascent() + text_run.descent();
+            let baseline_origin = Point2D(padding, line_top + text_run.ascent());
+            let text = DisplayItem::TextClass(box TextDisplayItem {
+                base: base_item(Rect(Point2D(padding, line_top), Size2D(advance, line_height))),
+                text_run: text_run,
+                range: range,
+                text_color: color::white(),
+                baseline_origin: baseline_origin,
+                orientation: TextOrientation::Upright,
+                blur_radius: Au(0),
+                shadows: Vec::new(),
+                decorations: Vec::new(),
+                ruby_annotation: None,
+                emphasis_marks: None,
+            });
+            display_list.get_mut(DisplayListSection::Content).push(text);
+            width = max(width, advance);
+            line_top = line_top + line_height;
+        }
+
+        let size = Size2D(width + padding * 2, line_top + padding);
+        let background = DisplayItem::SolidColorClass(box SolidColorDisplayItem {
+            base: base_item(Rect(ZERO_POINT, size)),
+            color: color::rgba(0.0, 0.0, 0.0, 0.75),
+        });
+        display_list.get_mut(DisplayListSection::BackgroundAndBorders).push(background);
+        let origin = root.overflow.origin + Point2D(padding, padding);
+        let hud = Arc::new(StackingContext::new(display_list,
+                                                &Rect(origin, size),
+                                                &Rect(ZERO_POINT, size),
+                                                i32::MAX,
+                                                &matrix::identity(),
+                                                &matrix::identity(),
+                                                false,
+                                                filter::T::new(Vec::new()),
+                                                mix_blend_mode::T::normal,
+                                                None,
+                                                root.device_pixels_per_px));
+
+        let mut children = root.display_list.children.clone();
+        children.push(hud);
+        let display_list = box root.display_list.clone_with_children(children);
+        Arc::new(StackingContext::new(display_list,
+                                      &root.bounds,
+                                      &root.overflow,
+                                      root.z_index,
+                                      &root.transform,
+                                      &root.perspective,
+                                      root.preserves_3d,
+                                      root.filters.clone(),
+                                      root.blend_mode,
+                                      root.layer.clone(),
+                                      root.device_pixels_per_px))
+    }
+}
+
+fn base_item(bounds: Rect<Au>) -> BaseDisplayItem {
+    BaseDisplayItem::new(bounds,
+                         DisplayItemMetadata {
+                             node: OpaqueNode(0),
+                             pointing: None,
+                             tag: 0,
+                         },
+                         ClippingRegion::max())
+}
+
+/// Returns how many display items there are in the stacking context and those in it.
+fn item_count(stacking_context: &StackingContext) -> usize {
+    let mut counter = ItemCounter(0);
+    stacking_context.visit_in_paint_order(&mut counter);
+    counter.0
+}
+
+struct ItemCounter(usize);
+
+impl DisplayItemVisitor for ItemCounter {
+    fn visit_item(&mut self, _: &DisplayItem) {
+        self.0 += 1
+    }
+}
@@ -0,0 +1,136 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Images that are drawn at paint time, at the size at which they are shown, by registered
+//! generators, in the manner of a CSS paint worklet. Pages refer to a generator by name with
+//! `paint(name, arguments…)` wherever an image can go.
+//!
+//! Generators are registered with `register()` for the whole process. Every paint task shares
+//! the registry with its worker threads, which keep what each generator draws for a size and list
+//! of arguments, so that the tiles that show an image and the frames painted after them don't
+//! draw it again.
+
+use net_traits::image::base::Image;
+use std::collections::HashMap;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, ONCE_INIT, Once};
+use util::cache::SizedLRUCache;
+
+/// How many bytes of generated images are kept before the least recently used are dropped.
+static MAX_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+/// Something that draws an image on request.
+pub trait ImageGenerator : Send + Sync {
+    /// Returns an image of `width` by `height` pixels in the pixel format that decoded images
+    /// have, or `None` to draw nothing. `arguments` are those that the page gave after the name
+    /// of the generator, each as the CSS it was written as.
+    fn generate(&self, width: u32, height: u32, arguments: &[String]) -> Option<Image>;
+}
+
+/// An image that a generator draws: what `paint()` refers to.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct GeneratedImage {
+    /// The name that the generator is registered under.
+    pub name: String,
+    pub arguments: Vec<String>,
+}
+
+/// The registry of the paint tasks, as their worker threads share it.
+pub type ImageGenerators = Arc<Mutex<ImageGeneratorRegistry>>;
+
+static mut IMAGE_GENERATORS: *const ImageGenerators = 0 as *const ImageGenerators;
+static INIT: Once = ONCE_INIT;
+
+/// Returns the registry that every paint task draws generated images with.
+pub fn registry() -> ImageGenerators {
+    INIT.call_once(|| {
+        let image_generators: Box<ImageGenerators> =
+            box Arc::new(Mutex::new(ImageGeneratorRegistry::new()));
+        unsafe {
+            IMAGE_GENERATORS = mem::transmute(image_generators);
+        }
+    });
+    unsafe {
+        debug_assert!(IMAGE_GENERATORS != ptr::null());
+        (*IMAGE_GENERATORS).clone()
+    }
+}
+
+/// Registers a generator for every page under the given name, replacing any generator of that
+/// name. Tiles that were painted before it was registered show it once they are painted again.
+pub fn register(name: String, generator: Box<ImageGenerator>) {
+    registry().lock().unwrap().register(name, generator)
+}
+
+/// The image generators that pages can refer to by name, and the images that they have drawn.
+pub struct ImageGeneratorRegistry {
+    generators: HashMap<String, Arc<Box<ImageGenerator>>>,
+    /// What the generators drew, by image and size, bounded by the bytes of their pixels.
+    cache: SizedLRUCache<(GeneratedImage, u32, u32), Option<Arc<Image>>>,
+}
+
+impl ImageGeneratorRegistry {
+    pub fn new() -> ImageGeneratorRegistry {
+        ImageGeneratorRegistry {
+            generators: HashMap::new(),
+            cache: SizedLRUCache::new(MAX_CACHE_SIZE),
+        }
+    }
+
+    /// Registers a generator under the given name, replacing any generator of that name along
+    /// with what it drew.
+    pub fn register(&mut self, name: String, generator: Box<ImageGenerator>) {
+        self.cache.remove_matching(|key| key.0.name == name);
+        self.generators.insert(name, Arc::new(generator));
+    }
+}
+
+/// Returns the image that the generator draws at `width` by `height` pixels, drawing it if it
+/// isn't in the cache. Returns `None` if there is nothing to draw, or if no generator is
+/// registered under the image's name. The registry isn't locked while the generator draws, so
+/// that threads painting other images don't wait for it.
+pub fn generate(generators: &Mutex<ImageGeneratorRegistry>,
+                generated_image: &GeneratedImage,
+                width: u32,
+                height: u32)
+                -> Option<Arc<Image>> {
+    if width == 0 || height == 0 {
+        return None
+    }
+
+    let key = (generated_image.clone(), width, height);
+    let generator = {
+        let mut registry = generators.lock().unwrap();
+        if let Some(image) = registry.cache.find(&key) {
+            return image
+        }
+        match registry.generators.get(&generated_image.name) {
+            Some(generator) => generator.clone(),
+            None => return None,
+        }
+    };
+
+    let image = generator.generate(width, height, &generated_image.arguments[..]).map(Arc::new);
+
+    // Keep the image unless the generator was replaced while it drew.
+    let mut registry = generators.lock().unwrap();
+    let is_current = match registry.generators.get(&generated_image.name) {
+        Some(current_generator) => &**current_generator as *const Box<ImageGenerator> ==
+            &*generator as *const Box<ImageGenerator>,
+        None => false,
+    };
+    if is_current {
+        let size = size_of(&image);
+        registry.cache.insert(key, image.clone(), size);
+    }
+    image
+}
+
+fn size_of(image: &Option<Arc<Image>>) -> usize {
+    match *image {
+        Some(ref image) => image.width as usize * image.height as usize * 4,
+        None => 0,
+    }
+}
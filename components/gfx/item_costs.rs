@@ -0,0 +1,262 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Attribution of the time that painting takes to the kinds of display items and to the DOM nodes
+//! that they come from (`-Z paint-item-costs`), to find the one item, such as a large blurred
+//! shadow, that makes a page slow to paint.
+//!
+//! Each painting thread times the items that it paints for a tile or a frame and adds the times to
+//! the costs that its paint task keeps. The paint task prints the items that cost the most once a
+//! new display list replaces the one that they were painted from. When simple items are batched
+//! into quads on the GPU, recording them is what is timed.
+//!
+//! Items are also timed when tiles have a budget (`--paint-budget`), so that a tile that takes
+//! longer than it to paint can be reported along with the items and stacking contexts that took
+//! the most of the time.
+
+use backends::geometry::ItemKind;
+use display_list::{DisplayItem, OpaqueNode, StackingContext};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use std::mem;
+use time::precise_time_ns;
+use util::opts;
+
+/// How many of the most expensive kinds of items and nodes are printed.
+static PRINTED_COUNT: usize = 10;
+
+/// How many of the most expensive kinds of items and stacking contexts slow tiles are reported
+/// with.
+static SLOW_PAINT_REPORTED_COUNT: usize = 3;
+
+thread_local!(static RECORDING: RefCell<Option<Recording>> = RefCell::new(None));
+
+struct Recording {
+    costs: ItemCosts,
+    /// The address of the stacking context that is being painted, if any.
+    stacking_context: Option<usize>,
+}
+
+/// How many items were painted and how long painting them took altogether.
+#[derive(Clone, Copy)]
+pub struct Cost {
+    pub count: usize,
+    /// In nanoseconds.
+    pub time: u64,
+}
+
+impl Cost {
+    fn add(&mut self, other: &Cost) {
+        self.count += other.count;
+        self.time += other.time;
+    }
+}
+
+/// The costs of painting display items, by their kind, by their node and by the stacking context
+/// that they are in.
+pub struct ItemCosts {
+    pub by_kind: HashMap<ItemKind, Cost>,
+    pub by_node: HashMap<OpaqueNode, Cost>,
+    /// By the address of the stacking context, which stays the same while its display list is
+    /// painted from.
+    pub by_stacking_context: HashMap<usize, Cost>,
+    /// What the stacking contexts in `by_stacking_context` are, for reports.
+    pub stacking_context_names: HashMap<usize, String>,
+}
+
+impl ItemCosts {
+    pub fn new() -> ItemCosts {
+        ItemCosts {
+            by_kind: HashMap::new(),
+            by_node: HashMap::new(),
+            by_stacking_context: HashMap::new(),
+            stacking_context_names: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_kind.is_empty()
+    }
+
+    /// Adds the costs in `other` to these.
+    pub fn merge(&mut self, other: &ItemCosts) {
+        for (kind, cost) in other.by_kind.iter() {
+            add_cost(&mut self.by_kind, *kind, cost)
+        }
+        for (node, cost) in other.by_node.iter() {
+            add_cost(&mut self.by_node, *node, cost)
+        }
+        for (address, cost) in other.by_stacking_context.iter() {
+            add_cost(&mut self.by_stacking_context, *address, cost)
+        }
+        for (address, name) in other.stacking_context_names.iter() {
+            self.stacking_context_names.insert(*address, name.clone());
+        }
+    }
+
+    /// Prints the kinds of items, the nodes and the stacking contexts that cost the most, most
+    /// expensive first.
+    pub fn print(&self) {
+        println!("#### paint costs by item kind:");
+        for &(kind, cost) in most_expensive(&self.by_kind).iter() {
+            println!("# {:?}: {:.3} ms over {} items", kind, milliseconds(&cost), cost.count)
+        }
+        println!("#### paint costs by node:");
+        for &(node, cost) in most_expensive(&self.by_node).iter() {
+            println!("# node {:x}: {:.3} ms over {} items",
+                     node.id(),
+                     milliseconds(&cost),
+                     cost.count)
+        }
+        println!("#### paint costs by stacking context:");
+        for &(address, cost) in most_expensive(&self.by_stacking_context).iter() {
+            println!("# {}: {:.3} ms over {} items",
+                     self.stacking_context_name(address),
+                     milliseconds(&cost),
+                     cost.count)
+        }
+    }
+
+    /// Prints that painting `what` took `time` nanoseconds, over the budget, and which kinds of
+    /// items and which stacking contexts took the most of it.
+    pub fn print_slow_paint(&self, what: &str, time: u64) {
+        let mut kinds = most_expensive(&self.by_kind);
+        kinds.truncate(SLOW_PAINT_REPORTED_COUNT);
+        let kinds: Vec<String> = kinds.iter().map(|&(kind, cost)| {
+            format!("{:?} ({:.3} ms)", kind, milliseconds(&cost))
+        }).collect();
+        let mut stacking_contexts = most_expensive(&self.by_stacking_context);
+        stacking_contexts.truncate(SLOW_PAINT_REPORTED_COUNT);
+        let stacking_contexts: Vec<String> = stacking_contexts.iter().map(|&(address, cost)| {
+            format!("{} ({:.3} ms)", self.stacking_context_name(address), milliseconds(&cost))
+        }).collect();
+        println!("slow paint: {} took {:.3} ms, most in items {} and in stacking contexts {}",
+                 what,
+                 time as f64 / 1000000.0,
+                 kinds.connect(", "),
+                 stacking_contexts.connect(", "))
+    }
+
+    fn stacking_context_name(&self, address: usize) -> &str {
+        match self.stacking_context_names.get(&address) {
+            Some(name) => &name[..],
+            None => "unknown stacking context",
+        }
+    }
+}
+
+/// Starts timing the display items that this thread paints, if paint costs are attributed or
+/// tiles have a budget.
+pub fn begin() {
+    if !opts::get().paint_item_costs && opts::get().paint_budget.is_none() {
+        return
+    }
+    RECORDING.with(|recording| {
+        *recording.borrow_mut() = Some(Recording {
+            costs: ItemCosts::new(),
+            stacking_context: None,
+        })
+    })
+}
+
+/// Returns the costs of the items painted since `begin()`, if any were timed.
+pub fn end() -> Option<ItemCosts> {
+    RECORDING.with(|recording| recording.borrow_mut().take().map(|recording| recording.costs))
+}
+
+/// Attributes the items painted until the returned scope is dropped to the stacking context, if
+/// items are being timed.
+pub fn enter_stacking_context(stacking_context: &StackingContext) -> StackingContextScope {
+    let address = stacking_context as *const StackingContext as usize;
+    let previous = RECORDING.with(|recording| {
+        match *recording.borrow_mut() {
+            Some(ref mut recording) => {
+                if !recording.costs.stacking_context_names.contains_key(&address) {
+                    recording.costs.stacking_context_names.insert(address,
+                                                                  name_of(stacking_context));
+                }
+                mem::replace(&mut recording.stacking_context, Some(address))
+            }
+            None => None,
+        }
+    });
+    StackingContextScope {
+        previous: previous,
+    }
+}
+
+/// Attributes items to the stacking context that was being painted before, once dropped.
+pub struct StackingContextScope {
+    previous: Option<usize>,
+}
+
+impl Drop for StackingContextScope {
+    fn drop(&mut self) {
+        RECORDING.with(|recording| {
+            if let Some(ref mut recording) = *recording.borrow_mut() {
+                recording.stacking_context = self.previous
+            }
+        })
+    }
+}
+
+fn name_of(stacking_context: &StackingContext) -> String {
+    let bounds = &stacking_context.bounds;
+    let layer = match stacking_context.layer {
+        Some(ref layer) => format!(" in layer {:?}", layer.id),
+        None => String::new(),
+    };
+    format!("stacking context at {}x{}+{}+{} (z-index {}){}",
+            bounds.size.width.to_subpx(),
+            bounds.size.height.to_subpx(),
+            bounds.origin.x.to_subpx(),
+            bounds.origin.y.to_subpx(),
+            stacking_context.z_index,
+            layer)
+}
+
+/// Calls `callback`, which paints the item, and adds how long it took to the costs if items are
+/// being timed.
+pub fn record<F>(item: &DisplayItem, callback: F) where F: FnOnce() {
+    if !RECORDING.with(|recording| recording.borrow().is_some()) {
+        return callback()
+    }
+
+    let start_time = precise_time_ns();
+    callback();
+    let cost = Cost {
+        count: 1,
+        time: precise_time_ns() - start_time,
+    };
+    RECORDING.with(|recording| {
+        if let Some(ref mut recording) = *recording.borrow_mut() {
+            add_cost(&mut recording.costs.by_kind, ItemKind::of(item), &cost);
+            add_cost(&mut recording.costs.by_node, item.base().metadata.node, &cost);
+            if let Some(address) = recording.stacking_context {
+                add_cost(&mut recording.costs.by_stacking_context, address, &cost)
+            }
+        }
+    })
+}
+
+fn add_cost<K>(costs: &mut HashMap<K, Cost>, key: K, cost: &Cost) where K: Eq + Hash {
+    match costs.entry(key) {
+        Entry::Vacant(entry) => { entry.insert(*cost); }
+        Entry::Occupied(mut entry) => entry.get_mut().add(cost),
+    }
+}
+
+fn most_expensive<K>(costs: &HashMap<K, Cost>) -> Vec<(K, Cost)> where K: Copy + Eq + Hash {
+    let mut costs: Vec<(K, Cost)> = costs.iter().map(|(key, cost)| (*key, *cost)).collect();
+    costs.sort_by(|&(_, ref a), &(_, ref b)| b.time.cmp(&a.time));
+    costs.truncate(PRINTED_COUNT);
+    costs
+}
+
+fn milliseconds(cost: &Cost) -> f64 {
+    cost.time as f64 / 1000000.0
+}
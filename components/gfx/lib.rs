@@ -22,6 +22,7 @@ extern crate azure;
 #[macro_use] extern crate bitflags;
 extern crate collections;
 extern crate geom;
+extern crate gleam;
 extern crate layers;
 extern crate libc;
 extern crate stb_image;
@@ -56,26 +57,44 @@ extern crate freetype;
 #[cfg(target_os="macos")] extern crate core_graphics;
 #[cfg(target_os="macos")] extern crate core_text;
 
+pub use paint_backend::PaintBackend;
 pub use paint_context::PaintContext;
 
 // Private painting modules
 mod paint_context;
 
 // Painting
+#[path="backends/mod.rs"]
+pub mod backends;
 pub mod color;
+pub mod color_matrix;
 #[path="display_list/mod.rs"]
 pub mod display_list;
+pub mod filters;
+pub mod headless;
+pub mod hud;
+pub mod image_generator;
+pub mod item_costs;
+pub mod paint_backend;
+pub mod paint_markers;
+pub mod paint_order;
 pub mod paint_task;
+pub mod shared_surface;
+pub mod skipped_painting;
+pub mod svg_filter;
 
 // Fonts
 pub mod font;
 pub mod font_context;
 pub mod font_cache_task;
+pub mod font_instance;
 pub mod font_template;
 
 // Misc.
 mod buffer_map;
-mod filters;
+pub mod mipmap_cache;
+mod tile_checksums;
+pub mod tile_queue;
 
 // Platform-specific implementations.
 #[path="platform/mod.rs"]
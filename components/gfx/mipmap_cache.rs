@@ -0,0 +1,138 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Downscaled copies of images, for drawing large images into small areas.
+//!
+//! Bilinear filtering only looks at the four source pixels nearest to each destination pixel, so
+//! an image drawn at a fraction of its size skips most of its pixels and aliases badly. Such
+//! images are instead drawn from a mipmap level: a copy box-filtered down by a power of two so
+//! that it is at most twice as large as the destination. Each paint thread keeps the levels it
+//! has made, so repeated paints of the same image reuse them.
+
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::cmp::{max, min};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use util::cache::SizedLRUCache;
+
+/// How many bytes of mipmap levels each paint thread keeps before it drops the least recently
+/// used ones.
+pub static MAX_MIPMAP_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+/// An image, compared by identity rather than by its pixels.
+#[derive(Clone)]
+struct ImageKey(Arc<Image>);
+
+impl PartialEq for ImageKey {
+    fn eq(&self, other: &ImageKey) -> bool {
+        &*self.0 as *const Image == &*other.0 as *const Image
+    }
+}
+
+impl Eq for ImageKey {}
+
+impl Hash for ImageKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (&*self.0 as *const Image as usize).hash(state)
+    }
+}
+
+pub struct MipmapCache {
+    /// The levels made so far, keyed by the image they were made from and the level number.
+    levels: SizedLRUCache<(ImageKey, u32), Arc<Image>>,
+}
+
+impl MipmapCache {
+    /// Creates a cache that keeps up to `max_size` bytes of mipmap levels.
+    pub fn new(max_size: usize) -> MipmapCache {
+        MipmapCache {
+            levels: SizedLRUCache::new(max_size),
+        }
+    }
+
+    /// Returns the number of bytes of mipmap levels in the cache.
+    pub fn size(&self) -> usize {
+        self.levels.size()
+    }
+
+    /// Returns the given mipmap level of the image, where level `n` is halved `n` times in each
+    /// dimension. Returns the image itself for level 0, or if it can't be downscaled.
+    pub fn find_or_create(&mut self, image: &Arc<Image>, level: u32) -> Arc<Image> {
+        if level == 0 {
+            return image.clone()
+        }
+
+        let key = (ImageKey(image.clone()), level);
+        if let Some(mipmap_level) = self.levels.find(&key) {
+            return mipmap_level
+        }
+
+        let larger_level = self.find_or_create(image, level - 1);
+        let level = match downscale(&*larger_level) {
+            Some(level) => Arc::new(level),
+            None => return larger_level,
+        };
+        let size = match level.pixels {
+            PixelsByColorType::K8(ref pixels) | PixelsByColorType::RGBA8(ref pixels) |
+            PixelsByColorType::RGB8(ref pixels) | PixelsByColorType::KA8(ref pixels) => {
+                pixels.len()
+            }
+        };
+        self.levels.insert(key, level.clone(), size);
+        level
+    }
+}
+
+/// Returns the mipmap level to draw an image of the given size from so that it covers an area
+/// of at least `device_width` by `device_height` pixels. Images are never enlarged by mipmapping.
+pub fn level_for_size(width: u32, height: u32, device_width: f32, device_height: f32) -> u32 {
+    if !(device_width > 0.0 && device_height > 0.0) {
+        return 0
+    }
+
+    let mut level = 0;
+    let (mut width, mut height) = (width, height);
+    while width > 1 && height > 1 &&
+            (width / 2) as f32 >= device_width && (height / 2) as f32 >= device_height {
+        width /= 2;
+        height /= 2;
+        level += 1;
+    }
+    level
+}
+
+/// Halves the image in each dimension with a box filter. The premultiplied channels are
+/// averaged directly. Returns `None` for pixel formats that can't be drawn.
+fn downscale(image: &Image) -> Option<Image> {
+    let (pixel_width, pixels) = match image.pixels {
+        PixelsByColorType::RGBA8(ref pixels) => (4, pixels),
+        PixelsByColorType::K8(ref pixels) => (1, pixels),
+        PixelsByColorType::RGB8(_) | PixelsByColorType::KA8(_) => return None,
+    };
+
+    let (width, height) = (max(1, image.width / 2), max(1, image.height / 2));
+    let sample = |x: u32, y: u32, channel: u32| {
+        pixels[((y * image.width + x) * pixel_width + channel) as usize] as u32
+    };
+
+    let mut result = Vec::with_capacity((width * height * pixel_width) as usize);
+    for y in 0..height {
+        let (top, bottom) = (min(y * 2, image.height - 1), min(y * 2 + 1, image.height - 1));
+        for x in 0..width {
+            let (left, right) = (min(x * 2, image.width - 1), min(x * 2 + 1, image.width - 1));
+            for channel in 0..pixel_width {
+                let sum = sample(left, top, channel) + sample(right, top, channel) +
+                    sample(left, bottom, channel) + sample(right, bottom, channel);
+                result.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+
+    let pixels = match image.pixels {
+        PixelsByColorType::K8(_) => PixelsByColorType::K8(result),
+        _ => PixelsByColorType::RGBA8(result),
+    };
+    Some(Image::new(width, height, pixels))
+}
@@ -0,0 +1,270 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The interface between display lists and the rasterizers that paint them.
+//!
+//! Display items and stacking contexts only talk to a `PaintBackend`, so a backend other than
+//! Azure can paint a display list without the display list code knowing. `PaintContext` is the
+//! Azure backend. Colors and gradient stops are handed to backends as the types here rather than
+//! Azure's, so that backends other than `PaintContext` don't depend on Azure.
+
+use display_list::{BorderRadii, BoxShadowClipMode, ClippingRegion, DisplayItem};
+use display_list::{ImageOrientation, TextDisplayItem};
+use image_generator::ImageGeneratorRegistry;
+
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use net_traits::image::base::Image;
+use std::num::Float;
+use std::sync::{Arc, Mutex};
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+use util::opts::{self, ColorSpace};
+
+/// Blurs are approximated in backends that blur slowly once their standard deviation is larger
+/// than this, in pixels of the surface.
+static MAX_EXPENSIVE_BLUR: f32 = 8.0;
+
+/// A color as backends take it: not premultiplied, in the color space of the surface, with
+/// components that are usually between 0 and 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    #[inline]
+    pub fn new(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color {
+            r: r,
+            g: g,
+            b: b,
+            a: a,
+        }
+    }
+}
+
+/// A stop of a gradient as backends take it, at `offset` from 0 to 1 along the gradient.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GradientStop {
+    pub offset: f32,
+    pub color: Color,
+}
+
+/// What a backend can paint itself. Display items that need what a backend can't do, or does
+/// slowly, are painted with the fallbacks in `backends::fallback` instead.
+#[derive(Clone, Copy, Debug)]
+pub struct BackendCapabilities {
+    pub linear_gradients: bool,
+    pub radial_gradients: bool,
+    pub blur: BlurCost,
+    pub blend_modes: BlendModes,
+}
+
+/// How well a backend blurs box shadows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlurCost {
+    /// The backend blurs quickly at any size, on the GPU or in whatever displays its output.
+    Cheap,
+    /// The backend blurs every pixel on the CPU, so large blurs are slow.
+    Expensive,
+    /// The backend draws shadows with hard edges.
+    Unsupported,
+}
+
+/// Which blend modes a backend composites stacking contexts with itself. Stacking contexts with
+/// the others are blended pixel by pixel with `backends::software::blend()`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlendModes {
+    All,
+    /// All but the non-separable `hue`, `saturation`, `color` and `luminosity` modes.
+    Separable,
+}
+
+impl BlendModes {
+    /// Returns true if the backend composites with the blend mode itself.
+    pub fn includes(self, blend_mode: mix_blend_mode::T) -> bool {
+        match (self, blend_mode) {
+            (BlendModes::All, _) => true,
+            (BlendModes::Separable, mix_blend_mode::T::hue) |
+            (BlendModes::Separable, mix_blend_mode::T::saturation) |
+            (BlendModes::Separable, mix_blend_mode::T::color) |
+            (BlendModes::Separable, mix_blend_mode::T::luminosity) => false,
+            (BlendModes::Separable, _) => true,
+        }
+    }
+}
+
+impl BackendCapabilities {
+    /// The capabilities of a backend that paints everything itself.
+    pub fn all() -> BackendCapabilities {
+        BackendCapabilities {
+            linear_gradients: true,
+            radial_gradients: true,
+            blur: BlurCost::Cheap,
+            blend_modes: BlendModes::All,
+        }
+    }
+
+    /// Returns true if box shadows blurred with the given standard deviation, in pixels of the
+    /// surface, should be painted with an approximate blur rather than by the backend.
+    pub fn approximates_blur(&self, std_deviation: f32) -> bool {
+        match self.blur {
+            BlurCost::Cheap => false,
+            BlurCost::Expensive => std_deviation > MAX_EXPENSIVE_BLUR,
+            BlurCost::Unsupported => std_deviation > 0.0,
+        }
+    }
+}
+
+/// Something that display lists can be painted into.
+///
+/// Coordinates are in app units and go through the current transform, which maps them to the
+/// pixels of the backend's surface.
+pub trait PaintBackend {
+    /// Returns the current transform.
+    fn transform(&self) -> Matrix2D<f32>;
+
+    /// Returns what the backend can paint itself.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::all()
+    }
+
+    /// Returns the color space of the surface, which the colors of display items are converted to
+    /// before they are handed to the backend.
+    fn color_space(&self) -> ColorSpace {
+        opts::get().color_space
+    }
+
+    /// Returns the size that the given rect, in pixels before the current transform, has in
+    /// pixels of the surface.
+    fn device_size(&self, rect: &Rect<f32>) -> Size2D<f32> {
+        let transform = self.transform();
+        let origin = transform.transform_point(&rect.origin);
+        let right = transform.transform_point(&Point2D(rect.max_x(), rect.origin.y));
+        let bottom = transform.transform_point(&Point2D(rect.origin.x, rect.max_y()));
+        Size2D((right - origin).x.hypot((right - origin).y),
+               (bottom - origin).x.hypot((bottom - origin).y))
+    }
+
+    /// Returns the generators that generated images are drawn with. Backends without them draw
+    /// nothing for generated images.
+    fn image_generators(&self) -> Option<&Mutex<ImageGeneratorRegistry>> {
+        None
+    }
+
+    /// Starts painting a stacking context. Until the matching `pop_stacking_context()`, drawing
+    /// uses `transform` and is clipped to `clip_rect`, and it is composited with `filters` and
+    /// `blend_mode` at the end, in which case the backend draws into a temporary surface.
+    /// `tile_bounds` is the part of the stacking context being painted, in its coordinates.
+    fn push_stacking_context(&mut self,
+                             tile_bounds: &Rect<f32>,
+                             transform: &Matrix2D<f32>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T);
+
+    /// Finishes painting the current stacking context, and restores the state from before its
+    /// `push_stacking_context()`.
+    fn pop_stacking_context(&mut self);
+
+    /// Clips the drawing that follows to `clip`, instead of to the region given by the last call.
+    /// Consecutive display items usually have the same clip, so backends should make calls with
+    /// an unchanged region cheap.
+    fn set_clip(&mut self, clip: &ClippingRegion);
+
+    /// Fills the paintable area with transparent black.
+    fn clear(&mut self);
+
+    /// Called with each display item before the calls that draw it, for backends that want to
+    /// know more about what they draw than those calls tell.
+    fn begin_display_item(&mut self, _: &DisplayItem) {}
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: Color);
+
+    fn draw_text(&mut self, text: &TextDisplayItem);
+
+    /// Draws `source_rect` of the image, in its pixels, or all of it, into `bounds`.
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<f32>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation);
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<Color>,
+                   style: &SideOffsets2D<border_style::T>);
+
+    /// Draws a gradient along the line from `start_point` to `end_point`. If `repeating` is set,
+    /// the stops are repeated along the whole line beyond the two points, instead of the end stops
+    /// being extended.
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[GradientStop],
+                            repeating: bool);
+
+    /// Draws a gradient whose stops are on ellipses around `center`, the one at offset 1 with the
+    /// radii `radius`. Both radii are positive.
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[GradientStop]);
+
+    fn draw_line(&mut self, bounds: &Rect<Au>, color: Color, style: border_style::T);
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode);
+}
+
+/// Border widths rounded to whole px, as all backends draw them.
+pub trait ToSideOffsetsPx {
+    fn to_float_px(&self) -> SideOffsets2D<f32>;
+}
+
+impl ToSideOffsetsPx for SideOffsets2D<Au> {
+    fn to_float_px(&self) -> SideOffsets2D<f32> {
+        SideOffsets2D::new(self.top.to_nearest_px() as f32,
+                           self.right.to_nearest_px() as f32,
+                           self.bottom.to_nearest_px() as f32,
+                           self.left.to_nearest_px() as f32)
+    }
+}
+
+/// Border radii rounded to whole px, as all backends draw them.
+pub trait ToRadiiPx {
+    fn to_radii_px(&self) -> BorderRadii<f32>;
+}
+
+impl ToRadiiPx for BorderRadii<Au> {
+    fn to_radii_px(&self) -> BorderRadii<f32> {
+        fn to_nearest_px(x: Au) -> f32 {
+            x.to_nearest_px() as f32
+        }
+
+        BorderRadii {
+            top_left: to_nearest_px(self.top_left),
+            top_right: to_nearest_px(self.top_right),
+            bottom_left: to_nearest_px(self.bottom_left),
+            bottom_right: to_nearest_px(self.bottom_right),
+        }
+    }
+}
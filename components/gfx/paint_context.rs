@@ -7,10 +7,17 @@
 use color;
 use display_list::TextOrientation::{SidewaysLeft, SidewaysRight, Upright};
 use display_list::{BLUR_INFLATION_FACTOR, BorderRadii, BoxShadowClipMode, ClippingRegion};
-use display_list::{TextDisplayItem};
+use display_list::{ImageOrientation, TextDisplayItem};
 use filters;
+use font::{SYNTHETIC_OBLIQUE_FONT_FLAG, SYNTHETIC_OBLIQUE_SKEW};
 use font_context::FontContext;
+use image_generator::ImageGeneratorRegistry;
+use mipmap_cache::{self, MipmapCache};
+use backends::{raster, software};
+use paint_backend::{self, BackendCapabilities, BlendModes, PaintBackend};
+use paint_backend::{ToRadiiPx, ToSideOffsetsPx};
 use text::TextRun;
+use text::hexbox::{self, Hexbox};
 use text::glyph::CharIndex;
 
 use azure::azure::AzIntSize;
@@ -19,7 +26,7 @@ use azure::azure_hl::{DrawOptions, DrawSurfaceOptions, DrawTarget, ExtendMode, F
 use azure::azure_hl::{GaussianBlurAttribute, StrokeOptions, SurfaceFormat};
 use azure::azure_hl::{GaussianBlurInput, GradientStop, Filter, FilterNode, LinearGradientPattern};
 use azure::azure_hl::{JoinStyle, CapStyle};
-use azure::azure_hl::{PatternRef, Path, PathBuilder, CompositionOp};
+use azure::azure_hl::{PatternRef, Path, PathBuilder, CompositionOp, RadialGradientPattern};
 use azure::scaled_font::ScaledFont;
 use azure::{AzFloat, struct__AzDrawOptions, struct__AzGlyph};
 use azure::{struct__AzGlyphBuffer, struct__AzPoint, AzDrawTargetFillGlyphs};
@@ -36,7 +43,7 @@ use std::f32;
 use std::mem;
 use std::num::Float;
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
 use util::geometry::{self, Au, MAX_RECT, ZERO_RECT};
 use util::opts;
@@ -45,16 +52,37 @@ use util::range::Range;
 pub struct PaintContext<'a> {
     pub draw_target: DrawTarget,
     pub font_context: &'a mut Box<FontContext>,
+    /// The downscaled images that this paint thread has made.
+    pub mipmap_cache: &'a mut MipmapCache,
+    /// The generators of the paint task, which draw generated images, if there is one.
+    pub image_generators: Option<&'a Mutex<ImageGeneratorRegistry>>,
     /// The rectangle that this context encompasses in page coordinates.
     pub page_rect: Rect<f32>,
     /// The rectangle that this context encompasses in screen coordinates (pixels).
     pub screen_rect: Rect<usize>,
+    /// How many device pixels a px of the page covers: the device pixel ratio times the zoom.
+    /// Glyphs are rasterized at this resolution rather than scaled up from px.
+    pub scale: f32,
     /// The clipping rect for the stacking context as a whole.
     pub clip_rect: Option<Rect<Au>>,
     /// The current transient clipping region, if any. A "transient clipping region" is the
     /// clipping region used by the last display item. We cache the last value so that we avoid
     /// pushing and popping clipping regions unnecessarily.
     pub transient_clip: Option<ClippingRegion>,
+    /// The state of the enclosing stacking contexts, innermost last.
+    pub saved_states: Vec<SavedPaintState>,
+}
+
+/// The state that `push_stacking_context()` saves and `pop_stacking_context()` restores.
+pub struct SavedPaintState {
+    draw_target: DrawTarget,
+    page_rect: Rect<f32>,
+    clip_rect: Option<Rect<Au>>,
+    transient_clip: Option<ClippingRegion>,
+    /// The transform of the draw target of the stacking context, from before it was set up.
+    transform: Matrix2D<AzFloat>,
+    filters: filter::T,
+    blend_mode: mix_blend_mode::T,
 }
 
 #[derive(Copy, Clone)]
@@ -78,7 +106,7 @@ impl<'a> PaintContext<'a> {
 
     pub fn draw_solid_color(&self, bounds: &Rect<Au>, color: Color) {
         self.draw_target.make_current();
-        self.draw_target.fill_rect(&bounds.to_azure_rect(),
+        self.draw_target.fill_rect(&bounds.to_subpx_azure_rect(),
                                    PatternRef::Color(&ColorPattern::new(color)),
                                    None);
     }
@@ -127,10 +155,53 @@ impl<'a> PaintContext<'a> {
         self.draw_target.pop_clip();
     }
 
-    pub fn draw_image(&self,
+    pub fn draw_image(&mut self,
                       bounds: &Rect<Au>,
                       image: Arc<Image>,
-                      image_rendering: image_rendering::T) {
+                      source_rect: Option<Rect<AzFloat>>,
+                      image_rendering: image_rendering::T,
+                      orientation: ImageOrientation) {
+        let mut source_rect = source_rect.unwrap_or(
+            Rect(Point2D(0.0, 0.0), Size2D(image.width as AzFloat, image.height as AzFloat)));
+        let mut dest_rect = bounds.to_subpx_azure_rect();
+
+        // Turn the image upright by drawing it at the origin in its stored orientation with a
+        // transform that maps it onto the destination.
+        let old_transform = self.draw_target.get_transform();
+        if orientation != ImageOrientation::identity() {
+            let transform = orientation.transform(&dest_rect);
+            self.draw_target.set_transform(&old_transform.mul(&transform));
+            dest_rect.origin = Point2D(0.0, 0.0);
+            if orientation.swaps_dimensions() {
+                dest_rect.size = Size2D(dest_rect.size.height, dest_rect.size.width);
+            }
+        }
+
+        // Draw images that are scaled down to less than half their size from a mipmap level, and
+        // scale the source rect down with them.
+        let image = match image_rendering {
+            image_rendering::T::Auto => {
+                let device_size = PaintBackend::device_size(self, &dest_rect);
+                let device_width =
+                    device_size.width * image.width as AzFloat / source_rect.size.width;
+                let device_height =
+                    device_size.height * image.height as AzFloat / source_rect.size.height;
+                let level = mipmap_cache::level_for_size(image.width,
+                                                         image.height,
+                                                         device_width,
+                                                         device_height);
+                let level_image = self.mipmap_cache.find_or_create(&image, level);
+                let scale = Size2D(level_image.width as AzFloat / image.width as AzFloat,
+                                   level_image.height as AzFloat / image.height as AzFloat);
+                source_rect = Rect(Point2D(source_rect.origin.x * scale.width,
+                                           source_rect.origin.y * scale.height),
+                                   Size2D(source_rect.size.width * scale.width,
+                                          source_rect.size.height * scale.height));
+                level_image
+            }
+            image_rendering::T::CrispEdges | image_rendering::T::Pixelated => image,
+        };
+
         let size = Size2D(image.width as i32, image.height as i32);
         let (pixel_width, pixels, source_format) = match image.pixels {
             PixelsByColorType::RGBA8(ref pixels) => (4, pixels, SurfaceFormat::B8G8R8A8),
@@ -146,9 +217,6 @@ impl<'a> PaintContext<'a> {
                                                                             size,
                                                                             stride as i32,
                                                                             source_format);
-        let source_rect = Rect(Point2D(0.0, 0.0),
-                               Size2D(image.width as AzFloat, image.height as AzFloat));
-        let dest_rect = bounds.to_azure_rect();
 
         // TODO(pcwalton): According to CSS-IMAGES-3 § 5.3, nearest-neighbor interpolation is a
         // conforming implementation of `crisp-edges`, but it is not the best we could do.
@@ -166,6 +234,7 @@ impl<'a> PaintContext<'a> {
                                      source_rect,
                                      draw_surface_options,
                                      draw_options);
+        draw_target_ref.set_transform(&old_transform);
     }
 
     pub fn clear(&self) {
@@ -617,7 +686,7 @@ impl<'a> PaintContext<'a> {
                                   border: &SideOffsets2D<f32>,
                                   color: Color,
                                   dash_size: DashSize) {
-        let rect = bounds.to_azure_rect();
+        let rect = bounds.to_subpx_azure_rect();
         let draw_opts = DrawOptions::new(1 as AzFloat, 0 as uint16_t);
         let border_width = match direction {
             Direction::Top => border.top,
@@ -672,7 +741,7 @@ impl<'a> PaintContext<'a> {
                                  border: &SideOffsets2D<f32>,
                                  radius: &BorderRadii<AzFloat>,
                                  color: Color) {
-        let rect = bounds.to_azure_rect();
+        let rect = bounds.to_subpx_azure_rect();
         self.draw_border_path(&rect, direction, border, radius, color);
     }
 
@@ -680,7 +749,7 @@ impl<'a> PaintContext<'a> {
                          bounds: &Rect<Au>,
                          border: &SideOffsets2D<f32>,
                          shrink_factor: f32) -> Rect<f32> {
-        let rect            = bounds.to_azure_rect();
+        let rect            = bounds.to_subpx_azure_rect();
         let scaled_border   = SideOffsets2D::new(shrink_factor * border.top,
                                                  shrink_factor * border.right,
                                                  shrink_factor * border.bottom,
@@ -825,7 +894,7 @@ impl<'a> PaintContext<'a> {
         // For sideways text, it’s easier to do the rotation such that its center (the baseline’s
         // start point) is at (0, 0) coordinates.
         let baseline_origin = match text.orientation {
-            Upright => text.baseline_origin,
+            Upright => snap_baseline(&text.baseline_origin, &draw_target_transform),
             SidewaysLeft => {
                 let x = text.baseline_origin.x.to_subpx() as AzFloat;
                 let y = text.baseline_origin.y.to_subpx() as AzFloat;
@@ -846,19 +915,82 @@ impl<'a> PaintContext<'a> {
 
         // Draw the text.
         let temporary_draw_target =
-            self.create_draw_target_for_blur_if_necessary(&text.base.bounds, text.blur_radius);
+            self.create_draw_target_for_text_blur_if_necessary(text, &baseline_origin);
         {
+            // Synthetic oblique shears the glyphs about the baseline.
+            let glyph_draw_target = &temporary_draw_target.draw_target;
+            let glyph_draw_target_transform = glyph_draw_target.get_transform();
+            let synthetic_oblique =
+                text.text_run.synthesis.contains(SYNTHETIC_OBLIQUE_FONT_FLAG);
+            if synthetic_oblique {
+                let skew = SYNTHETIC_OBLIQUE_SKEW as AzFloat;
+                let baseline_y = baseline_origin.y.to_subpx() as AzFloat;
+                glyph_draw_target.set_transform(&glyph_draw_target_transform.mul(
+                        &Matrix2D::new(1., 0., -skew, 1., skew * baseline_y, 0.)));
+            }
+
             // FIXME(https://github.com/rust-lang/rust/issues/23338)
-            let font = self.font_context.get_paint_font_from_template(
-                &text.text_run.font_template, text.text_run.actual_pt_size);
-            font
-            .borrow()
-            .draw_text(&temporary_draw_target.draw_target,
-                       &*text.text_run,
-                       &text.range,
-                       baseline_origin,
-                       text.text_color,
-                       opts::get().enable_text_antialiasing);
+            let scale = self.scale;
+            let paint_font = self.font_context.get_paint_font_from_template(
+                &text.text_run.font_template, &text.text_run.font_key, scale);
+            if let Some(ref paint_font) = paint_font {
+                let font = paint_font.lock();
+                PaintContext::draw_text_glyphs(glyph_draw_target,
+                                               &*font,
+                                               scale,
+                                               text,
+                                               baseline_origin);
+
+                // Synthetic bold strikes the glyphs a second time, slightly offset.
+                let synthetic_bold_offset = text.text_run.synthetic_bold_offset();
+                if synthetic_bold_offset != Au(0) {
+                    PaintContext::draw_text_glyphs(glyph_draw_target,
+                                                   &*font,
+                                                   scale,
+                                                   text,
+                                                   Point2D(baseline_origin.x +
+                                                               synthetic_bold_offset,
+                                                           baseline_origin.y));
+                }
+            }
+
+            if synthetic_oblique {
+                glyph_draw_target.set_transform(&glyph_draw_target_transform)
+            }
+
+            // Ruby annotations are painted in their own font, beside the base text.
+            if let Some(ref annotation) = text.ruby_annotation {
+                let offset = annotation.baseline_offset(&**text.text_run, &text.range);
+                let annotation_font = self.font_context.get_paint_font_from_template(
+                    &annotation.text_run.font_template, &annotation.text_run.font_key, scale);
+                if let Some(annotation_font) = annotation_font {
+                    annotation_font.lock().draw_text(glyph_draw_target,
+                                                     scale,
+                                                     &*annotation.text_run,
+                                                     &annotation.range,
+                                                     baseline_origin + offset,
+                                                     annotation.text_color,
+                                                     opts::get().enable_text_antialiasing);
+                }
+            }
+
+            // So are emphasis marks, one for each character that isn't whitespace.
+            if let Some(ref marks) = text.emphasis_marks {
+                let marks_font = self.font_context.get_paint_font_from_template(
+                    &marks.text_run.font_template, &marks.text_run.font_key, scale);
+                if let Some(marks_font) = marks_font {
+                    let marks_font = marks_font.lock();
+                    for offset in marks.baseline_offsets(&**text.text_run, &text.range).iter() {
+                        marks_font.draw_text(glyph_draw_target,
+                                             scale,
+                                             &*marks.text_run,
+                                             &marks.range,
+                                             baseline_origin + *offset,
+                                             marks.text_color,
+                                             opts::get().enable_text_antialiasing);
+                    }
+                }
+            }
         }
 
         // Blur, if necessary.
@@ -870,25 +1002,104 @@ impl<'a> PaintContext<'a> {
         }
     }
 
+    /// Draws the glyphs of the given text item starting at the given point on the baseline. In
+    /// sideways text, the glyph runs that `text-orientation: mixed` keeps upright are turned back
+    /// one glyph at a time, about the center of the space that the glyph occupies on the line.
+    /// `font` is sized for `scale` device pixels per px.
+    fn draw_text_glyphs(draw_target: &DrawTarget,
+                        font: &ScaledFont,
+                        scale: f32,
+                        text: &TextDisplayItem,
+                        baseline_origin: Point2D<Au>) {
+        let antialias = opts::get().enable_text_antialiasing;
+        let run = &text.text_run;
+        if text.orientation == Upright || !run.glyphs.iter().any(|glyph_run| glyph_run.upright) {
+            font.draw_text(draw_target, scale, &**run, &text.range, baseline_origin,
+                           text.text_color, antialias);
+            return
+        }
+
+        let draw_target_transform = draw_target.get_transform();
+        let mut origin = baseline_origin;
+        for glyph_run in run.glyphs.iter() {
+            let range = glyph_run.range.intersect(&text.range);
+            if range.is_empty() {
+                continue
+            }
+            if !glyph_run.upright {
+                font.draw_text(draw_target, scale, &**run, &range, origin, text.text_color,
+                               antialias);
+                origin.x = origin.x + run.advance_for_range(&range);
+                continue
+            }
+
+            for index in range.each_index() {
+                let char_range = Range::new(index, CharIndex(1));
+                let advance = run.advance_for_range(&char_range);
+                let center_x = (origin.x + advance / 2).to_subpx() as AzFloat;
+                let center_y = (origin.y + (run.font_metrics.descent -
+                                            run.font_metrics.ascent) / 2).to_subpx() as AzFloat;
+                // Undo the rotation that `draw_text` applied to the whole item.
+                let rotation = match text.orientation {
+                    SidewaysLeft => Matrix2D::new(0., 1., -1., 0.,
+                                                  center_x + center_y, center_y - center_x),
+                    _ => Matrix2D::new(0., -1., 1., 0.,
+                                       center_x - center_y, center_x + center_y),
+                };
+                draw_target.set_transform(&draw_target_transform.mul(&rotation));
+                font.draw_text(draw_target, scale, &**run, &char_range, origin,
+                               text.text_color, antialias);
+                origin.x = origin.x + advance
+            }
+            draw_target.set_transform(&draw_target_transform);
+        }
+    }
+
     /// Draws a linear gradient in the given boundaries from the given start point to the given end
-    /// point with the given stops.
+    /// point with the given stops, repeating them beyond the two points if `repeating` is set.
     pub fn draw_linear_gradient(&self,
                                 bounds: &Rect<Au>,
                                 start_point: &Point2D<Au>,
                                 end_point: &Point2D<Au>,
-                                stops: &[GradientStop]) {
+                                stops: &[GradientStop],
+                                repeating: bool) {
         self.draw_target.make_current();
 
-        let stops = self.draw_target.create_gradient_stops(stops, ExtendMode::Clamp);
-        let pattern = LinearGradientPattern::new(&start_point.to_azure_point(),
-                                                 &end_point.to_azure_point(),
+        let extend_mode = if repeating { ExtendMode::Repeat } else { ExtendMode::Clamp };
+        let stops = self.draw_target.create_gradient_stops(stops, extend_mode);
+        let pattern = LinearGradientPattern::new(&start_point.to_subpx_azure_point(),
+                                                 &end_point.to_subpx_azure_point(),
                                                  stops,
                                                  &Matrix2D::identity());
-        self.draw_target.fill_rect(&bounds.to_azure_rect(),
+        self.draw_target.fill_rect(&bounds.to_subpx_azure_rect(),
                                    PatternRef::LinearGradient(&pattern),
                                    None);
     }
 
+    /// Draws a radial gradient in the given boundaries with the given stops on ellipses around
+    /// the center, the one at offset 1 with the given radii.
+    pub fn draw_radial_gradient(&self,
+                                bounds: &Rect<Au>,
+                                center: &Point2D<Au>,
+                                radius: &Size2D<Au>,
+                                stops: &[GradientStop]) {
+        self.draw_target.make_current();
+
+        // The gradient is a unit circle that its matrix stretches into the ellipse.
+        let stops = self.draw_target.create_gradient_stops(stops, ExtendMode::Clamp);
+        let origin = Point2D(0.0, 0.0);
+        let pattern = RadialGradientPattern::new(&origin,
+                                                 &origin,
+                                                 0.0,
+                                                 1.0,
+                                                 stops,
+                                                 &raster::radial_gradient_transform(center,
+                                                                                    radius));
+        self.draw_target.fill_rect(&bounds.to_subpx_azure_rect(),
+                                   PatternRef::RadialGradient(&pattern),
+                                   None);
+    }
+
     pub fn get_or_create_temporary_draw_target(&mut self,
                                                filters: &filter::T,
                                                blend_mode: mix_blend_mode::T)
@@ -909,12 +1120,11 @@ impl<'a> PaintContext<'a> {
         let size = self.draw_target.get_size(); //Az size.
         let mut size = Size2D(size.width, size.height); //Geom::Size.
 
-        // Pre-calculate if there is a blur expansion need.
-        let accum_blur = filters::calculate_accumulated_blur(filters);
+        // Pre-calculate if blurs or drop shadows need the surface to be expanded.
+        let side_inflation = filters::calculate_inflation(filters);
         let mut matrix = self.draw_target.get_transform();
-        if accum_blur > Au(0) {
+        if side_inflation > Au(0) {
             // Set the correct size.
-            let side_inflation = accum_blur * BLUR_INFLATION_FACTOR;
             size = Size2D(size.width + (side_inflation.to_nearest_px() * 2) as i32, size.height + (side_inflation.to_nearest_px() * 2) as i32);
 
             // Calculate the transform matrix.
@@ -958,10 +1168,6 @@ impl<'a> PaintContext<'a> {
                                                              filters,
                                                              &mut accum_blur);
 
-        // Perform the blit operation.
-        let mut draw_options = DrawOptions::new(opacity, 0);
-        draw_options.set_composition_op(blend_mode.to_azure_composition_op());
-
        // If there is a blur expansion, shift the transform and update the size.
         if accum_blur > Au(0) {
             // Remove both the transient clip and the stacking context clip, because we may need to
@@ -970,16 +1176,74 @@ impl<'a> PaintContext<'a> {
             self.pop_clip_if_applicable();
 
             debug!("######### use expanded Rect.");
-            self.draw_target.draw_filter(&filter_node, &rect_temporary, &rect_temporary.origin, draw_options);
+            self.composite_filter(&filter_node, &rect_temporary, opacity, blend_mode);
             self.push_clip_if_applicable();
         } else {
             debug!("######### use regular Rect.");
-            self.draw_target.draw_filter(&filter_node, &rect, &rect.origin, draw_options);
+            self.composite_filter(&filter_node, &rect, opacity, blend_mode);
         }
 
         self.draw_target.set_transform(&old_transform);
     }
 
+    /// Draws the end of a filter pipeline onto the draw target, whose transform must be the
+    /// identity, with the opacity and blend mode. Blend modes that aren't left to Azure are
+    /// blended in software.
+    fn composite_filter(&self,
+                        filter_node: &FilterNode,
+                        rect: &Rect<AzFloat>,
+                        opacity: AzFloat,
+                        blend_mode: mix_blend_mode::T) {
+        let mut draw_options = DrawOptions::new(opacity, 0);
+        if self.capabilities().blend_modes.includes(blend_mode) {
+            draw_options.set_composition_op(blend_mode.to_azure_composition_op());
+            self.draw_target.draw_filter(filter_node, rect, &rect.origin, draw_options);
+            return
+        }
+
+        // Draw what is blended by itself, and read it back with the backdrop.
+        let size = self.draw_target.get_size();
+        let size = Size2D(size.width, size.height);
+        let source_draw_target =
+            self.draw_target.create_similar_draw_target(&size, self.draw_target.get_format());
+        source_draw_target.draw_filter(filter_node, rect, &rect.origin, draw_options);
+        let source = source_draw_target.snapshot().get_data_surface();
+        let backdrop = self.draw_target.snapshot().get_data_surface();
+        let (source_stride, backdrop_stride) = (source.stride() as usize,
+                                                backdrop.stride() as usize);
+        let (width, height) = (size.width as usize, size.height as usize);
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        source.with_data(|source_data| {
+            backdrop.with_data(|backdrop_data| {
+                for y in 0..height {
+                    for x in 0..width {
+                        let source_pixel = bgra_pixel(&source_data[y * source_stride + x * 4..]);
+                        let backdrop_pixel =
+                            bgra_pixel(&backdrop_data[y * backdrop_stride + x * 4..]);
+                        let pixel = software::blend(&source_pixel, &backdrop_pixel, blend_mode);
+                        pixels.push_all(&[to_byte(pixel[2]),
+                                          to_byte(pixel[1]),
+                                          to_byte(pixel[0]),
+                                          to_byte(pixel[3])])
+                    }
+                }
+            })
+        });
+
+        let surface = self.draw_target.create_source_surface_from_data(&pixels,
+                                                                       size,
+                                                                       (width * 4) as i32,
+                                                                       SurfaceFormat::B8G8R8A8);
+        let whole_rect = Rect(Point2D(0.0, 0.0), Size2D(width as AzFloat, height as AzFloat));
+        let mut draw_options = DrawOptions::new(1.0, 0);
+        draw_options.set_composition_op(CompositionOp::Source);
+        self.draw_target.draw_surface(surface,
+                                      whole_rect,
+                                      whole_rect,
+                                      DrawSurfaceOptions::new(Filter::Point, true),
+                                      draw_options);
+    }
+
     /// Draws a box shadow with the given boundaries, color, offset, blur radius, and spread
     /// radius. `box_bounds` represents the boundaries of the box.
     pub fn draw_box_shadow(&mut self,
@@ -1053,6 +1317,35 @@ impl<'a> PaintContext<'a> {
         TemporaryDrawTarget::from_bounds(&self.draw_target, &tile_box_bounds)
     }
 
+    /// If a text item has blur, creates a new draw target just large enough to hold the blurred
+    /// glyphs. The glyphs are painted into it as a mask and then blurred with the same kernel as
+    /// box shadows.
+    ///
+    /// Unlike the item bounds, which were inflated at display list construction time, the glyph
+    /// ink bounds are relative to `baseline_origin` and therefore remain correct when the draw
+    /// target has been rotated for sideways text.
+    fn create_draw_target_for_text_blur_if_necessary(&self,
+                                                     text: &TextDisplayItem,
+                                                     baseline_origin: &Point2D<Au>)
+                                                     -> TemporaryDrawTarget {
+        if text.blur_radius == Au(0) {
+            return TemporaryDrawTarget::from_main_draw_target(&self.draw_target)
+        }
+
+        let mut ink_bounds = text.text_run.metrics_for_range(&text.range).bounding_box;
+        ink_bounds.size.width = ink_bounds.size.width + text.text_run.synthetic_bold_offset();
+        let ink_bounds = ink_bounds.translate(baseline_origin);
+        if text.orientation == Upright {
+            return self.create_draw_target_for_blur_if_necessary(&ink_bounds, text.blur_radius)
+        }
+
+        // The tile bounds are in page coordinates, which don't apply to rotated text, so just
+        // size the draw target to the blurred glyphs.
+        let side_inflation = text.blur_radius * BLUR_INFLATION_FACTOR;
+        TemporaryDrawTarget::from_bounds(&self.draw_target,
+                                         &ink_bounds.inflate(side_inflation, side_inflation))
+    }
+
     /// Performs a blur using the draw target created in
     /// `create_draw_target_for_blur_if_necessary`.
     fn blur_if_necessary(&self, temporary_draw_target: TemporaryDrawTarget, blur_radius: Au) {
@@ -1103,6 +1396,169 @@ impl<'a> PaintContext<'a> {
     }
 }
 
+impl<'a> PaintBackend for PaintContext<'a> {
+    fn capabilities(&self) -> BackendCapabilities {
+        // The non-separable blend modes are blended in software, so that they come out as
+        // COMPOSITING-1 defines them and the same as in the other backends.
+        BackendCapabilities {
+            blend_modes: BlendModes::Separable,
+            ..BackendCapabilities::all()
+        }
+    }
+
+    fn transform(&self) -> Matrix2D<AzFloat> {
+        self.draw_target.get_transform()
+    }
+
+    fn image_generators(&self) -> Option<&Mutex<ImageGeneratorRegistry>> {
+        self.image_generators
+    }
+
+    fn push_stacking_context(&mut self,
+                             tile_bounds: &Rect<AzFloat>,
+                             transform: &Matrix2D<AzFloat>,
+                             clip_rect: Option<&Rect<Au>>,
+                             filters: &filter::T,
+                             blend_mode: mix_blend_mode::T) {
+        let temporary_draw_target = self.get_or_create_temporary_draw_target(filters, blend_mode);
+        let transform_of_temporary_draw_target = temporary_draw_target.get_transform();
+        self.saved_states.push(SavedPaintState {
+            draw_target: mem::replace(&mut self.draw_target, temporary_draw_target),
+            page_rect: mem::replace(&mut self.page_rect, *tile_bounds),
+            clip_rect: mem::replace(&mut self.clip_rect, clip_rect.map(|clip_rect| *clip_rect)),
+            transient_clip: mem::replace(&mut self.transient_clip, None),
+            transform: transform_of_temporary_draw_target,
+            filters: filters.clone(),
+            blend_mode: blend_mode,
+        });
+
+        self.draw_target.set_transform(transform);
+        self.push_clip_if_applicable();
+    }
+
+    fn pop_stacking_context(&mut self) {
+        let saved_state = self.saved_states.pop().expect("no stacking context to pop");
+
+        // Undo our clipping and transform.
+        self.remove_transient_clip_if_applicable();
+        self.pop_clip_if_applicable();
+        self.draw_target.set_transform(&saved_state.transform);
+
+        let temporary_draw_target = mem::replace(&mut self.draw_target, saved_state.draw_target);
+        self.page_rect = saved_state.page_rect;
+        self.clip_rect = saved_state.clip_rect;
+        self.transient_clip = saved_state.transient_clip;
+        self.draw_temporary_draw_target_if_necessary(&temporary_draw_target,
+                                                     &saved_state.filters,
+                                                     saved_state.blend_mode)
+    }
+
+    fn set_clip(&mut self, clip: &ClippingRegion) {
+        match self.transient_clip {
+            Some(ref transient_clip) if transient_clip == clip => {}
+            Some(_) | None => self.push_transient_clip((*clip).clone()),
+        }
+    }
+
+    fn clear(&mut self) {
+        PaintContext::clear(self)
+    }
+
+    fn draw_solid_color(&mut self, bounds: &Rect<Au>, color: paint_backend::Color) {
+        PaintContext::draw_solid_color(self, bounds, color.to_azure_color())
+    }
+
+    fn draw_text(&mut self, text: &TextDisplayItem) {
+        PaintContext::draw_text(self, text)
+    }
+
+    fn draw_image(&mut self,
+                  bounds: &Rect<Au>,
+                  image: Arc<Image>,
+                  source_rect: Option<Rect<AzFloat>>,
+                  image_rendering: image_rendering::T,
+                  orientation: ImageOrientation) {
+        PaintContext::draw_image(self, bounds, image, source_rect, image_rendering, orientation)
+    }
+
+    fn draw_border(&mut self,
+                   bounds: &Rect<Au>,
+                   border: &SideOffsets2D<Au>,
+                   radius: &BorderRadii<Au>,
+                   color: &SideOffsets2D<paint_backend::Color>,
+                   style: &SideOffsets2D<border_style::T>) {
+        let color = SideOffsets2D::new(color.top.to_azure_color(),
+                                       color.right.to_azure_color(),
+                                       color.bottom.to_azure_color(),
+                                       color.left.to_azure_color());
+        PaintContext::draw_border(self, bounds, border, radius, &color, style)
+    }
+
+    fn draw_linear_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            start_point: &Point2D<Au>,
+                            end_point: &Point2D<Au>,
+                            stops: &[paint_backend::GradientStop],
+                            repeating: bool) {
+        PaintContext::draw_linear_gradient(self,
+                                           bounds,
+                                           start_point,
+                                           end_point,
+                                           &to_azure_stops(stops),
+                                           repeating)
+    }
+
+    fn draw_radial_gradient(&mut self,
+                            bounds: &Rect<Au>,
+                            center: &Point2D<Au>,
+                            radius: &Size2D<Au>,
+                            stops: &[paint_backend::GradientStop]) {
+        PaintContext::draw_radial_gradient(self, bounds, center, radius, &to_azure_stops(stops))
+    }
+
+    fn draw_line(&mut self,
+                 bounds: &Rect<Au>,
+                 color: paint_backend::Color,
+                 style: border_style::T) {
+        PaintContext::draw_line(self, bounds, color.to_azure_color(), style)
+    }
+
+    fn draw_box_shadow(&mut self,
+                       box_bounds: &Rect<Au>,
+                       offset: &Point2D<Au>,
+                       color: paint_backend::Color,
+                       blur_radius: Au,
+                       spread_radius: Au,
+                       clip_mode: BoxShadowClipMode) {
+        PaintContext::draw_box_shadow(self,
+                                      box_bounds,
+                                      offset,
+                                      color.to_azure_color(),
+                                      blur_radius,
+                                      spread_radius,
+                                      clip_mode)
+    }
+}
+
+pub trait ToAzureColor {
+    fn to_azure_color(&self) -> Color;
+}
+
+impl ToAzureColor for paint_backend::Color {
+    fn to_azure_color(&self) -> Color {
+        Color::new(self.r, self.g, self.b, self.a)
+    }
+}
+
+fn to_azure_stops(stops: &[paint_backend::GradientStop]) -> Vec<GradientStop> {
+    stops.iter().map(|stop| {
+        GradientStop {
+            offset: stop.offset,
+            color: stop.color.to_azure_color(),
+        }
+    }).collect()
+}
+
 pub trait ToAzurePoint {
     fn to_azure_point(&self) -> Point2D<AzFloat>;
     fn to_subpx_azure_point(&self) -> Point2D<AzFloat>;
@@ -1166,41 +1622,12 @@ impl ToAzureIntSize for Size2D<i32> {
     }
 }
 
-trait ToSideOffsetsPx {
-    fn to_float_px(&self) -> SideOffsets2D<AzFloat>;
-}
-
-impl ToSideOffsetsPx for SideOffsets2D<Au> {
-    fn to_float_px(&self) -> SideOffsets2D<AzFloat> {
-        SideOffsets2D::new(self.top.to_nearest_px() as AzFloat,
-                           self.right.to_nearest_px() as AzFloat,
-                           self.bottom.to_nearest_px() as AzFloat,
-                           self.left.to_nearest_px() as AzFloat)
-    }
-}
-
-trait ToRadiiPx {
-    fn to_radii_px(&self) -> BorderRadii<AzFloat>;
-}
-
-impl ToRadiiPx for BorderRadii<Au> {
-    fn to_radii_px(&self) -> BorderRadii<AzFloat> {
-        fn to_nearest_px(x: Au) -> AzFloat {
-            x.to_nearest_px() as AzFloat
-        }
-
-        BorderRadii {
-            top_left: to_nearest_px(self.top_left),
-            top_right: to_nearest_px(self.top_right),
-            bottom_left: to_nearest_px(self.bottom_left),
-            bottom_right: to_nearest_px(self.bottom_right),
-        }
-    }
-}
-
 trait ScaledFontExtensionMethods {
+    /// Draws the glyphs of the range of the run with this font, which is sized for `scale`
+    /// device pixels per px.
     fn draw_text(&self,
                  draw_target: &DrawTarget,
+                 scale: f32,
                  run: &Box<TextRun>,
                  range: &Range<CharIndex>,
                  baseline_origin: Point2D<Au>,
@@ -1211,6 +1638,7 @@ trait ScaledFontExtensionMethods {
 impl ScaledFontExtensionMethods for ScaledFont {
     fn draw_text(&self,
                  draw_target: &DrawTarget,
+                 scale: f32,
                  run: &Box<TextRun>,
                  range: &Range<CharIndex>,
                  baseline_origin: Point2D<Au>,
@@ -1234,21 +1662,61 @@ impl ScaledFontExtensionMethods for ScaledFont {
         let mut origin = baseline_origin.clone();
         let mut azglyphs = vec!();
         azglyphs.reserve(range.length().to_usize());
+        let mut hexboxes = vec!();
+        let show_hexboxes = opts::get().show_hexboxes;
+
+        // The characters of the range, which glyphs carry so that the ones they are missing for
+        // can be found without walking the text from its start for each.
+        let characters: Vec<char> = if show_hexboxes {
+            run.text.chars()
+                    .skip(range.begin().to_usize())
+                    .take(range.length().to_usize())
+                    .collect()
+        } else {
+            vec!()
+        };
 
+        // Glyphs are stored in logical order. Right-to-left runs are painted starting from their
+        // last glyph, so that the origin always progresses from left to right.
+        let mut glyphs = vec!();
         for slice in run.natural_word_slices_in_range(range) {
-            for (_i, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
-                let glyph_advance = glyph.advance();
-                let glyph_offset = glyph.offset().unwrap_or(Point2D::zero());
-                let azglyph = struct__AzGlyph {
-                    mIndex: glyph.id() as uint32_t,
-                    mPosition: struct__AzPoint {
-                        x: (origin.x + glyph_offset.x).to_subpx() as AzFloat,
-                        y: (origin.y + glyph_offset.y).to_subpx() as AzFloat
+            for (i, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+                let character = characters.get((slice.offset + i - range.begin()).to_usize());
+                glyphs.push((character.map(|c| *c), glyph.id(), glyph.advance(), glyph.offset()))
+            }
+        }
+        if run.is_rtl() {
+            glyphs.reverse()
+        }
+
+        for &(character, glyph_id, glyph_advance, glyph_offset) in glyphs.iter() {
+            // Glyph 0 is `.notdef`. The shaper reserved space for a hexbox in its place.
+            if glyph_id == 0 {
+                if let Some(character) = character {
+                    if hexbox::needs_hexbox(character) {
+                        hexboxes.push(Hexbox::new(character, run.actual_pt_size).rects(&origin));
+                        origin = Point2D(origin.x + glyph_advance, origin.y);
+                        continue
                     }
-                };
-                origin = Point2D(origin.x + glyph_advance, origin.y);
-                azglyphs.push(azglyph)
+                }
+            }
+
+            let glyph_offset = glyph_offset.unwrap_or(Point2D::zero());
+            let azglyph = struct__AzGlyph {
+                mIndex: glyph_id as uint32_t,
+                mPosition: struct__AzPoint {
+                    x: (origin.x + glyph_offset.x).to_subpx() as AzFloat * scale,
+                    y: (origin.y + glyph_offset.y).to_subpx() as AzFloat * scale,
+                }
             };
+            origin = Point2D(origin.x + glyph_advance, origin.y);
+            azglyphs.push(azglyph)
+        }
+
+        for rects in hexboxes.iter() {
+            for rect in rects.iter() {
+                draw_target.fill_rect(&rect.to_azure_rect(), PatternRef::Color(&pattern), None);
+            }
         }
 
         let azglyph_buf_len = azglyphs.len();
@@ -1259,6 +1727,9 @@ impl ScaledFontExtensionMethods for ScaledFont {
             mNumGlyphs: azglyph_buf_len as uint32_t
         };
 
+        // The glyph positions and the font are in device pixels, so px are scaled down to them.
+        let transform = draw_target.get_transform();
+        draw_target.set_transform(&transform.scale(1.0 / scale, 1.0 / scale));
         unsafe {
             // TODO(Issue #64): this call needs to move into azure_hl.rs
             AzDrawTargetFillGlyphs(draw_target.azure_draw_target,
@@ -1268,6 +1739,7 @@ impl ScaledFontExtensionMethods for ScaledFont {
                                    &mut options,
                                    ptr::null_mut());
         }
+        draw_target.set_transform(&transform);
     }
 }
 
@@ -1423,3 +1895,27 @@ impl TemporaryDrawTarget {
     }
 }
 
+
+/// Returns the baseline origin moved up or down onto a whole device pixel, unless the transform
+/// rotates or skews. Layout snaps baselines to the pixels of the page zoom, which the pinch zoom
+/// magnifies into fractions of device pixels, and glyphs drawn between pixels are blurred.
+fn snap_baseline(origin: &Point2D<Au>, transform: &Matrix2D<AzFloat>) -> Point2D<Au> {
+    if transform.m12 != 0.0 || transform.m21 != 0.0 || transform.m22 == 0.0 {
+        return *origin
+    }
+    let device_y = origin.y.to_subpx() as AzFloat * transform.m22 + transform.m32;
+    let y = (device_y.round() - transform.m32) / transform.m22;
+    Point2D(origin.x, Au::from_frac32_px(y))
+}
+
+/// Returns the premultiplied RGBA pixel that starts the BGRA8 data.
+fn bgra_pixel(data: &[u8]) -> software::Pixel {
+    [data[2] as AzFloat / 255.0,
+     data[1] as AzFloat / 255.0,
+     data[0] as AzFloat / 255.0,
+     data[3] as AzFloat / 255.0]
+}
+
+fn to_byte(value: AzFloat) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+}
@@ -0,0 +1,91 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Timeline markers for the phases of painting, which are sent to the time profiler when it keeps
+//! a timeline (`--profiler-timeline`).
+//!
+//! Markers are gathered on the thread that paints, so that the display list code needn't be
+//! handed the profiler, and are sent together once the tile or frame that they are for is done.
+
+use profile_traits::time::{ProfilerCategory, ProfilerChan, ProfilerMsg, TimelineMarker};
+use std::cell::RefCell;
+use time::precise_time_ns;
+use util::opts;
+use util::tid::tid;
+
+thread_local!(static RECORDING: RefCell<Option<Recording>> = RefCell::new(None));
+
+struct Recording {
+    subject: String,
+    markers: Vec<TimelineMarker>,
+}
+
+/// Starts gathering markers on this thread for painting `subject`, if the profiler keeps a
+/// timeline.
+pub fn begin(subject: String) {
+    if opts::get().profiler_timeline_file.is_none() {
+        return
+    }
+    RECORDING.with(|recording| {
+        *recording.borrow_mut() = Some(Recording {
+            subject: subject,
+            markers: Vec::new(),
+        })
+    })
+}
+
+/// Sends the markers gathered since `begin()` to the profiler.
+pub fn end(profiler_chan: &ProfilerChan) {
+    let recording = RECORDING.with(|recording| recording.borrow_mut().take());
+    if let Some(recording) = recording {
+        for marker in recording.markers.into_iter() {
+            profiler_chan.send(ProfilerMsg::Marker(marker))
+        }
+    }
+}
+
+/// Calls `callback`, and records how long it took as a marker of the category if markers are
+/// being gathered.
+pub fn record<T, F>(category: ProfilerCategory, callback: F) -> T where F: FnOnce() -> T {
+    if !is_recording() {
+        return callback()
+    }
+
+    let start_time = precise_time_ns();
+    let value = callback();
+    let end_time = precise_time_ns();
+    RECORDING.with(|recording| {
+        if let Some(ref mut recording) = *recording.borrow_mut() {
+            let subject = recording.subject.clone();
+            recording.markers.push(TimelineMarker {
+                category: category,
+                subject: subject,
+                thread: tid(),
+                start_time: start_time,
+                end_time: end_time,
+            })
+        }
+    });
+    value
+}
+
+/// Sends a marker for something that takes no time straight to the profiler, if it keeps a
+/// timeline.
+pub fn mark(profiler_chan: &ProfilerChan, category: ProfilerCategory, subject: String) {
+    if opts::get().profiler_timeline_file.is_none() {
+        return
+    }
+    let time = precise_time_ns();
+    profiler_chan.send(ProfilerMsg::Marker(TimelineMarker {
+        category: category,
+        subject: subject,
+        thread: tid(),
+        start_time: time,
+        end_time: time,
+    }))
+}
+
+fn is_recording() -> bool {
+    RECORDING.with(|recording| recording.borrow().is_some())
+}
@@ -0,0 +1,113 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Labels each display item with the number of its place in the order that items are painted in,
+//! following CSS 2.1 Appendix E (`-Z show-paint-order`), so that what is painted over what can be
+//! read straight off the page.
+//!
+//! Items are numbered from the start of each layer. Every tile of a layer paints all of its items
+//! in this mode, so that each item has the same number in all of them. Labels are drawn in a tiny
+//! built-in font of digits, so they look the same with every paint backend.
+
+use display_list::DisplayItem;
+use paint_backend::{Color, PaintBackend};
+
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use std::cell::RefCell;
+use util::geometry::Au;
+use util::opts;
+
+/// How many px each pixel of the font of labels covers.
+static LABEL_PIXEL_SIZE: isize = 2;
+
+/// Glyphs of 3 by 5 pixels for the digits, a row to each byte.
+static DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+thread_local!(static NUMBERING: RefCell<Numbering> = RefCell::new(Numbering {
+    depth: 0,
+    next_number: 0,
+}));
+
+struct Numbering {
+    /// How many stacking contexts are being painted, one in another.
+    depth: usize,
+    next_number: usize,
+}
+
+/// Returns true if items are labeled with their places in the paint order.
+pub fn is_enabled() -> bool {
+    opts::get().show_paint_order
+}
+
+/// Notes that a stacking context is being painted until the returned scope is dropped. Numbering
+/// starts over with each stacking context that isn't painted in another one, which is each layer.
+pub fn enter_stacking_context() -> StackingContextScope {
+    NUMBERING.with(|numbering| {
+        let mut numbering = numbering.borrow_mut();
+        if numbering.depth == 0 {
+            numbering.next_number = 0
+        }
+        numbering.depth += 1
+    });
+    StackingContextScope
+}
+
+/// Notes that the stacking context is painted, once dropped.
+pub struct StackingContextScope;
+
+impl Drop for StackingContextScope {
+    fn drop(&mut self) {
+        NUMBERING.with(|numbering| numbering.borrow_mut().depth -= 1)
+    }
+}
+
+/// Labels the item, which has just been painted, with the next number, over its top left corner.
+pub fn label(item: &DisplayItem, paint_backend: &mut PaintBackend) {
+    if !is_enabled() {
+        return
+    }
+    let number = NUMBERING.with(|numbering| {
+        let mut numbering = numbering.borrow_mut();
+        numbering.next_number += 1;
+        numbering.next_number - 1
+    });
+
+    let label = number.to_string();
+    let pixel_size = Au::from_px(LABEL_PIXEL_SIZE);
+    let origin = item.base().bounds.origin;
+    paint_backend.draw_solid_color(&Rect(origin,
+                                         Size2D(pixel_size * (label.len() as i32 * 4 + 1),
+                                                pixel_size * 7)),
+                                   Color::new(0.0, 0.0, 0.0, 1.0));
+    for (index, character) in label.chars().enumerate() {
+        let glyph = &DIGIT_GLYPHS[character.to_digit(10).unwrap() as usize];
+        let glyph_origin = Point2D(origin.x + pixel_size * (index as i32 * 4 + 1),
+                                   origin.y + pixel_size);
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue
+                }
+                paint_backend.draw_solid_color(
+                    &Rect(Point2D(glyph_origin.x + pixel_size * column as i32,
+                                  glyph_origin.y + pixel_size * row as i32),
+                          Size2D(pixel_size, pixel_size)),
+                    Color::new(1.0, 1.0, 1.0, 1.0))
+            }
+        }
+    }
+}
@@ -4,14 +4,32 @@
 
 //! The task that handles all painting.
 
+use backends::quad_batch::{self, Batch};
+use backends::quad_renderer::QuadRenderer;
+use backends::recording::{self, RecordingBackend};
+use backends::software::SoftwareBackend;
 use buffer_map::BufferMap;
 use display_list::{self, StackingContext};
+use display_list::invalidation;
+use display_list::layer_tree::{self, LayerTreeNode};
+use display_list::squashing;
+use display_list::transform3d;
 use font_cache_task::FontCacheTask;
 use font_context::FontContext;
+use hud::Hud;
+use image_generator::{self, ImageGenerators};
+use item_costs::{self, ItemCosts};
+use mipmap_cache::{MAX_MIPMAP_CACHE_SIZE, MipmapCache};
 use paint_context::PaintContext;
+use paint_markers;
+use shared_surface::SharedSurface;
+use skipped_painting;
+use tile_checksums;
+use tile_queue::TileQueue;
 
 use azure::azure_hl::{SurfaceFormat, Color, DrawTarget, BackendType};
 use azure::AzFloat;
+use geom::matrix::Matrix4;
 use geom::matrix2d::Matrix2D;
 use geom::point::Point2D;
 use geom::rect::Rect;
@@ -20,23 +38,29 @@ use layers::platform::surface::{NativeGraphicsMetadata, NativePaintingGraphicsCo
 use layers::platform::surface::NativeSurface;
 use layers::layers::{BufferRequest, LayerBuffer, LayerBufferSet};
 use layers;
-use msg::compositor_msg::{Epoch, PaintState, LayerId};
-use msg::compositor_msg::{LayerMetadata, PaintListener, ScrollPolicy};
+use msg::compositor_msg::{Epoch, ExternalTextureId, PaintState, LayerAnimation, LayerId};
+use msg::compositor_msg::{DirectFrame, LayerKind, LayerMetadata, PaintListener, ScrollPolicy};
+use msg::compositor_msg::{OverscrollBehavior, ScrollId, ScrollMetadata, SharedTile};
 use msg::constellation_msg::Msg as ConstellationMsg;
 use msg::constellation_msg::{ConstellationChan, Failure, PipelineId};
 use msg::constellation_msg::PipelineExitType;
 use profile_traits::time::{self, profile};
 use skia::SkiaGrGLNativeContextRef;
 use std::borrow::ToOwned;
+use std::cmp;
+use std::collections::VecDeque;
 use std::mem;
-use std::sync::Arc;
+use std::num::Float;
+use std::path::Path;
+use std::slice::bytes::copy_memory;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Receiver, Sender, channel};
+use time::precise_time_ns;
 use util::geometry::{Au, ZERO_POINT};
-use util::opts;
+use util::opts::{self, PaintingWork, PixelFormat, TilingStrategy};
 use util::smallvec::SmallVec;
-use util::task::spawn_named_with_send_on_failure;
+use util::task::{self, spawn_named_with_send_on_failure, spawn_named_with_stack_size};
 use util::task_state;
-use util::task::spawn_named;
 
 /// Information about a hardware graphics layer that layout sends to the painting task.
 #[derive(Clone)]
@@ -47,17 +71,105 @@ pub struct PaintLayer {
     pub background_color: Color,
     /// The scrolling policy of this layer.
     pub scroll_policy: ScrollPolicy,
+    /// How the layer scrolls its own content, if it is a scroll layer.
+    pub scrolling: Option<LayerScrolling>,
+    /// What the layer holds.
+    pub kind: LayerKind,
+    /// True if the layer paints fully opaque pixels over all of itself, so that the compositor
+    /// need not blend it with what is behind it. The stacking context of the layer works this out
+    /// from its background color and display list.
+    pub is_opaque: bool,
+    /// Animations of the transform or opacity of the layer that the compositor runs by itself.
+    pub animations: Vec<LayerAnimation>,
 }
 
 impl PaintLayer {
-    /// Creates a new `PaintLayer`.
+    /// Creates a new `PaintLayer` for page content.
     pub fn new(id: LayerId, background_color: Color, scroll_policy: ScrollPolicy) -> PaintLayer {
+        PaintLayer::new_with_kind(id, background_color, scroll_policy, LayerKind::Content)
+    }
+
+    /// Creates a new `PaintLayer` that holds something other than page content.
+    pub fn new_with_kind(id: LayerId,
+                         background_color: Color,
+                         scroll_policy: ScrollPolicy,
+                         kind: LayerKind)
+                         -> PaintLayer {
+        PaintLayer {
+            id: id,
+            background_color: background_color,
+            scroll_policy: scroll_policy,
+            scrolling: None,
+            kind: kind,
+            is_opaque: false,
+            animations: Vec::new(),
+        }
+    }
+
+    /// Creates a new `PaintLayer` for a scroll frame, which the compositor scrolls the content of
+    /// within its viewport.
+    pub fn new_scroll_layer(id: LayerId,
+                            background_color: Color,
+                            scroll_policy: ScrollPolicy,
+                            scrolling: LayerScrolling)
+                            -> PaintLayer {
         PaintLayer {
             id: id,
             background_color: background_color,
             scroll_policy: scroll_policy,
+            scrolling: Some(scrolling),
+            kind: LayerKind::Content,
+            is_opaque: false,
+            animations: Vec::new(),
+        }
+    }
+}
+
+/// The extents of a scroll layer, relative to the top left of the layer.
+#[derive(Clone, Copy, PartialEq)]
+pub struct LayerScrolling {
+    pub scroll_id: ScrollId,
+    /// The size of all of the content that can be scrolled into view. This may be larger than the
+    /// overflow of the display list built for the layer so far, in which case the rest is painted
+    /// as a checkerboard until it is built.
+    pub content_size: Size2D<Au>,
+    /// The part of the layer that the content is seen through.
+    pub viewport: Rect<Au>,
+    /// How far the content can be scrolled up and to the left. This is zero unless the content
+    /// reaches out of the top or left of the viewport, and negative if it does.
+    pub min_scroll_offset: Point2D<Au>,
+    /// What happens when the content is scrolled past its ends.
+    pub overscroll: OverscrollBehavior,
+}
+
+impl LayerScrolling {
+    fn to_metadata(&self) -> ScrollMetadata {
+        ScrollMetadata {
+            id: self.scroll_id,
+            content_size: Size2D(self.content_size.width.to_nearest_px() as i32,
+                                 self.content_size.height.to_nearest_px() as i32),
+            viewport: Rect(Point2D(self.viewport.origin.x.to_nearest_px() as i32,
+                                   self.viewport.origin.y.to_nearest_px() as i32),
+                           Size2D(self.viewport.size.width.to_nearest_px() as i32,
+                                  self.viewport.size.height.to_nearest_px() as i32)),
+            min_scroll_offset: Point2D(self.min_scroll_offset.x.to_nearest_px() as i32,
+                                       self.min_scroll_offset.y.to_nearest_px() as i32),
+            max_scroll_offset: Point2D(self.max_scroll_offset().x.to_nearest_px() as i32,
+                                       self.max_scroll_offset().y.to_nearest_px() as i32),
+            overscroll: self.overscroll,
         }
     }
+
+    /// Returns how far the content can be scrolled down and to the right, which is how far the
+    /// rest of it reaches out of the bottom and right of the viewport.
+    pub fn max_scroll_offset(&self) -> Point2D<Au> {
+        Point2D(Au::max(self.content_size.width + self.min_scroll_offset.x -
+                        self.viewport.size.width,
+                        Au(0)),
+                Au::max(self.content_size.height + self.min_scroll_offset.y -
+                        self.viewport.size.height,
+                        Au(0)))
+    }
 }
 
 pub struct PaintRequest {
@@ -65,17 +177,80 @@ pub struct PaintRequest {
     pub scale: f32,
     pub layer_id: LayerId,
     pub epoch: Epoch,
+    /// The part of the layer in view, in page coordinates. Tiles in it are painted first.
+    pub viewport: Rect<f32>,
+    /// The direction that the viewport last moved in. Tiles furthest along it are painted first.
+    pub scroll_direction: Point2D<f32>,
+    /// True to send the tiles painted at low resolution first, for tiles that scrolling brought
+    /// into view.
+    pub low_res_prepaint: bool,
+}
+
+/// A request for the part of the page in view, painted in one piece for the window, of a pipeline
+/// that the paint task told the compositor can be painted directly.
+#[derive(Clone, PartialEq)]
+pub struct DirectPaintRequest {
+    /// The area of the page in view, in page coordinates.
+    pub page_rect: Rect<f32>,
+    /// The size of the window in device pixels.
+    pub size: Size2D<usize>,
+    pub scale: f32,
+    pub epoch: Epoch,
 }
 
 pub enum Msg {
     PaintInit(Arc<StackingContext>),
     Paint(Vec<PaintRequest>),
+    PaintDirect(DirectPaintRequest),
     UnusedBuffer(Vec<Box<LayerBuffer>>),
+    /// Paints the tiles over the given rects of layers again, because the animated images there
+    /// are due to show their next frames. Ignored unless the display list is still that of the
+    /// epoch.
+    RepaintAnimatedImages(Epoch, Vec<(LayerId, Vec<Rect<Au>>)>),
     PaintPermissionGranted,
     PaintPermissionRevoked,
+    /// Changes the threads that tiles are painted on, for example to use fewer while on battery.
+    ConfigureWorkers(WorkerPoolConfig),
+    /// Skips the given kinds of painting work from now on, and does the others, then paints the
+    /// layers again to show the difference. This applies to all paint tasks.
+    SkipPainting(Vec<PaintingWork>),
+    /// Asks for the tree of layers that the current display list is painted into, after layers
+    /// that aren't worth having are squashed. It is empty if there is no display list yet.
+    GetLayerTree(Sender<Vec<LayerTreeNode>>),
     Exit(Option<Sender<()>>, PipelineExitType),
 }
 
+/// How many threads tiles are painted on, and how they run.
+#[derive(Clone, PartialEq)]
+pub struct WorkerPoolConfig {
+    /// The number of threads. There is only ever one when painting on the GPU.
+    pub thread_count: usize,
+    /// The size of the stacks of the threads in bytes, or `None` for the default.
+    pub stack_size: Option<usize>,
+    /// The CPUs that the threads are kept to, one per thread in turn, or none to let them run on
+    /// any.
+    pub cpus: Vec<usize>,
+}
+
+impl WorkerPoolConfig {
+    /// Returns the configuration given on the command line.
+    pub fn from_opts() -> WorkerPoolConfig {
+        WorkerPoolConfig {
+            thread_count: opts::get().paint_threads,
+            stack_size: opts::get().paint_thread_stack_size,
+            cpus: opts::get().paint_thread_cpus.clone(),
+        }
+    }
+
+    fn thread_count(&self) -> usize {
+        if opts::get().gpu_painting {
+            1
+        } else {
+            cmp::max(self.thread_count, 1)
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct PaintChan(Sender<Msg>);
 
@@ -122,9 +297,52 @@ pub struct PaintTask<C> {
     /// Communication handles to each of the worker threads.
     worker_threads: Vec<WorkerThreadProxy>,
 
+    /// Receives what the worker threads have painted, along with the index of the thread that
+    /// painted it.
+    worker_receiver: Receiver<(usize, MsgFromWorkerThread)>,
+
+    /// Handed to worker threads to send what they have painted with.
+    worker_sender: Sender<(usize, MsgFromWorkerThread)>,
+
+    /// How the worker threads are set up.
+    worker_pool_config: WorkerPoolConfig,
+
+    /// Handed to worker threads started after the paint task.
+    font_cache_task: FontCacheTask,
+
+    /// What painting the items of the current display list has cost so far, which the worker
+    /// threads add to when the costs of items are attributed.
+    item_costs: Arc<Mutex<ItemCosts>>,
+
+    /// The generators that generated images are drawn with, shared with the worker threads.
+    image_generators: ImageGenerators,
+
+    /// How many display lists have been written to the directory given with
+    /// `--dump-display-list`, which numbers their files.
+    dumped_display_list_count: usize,
+
+    /// Shows statistics over the page, with `-Z show-hud`.
+    hud: Option<Hud>,
+
     /// Tracks the number of buffers that the compositor currently owns. The
     /// PaintTask waits to exit until all buffers are returned.
     used_buffer_count: usize,
+
+    /// Messages that arrived while painting, which are handled before any others.
+    pending_msgs: VecDeque<Msg>,
+
+    /// The tiles of each layer painted for the current epoch, with the scale of the latest of
+    /// them. When a new display list only changes parts of layers, the tiles over those parts
+    /// are painted again without starting a new epoch.
+    painted_tiles: Vec<(LayerId, f32, Vec<BufferRequest>)>,
+
+    /// True if the compositor may have tiles that don't match the current display list, so the
+    /// next one has to be painted in full.
+    tiles_are_stale: bool,
+
+    /// True if the compositor was told that the current epoch can be painted straight into the
+    /// window, in which case it asks for frames instead of tiles.
+    paints_directly: bool,
 }
 
 // If we implement this as a function, we get borrowck errors from borrowing
@@ -152,9 +370,20 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                 let mut compositor = compositor;
                 let native_graphics_context = compositor.get_graphics_metadata().map(
                     |md| NativePaintingGraphicsContext::from_metadata(&md));
-                let worker_threads = WorkerThreadProxy::spawn(compositor.get_graphics_metadata(),
-                                                              font_cache_task,
-                                                              time_profiler_chan.clone());
+                let worker_pool_config = WorkerPoolConfig::from_opts();
+                let item_costs = Arc::new(Mutex::new(ItemCosts::new()));
+                let image_generators = image_generator::registry();
+                let (worker_sender, worker_receiver) = channel();
+                let worker_threads = (0..worker_pool_config.thread_count()).map(|index| {
+                    WorkerThreadProxy::spawn(index,
+                                             &worker_pool_config,
+                                             worker_sender.clone(),
+                                             compositor.get_graphics_metadata(),
+                                             font_cache_task.clone(),
+                                             item_costs.clone(),
+                                             image_generators.clone(),
+                                             time_profiler_chan.clone())
+                }).collect();
 
                 // FIXME: rust/#5967
                 let mut paint_task = PaintTask {
@@ -169,8 +398,23 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                     epoch: Epoch(0),
                     buffer_map: BufferMap::new(10000000),
                     worker_threads: worker_threads,
+                    worker_receiver: worker_receiver,
+                    worker_sender: worker_sender,
+                    worker_pool_config: worker_pool_config,
+                    font_cache_task: font_cache_task,
+                    item_costs: item_costs,
+                    image_generators: image_generators,
+                    dumped_display_list_count: 0,
+                    hud: None,
                     used_buffer_count: 0,
+                    pending_msgs: VecDeque::new(),
+                    painted_tiles: Vec::new(),
+                    tiles_are_stale: true,
+                    paints_directly: false,
                 };
+                if opts::get().show_hud {
+                    paint_task.hud = Some(Hud::new(paint_task.font_cache_task.clone()))
+                }
 
                 paint_task.start();
 
@@ -197,19 +441,52 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
         let mut exit_response_channel : Option<Sender<()>> = None;
         let mut waiting_for_compositor_buffers_to_exit = false;
         loop {
-            match self.port.recv().unwrap() {
+            let msg = match self.pending_msgs.pop_front() {
+                Some(msg) => msg,
+                None => self.port.recv().unwrap(),
+            };
+            match msg {
                 Msg::PaintInit(stacking_context) => {
-                    self.root_stacking_context = Some(stacking_context.clone());
+                    paint_markers::mark(&self.time_profiler_chan,
+                                        time::ProfilerCategory::PaintingDisplayListReceipt,
+                                        format!("display list of {:?}", self.id));
+                    self.print_item_costs();
+                    let stacking_context = squashing::squash_layers(&stacking_context);
+                    self.dump_display_list(&*stacking_context);
+                    if opts::get().dump_layer_tree {
+                        println!("#### start printing layer tree.");
+                        for layer in layer_tree::layer_tree(&*stacking_context).iter() {
+                            layer.print(String::from_str("#"))
+                        }
+                    }
+                    let stacking_context = match self.hud {
+                        Some(ref mut hud) => hud.add_to(&stacking_context),
+                        None => stacking_context,
+                    };
+                    let changes = match self.root_stacking_context {
+                        Some(ref old_stacking_context) if self.repaints_changed_tiles() => {
+                            invalidation::changed_rects(&**old_stacking_context,
+                                                        &*stacking_context)
+                        }
+                        _ => None,
+                    };
+                    self.root_stacking_context = Some(stacking_context);
 
                     if !self.paint_permission {
                         debug!("PaintTask: paint ready msg");
+                        self.tiles_are_stale = true;
                         let ConstellationChan(ref mut c) = self.constellation_chan;
                         c.send(ConstellationMsg::PainterReady(self.id)).unwrap();
                         continue;
                     }
 
-                    self.epoch.next();
-                    self.initialize_layers();
+                    match changes {
+                        Some(changes) => self.repaint_changed_tiles(changes),
+                        None => {
+                            self.epoch.next();
+                            self.initialize_layers();
+                        }
+                    }
                 }
                 Msg::Paint(requests) => {
                     if !self.paint_permission {
@@ -221,11 +498,44 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                     }
 
                     let mut replies = Vec::new();
+                    let mut shared_tiles = Vec::new();
                     self.compositor.set_paint_state(self.id, PaintState::Painting);
-                    for PaintRequest { buffer_requests, scale, layer_id, epoch }
-                          in requests.into_iter() {
+
+                    // Quickly show something for the tiles scrolled into view, then paint them
+                    // properly right after.
+                    if prepaints_low_res() {
+                        for request in requests.iter() {
+                            if request.low_res_prepaint && self.epoch == request.epoch {
+                                let tiles = request.buffer_requests
+                                                   .iter()
+                                                   .map(low_res_tile)
+                                                   .collect();
+                                self.paint(&mut replies,
+                                           &mut shared_tiles,
+                                           TileQueue::new(tiles,
+                                                          &request.viewport,
+                                                          &request.scroll_direction),
+                                           request.scale * LOW_RES_PREPAINT_SCALE,
+                                           request.layer_id);
+                            }
+                        }
+                        if !replies.is_empty() {
+                            let replies = mem::replace(&mut replies, Vec::new());
+                            self.return_painted_buffers(replies);
+                        }
+                    }
+
+                    for PaintRequest { buffer_requests, scale, layer_id, epoch, viewport,
+                                       scroll_direction, .. } in requests.into_iter() {
                         if self.epoch == epoch {
-                            self.paint(&mut replies, buffer_requests, scale, layer_id);
+                            self.remember_painted_tiles(layer_id, scale, &buffer_requests);
+                            self.paint(&mut replies,
+                                       &mut shared_tiles,
+                                       TileQueue::new(buffer_requests,
+                                                      &viewport,
+                                                      &scroll_direction),
+                                       scale,
+                                       layer_id);
                         } else {
                             debug!("painter epoch mismatch: {:?} != {:?}", self.epoch, epoch);
                         }
@@ -233,13 +543,54 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
 
                     self.compositor.set_paint_state(self.id, PaintState::Idle);
 
-                    for reply in replies.iter() {
-                        let &(_, ref buffer_set) = reply;
-                        self.used_buffer_count += (*buffer_set).buffers.len();
+                    if !shared_tiles.is_empty() {
+                        debug!("PaintTask: returning shared tiles");
+                        self.compositor.assign_shared_tiles(self.id, self.epoch, shared_tiles);
+                    }
+
+                    self.return_painted_buffers(replies);
+                    if let Some(ref mut hud) = self.hud {
+                        hud.frame_painted()
+                    }
+                }
+                Msg::PaintDirect(request) => {
+                    if !self.paint_permission || self.epoch != request.epoch {
+                        debug!("PaintTask: discarding direct paint request");
+                        self.compositor.paint_msg_discarded();
+                        continue;
                     }
+                    let root_stacking_context = match self.root_stacking_context {
+                        Some(ref root_stacking_context) => root_stacking_context.clone(),
+                        None => {
+                            self.compositor.paint_msg_discarded();
+                            continue;
+                        }
+                    };
+
+                    self.compositor.set_paint_state(self.id, PaintState::Painting);
+                    let time_profiler_chan = self.time_profiler_chan.clone();
+                    let worker_thread = &mut self.worker_threads[0];
+                    let worker_receiver = &self.worker_receiver;
+                    let frame = time::profile(time::ProfilerCategory::Painting,
+                                              None,
+                                              time_profiler_chan,
+                                              || {
+                        worker_thread.paint_frame(request.page_rect,
+                                                  request.size,
+                                                  root_stacking_context,
+                                                  request.scale);
+                        match worker_receiver.recv().unwrap() {
+                            (_, MsgFromWorkerThread::PaintedFrame(frame)) => frame,
+                            _ => panic!("expected a frame painted for the window"),
+                        }
+                    });
+                    self.compositor.set_paint_state(self.id, PaintState::Idle);
 
-                    debug!("PaintTask: returning surfaces");
-                    self.compositor.assign_painted_buffers(self.id, self.epoch, replies);
+                    debug!("PaintTask: returning direct frame");
+                    self.compositor.assign_direct_frame(self.id, self.epoch, frame);
+                    if let Some(ref mut hud) = self.hud {
+                        hud.frame_painted()
+                    }
                 }
                 Msg::UnusedBuffer(unused_buffers) => {
                     debug!("PaintTask: Received {} unused buffers", unused_buffers.len());
@@ -255,6 +606,17 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                         break;
                     }
                 }
+                Msg::RepaintAnimatedImages(epoch, rects) => {
+                    if !self.paint_permission || epoch != self.epoch {
+                        continue
+                    }
+                    if self.repaints_changed_tiles() {
+                        self.repaint_changed_tiles(rects)
+                    } else {
+                        self.epoch.next();
+                        self.initialize_layers();
+                    }
+                }
                 Msg::PaintPermissionGranted => {
                     self.paint_permission = true;
 
@@ -266,7 +628,26 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                 Msg::PaintPermissionRevoked => {
                     self.paint_permission = false;
                 }
+                Msg::ConfigureWorkers(config) => self.configure_workers(config),
+                Msg::SkipPainting(work) => {
+                    skipped_painting::set_skipped(&work);
+                    if self.paint_permission && self.root_stacking_context.is_some() {
+                        self.epoch.next();
+                        self.initialize_layers();
+                    }
+                }
+                Msg::GetLayerTree(sender) => {
+                    let layers = match self.root_stacking_context {
+                        Some(ref root_stacking_context) => {
+                            layer_tree::layer_tree(&**root_stacking_context)
+                        }
+                        None => Vec::new(),
+                    };
+                    let _ = sender.send(layers);
+                }
                 Msg::Exit(response_channel, exit_type) => {
+                    self.print_item_costs();
+                    tile_checksums::save();
                     let should_wait_for_compositor_buffers = match exit_type {
                         PipelineExitType::Complete => false,
                         PipelineExitType::PipelineOnly => self.used_buffer_count != 0
@@ -289,13 +670,173 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
         }
     }
 
+    /// Returns true if a new display list has arrived since painting started. Messages that arrived
+    /// in the meantime are kept to be handled next.
+    fn new_display_list_arrived(&mut self) -> bool {
+        while let Ok(msg) = self.port.try_recv() {
+            self.pending_msgs.push_back(msg)
+        }
+        self.pending_msgs.iter().any(|msg| {
+            match *msg {
+                Msg::PaintInit(_) => true,
+                _ => false,
+            }
+        })
+    }
+
+    /// Sends the painted buffers to the compositor, which owns them until it sends them back.
+    fn return_painted_buffers(&mut self, replies: Vec<(LayerId, Box<LayerBufferSet>)>) {
+        for reply in replies.iter() {
+            let &(_, ref buffer_set) = reply;
+            self.used_buffer_count += (*buffer_set).buffers.len();
+        }
+
+        debug!("PaintTask: returning surfaces");
+        self.compositor.assign_painted_buffers(self.id, self.epoch, replies);
+    }
+
+    /// Returns true if new display lists that only change parts of layers have just the tiles
+    /// over those parts painted again. Tiles in shared memory and frames painted for the window
+    /// are always painted in full.
+    fn repaints_changed_tiles(&self) -> bool {
+        !self.tiles_are_stale && !self.paints_directly && !opts::get().shared_memory_tiles
+    }
+
+    /// Records the tiles of a layer that the compositor asked for, so that they can be painted
+    /// again if a new display list changes them. Tiles at another scale are forgotten.
+    fn remember_painted_tiles(&mut self, layer_id: LayerId, scale: f32, tiles: &[BufferRequest]) {
+        let index = match self.painted_tiles.iter().position(|&(id, _, _)| id == layer_id) {
+            Some(index) => index,
+            None => {
+                self.painted_tiles.push((layer_id, scale, Vec::new()));
+                self.painted_tiles.len() - 1
+            }
+        };
+        let (_, ref mut painted_scale, ref mut painted_tiles) = self.painted_tiles[index];
+        if *painted_scale != scale {
+            *painted_scale = scale;
+            painted_tiles.clear();
+        }
+        for tile in tiles.iter() {
+            painted_tiles.retain(|painted_tile| painted_tile.screen_rect != tile.screen_rect);
+            painted_tiles.push(copy_tile(tile));
+        }
+    }
+
+    /// Paints the tiles that the compositor has over the parts of layers that the new display
+    /// list changes again, and sends them in the current epoch, so that the compositor keeps the
+    /// rest of its tiles.
+    fn repaint_changed_tiles(&mut self, changes: Vec<(LayerId, Vec<Rect<Au>>)>) {
+        let mut replies = Vec::new();
+        let mut shared_tiles = Vec::new();
+        self.compositor.set_paint_state(self.id, PaintState::Painting);
+        for (layer_id, rects) in changes.into_iter() {
+            let rects: Vec<Rect<f32>> = rects.iter().map(|rect| {
+                Rect(Point2D(rect.origin.x.to_frac32_px(), rect.origin.y.to_frac32_px()),
+                     Size2D(rect.size.width.to_frac32_px(), rect.size.height.to_frac32_px()))
+            }).collect();
+            let (scale, tiles) = match self.painted_tiles.iter().find(|&&(id, _, _)| {
+                id == layer_id
+            }) {
+                Some(&(_, scale, ref tiles)) => {
+                    let tiles: Vec<BufferRequest> = tiles.iter().filter(|tile| {
+                        rects.iter().any(|rect| rect.intersects(&tile.page_rect))
+                    }).map(copy_tile).collect();
+                    (scale, tiles)
+                }
+                None => continue,
+            };
+            debug!("PaintTask: repainting {} changed tiles of {:?}", tiles.len(), layer_id);
+            self.paint(&mut replies,
+                       &mut shared_tiles,
+                       TileQueue::new(tiles, &Rect::zero(), &Point2D::zero()),
+                       scale,
+                       layer_id);
+        }
+        self.compositor.set_paint_state(self.id, PaintState::Idle);
+
+        let mut animations = Vec::new();
+        let mut external_textures = Vec::new();
+        if let Some(ref root_stacking_context) = self.root_stacking_context {
+            collect_layer_animations(&**root_stacking_context, &mut animations);
+            collect_external_textures(&**root_stacking_context, &mut external_textures);
+        }
+        self.compositor.set_layer_animations(self.id, animations);
+        self.compositor.set_external_textures(self.id, self.epoch, external_textures);
+
+        // A new display list may have stopped the tiles from all being painted.
+        if self.new_display_list_arrived() {
+            self.tiles_are_stale = true
+        }
+        self.return_painted_buffers(replies);
+    }
+
+    /// Prints what painting the items of the display list has cost, if anything, and starts
+    /// adding up the costs of the next one.
+    fn print_item_costs(&self) {
+        let mut item_costs = self.item_costs.lock().unwrap();
+        if !item_costs.is_empty() {
+            println!("#### paint item costs of {:?}:", self.id);
+            item_costs.print();
+            *item_costs = ItemCosts::new();
+        }
+    }
+
+    /// Writes the display list to the next numbered file in the directory given with
+    /// `--dump-display-list`, if there is one.
+    fn dump_display_list(&mut self, stacking_context: &StackingContext) {
+        let directory = match opts::get().dump_display_list_dir {
+            Some(ref directory) => directory,
+            None => return,
+        };
+        let PipelineId(pipeline_id) = self.id;
+        let file_name = format!("display-list-{}-{}.json",
+                                pipeline_id,
+                                self.dumped_display_list_count);
+        self.dumped_display_list_count += 1;
+        let path = Path::new(directory).join(file_name);
+        if let Err(error) = display_list::json::write_stacking_context(stacking_context, &path) {
+            println!("couldn't write the display list to {:?}: {}", path, error)
+        }
+    }
+
+    /// Starts and stops worker threads to match the configuration. Threads are only started over
+    /// if their stacks or CPUs change.
+    fn configure_workers(&mut self, config: WorkerPoolConfig) {
+        if config.stack_size != self.worker_pool_config.stack_size ||
+                config.cpus != self.worker_pool_config.cpus {
+            for worker_thread in self.worker_threads.iter_mut() {
+                worker_thread.exit()
+            }
+            self.worker_threads.clear();
+        }
+
+        let thread_count = config.thread_count();
+        while self.worker_threads.len() > thread_count {
+            self.worker_threads.pop().unwrap().exit()
+        }
+        while self.worker_threads.len() < thread_count {
+            let index = self.worker_threads.len();
+            let worker_thread = WorkerThreadProxy::spawn(index,
+                                                         &config,
+                                                         self.worker_sender.clone(),
+                                                         self.compositor.get_graphics_metadata(),
+                                                         self.font_cache_task.clone(),
+                                                         self.item_costs.clone(),
+                                                         self.image_generators.clone(),
+                                                         self.time_profiler_chan.clone());
+            self.worker_threads.push(worker_thread)
+        }
+        self.worker_pool_config = config;
+    }
+
     /// Retrieves an appropriately-sized layer buffer from the cache to match the requirements of
     /// the given tile, or creates one if a suitable one cannot be found.
     fn find_or_create_layer_buffer_for_tile(&mut self, tile: &BufferRequest, scale: f32)
                                             -> Option<Box<LayerBuffer>> {
         let width = tile.screen_rect.size.width;
         let height = tile.screen_rect.size.height;
-        if opts::get().gpu_painting {
+        if opts::get().gpu_painting || opts::get().shared_memory_tiles {
             return None
         }
 
@@ -331,10 +872,14 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
         })
     }
 
-    /// Paints one layer and places the painted tiles in `replies`.
+    /// Paints the tiles of one layer in the order of the queue and places them in `replies`, or in
+    /// `shared_tiles` when painting tiles into shared memory. The tiles still in the queue when a
+    /// new display list arrives are left unpainted, since the compositor asks for all of them
+    /// again for it.
     fn paint(&mut self,
               replies: &mut Vec<(LayerId, Box<LayerBufferSet>)>,
-              mut tiles: Vec<BufferRequest>,
+              shared_tiles: &mut Vec<(LayerId, Vec<SharedTile>)>,
+              mut tiles: TileQueue,
               scale: f32,
               layer_id: LayerId) {
         time::profile(time::ProfilerCategory::Painting, None, self.time_profiler_chan.clone(), || {
@@ -349,46 +894,138 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                 return
             };
 
-            // Divide up the layer into tiles and distribute them to workers via a simple round-
-            // robin strategy.
-            let tiles = mem::replace(&mut tiles, Vec::new());
-            let tile_count = tiles.len();
-            for (i, tile) in tiles.into_iter().enumerate() {
-                let thread_id = i % self.worker_threads.len();
-                let layer_buffer = self.find_or_create_layer_buffer_for_tile(&tile, scale);
-                self.worker_threads[thread_id].paint_tile(thread_id,
-                                                          tile,
-                                                          layer_buffer,
-                                                          stacking_context.clone(),
-                                                          scale);
-            }
-            let new_buffers = (0..tile_count).map(|i| {
-                let thread_id = i % self.worker_threads.len();
-                self.worker_threads[thread_id].get_painted_tile_buffer()
+            // Hand the tiles, or strips of them, to the workers one job at a time, so that the
+            // ones with the highest priority are painted first. Each worker is given its next job
+            // as soon as it has sent back all the tiles of its last one, unless a new display list
+            // has arrived, which stops the rest from being painted.
+            let mut tile_rects = Vec::new();
+            let mut new_buffers = Vec::new();
+            let mut new_tiles = Vec::new();
+            let mut unreturned_tiles: Vec<usize> = (0..self.worker_threads.len()).map(|thread_id| {
+                self.send_tile_job(thread_id, &mut tiles, &stacking_context, scale, &mut tile_rects)
             }).collect();
+            while unreturned_tiles.iter().any(|&count| count > 0) {
+                let (thread_id, msg) = self.worker_receiver.recv().unwrap();
+                match msg {
+                    MsgFromWorkerThread::PaintedTile(buffer) => new_buffers.push(buffer),
+                    MsgFromWorkerThread::PaintedSharedTile(tile) => {
+                        new_tiles.extend(tile.into_iter())
+                    }
+                    MsgFromWorkerThread::PaintedFrame(_) => panic!("expected a painted tile"),
+                }
+                unreturned_tiles[thread_id] -= 1;
+                if unreturned_tiles[thread_id] == 0 && !self.new_display_list_arrived() {
+                    unreturned_tiles[thread_id] = self.send_tile_job(thread_id,
+                                                                     &mut tiles,
+                                                                     &stacking_context,
+                                                                     scale,
+                                                                     &mut tile_rects)
+                }
+            }
+            if opts::get().shared_memory_tiles {
+                shared_tiles.push((layer_id, new_tiles));
+            } else {
+                let layer_buffer_set = box LayerBufferSet {
+                    buffers: new_buffers,
+                };
+                replies.push((layer_id, layer_buffer_set));
+            }
 
-            let layer_buffer_set = box LayerBufferSet {
-                buffers: new_buffers,
-            };
-            replies.push((layer_id, layer_buffer_set));
+            // Ask the compositor to have the tiles showing animated images repainted when the
+            // images are due to show their next frames. Tiles are positioned relative to the
+            // stacking context's overflow rect.
+            let origin = stacking_context.overflow.origin;
+            let animated_images = stacking_context.animated_images(precise_time_ns());
+            for (bounds, next_frame_time) in animated_images.into_iter() {
+                let rect = Rect(Point2D((bounds.origin.x - origin.x).to_subpx() as f32,
+                                        (bounds.origin.y - origin.y).to_subpx() as f32),
+                                Size2D(bounds.size.width.to_subpx() as f32,
+                                       bounds.size.height.to_subpx() as f32));
+                if tile_rects.iter().any(|tile_rect| tile_rect.intersects(&rect)) {
+                    self.compositor.schedule_frame_repaint(self.id,
+                                                           self.epoch,
+                                                           layer_id,
+                                                           rect,
+                                                           next_frame_time);
+                }
+            }
         })
     }
 
+    /// Hands the worker thread the tile with the highest priority in the queue, or the strip of
+    /// tiles that it is in when painting strips, and adds their page rects to `tile_rects`.
+    /// Returns how many tiles the worker was given, which is none once the queue is empty.
+    fn send_tile_job(&mut self,
+                     thread_id: usize,
+                     tiles: &mut TileQueue,
+                     stacking_context: &Arc<StackingContext>,
+                     scale: f32,
+                     tile_rects: &mut Vec<Rect<f32>>)
+                     -> usize {
+        if paints_strips() {
+            let strip = match tiles.pop_strip() {
+                Some(strip) => strip,
+                None => return 0,
+            };
+            let strip: Vec<(BufferRequest, Option<Box<LayerBuffer>>)> =
+                strip.into_iter().map(|tile| {
+                    tile_rects.push(tile.page_rect);
+                    let layer_buffer = self.find_or_create_layer_buffer_for_tile(&tile, scale);
+                    (tile, layer_buffer)
+                }).collect();
+            let count = strip.len();
+            self.worker_threads[thread_id].paint_strip(thread_id,
+                                                       strip,
+                                                       stacking_context.clone(),
+                                                       scale);
+            count
+        } else {
+            let tile = match tiles.pop() {
+                Some(tile) => tile,
+                None => return 0,
+            };
+            tile_rects.push(tile.page_rect);
+            let layer_buffer = self.find_or_create_layer_buffer_for_tile(&tile, scale);
+            self.worker_threads[thread_id].paint_tile(thread_id,
+                                                      tile,
+                                                      layer_buffer,
+                                                      stacking_context.clone(),
+                                                      scale);
+            1
+        }
+    }
+
     fn initialize_layers(&mut self) {
         let root_stacking_context = match self.root_stacking_context {
             None => return,
             Some(ref root_stacking_context) => root_stacking_context,
         };
 
+        // Let the compositor skip tiles for pages without anything for it to do by itself. This
+        // goes first, so that it knows before it asks for tiles for the new layers. Frames are
+        // painted on the CPU, so leave the other painting modes be.
+        let direct = !opts::get().disable_direct_painting && !opts::get().gpu_painting &&
+            !opts::get().shared_memory_tiles && root_stacking_context.can_paint_directly();
+        self.compositor.set_direct_painting(self.id, self.epoch, direct);
+        self.paints_directly = direct;
+        self.painted_tiles.clear();
+        self.tiles_are_stale = false;
+
         let mut metadata = Vec::new();
         build(&mut metadata, &**root_stacking_context, &ZERO_POINT);
         self.compositor.initialize_layers_for_pipeline(self.id, metadata, self.epoch);
+        let mut animations = Vec::new();
+        collect_layer_animations(&**root_stacking_context, &mut animations);
+        self.compositor.set_layer_animations(self.id, animations);
+        let mut external_textures = Vec::new();
+        collect_external_textures(&**root_stacking_context, &mut external_textures);
+        self.compositor.set_external_textures(self.id, self.epoch, external_textures);
 
         fn build(metadata: &mut Vec<LayerMetadata>,
                  stacking_context: &StackingContext,
                  page_position: &Point2D<Au>) {
             let page_position = stacking_context.bounds.origin + *page_position;
-            if let Some(ref paint_layer) = stacking_context.layer {
+            if let Some(paint_layer) = stacking_context.compositor_layer() {
                 // Layers start at the top left of their overflow rect, as far as the info we give to
                 // the compositor is concerned.
                 let overflow_relative_page_position = page_position + stacking_context.overflow.origin;
@@ -402,6 +1039,9 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
                     position: layer_position,
                     background_color: paint_layer.background_color,
                     scroll_policy: paint_layer.scroll_policy,
+                    scroll: paint_layer.scrolling.map(|scrolling| scrolling.to_metadata()),
+                    kind: paint_layer.kind,
+                    is_opaque: paint_layer.is_opaque,
                 })
             }
 
@@ -414,38 +1054,44 @@ impl<C> PaintTask<C> where C: PaintListener + Send + 'static {
 
 struct WorkerThreadProxy {
     sender: Sender<MsgToWorkerThread>,
-    receiver: Receiver<MsgFromWorkerThread>,
 }
 
 impl WorkerThreadProxy {
-    fn spawn(native_graphics_metadata: Option<NativeGraphicsMetadata>,
+    /// Starts the worker thread at the given index in the pool.
+    fn spawn(index: usize,
+             config: &WorkerPoolConfig,
+             from_worker_sender: Sender<(usize, MsgFromWorkerThread)>,
+             native_graphics_metadata: Option<NativeGraphicsMetadata>,
              font_cache_task: FontCacheTask,
+             item_costs: Arc<Mutex<ItemCosts>>,
+             image_generators: ImageGenerators,
              time_profiler_chan: time::ProfilerChan)
-             -> Vec<WorkerThreadProxy> {
-        let thread_count = if opts::get().gpu_painting {
-            1
+             -> WorkerThreadProxy {
+        let (to_worker_sender, to_worker_receiver) = channel();
+        let cpu = if config.cpus.is_empty() {
+            None
         } else {
-            opts::get().paint_threads
+            Some(config.cpus[index % config.cpus.len()])
         };
-        (0..thread_count).map(|_| {
-            let (from_worker_sender, from_worker_receiver) = channel();
-            let (to_worker_sender, to_worker_receiver) = channel();
-            let native_graphics_metadata = native_graphics_metadata.clone();
-            let font_cache_task = font_cache_task.clone();
-            let time_profiler_chan = time_profiler_chan.clone();
-            spawn_named("PaintWorker".to_owned(), move || {
-                let mut worker_thread = WorkerThread::new(from_worker_sender,
-                                                          to_worker_receiver,
-                                                          native_graphics_metadata,
-                                                          font_cache_task,
-                                                          time_profiler_chan);
-                worker_thread.main();
-            });
-            WorkerThreadProxy {
-                receiver: from_worker_receiver,
-                sender: to_worker_sender,
+        spawn_named_with_stack_size("PaintWorker".to_owned(), config.stack_size, move || {
+            if let Some(cpu) = cpu {
+                if !task::set_cpu_affinity(cpu) {
+                    debug!("PaintWorker: couldn't keep the thread to CPU {}", cpu);
+                }
             }
-        }).collect()
+            let mut worker_thread = WorkerThread::new(index,
+                                                      from_worker_sender,
+                                                      to_worker_receiver,
+                                                      native_graphics_metadata,
+                                                      font_cache_task,
+                                                      item_costs,
+                                                      image_generators,
+                                                      time_profiler_chan);
+            worker_thread.main();
+        });
+        WorkerThreadProxy {
+            sender: to_worker_sender,
+        }
     }
 
     fn paint_tile(&mut self,
@@ -457,10 +1103,24 @@ impl WorkerThreadProxy {
         self.sender.send(MsgToWorkerThread::PaintTile(thread_id, tile, layer_buffer, stacking_context, scale)).unwrap()
     }
 
-    fn get_painted_tile_buffer(&mut self) -> Box<LayerBuffer> {
-        match self.receiver.recv().unwrap() {
-            MsgFromWorkerThread::PaintedTile(layer_buffer) => layer_buffer,
-        }
+    /// Has the tiles painted together as a strip. The worker sends each of them back as if it had
+    /// been painted by itself.
+    fn paint_strip(&mut self,
+                   thread_id: usize,
+                   tiles: Vec<(BufferRequest, Option<Box<LayerBuffer>>)>,
+                   stacking_context: Arc<StackingContext>,
+                   scale: f32) {
+        self.sender.send(MsgToWorkerThread::PaintStrip(thread_id, tiles, stacking_context, scale))
+                   .unwrap()
+    }
+
+    fn paint_frame(&mut self,
+                   page_rect: Rect<f32>,
+                   size: Size2D<usize>,
+                   stacking_context: Arc<StackingContext>,
+                   scale: f32) {
+        self.sender.send(MsgToWorkerThread::PaintFrame(page_rect, size, stacking_context, scale))
+                   .unwrap()
     }
 
     fn exit(&mut self) {
@@ -469,54 +1129,132 @@ impl WorkerThreadProxy {
 }
 
 struct WorkerThread {
-    sender: Sender<MsgFromWorkerThread>,
+    /// The index of this thread, which is sent along with everything it paints.
+    index: usize,
+    sender: Sender<(usize, MsgFromWorkerThread)>,
     receiver: Receiver<MsgToWorkerThread>,
     native_graphics_context: Option<NativePaintingGraphicsContext>,
     font_context: Box<FontContext>,
+    mipmap_cache: MipmapCache,
+    /// Draws batches of simple display items when painting on the GPU with quad batching, once
+    /// the GL context has been made current.
+    quad_renderer: Option<QuadRenderer>,
+    /// The costs of items that the paint task keeps, which this thread adds to.
+    item_costs: Arc<Mutex<ItemCosts>>,
+    /// The generators of the paint task.
+    image_generators: ImageGenerators,
     time_profiler_sender: time::ProfilerChan,
 }
 
 impl WorkerThread {
-    fn new(sender: Sender<MsgFromWorkerThread>,
+    fn new(index: usize,
+           sender: Sender<(usize, MsgFromWorkerThread)>,
            receiver: Receiver<MsgToWorkerThread>,
            native_graphics_metadata: Option<NativeGraphicsMetadata>,
            font_cache_task: FontCacheTask,
+           item_costs: Arc<Mutex<ItemCosts>>,
+           image_generators: ImageGenerators,
            time_profiler_sender: time::ProfilerChan)
            -> WorkerThread {
         WorkerThread {
+            index: index,
             sender: sender,
             receiver: receiver,
             native_graphics_context: native_graphics_metadata.map(|metadata| {
                 NativePaintingGraphicsContext::from_metadata(&metadata)
             }),
             font_context: box FontContext::new(font_cache_task.clone()),
+            mipmap_cache: MipmapCache::new(MAX_MIPMAP_CACHE_SIZE),
+            quad_renderer: None,
+            item_costs: item_costs,
+            image_generators: image_generators,
             time_profiler_sender: time_profiler_sender,
         }
     }
 
+    /// Sends what this thread has painted back to the paint task.
+    fn send(&self, msg: MsgFromWorkerThread) {
+        self.sender.send((self.index, msg)).unwrap()
+    }
+
     fn main(&mut self) {
         loop {
             match self.receiver.recv().unwrap() {
                 MsgToWorkerThread::Exit => break,
                 MsgToWorkerThread::PaintTile(thread_id, tile, layer_buffer, stacking_context, scale) => {
-                    let draw_target = self.optimize_and_paint_tile(thread_id, &tile, stacking_context, scale);
+                    paint_markers::begin(format!("tile {:?}", tile.page_rect));
+                    let background_color = opaque_background_color(&*stacking_context);
+                    if opts::get().shared_memory_tiles {
+                        let shared_tile = if opts::get().pixel_format == PixelFormat::Bgra8 {
+                            let draw_target = self.optimize_and_paint_tile(thread_id,
+                                                                           &tile.page_rect,
+                                                                           &tile.screen_rect,
+                                                                           stacking_context,
+                                                                           scale,
+                                                                           background_color);
+                            self.create_shared_tile(&tile, draw_target, scale)
+                        } else {
+                            self.paint_deep_shared_tile(&tile, stacking_context, scale)
+                        };
+                        paint_markers::end(&self.time_profiler_sender);
+                        self.send(MsgFromWorkerThread::PaintedSharedTile(shared_tile));
+                        continue
+                    }
+                    let draw_target = self.optimize_and_paint_tile(thread_id,
+                                                                   &tile.page_rect,
+                                                                   &tile.screen_rect,
+                                                                   stacking_context,
+                                                                   scale,
+                                                                   background_color);
                     let buffer = self.create_layer_buffer_for_painted_tile(&tile,
                                                                            layer_buffer,
                                                                            draw_target,
                                                                            scale);
-                    self.sender.send(MsgFromWorkerThread::PaintedTile(buffer)).unwrap()
+                    paint_markers::end(&self.time_profiler_sender);
+                    self.send(MsgFromWorkerThread::PaintedTile(buffer))
+                }
+                MsgToWorkerThread::PaintStrip(thread_id, tiles, stacking_context, scale) => {
+                    paint_markers::begin(format!("strip of {} tiles from {:?}",
+                                                 tiles.len(),
+                                                 tiles[0].0.page_rect));
+                    self.paint_strip(thread_id, tiles, stacking_context, scale);
+                    paint_markers::end(&self.time_profiler_sender)
+                }
+                MsgToWorkerThread::PaintFrame(page_rect, size, stacking_context, scale) => {
+                    paint_markers::begin(format!("frame {:?}", page_rect));
+                    let screen_rect = Rect(Point2D(0, 0), size);
+                    let background_color = stacking_context.layer
+                                                           .as_ref()
+                                                           .map(|layer| layer.background_color);
+                    let draw_target = self.optimize_and_paint_tile(0,
+                                                                   &page_rect,
+                                                                   &screen_rect,
+                                                                   stacking_context,
+                                                                   scale,
+                                                                   background_color);
+                    let frame = paint_markers::record(time::ProfilerCategory::PaintingUpload, || {
+                        read_frame(&page_rect, &size, scale, draw_target)
+                    });
+                    paint_markers::end(&self.time_profiler_sender);
+                    self.send(MsgFromWorkerThread::PaintedFrame(frame))
                 }
             }
         }
     }
 
+    /// Paints the `page_rect` area of the stacking context into a new draw target the size of
+    /// `screen_rect`. If `background_color` isn't `None`, the tile is filled with it first, for
+    /// frames that have no compositor layer to show the background color under them, and for
+    /// tiles of layers with opaque backgrounds, so that the tiles are opaque too.
     fn optimize_and_paint_tile(&mut self,
                                thread_id: usize,
-                               tile: &BufferRequest,
+                               page_rect: &Rect<f32>,
+                               screen_rect: &Rect<usize>,
                                stacking_context: Arc<StackingContext>,
-                               scale: f32)
+                               scale: f32,
+                               background_color: Option<Color>)
                                -> DrawTarget {
-        let size = Size2D(tile.screen_rect.size.width as i32, tile.screen_rect.size.height as i32);
+        let size = Size2D(screen_rect.size.width as i32, screen_rect.size.height as i32);
         let draw_target = if !opts::get().gpu_painting {
             DrawTarget::new(BackendType::Skia, size, SurfaceFormat::B8G8R8A8)
         } else {
@@ -533,43 +1271,90 @@ impl WorkerThread {
             draw_target
         };
 
+        let mut quad_renderer = if opts::get().gpu_painting && opts::get().gpu_quad_batching {
+            self.quad_renderer.take().or_else(QuadRenderer::new)
+        } else {
+            None
+        };
+
+        let image_generators = self.image_generators.clone();
+        let start_time = precise_time_ns();
         {
             // Build the paint context.
             let mut paint_context = PaintContext {
                 draw_target: draw_target.clone(),
                 font_context: &mut self.font_context,
-                page_rect: tile.page_rect,
-                screen_rect: tile.screen_rect,
+                mipmap_cache: &mut self.mipmap_cache,
+                image_generators: Some(&*image_generators),
+                page_rect: *page_rect,
+                screen_rect: *screen_rect,
+                scale: scale,
                 clip_rect: None,
                 transient_clip: None,
+                saved_states: Vec::new(),
             };
 
-            // Apply a translation to start at the boundaries of the stacking context, since the
-            // layer's origin starts at its overflow rect's origin.
-            let tile_bounds = tile.page_rect.translate(
-                &Point2D(stacking_context.overflow.origin.x.to_subpx() as AzFloat,
-                         stacking_context.overflow.origin.y.to_subpx() as AzFloat));
-
-            // Apply the translation to paint the tile we want.
-            let matrix: Matrix2D<AzFloat> = Matrix2D::identity();
-            let matrix = matrix.scale(scale as AzFloat, scale as AzFloat);
-            let matrix = matrix.translate(-tile_bounds.origin.x as AzFloat,
-                                          -tile_bounds.origin.y as AzFloat);
-
-            // Clear the buffer.
-            paint_context.clear();
+            let (tile_bounds, matrix) =
+                tile_bounds_and_transform(page_rect, &*stacking_context, scale);
+
+            // Clear the buffer, unless the stacking context paints over all of the tile with
+            // opaque pixels anyway.
+            if !stacking_context_is_opaque_over(&*stacking_context, &tile_bounds) {
+                paint_context.clear();
+                if let Some(background_color) = background_color {
+                    paint_context.draw_solid_color(&Rect(Point2D(Au(0), Au(0)),
+                                                         Size2D(Au::from_px(size.width as isize),
+                                                                Au::from_px(size.height as isize))),
+                                                   background_color);
+                }
+            }
+            paint_checkerboard(&mut paint_context, &*stacking_context, page_rect, scale);
 
             // Draw the display list.
+            item_costs::begin();
             time::profile(time::ProfilerCategory::PaintingPerTile,
                           None,
                           self.time_profiler_sender.clone(),
                           || {
-                stacking_context.optimize_and_draw_into_context(&mut paint_context,
-                                                                &tile_bounds,
-                                                                &matrix,
-                                                                None);
+                match quad_renderer {
+                    None => {
+                        stacking_context.optimize_and_draw_into_context(&mut paint_context,
+                                                                        &tile_bounds,
+                                                                        &matrix,
+                                                                        None);
+                    }
+                    Some(ref mut quad_renderer) => {
+                        // Record the display list, then draw the simple items in it as quads and
+                        // paint the rest, in order.
+                        let mut recording_backend =
+                            RecordingBackend::new(Some(image_generators.clone()));
+                        stacking_context.optimize_and_draw_into_context(&mut recording_backend,
+                                                                        &tile_bounds,
+                                                                        &matrix,
+                                                                        None);
+                        let batches = quad_batch::batch_commands(recording_backend.commands(),
+                                                                 &size);
+                        for batch in batches.iter() {
+                            match *batch {
+                                Batch::Commands(ref commands) => {
+                                    recording::replay(&commands[..], &mut paint_context)
+                                }
+                                Batch::Quads(ref batch) => {
+                                    paint_context.draw_target.flush();
+                                    quad_renderer.draw(batch, &size)
+                                }
+                            }
+                        }
+                    }
+                }
                 paint_context.draw_target.flush();
                     });
+            tile_checksums::check(&draw_target,
+                                  &screen_rect.size,
+                                  &*stacking_context,
+                                  page_rect,
+                                  &tile_bounds,
+                                  scale);
 
             if opts::get().show_debug_parallel_paint {
                 // Overlay a transparent solid color to identify the thread that
@@ -582,9 +1367,137 @@ impl WorkerThread {
             }
         }
 
+        self.add_item_costs(page_rect, start_time);
+        self.quad_renderer = quad_renderer;
         draw_target
     }
 
+    /// Adds the costs of the items painted since `item_costs::begin()` to those that the paint task
+    /// keeps, and reports the tile at `page_rect` if painting it, from `start_time`, took longer
+    /// than the budget.
+    fn add_item_costs(&self, page_rect: &Rect<f32>, start_time: u64) {
+        let costs = match item_costs::end() {
+            Some(costs) => costs,
+            None => return,
+        };
+        if let Some(budget) = opts::get().paint_budget {
+            let paint_time = precise_time_ns() - start_time;
+            if paint_time as f64 > budget * 1000000.0 {
+                costs.print_slow_paint(&format!("tile {:?}", page_rect), paint_time)
+            }
+        }
+        if opts::get().paint_item_costs {
+            self.item_costs.lock().unwrap().merge(&costs)
+        }
+    }
+
+    /// Paints the tiles, which are in the same row, into one draw target as wide as all of them,
+    /// then uploads the part of it that each one shows into its buffer and sends it back.
+    fn paint_strip(&mut self,
+                   thread_id: usize,
+                   tiles: Vec<(BufferRequest, Option<Box<LayerBuffer>>)>,
+                   stacking_context: Arc<StackingContext>,
+                   scale: f32) {
+        let (mut page_rect, mut screen_rect) = (tiles[0].0.page_rect, tiles[0].0.screen_rect);
+        for &(ref tile, _) in tiles.iter() {
+            page_rect = page_rect.union(&tile.page_rect);
+            screen_rect = screen_rect.union(&tile.screen_rect);
+        }
+        let background_color = opaque_background_color(&*stacking_context);
+        let draw_target = self.optimize_and_paint_tile(thread_id,
+                                                       &page_rect,
+                                                       &screen_rect,
+                                                       stacking_context,
+                                                       scale,
+                                                       background_color);
+
+        let data_surface = draw_target.snapshot().get_data_surface();
+        let stride = data_surface.stride() as usize;
+        data_surface.with_data(|data| {
+            for (tile, layer_buffer) in tiles.into_iter() {
+                let left = (tile.screen_rect.origin.x - screen_rect.origin.x) * 4;
+                let top = tile.screen_rect.origin.y - screen_rect.origin.y;
+                let row_length = tile.screen_rect.size.width * 4;
+                let mut pixels = vec![0; row_length * tile.screen_rect.size.height];
+                for row in 0..tile.screen_rect.size.height {
+                    let start = (top + row) * stride + left;
+                    copy_memory(&mut pixels[row * row_length..(row + 1) * row_length],
+                                &data[start..start + row_length]);
+                }
+
+                let mut buffer = layer_buffer.unwrap();
+                paint_markers::record(time::ProfilerCategory::PaintingUpload, || {
+                    buffer.native_surface.upload(native_graphics_context!(self), &pixels[..])
+                });
+                self.send(MsgFromWorkerThread::PaintedTile(buffer))
+            }
+        });
+    }
+
+    /// Copies the painted tile into a new surface in shared memory. Returns `None` if there isn't
+    /// enough shared memory for it.
+    ///
+    /// FIXME: Paint into the shared memory in the first place, once Azure can make draw targets
+    /// for memory that it doesn't own.
+    fn create_shared_tile(&mut self, tile: &BufferRequest, draw_target: DrawTarget, scale: f32)
+                          -> Option<SharedTile> {
+        let size = Size2D(tile.screen_rect.size.width as i32, tile.screen_rect.size.height as i32);
+        let mut surface = match create_shared_surface(&size, PixelFormat::Bgra8) {
+            Some(surface) => surface,
+            None => return None,
+        };
+
+        let stride = surface.handle().stride as usize;
+        let row_length = size.width as usize * 4;
+        let data_surface = draw_target.snapshot().get_data_surface();
+        let source_stride = data_surface.stride() as usize;
+        data_surface.with_data(|data| {
+            let destination = surface.data_mut();
+            for row in 0..(size.height as usize) {
+                copy_memory(&mut destination[row * stride..row * stride + row_length],
+                            &data[row * source_stride..row * source_stride + row_length]);
+            }
+        });
+        Some(shared_tile(tile, &surface, scale))
+    }
+
+    /// Paints the tile into a new surface in shared memory, in the pixel format from the options,
+    /// with the software backend, which keeps more than the 8 bits per channel that Azure does.
+    /// Returns `None` if there isn't enough shared memory for it.
+    fn paint_deep_shared_tile(&mut self,
+                              tile: &BufferRequest,
+                              stacking_context: Arc<StackingContext>,
+                              scale: f32)
+                              -> Option<SharedTile> {
+        let size = Size2D(tile.screen_rect.size.width as i32, tile.screen_rect.size.height as i32);
+        let format = opts::get().pixel_format;
+        let mut surface = match create_shared_surface(&size, format) {
+            Some(surface) => surface,
+            None => return None,
+        };
+
+        let (tile_bounds, matrix) =
+            tile_bounds_and_transform(&tile.page_rect, &*stacking_context, scale);
+        let mut software_backend = SoftwareBackend::new(size.width as usize,
+                                                        size.height as usize);
+        let start_time = precise_time_ns();
+        item_costs::begin();
+        time::profile(time::ProfilerCategory::PaintingPerTile,
+                      None,
+                      self.time_profiler_sender.clone(),
+                      || {
+            stacking_context.optimize_and_draw_into_context(&mut software_backend,
+                                                            &tile_bounds,
+                                                            &matrix,
+                                                            None)
+        });
+        self.add_item_costs(&tile.page_rect, start_time);
+
+        let stride = surface.handle().stride as usize;
+        software_backend.write_pixels(format, surface.data_mut(), stride);
+        Some(shared_tile(tile, &surface, scale))
+    }
+
     fn create_layer_buffer_for_painted_tile(&mut self,
                                             tile: &BufferRequest,
                                             layer_buffer: Option<Box<LayerBuffer>>,
@@ -599,7 +1512,9 @@ impl WorkerThread {
         if !opts::get().gpu_painting {
             let mut buffer = layer_buffer.unwrap();
             draw_target.snapshot().get_data_surface().with_data(|data| {
-                buffer.native_surface.upload(native_graphics_context!(self), data);
+                paint_markers::record(time::ProfilerCategory::PaintingUpload, || {
+                    buffer.native_surface.upload(native_graphics_context!(self), data)
+                });
                 debug!("painting worker thread uploading to native surface {}",
                        buffer.native_surface.get_id());
             });
@@ -630,10 +1545,16 @@ impl WorkerThread {
 enum MsgToWorkerThread {
     Exit,
     PaintTile(usize, BufferRequest, Option<Box<LayerBuffer>>, Arc<StackingContext>, f32),
+    /// Paints the tiles, with the buffers to paint them into, together as one strip.
+    PaintStrip(usize, Vec<(BufferRequest, Option<Box<LayerBuffer>>)>, Arc<StackingContext>, f32),
+    /// Paints the given area, in page coordinates, into a frame for the window of the given size.
+    PaintFrame(Rect<f32>, Size2D<usize>, Arc<StackingContext>, f32),
 }
 
 enum MsgFromWorkerThread {
     PaintedTile(Box<LayerBuffer>),
+    PaintedSharedTile(Option<SharedTile>),
+    PaintedFrame(DirectFrame),
 }
 
 pub static THREAD_TINT_COLORS: [Color; 8] = [
@@ -647,3 +1568,241 @@ pub static THREAD_TINT_COLORS: [Color; 8] = [
     Color { r: 137.0/255.0, g: 196.0/255.0, b: 78.0/255.0, a: 0.7 },
 ];
 
+
+/// The resolution, relative to the one asked for, of tiles painted before they are painted
+/// properly.
+static LOW_RES_PREPAINT_SCALE: f32 = 0.25;
+
+/// The length of the sides of the squares of checkerboards, in px.
+static CHECKERBOARD_SQUARE_SIZE: f32 = 16.0;
+
+/// How much darker the dark squares of checkerboards in the background color of a layer are.
+static CHECKERBOARD_SHADE: f32 = 0.08;
+
+static CHECKERBOARD_COLORS: [Color; 2] = [
+    Color { r: 0.9, g: 0.9, b: 0.9, a: 1.0 },
+    Color { r: 0.75, g: 0.75, b: 0.75, a: 1.0 },
+];
+
+/// Returns the colors of the squares of checkerboards in the layer: shades of its background
+/// color if that is opaque, so that content yet to be built blends in with the page, or gray.
+fn checkerboard_colors(layer: &PaintLayer) -> [Color; 2] {
+    let background_color = layer.background_color;
+    if background_color.a < 1.0 {
+        return CHECKERBOARD_COLORS
+    }
+    let shade = |component: f32| component * (1.0 - CHECKERBOARD_SHADE);
+    [
+        background_color,
+        Color {
+            r: shade(background_color.r),
+            g: shade(background_color.g),
+            b: shade(background_color.b),
+            a: 1.0,
+        },
+    ]
+}
+
+/// Paints a checkerboard over the part of the tile that shows content of a scroll layer that the
+/// display list doesn't cover yet. The paint context must not be transformed yet.
+fn paint_checkerboard(paint_context: &mut PaintContext,
+                      stacking_context: &StackingContext,
+                      page_rect: &Rect<f32>,
+                      scale: f32) {
+    let (content_size, colors) = match stacking_context.layer {
+        Some(ref layer) => match layer.scrolling {
+            Some(ref scrolling) => (scrolling.content_size, checkerboard_colors(layer)),
+            None => return,
+        },
+        None => return,
+    };
+
+    // Tiles start at the top left of the overflow, like where the content is measured from. Take
+    // the content to the right of the overflow, and the content below it.
+    let painted_size = stacking_context.overflow.size;
+    let unpainted = [
+        Rect(Point2D(painted_size.width, Au(0)),
+             Size2D(content_size.width - painted_size.width, content_size.height)),
+        Rect(Point2D(Au(0), painted_size.height),
+             Size2D(cmp::min(content_size.width, painted_size.width),
+                    content_size.height - painted_size.height)),
+    ];
+
+    let first_column = (page_rect.origin.x / CHECKERBOARD_SQUARE_SIZE).floor() as i32;
+    let first_row = (page_rect.origin.y / CHECKERBOARD_SQUARE_SIZE).floor() as i32;
+    let last_column = (page_rect.max_x() / CHECKERBOARD_SQUARE_SIZE).ceil() as i32;
+    let last_row = (page_rect.max_y() / CHECKERBOARD_SQUARE_SIZE).ceil() as i32;
+    for row in first_row..last_row {
+        for column in first_column..last_column {
+            let square = Rect(Point2D(Au::from_frac32_px(column as f32 * CHECKERBOARD_SQUARE_SIZE),
+                                      Au::from_frac32_px(row as f32 * CHECKERBOARD_SQUARE_SIZE)),
+                              Size2D(Au::from_frac32_px(CHECKERBOARD_SQUARE_SIZE),
+                                     Au::from_frac32_px(CHECKERBOARD_SQUARE_SIZE)));
+            let color = colors[((row + column) & 1) as usize];
+            for rect in unpainted.iter() {
+                if rect.size.width <= Au(0) || rect.size.height <= Au(0) {
+                    continue
+                }
+                if let Some(part) = square.intersection(rect) {
+                    // Into the pixels of the tile.
+                    let to_device = |length: Au, origin: f32| {
+                        Au::from_frac32_px((length.to_frac32_px() - origin) * scale)
+                    };
+                    let device_part = Rect(Point2D(to_device(part.origin.x, page_rect.origin.x),
+                                                   to_device(part.origin.y, page_rect.origin.y)),
+                                           Size2D(to_device(part.size.width, 0.0),
+                                                  to_device(part.size.height, 0.0)));
+                    paint_context.draw_solid_color(&device_part, color)
+                }
+            }
+        }
+    }
+}
+
+/// Returns the background color of the layer of the stacking context, if it is fully opaque.
+fn opaque_background_color(stacking_context: &StackingContext) -> Option<Color> {
+    match stacking_context.layer {
+        Some(ref layer) if layer.background_color.a == 1.0 => Some(layer.background_color),
+        _ => None,
+    }
+}
+
+/// Returns true if tiles are painted in strips, which only CPU painting into native surfaces does.
+fn paints_strips() -> bool {
+    opts::get().tiling_strategy == TilingStrategy::Strips && !opts::get().gpu_painting &&
+        !opts::get().shared_memory_tiles
+}
+
+/// Returns true if tiles scrolled into view are painted at low resolution first, which only CPU
+/// painting into native surfaces does.
+fn prepaints_low_res() -> bool {
+    opts::get().low_res_prepaint && !opts::get().gpu_painting && !opts::get().shared_memory_tiles
+}
+
+/// Adds the animations of the layers of the stacking context and those in it to `animations`.
+fn collect_layer_animations(stacking_context: &StackingContext,
+                            animations: &mut Vec<LayerAnimation>) {
+    if let Some(paint_layer) = stacking_context.compositor_layer() {
+        animations.extend(paint_layer.animations.iter().cloned());
+    }
+    for kid in stacking_context.display_list.children.iter() {
+        collect_layer_animations(&**kid, animations)
+    }
+}
+
+/// Adds where each layer in the tree of stacking contexts shows external textures, relative to
+/// the top left of the layer, to `textures`. Layers that show none are left out.
+fn collect_external_textures(stacking_context: &StackingContext,
+                             textures: &mut Vec<(LayerId, Vec<(ExternalTextureId, Rect<f32>)>)>) {
+    if let Some(paint_layer) = stacking_context.compositor_layer() {
+        let layer_origin = -stacking_context.overflow.origin;
+        let layer_textures: Vec<(ExternalTextureId, Rect<f32>)> =
+            stacking_context.external_textures().into_iter().map(|(texture, bounds)| {
+                let bounds = bounds.translate(&layer_origin);
+                (texture,
+                 Rect(Point2D(bounds.origin.x.to_frac32_px(), bounds.origin.y.to_frac32_px()),
+                      Size2D(bounds.size.width.to_frac32_px(),
+                             bounds.size.height.to_frac32_px())))
+            }).collect();
+        if !layer_textures.is_empty() {
+            textures.push((paint_layer.id, layer_textures))
+        }
+    }
+    for kid in stacking_context.display_list.children.iter() {
+        collect_external_textures(&**kid, textures)
+    }
+}
+
+/// Returns a request for the same area of the tile, at the same resolution.
+fn copy_tile(tile: &BufferRequest) -> BufferRequest {
+    BufferRequest {
+        screen_rect: tile.screen_rect,
+        page_rect: tile.page_rect,
+        content_age: tile.content_age,
+    }
+}
+
+/// Returns a request for the same area as the tile, at `LOW_RES_PREPAINT_SCALE` times its
+/// resolution. The buffer keeps the tile's position on the screen so that the compositor puts it
+/// in the tile's place, where it is stretched over the tile's page rect until it is replaced.
+fn low_res_tile(tile: &BufferRequest) -> BufferRequest {
+    let size = tile.screen_rect.size;
+    let size = Size2D((size.width as f32 * LOW_RES_PREPAINT_SCALE).ceil() as usize,
+                      (size.height as f32 * LOW_RES_PREPAINT_SCALE).ceil() as usize);
+    BufferRequest {
+        screen_rect: Rect(tile.screen_rect.origin, size),
+        page_rect: tile.page_rect,
+        content_age: tile.content_age,
+    }
+}
+
+/// Reads the pixels of a frame painted for the window out of its draw target.
+fn read_frame(page_rect: &Rect<f32>, size: &Size2D<usize>, scale: f32, draw_target: DrawTarget)
+              -> DirectFrame {
+    let row_length = size.width * 4;
+    let mut pixels = vec![0; row_length * size.height];
+    let data_surface = draw_target.snapshot().get_data_surface();
+    let stride = data_surface.stride() as usize;
+    data_surface.with_data(|data| {
+        for row in 0..size.height {
+            copy_memory(&mut pixels[row * row_length..(row + 1) * row_length],
+                        &data[row * stride..row * stride + row_length]);
+        }
+    });
+    DirectFrame {
+        page_rect: *page_rect,
+        size: Size2D(size.width as i32, size.height as i32),
+        resolution: scale,
+        pixels: pixels,
+    }
+}
+
+/// Returns the part of the stacking context that the tile shows, in its coordinates, and the
+/// transform to paint the stacking context into the tile with.
+fn tile_bounds_and_transform(page_rect: &Rect<f32>,
+                             stacking_context: &StackingContext,
+                             scale: f32)
+                             -> (Rect<AzFloat>, Matrix4<AzFloat>) {
+    // Apply a translation to start at the boundaries of the stacking context, since the layer's
+    // origin starts at its overflow rect's origin.
+    let tile_bounds = page_rect.translate(
+        &Point2D(stacking_context.overflow.origin.x.to_subpx() as AzFloat,
+                 stacking_context.overflow.origin.y.to_subpx() as AzFloat));
+
+    // Apply the translation to paint the tile we want.
+    let matrix: Matrix2D<AzFloat> = Matrix2D::identity();
+    let matrix = matrix.scale(scale as AzFloat, scale as AzFloat);
+    let matrix = matrix.translate(-tile_bounds.origin.x as AzFloat,
+                                  -tile_bounds.origin.y as AzFloat);
+    (tile_bounds, transform3d::from_2d(&matrix))
+}
+
+fn create_shared_surface(size: &Size2D<i32>, format: PixelFormat) -> Option<SharedSurface> {
+    let surface = SharedSurface::new(size, format);
+    if surface.is_none() {
+        debug!("painting worker thread couldn't create a shared surface of {:?}", size);
+    }
+    surface
+}
+
+fn shared_tile(tile: &BufferRequest, surface: &SharedSurface, scale: f32) -> SharedTile {
+    SharedTile {
+        page_rect: tile.page_rect,
+        screen_rect: tile.screen_rect,
+        resolution: scale,
+        surface: surface.handle().clone(),
+    }
+}
+
+/// Returns true if the stacking context is sure to cover all of the given tile, in its coordinate
+/// system, with opaque pixels.
+fn stacking_context_is_opaque_over(stacking_context: &StackingContext,
+                                   tile_bounds: &Rect<AzFloat>)
+                                   -> bool {
+    // Round the tile out to whole pixels, since pixels that it only partly covers are painted too.
+    let (left, top) = (tile_bounds.origin.x.floor() as f64, tile_bounds.origin.y.floor() as f64);
+    let (right, bottom) = (tile_bounds.max_x().ceil() as f64, tile_bounds.max_y().ceil() as f64);
+    let tile_bounds = Rect(Point2D(Au::from_frac_px(left), Au::from_frac_px(top)),
+                           Size2D(Au::from_frac_px(right - left), Au::from_frac_px(bottom - top)));
+    stacking_context.paints_opaque_over(&tile_bounds)
+}
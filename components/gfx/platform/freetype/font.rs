@@ -5,7 +5,7 @@
 extern crate freetype;
 
 use font::{FontHandleMethods, FontMetrics, FontTableMethods};
-use font::{FontTableTag, FractionalPixel};
+use font::{FontTableTag, FontVariations, FractionalPixel};
 use util::geometry::Au;
 use util::geometry;
 use util::str::c_str_to_string;
@@ -23,10 +23,11 @@ use freetype::freetype::{FTErrorMethods, FT_F26Dot6, FT_Face, FT_FaceRec};
 use freetype::freetype::{FT_GlyphSlot, FT_Library, FT_Long, FT_ULong};
 use freetype::freetype::{FT_KERNING_DEFAULT, FT_STYLE_FLAG_ITALIC, FT_STYLE_FLAG_BOLD};
 use freetype::freetype::{FT_SizeRec, FT_UInt, FT_Size_Metrics, struct_FT_Vector_};
-use freetype::freetype::{ft_sfnt_os2};
+use freetype::freetype::{ft_sfnt_os2, FT_Error};
 use freetype::tt_os2::TT_OS2;
 
-use libc::c_char;
+use libc::{self, c_char, c_long};
+use std::iter::repeat;
 use std::mem;
 use std::num::Float;
 use std::ptr;
@@ -40,11 +41,54 @@ fn fixed_to_float_ft(f: i32) -> f64 {
     fixed_to_float(6, f)
 }
 
-pub struct FontTable;
+// The multiple master API isn't covered by the FreeType bindings yet.
+#[allow(non_camel_case_types)]
+type FT_Fixed = c_long;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_Var_Axis {
+    name: *mut c_char,
+    minimum: FT_Fixed,
+    def: FT_Fixed,
+    maximum: FT_Fixed,
+    tag: FT_ULong,
+    strid: FT_UInt,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_Var_Named_Style {
+    coords: *mut FT_Fixed,
+    strid: FT_UInt,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_MM_Var {
+    num_axis: FT_UInt,
+    num_designs: FT_UInt,
+    num_namedstyles: FT_UInt,
+    axis: *mut FT_Var_Axis,
+    namedstyle: *mut FT_Var_Named_Style,
+}
+
+extern {
+    fn FT_Get_MM_Var(face: FT_Face, amaster: *mut *mut FT_MM_Var) -> FT_Error;
+    fn FT_Set_Var_Design_Coordinates(face: FT_Face, num_coords: FT_UInt, coords: *const FT_Fixed)
+                                     -> FT_Error;
+    fn FT_Load_Sfnt_Table(face: FT_Face, tag: FT_ULong, offset: FT_Long, buffer: *mut u8,
+                          length: *mut FT_ULong)
+                          -> FT_Error;
+}
+
+pub struct FontTable {
+    buffer: Vec<u8>,
+}
 
 impl FontTableMethods for FontTable {
-    fn with_buffer<F>(&self, _blk: F) where F: FnOnce(*const u8, usize) {
-        panic!()
+    fn with_buffer<F>(&self, blk: F) where F: FnOnce(*const u8, usize) {
+        blk(self.buffer.as_ptr(), self.buffer.len())
     }
 }
 
@@ -165,6 +209,64 @@ impl FontHandleMethods for FontHandle {
         font_stretch::T::normal
     }
 
+    fn set_variations(&mut self, variations: &FontVariations) -> Vec<FontTableTag> {
+        assert!(!self.face.is_null());
+        unsafe {
+            let mut mm_var: *mut FT_MM_Var = ptr::null_mut();
+            if !FT_Get_MM_Var(self.face, &mut mm_var).succeeded() || mm_var.is_null() {
+                // Not a variable font.
+                return vec!()
+            }
+
+            // Start from the defaults, or from the named instance if one was requested.
+            let num_axis = (*mm_var).num_axis as usize;
+            let mut coords: Vec<FT_Fixed> = (0..num_axis).map(|i| {
+                (*(*mm_var).axis.offset(i as isize)).def
+            }).collect();
+            let mut varied_axes = vec!();
+            if let Some(named_instance) = variations.named_instance {
+                if (named_instance as FT_UInt) < (*mm_var).num_namedstyles {
+                    let style = &*(*mm_var).namedstyle.offset(named_instance as isize);
+                    for (i, coord) in coords.iter_mut().enumerate() {
+                        *coord = *style.coords.offset(i as isize);
+                        varied_axes.push((*(*mm_var).axis.offset(i as isize)).tag as FontTableTag)
+                    }
+                }
+            }
+
+            // Apply explicit coordinates, clamped to the range of each axis.
+            for variation in variations.axes.iter() {
+                for (i, coord) in coords.iter_mut().enumerate() {
+                    let axis = &*(*mm_var).axis.offset(i as isize);
+                    if axis.tag as u32 == variation.tag {
+                        let value = variation.value as FT_Fixed;
+                        *coord = if value < axis.minimum {
+                            axis.minimum
+                        } else if value > axis.maximum {
+                            axis.maximum
+                        } else {
+                            value
+                        };
+                        if !varied_axes.contains(&variation.tag) {
+                            varied_axes.push(variation.tag)
+                        }
+                    }
+                }
+            }
+
+            if !FT_Set_Var_Design_Coordinates(self.face,
+                                              num_axis as FT_UInt,
+                                              coords.as_ptr()).succeeded() {
+                debug!("Unable to set variation coordinates for {}", self.face_name());
+                varied_axes.clear()
+            }
+
+            // The multiple master record was allocated through our allocator, which is `malloc`.
+            libc::free(mm_var as *mut libc::c_void);
+            varied_axes
+        }
+    }
+
     fn glyph_index(&self, codepoint: char) -> Option<GlyphId> {
         assert!(!self.face.is_null());
         unsafe {
@@ -191,7 +293,7 @@ impl FontHandleMethods for FontHandle {
     fn glyph_h_advance(&self, glyph: GlyphId) -> Option<FractionalPixel> {
         assert!(!self.face.is_null());
         unsafe {
-            let res =  FT_Load_Glyph(self.face, glyph as FT_UInt, 0);
+            let res =  FT_Load_Glyph(self.face, glyph as FT_UInt, self.handle.load_flags);
             if res.succeeded() {
                 let void_glyph = (*self.face).glyph;
                 let slot: FT_GlyphSlot = mem::transmute(void_glyph);
@@ -207,12 +309,33 @@ impl FontHandleMethods for FontHandle {
         }
     }
 
+    fn glyph_descent(&self, glyph: GlyphId) -> Option<FractionalPixel> {
+        assert!(!self.face.is_null());
+        unsafe {
+            let res =  FT_Load_Glyph(self.face, glyph as FT_UInt, self.handle.load_flags);
+            if !res.succeeded() {
+                debug!("Unable to load glyph {}. reason: {}", glyph, res);
+                return None;
+            }
+            let void_glyph = (*self.face).glyph;
+            let slot: FT_GlyphSlot = mem::transmute(void_glyph);
+            assert!(!slot.is_null());
+            // The bearing is the distance from the baseline up to the top of the ink.
+            let metrics = &(*slot).metrics;
+            let descent = (metrics.height - metrics.horiBearingY) as i32;
+            Some(fixed_to_float_ft(descent) as FractionalPixel)
+        }
+    }
+
     fn get_metrics(&self) -> FontMetrics {
         /* TODO(Issue #76): complete me */
         let face = self.get_face_rec();
 
+        // FreeType reads these from the `post` table, but moves the position to the center of the
+        // stroke; move it back to the top like the `post` table and the other platforms.
         let underline_size = self.font_units_to_au(face.underline_thickness as f64);
-        let underline_offset = self.font_units_to_au(face.underline_position as f64);
+        let underline_offset = self.font_units_to_au(face.underline_position as f64 +
+                                                     face.underline_thickness as f64 / 2.0);
         let em_size = self.font_units_to_au(face.units_per_EM as f64);
         let ascent = self.font_units_to_au(face.ascender as f64);
         let descent = self.font_units_to_au(face.descender as f64);
@@ -258,14 +381,29 @@ impl FontHandleMethods for FontHandle {
             max_advance:      max_advance,
             average_advance:  average_advance,
             line_gap:         height,
-        };
+        }.with_decoration_fallbacks();
 
         debug!("Font metrics (@{} pt): {:?}", geometry::to_pt(em_size), metrics);
         return metrics;
     }
 
-    fn get_table_for_tag(&self, _: FontTableTag) -> Option<FontTable> {
-        None
+    fn get_table_for_tag(&self, tag: FontTableTag) -> Option<FontTable> {
+        let tag = tag as FT_ULong;
+        unsafe {
+            // Get the length of the table first, then fill a buffer of that size.
+            let mut length = 0;
+            if !FT_Load_Sfnt_Table(self.face, tag, 0, ptr::null_mut(), &mut length).succeeded() {
+                return None
+            }
+            let mut buffer = repeat(0u8).take(length as usize).collect::<Vec<u8>>();
+            if !FT_Load_Sfnt_Table(self.face, tag, 0, buffer.as_mut_ptr(), &mut length)
+                    .succeeded() {
+                return None
+            }
+            Some(FontTable {
+                buffer: buffer,
+            })
+        }
     }
 }
 
@@ -12,6 +12,7 @@ use freetype::freetype::struct_FT_MemoryRec_;
 
 use std::ptr;
 use std::rc::Rc;
+use util::opts::{self, FontAutohint, FontHinting};
 
 use libc;
 use libc::{c_void, c_long, size_t, malloc};
@@ -45,6 +46,30 @@ pub struct FreeTypeLibraryHandle {
 #[derive(Clone)]
 pub struct FontContextHandle {
     pub ctx: Rc<FreeTypeLibraryHandle>,
+    /// The flags passed to `FT_Load_Glyph`, derived from the hinting options.
+    pub load_flags: i32,
+}
+
+// These aren't exported by the FreeType bindings.
+const FT_LOAD_DEFAULT: i32 = 0;
+const FT_LOAD_NO_HINTING: i32 = 1 << 1;
+const FT_LOAD_FORCE_AUTOHINT: i32 = 1 << 5;
+const FT_LOAD_NO_AUTOHINT: i32 = 1 << 15;
+const FT_LOAD_TARGET_LIGHT: i32 = 1 << 16;
+
+/// Computes the glyph load flags corresponding to the given hinting options.
+fn load_flags_for_hinting(hinting: FontHinting, autohint: FontAutohint) -> i32 {
+    let mut flags = match hinting {
+        FontHinting::None => return FT_LOAD_NO_HINTING,
+        FontHinting::Slight => FT_LOAD_TARGET_LIGHT,
+        FontHinting::Full => FT_LOAD_DEFAULT,
+    };
+    match autohint {
+        FontAutohint::Default => {}
+        FontAutohint::Force => flags |= FT_LOAD_FORCE_AUTOHINT,
+        FontAutohint::Disable => flags |= FT_LOAD_NO_AUTOHINT,
+    }
+    flags
 }
 
 impl Drop for FreeTypeLibraryHandle {
@@ -56,6 +81,13 @@ impl Drop for FreeTypeLibraryHandle {
 
 impl FontContextHandle {
     pub fn new() -> FontContextHandle {
+        let opts = opts::get();
+        FontContextHandle::new_with_hinting(opts.font_hinting, opts.font_autohint)
+    }
+
+    /// Creates a library whose fonts load glyphs with the given hinting rather than with the
+    /// hinting options, for font instances that say how they are hinted.
+    pub fn new_with_hinting(hinting: FontHinting, autohint: FontAutohint) -> FontContextHandle {
         unsafe {
 
             let ptr = libc::malloc(mem::size_of::<struct_FT_MemoryRec_>() as size_t);
@@ -76,6 +108,7 @@ impl FontContextHandle {
 
             FontContextHandle {
                 ctx: Rc::new(FreeTypeLibraryHandle { ctx: ctx }),
+                load_flags: load_flags_for_hinting(hinting, autohint),
             }
         }
     }
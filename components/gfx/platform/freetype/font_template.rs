@@ -35,4 +35,9 @@ impl FontTemplateData {
             identifier: identifier.to_owned(),
         }
     }
+
+    /// Returns the contents of the font file, for embedding the font in exported documents.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        Some(&self.bytes[..])
+    }
 }
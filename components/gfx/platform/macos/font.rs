@@ -9,7 +9,7 @@ extern crate core_graphics;
 extern crate core_text;
 
 use font::{FontHandleMethods, FontMetrics, FontTableMethods};
-use font::FontTableTag;
+use font::{FontTableTag, FontVariations};
 use font::FractionalPixel;
 use util::geometry::{Au, px_to_pt};
 use util::geometry;
@@ -18,19 +18,41 @@ use text::glyph::GlyphId;
 use style::computed_values::{font_stretch, font_weight};
 use platform::font_template::FontTemplateData;
 
-use core_foundation::base::CFIndex;
+use core_foundation::base::{CFIndex, CFRelease, CFTypeRef, TCFType};
 use core_foundation::data::CFData;
-use core_foundation::string::UniChar;
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::string::{CFString, CFStringRef, UniChar};
+use core_graphics::base::CGFloat;
 use core_graphics::font::CGGlyph;
 use core_graphics::geometry::CGRect;
-use core_text::font::CTFont;
-use core_text::font_descriptor::{SymbolicTraitAccessors, TraitAccessors};
-use core_text::font_descriptor::{kCTFontDefaultOrientation};
+use core_text::font::{CTFont, CTFontRef};
+use core_text::font_descriptor::{CTFontDescriptorRef, SymbolicTraitAccessors, TraitAccessors};
+use core_text::font_descriptor::{CTFontOrientation, kCTFontDefaultOrientation};
 
+use libc::c_void;
 use std::num::Float;
 use std::ptr;
 use std::sync::Arc;
 
+#[link(name = "ApplicationServices", kind = "framework")]
+extern {
+    fn CTFontGetBoundingRectsForGlyphs(font: CTFontRef,
+                                       orientation: CTFontOrientation,
+                                       glyphs: *const CGGlyph,
+                                       bounding_rects: *mut CGRect,
+                                       count: CFIndex)
+                                       -> CGRect;
+    fn CTFontCreateCopyWithAttributes(font: CTFontRef,
+                                      size: CGFloat,
+                                      matrix: *const c_void,
+                                      attributes: CTFontDescriptorRef)
+                                      -> CTFontRef;
+    fn CTFontDescriptorCreateWithAttributes(attributes: CFDictionaryRef) -> CTFontDescriptorRef;
+
+    static kCTFontVariationAttribute: CFStringRef;
+}
+
 pub struct FontTable {
     data: CFData,
 }
@@ -44,8 +66,106 @@ impl FontTable {
     pub fn wrap(data: CFData) -> FontTable {
         FontTable { data: data }
     }
+
+    /// Reads the big-endian 16-bit field at `offset`, if the table is long enough.
+    fn read_i16(&self, offset: usize) -> Option<i16> {
+        let bytes = self.data.bytes();
+        if offset + 2 > bytes.len() {
+            return None
+        }
+        Some(((bytes[offset] as u16) << 8 | bytes[offset + 1] as u16) as i16)
+    }
+
+    /// Reads the big-endian 32-bit field at `offset`, if the table is long enough.
+    fn read_i32(&self, offset: usize) -> Option<i32> {
+        match (self.read_i16(offset), self.read_i16(offset + 2)) {
+            (Some(high), Some(low)) => Some((high as i32) << 16 | low as u16 as i32),
+            _ => None,
+        }
+    }
+
+    /// Reads the unsigned big-endian 16-bit field at `offset`, if the table is long enough.
+    fn read_u16(&self, offset: usize) -> Option<usize> {
+        self.read_i16(offset).map(|value| value as u16 as usize)
+    }
+
+    /// Reads the axis records of an `fvar` table.
+    fn variation_axes(&self) -> Option<Vec<VariationAxis>> {
+        let (axes_offset, axis_count, axis_size) = match (self.read_u16(FVAR_AXES_ARRAY_OFFSET),
+                                                          self.read_u16(FVAR_AXIS_COUNT_OFFSET),
+                                                          self.read_u16(FVAR_AXIS_SIZE_OFFSET)) {
+            (Some(axes_offset), Some(axis_count), Some(axis_size)) => {
+                (axes_offset, axis_count, axis_size)
+            }
+            _ => return None,
+        };
+        (0..axis_count).map(|i| {
+            let offset = axes_offset + i * axis_size;
+            match (self.read_i32(offset),
+                   self.read_i32(offset + FVAR_AXIS_MINIMUM_OFFSET),
+                   self.read_i32(offset + FVAR_AXIS_DEFAULT_OFFSET),
+                   self.read_i32(offset + FVAR_AXIS_MAXIMUM_OFFSET)) {
+                (Some(tag), Some(minimum), Some(default), Some(maximum)) => {
+                    Some(VariationAxis {
+                        tag: tag as FontTableTag,
+                        minimum: minimum,
+                        default: default,
+                        maximum: maximum,
+                    })
+                }
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// Reads the coordinates of a named instance from an `fvar` table with `axis_count` axes.
+    fn instance_coordinates(&self, instance: u16, axis_count: usize) -> Option<Vec<i32>> {
+        match (self.read_u16(FVAR_AXES_ARRAY_OFFSET),
+               self.read_u16(FVAR_AXIS_SIZE_OFFSET),
+               self.read_u16(FVAR_INSTANCE_COUNT_OFFSET),
+               self.read_u16(FVAR_INSTANCE_SIZE_OFFSET)) {
+            (Some(axes_offset), Some(axis_size), Some(instance_count), Some(instance_size))
+                    if (instance as usize) < instance_count => {
+                let offset = axes_offset + axis_count * axis_size +
+                    instance as usize * instance_size + FVAR_INSTANCE_COORDINATES_OFFSET;
+                (0..axis_count).map(|i| self.read_i32(offset + i * 4)).collect()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A variation axis of a font, with coordinates in 16.16 fixed point.
+struct VariationAxis {
+    tag: FontTableTag,
+    minimum: i32,
+    default: i32,
+    maximum: i32,
 }
 
+static HEAD_TABLE_TAG: FontTableTag = 0x68656164;
+static OS2_TABLE_TAG: FontTableTag = 0x4f532f32;
+static POST_TABLE_TAG: FontTableTag = 0x706f7374;
+static FVAR_TABLE_TAG: FontTableTag = 0x66766172;
+
+// Byte offsets of the fields used for text decorations.
+static HEAD_UNITS_PER_EM_OFFSET: usize = 18;
+static OS2_STRIKEOUT_SIZE_OFFSET: usize = 26;
+static OS2_STRIKEOUT_POSITION_OFFSET: usize = 28;
+static POST_UNDERLINE_POSITION_OFFSET: usize = 8;
+static POST_UNDERLINE_THICKNESS_OFFSET: usize = 10;
+
+// Byte offsets of the fields of the `fvar` header, then of those of its axis and instance records.
+static FVAR_AXES_ARRAY_OFFSET: usize = 4;
+static FVAR_AXIS_COUNT_OFFSET: usize = 8;
+static FVAR_AXIS_SIZE_OFFSET: usize = 10;
+static FVAR_INSTANCE_COUNT_OFFSET: usize = 12;
+static FVAR_INSTANCE_SIZE_OFFSET: usize = 14;
+static FVAR_AXIS_MINIMUM_OFFSET: usize = 4;
+static FVAR_AXIS_DEFAULT_OFFSET: usize = 8;
+static FVAR_AXIS_MAXIMUM_OFFSET: usize = 12;
+static FVAR_INSTANCE_COORDINATES_OFFSET: usize = 4;
+
 impl FontTableMethods for FontTable {
     fn with_buffer<F>(&self, blk: F) where F: FnOnce(*const u8, usize) {
         blk(self.data.bytes().as_ptr(), self.data.len() as usize);
@@ -126,6 +246,72 @@ impl FontHandleMethods for FontHandle {
         }
     }
 
+    fn set_variations(&mut self, variations: &FontVariations) -> Vec<FontTableTag> {
+        let fvar = match self.get_table_for_tag(FVAR_TABLE_TAG) {
+            Some(fvar) => fvar,
+            None => return vec!(),  // Not a variable font.
+        };
+        let axes = match fvar.variation_axes() {
+            Some(axes) => axes,
+            None => {
+                debug!("Invalid fvar table in {}", self.face_name());
+                return vec!()
+            }
+        };
+
+        // Start from the defaults, or from the named instance if one was requested.
+        let mut coords: Vec<i32> = axes.iter().map(|axis| axis.default).collect();
+        let mut varied_axes = vec!();
+        if let Some(named_instance) = variations.named_instance {
+            if let Some(instance_coords) = fvar.instance_coordinates(named_instance, axes.len()) {
+                coords = instance_coords;
+                varied_axes.extend(axes.iter().map(|axis| axis.tag))
+            }
+        }
+
+        // Apply explicit coordinates, clamped to the range of each axis.
+        for variation in variations.axes.iter() {
+            for (axis, coord) in axes.iter().zip(coords.iter_mut()) {
+                if axis.tag == variation.tag {
+                    *coord = if variation.value < axis.minimum {
+                        axis.minimum
+                    } else if variation.value > axis.maximum {
+                        axis.maximum
+                    } else {
+                        variation.value
+                    };
+                    if !varied_axes.contains(&variation.tag) {
+                        varied_axes.push(variation.tag)
+                    }
+                }
+            }
+        }
+
+        // Core Text takes the coordinates as numbers keyed by axis tag.
+        let pairs: Vec<(CFNumber, CFNumber)> = axes.iter().zip(coords.iter()).map(|(axis, coord)| {
+            (CFNumber::from_i32(axis.tag as i32), CFNumber::from_f64(*coord as f64 / 65536.0))
+        }).collect();
+        let coordinates = CFDictionary::from_CFType_pairs(&pairs);
+        unsafe {
+            let attribute = CFString::wrap_under_get_rule(kCTFontVariationAttribute);
+            let attributes = CFDictionary::from_CFType_pairs(&[(attribute.as_CFType(),
+                                                                coordinates.as_CFType())]);
+            let descriptor = CTFontDescriptorCreateWithAttributes(
+                attributes.as_concrete_TypeRef());
+            let ctfont = CTFontCreateCopyWithAttributes(self.ctfont.as_concrete_TypeRef(),
+                                                        self.ctfont.pt_size() as CGFloat,
+                                                        ptr::null(),
+                                                        descriptor);
+            CFRelease(descriptor as CFTypeRef);
+            if ctfont.is_null() {
+                debug!("Unable to set variation coordinates for {}", self.face_name());
+                return vec!()
+            }
+            self.ctfont = TCFType::wrap_under_create_rule(ctfont);
+        }
+        varied_axes
+    }
+
     fn glyph_index(&self, codepoint: char) -> Option<GlyphId> {
         let characters: [UniChar; 1] = [codepoint as UniChar];
         let mut glyphs: [CGGlyph; 1] = [0 as CGGlyph];
@@ -159,6 +345,19 @@ impl FontHandleMethods for FontHandle {
         Some(advance as FractionalPixel)
     }
 
+    fn glyph_descent(&self, glyph: GlyphId) -> Option<FractionalPixel> {
+        let glyphs = [glyph as CGGlyph];
+        // Core Graphics has the y axis pointing up, so the bottom of the ink is the origin.
+        let bounds = unsafe {
+            CTFontGetBoundingRectsForGlyphs(self.ctfont.as_concrete_TypeRef(),
+                                            kCTFontDefaultOrientation,
+                                            &glyphs[0],
+                                            ptr::null_mut(),
+                                            1)
+        };
+        Some(-bounds.origin.y as FractionalPixel)
+    }
+
     fn get_metrics(&self) -> FontMetrics {
         let bounding_rect: CGRect = self.ctfont.bounding_box();
         let ascent = self.ctfont.ascent() as f64;
@@ -175,16 +374,32 @@ impl FontHandleMethods for FontHandle {
                                   .map(|advance| Au::from_frac_px(advance))
                                   .unwrap_or(max_advance_width);
 
+        // Core Text's underline metrics are not reliable, so read them from the font tables.
+        //
+        // see also: https://bugs.webkit.org/show_bug.cgi?id=16768
+        // see also: https://bugreports.qt-project.org/browse/QTBUG-13364
+        let font_units_per_em = self.get_table_for_tag(HEAD_TABLE_TAG)
+                                    .and_then(|head| head.read_i16(HEAD_UNITS_PER_EM_OFFSET))
+                                    .map(|units| units as u16 as f64)
+                                    .unwrap_or(0.0);
+        let font_units_to_au = |table: FontTableTag, offset: usize| {
+            if font_units_per_em == 0.0 {
+                return geometry::from_pt(0.0)
+            }
+            self.get_table_for_tag(table)
+                .and_then(|table| table.read_i16(offset))
+                .map(|value| {
+                    Au::from_frac_px(value as f64 * self.ctfont.pt_size() as f64 /
+                                     font_units_per_em)
+                })
+                .unwrap_or(geometry::from_pt(0.0))
+        };
+
         let metrics =  FontMetrics {
-            underline_size:   Au::from_pt(self.ctfont.underline_thickness() as f64),
-            // TODO(Issue #201): underline metrics are not reliable. Have to pull out of font table
-            // directly.
-            //
-            // see also: https://bugs.webkit.org/show_bug.cgi?id=16768
-            // see also: https://bugreports.qt-project.org/browse/QTBUG-13364
-            underline_offset: Au::from_pt(self.ctfont.underline_position() as f64),
-            strikeout_size:   geometry::from_pt(0.0), // FIXME(Issue #942)
-            strikeout_offset: geometry::from_pt(0.0), // FIXME(Issue #942)
+            underline_size:   font_units_to_au(POST_TABLE_TAG, POST_UNDERLINE_THICKNESS_OFFSET),
+            underline_offset: font_units_to_au(POST_TABLE_TAG, POST_UNDERLINE_POSITION_OFFSET),
+            strikeout_size:   font_units_to_au(OS2_TABLE_TAG, OS2_STRIKEOUT_SIZE_OFFSET),
+            strikeout_offset: font_units_to_au(OS2_TABLE_TAG, OS2_STRIKEOUT_POSITION_OFFSET),
             leading:          Au::from_pt(leading),
             x_height:         Au::from_pt(self.ctfont.x_height() as f64),
             em_size:          em_size,
@@ -193,7 +408,7 @@ impl FontHandleMethods for FontHandle {
             max_advance:      max_advance_width,
             average_advance:  average_advance,
             line_gap:         Au::from_frac_px(line_gap),
-        };
+        }.with_decoration_fallbacks();
         debug!("Font metrics (@{} pt): {:?}", self.ctfont.pt_size() as f64, metrics);
         return metrics;
     }
@@ -2,6 +2,10 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use util::opts::{FontAutohint, FontHinting};
+
+/// Core Text does not expose hinting controls, so `opts::get().font_hinting` and
+/// `opts::get().font_autohint` have no effect on this platform.
 #[derive(Clone)]
 pub struct FontContextHandle {
     ctx: ()
@@ -12,4 +16,9 @@ impl FontContextHandle {
     pub fn new() -> FontContextHandle {
         FontContextHandle { ctx: () }
     }
+
+    /// Core Text fonts are never hinted, so this is the same as `new`.
+    pub fn new_with_hinting(_: FontHinting, _: FontAutohint) -> FontContextHandle {
+        FontContextHandle::new()
+    }
 }
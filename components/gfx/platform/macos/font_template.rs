@@ -44,4 +44,11 @@ impl FontTemplateData {
             font_data: font_data
         }
     }
+
+    /// Returns the contents of the font file, for embedding the font in exported documents.
+    ///
+    /// TODO: Get the data of system fonts, which are only loaded by name, from Core Text.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.font_data.as_ref().map(|font_data| &font_data[..])
+    }
 }
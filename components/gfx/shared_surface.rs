@@ -0,0 +1,210 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Surfaces in POSIX shared memory, which tiles can be painted into for an embedder or a compositor
+//! in another process to read.
+//!
+//! The painter creates a surface, paints into it and sends its `SharedSurfaceHandle` on. Whoever
+//! receives the handle owns the shared memory object from then on: it opens the surface with
+//! `SharedSurface::open()`, and the object goes away once that surface is dropped. A receiver
+//! that doesn't want the pixels must still call `SharedSurface::discard()`, or the object stays
+//! in shared memory until the system restarts.
+//!
+//! Android has no POSIX shared memory, so creating a surface always fails there, as it does
+//! elsewhere when shared memory runs out.
+
+use geom::size::Size2D;
+use msg::compositor_msg::SharedSurfaceHandle;
+use std::slice;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use util::opts::PixelFormat;
+
+/// Counts the surfaces that this process has created, to give each one a name of its own.
+static SURFACE_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A mapping of a surface of pixels in shared memory.
+pub struct SharedSurface {
+    handle: SharedSurfaceHandle,
+    data: *mut u8,
+    length: usize,
+    /// Whether the shared memory object goes away when the surface is dropped.
+    owned: bool,
+}
+
+// The mapping belongs to this surface alone: `data` is only reached through `&self` or
+// `&mut self`, and is unmapped when the surface is dropped, so the surface can move between
+// threads like any other owned buffer. The process at the other end of the shared memory never
+// writes to a surface that it has handed over.
+unsafe impl Send for SharedSurface {}
+
+impl SharedSurface {
+    /// Creates a surface of the given size and format in shared memory, or returns `None` if
+    /// there isn't enough of it.
+    pub fn new(size: &Size2D<i32>, format: PixelFormat) -> Option<SharedSurface> {
+        let name = format!("/servo-surface-{}-{}",
+                           platform::process_id(),
+                           SURFACE_COUNT.fetch_add(1, Ordering::SeqCst));
+        let handle = SharedSurfaceHandle {
+            name: name,
+            size: *size,
+            format: format,
+            stride: size.width * format.bytes_per_pixel() as i32,
+        };
+        let length = (handle.stride * size.height) as usize;
+        platform::create(&handle.name, length).map(|data| {
+            SharedSurface {
+                handle: handle,
+                data: data,
+                length: length,
+                owned: false,
+            }
+        })
+    }
+
+    /// Opens the surface that the handle describes, which goes away once the returned surface is
+    /// dropped. The shared memory object goes away even if it can't be opened.
+    pub fn open(handle: &SharedSurfaceHandle) -> Option<SharedSurface> {
+        let length = (handle.stride * handle.size.height) as usize;
+        let data = platform::open(&handle.name, length);
+        if data.is_none() {
+            platform::unlink(&handle.name)
+        }
+        data.map(|data| {
+            SharedSurface {
+                handle: handle.clone(),
+                data: data,
+                length: length,
+                owned: true,
+            }
+        })
+    }
+
+    /// Removes the shared memory object that the handle describes without reading it, for
+    /// receivers of surfaces that have no use for them.
+    pub fn discard(handle: &SharedSurfaceHandle) {
+        platform::unlink(&handle.name)
+    }
+
+    pub fn handle(&self) -> &SharedSurfaceHandle {
+        &self.handle
+    }
+
+    /// Returns the pixels, in rows `stride` bytes apart.
+    pub fn data(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self.data, self.length)
+        }
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.data, self.length)
+        }
+    }
+}
+
+impl Drop for SharedSurface {
+    fn drop(&mut self) {
+        platform::unmap(self.data, self.length);
+        if self.owned {
+            platform::unlink(&self.handle.name)
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+mod platform {
+    use libc::consts::os::posix88::{MAP_FAILED, MAP_SHARED, O_CREAT, O_EXCL, O_RDWR, PROT_READ};
+    use libc::consts::os::posix88::{PROT_WRITE, S_IRUSR, S_IWUSR};
+    use libc::funcs::posix88::mman::{mmap, munmap, shm_open, shm_unlink};
+    use libc::funcs::posix88::unistd::{close, ftruncate, getpid};
+    use libc::{c_int, c_void, off_t, size_t};
+    use std::ffi::CString;
+    use std::ptr;
+
+    pub fn process_id() -> i32 {
+        unsafe { getpid() as i32 }
+    }
+
+    /// Creates a shared memory object of the given length and maps it.
+    pub fn create(name: &str, length: usize) -> Option<*mut u8> {
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            let fd = shm_open(c_name.as_ptr(), O_CREAT | O_EXCL | O_RDWR, S_IRUSR | S_IWUSR);
+            if fd < 0 {
+                return None
+            }
+            if ftruncate(fd, length as off_t) != 0 {
+                close(fd);
+                shm_unlink(c_name.as_ptr());
+                return None
+            }
+            let data = map(fd, length);
+            if data.is_none() {
+                shm_unlink(c_name.as_ptr());
+            }
+            data
+        }
+    }
+
+    /// Maps an existing shared memory object.
+    pub fn open(name: &str, length: usize) -> Option<*mut u8> {
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            let fd = shm_open(c_name.as_ptr(), O_RDWR, 0);
+            if fd < 0 {
+                return None
+            }
+            map(fd, length)
+        }
+    }
+
+    /// Maps the shared memory object that the file descriptor is open on, then closes it.
+    unsafe fn map(fd: c_int, length: usize) -> Option<*mut u8> {
+        let data = mmap(ptr::null_mut(),
+                        length as size_t,
+                        PROT_READ | PROT_WRITE,
+                        MAP_SHARED,
+                        fd,
+                        0);
+        close(fd);
+        if data == MAP_FAILED {
+            return None
+        }
+        Some(data as *mut u8)
+    }
+
+    pub fn unmap(data: *mut u8, length: usize) {
+        unsafe {
+            munmap(data as *mut c_void, length as size_t);
+        }
+    }
+
+    pub fn unlink(name: &str) {
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            shm_unlink(c_name.as_ptr());
+        }
+    }
+}
+
+#[cfg(target_os = "android")]
+mod platform {
+    pub fn process_id() -> i32 {
+        0
+    }
+
+    pub fn create(_: &str, _: usize) -> Option<*mut u8> {
+        debug!("no shared memory surfaces on Android");
+        None
+    }
+
+    pub fn open(_: &str, _: usize) -> Option<*mut u8> {
+        None
+    }
+
+    pub fn unmap(_: *mut u8, _: usize) {}
+
+    pub fn unlink(_: &str) {}
+}
@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Switches that stop kinds of painting work from being done, so that what makes a page slow to
+//! paint can be found by taking kinds of work away, one at a time, without rebuilding.
+//!
+//! The switches start as set with `--skip-painting`. A paint task can be told to change them while
+//! it runs with `Msg::SkipPainting`; they are shared by all of painting, so the change shows in
+//! other pipelines once they paint again.
+
+use backends::geometry::ItemKind;
+use display_list::DisplayItem;
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::sync::{ONCE_INIT, Once};
+use util::opts::{self, PaintingWork};
+
+/// The kinds of work that are skipped, a bit for each.
+static SKIPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Takes the switches from the command line the first time that they are needed.
+static INIT: Once = ONCE_INIT;
+
+/// Skips the given kinds of painting work, and does all of the others.
+pub fn set_skipped(work: &[PaintingWork]) {
+    INIT.call_once(|| {});
+    store(work)
+}
+
+/// Returns true if the kind of painting work is skipped.
+pub fn is_skipped(work: PaintingWork) -> bool {
+    INIT.call_once(|| store(&opts::get().skipped_painting_work));
+    SKIPPED.load(Ordering::SeqCst) & bit(work) != 0
+}
+
+/// Returns true if painting the display item is skipped.
+pub fn is_item_skipped(item: &DisplayItem) -> bool {
+    let work = match ItemKind::of(item) {
+        ItemKind::SolidColor => PaintingWork::SolidColors,
+        ItemKind::Text => PaintingWork::Text,
+        ItemKind::Image => PaintingWork::Images,
+        ItemKind::Border => PaintingWork::Borders,
+        ItemKind::Gradient => PaintingWork::Gradients,
+        ItemKind::Line => PaintingWork::Lines,
+        ItemKind::BoxShadow => PaintingWork::BoxShadows,
+        // The compositor draws these.
+        ItemKind::ExternalTexture | ItemKind::Iframe => return false,
+    };
+    is_skipped(work)
+}
+
+fn store(work: &[PaintingWork]) {
+    SKIPPED.store(work.iter().fold(0, |bits, work| bits | bit(*work)), Ordering::SeqCst)
+}
+
+fn bit(work: PaintingWork) -> usize {
+    1 << (work as usize)
+}
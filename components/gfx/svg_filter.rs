@@ -0,0 +1,406 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The SVG `<filter>` elements that `filter: url()` refers to, applied to the surface of a
+//! stacking context like the CSS filter functions are. The `feGaussianBlur`, `feColorMatrix` and
+//! `feComposite` primitives are supported, except for the `arithmetic` operator.
+//!
+//! There is no SVG DOM, so layout loads the documents that the stacking contexts refer to before
+//! they are painted (`load_referenced()`), and the filters are read straight from their markup and
+//! kept by URL for all of painting. Filters that aren't loaded, or that use anything else, are
+//! left out of the filter chain.
+
+use display_list::{BLUR_INFLATION_FACTOR, StackingContext};
+use color_matrix;
+
+use azure::AzFloat;
+use azure::azure_hl::{ColorMatrixAttribute, ColorMatrixInput, CompositeInput, CompositionOp};
+use azure::azure_hl::{DrawOptions, DrawTarget, FilterNode, FilterType, GaussianBlurAttribute};
+use azure::azure_hl::{GaussianBlurInput, Matrix5x4};
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use net_traits::{ResourceTask, load_whole_resource};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, ONCE_INIT, Once};
+use style::computed_values::filter;
+use url::Url;
+use util::geometry::Au;
+
+/// What a filter primitive is applied to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum FilterInput {
+    /// What the stacking context painted, after the filters before this one.
+    SourceGraphic,
+    /// The alpha of `SourceGraphic`, in black.
+    SourceAlpha,
+    /// The result of the primitive before, or `SourceGraphic` for the first one.
+    Previous,
+    /// The result of an earlier primitive with the given `result` attribute.
+    Named(String),
+}
+
+/// The Porter-Duff operators of `feComposite`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompositeOperator {
+    Over,
+    In,
+    Out,
+    Atop,
+    Xor,
+}
+
+pub enum Primitive {
+    /// A blur with the given standard deviation, in px.
+    GaussianBlur(AzFloat),
+    ColorMatrix(Matrix5x4),
+    /// The input composited over the second input with the operator.
+    Composite(FilterInput, CompositeOperator),
+}
+
+pub struct FilterPrimitive {
+    pub primitive: Primitive,
+    pub input: FilterInput,
+    /// The name that later primitives can use this one's result by.
+    pub result: Option<String>,
+}
+
+/// A `<filter>` element.
+pub struct SvgFilter {
+    pub primitives: Vec<FilterPrimitive>,
+}
+
+/// The filters that have been loaded, by URL, or `None` for those that couldn't be.
+type SvgFilters = Mutex<HashMap<String, Option<Arc<SvgFilter>>>>;
+
+static mut SVG_FILTERS: *const SvgFilters = 0 as *const SvgFilters;
+static INIT: Once = ONCE_INIT;
+
+fn svg_filters() -> &'static SvgFilters {
+    INIT.call_once(|| {
+        let svg_filters: Box<SvgFilters> = box Mutex::new(HashMap::new());
+        unsafe {
+            SVG_FILTERS = mem::transmute(svg_filters);
+        }
+    });
+    unsafe {
+        debug_assert!(SVG_FILTERS != ptr::null());
+        mem::transmute(SVG_FILTERS)
+    }
+}
+
+/// Returns the filter at the URL, if it has been loaded.
+pub fn get(url: &Url) -> Option<Arc<SvgFilter>> {
+    match svg_filters().lock().unwrap().get(&url.serialize()) {
+        Some(&Some(ref svg_filter)) => Some(svg_filter.clone()),
+        _ => None,
+    }
+}
+
+/// Loads the filters that the stacking context and those in it refer to and that haven't been
+/// loaded yet, waiting for the documents that they are in.
+pub fn load_referenced(stacking_context: &StackingContext, resource_task: &ResourceTask) {
+    for style_filter in stacking_context.filters.filters.iter() {
+        if let filter::Filter::Url(ref url) = *style_filter {
+            let key = url.serialize();
+            if svg_filters().lock().unwrap().contains_key(&key) {
+                continue
+            }
+            let svg_filter = match load(url, resource_task) {
+                Ok(svg_filter) => Some(Arc::new(svg_filter)),
+                Err(error) => {
+                    println!("couldn't load the SVG filter {}: {}", key, error);
+                    None
+                }
+            };
+            svg_filters().lock().unwrap().insert(key, svg_filter);
+        }
+    }
+    for kid in stacking_context.display_list.children.iter() {
+        load_referenced(&**kid, resource_task)
+    }
+}
+
+fn load(url: &Url, resource_task: &ResourceTask) -> Result<SvgFilter, String> {
+    let id = match url.fragment {
+        Some(ref id) => id.clone(),
+        None => return Err("the URL doesn't name an element".to_owned()),
+    };
+    let mut document_url = url.clone();
+    document_url.fragment = None;
+    let (_, bytes) = try!(load_whole_resource(resource_task, document_url));
+    parse(&String::from_utf8_lossy(&bytes), &id)
+}
+
+/// Reads the `<filter>` element with the id out of the SVG markup.
+pub fn parse(source: &str, id: &str) -> Result<SvgFilter, String> {
+    let tags = tags(source);
+    let start = match tags.iter().position(|tag| {
+        tag.name == "filter" && !tag.closing && tag.attribute("id") == Some(id)
+    }) {
+        Some(start) => start,
+        None => return Err(format!("there is no filter with the id {}", id)),
+    };
+
+    let mut primitives = Vec::new();
+    for tag in tags[start + 1..].iter() {
+        if tag.name == "filter" {
+            break
+        }
+        if tag.closing {
+            continue
+        }
+        let primitive = match &*tag.name {
+            "feGaussianBlur" => {
+                let std_deviation = tag.attribute("stdDeviation").unwrap_or("0");
+                Primitive::GaussianBlur(try!(numbers(std_deviation).into_iter()
+                                                                   .next()
+                                                                   .ok_or("bad stdDeviation")))
+            }
+            "feColorMatrix" => Primitive::ColorMatrix(try!(color_matrix(tag))),
+            "feComposite" => {
+                let operator = match tag.attribute("operator").unwrap_or("over") {
+                    "over" => CompositeOperator::Over,
+                    "in" => CompositeOperator::In,
+                    "out" => CompositeOperator::Out,
+                    "atop" => CompositeOperator::Atop,
+                    "xor" => CompositeOperator::Xor,
+                    operator => return Err(format!("feComposite operator {} is unsupported",
+                                                   operator)),
+                };
+                Primitive::Composite(input(tag.attribute("in2")), operator)
+            }
+            name => return Err(format!("{} is unsupported", name)),
+        };
+        primitives.push(FilterPrimitive {
+            primitive: primitive,
+            input: input(tag.attribute("in")),
+            result: tag.attribute("result").map(|result| result.to_owned()),
+        })
+    }
+    Ok(SvgFilter {
+        primitives: primitives,
+    })
+}
+
+fn input(name: Option<&str>) -> FilterInput {
+    match name {
+        None => FilterInput::Previous,
+        Some("SourceGraphic") => FilterInput::SourceGraphic,
+        Some("SourceAlpha") => FilterInput::SourceAlpha,
+        Some(name) => FilterInput::Named(name.to_owned()),
+    }
+}
+
+fn color_matrix(tag: &Tag) -> Result<Matrix5x4, String> {
+    let values = numbers(tag.attribute("values").unwrap_or(""));
+    match tag.attribute("type").unwrap_or("matrix") {
+        "matrix" if values.len() == 20 => {
+            let m = &values;
+            Ok(Matrix5x4 {
+                m11: m[0], m21: m[1], m31: m[2], m41: m[3], m51: m[4],
+                m12: m[5], m22: m[6], m32: m[7], m42: m[8], m52: m[9],
+                m13: m[10], m23: m[11], m33: m[12], m43: m[13], m53: m[14],
+                m14: m[15], m24: m[16], m34: m[17], m44: m[18], m54: m[19],
+            })
+        }
+        "matrix" if values.is_empty() => Ok(color_matrix::saturate(1.0)),
+        "saturate" => Ok(color_matrix::saturate(*values.get(0).unwrap_or(&1.0))),
+        "hueRotate" => Ok(color_matrix::hue_rotate(*values.get(0).unwrap_or(&0.0) * PI / 180.0)),
+        "luminanceToAlpha" => {
+            Ok(Matrix5x4 {
+                m11: 0.0, m21: 0.0, m31: 0.0, m41: 0.0, m51: 0.0,
+                m12: 0.0, m22: 0.0, m32: 0.0, m42: 0.0, m52: 0.0,
+                m13: 0.0, m23: 0.0, m33: 0.0, m43: 0.0, m53: 0.0,
+                m14: 0.2125, m24: 0.7154, m34: 0.0721, m44: 0.0, m54: 0.0,
+            })
+        }
+        kind => Err(format!("bad feColorMatrix of type {}", kind)),
+    }
+}
+
+/// Returns the numbers in a list separated by whitespace or commas, skipping those that aren't.
+fn numbers(list: &str) -> Vec<AzFloat> {
+    list.split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|number| number.parse().ok())
+        .collect()
+}
+
+/// A start or end tag of SVG markup.
+struct Tag {
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|&&(ref key, _)| *key == name).map(|&(_, ref value)| &**value)
+    }
+}
+
+/// Returns the tags of the markup, in order. This is only good enough to find filters in SVG
+/// documents: comments, processing instructions and declarations are skipped, and entities
+/// aren't expanded.
+fn tags(source: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let mut tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with("!") || tag.starts_with("?") {
+            continue
+        }
+        let closing = tag.starts_with("/");
+        if closing {
+            tag = &tag[1..]
+        }
+        let tag = tag.trim_right_matches('/');
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        tags.push(Tag {
+            name: tag[..name_end].to_owned(),
+            closing: closing,
+            attributes: attributes(&tag[name_end..]),
+        })
+    }
+    tags
+}
+
+fn attributes(mut source: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        source = source.trim_left();
+        let equals = match source.find('=') {
+            Some(equals) => equals,
+            None => return attributes,
+        };
+        let name = source[..equals].trim();
+        source = source[equals + 1..].trim_left();
+        let quote = match source.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => return attributes,
+        };
+        let value_end = match source[1..].find(quote) {
+            Some(value_end) => value_end + 1,
+            None => return attributes,
+        };
+        attributes.push((name.to_owned(), source[1..value_end].to_owned()));
+        source = &source[value_end + 1..];
+    }
+}
+
+impl SvgFilter {
+    /// Returns how far the filter may paint outside of what it is applied to, on each side.
+    pub fn inflation(&self) -> Au {
+        self.primitives.iter().fold(Au(0), |inflation, primitive| {
+            match primitive.primitive {
+                Primitive::GaussianBlur(std_deviation) => {
+                    inflation + Au::from_frac32_px(std_deviation) * BLUR_INFLATION_FACTOR
+                }
+                _ => inflation,
+            }
+        })
+    }
+
+    /// Adds the primitives of the filter to the end of an Azure filter pipeline, whose last node
+    /// is `source`, and returns the new last node. Composites are done in draw targets the size
+    /// of the temporary draw target.
+    pub fn create_filter(&self,
+                         draw_target: &DrawTarget,
+                         temporary_draw_target: &DrawTarget,
+                         source: FilterNode)
+                         -> FilterNode {
+        let source_alpha = draw_target.create_filter(FilterType::ColorMatrix);
+        source_alpha.set_attribute(ColorMatrixAttribute::Matrix(Matrix5x4 {
+            m11: 0.0, m21: 0.0, m31: 0.0, m41: 0.0, m51: 0.0,
+            m12: 0.0, m22: 0.0, m32: 0.0, m42: 0.0, m52: 0.0,
+            m13: 0.0, m23: 0.0, m33: 0.0, m43: 0.0, m53: 0.0,
+            m14: 0.0, m24: 0.0, m34: 0.0, m44: 1.0, m54: 0.0,
+        }));
+        source_alpha.set_input(ColorMatrixInput, &source);
+
+        let mut results: Vec<FilterNode> = Vec::new();
+        let mut names = HashMap::new();
+        for primitive in self.primitives.iter() {
+            let node = {
+                let input = resolve(&primitive.input, &source, &source_alpha, &results, &names);
+                match primitive.primitive {
+                    Primitive::GaussianBlur(std_deviation) => {
+                        let blur = draw_target.create_filter(FilterType::GaussianBlur);
+                        blur.set_attribute(GaussianBlurAttribute::StdDeviation(std_deviation));
+                        blur.set_input(GaussianBlurInput, input);
+                        blur
+                    }
+                    Primitive::ColorMatrix(ref matrix) => {
+                        let color_matrix = draw_target.create_filter(FilterType::ColorMatrix);
+                        color_matrix.set_attribute(ColorMatrixAttribute::Matrix(*matrix));
+                        color_matrix.set_input(ColorMatrixInput, input);
+                        color_matrix
+                    }
+                    Primitive::Composite(ref input2, operator) => {
+                        let size = temporary_draw_target.get_size();
+                        let size = Size2D(size.width, size.height);
+                        let composite_draw_target =
+                            temporary_draw_target.create_similar_draw_target(
+                                &size,
+                                draw_target.get_format());
+                        let rect = Rect(Point2D(0.0, 0.0),
+                                        Size2D(size.width as AzFloat, size.height as AzFloat));
+                        let input2 = resolve(input2, &source, &source_alpha, &results, &names);
+                        composite_draw_target.draw_filter(input2,
+                                                          &rect,
+                                                          &rect.origin,
+                                                          DrawOptions::new(1.0, 0));
+                        let mut draw_options = DrawOptions::new(1.0, 0);
+                        draw_options.set_composition_op(match operator {
+                            CompositeOperator::Over => CompositionOp::Over,
+                            CompositeOperator::In => CompositionOp::In,
+                            CompositeOperator::Out => CompositionOp::Out,
+                            CompositeOperator::Atop => CompositionOp::Atop,
+                            CompositeOperator::Xor => CompositionOp::Xor,
+                        });
+                        composite_draw_target.draw_filter(input, &rect, &rect.origin, draw_options);
+                        let composite = draw_target.create_filter(FilterType::Composite);
+                        composite.set_input(CompositeInput, &composite_draw_target.snapshot());
+                        composite
+                    }
+                }
+            };
+            if let Some(ref result) = primitive.result {
+                names.insert(result.clone(), results.len());
+            }
+            results.push(node)
+        }
+        results.pop().unwrap_or(source)
+    }
+}
+
+/// Returns the node of the pipeline that is the input. Unknown names are taken to be the result
+/// of the primitive before, as in SVG 1.1.
+fn resolve<'a>(input: &FilterInput,
+               source: &'a FilterNode,
+               source_alpha: &'a FilterNode,
+               results: &'a [FilterNode],
+               names: &HashMap<String, usize>)
+               -> &'a FilterNode {
+    match *input {
+        FilterInput::SourceGraphic => source,
+        FilterInput::SourceAlpha => source_alpha,
+        FilterInput::Named(ref name) => {
+            match names.get(name) {
+                Some(&index) => &results[index],
+                None => results.last().unwrap_or(source),
+            }
+        }
+        FilterInput::Previous => results.last().unwrap_or(source),
+    }
+}
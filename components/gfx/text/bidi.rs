@@ -0,0 +1,144 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Resolution of bidi embedding levels, after the Unicode bidirectional algorithm (UAX #9).
+//!
+//! Only the implicit rules are implemented: explicit embeddings, overrides and isolates are not,
+//! and the weak rules for separators and terminators between numbers are simplified, so such
+//! characters are resolved as neutrals.
+
+use text::glyph::CharIndex;
+use util::range::Range;
+
+/// The bidi classes that the implicit rules distinguish.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BidiClass {
+    /// Strong left-to-right.
+    L,
+    /// Strong right-to-left.
+    R,
+    /// Strong right-to-left Arabic letters, which make the numbers after them Arabic numbers.
+    AL,
+    /// European numbers.
+    EN,
+    /// Arabic numbers.
+    AN,
+    /// Nonspacing marks, which take the class of the character before them.
+    NSM,
+    /// Whitespace, punctuation and everything else without a direction of its own.
+    ON,
+}
+
+impl BidiClass {
+    pub fn for_char(ch: char) -> BidiClass {
+        match ch as u32 {
+            0x0030...0x0039 | 0x00b2...0x00b3 | 0x00b9 | 0x06f0...0x06f9 | 0x2070 |
+            0x2074...0x2079 | 0x2080...0x2089 | 0xff10...0xff19 => BidiClass::EN,
+            0x0600...0x0605 | 0x0660...0x0669 | 0x066b...0x066c | 0x06dd => BidiClass::AN,
+            0x0300...0x036f | 0x0591...0x05bd | 0x05bf | 0x05c1...0x05c2 | 0x05c4...0x05c5 |
+            0x05c7 | 0x0610...0x061a | 0x064b...0x065f | 0x0670 | 0x06d6...0x06dc |
+            0x06df...0x06e4 | 0x06e7...0x06e8 | 0x06ea...0x06ed | 0x1ab0...0x1aff |
+            0x1dc0...0x1dff | 0x20d0...0x20ff | 0xfe00...0xfe0f | 0xfe20...0xfe2f => {
+                BidiClass::NSM
+            }
+            0x0590...0x05ff | 0x07c0...0x085f | 0x200f | 0xfb1d...0xfb4f |
+            0x10800...0x10fff | 0x1e800...0x1edff => BidiClass::R,
+            0x0600...0x07bf | 0x0860...0x08ff | 0xfb50...0xfdcf | 0xfdf0...0xfdff |
+            0xfe70...0xfefe | 0x1ee00...0x1eeff => BidiClass::AL,
+            0x200e => BidiClass::L,
+            _ if ch.is_alphabetic() => BidiClass::L,
+            _ => BidiClass::ON,
+        }
+    }
+}
+
+/// Returns the embedding level of each character of `text`, a paragraph whose base level is
+/// `paragraph_level`. Odd levels are right-to-left.
+pub fn resolve_levels(text: &str, paragraph_level: u8) -> Vec<u8> {
+    let embedding_direction = if paragraph_level % 2 == 0 { BidiClass::L } else { BidiClass::R };
+
+    // Weak types (W1–W3 and W7). Marks take the class of the character before them, numbers
+    // after Arabic letters are Arabic numbers, and numbers after left-to-right text are
+    // left-to-right.
+    let mut classes = Vec::with_capacity(text.len());
+    let mut previous = embedding_direction;
+    let mut last_strong = embedding_direction;
+    for ch in text.chars() {
+        let class = match BidiClass::for_char(ch) {
+            BidiClass::NSM => previous,
+            class => class,
+        };
+        previous = class;
+        match class {
+            BidiClass::L | BidiClass::R | BidiClass::AL => last_strong = class,
+            _ => {}
+        }
+        classes.push(match class {
+            BidiClass::EN if last_strong == BidiClass::AL => BidiClass::AN,
+            BidiClass::EN if last_strong == BidiClass::L => BidiClass::L,
+            BidiClass::AL => BidiClass::R,
+            class => class,
+        })
+    }
+
+    // Neutral types (N1 and N2). A sequence of neutrals takes the direction of the text on both
+    // sides of it if they agree, and the embedding direction otherwise. Numbers count as
+    // right-to-left text.
+    let strong_direction = |class: BidiClass| {
+        if class == BidiClass::L { BidiClass::L } else { BidiClass::R }
+    };
+    let mut i = 0;
+    while i < classes.len() {
+        if classes[i] != BidiClass::ON {
+            i += 1;
+            continue
+        }
+        let start = i;
+        while i < classes.len() && classes[i] == BidiClass::ON {
+            i += 1
+        }
+        let before = if start == 0 {
+            embedding_direction
+        } else {
+            strong_direction(classes[start - 1])
+        };
+        let after = if i == classes.len() {
+            embedding_direction
+        } else {
+            strong_direction(classes[i])
+        };
+        let direction = if before == after { before } else { embedding_direction };
+        for class in classes[start..i].iter_mut() {
+            *class = direction
+        }
+    }
+
+    // Implicit levels (I1 and I2).
+    classes.iter().map(|&class| {
+        match (paragraph_level % 2 == 0, class) {
+            (true, BidiClass::L) | (false, BidiClass::R) => paragraph_level,
+            (true, BidiClass::R) | (false, _) => paragraph_level + 1,
+            (true, _) => paragraph_level + 2,
+        }
+    }).collect()
+}
+
+/// Splits `range` wherever the embedding level changes, returning each part with its level.
+/// `levels` holds the level of each character that `range` indexes.
+pub fn split_at_level_changes(range: &Range<CharIndex>, levels: &[u8])
+                              -> Vec<(Range<CharIndex>, u8)> {
+    let mut parts: Vec<(Range<CharIndex>, u8)> = vec!();
+    for index in range.each_index() {
+        let level = levels[index.to_usize()];
+        match parts.last_mut() {
+            Some(&mut (ref mut part, part_level)) if part_level == level => {
+                part.extend_by(CharIndex(1));
+                continue
+            }
+            _ => {}
+        }
+        parts.push((Range::new(index, CharIndex(1)), level))
+    }
+    parts
+}
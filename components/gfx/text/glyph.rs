@@ -361,6 +361,7 @@ impl<'a> DetailedGlyphStore {
 
         let i = self.detail_lookup.binary_search_index(&key)
             .expect("Invalid index not found in detailed glyph lookup table!");
+        let i = self.detail_lookup[i].detail_offset;
 
         assert!(i + (count as usize) <= self.detail_buffer.len());
         // return a slice into the buffer
@@ -381,6 +382,7 @@ impl<'a> DetailedGlyphStore {
 
         let i = self.detail_lookup.binary_search_index(&key)
             .expect("Invalid index not found in detailed glyph lookup table!");
+        let i = self.detail_lookup[i].detail_offset;
 
         assert!(i + (detail_offset as usize) < self.detail_buffer.len());
         &self.detail_buffer[i + (detail_offset as usize)]
@@ -708,6 +710,19 @@ impl<'a> GlyphStore {
         self.entry_buffer[i.to_usize()] = entry.set_can_break_before(t);
     }
 
+    /// Replaces the advance of the glyph for the given character, returning false if the glyph
+    /// could not be updated in place. Only simple glyphs are supported.
+    pub fn set_advance_for_char_index(&mut self, i: CharIndex, advance: Au) -> bool {
+        assert!(i < self.char_len());
+        let entry = &mut self.entry_buffer[i.to_usize()];
+        if !entry.is_simple() || !is_simple_advance(advance) {
+            return false
+        }
+        entry.value = (entry.value & !GLYPH_ADVANCE_MASK) |
+            ((advance.to_u32().unwrap()) << GLYPH_ADVANCE_SHIFT);
+        true
+    }
+
     pub fn space_count_in_range(&self, range: &Range<CharIndex>) -> u32 {
         let mut spaces = 0;
         for index in range.each_index() {
@@ -737,6 +752,88 @@ impl<'a> GlyphStore {
             }
         }
     }
+
+    /// Returns the values that the glyphs are stored as, to send them to another process.
+    pub fn to_data(&self) -> GlyphStoreData {
+        let detail_store = &self.detail_store;
+        GlyphStoreData {
+            entries: self.entry_buffer.iter().map(|entry| entry.value).collect(),
+            detailed_glyphs: detail_store.detail_buffer.iter().map(|glyph| {
+                (glyph.id, glyph.advance, glyph.offset)
+            }).collect(),
+            detail_lookup: detail_store.detail_lookup.iter().map(|record| {
+                (record.entry_offset, record.detail_offset)
+            }).collect(),
+            is_whitespace: self.is_whitespace,
+        }
+    }
+
+    /// Returns the glyphs that `to_data()` returned the values of, or `None` if the values refer
+    /// to a character or a detailed glyph that isn't there, which lookups would panic on.
+    pub fn from_data(data: GlyphStoreData) -> Option<GlyphStore> {
+        let entry_count = data.entries.len();
+        let detailed_glyph_count = data.detailed_glyphs.len();
+        if data.detail_lookup.iter().any(|&(entry_offset, detail_offset)| {
+            entry_offset < CharIndex(0) || entry_offset.to_usize() >= entry_count ||
+                detail_offset > detailed_glyph_count
+        }) {
+            return None
+        }
+
+        let mut detail_store = DetailedGlyphStore {
+            detail_buffer: data.detailed_glyphs.into_iter().map(|(id, advance, offset)| {
+                DetailedGlyph::new(id, advance, offset)
+            }).collect(),
+            detail_lookup: data.detail_lookup.into_iter().map(|(entry_offset, detail_offset)| {
+                DetailedGlyphRecord {
+                    entry_offset: entry_offset,
+                    detail_offset: detail_offset,
+                }
+            }).collect(),
+            lookup_is_sorted: false,
+        };
+        detail_store.ensure_sorted();
+        if detail_store.detail_lookup.windows(2).any(|records| {
+            records[0].entry_offset == records[1].entry_offset
+        }) {
+            return None
+        }
+
+        // Every complex entry needs as many detailed glyphs as it says it has.
+        let entry_buffer: Vec<_> = data.entries.into_iter().map(GlyphEntry::new).collect();
+        for (index, entry) in entry_buffer.iter().enumerate() {
+            if entry.is_simple() || entry.glyph_count() == 0 {
+                continue
+            }
+            let key = DetailedGlyphRecord {
+                entry_offset: CharIndex(index as isize),
+                detail_offset: 0,
+            };
+            match detail_store.detail_lookup.binary_search_index(&key) {
+                Some(i) if detail_store.detail_lookup[i].detail_offset +
+                        entry.glyph_count() as usize <= detailed_glyph_count => {}
+                _ => return None,
+            }
+        }
+
+        Some(GlyphStore {
+            entry_buffer: entry_buffer,
+            detail_store: detail_store,
+            is_whitespace: data.is_whitespace,
+        })
+    }
+}
+
+/// The values that a `GlyphStore` is made of, so that it can be written out and read back.
+pub struct GlyphStoreData {
+    /// The glyph entry of each character, packed as in `GlyphEntry`.
+    pub entries: Vec<u32>,
+    /// The id, advance and offset of each glyph that doesn't fit in an entry.
+    pub detailed_glyphs: Vec<(GlyphId, Au, Point2D<Au>)>,
+    /// The character that each run of detailed glyphs belongs to, and where the run starts in
+    /// `detailed_glyphs`.
+    pub detail_lookup: Vec<(CharIndex, usize)>,
+    pub is_whitespace: bool,
 }
 
 /// An iterator over the glyphs in a character range in a `GlyphStore`.
@@ -0,0 +1,112 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Hexboxes are painted in place of characters that the font has no glyph for. Like Firefox's,
+//! they are small boxes containing the code point in hexadecimal, which makes encoding and font
+//! coverage problems much easier to diagnose than blank space or `.notdef` boxes.
+//!
+//! The digits are drawn from a built-in 3×5 bitmap font so that painting never depends on the
+//! availability of another font.
+
+use geom::{Point2D, Rect, Size2D};
+use util::geometry::Au;
+
+/// The rows of each hexadecimal digit, top to bottom. The low three bits of each row are the
+/// pixels, left to right.
+static DIGIT_BITMAPS: [[u8; 5]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],    // 0
+    [0b010, 0b110, 0b010, 0b010, 0b111],    // 1
+    [0b111, 0b001, 0b111, 0b100, 0b111],    // 2
+    [0b111, 0b001, 0b111, 0b001, 0b111],    // 3
+    [0b101, 0b101, 0b111, 0b001, 0b001],    // 4
+    [0b111, 0b100, 0b111, 0b001, 0b111],    // 5
+    [0b111, 0b100, 0b111, 0b101, 0b111],    // 6
+    [0b111, 0b001, 0b001, 0b001, 0b001],    // 7
+    [0b111, 0b101, 0b111, 0b101, 0b111],    // 8
+    [0b111, 0b101, 0b111, 0b001, 0b111],    // 9
+    [0b111, 0b101, 0b111, 0b101, 0b101],    // A
+    [0b110, 0b101, 0b110, 0b101, 0b110],    // B
+    [0b111, 0b100, 0b100, 0b100, 0b111],    // C
+    [0b110, 0b101, 0b101, 0b101, 0b110],    // D
+    [0b111, 0b100, 0b111, 0b100, 0b111],    // E
+    [0b111, 0b100, 0b111, 0b100, 0b100],    // F
+];
+
+static DIGIT_WIDTH: i32 = 3;
+static DIGIT_HEIGHT: i32 = 5;
+
+/// Returns true if a hexbox should be painted for the given character when it is missing from
+/// the font. Whitespace and control characters are invisible anyway.
+pub fn needs_hexbox(character: char) -> bool {
+    !character.is_whitespace() && !character.is_control()
+}
+
+/// The layout of the hexbox for one character. All lengths are in multiples of `pixel`.
+#[derive(Clone, Copy, Debug)]
+pub struct Hexbox {
+    /// The code point to display.
+    pub character: char,
+    /// The length of one pixel of the bitmap font.
+    pub pixel: Au,
+    /// The number of digit columns: two for code points in the BMP and three above that.
+    pub columns: i32,
+}
+
+impl Hexbox {
+    pub fn new(character: char, pt_size: Au) -> Hexbox {
+        Hexbox {
+            character: character,
+            pixel: Au::max(Au::from_frac_px(0.5), pt_size.scale_by(1.0 / 20.0)),
+            columns: if (character as u32) > 0xffff { 3 } else { 2 },
+        }
+    }
+
+    /// The horizontal advance of the hexbox, including one pixel of spacing on each side.
+    pub fn advance(&self) -> Au {
+        self.size().width + self.pixel * 2
+    }
+
+    /// The size of the hexbox: a one pixel border and one pixel of padding around two rows of
+    /// digits that are one pixel apart.
+    pub fn size(&self) -> Size2D<Au> {
+        let width = self.columns * DIGIT_WIDTH + (self.columns - 1) + 4;
+        let height = 2 * DIGIT_HEIGHT + 1 + 4;
+        Size2D(self.pixel * width, self.pixel * height)
+    }
+
+    /// Returns the rectangles to fill in order to paint the hexbox whose advance starts at the
+    /// given point on the baseline.
+    pub fn rects(&self, baseline_origin: &Point2D<Au>) -> Vec<Rect<Au>> {
+        let size = self.size();
+        let origin = Point2D(baseline_origin.x + self.pixel, baseline_origin.y - size.height);
+        let pixel = self.pixel;
+        let mut rects = vec!(
+            // The border.
+            Rect(origin, Size2D(size.width, pixel)),
+            Rect(Point2D(origin.x, origin.y + size.height - pixel), Size2D(size.width, pixel)),
+            Rect(origin, Size2D(pixel, size.height)),
+            Rect(Point2D(origin.x + size.width - pixel, origin.y), Size2D(pixel, size.height)),
+        );
+
+        // The digits, most significant first, filling the top row before the bottom one.
+        let digit_count = self.columns * 2;
+        for digit_index in 0..digit_count {
+            let shift = 4 * (digit_count - 1 - digit_index);
+            let digit = ((self.character as u32) >> (shift as usize)) & 0xf;
+            let (row, column) = (digit_index / self.columns, digit_index % self.columns);
+            let digit_origin = Point2D(origin.x + pixel * (2 + column * (DIGIT_WIDTH + 1)),
+                                       origin.y + pixel * (2 + row * (DIGIT_HEIGHT + 1)));
+            for (y, bits) in DIGIT_BITMAPS[digit as usize].iter().enumerate() {
+                for x in 0..DIGIT_WIDTH {
+                    if (*bits >> ((DIGIT_WIDTH - 1 - x) as usize)) & 1 != 0 {
+                        rects.push(Rect(Point2D(digit_origin.x + pixel * x,
+                                                digit_origin.y + pixel * (y as i32)),
+                                        Size2D(pixel, pixel)))
+                    }
+                }
+            }
+        }
+        rects
+    }
+}
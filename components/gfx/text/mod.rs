@@ -11,7 +11,10 @@ servo.rc. This is not ideal and may be changed in the future. */
 pub use text::shaping::Shaper;
 pub use text::text_run::TextRun;
 
+pub mod bidi;
 pub mod glyph;
+pub mod hexbox;
+pub mod shape_cache;
 #[path="shaping/mod.rs"] pub mod shaping;
 pub mod text_run;
 pub mod util;
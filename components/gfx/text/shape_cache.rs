@@ -0,0 +1,114 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A cache of shaped text that is shared by all layout threads.
+//!
+//! Each `Font` instance already remembers what it has shaped, but fonts belong to a single
+//! thread, so identical strings such as menu labels and table cells are shaped again by every
+//! layout worker. This cache sits behind the per-font caches and holds a bounded number of the
+//! most recently used glyph buffers for everybody. It is split into shards with a lock each, so
+//! that threads looking up different text rarely wait for each other.
+
+use font::ShapingOptions;
+use font_instance::FontInstanceKey;
+use platform::font_template::FontTemplateData;
+use text::glyph::GlyphStore;
+
+use std::hash::{Hash, Hasher, SipHasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use util::cache::SizedLRUCache;
+
+/// The number of shaped strings kept in each shard of the cache.
+static SHAPED_TEXT_SHARD_SIZE: usize = 32;
+
+/// The number of shards, each with a lock of its own.
+static SHAPED_TEXT_SHARD_COUNT: usize = 8;
+
+/// A font template, compared by identity. Templates of different faces may share an identifier,
+/// such as web fonts loaded from data or the faces of a collection, so the identifier alone
+/// doesn't tell them apart. The key keeps the template alive, so no other template can take its
+/// address while the entry is cached.
+#[derive(Clone)]
+pub struct FontTemplateRef(pub Arc<FontTemplateData>);
+
+impl PartialEq for FontTemplateRef {
+    fn eq(&self, other: &FontTemplateRef) -> bool {
+        &*self.0 as *const FontTemplateData == &*other.0 as *const FontTemplateData
+    }
+}
+
+impl Eq for FontTemplateRef {}
+
+impl Hash for FontTemplateRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (&*self.0 as *const FontTemplateData as usize).hash(state)
+    }
+}
+
+/// Identifies a shaping result. The script is not part of the key, since the shaper derives it
+/// from the text itself; the direction is part of the shaping options.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ShapedTextKey {
+    pub template: FontTemplateRef,
+    pub font: FontInstanceKey,
+    pub text: String,
+    pub options: ShapingOptions,
+}
+
+pub struct ShapedTextCache {
+    shards: Vec<Mutex<SizedLRUCache<ShapedTextKey, Arc<GlyphStore>>>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ShapedTextCache {
+    pub fn new() -> ShapedTextCache {
+        ShapedTextCache {
+            shards: (0..SHAPED_TEXT_SHARD_COUNT).map(|_| {
+                Mutex::new(SizedLRUCache::new(SHAPED_TEXT_SHARD_SIZE))
+            }).collect(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the shaping result for the key, calling `shape` to produce it if it isn't cached.
+    /// The shard isn't locked while the text is shaped, so that threads looking up other text
+    /// don't wait for it; if another thread cached the same text in the meantime, its result is
+    /// returned instead, so that each key is only cached once.
+    pub fn find_or_shape<F>(&self, key: ShapedTextKey, shape: F) -> Arc<GlyphStore>
+                            where F: FnOnce() -> GlyphStore {
+        let shard = &self.shards[self.shard_index(&key)];
+        if let Some(glyphs) = shard.lock().unwrap().find(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return glyphs
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let glyphs = Arc::new(shape());
+        let mut shard = shard.lock().unwrap();
+        if let Some(glyphs) = shard.find(&key) {
+            return glyphs
+        }
+        shard.insert(key, glyphs.clone(), 1);
+        glyphs
+    }
+
+    fn shard_index(&self, key: &ShapedTextKey) -> usize {
+        let mut hasher = SipHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// The number of lookups that found a result.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups that found nothing.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
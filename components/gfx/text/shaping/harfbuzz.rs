@@ -5,14 +5,15 @@
 extern crate harfbuzz;
 
 use font::{DISABLE_KERNING_SHAPING_FLAG, Font, FontHandleMethods, FontTableMethods, FontTableTag};
-use font::{IGNORE_LIGATURES_SHAPING_FLAG, ShapingOptions};
+use font::{IGNORE_LIGATURES_SHAPING_FLAG, RTL_SHAPING_FLAG, ShapingOptions};
 use platform::font::FontTable;
 use text::glyph::{CharIndex, GlyphStore, GlyphId, GlyphData};
+use text::hexbox::{self, Hexbox};
 use text::shaping::ShaperMethods;
 use text::util::{float_to_fixed, fixed_to_float};
 
 use geom::Point2D;
-use harfbuzz::{HB_MEMORY_MODE_READONLY, HB_DIRECTION_LTR};
+use harfbuzz::{HB_MEMORY_MODE_READONLY, HB_DIRECTION_LTR, HB_DIRECTION_RTL};
 use harfbuzz::{RUST_hb_blob_create, RUST_hb_face_create_for_tables};
 use harfbuzz::{hb_blob_t};
 use harfbuzz::{hb_bool_t};
@@ -20,7 +21,7 @@ use harfbuzz::{RUST_hb_buffer_add_utf8};
 use harfbuzz::{RUST_hb_buffer_destroy};
 use harfbuzz::{RUST_hb_buffer_get_glyph_positions};
 use harfbuzz::{RUST_hb_buffer_get_length};
-use harfbuzz::{RUST_hb_buffer_set_direction};
+use harfbuzz::{RUST_hb_buffer_set_direction, RUST_hb_buffer_reverse};
 use harfbuzz::{RUST_hb_face_destroy};
 use harfbuzz::{hb_face_t, hb_font_t};
 use harfbuzz::{hb_feature_t};
@@ -41,6 +42,7 @@ use harfbuzz::{hb_position_t, hb_tag_t};
 use harfbuzz::{RUST_hb_shape, RUST_hb_buffer_get_glyph_infos};
 use libc::{c_uint, c_int, c_void, c_char};
 use util::geometry::Au;
+use util::opts;
 use util::range::Range;
 use std::char;
 use std::iter::repeat;
@@ -230,7 +232,9 @@ impl ShaperMethods for Shaper {
     fn shape_text(&self, text: &str, options: &ShapingOptions, glyphs: &mut GlyphStore) {
         unsafe {
             let hb_buffer: *mut hb_buffer_t = RUST_hb_buffer_create();
-            RUST_hb_buffer_set_direction(hb_buffer, HB_DIRECTION_LTR);
+            let is_rtl = options.flags.contains(RTL_SHAPING_FLAG);
+            RUST_hb_buffer_set_direction(hb_buffer,
+                                         if is_rtl { HB_DIRECTION_RTL } else { HB_DIRECTION_LTR });
 
             RUST_hb_buffer_add_utf8(hb_buffer,
                                     text.as_ptr() as *const c_char,
@@ -257,6 +261,12 @@ impl ShaperMethods for Shaper {
             }
 
             RUST_hb_shape(self.hb_font, hb_buffer, features.as_mut_ptr(), features.len() as u32);
+
+            // HarfBuzz returns right-to-left glyphs in visual order. Put them back in logical
+            // order, which is how the glyph store indexes them; painting reverses them again.
+            if is_rtl {
+                RUST_hb_buffer_reverse(hb_buffer);
+            }
             self.save_glyph_results(text, options, glyphs, hb_buffer);
             RUST_hb_buffer_destroy(hb_buffer);
         }
@@ -453,7 +463,16 @@ impl Shaper {
                 // we'll need to do something special to handle `word-spacing` properly.
                 let shape = glyph_data.get_entry_for_glyph(glyph_span.begin(), &mut y_pos);
                 let character = text.char_at(char_byte_span.begin());
-                let advance = self.advance_for_shaped_glyph(shape.advance, character, options);
+                let advance = if shape.codepoint == 0 && opts::get().show_hexboxes &&
+                        hexbox::needs_hexbox(character) {
+                    // Glyph 0 is `.notdef`. Make room for the hexbox that will be painted instead.
+                    let pt_size = unsafe {
+                        (*self.font_and_shaping_options.font).actual_pt_size
+                    };
+                    Hexbox::new(character, pt_size).advance()
+                } else {
+                    self.advance_for_shaped_glyph(shape.advance, character, options)
+                };
                 let data = GlyphData::new(shape.codepoint,
                                           advance,
                                           shape.offset,
@@ -518,7 +537,9 @@ impl Shaper {
         if character == ' ' || character == '\u{a0}' {
             advance = advance + options.word_spacing
         } else if character == '\t' {
-            let tab_size = 8f64;
+            // This is the width of a whole tab stop interval. `TextRun::break_and_shape` shortens
+            // it as necessary to align the following text to the next tab stop.
+            let tab_size = options.tab_size as f64;
             advance = Au::from_frac_px(tab_size * glyph_space_advance(self.font_and_shaping_options.font));
         }
 
@@ -602,19 +623,20 @@ extern fn get_font_table_func(_: *mut hb_face_t,
         assert!(!font_and_shaping_options.is_null());
         assert!(!(*font_and_shaping_options).font.is_null());
 
-        // TODO(Issue #197): reuse font table data, which will change the unsound trickery here.
+        // TODO(Issue #197): reuse font table data instead of copying it for every face.
         match (*(*font_and_shaping_options).font).get_table_for_tag(tag as FontTableTag) {
             None => ptr::null_mut(),
-            Some(ref font_table) => {
-                let skinny_font_table_ptr: *const FontTable = font_table;   // private context
+            Some(font_table) => {
+                // The table is boxed so that it outlives this call; HarfBuzz hands it back to
+                // `destroy_blob_func` when the buffer is no longer needed.
+                let font_table_ptr: *mut FontTable = mem::transmute(box font_table);
 
                 let mut blob: *mut hb_blob_t = ptr::null_mut();
-                (*skinny_font_table_ptr).with_buffer(|buf: *const u8, len: usize| {
-                    // HarfBuzz calls `destroy_blob_func` when the buffer is no longer needed.
+                (*font_table_ptr).with_buffer(|buf: *const u8, len: usize| {
                     blob = RUST_hb_blob_create(buf as *const c_char,
                                                len as c_uint,
                                                HB_MEMORY_MODE_READONLY,
-                                               mem::transmute(skinny_font_table_ptr),
+                                               font_table_ptr as *mut c_void,
                                                destroy_blob_func);
                 });
 
@@ -625,10 +647,9 @@ extern fn get_font_table_func(_: *mut hb_face_t,
     }
 }
 
-// TODO(Issue #197): reuse font table data, which will change the unsound trickery here.
-// In particular, we'll need to cast to a boxed, rather than owned, FontTable.
-
-// even better, should cache the harfbuzz blobs directly instead of recreating a lot.
-extern fn destroy_blob_func(_: *mut c_void) {
-    // TODO: Previous code here was broken. Rewrite.
+// TODO(Issue #197): cache the harfbuzz blobs directly instead of recreating a lot.
+extern fn destroy_blob_func(font_table: *mut c_void) {
+    unsafe {
+        let _: Box<FontTable> = mem::transmute(font_table);
+    }
 }
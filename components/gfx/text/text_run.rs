@@ -3,15 +3,20 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use font::{Font, FontHandleMethods, FontMetrics, IS_WHITESPACE_SHAPING_FLAG, RunMetrics};
-use font::{ShapingOptions};
+use font::{FontSynthesisFlags, MIXED_ORIENTATION_SHAPING_FLAG, RTL_SHAPING_FLAG};
+use font::{SYNTHETIC_BOLD_FONT_FLAG, ShapingOptions};
+use font_instance::FontInstanceKey;
 use platform::font_template::FontTemplateData;
+use geom::{Point2D, Rect, Size2D};
 use util::geometry::Au;
 use util::range::Range;
 use util::vec::{Comparator, FullBinarySearchMethods};
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::slice::Iter;
 use std::sync::Arc;
-use text::glyph::{CharIndex, GlyphStore};
+use text::glyph::{CharIndex, GlyphId, GlyphStore};
+use text::util::is_upright_in_vertical_text;
 
 /// A single "paragraph" of text in one font size and style.
 #[derive(Clone)]
@@ -19,10 +24,19 @@ pub struct TextRun {
     /// The UTF-8 string represented by this text run.
     pub text: Arc<String>,
     pub font_template: Arc<FontTemplateData>,
+    /// Identifies the font instance, so that the paint task can share it with other threads.
+    pub font_key: FontInstanceKey,
     pub actual_pt_size: Au,
     pub font_metrics: FontMetrics,
+    /// Styles that the font lacks and that the paint task must synthesize.
+    pub synthesis: FontSynthesisFlags,
     /// The glyph runs that make up this text run.
     pub glyphs: Arc<Vec<GlyphRun>>,
+    /// How far the ink of each glyph that reaches below the baseline goes, so that underlines
+    /// can skip it.
+    pub glyph_descents: Arc<HashMap<GlyphId, Au>>,
+    /// The resolved bidi embedding level. Odd levels are right-to-left.
+    pub bidi_level: u8,
 }
 
 /// A single series of glyphs within a text run.
@@ -32,6 +46,9 @@ pub struct GlyphRun {
     pub glyph_store: Arc<GlyphStore>,
     /// The range of characters in the containing run.
     pub range: Range<CharIndex>,
+    /// True if the glyphs stay upright when the run is painted sideways, as upright characters
+    /// do under `text-orientation: mixed`.
+    pub upright: bool,
 }
 
 pub struct NaturalWordSliceIterator<'a> {
@@ -186,68 +203,106 @@ impl<'a> Iterator for LineIterator<'a> {
 }
 
 impl<'a> TextRun {
-    pub fn new(font: &mut Font, text: String, options: &ShapingOptions) -> TextRun {
-        let glyphs = TextRun::break_and_shape(font, &text, options);
+    pub fn new(font: &mut Font, text: String, options: &ShapingOptions, bidi_level: u8)
+               -> TextRun {
+        let mut options = *options;
+        if bidi_level % 2 == 1 {
+            options.flags.insert(RTL_SHAPING_FLAG)
+        }
+        let glyphs = TextRun::break_and_shape(font, &text, &options);
+        let glyph_descents = TextRun::glyph_descents(font, &glyphs);
         let run = TextRun {
             text: Arc::new(text),
             font_metrics: font.metrics.clone(),
             font_template: font.handle.get_template(),
+            font_key: font.instance_key(),
             actual_pt_size: font.actual_pt_size,
+            synthesis: font.synthesis,
             glyphs: Arc::new(glyphs),
+            glyph_descents: Arc::new(glyph_descents),
+            bidi_level: bidi_level,
         };
         return run;
     }
 
+    fn glyph_descents(font: &mut Font, glyphs: &[GlyphRun]) -> HashMap<GlyphId, Au> {
+        let mut descents = HashMap::new();
+        for glyph_run in glyphs.iter() {
+            let store = &glyph_run.glyph_store;
+            let range = Range::new(CharIndex(0), store.char_len());
+            for (_, glyph) in store.iter_glyphs_for_char_range(&range) {
+                let id = glyph.id();
+                if descents.contains_key(&id) {
+                    continue
+                }
+                let descent = font.glyph_descent(id);
+                if descent > Au(0) {
+                    descents.insert(id, descent);
+                }
+            }
+        }
+        descents
+    }
+
+    /// Returns true if this run is right-to-left. Its glyphs are stored in logical order and
+    /// painted in reverse.
+    #[inline]
+    pub fn is_rtl(&self) -> bool {
+        self.bidi_level % 2 == 1
+    }
+
     pub fn break_and_shape(font: &mut Font, text: &str, options: &ShapingOptions)
                            -> Vec<GlyphRun> {
         // TODO(Issue #230): do a better job. See Gecko's LineBreaker.
         let mut glyphs = vec!();
         let (mut byte_i, mut char_i) = (0, CharIndex(0));
         let mut cur_slice_is_whitespace = false;
+        let mut cur_slice_is_upright = false;
+        let mixed_orientation = options.flags.contains(MIXED_ORIENTATION_SHAPING_FLAG);
         let (mut byte_last_boundary, mut char_last_boundary) = (0, CharIndex(0));
+        let mut line_advance = Au(0);
         while byte_i < text.len() {
             let range = text.char_range_at(byte_i);
             let ch = range.ch;
             let next = range.next;
 
             // Slices alternate between whitespace and non-whitespace,
-            // representing line break opportunities.
-            let can_break_before = if cur_slice_is_whitespace {
-                match ch {
-                    ' ' | '\t' | '\n' => false,
-                    _ => {
-                        cur_slice_is_whitespace = false;
-                        true
-                    }
-                }
+            // representing line break opportunities. With mixed orientation,
+            // slices also end where characters switch between upright and
+            // sideways; whitespace takes the orientation of the text before it.
+            let ch_is_whitespace = match ch {
+                ' ' | '\t' | '\n' => true,
+                _ => false,
+            };
+            let ch_is_upright = if mixed_orientation && !ch_is_whitespace {
+                is_upright_in_vertical_text(ch)
             } else {
-                match ch {
-                    ' ' | '\t' | '\n' => {
-                        cur_slice_is_whitespace = true;
-                        true
-                    },
-                    _ => false
-                }
+                cur_slice_is_upright
             };
+            let can_break_before = ch_is_whitespace != cur_slice_is_whitespace ||
+                ch_is_upright != cur_slice_is_upright;
 
             // Create a glyph store for this slice if it's nonempty.
             if can_break_before && byte_i > byte_last_boundary {
                 let slice = &text[byte_last_boundary .. byte_i];
                 debug!("creating glyph store for slice {} (ws? {}), {} - {} in run {}",
-                        slice, !cur_slice_is_whitespace, byte_last_boundary, byte_i, text);
+                        slice, cur_slice_is_whitespace, byte_last_boundary, byte_i, text);
 
                 let mut options = *options;
-                if !cur_slice_is_whitespace {
+                if cur_slice_is_whitespace {
                     options.flags.insert(IS_WHITESPACE_SHAPING_FLAG);
                 }
 
                 glyphs.push(GlyphRun {
-                    glyph_store: font.shape_text(slice, &options),
+                    glyph_store: TextRun::shape_slice(font, slice, &options, &mut line_advance),
                     range: Range::new(char_last_boundary, char_i - char_last_boundary),
+                    upright: cur_slice_is_upright,
                 });
                 byte_last_boundary = byte_i;
                 char_last_boundary = char_i;
             }
+            cur_slice_is_whitespace = ch_is_whitespace;
+            cur_slice_is_upright = ch_is_upright;
 
             byte_i = next;
             char_i = char_i + CharIndex(1);
@@ -265,14 +320,66 @@ impl<'a> TextRun {
             }
 
             glyphs.push(GlyphRun {
-                glyph_store: font.shape_text(slice, &options),
+                glyph_store: TextRun::shape_slice(font, slice, &options, &mut line_advance),
                 range: Range::new(char_last_boundary, char_i - char_last_boundary),
+                upright: cur_slice_is_upright,
             });
         }
 
         glyphs
     }
 
+    /// Shapes one slice of a run, shortening the advances of any tabs in it so that the text
+    /// after each tab starts at the next tab stop. `line_advance` is the advance of the current
+    /// line up to the slice and is updated to include it.
+    ///
+    /// FIXME: Tab stops are measured from the start of the run rather than from the start of the
+    /// line box, so text preceding this run on the same line isn't taken into account.
+    fn shape_slice(font: &mut Font, slice: &str, options: &ShapingOptions, line_advance: &mut Au)
+                   -> Arc<GlyphStore> {
+        let glyphs = font.shape_text(slice, options);
+        if !slice.chars().any(|ch| ch == '\t' || ch == '\n') {
+            let whole_slice = Range::new(CharIndex(0), glyphs.char_len());
+            *line_advance = *line_advance + glyphs.advance_for_char_range(&whole_slice);
+            return glyphs
+        }
+
+        let tab_stop_interval = match font.glyph_index(' ') {
+            Some(space_glyph) => {
+                Au::from_frac_px(font.glyph_h_advance(space_glyph) * options.tab_size as f64)
+            }
+            None => Au(0),
+        };
+
+        // The shaped glyphs are shared through the shape cache, so copy them before aligning.
+        let mut aligned_glyphs = None;
+        for (i, ch) in slice.chars().enumerate() {
+            let char_index = CharIndex(i as isize);
+            let shaped_advance = glyphs.advance_for_char_range(&Range::new(char_index,
+                                                                           CharIndex(1)));
+            match ch {
+                '\n' => *line_advance = Au(0),
+                '\t' if tab_stop_interval > Au(0) => {
+                    let (Au(interval), Au(position)) = (tab_stop_interval, *line_advance);
+                    let advance = Au(interval - position % interval);
+                    if aligned_glyphs.is_none() {
+                        aligned_glyphs = Some((*glyphs).clone())
+                    }
+                    let aligned = aligned_glyphs.as_mut()
+                                                .unwrap()
+                                                .set_advance_for_char_index(char_index, advance);
+                    *line_advance = *line_advance + if aligned { advance } else { shaped_advance }
+                }
+                _ => *line_advance = *line_advance + shaped_advance,
+            }
+        }
+
+        match aligned_glyphs {
+            Some(aligned_glyphs) => Arc::new(aligned_glyphs),
+            None => glyphs,
+        }
+    }
+
     pub fn char_len(&self) -> CharIndex {
         match self.glyphs.last() {
             None => CharIndex(0),
@@ -284,10 +391,59 @@ impl<'a> TextRun {
         &*self.glyphs
     }
 
+    /// Returns true if the characters in `range` of this run and of `other` are painted the same
+    /// way: they are the same text in the same font instance, shaped into the same glyphs at the
+    /// same advances and offsets. Layout makes new runs at every reflow, so runs are compared by
+    /// what is in them rather than by which run they are.
+    pub fn paints_same_as(&self, other: &TextRun, range: &Range<CharIndex>) -> bool {
+        if self as *const TextRun == other as *const TextRun {
+            return true
+        }
+        if self.font_key != other.font_key || self.synthesis != other.synthesis ||
+                self.bidi_level != other.bidi_level {
+            return false
+        }
+
+        let (begin, length) = (range.begin().to_usize(), range.length().to_usize());
+        let mut chars = self.text.chars().skip(begin).take(length);
+        let mut other_chars = other.text.chars().skip(begin).take(length);
+        loop {
+            match (chars.next(), other_chars.next()) {
+                (Some(a), Some(b)) if a == b => {}
+                (None, None) => break,
+                _ => return false,
+            }
+        }
+
+        let mut slices = self.natural_word_slices_in_range(range);
+        let mut other_slices = other.natural_word_slices_in_range(range);
+        loop {
+            match (slices.next(), other_slices.next()) {
+                (Some(slice), Some(other_slice)) => {
+                    if slice.text_run_range() != other_slice.text_run_range() ||
+                            !same_glyphs(&slice, &other_slice) {
+                        return false
+                    }
+                }
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+
     pub fn range_is_trimmable_whitespace(&self, range: &Range<CharIndex>) -> bool {
         self.natural_word_slices_in_range(range).all(|slice| slice.glyphs.is_whitespace())
     }
 
+    /// Returns the horizontal distance by which the second strike of a synthetic bold run is
+    /// offset, or zero if this run does not need synthetic bold.
+    pub fn synthetic_bold_offset(&self) -> Au {
+        if !self.synthesis.contains(SYNTHETIC_BOLD_FONT_FLAG) {
+            return Au(0)
+        }
+        Au::max(Au::from_px(1), self.actual_pt_size.scale_by(1.0 / 24.0))
+    }
+
     pub fn ascent(&self) -> Au {
         self.font_metrics.ascent
     }
@@ -318,6 +474,107 @@ impl<'a> TextRun {
                         self.font_metrics.descent)
     }
 
+    /// Returns the union of the bounding rects of the glyphs for the characters in `range`. The
+    /// rect is relative to the left end of `run_range` on the baseline, which is where it ends if
+    /// the run is right-to-left, so the glyphs don't have to be reshaped to compute selection
+    /// highlights or client rects.
+    ///
+    /// TODO(Issue #125): Use the glyph extents rather than the advance and the font ascent and
+    /// descent.
+    pub fn glyph_bounds_for_range(&self, run_range: &Range<CharIndex>, range: &Range<CharIndex>)
+                                  -> Rect<Au> {
+        let range = run_range.intersect(range);
+        let line_height = self.font_metrics.ascent + self.font_metrics.descent;
+        let mut origin = Au(0);
+        let mut bounds: Option<Rect<Au>> = None;
+        for slice in self.natural_word_slices_in_range(run_range) {
+            for (i, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+                let advance = glyph.advance();
+                if range.contains(slice.offset + i) {
+                    let (x, width) = if advance < Au(0) {
+                        (origin + advance, -advance)
+                    } else {
+                        (origin, advance)
+                    };
+                    let glyph_rect = Rect(Point2D(x, -self.font_metrics.ascent),
+                                          Size2D(width, line_height));
+                    bounds = Some(match bounds {
+                        None => glyph_rect,
+                        Some(bounds) => bounds.union(&glyph_rect),
+                    })
+                }
+                origin = origin + advance
+            }
+        }
+        match bounds {
+            Some(mut bounds) => {
+                if self.is_rtl() {
+                    bounds.origin.x = origin - bounds.max_x()
+                }
+                bounds
+            }
+            None => Rect(Point2D(Au(0), -self.font_metrics.ascent), Size2D(Au(0), line_height)),
+        }
+    }
+
+    /// Returns the intervals along the baseline, relative to the left end of `run_range`, of the
+    /// glyphs whose ink reaches below `top`, which is measured downwards from the baseline.
+    pub fn descender_intervals(&self, run_range: &Range<CharIndex>, top: Au) -> Vec<(Au, Au)> {
+        let mut origin = Au(0);
+        let mut intervals = vec!();
+        for slice in self.natural_word_slices_in_range(run_range) {
+            for (_, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+                let advance = glyph.advance();
+                let descends = match self.glyph_descents.get(&glyph.id()) {
+                    Some(&descent) => descent > top,
+                    None => false,
+                };
+                if descends {
+                    intervals.push(if advance < Au(0) {
+                        (origin + advance, origin)
+                    } else {
+                        (origin, origin + advance)
+                    })
+                }
+                origin = origin + advance
+            }
+        }
+        if self.is_rtl() {
+            for interval in intervals.iter_mut() {
+                *interval = (origin - interval.1, origin - interval.0)
+            }
+        }
+        intervals
+    }
+
+    /// Returns the index of each character in `run_range` that has glyphs, with the interval
+    /// along the baseline, relative to the left end of `run_range`, that its glyphs take up.
+    /// Characters that share the glyphs of the character before them, as in ligatures, are left
+    /// out.
+    pub fn character_intervals(&self, run_range: &Range<CharIndex>) -> Vec<(CharIndex, Au, Au)> {
+        let mut origin = Au(0);
+        let mut intervals: Vec<(CharIndex, Au, Au)> = vec!();
+        for slice in self.natural_word_slices_in_range(run_range) {
+            for (i, glyph) in slice.glyphs.iter_glyphs_for_char_range(&slice.range) {
+                let index = slice.offset + i;
+                let end = origin + glyph.advance();
+                match intervals.last_mut() {
+                    Some(&mut (last_index, _, ref mut last_end)) if last_index == index => {
+                        *last_end = end
+                    }
+                    _ => intervals.push((index, origin, end)),
+                }
+                origin = end
+            }
+        }
+        if self.is_rtl() {
+            for interval in intervals.iter_mut() {
+                *interval = (interval.0, origin - interval.2, origin - interval.1)
+            }
+        }
+        intervals
+    }
+
     pub fn min_width_for_range(&self, range: &Range<CharIndex>) -> Au {
         debug!("iterating outer range {:?}", range);
         self.natural_word_slices_in_range(range).fold(Au(0), |max_piece_width, slice| {
@@ -375,3 +632,21 @@ impl<'a> TextRun {
         }
     }
 }
+
+/// Returns true if the slices, which cover the same characters, have the same glyphs.
+fn same_glyphs(slice: &TextRunSlice, other_slice: &TextRunSlice) -> bool {
+    let mut glyphs = slice.glyphs.iter_glyphs_for_char_range(&slice.range);
+    let mut other_glyphs = other_slice.glyphs.iter_glyphs_for_char_range(&other_slice.range);
+    loop {
+        match (glyphs.next(), other_glyphs.next()) {
+            (Some((_, glyph)), Some((_, other_glyph))) => {
+                if glyph.id() != other_glyph.id() || glyph.advance() != other_glyph.advance() ||
+                        glyph.offset() != other_glyph.offset() {
+                    return false
+                }
+            }
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
@@ -114,3 +114,85 @@ pub fn fixed_to_rounded_int(before: isize, f: i32) -> isize {
        -((half - f) >> before as usize) as isize
     }
 }
+
+/// Returns true if the character stays upright in vertical text with `text-orientation: mixed`.
+/// This approximates the `U` and `Tu` values of UAX #50 by the blocks of the CJK scripts,
+/// fullwidth forms and pictographs; everything else is set sideways.
+pub fn is_upright_in_vertical_text(ch: char) -> bool {
+    match ch as u32 {
+        0x1100...0x11ff |   // Hangul Jamo
+        0x2e80...0x2fff |   // CJK and Kangxi radicals, ideographic description characters
+        0x3000...0x9fff |   // CJK symbols, kana, Bopomofo, Hangul compatibility, CJK ideographs
+        0xa000...0xa4cf |   // Yi
+        0xac00...0xd7ff |   // Hangul syllables
+        0xf900...0xfaff |   // CJK compatibility ideographs
+        0xfe10...0xfe1f |   // Vertical forms
+        0xfe30...0xfe4f |   // CJK compatibility forms
+        0xff01...0xff60 |   // Fullwidth forms
+        0xffe0...0xffe6 |   // Fullwidth signs
+        0x1f000...0x1faff | // Mahjong and playing cards, enclosed characters, pictographs
+        0x20000...0x3ffff => true,
+        _ => false,
+    }
+}
+
+/// The scripts that generic font families can have their own default families for (see
+/// `--script-font`).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Script {
+    Arabic,
+    Cyrillic,
+    Devanagari,
+    Greek,
+    /// Chinese characters as used by all CJK languages, and Bopomofo.
+    Han,
+    Hangul,
+    Hebrew,
+    /// Hiragana and katakana.
+    Kana,
+    Latin,
+    Thai,
+}
+
+impl Script {
+    /// Returns the script with the given lowercase name, as used in `--script-font`.
+    pub fn from_name(name: &str) -> Option<Script> {
+        match name {
+            "arabic" => Some(Script::Arabic),
+            "cyrillic" => Some(Script::Cyrillic),
+            "devanagari" => Some(Script::Devanagari),
+            "greek" => Some(Script::Greek),
+            "han" => Some(Script::Han),
+            "hangul" => Some(Script::Hangul),
+            "hebrew" => Some(Script::Hebrew),
+            "kana" => Some(Script::Kana),
+            "latin" => Some(Script::Latin),
+            "thai" => Some(Script::Thai),
+            _ => None,
+        }
+    }
+
+    /// Returns the script of the character by its Unicode block, or `None` for characters that
+    /// are shared by all scripts, such as spaces, digits and punctuation, and for scripts that
+    /// aren't listed here.
+    pub fn for_char(ch: char) -> Option<Script> {
+        match ch as u32 {
+            0x0041...0x005a | 0x0061...0x007a | 0x00c0...0x00d6 | 0x00d8...0x00f6 |
+            0x00f8...0x024f | 0x1e00...0x1eff => Some(Script::Latin),
+            0x0370...0x03ff | 0x1f00...0x1fff => Some(Script::Greek),
+            0x0400...0x052f | 0x2de0...0x2dff | 0xa640...0xa69f => Some(Script::Cyrillic),
+            0x0590...0x05ff | 0xfb1d...0xfb4f => Some(Script::Hebrew),
+            0x0600...0x06ff | 0x0750...0x077f | 0x08a0...0x08ff | 0xfb50...0xfdff |
+            0xfe70...0xfeff => Some(Script::Arabic),
+            0x0900...0x097f | 0xa8e0...0xa8ff => Some(Script::Devanagari),
+            0x0e00...0x0e7f => Some(Script::Thai),
+            0x1100...0x11ff | 0x3130...0x318f | 0xa960...0xa97f | 0xac00...0xd7ff => {
+                Some(Script::Hangul)
+            }
+            0x3040...0x30ff | 0x31f0...0x31ff | 0xff66...0xff9f => Some(Script::Kana),
+            0x2e80...0x2fdf | 0x3100...0x312f | 0x3400...0x4dbf | 0x4e00...0x9fff |
+            0xf900...0xfaff | 0x20000...0x2ffff => Some(Script::Han),
+            _ => None,
+        }
+    }
+}
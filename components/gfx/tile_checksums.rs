@@ -0,0 +1,184 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks each tile that is painted against a baseline of hashes of their pixels, for reftests and
+//! fuzzing (`--tile-checksums`), and keeps what is needed to look into the tiles that differ.
+//!
+//! The baseline file has a line for each tile, with the layer, the part of it in px and the scale
+//! that name the tile, then the hash. Tiles that aren't in it yet are added, and it is written back
+//! once painting is done, so the first run makes the baseline. When a tile hashes differently from
+//! the baseline, its pixels are written to a PNG, and the display list that it was painted from,
+//! optimized for it, to JSON, next to the baseline file.
+
+use display_list::StackingContext;
+use display_list::json;
+
+use azure::AzFloat;
+use azure::azure_hl::DrawTarget;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use png::{self, PixelsByColorType};
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hasher, SipHasher};
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Mutex, ONCE_INIT, Once};
+use util::opts;
+
+struct TileChecksums {
+    /// The file that the baseline is read from and written to.
+    path: PathBuf,
+    /// The hash of each tile, by its name.
+    hashes: HashMap<String, u64>,
+    /// How many tiles have differed, which numbers the files written for them.
+    mismatch_count: usize,
+}
+
+static mut CHECKSUMS: *const Mutex<TileChecksums> = 0 as *const Mutex<TileChecksums>;
+static INIT: Once = ONCE_INIT;
+
+/// Returns the checksums, reading the baseline the first time, or `None` if tiles aren't checked.
+fn checksums() -> Option<&'static Mutex<TileChecksums>> {
+    let path = match opts::get().tile_checksums_file {
+        Some(ref path) => path,
+        None => return None,
+    };
+    INIT.call_once(|| {
+        let checksums = box Mutex::new(TileChecksums {
+            path: PathBuf::new(path),
+            hashes: read_baseline(Path::new(path)),
+            mismatch_count: 0,
+        });
+        unsafe {
+            CHECKSUMS = mem::transmute(checksums);
+        }
+    });
+    unsafe {
+        if CHECKSUMS == ptr::null() {
+            None
+        } else {
+            Some(mem::transmute(CHECKSUMS))
+        }
+    }
+}
+
+/// Reads the hashes of the baseline file. A file that doesn't exist yet makes an empty baseline.
+fn read_baseline(path: &Path) -> HashMap<String, u64> {
+    let mut hashes = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return hashes,
+    };
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let (name, hash) = match line.rfind(' ') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => continue,
+        };
+        if let Ok(hash) = u64::from_str_radix(hash, 16) {
+            hashes.insert(name.to_owned(), hash);
+        }
+    }
+    hashes
+}
+
+/// Checks the tile that was painted into the draw target against the baseline. `page_rect` is the
+/// part of the layer of the stacking context that it shows, and `tile_bounds` the same part in the
+/// coordinates of the stacking context.
+pub fn check(draw_target: &DrawTarget,
+             size: &Size2D<usize>,
+             stacking_context: &StackingContext,
+             page_rect: &Rect<f32>,
+             tile_bounds: &Rect<AzFloat>,
+             scale: f32) {
+    let checksums = match checksums() {
+        Some(checksums) => checksums,
+        None => return,
+    };
+
+    // The rows of pixels, without the padding at the end of each, from the top, in BGRA.
+    let row_length = size.width * 4;
+    let mut pixels = Vec::with_capacity(row_length * size.height);
+    let data_surface = draw_target.snapshot().get_data_surface();
+    let stride = data_surface.stride() as usize;
+    data_surface.with_data(|data| {
+        for row in 0..size.height {
+            pixels.push_all(&data[row * stride..row * stride + row_length])
+        }
+    });
+    let mut hasher = SipHasher::new();
+    hasher.write(&pixels[..]);
+    let hash = hasher.finish();
+
+    let layer_id = stacking_context.layer.as_ref().map(|layer| layer.id);
+    let name = format!("{:?} {},{},{},{} {}",
+                       layer_id,
+                       page_rect.origin.x,
+                       page_rect.origin.y,
+                       page_rect.size.width,
+                       page_rect.size.height,
+                       scale);
+    let mut checksums = checksums.lock().unwrap();
+    let baseline_hash = match checksums.hashes.get(&name).map(|baseline_hash| *baseline_hash) {
+        Some(baseline_hash) => baseline_hash,
+        None => {
+            checksums.hashes.insert(name, hash);
+            return
+        }
+    };
+    if baseline_hash == hash {
+        return
+    }
+
+    let index = checksums.mismatch_count;
+    checksums.mismatch_count += 1;
+    let directory = checksums.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    println!("tile {} differs from the baseline; writing tile-mismatch-{}.png and .json to {:?}",
+             name,
+             index,
+             directory);
+
+    for pixel in pixels.chunks_mut(4) {
+        pixel.swap(0, 2)
+    }
+    let mut image = png::Image {
+        width: size.width as u32,
+        height: size.height as u32,
+        pixels: PixelsByColorType::RGBA8(pixels),
+    };
+    let image_path = directory.join(format!("tile-mismatch-{}.png", index));
+    if png::store_png(&mut image, &image_path).is_err() {
+        println!("couldn't write {:?}", image_path)
+    }
+    let json_path = directory.join(format!("tile-mismatch-{}.json", index));
+    if let Err(error) = json::write_for_tile(stacking_context, tile_bounds, &json_path) {
+        println!("couldn't write {:?}: {}", json_path, error)
+    }
+}
+
+/// Writes the baseline back to its file, with the tiles that weren't in it before.
+pub fn save() {
+    let checksums = match checksums() {
+        Some(checksums) => checksums,
+        None => return,
+    };
+    let checksums = checksums.lock().unwrap();
+    let mut hashes: Vec<(&String, &u64)> = checksums.hashes.iter().collect();
+    hashes.sort_by(|&(a, _), &(b, _)| a.cmp(b));
+    let result = File::create(&checksums.path).and_then(|mut file| {
+        for &(name, hash) in hashes.iter() {
+            try!(write!(&mut file, "{} {:x}\n", name, hash))
+        }
+        Ok(())
+    });
+    if let Err(error) = result {
+        println!("couldn't write the tile checksums to {:?}: {}", checksums.path, error)
+    }
+}
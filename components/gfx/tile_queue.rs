@@ -0,0 +1,115 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The order in which the tiles of a layer are painted.
+//!
+//! The compositor asks for the tiles in view along with those in the margin around it that it
+//! paints ahead of time. The ones in view are painted first. Among each of the two, the tiles
+//! furthest along the direction that the viewport last moved in go first, since that is where
+//! content is about to come into view; the rest keep the order that they were asked for in.
+
+use geom::point::Point2D;
+use geom::rect::Rect;
+use layers::layers::BufferRequest;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::mem;
+
+/// A queue of the tiles of a layer that are still to be painted, highest priority first.
+pub struct TileQueue {
+    jobs: BinaryHeap<TileJob>,
+}
+
+impl TileQueue {
+    /// Creates a queue of the tiles. `viewport` is the part of the layer in view and
+    /// `scroll_direction` the direction that it last moved in, both in the page coordinates that
+    /// the tiles' page rects are in.
+    pub fn new(tiles: Vec<BufferRequest>, viewport: &Rect<f32>, scroll_direction: &Point2D<f32>)
+               -> TileQueue {
+        let viewport_center = center(viewport);
+        let jobs = tiles.into_iter().enumerate().map(|(sequence, tile)| {
+            let offset = center(&tile.page_rect) - viewport_center;
+            TileJob {
+                visible: tile.page_rect.intersects(viewport),
+                lead: offset.x * scroll_direction.x + offset.y * scroll_direction.y,
+                sequence: sequence,
+                tile: tile,
+            }
+        }).collect();
+        TileQueue {
+            jobs: jobs,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Removes the tile with the highest priority.
+    pub fn pop(&mut self) -> Option<BufferRequest> {
+        self.jobs.pop().map(|job| job.tile)
+    }
+
+    /// Removes the tile with the highest priority along with the rest of the tiles in the same
+    /// row of the layer, in order from left to right, to be painted together as a strip.
+    pub fn pop_strip(&mut self) -> Option<Vec<BufferRequest>> {
+        let first = match self.jobs.pop() {
+            Some(job) => job,
+            None => return None,
+        };
+        let jobs = mem::replace(&mut self.jobs, BinaryHeap::new()).into_vec();
+        let (mut strip, rest): (Vec<TileJob>, Vec<TileJob>) =
+            jobs.into_iter().partition(|job| {
+                job.tile.screen_rect.origin.y == first.tile.screen_rect.origin.y &&
+                    job.tile.screen_rect.size.height == first.tile.screen_rect.size.height
+            });
+        self.jobs.extend(rest.into_iter());
+
+        strip.push(first);
+        strip.sort_by(|a, b| a.tile.screen_rect.origin.x.cmp(&b.tile.screen_rect.origin.x));
+        Some(strip.into_iter().map(|job| job.tile).collect())
+    }
+}
+
+struct TileJob {
+    /// Whether any of the tile is in view.
+    visible: bool,
+    /// How far the tile is along the direction that the viewport last moved in.
+    lead: f32,
+    /// The position of the tile among those asked for.
+    sequence: usize,
+    tile: BufferRequest,
+}
+
+impl Ord for TileJob {
+    fn cmp(&self, other: &TileJob) -> Ordering {
+        match self.visible.cmp(&other.visible) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+        match self.lead.partial_cmp(&other.lead) {
+            Some(Ordering::Equal) | None => {}
+            Some(ordering) => return ordering,
+        }
+        other.sequence.cmp(&self.sequence)
+    }
+}
+
+impl PartialOrd for TileJob {
+    fn partial_cmp(&self, other: &TileJob) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TileJob {
+    fn eq(&self, other: &TileJob) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TileJob {}
+
+fn center(rect: &Rect<f32>) -> Point2D<f32> {
+    Point2D(rect.origin.x + rect.size.width * 0.5, rect.origin.y + rect.size.height * 0.5)
+}
@@ -27,6 +27,7 @@ use fragment::{Fragment, GeneratedContentInfo, IframeFragmentInfo};
 use fragment::CanvasFragmentInfo;
 use fragment::ImageFragmentInfo;
 use fragment::InlineAbsoluteHypotheticalFragmentInfo;
+use fragment::RubyAnnotationInfo;
 use fragment::TableColumnFragmentInfo;
 use fragment::UnscannedTextFragmentInfo;
 use fragment::{InlineBlockFragmentInfo, SpecificFragmentInfo};
@@ -53,10 +54,12 @@ use script::dom::htmlelement::HTMLElementTypeId;
 use script::dom::htmlobjectelement::is_image_data;
 use script::dom::node::NodeTypeId;
 use util::opts;
+use util::str::is_whitespace;
 use std::borrow::ToOwned;
 use std::collections::LinkedList;
 use std::mem;
 use std::sync::atomic::Ordering;
+use string_cache::Atom;
 use style::computed_values::content::ContentItem;
 use style::computed_values::{caption_side, display, empty_cells, float, list_style_position};
 use style::computed_values::{position};
@@ -677,12 +680,26 @@ impl<'a> FlowConstructor<'a> {
         let mut opt_inline_block_splits: LinkedList<InlineBlockSplit> = LinkedList::new();
         let mut fragment_accumulator = InlineFragmentsAccumulator::from_inline_node(node);
         let mut abs_descendants = Descendants::new();
+        let is_ruby = is_html_element_named(node, &atom!("ruby"));
 
         // Concatenate all the fragments of our kids, creating {ib} splits as necessary.
         for kid in node.children() {
             if kid.get_pseudo_element_type() != PseudoElementType::Normal {
                 self.process(&kid);
             }
+
+            // The `<rt>` children of a `<ruby>` element annotate the base text before them instead
+            // of being laid out inline, and its `<rp>` fallback parentheses are not shown.
+            if is_ruby && is_html_element_named(&kid, &atom!("rt")) {
+                let annotation_result = kid.swap_out_construction_result();
+                self.add_ruby_annotation(&mut fragment_accumulator, annotation_result);
+                continue
+            }
+            if is_ruby && is_html_element_named(&kid, &atom!("rp")) {
+                kid.swap_out_construction_result();
+                continue
+            }
+
             match kid.swap_out_construction_result() {
                 ConstructionResult::None => {}
                 ConstructionResult::Flow(flow, kid_abs_descendants) => {
@@ -762,6 +779,55 @@ impl<'a> FlowConstructor<'a> {
         }
     }
 
+    /// Scans the text of an `<rt>` element into a ruby annotation of the last text in the given
+    /// accumulator that isn't whitespace, which is the base text of the annotation. Only the text
+    /// of the first run of the annotation is kept, which is all of it unless it mixes styles or
+    /// fonts.
+    fn add_ruby_annotation(&mut self,
+                           fragment_accumulator: &mut InlineFragmentsAccumulator,
+                           annotation_result: ConstructionResult) {
+        let mut text_fragments = LinkedList::new();
+        if let ConstructionResult::ConstructionItem(ConstructionItem::InlineFragments(
+                InlineFragmentsConstructionResult {
+                    fragments,
+                    ..
+                })) = annotation_result {
+            for fragment in fragments.into_iter() {
+                if let SpecificFragmentInfo::UnscannedText(_) = fragment.specific {
+                    text_fragments.push_back(fragment)
+                }
+            }
+        }
+
+        let scanned_fragments = TextRunScanner::new().scan_for_runs(
+            self.layout_context.font_context(),
+            text_fragments);
+        let annotation = match scanned_fragments.fragments.first() {
+            Some(fragment) => {
+                match fragment.specific {
+                    SpecificFragmentInfo::ScannedText(ref info) => {
+                        RubyAnnotationInfo {
+                            run: info.run.clone(),
+                            range: info.range,
+                            style: fragment.style.clone(),
+                        }
+                    }
+                    _ => return,
+                }
+            }
+            None => return,
+        };
+
+        for fragment in fragment_accumulator.fragments.iter_mut().rev() {
+            if let SpecificFragmentInfo::UnscannedText(ref mut info) = fragment.specific {
+                if !is_whitespace(&*info.text) {
+                    info.ruby_annotation = Some(annotation);
+                    return
+                }
+            }
+        }
+    }
+
     /// Creates an `InlineFragmentsConstructionResult` for replaced content. Replaced content
     /// doesn't render its children, so this just nukes a child's fragments and creates a
     /// `Fragment`.
@@ -1530,3 +1596,11 @@ pub fn strip_ignorable_whitespace_from_end(this: &mut LinkedList<Fragment>) {
     }
 }
 
+
+/// Returns true if the given node is the HTML element with the given local name. This finds the
+/// elements of ruby markup, which have no element type of their own.
+fn is_html_element_named(node: &ThreadSafeLayoutNode, local_name: &Atom) -> bool {
+    node.type_id() == Some(NodeTypeId::Element(ElementTypeId::HTMLElement(
+                HTMLElementTypeId::HTMLElement))) &&
+        node.as_element().get_local_name() == local_name
+}
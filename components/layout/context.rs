@@ -13,7 +13,7 @@ use gfx::display_list::OpaqueNode;
 use gfx::font_cache_task::FontCacheTask;
 use gfx::font_context::FontContext;
 use msg::constellation_msg::ConstellationChan;
-use net_traits::image::base::Image;
+use net_traits::image::base::{Image, LazyImage};
 use net_traits::image_cache_task::{ImageCacheChan, ImageCacheTask, ImageState};
 use script::layout_interface::{Animation, LayoutChan, ReflowGoal};
 use std::boxed;
@@ -65,6 +65,10 @@ pub struct SharedLayoutContext {
     /// The current screen size.
     pub screen_size: Size2D<Au>,
 
+    /// How many device pixels a px covers at the page zoom. The pinch zoom isn't included, since
+    /// it doesn't change layout.
+    pub device_pixels_per_px: f32,
+
     /// Screen sized changed?
     pub screen_size_changed: bool,
 
@@ -184,4 +188,16 @@ impl<'a> LayoutContext<'a> {
             }
         }
     }
+
+    /// Like `get_or_request_image()`, but returns the image without decoding it, so that it is
+    /// only decoded if it is painted.
+    pub fn get_or_request_lazy_image(&self, url: Url) -> Option<LazyImage> {
+        match self.shared.image_cache_task.get_lazy_image_if_available(url.clone()) {
+            Ok(image) => Some(image),
+            // Request the image, or wait for it if we are emitting an output file.
+            Err(_) => self.get_or_request_image(url.clone()).and_then(|_| {
+                self.shared.image_cache_task.get_lazy_image_if_available(url).ok()
+            }),
+        }
+    }
 }
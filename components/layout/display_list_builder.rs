@@ -22,20 +22,29 @@ use list_item::ListItemFlow;
 use model::{self, MaybeAuto, ToGfxMatrix};
 use table_cell::CollapsedBordersForCell;
 
-use geom::{Matrix2D, Point2D, Rect, Size2D, SideOffsets2D};
+use geom::{Point2D, Rect, Size2D, SideOffsets2D};
+use geom::matrix;
 use gfx::color;
 use gfx::display_list::{BLUR_INFLATION_FACTOR, BaseDisplayItem, BorderDisplayItem};
 use gfx::display_list::{BorderRadii, BoxShadowClipMode, BoxShadowDisplayItem, ClippingRegion};
-use gfx::display_list::{DisplayItem, DisplayList, DisplayItemMetadata};
-use gfx::display_list::{GradientDisplayItem};
-use gfx::display_list::{GradientStop, ImageDisplayItem, LineDisplayItem};
-use gfx::display_list::{OpaqueNode, SolidColorDisplayItem};
-use gfx::display_list::{StackingContext, TextDisplayItem, TextOrientation};
+use gfx::display_list::{DisplayItem, DisplayItemMetadata, DisplayList, DisplayListSection};
+use gfx::display_list::{EmphasisMarks, GradientDisplayItem, IframeDisplayItem};
+use gfx::display_list::{GradientStop, ImageAnimation, ImageDisplayItem, ImageOrientation};
+use gfx::display_list::{ImageRepeat, ImageSource};
+use gfx::display_list::{LineDisplayItem};
+use gfx::display_list::{OpaqueNode, RubyAnnotation, RubyPosition, SolidColorDisplayItem};
+use gfx::display_list::{StackingContext, TextDecoration, TextDecorationLine};
+use gfx::display_list::{TextDecorationStyle, TextDisplayItem, TextOrientation, TextShadow};
+use gfx::display_list::transform3d;
+use gfx::image_generator::GeneratedImage;
 use gfx::paint_task::{PaintLayer, THREAD_TINT_COLORS};
+use gfx::text::glyph::CharIndex;
 use msg::compositor_msg::ScrollPolicy;
 use msg::constellation_msg::ConstellationChan;
 use msg::constellation_msg::Msg as ConstellationMsg;
-use png::{self, PixelsByColorType};
+use net_traits::image::base::Image as DecodedImage;
+use net_traits::image::base::LazyImage;
+use png::PixelsByColorType;
 use std::cmp;
 use std::default::Default;
 use std::iter::repeat;
@@ -46,17 +55,21 @@ use std::sync::mpsc::channel;
 use style::computed_values::filter::Filter;
 use style::computed_values::transform::ComputedMatrix;
 use style::computed_values::{background_attachment, background_repeat, background_size};
-use style::computed_values::{border_style, image_rendering, overflow_x, position, visibility};
+use style::computed_values::{border_style, overflow_x, position, ruby_position};
+use style::computed_values::text_decoration_skip_ink;
+use style::computed_values::{text_decoration_style, transform_style, visibility};
 use style::properties::ComputedValues;
 use style::properties::style_structs::Border;
 use style::values::RGBA;
 use style::values::computed::{Image, LinearGradient, LengthOrPercentage, LengthOrPercentageOrAuto};
+use style::values::computed::PaintImage;
 use style::values::specified::{AngleOrCorner, HorizontalDirection, VerticalDirection};
 use url::Url;
 use util::cursor::Cursor;
-use util::geometry::{self, Au, ZERO_POINT, to_px, to_frac_px};
+use util::geometry::{self, Au, to_px, to_frac_px};
 use util::logical_geometry::{LogicalPoint, LogicalRect, LogicalSize, WritingMode};
 use util::opts;
+use util::range::Range;
 
 /// The results of display list building for a single flow.
 pub enum DisplayListBuildingResult {
@@ -73,7 +86,7 @@ impl DisplayListBuildingResult {
         match *self {
             DisplayListBuildingResult::None => return,
             DisplayListBuildingResult::StackingContext(ref mut stacking_context) => {
-                display_list.children.push_back((*stacking_context).clone())
+                display_list.children.push((*stacking_context).clone())
             }
             DisplayListBuildingResult::Normal(ref mut source_display_list) => {
                 display_list.append_from(&mut **source_display_list)
@@ -98,7 +111,7 @@ pub trait FragmentDisplayListBuilding {
     fn compute_background_image_size(&self,
                                      style: &ComputedValues,
                                      bounds: &Rect<Au>,
-                                     image: &png::Image)
+                                     image: &LazyImage)
                                      -> Size2D<Au>;
 
     /// Adds the display items necessary to paint the background image of this fragment to the
@@ -122,6 +135,16 @@ pub trait FragmentDisplayListBuilding {
                                                          gradient: &LinearGradient,
                                                          style: &ComputedValues);
 
+    /// Adds the display item that draws the `paint()` background image of this fragment with its
+    /// generator to the display list at the appropriate stacking level.
+    fn build_display_list_for_background_paint_image(&self,
+                                                     display_list: &mut DisplayList,
+                                                     level: StackingLevel,
+                                                     absolute_bounds: &Rect<Au>,
+                                                     clip: &ClippingRegion,
+                                                     paint_image: &PaintImage,
+                                                     style: &ComputedValues);
+
     /// Adds the display items necessary to paint the borders of this fragment to a display list if
     /// necessary.
     fn build_display_list_for_borders_if_applicable(
@@ -208,27 +231,16 @@ pub trait FragmentDisplayListBuilding {
                                       stacking_relative_border_box: &Rect<Au>)
                                       -> ClippingRegion;
 
-    /// Creates the text display item for one text fragment. This can be called multiple times for
-    /// one fragment if there are text shadows.
-    ///
-    /// `shadow_blur_radius` will be `Some` if this is a shadow, even if the blur radius is zero.
+    /// Creates the text display item for one text fragment, which paints its text shadows and text
+    /// decorations too.
     fn build_display_list_for_text_fragment(&self,
                                             display_list: &mut DisplayList,
                                             text_fragment: &ScannedTextFragmentInfo,
                                             text_color: RGBA,
                                             stacking_relative_content_box: &Rect<Au>,
-                                            shadow_blur_radius: Option<Au>,
-                                            offset: &Point2D<Au>,
+                                            shadows: Vec<TextShadow>,
                                             clip: &ClippingRegion);
 
-    /// Creates the display item for a text decoration: underline, overline, or line-through.
-    fn build_display_list_for_text_decoration(&self,
-                                              display_list: &mut DisplayList,
-                                              color: &RGBA,
-                                              stacking_relative_box: &LogicalRect<Au>,
-                                              clip: &ClippingRegion,
-                                              blur_radius: Au);
-
     /// A helper method that `build_display_list` calls to create per-fragment-type display items.
     fn build_fragment_type_specific_display_items(&mut self,
                                                   display_list: &mut DisplayList,
@@ -239,7 +251,8 @@ pub trait FragmentDisplayListBuilding {
     fn create_stacking_context(&self,
                                base_flow: &BaseFlow,
                                display_list: Box<DisplayList>,
-                               layer: Option<Arc<PaintLayer>>)
+                               layer: Option<Arc<PaintLayer>>,
+                               layout_context: &LayoutContext)
                                -> Arc<StackingContext>;
 
 }
@@ -343,13 +356,21 @@ impl FragmentDisplayListBuilding for Fragment {
                                                              &clip,
                                                              image_url)
             }
+            Some(Image::Paint(ref paint_image)) => {
+                self.build_display_list_for_background_paint_image(display_list,
+                                                                   level,
+                                                                   absolute_bounds,
+                                                                   &clip,
+                                                                   paint_image,
+                                                                   style)
+            }
         }
     }
 
     fn compute_background_image_size(&self,
                                      style: &ComputedValues,
                                      bounds: &Rect<Au>,
-                                     image: &png::Image)
+                                     image: &LazyImage)
                                      -> Size2D<Au> {
         // If `image_aspect_ratio` < `bounds_aspect_ratio`, the image is tall; otherwise, it is
         // wide.
@@ -408,13 +429,13 @@ impl FragmentDisplayListBuilding for Fragment {
                                                clip: &ClippingRegion,
                                                image_url: &Url) {
         let background = style.get_background();
-        let image = layout_context.get_or_request_image(image_url.clone());
+        let image = layout_context.get_or_request_lazy_image(image_url.clone());
         if let Some(image) = image {
             debug!("(building display list) building background image");
 
             // Use `background-size` to get the size.
             let mut bounds = *absolute_bounds;
-            let image_size = self.compute_background_image_size(style, &bounds, &*image);
+            let image_size = self.compute_background_image_size(style, &bounds, &image);
 
             // Clip.
             //
@@ -427,6 +448,9 @@ impl FragmentDisplayListBuilding for Fragment {
                     (absolute_bounds.origin.x, absolute_bounds.origin.y)
                 }
                 background_attachment::T::fixed => {
+                    // The layout task moves the root background into a layer that doesn't
+                    // scroll. FIXME: Other fixed backgrounds are repainted on every scroll,
+                    // since compositor layers can't be clipped to a scrolling box yet.
                     (Au(0), Au(0))
                 }
             };
@@ -441,12 +465,13 @@ impl FragmentDisplayListBuilding for Fragment {
             let abs_y = virtual_origin_y + vertical_position;
 
             // Adjust origin and size based on background-repeat
-            match background.background_repeat {
+            let (horizontal_repeat, vertical_repeat) = match background.background_repeat {
                 background_repeat::T::no_repeat => {
                     bounds.origin.x = abs_x;
                     bounds.origin.y = abs_y;
                     bounds.size.width = image_size.width;
                     bounds.size.height = image_size.height;
+                    (ImageRepeat::NoRepeat, ImageRepeat::NoRepeat)
                 }
                 background_repeat::T::repeat_x => {
                     bounds.origin.y = abs_y;
@@ -455,6 +480,7 @@ impl FragmentDisplayListBuilding for Fragment {
                                                   &mut bounds.size.width,
                                                   abs_x,
                                                   image_size.width.to_nearest_px() as u32);
+                    (ImageRepeat::Repeat, ImageRepeat::NoRepeat)
                 }
                 background_repeat::T::repeat_y => {
                     bounds.origin.x = abs_x;
@@ -463,6 +489,7 @@ impl FragmentDisplayListBuilding for Fragment {
                                                   &mut bounds.size.height,
                                                   abs_y,
                                                   image_size.height.to_nearest_px() as u32);
+                    (ImageRepeat::NoRepeat, ImageRepeat::Repeat)
                 }
                 background_repeat::T::repeat => {
                     ImageFragmentInfo::tile_image(&mut bounds.origin.x,
@@ -473,6 +500,35 @@ impl FragmentDisplayListBuilding for Fragment {
                                                   &mut bounds.size.height,
                                                   abs_y,
                                                   image_size.height.to_nearest_px() as u32);
+                    (ImageRepeat::Repeat, ImageRepeat::Repeat)
+                }
+                background_repeat::T::space => {
+                    // The tiles are spread over the whole background positioning area, unless
+                    // fewer than two fit, in which case one image is placed at the background
+                    // position.
+                    let horizontal_repeat = if bounds.size.width < image_size.width * 2 {
+                        bounds.origin.x = abs_x;
+                        bounds.size.width = image_size.width;
+                        ImageRepeat::NoRepeat
+                    } else {
+                        ImageRepeat::Space
+                    };
+                    let vertical_repeat = if bounds.size.height < image_size.height * 2 {
+                        bounds.origin.y = abs_y;
+                        bounds.size.height = image_size.height;
+                        ImageRepeat::NoRepeat
+                    } else {
+                        ImageRepeat::Space
+                    };
+                    (horizontal_repeat, vertical_repeat)
+                }
+                background_repeat::T::round => {
+                    // The tiles are rescaled to fit the background positioning area, so the
+                    // background position has no effect.
+                    //
+                    // TODO: Keep the aspect ratio of the image when `background-size` is `auto`
+                    // in one dimension, as CSS-BACKGROUNDS-3 § 3.4 requires.
+                    (ImageRepeat::Round, ImageRepeat::Round)
                 }
             };
 
@@ -483,13 +539,52 @@ impl FragmentDisplayListBuilding for Fragment {
                                                                     style,
                                                                     Cursor::DefaultCursor),
                                            clip),
-                image: image.clone(),
+                image: ImageSource::Lazy(image.clone()),
+                source_rect: None,
                 stretch_size: Size2D(image_size.width, image_size.height),
+                horizontal_repeat: horizontal_repeat,
+                vertical_repeat: vertical_repeat,
                 image_rendering: style.get_effects().image_rendering.clone(),
+                animation: image.animation().as_ref().map(ImageAnimation::from_frames),
+                orientation: ImageOrientation::identity(),
+                is_opaque: image.is_opaque,
             }), level);
         }
     }
 
+    fn build_display_list_for_background_paint_image(&self,
+                                                     display_list: &mut DisplayList,
+                                                     level: StackingLevel,
+                                                     absolute_bounds: &Rect<Au>,
+                                                     clip: &ClippingRegion,
+                                                     paint_image: &PaintImage,
+                                                     style: &ComputedValues) {
+        // Generated images have no size of their own, so, like gradients, they are drawn over the
+        // whole background positioning area, at the size at which it is shown.
+        //
+        // TODO: Size and place them with `background-size` and `background-position`.
+        let clip = clip.clone().intersect_rect(absolute_bounds);
+        display_list.push(DisplayItem::ImageClass(box ImageDisplayItem {
+            base: BaseDisplayItem::new(*absolute_bounds,
+                                       DisplayItemMetadata::new(self.node,
+                                                                style,
+                                                                Cursor::DefaultCursor),
+                                       clip),
+            image: ImageSource::Generated(GeneratedImage {
+                name: paint_image.name.clone(),
+                arguments: paint_image.arguments.clone(),
+            }),
+            source_rect: None,
+            stretch_size: absolute_bounds.size,
+            horizontal_repeat: ImageRepeat::NoRepeat,
+            vertical_repeat: ImageRepeat::NoRepeat,
+            image_rendering: style.get_effects().image_rendering.clone(),
+            animation: None,
+            orientation: ImageOrientation::identity(),
+            is_opaque: false,
+        }), level);
+    }
+
     fn build_display_list_for_background_linear_gradient(&self,
                                                          display_list: &mut DisplayList,
                                                          level: StackingLevel,
@@ -597,6 +692,7 @@ impl FragmentDisplayListBuilding for Fragment {
             start_point: center - delta,
             end_point: center + delta,
             stops: stops,
+            repeating: false,
         });
 
         display_list.push(gradient_display_item, level)
@@ -725,7 +821,8 @@ impl FragmentDisplayListBuilding for Fragment {
 
         // Append the outline to the display list.
         let color = style.resolve_color(style.get_outline().outline_color).to_gfx_color();
-        display_list.outlines.push_back(DisplayItem::BorderClass(box BorderDisplayItem {
+        let outlines = display_list.get_mut(DisplayListSection::Outlines);
+        outlines.push(DisplayItem::BorderClass(box BorderDisplayItem {
             base: BaseDisplayItem::new(bounds,
                                        DisplayItemMetadata::new(self.node,
                                                                 style,
@@ -749,7 +846,7 @@ impl FragmentDisplayListBuilding for Fragment {
         let container_size = Size2D::zero();
 
         // Compute the text fragment bounds and draw a border surrounding them.
-        display_list.content.push_back(DisplayItem::BorderClass(box BorderDisplayItem {
+        display_list.push(DisplayItem::BorderClass(box BorderDisplayItem {
             base: BaseDisplayItem::new(*stacking_relative_border_box,
                                        DisplayItemMetadata::new(self.node,
                                                                 style,
@@ -759,7 +856,7 @@ impl FragmentDisplayListBuilding for Fragment {
             color: SideOffsets2D::new_all_same(color::rgb(0, 0, 200)),
             style: SideOffsets2D::new_all_same(border_style::T::solid),
             radius: Default::default(),
-        }));
+        }), StackingLevel::Content);
 
         // Draw a rectangle representing the baselines.
         let mut baseline = LogicalRect::from_physical(self.style.writing_mode,
@@ -776,7 +873,7 @@ impl FragmentDisplayListBuilding for Fragment {
             color: color::rgb(0, 200, 0),
             style: border_style::T::dashed,
         };
-        display_list.content.push_back(DisplayItem::LineClass(line_display_item));
+        display_list.push(DisplayItem::LineClass(line_display_item), StackingLevel::Content);
     }
 
     fn build_debug_borders_around_fragment(&self,
@@ -784,7 +881,7 @@ impl FragmentDisplayListBuilding for Fragment {
                                            stacking_relative_border_box: &Rect<Au>,
                                            clip: &ClippingRegion) {
         // This prints a debug border around the border of this fragment.
-        display_list.content.push_back(DisplayItem::BorderClass(box BorderDisplayItem {
+        display_list.push(DisplayItem::BorderClass(box BorderDisplayItem {
             base: BaseDisplayItem::new(*stacking_relative_border_box,
                                        DisplayItemMetadata::new(self.node,
                                                                 &*self.style,
@@ -794,7 +891,7 @@ impl FragmentDisplayListBuilding for Fragment {
             color: SideOffsets2D::new_all_same(color::rgb(0, 0, 200)),
             style: SideOffsets2D::new_all_same(border_style::T::solid),
             radius: Default::default(),
-        }));
+        }), StackingLevel::Content);
     }
 
     fn calculate_style_specified_clip(&self,
@@ -960,30 +1057,24 @@ impl FragmentDisplayListBuilding for Fragment {
 
         match self.specific {
             SpecificFragmentInfo::ScannedText(ref text_fragment) => {
-                // Create items for shadows.
+                // Create the text display item, which paints the shadows of the text itself.
                 //
                 // NB: According to CSS-BACKGROUNDS, text shadows render in *reverse* order (front
-                // to back).
+                // to back), which the display item does.
                 let text_color = self.style().get_color().color;
-                for text_shadow in self.style.get_effects().text_shadow.0.iter().rev() {
-                    let offset = &Point2D(text_shadow.offset_x, text_shadow.offset_y);
-                    let color = self.style().resolve_color(text_shadow.color);
-                    self.build_display_list_for_text_fragment(display_list,
-                                                              &**text_fragment,
-                                                              color,
-                                                              &stacking_relative_content_box,
-                                                              Some(text_shadow.blur_radius),
-                                                              offset,
-                                                              clip);
-                }
-
-                // Create the main text display item.
+                let text_shadows = &self.style.get_effects().text_shadow.0;
+                let shadows = text_shadows.iter().map(|text_shadow| {
+                    TextShadow {
+                        offset: Point2D(text_shadow.offset_x, text_shadow.offset_y),
+                        blur_radius: text_shadow.blur_radius,
+                        color: self.style().resolve_color(text_shadow.color).to_gfx_color(),
+                    }
+                }).collect();
                 self.build_display_list_for_text_fragment(display_list,
                                                           &**text_fragment,
                                                           text_color,
                                                           &stacking_relative_content_box,
-                                                          None,
-                                                          &Point2D(Au(0), Au(0)),
+                                                          shadows,
                                                           clip);
 
                 if opts::get().show_debug_fragment_borders {
@@ -995,9 +1086,26 @@ impl FragmentDisplayListBuilding for Fragment {
                                                                    clip)
                 }
             }
+            SpecificFragmentInfo::Iframe(ref iframe_fragment) => {
+                // The compositor shows the document in the iframe over its content box.
+                display_list.push(DisplayItem::IframeClass(box IframeDisplayItem {
+                    base: BaseDisplayItem::new(stacking_relative_content_box,
+                                               DisplayItemMetadata::new(self.node,
+                                                                        &*self.style,
+                                                                        Cursor::DefaultCursor),
+                                               (*clip).clone()),
+                    pipeline_id: iframe_fragment.pipeline_id,
+                    subpage_id: iframe_fragment.subpage_id,
+                }), StackingLevel::Content);
+
+                if opts::get().show_debug_fragment_borders {
+                    self.build_debug_borders_around_fragment(display_list,
+                                                             stacking_relative_border_box,
+                                                             clip);
+                }
+            }
             SpecificFragmentInfo::Generic |
             SpecificFragmentInfo::GeneratedContent(..) |
-            SpecificFragmentInfo::Iframe(..) |
             SpecificFragmentInfo::Table |
             SpecificFragmentInfo::TableCell |
             SpecificFragmentInfo::TableRow |
@@ -1013,16 +1121,24 @@ impl FragmentDisplayListBuilding for Fragment {
             SpecificFragmentInfo::Image(ref mut image_fragment) => {
                 // Place the image into the display list.
                 if let Some(ref image) = image_fragment.image {
-                    display_list.content.push_back(DisplayItem::ImageClass(box ImageDisplayItem {
+                    display_list.push(DisplayItem::ImageClass(box ImageDisplayItem {
                         base: BaseDisplayItem::new(stacking_relative_content_box,
                                                    DisplayItemMetadata::new(self.node,
                                                                             &*self.style,
                                                                             Cursor::DefaultCursor),
                                                    (*clip).clone()),
-                        image: image.clone(),
+                        // TODO: Use `ImageSource::Lazy`, as backgrounds do, once image elements
+                        // and their fragments stop holding the decoded image.
+                        image: ImageSource::Decoded(image.clone()),
+                        source_rect: None,
                         stretch_size: stacking_relative_content_box.size,
+                        horizontal_repeat: ImageRepeat::NoRepeat,
+                        vertical_repeat: ImageRepeat::NoRepeat,
                         image_rendering: self.style.get_effects().image_rendering.clone(),
-                    }));
+                        animation: ImageAnimation::from_image(&*image),
+                        orientation: image_fragment.orientation,
+                        is_opaque: image.is_opaque,
+                    }), StackingLevel::Content);
                 }
             }
             SpecificFragmentInfo::Canvas(ref canvas_fragment_info) => {
@@ -1040,22 +1156,29 @@ impl FragmentDisplayListBuilding for Fragment {
                     None => repeat(0xFFu8).take(width * height * 4).collect(),
                 };
 
+                let canvas_image = DecodedImage::new(width as u32,
+                                                     height as u32,
+                                                     PixelsByColorType::RGBA8(canvas_data));
+                let canvas_is_opaque = canvas_image.is_opaque;
                 let canvas_display_item = box ImageDisplayItem {
                     base: BaseDisplayItem::new(stacking_relative_content_box,
                                                DisplayItemMetadata::new(self.node,
                                                                             &*self.style,
                                                                             Cursor::DefaultCursor),
                                                (*clip).clone()),
-                    image: Arc::new(png::Image {
-                        width: width as u32,
-                        height: height as u32,
-                        pixels: PixelsByColorType::RGBA8(canvas_data),
-                    }),
+                    image: ImageSource::Decoded(Arc::new(canvas_image)),
+                    source_rect: None,
                     stretch_size: stacking_relative_content_box.size,
-                    image_rendering: image_rendering::T::Auto,
+                    horizontal_repeat: ImageRepeat::NoRepeat,
+                    vertical_repeat: ImageRepeat::NoRepeat,
+                    image_rendering: self.style.get_effects().image_rendering.clone(),
+                    animation: None,
+                    orientation: ImageOrientation::identity(),
+                    is_opaque: canvas_is_opaque,
                 };
 
-                display_list.content.push_back(DisplayItem::ImageClass(canvas_display_item));
+                display_list.push(DisplayItem::ImageClass(canvas_display_item),
+                                  StackingLevel::Content);
             }
             SpecificFragmentInfo::UnscannedText(_) => {
                 panic!("Shouldn't see unscanned fragments here.")
@@ -1069,7 +1192,8 @@ impl FragmentDisplayListBuilding for Fragment {
     fn create_stacking_context(&self,
                                base_flow: &BaseFlow,
                                display_list: Box<DisplayList>,
-                               layer: Option<Arc<PaintLayer>>)
+                               layer: Option<Arc<PaintLayer>>,
+                               layout_context: &LayoutContext)
                                -> Arc<StackingContext> {
 
         let border_box = self.stacking_relative_border_box(&base_flow.stacking_relative_position,
@@ -1088,8 +1212,22 @@ impl FragmentDisplayListBuilding for Fragment {
         let transform = self.style().get_effects().transform
             .unwrap_or(ComputedMatrix::identity()).to_gfx_matrix(&border_box.size);
 
-        let transform = Matrix2D::identity().translate(transform_origin.x, transform_origin.y)
-            .mul(&transform).translate(-transform_origin.x, -transform_origin.y);
+        let transform = matrix::identity().translate(transform_origin.x, transform_origin.y, 0.0)
+            .mul(&transform).translate(-transform_origin.x, -transform_origin.y, 0.0);
+
+        // TODO: Support `perspective-origin`. Its initial value, the centre, is used for now.
+        let perspective = match self.style().get_effects().perspective {
+            Some(distance) => {
+                // Perspective of zero or less than a pixel is as strong as that of a pixel.
+                let distance = cmp::max(distance, Au::from_px(1));
+                let origin = Point2D(border_box.size.width.to_frac32_px() / 2.0,
+                                     border_box.size.height.to_frac32_px() / 2.0);
+                transform3d::perspective(distance.to_frac32_px(), &origin)
+            }
+            None => matrix::identity(),
+        };
+        let preserves_3d =
+            self.style().get_effects().transform_style == transform_style::T::preserve_3d;
 
         // FIXME(pcwalton): Is this vertical-writing-direction-safe?
         let margin = self.margin.to_physical(base_flow.writing_mode);
@@ -1107,9 +1245,12 @@ impl FragmentDisplayListBuilding for Fragment {
                                       &overflow,
                                       self.style().get_box().z_index.number_or_zero(),
                                       &transform,
+                                      &perspective,
+                                      preserves_3d,
                                       filters,
                                       self.style().get_effects().mix_blend_mode,
-                                      layer))
+                                      layer,
+                                      layout_context.shared.device_pixels_per_px))
     }
 
     #[inline(never)]
@@ -1181,8 +1322,7 @@ impl FragmentDisplayListBuilding for Fragment {
                                             text_fragment: &ScannedTextFragmentInfo,
                                             text_color: RGBA,
                                             stacking_relative_content_box: &Rect<Au>,
-                                            shadow_blur_radius: Option<Au>,
-                                            offset: &Point2D<Au>,
+                                            shadows: Vec<TextShadow>,
                                             clip: &ClippingRegion) {
         // Determine the orientation and cursor to use.
         let (orientation, cursor) = if self.style.writing_mode.is_vertical() {
@@ -1200,16 +1340,76 @@ impl FragmentDisplayListBuilding for Fragment {
         // FIXME(pcwalton): Get the real container size.
         let container_size = Size2D::zero();
         let metrics = &text_fragment.run.font_metrics;
-        let stacking_relative_content_box = stacking_relative_content_box.translate(offset);
         let baseline_origin = stacking_relative_content_box.origin +
             LogicalPoint::new(self.style.writing_mode,
                               Au(0),
                               metrics.ascent).to_physical(self.style.writing_mode,
                                                           container_size);
 
-        // Create the text display item.
-        display_list.content.push_back(DisplayItem::TextClass(box TextDisplayItem {
-            base: BaseDisplayItem::new(stacking_relative_content_box,
+        // Find the lines that decorate the text.
+        let text_decorations = self.style()
+                                   .get_inheritedtext()
+                                   ._servo_text_decorations_in_effect;
+        let skip_ink = self.style().get_inheritedtext().text_decoration_skip_ink ==
+            text_decoration_skip_ink::T::auto;
+        let mut decorations = Vec::new();
+        for &(line, decoration) in [
+            (TextDecorationLine::Underline, text_decorations.underline),
+            (TextDecorationLine::Overline, text_decorations.overline),
+            (TextDecorationLine::LineThrough, text_decorations.line_through),
+        ].iter() {
+            if let Some(decoration) = decoration {
+                decorations.push(TextDecoration {
+                    line: line,
+                    style: match decoration.style {
+                        text_decoration_style::T::solid => TextDecorationStyle::Solid,
+                        text_decoration_style::T::double => TextDecorationStyle::Double,
+                        text_decoration_style::T::dotted => TextDecorationStyle::Dotted,
+                        text_decoration_style::T::dashed => TextDecorationStyle::Dashed,
+                        text_decoration_style::T::wavy => TextDecorationStyle::Wavy,
+                    },
+                    color: decoration.color.to_gfx_color(),
+                    skip_ink: skip_ink,
+                })
+            }
+        }
+
+        // Place the ruby annotation of the text, if any, on the side that its style says.
+        let ruby_annotation = text_fragment.ruby_annotation.as_ref().map(|annotation| {
+            RubyAnnotation {
+                text_run: annotation.run.clone(),
+                range: annotation.range,
+                text_color: annotation.style.get_color().color.to_gfx_color(),
+                position: match annotation.style.get_inheritedtext().ruby_position {
+                    ruby_position::T::over => RubyPosition::Over,
+                    ruby_position::T::under => RubyPosition::Under,
+                },
+            }
+        });
+
+        // Place the emphasis marks of the text, if any. In vertical text, marks on the right are
+        // on the line-over side.
+        let emphasis_marks = text_fragment.emphasis_mark.as_ref().map(|mark| {
+            let inherited_text = self.style().get_inheritedtext();
+            let emphasis_position = inherited_text.text_emphasis_position;
+            let is_over = if self.style().writing_mode.is_vertical() {
+                emphasis_position.right
+            } else {
+                emphasis_position.over
+            };
+            EmphasisMarks {
+                text_run: mark.clone(),
+                range: Range::new(CharIndex(0), CharIndex(mark.text.chars().count() as isize)),
+                text_color: self.style().resolve_color(inherited_text.text_emphasis_color)
+                                        .to_gfx_color(),
+                position: if is_over { RubyPosition::Over } else { RubyPosition::Under },
+            }
+        });
+
+        // Create the text display item. Its shadows, decorations, ruby annotation and emphasis
+        // marks paint outside of its bounds, which the ink bounds of the item take into account.
+        display_list.push(DisplayItem::TextClass(box TextDisplayItem {
+            base: BaseDisplayItem::new(*stacking_relative_content_box,
                                        DisplayItemMetadata::new(self.node, self.style(), cursor),
                                        (*clip).clone()),
             text_run: text_fragment.run.clone(),
@@ -1217,85 +1417,13 @@ impl FragmentDisplayListBuilding for Fragment {
             text_color: text_color.to_gfx_color(),
             orientation: orientation,
             baseline_origin: baseline_origin,
-            blur_radius: shadow_blur_radius.unwrap_or(Au(0)),
-        }));
-
-        // Create display items for text decorations.
-        let mut text_decorations = self.style()
-                                       .get_inheritedtext()
-                                       ._servo_text_decorations_in_effect;
-        if shadow_blur_radius.is_some() {
-            // If we're painting a shadow, paint the decorations the same color as the shadow.
-            text_decorations.underline = text_decorations.underline.map(|_| text_color);
-            text_decorations.overline = text_decorations.overline.map(|_| text_color);
-            text_decorations.line_through = text_decorations.line_through.map(|_| text_color);
-        }
-
-        let stacking_relative_content_box =
-            LogicalRect::from_physical(self.style.writing_mode,
-                                       stacking_relative_content_box,
-                                       container_size);
-        if let Some(ref underline_color) = text_decorations.underline {
-            let mut stacking_relative_box = stacking_relative_content_box;
-            stacking_relative_box.start.b = stacking_relative_content_box.start.b +
-                metrics.ascent - metrics.underline_offset;
-            stacking_relative_box.size.block = metrics.underline_size;
-            self.build_display_list_for_text_decoration(display_list,
-                                                        underline_color,
-                                                        &stacking_relative_box,
-                                                        clip,
-                                                        shadow_blur_radius.unwrap_or(Au(0)))
-        }
-
-        if let Some(ref overline_color) = text_decorations.overline {
-            let mut stacking_relative_box = stacking_relative_content_box;
-            stacking_relative_box.size.block = metrics.underline_size;
-            self.build_display_list_for_text_decoration(display_list,
-                                                        overline_color,
-                                                        &stacking_relative_box,
-                                                        clip,
-                                                        shadow_blur_radius.unwrap_or(Au(0)))
-        }
-
-        if let Some(ref line_through_color) = text_decorations.line_through {
-            let mut stacking_relative_box = stacking_relative_content_box;
-            stacking_relative_box.start.b = stacking_relative_box.start.b + metrics.ascent -
-                metrics.strikeout_offset;
-            stacking_relative_box.size.block = metrics.strikeout_size;
-            self.build_display_list_for_text_decoration(display_list,
-                                                        line_through_color,
-                                                        &stacking_relative_box,
-                                                        clip,
-                                                        shadow_blur_radius.unwrap_or(Au(0)))
-        }
-    }
+            blur_radius: Au(0),
+            shadows: shadows,
+            decorations: decorations,
+            ruby_annotation: ruby_annotation,
+            emphasis_marks: emphasis_marks,
+        }), StackingLevel::Content);
 
-    fn build_display_list_for_text_decoration(&self,
-                                              display_list: &mut DisplayList,
-                                              color: &RGBA,
-                                              stacking_relative_box: &LogicalRect<Au>,
-                                              clip: &ClippingRegion,
-                                              blur_radius: Au) {
-        // Perhaps surprisingly, text decorations are box shadows. This is because they may need
-        // to have blur in the case of `text-shadow`, and this doesn't hurt performance because box
-        // shadows are optimized into essentially solid colors if there is no need for the blur.
-        //
-        // FIXME(pcwalton, #2795): Get the real container size.
-        let container_size = Size2D::zero();
-        let stacking_relative_box = stacking_relative_box.to_physical(self.style.writing_mode,
-                                                                      container_size);
-        let metadata = DisplayItemMetadata::new(self.node, &*self.style, Cursor::DefaultCursor);
-        display_list.content.push_back(DisplayItem::BoxShadowClass(box BoxShadowDisplayItem {
-            base: BaseDisplayItem::new(shadow_bounds(&stacking_relative_box, blur_radius, Au(0)),
-                                       metadata,
-                                       (*clip).clone()),
-            box_bounds: stacking_relative_box,
-            color: color.to_gfx_color(),
-            offset: ZERO_POINT,
-            blur_radius: blur_radius,
-            spread_radius: Au(0),
-            clip_mode: BoxShadowClipMode::None,
-        }))
     }
 }
 
@@ -1366,7 +1494,11 @@ impl BlockFlowDisplayListBuilding for BlockFlow {
                                                background_border_level);
 
         self.base.display_list_building_result = if self.fragment.establishes_stacking_context() {
-            DisplayListBuildingResult::StackingContext(self.fragment.create_stacking_context(&self.base, display_list, None))
+            DisplayListBuildingResult::StackingContext(
+                self.fragment.create_stacking_context(&self.base,
+                                                      display_list,
+                                                      None,
+                                                      layout_context))
         } else {
             DisplayListBuildingResult::Normal(display_list)
         }
@@ -1386,8 +1518,11 @@ impl BlockFlowDisplayListBuilding for BlockFlow {
                 !self.base.flags.contains(NEEDS_LAYER) {
             // We didn't need a layer.
             self.base.display_list_building_result =
-                DisplayListBuildingResult::StackingContext(self.fragment
-                                                               .create_stacking_context(&self.base, display_list, None));
+                DisplayListBuildingResult::StackingContext(
+                    self.fragment.create_stacking_context(&self.base,
+                                                          display_list,
+                                                          None,
+                                                          layout_context));
             return
         }
 
@@ -1400,10 +1535,11 @@ impl BlockFlowDisplayListBuilding for BlockFlow {
 
 
         let transparent = color::transparent();
-        let stacking_context = self.fragment.create_stacking_context(&self.base, display_list,
-                                                                     Some(Arc::new(PaintLayer::new(self.layer_id(0),
-                                                                                                   transparent,
-                                                                                                   scroll_policy))));
+        let layer = Arc::new(PaintLayer::new(self.layer_id(0), transparent, scroll_policy));
+        let stacking_context = self.fragment.create_stacking_context(&self.base,
+                                                                     display_list,
+                                                                     Some(layer),
+                                                                     layout_context);
         self.base.display_list_building_result =
             DisplayListBuildingResult::StackingContext(stacking_context)
     }
@@ -1419,8 +1555,11 @@ impl BlockFlowDisplayListBuilding for BlockFlow {
         display_list.form_float_pseudo_stacking_context();
 
         self.base.display_list_building_result = if self.fragment.establishes_stacking_context() {
-            DisplayListBuildingResult::StackingContext(self.fragment
-                                                           .create_stacking_context(&self.base, display_list, None))
+            DisplayListBuildingResult::StackingContext(
+                self.fragment.create_stacking_context(&self.base,
+                                                      display_list,
+                                                      None,
+                                                      layout_context))
         } else {
             DisplayListBuildingResult::Normal(display_list)
         }
@@ -1500,7 +1639,11 @@ impl InlineFlowDisplayListBuilding for InlineFlow {
         // and remove the check for filter presence. Further details on #5812.
         if has_stacking_context && !self.fragments.fragments[0].style().get_effects().filter.is_empty() {
             self.base.display_list_building_result =
-                DisplayListBuildingResult::StackingContext(self.fragments.fragments[0].create_stacking_context(&self.base, display_list, None));
+                DisplayListBuildingResult::StackingContext(
+                    self.fragments.fragments[0].create_stacking_context(&self.base,
+                                                                        display_list,
+                                                                        None,
+                                                                        layout_context));
         } else {
             self.base.display_list_building_result = DisplayListBuildingResult::Normal(display_list);
         }
@@ -1573,6 +1716,7 @@ impl BaseFlowDisplayListBuilding for BaseFlow {
                                        DisplayItemMetadata {
                                            node: node,
                                            pointing: None,
+                                           tag: 0,
                                        },
                                        self.clip.clone()),
             border_widths: SideOffsets2D::new_all_same(Au::from_px(2)),
@@ -1645,15 +1789,14 @@ trait StackingContextConstruction {
 
 impl StackingContextConstruction for DisplayList {
     fn push(&mut self, display_item: DisplayItem, level: StackingLevel) {
-        match level {
-            StackingLevel::BackgroundAndBorders => {
-                self.background_and_borders.push_back(display_item)
-            }
+        let section = match level {
+            StackingLevel::BackgroundAndBorders => DisplayListSection::BackgroundAndBorders,
             StackingLevel::BlockBackgroundsAndBorders => {
-                self.block_backgrounds_and_borders.push_back(display_item)
+                DisplayListSection::BlockBackgroundsAndBorders
             }
-            StackingLevel::Content => self.content.push_back(display_item),
-        }
+            StackingLevel::Content => DisplayListSection::Content,
+        };
+        self.get_mut(section).push(display_item)
     }
 }
 
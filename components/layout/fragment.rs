@@ -23,7 +23,8 @@ use wrapper::{TLayoutNode, ThreadSafeLayoutNode};
 
 use geom::num::Zero;
 use geom::{Point2D, Rect, Size2D};
-use gfx::display_list::{BLUR_INFLATION_FACTOR, OpaqueNode};
+use gfx::display_list::{BLUR_INFLATION_FACTOR, ImageOrientation, OpaqueNode};
+use gfx::filters;
 use gfx::text::glyph::CharIndex;
 use gfx::text::text_run::{TextRun, TextRunSlice};
 use msg::constellation_msg::{ConstellationChan, Msg, PipelineId, SubpageId};
@@ -40,8 +41,10 @@ use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use string_cache::Atom;
 use style::computed_values::content::ContentItem;
-use style::computed_values::{border_collapse, clear, mix_blend_mode, overflow_wrap, position};
-use style::computed_values::{text_align, text_decoration, white_space, word_break};
+use style::computed_values::{border_collapse, clear, image_orientation, mix_blend_mode};
+use style::computed_values::{overflow_wrap, position, ruby_position};
+use style::computed_values::{text_align, text_decoration, transform_style, white_space};
+use style::computed_values::word_break;
 use style::node::{TElement, TNode};
 use style::properties::{ComputedValues, cascade_anonymous, make_border};
 use style::values::computed::{LengthOrPercentage, LengthOrPercentageOrAuto};
@@ -297,6 +300,9 @@ pub struct ImageFragmentInfo {
     /// The image held within this fragment.
     pub replaced_image_fragment_info: ReplacedImageFragmentInfo,
     pub image: Option<Arc<Image>>,
+    /// How the image is turned to be shown upright, from its EXIF data unless
+    /// `image-orientation` is `none`.
+    pub orientation: ImageOrientation,
 }
 
 impl ImageFragmentInfo {
@@ -316,20 +322,34 @@ impl ImageFragmentInfo {
         }
 
         let image = url.and_then(|url| layout_context.get_or_request_image(url));
+        let orientation = match (&image, node.style().get_inheritedbox().image_orientation) {
+            (&Some(ref image), image_orientation::T::from_image) => {
+                ImageOrientation::from_exif(image.exif_orientation)
+            }
+            _ => ImageOrientation::identity(),
+        };
 
         ImageFragmentInfo {
             replaced_image_fragment_info: ReplacedImageFragmentInfo::new(node,
                 convert_length(node, &atom!("width")),
                 convert_length(node, &atom!("height"))),
             image: image,
+            orientation: orientation,
         }
     }
 
+    /// Whether the stored width of the image runs along the block axis when it is shown: the
+    /// image is either laid out in a vertical writing mode or turned a quarter, but not both.
+    fn width_is_block_size(&self) -> bool {
+        self.replaced_image_fragment_info.writing_mode_is_vertical !=
+            self.orientation.swaps_dimensions()
+    }
+
     /// Returns the original inline-size of the image.
     pub fn image_inline_size(&mut self) -> Au {
         match self.image {
             Some(ref image) => {
-                Au::from_px(if self.replaced_image_fragment_info.writing_mode_is_vertical {
+                Au::from_px(if self.width_is_block_size() {
                     image.height
                 } else {
                     image.width
@@ -343,7 +363,7 @@ impl ImageFragmentInfo {
     pub fn image_block_size(&mut self) -> Au {
         match self.image {
             Some(ref image) => {
-                Au::from_px(if self.replaced_image_fragment_info.writing_mode_is_vertical {
+                Au::from_px(if self.width_is_block_size() {
                     image.width
                 } else {
                     image.height
@@ -605,6 +625,14 @@ pub struct ScannedTextFragmentInfo {
     /// Whether a line break is required after this fragment if wrapping on newlines (e.g. if
     /// `white-space: pre` is in effect).
     pub requires_line_break_afterward_if_wrapping_on_newlines: bool,
+
+    /// The ruby annotation of this text, if any. When the text is split across lines, only the
+    /// first piece keeps it.
+    pub ruby_annotation: Option<RubyAnnotationInfo>,
+
+    /// The text run of the emphasis mark that `text-emphasis-style` puts on each character of
+    /// this text, if any.
+    pub emphasis_mark: Option<Arc<Box<TextRun>>>,
 }
 
 impl ScannedTextFragmentInfo {
@@ -621,10 +649,26 @@ impl ScannedTextFragmentInfo {
             range_end_including_stripped_whitespace: range.end(),
             requires_line_break_afterward_if_wrapping_on_newlines:
                 requires_line_break_afterward_if_wrapping_on_newlines,
+            ruby_annotation: None,
+            emphasis_mark: None,
         }
     }
 }
 
+/// The text of an `<rt>` element, scanned into a run, that annotates the base text of a `<ruby>`
+/// element before it.
+#[derive(Clone)]
+pub struct RubyAnnotationInfo {
+    /// The text run of the annotation.
+    pub run: Arc<Box<TextRun>>,
+
+    /// The range within the above text run that the annotation is.
+    pub range: Range<CharIndex>,
+
+    /// The style of the `<rt>` element, which gives the color and side of the annotation.
+    pub style: Arc<ComputedValues>,
+}
+
 /// Describes how to split a fragment. This is used during line breaking as part of the return
 /// value of `find_split_info_for_inline_size()`.
 #[derive(Debug, Clone)]
@@ -672,6 +716,9 @@ pub struct UnscannedTextFragmentInfo {
     /// FIXME(pcwalton): Is there something more clever we can do here that avoids the double
     /// indirection while not penalizing all fragments?
     pub text: Box<String>,
+
+    /// The ruby annotation of this text, if it is the base text of a `<ruby>` element.
+    pub ruby_annotation: Option<RubyAnnotationInfo>,
 }
 
 impl UnscannedTextFragmentInfo {
@@ -680,6 +727,7 @@ impl UnscannedTextFragmentInfo {
     pub fn from_text(text: String) -> UnscannedTextFragmentInfo {
         UnscannedTextFragmentInfo {
             text: box text,
+            ruby_annotation: None,
         }
     }
 }
@@ -815,14 +863,25 @@ impl Fragment {
                                     self.border_box.size.block);
         let requires_line_break_afterward_if_wrapping_on_newlines =
             self.requires_line_break_afterward_if_wrapping_on_newlines();
-        let info = box ScannedTextFragmentInfo::new(
+        let mut info = box ScannedTextFragmentInfo::new(
             text_run,
             split.range,
             size,
             requires_line_break_afterward_if_wrapping_on_newlines);
+        if let SpecificFragmentInfo::ScannedText(ref this_info) = self.specific {
+            info.ruby_annotation = this_info.ruby_annotation.clone();
+            info.emphasis_mark = this_info.emphasis_mark.clone()
+        }
         self.transform(size, SpecificFragmentInfo::ScannedText(info))
     }
 
+    /// Removes the ruby annotation of this fragment, if it has one.
+    pub fn remove_ruby_annotation(&mut self) {
+        if let SpecificFragmentInfo::ScannedText(ref mut info) = self.specific {
+            info.ruby_annotation = None
+        }
+    }
+
     /// Transforms this fragment into an ellipsis fragment, preserving all the other data.
     pub fn transform_into_ellipsis(&self, layout_context: &LayoutContext) -> Fragment {
         let mut unscanned_ellipsis_fragments = LinkedList::new();
@@ -1756,7 +1815,31 @@ impl Fragment {
             SpecificFragmentInfo::ScannedText(ref text_fragment) => {
                 // See CSS 2.1 § 10.8.1.
                 let line_height = self.calculate_line_height(layout_context);
-                InlineMetrics::from_font_metrics(&text_fragment.run.font_metrics, line_height)
+                let font_metrics = &text_fragment.run.font_metrics;
+                let mut inline_metrics = InlineMetrics::from_font_metrics(font_metrics,
+                                                                          line_height);
+
+                // The ruby annotation sits outside the ascent or descent of the base text, on the
+                // side that it is placed on. The line must be tall enough to hold it where the
+                // leading doesn't already make room.
+                if let Some(ref annotation) = text_fragment.ruby_annotation {
+                    let annotation_metrics = &annotation.run.font_metrics;
+                    let annotation_block_size = annotation_metrics.ascent +
+                        annotation_metrics.descent;
+                    match annotation.style.get_inheritedtext().ruby_position {
+                        ruby_position::T::over => {
+                            inline_metrics.block_size_above_baseline =
+                                max(inline_metrics.block_size_above_baseline,
+                                    font_metrics.ascent + annotation_block_size)
+                        }
+                        ruby_position::T::under => {
+                            inline_metrics.depth_below_baseline =
+                                max(inline_metrics.depth_below_baseline,
+                                    font_metrics.descent + annotation_block_size)
+                        }
+                    }
+                }
+                inline_metrics
             }
             SpecificFragmentInfo::InlineBlock(ref info) => {
                 // See CSS 2.1 § 10.8.1.
@@ -1804,6 +1887,8 @@ impl Fragment {
                 self.style().get_font() == other.style().get_font() &&
                     self.text_decoration() == other.text_decoration() &&
                     self.white_space() == other.white_space() &&
+                    self.style().get_inheritedbox().direction ==
+                        other.style().get_inheritedbox().direction &&
                     (length == 0 || first_unscanned_text.text.char_at_reverse(length) != '\n')
             }
             _ => false,
@@ -1921,6 +2006,12 @@ impl Fragment {
         if self.style().get_effects().transform.is_some() {
             return true
         }
+        if self.style().get_effects().perspective.is_some() {
+            return true
+        }
+        if self.style().get_effects().transform_style == transform_style::T::preserve_3d {
+            return true
+        }
         match self.style().get_box().position {
             position::T::absolute | position::T::fixed => {
                 // FIXME(pcwalton): This should only establish a new stacking context when
@@ -1959,6 +2050,9 @@ impl Fragment {
             overflow = overflow.union(&border_box.translate(&offset).inflate(inflation, inflation))
         }
 
+        // Blurs and drop shadows in filters cause us to draw outside our border box.
+        overflow = filters::inflate_rect(&self.style().get_effects().filter, &overflow);
+
         // Outlines cause us to draw outside our border box.
         let outline_width = self.style.get_outline().outline_width;
         if outline_width != Au(0) {
@@ -588,7 +588,12 @@ impl LineBreaker {
             fragment.transform_with_split_info(x, split_result.text_run.clone())
         });
         inline_end_fragment = split_result.inline_end.as_ref().map(|x| {
-            fragment.transform_with_split_info(x, split_result.text_run.clone())
+            let mut inline_end_fragment =
+                fragment.transform_with_split_info(x, split_result.text_run.clone());
+            if split_result.inline_start.is_some() {
+                inline_end_fragment.remove_ruby_annotation()
+            }
+            inline_end_fragment
         });
 
         // Push the first fragment onto the line we're working on and start off the next line with
@@ -26,22 +26,24 @@ use wrapper::{LayoutNode, TLayoutNode};
 use azure::azure::AzColor;
 use encoding::EncodingRef;
 use encoding::all::UTF_8;
-use geom::matrix2d::Matrix2D;
+use geom::matrix;
 use geom::point::Point2D;
 use geom::rect::Rect;
 use geom::scale_factor::ScaleFactor;
 use geom::size::Size2D;
 use gfx::color;
-use gfx::display_list::{ClippingRegion, DisplayItemMetadata, DisplayList, OpaqueNode};
-use gfx::display_list::{StackingContext};
+use gfx::display_list::{ClippingRegion, DisplayItem, DisplayItemMetadata, DisplayList};
+use gfx::display_list::{DisplayListSection, IframeHit, OpaqueNode, StackingContext};
 use gfx::font_cache_task::FontCacheTask;
 use gfx::paint_task::Msg as PaintMsg;
 use gfx::paint_task::{PaintChan, PaintLayer};
+use gfx::svg_filter;
 use layout_traits::{LayoutControlMsg, LayoutTaskFactory};
 use log;
 use msg::compositor_msg::ScrollPolicy;
 use msg::constellation_msg::Msg as ConstellationMsg;
 use msg::constellation_msg::{ConstellationChan, Failure, PipelineExitType, PipelineId};
+use msg::constellation_msg::SubpageId;
 use profile_traits::mem::{self, Report, ReportsChan};
 use profile_traits::time::{self, ProfilerMetadata, profile};
 use profile_traits::time::{TimerMetadataFrameType, TimerMetadataReflowType};
@@ -57,23 +59,28 @@ use script_traits::{ConstellationControlMsg, OpaqueScriptLayoutChannel};
 use script_traits::ScriptControlChan;
 use std::borrow::ToOwned;
 use std::cell::Cell;
-use std::mem::transmute;
+use std::collections::HashSet;
+use std::i32;
+use std::mem::{replace, transmute};
 use std::ops::{Deref, DerefMut};
 use std::ptr;
 use std::sync::mpsc::{channel, Sender, Receiver, Select};
 use std::sync::{Arc, Mutex, MutexGuard};
-use style::computed_values::{filter, mix_blend_mode};
+use string_cache::Atom;
+use style::computed_values::{background_attachment, filter, mix_blend_mode};
+use style::font_face::Source;
 use style::media_queries::{MediaType, MediaQueryList, Device};
 use style::node::TNode;
 use style::selector_matching::Stylist;
 use style::stylesheets::{Origin, Stylesheet, CSSRuleIteratorExt};
 use url::Url;
 use util::cursor::Cursor;
-use util::geometry::{Au, MAX_RECT};
+use util::geometry::{Au, MAX_RECT, ZERO_POINT};
 use util::logical_geometry::LogicalPoint;
 use util::mem::HeapSizeOf;
 use util::opts;
 use util::smallvec::SmallVec;
+use util::str::LowercaseString;
 use util::task::spawn_named_with_send_on_failure;
 use util::task_state;
 use util::workqueue::WorkQueue;
@@ -94,9 +101,17 @@ pub struct LayoutTaskData {
     /// The size of the viewport.
     pub screen_size: Size2D<Au>,
 
+    /// How many device pixels a px covers at the page zoom, which display items are snapped to.
+    pub device_pixels_per_px: f32,
+
     /// The root stacking context.
     pub stacking_context: Option<Arc<StackingContext>>,
 
+    /// How many times script had changed the document when the root stacking context was built.
+    /// Hit tests against it are refused once the document has changed again, since the nodes in
+    /// it may be gone.
+    pub stacking_context_dom_generation: u32,
+
     /// Performs CSS selector matching and style resolution.
     pub stylist: Box<Stylist>,
 
@@ -125,6 +140,18 @@ pub struct LayoutTaskData {
     /// A channel on which new animations that have been triggered by style recalculation can be
     /// sent.
     pub new_animations_sender: Sender<Animation>,
+
+    /// Whether a web font face has become available since the last reflow, in which case all
+    /// nodes must be restyled so that text is rebuilt with it.
+    pub web_fonts_changed: bool,
+
+    /// The lowercased names of the web font families that the stylesheets of this document
+    /// declare faces of. The font cache reports faces of every document's families.
+    pub web_font_families: HashSet<Atom>,
+
+    /// The families and URLs of the web font faces that this document registered with the font
+    /// cache, which are removed again when the document goes away.
+    pub web_font_faces: Vec<(Atom, String)>,
 }
 
 /// Information needed by the layout task.
@@ -150,6 +177,9 @@ pub struct LayoutTask {
     /// The channel on which the image cache can send messages to ourself.
     image_cache_sender: ImageCacheChan,
 
+    /// The port on which the font cache tells us about newly available web font faces.
+    font_cache_receiver: Receiver<Atom>,
+
     /// The channel on which we or others can send messages to ourselves.
     pub chan: LayoutChan,
 
@@ -298,6 +328,8 @@ impl LayoutTask {
         // Create the channel on which new animations can be sent.
         let (new_animations_sender, new_animations_receiver) = channel();
         let (image_cache_sender, image_cache_receiver) = channel();
+        let (font_cache_sender, font_cache_receiver) = channel();
+        font_cache_task.add_web_font_listener(font_cache_sender);
 
         LayoutTask {
             id: id,
@@ -317,13 +349,16 @@ impl LayoutTask {
             first_reflow: Cell::new(true),
             image_cache_receiver: image_cache_receiver,
             image_cache_sender: ImageCacheChan(image_cache_sender),
+            font_cache_receiver: font_cache_receiver,
             rw_data: Arc::new(Mutex::new(
                 LayoutTaskData {
                     root_flow: None,
                     image_cache_task: image_cache_task,
                     constellation_chan: constellation_chan,
                     screen_size: screen_size,
+                    device_pixels_per_px: 1.0,
                     stacking_context: None,
+                    stacking_context_dom_generation: 0,
                     stylist: box Stylist::new(device),
                     parallel_traversal: parallel_traversal,
                     dirty: Rect::zero(),
@@ -333,6 +368,9 @@ impl LayoutTask {
                     running_animations: Vec::new(),
                     new_animations_receiver: new_animations_receiver,
                     new_animations_sender: new_animations_sender,
+                    web_fonts_changed: false,
+                    web_font_families: HashSet::new(),
+                    web_font_faces: Vec::new(),
               })),
         }
     }
@@ -357,6 +395,7 @@ impl LayoutTask {
             image_cache_task: rw_data.image_cache_task.clone(),
             image_cache_sender: self.image_cache_sender.clone(),
             screen_size: rw_data.screen_size.clone(),
+            device_pixels_per_px: rw_data.device_pixels_per_px,
             screen_size_changed: screen_size_changed,
             constellation_chan: rw_data.constellation_chan.clone(),
             layout_chan: self.chan.clone(),
@@ -379,6 +418,7 @@ impl LayoutTask {
             Pipeline,
             Script,
             ImageCache,
+            FontCache,
         }
 
         let port_to_read = {
@@ -386,10 +426,12 @@ impl LayoutTask {
             let mut port1 = sel.handle(&self.port);
             let mut port2 = sel.handle(&self.pipeline_port);
             let mut port3 = sel.handle(&self.image_cache_receiver);
+            let mut port4 = sel.handle(&self.font_cache_receiver);
             unsafe {
                 port1.add();
                 port2.add();
                 port3.add();
+                port4.add();
             }
             let ret = sel.wait();
             if ret == port1.id() {
@@ -398,6 +440,8 @@ impl LayoutTask {
                 PortToRead::Pipeline
             } else if ret == port3.id() {
                 PortToRead::ImageCache
+            } else if ret == port4.id() {
+                PortToRead::FontCache
             } else {
                 panic!("invalid select result");
             }
@@ -423,9 +467,37 @@ impl LayoutTask {
                 let _ = self.image_cache_receiver.recv().unwrap();
                 self.repaint(possibly_locked_rw_data)
             }
+            PortToRead::FontCache => {
+                let family = self.font_cache_receiver.recv().unwrap();
+                self.handle_web_font_loaded(family, possibly_locked_rw_data);
+                true
+            }
         }
     }
 
+    /// Notes that a face of the given web font family has become available and, if this document
+    /// uses the family, asks script for a reflow. Text is shaped during flow construction, so
+    /// that reflow restyles every node to rebuild it.
+    fn handle_web_font_loaded<'a>(&'a self,
+                                  family: Atom,
+                                  possibly_locked_rw_data:
+                                    &mut Option<MutexGuard<'a, LayoutTaskData>>) {
+        let mut rw_data = self.lock_rw_data(possibly_locked_rw_data);
+        let uses_family = rw_data.web_font_families.contains(&family);
+        if uses_family {
+            debug!("layout: web font family {:?} is now available", family);
+            rw_data.web_fonts_changed = true;
+        }
+        LayoutTask::return_rw_data(possibly_locked_rw_data, rw_data);
+        if !uses_family {
+            return
+        }
+
+        // Script may have shut down already, in which case there is nothing left to reflow.
+        let ScriptControlChan(ref script_chan) = self.script_chan;
+        let _ = script_chan.send(ConstellationControlMsg::WebFontLoaded(self.id));
+    }
+
     /// If no reflow has happened yet, this will just return the lock in
     /// `possibly_locked_rw_data`. Otherwise, it will acquire the `rw_data` lock.
     ///
@@ -583,6 +655,11 @@ impl LayoutTask {
             if let Some(ref mut traversal) = (&mut *rw_data).parallel_traversal {
                 traversal.shutdown()
             }
+
+            // Web font faces that no other document registered are evicted from the font cache.
+            for (family, url) in replace(&mut rw_data.web_font_faces, vec!()).into_iter() {
+                self.font_cache_task.remove_web_font(family, url)
+            }
             LayoutTask::return_rw_data(possibly_locked_rw_data, rw_data);
         }
 
@@ -625,11 +702,21 @@ impl LayoutTask {
         let mut rw_data = self.lock_rw_data(possibly_locked_rw_data);
 
         if mq.evaluate(&rw_data.stylist.device) {
+            let mut families = vec!();
+            let mut faces = vec!();
             for font_face in sheet.effective_rules(&rw_data.stylist.device).font_face() {
+                families.push(Atom::from_slice(&LowercaseString::new(&font_face.family)));
                 for source in font_face.sources.iter() {
-                    self.font_cache_task.add_web_font(font_face.family.clone(), source.clone());
+                    if let Source::Url(ref url_source) = *source {
+                        faces.push((font_face.family.clone(), url_source.url.to_string()))
+                    }
+                    self.font_cache_task.add_web_font(font_face.family.clone(),
+                                                     source.clone(),
+                                                     font_face.unicode_range.clone());
                 }
             }
+            rw_data.web_font_families.extend(families.into_iter());
+            rw_data.web_font_faces.extend(faces.into_iter());
             rw_data.stylist.add_stylesheet(sheet);
         }
 
@@ -799,6 +886,20 @@ impl LayoutTask {
                 let mut display_list = box DisplayList::new();
                 flow::mut_base(&mut **layout_root).display_list_building_result
                                                   .add_to(&mut *display_list);
+
+                // If the root background is fixed, its layer paints the canvas background.
+                let fixed_background = build_fixed_root_background(&mut **layout_root,
+                                                                   &mut *display_list,
+                                                                   root_background_color,
+                                                                   rw_data.screen_size,
+                                                                   rw_data.device_pixels_per_px);
+                let root_background_color = match fixed_background {
+                    Some(fixed_background) => {
+                        display_list.children.insert(0, fixed_background);
+                        color::transparent()
+                    }
+                    None => root_background_color,
+                };
                 let paint_layer = Arc::new(PaintLayer::new(layout_root.layer_id(0),
                                                            root_background_color,
                                                            ScrollPolicy::Scrollable));
@@ -806,17 +907,23 @@ impl LayoutTask {
 
                 if opts::get().dump_display_list {
                     println!("#### start printing display list.");
-                    display_list.print_items(String::from_str("#"));
+                    println!("{:?}", display_list.tree(opts::get().dump_display_list_depth));
                 }
 
                 let stacking_context = Arc::new(StackingContext::new(display_list,
                                                                      &origin,
                                                                      &origin,
                                                                      0,
-                                                                     &Matrix2D::identity(),
+                                                                     &matrix::identity(),
+                                                                     &matrix::identity(),
+                                                                     false,
                                                                      filter::T::new(Vec::new()),
                                                                      mix_blend_mode::T::normal,
-                                                                     Some(paint_layer)));
+                                                                     Some(paint_layer),
+                                                                     rw_data.device_pixels_per_px));
+
+                // Painting needs the SVG filters that `filter: url()` refers to at hand.
+                svg_filter::load_referenced(&*stacking_context, &self.resource_task);
 
                 rw_data.stacking_context = Some(stacking_context.clone());
 
@@ -855,6 +962,7 @@ impl LayoutTask {
         let current_screen_size = Size2D(Au::from_frac32_px(viewport_size.width.get()),
                                          Au::from_frac32_px(viewport_size.height.get()));
         rw_data.screen_size = current_screen_size;
+        rw_data.device_pixels_per_px = data.window_size.device_pixel_ratio.get();
 
         // Handle conditions where the entire flow tree is invalid.
         let screen_size_changed = current_screen_size != old_screen_size;
@@ -864,7 +972,9 @@ impl LayoutTask {
         }
 
         // If the entire flow tree is invalid, then it will be reflowed anyhow.
-        let needs_dirtying = rw_data.stylist.update();
+        let stylesheets_changed = rw_data.stylist.update();
+        let needs_dirtying = stylesheets_changed || rw_data.web_fonts_changed;
+        rw_data.web_fonts_changed = false;
         let needs_reflow = screen_size_changed && !needs_dirtying;
         unsafe {
             if needs_dirtying {
@@ -914,6 +1024,9 @@ impl LayoutTask {
         self.perform_post_style_recalc_layout_passes(&data.reflow_info,
                                                      &mut rw_data,
                                                      &mut shared_layout_context);
+        if data.reflow_info.goal == ReflowGoal::ForDisplay {
+            rw_data.stacking_context_dom_generation = data.dom_generation
+        }
 
         let mut root_flow = (*rw_data.root_flow.as_ref().unwrap()).clone();
         match data.query_type {
@@ -1032,6 +1145,13 @@ impl LayoutTask {
             root_flow.dump();
         }
 
+        if log_enabled!(log::DEBUG) {
+            let shaped_text_cache = self.font_cache_task.shaped_text_cache();
+            debug!("layout: shaped text cache hits={} misses={}",
+                   shaped_text_cache.hits(),
+                   shaped_text_cache.misses());
+        }
+
         rw_data.generation += 1;
     }
 
@@ -1096,18 +1216,23 @@ impl LayoutRPC for LayoutRPCImpl {
     }
 
     /// Requests the node containing the point of interest.
-    fn hit_test(&self, _: TrustedNodeAddress, point: Point2D<f32>) -> Result<HitTestResponse, ()> {
+    fn hit_test(&self, _: TrustedNodeAddress, point: Point2D<f32>, dom_generation: u32)
+                -> Result<HitTestResponse, ()> {
         let point = Point2D(Au::from_frac_px(point.x as f64), Au::from_frac_px(point.y as f64));
         let resp = {
             let &LayoutRPCImpl(ref rw_data) = self;
             let rw_data = rw_data.lock().unwrap();
+            if rw_data.stacking_context_dom_generation != dom_generation {
+                return Err(())
+            }
             match rw_data.stacking_context {
                 None => panic!("no root stacking context!"),
                 Some(ref stacking_context) => {
                     let mut result = Vec::new();
-                    stacking_context.hit_test(point, &mut result, true);
+                    let iframe_hit = stacking_context.hit_test(point, &mut result, true);
                     if !result.is_empty() {
-                        Some(HitTestResponse(result[0].node.to_untrusted_node_address()))
+                        Some(HitTestResponse(result[0].node.to_untrusted_node_address(),
+                                             iframe_hit.map(forwarded_iframe_hit)))
                     } else {
                         None
                     }
@@ -1121,17 +1246,21 @@ impl LayoutRPC for LayoutRPCImpl {
         Err(())
     }
 
-    fn mouse_over(&self, _: TrustedNodeAddress, point: Point2D<f32>)
+    fn mouse_over(&self, _: TrustedNodeAddress, point: Point2D<f32>, dom_generation: u32)
                   -> Result<MouseOverResponse, ()> {
         let mut mouse_over_list: Vec<DisplayItemMetadata> = vec!();
+        let iframe_hit;
         let point = Point2D(Au::from_frac_px(point.x as f64), Au::from_frac_px(point.y as f64));
         {
             let &LayoutRPCImpl(ref rw_data) = self;
             let rw_data = rw_data.lock().unwrap();
+            if rw_data.stacking_context_dom_generation != dom_generation {
+                return Err(())
+            }
             match rw_data.stacking_context {
                 None => panic!("no root stacking context!"),
                 Some(ref stacking_context) => {
-                    stacking_context.hit_test(point, &mut mouse_over_list, false);
+                    iframe_hit = stacking_context.hit_test(point, &mut mouse_over_list, false);
                 }
             }
 
@@ -1152,11 +1281,18 @@ impl LayoutRPC for LayoutRPCImpl {
                 mouse_over_list.iter()
                                .map(|metadata| metadata.node.to_untrusted_node_address())
                                .collect();
-            Ok(MouseOverResponse(response_list))
+            Ok(MouseOverResponse(response_list, iframe_hit.map(forwarded_iframe_hit)))
         }
     }
 }
 
+/// Where a hit test found an iframe on top, in the form that script hands over to the pipeline of
+/// the document in it.
+fn forwarded_iframe_hit(iframe_hit: IframeHit) -> (SubpageId, Point2D<f32>) {
+    (iframe_hit.subpage_id,
+     Point2D(iframe_hit.point.x.to_frac32_px(), iframe_hit.point.y.to_frac32_px()))
+}
+
 struct UnioningFragmentBorderBoxIterator {
     node_address: OpaqueNode,
     rect: Option<Rect<Au>>,
@@ -1241,3 +1377,78 @@ fn get_root_flow_background_color(flow: &mut Flow) -> AzColor {
                   .resolve_color(kid_block_flow.fragment.style.get_background().background_color)
                   .to_gfx_color()
 }
+
+/// Moves the background of the root element into a layer of its own that stays in place when the
+/// page scrolls, if it has `background-attachment: fixed`, so that scrolling doesn't repaint it.
+/// The flow and the background are found as in `get_root_flow_background_color`. Returns the
+/// stacking context of the new layer.
+fn build_fixed_root_background(flow: &mut Flow,
+                               display_list: &mut DisplayList,
+                               background_color: AzColor,
+                               viewport_size: Size2D<Au>,
+                               device_pixels_per_px: f32)
+                               -> Option<Arc<StackingContext>> {
+    if !flow.is_block_like() {
+        return None
+    }
+
+    let layer_id = flow.layer_id(1);
+    let block_flow = flow.as_block();
+    let kid = match block_flow.base.children.iter_mut().next() {
+        None => return None,
+        Some(kid) => kid,
+    };
+    if !kid.is_block_like() {
+        return None
+    }
+
+    let fragment = &kid.as_block().fragment;
+    let background = fragment.style.get_background();
+    if background.background_attachment != background_attachment::T::fixed ||
+            background.background_image.is_none() {
+        return None
+    }
+
+    let mut background_display_list = box DisplayList::new();
+    move_background_items(display_list.get_mut(DisplayListSection::BackgroundAndBorders),
+                          background_display_list.get_mut(DisplayListSection::BackgroundAndBorders),
+                          fragment.node);
+    move_background_items(display_list.get_mut(DisplayListSection::BlockBackgroundsAndBorders),
+                          background_display_list.get_mut(DisplayListSection::BackgroundAndBorders),
+                          fragment.node);
+
+    let viewport = Rect(ZERO_POINT, viewport_size);
+    let paint_layer = Arc::new(PaintLayer::new(layer_id,
+                                               background_color,
+                                               ScrollPolicy::FixedBackground));
+    return Some(Arc::new(StackingContext::new(background_display_list,
+                                              &viewport,
+                                              &viewport,
+                                              i32::MIN,
+                                              &matrix::identity(),
+                                              &matrix::identity(),
+                                              false,
+                                              filter::T::new(Vec::new()),
+                                              mix_blend_mode::T::normal,
+                                              Some(paint_layer),
+                                              device_pixels_per_px)));
+
+    fn move_background_items(from: &mut Vec<DisplayItem>,
+                             to: &mut Vec<DisplayItem>,
+                             node: OpaqueNode) {
+        for item in replace(from, Vec::new()).into_iter() {
+            let is_background = match item {
+                DisplayItem::SolidColorClass(_) |
+                DisplayItem::ImageClass(_) |
+                DisplayItem::GradientClass(_) |
+                DisplayItem::RadialGradientClass(_) => item.base().metadata.node == node,
+                _ => false,
+            };
+            if is_background {
+                to.push(item)
+            } else {
+                from.push(item)
+            }
+        }
+    }
+}
@@ -8,7 +8,8 @@
 
 use fragment::Fragment;
 
-use geom::{Matrix2D, SideOffsets2D, Size2D};
+use geom::{SideOffsets2D, Size2D};
+use geom::matrix::Matrix4;
 use std::cmp::{max, min};
 use std::fmt;
 use style::computed_values::transform::ComputedMatrix;
@@ -425,17 +426,18 @@ pub fn padding_from_style(style: &ComputedValues, containing_block_inline_size:
 }
 
 pub trait ToGfxMatrix {
-    fn to_gfx_matrix(&self, containing_size: &Size2D<Au>) -> Matrix2D<f32>;
+    fn to_gfx_matrix(&self, containing_size: &Size2D<Au>) -> Matrix4<f32>;
 }
 
 impl ToGfxMatrix for ComputedMatrix {
-    fn to_gfx_matrix(&self, containing_size: &Size2D<Au>) -> Matrix2D<f32> {
-        Matrix2D::new(self.m11 as f32,
-                      self.m12 as f32,
-                      self.m21 as f32,
-                      self.m22 as f32,
-                      self.m31.to_au(containing_size.width).to_subpx() as f32,
-                      self.m32.to_au(containing_size.height).to_subpx() as f32)
+    fn to_gfx_matrix(&self, containing_size: &Size2D<Au>) -> Matrix4<f32> {
+        Matrix4::new(self.m11 as f32, self.m12 as f32, self.m13 as f32, 0.0,
+                     self.m21 as f32, self.m22 as f32, self.m23 as f32, 0.0,
+                     self.m31 as f32, self.m32 as f32, self.m33 as f32, 0.0,
+                     self.m41.to_au(containing_size.width).to_subpx() as f32,
+                     self.m42.to_au(containing_size.height).to_subpx() as f32,
+                     self.m43.to_au(containing_size.width).to_subpx() as f32,
+                     1.0)
     }
 }
 
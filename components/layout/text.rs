@@ -10,8 +10,9 @@ use fragment::{Fragment, SpecificFragmentInfo, ScannedTextFragmentInfo, Unscanne
 use inline::InlineFragments;
 
 use gfx::font::{DISABLE_KERNING_SHAPING_FLAG, FontMetrics, IGNORE_LIGATURES_SHAPING_FLAG};
-use gfx::font::{RunMetrics, ShapingFlags, ShapingOptions};
+use gfx::font::{MIXED_ORIENTATION_SHAPING_FLAG, RunMetrics, ShapingFlags, ShapingOptions};
 use gfx::font_context::FontContext;
+use gfx::text::bidi;
 use gfx::text::glyph::CharIndex;
 use gfx::text::text_run::TextRun;
 use gfx::text::util::{self, CompressionMode};
@@ -20,7 +21,7 @@ use std::collections::LinkedList;
 use std::mem;
 use std::sync::Arc;
 use style::computed_values::{line_height, text_orientation, text_rendering, text_transform};
-use style::computed_values::{white_space};
+use style::computed_values::{direction, white_space};
 use style::properties::ComputedValues;
 use style::properties::style_structs::Font as FontStyle;
 use util::geometry::Au;
@@ -107,13 +108,16 @@ impl TextRunScanner {
         // Concatenate all of the transformed strings together, saving the new character indices.
         let mut new_ranges: SmallVec1<Range<CharIndex>> = SmallVec1::new();
         let mut char_total = CharIndex(0);
-        let run = {
+        let runs = {
             let fontgroup;
             let compression;
             let text_transform;
             let letter_spacing;
             let word_spacing;
+            let tab_size;
             let text_rendering;
+            let paragraph_level;
+            let mixed_orientation;
             {
                 let in_fragment = self.clump.front().unwrap();
                 let font_style = in_fragment.style().get_font_arc();
@@ -128,7 +132,15 @@ impl TextRunScanner {
                 text_transform = inherited_text_style.text_transform;
                 letter_spacing = inherited_text_style.letter_spacing;
                 word_spacing = inherited_text_style.word_spacing.unwrap_or(Au(0));
+                tab_size = inherited_text_style.tab_size;
                 text_rendering = inherited_text_style.text_rendering;
+                paragraph_level = match in_fragment.style().get_inheritedbox().direction {
+                    direction::T::ltr => 0,
+                    direction::T::rtl => 1,
+                };
+                mixed_orientation = in_fragment.style().writing_mode.is_vertical() &&
+                    in_fragment.style().get_inheritedbox().text_orientation ==
+                        text_orientation::T::mixed;
             }
 
             // First, transform/compress text of all the nodes.
@@ -178,48 +190,98 @@ impl TextRunScanner {
                 flags.insert(IGNORE_LIGATURES_SHAPING_FLAG);
                 flags.insert(DISABLE_KERNING_SHAPING_FLAG)
             }
+            if mixed_orientation {
+                flags.insert(MIXED_ORIENTATION_SHAPING_FLAG)
+            }
             let options = ShapingOptions {
                 letter_spacing: letter_spacing,
                 word_spacing: word_spacing,
+                tab_size: tab_size,
                 flags: flags,
             };
 
-            // FIXME(https://github.com/rust-lang/rust/issues/23338)
-            let mut font = fontgroup.fonts.get(0).borrow_mut();
-            Arc::new(box TextRun::new(&mut *font, run_text, &options))
+            // Make a run for each range of characters that one font of the group renders, so that
+            // faces with a `unicode-range` only shape the characters that they cover, and split
+            // those wherever the embedding level changes, so that each run has one direction.
+            // Levels are resolved within the text of the clump rather than the whole paragraph.
+            let levels = bidi::resolve_levels(&run_text, paragraph_level);
+            let mut run_ranges = vec!();
+            for (font_index, font_range) in fontgroup.font_ranges_for_text(&run_text).into_iter() {
+                for (run_range, level) in
+                        bidi::split_at_level_changes(&font_range, &levels[..]).into_iter() {
+                    run_ranges.push((font_index, run_range, level))
+                }
+            }
+            if run_ranges.len() == 1 {
+                // FIXME(https://github.com/rust-lang/rust/issues/23338)
+                let (font_index, run_range, level) = run_ranges[0];
+                let mut font = fontgroup.fonts.get(font_index).borrow_mut();
+                vec!((run_range, Arc::new(box TextRun::new(&mut *font,
+                                                             run_text,
+                                                             &options,
+                                                             level))))
+            } else {
+                let mut chars = run_text.chars();
+                run_ranges.into_iter().map(|(font_index, run_range, level)| {
+                    let text = chars.by_ref().take(run_range.length().get() as usize).collect();
+                    let mut font = fontgroup.fonts.get(font_index).borrow_mut();
+                    (run_range, Arc::new(box TextRun::new(&mut *font, text, &options, level)))
+                }).collect::<Vec<_>>()
+            }
         };
 
         // Make new fragments with the run and adjusted text indices.
         debug!("TextRunScanner: pushing {} fragment(s)", self.clump.len());
         for (logical_offset, old_fragment) in
                 mem::replace(&mut self.clump, LinkedList::new()).into_iter().enumerate() {
-            let mut range = *new_ranges.get(logical_offset);
-            if range.is_empty() {
+            let fragment_range = *new_ranges.get(logical_offset);
+            if fragment_range.is_empty() {
                 debug!("Elided an `SpecificFragmentInfo::UnscannedText` because it was \
                         zero-length after compression");
                 continue
             }
 
-            let requires_line_break_afterward_if_wrapping_on_newlines =
-                run.text.char_at_reverse(range.end().get() as usize) == '\n';
-            if requires_line_break_afterward_if_wrapping_on_newlines {
-                range.extend_by(CharIndex(-1))
-            }
+            let emphasis_mark = emphasis_mark_run(font_context, &*old_fragment.style);
 
-            let text_size = old_fragment.border_box.size;
-            let mut new_text_fragment_info = box ScannedTextFragmentInfo::new(
-                run.clone(),
-                range,
-                text_size,
-                requires_line_break_afterward_if_wrapping_on_newlines);
-            let new_metrics = new_text_fragment_info.run.metrics_for_range(&range);
-            let bounding_box_size = bounding_box_for_run_metrics(&new_metrics,
-                                                                 old_fragment.style.writing_mode);
-            new_text_fragment_info.content_size = bounding_box_size;
-            let new_fragment =
-                old_fragment.transform(bounding_box_size,
-                                       SpecificFragmentInfo::ScannedText(new_text_fragment_info));
-            out_fragments.push(new_fragment)
+            // A fragment whose text is rendered with several fonts is split into a fragment per
+            // run. The first of them keeps the ruby annotation of the fragment.
+            let mut ruby_annotation = match old_fragment.specific {
+                SpecificFragmentInfo::UnscannedText(ref text_fragment_info) => {
+                    text_fragment_info.ruby_annotation.clone()
+                }
+                _ => None,
+            };
+            for &(ref run_range, ref run) in runs.iter() {
+                let range = fragment_range.intersect(run_range);
+                if range.is_empty() {
+                    continue
+                }
+                let mut range = Range::new(range.begin() - run_range.begin(), range.length());
+
+                let requires_line_break_afterward_if_wrapping_on_newlines =
+                    run.text.char_at_reverse(range.end().get() as usize) == '\n';
+                if requires_line_break_afterward_if_wrapping_on_newlines {
+                    range.extend_by(CharIndex(-1))
+                }
+
+                let text_size = old_fragment.border_box.size;
+                let mut new_text_fragment_info = box ScannedTextFragmentInfo::new(
+                    run.clone(),
+                    range,
+                    text_size,
+                    requires_line_break_afterward_if_wrapping_on_newlines);
+                let new_metrics = new_text_fragment_info.run.metrics_for_range(&range);
+                let bounding_box_size =
+                    bounding_box_for_run_metrics(&new_metrics, old_fragment.style.writing_mode);
+                new_text_fragment_info.content_size = bounding_box_size;
+                new_text_fragment_info.ruby_annotation = ruby_annotation.take();
+                new_text_fragment_info.emphasis_mark = emphasis_mark.clone();
+                let new_fragment =
+                    old_fragment.transform(bounding_box_size,
+                                           SpecificFragmentInfo::ScannedText(
+                                               new_text_fragment_info));
+                out_fragments.push(new_fragment)
+            }
         }
 
         last_whitespace
@@ -287,12 +349,15 @@ fn bounding_box_for_run_metrics(metrics: &RunMetrics, writing_mode: WritingMode)
         Some(text_orientation::T::sideways_right) |
         Some(text_orientation::T::sideways_left) |
         Some(text_orientation::T::sideways) |
+        Some(text_orientation::T::mixed) |
         None => {}
     }
 
     // In vertical sideways or horizontal upright text,
     // the "width" of text metrics is always inline
     // This will need to be updated when other text orientations are supported.
+    // Upright glyphs under `mixed` are approximated as taking their horizontal
+    // advance along the line too.
     LogicalSize::new(
         writing_mode,
         metrics.bounding_box.size.width,
@@ -300,6 +365,27 @@ fn bounding_box_for_run_metrics(metrics: &RunMetrics, writing_mode: WritingMode)
 
 }
 
+/// Returns the text run of the emphasis mark that `text-emphasis-style` puts on each character
+/// of text in the given style, if any. Marks are set in the font of the text at half its size.
+fn emphasis_mark_run(font_context: &mut FontContext, style: &ComputedValues)
+                     -> Option<Arc<Box<TextRun>>> {
+    let is_vertical = style.writing_mode.is_vertical();
+    let mark = match style.get_inheritedtext().text_emphasis_style.mark(is_vertical) {
+        Some(mark) => mark,
+        None => return None,
+    };
+    let mut font_style = style.get_font().clone();
+    font_style.font_size = font_style.font_size / 2;
+    let fontgroup = font_context.get_layout_font_group_for_style(Arc::new(font_style));
+    let options = ShapingOptions {
+        letter_spacing: None,
+        word_spacing: Au(0),
+        tab_size: 8,
+        flags: ShapingFlags::empty(),
+    };
+    Some(Arc::new(box fontgroup.create_textrun(mark, &options, 0)))
+}
+
 /// Returns the metrics of the font represented by the given `FontStyle`, respectively.
 ///
 /// `#[inline]` because often the caller only needs a few fields from the font metrics.
@@ -357,6 +443,7 @@ fn split_first_fragment_at_newline_if_necessary(fragments: &mut LinkedList<Fragm
         first_fragment.transform(first_fragment.border_box.size,
                                  SpecificFragmentInfo::UnscannedText(UnscannedTextFragmentInfo {
             text: string_before,
+            ruby_annotation: None,
         }))
     };
 
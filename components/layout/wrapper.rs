@@ -1121,6 +1121,11 @@ impl<'le> ThreadSafeLayoutElement<'le> {
             self.element.get_attr_val_for_layout(namespace, name)
         }
     }
+
+    #[inline]
+    pub fn get_local_name(&self) -> &'le Atom {
+        self.element.local_name()
+    }
 }
 
 /// A bottom-up, parallelizable traversal.
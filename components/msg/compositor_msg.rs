@@ -4,12 +4,16 @@
 
 use azure::azure_hl::Color;
 use constellation_msg::{Key, KeyState, KeyModifiers};
+use geom::matrix2d::Matrix2D;
 use geom::point::Point2D;
 use geom::rect::Rect;
+use geom::size::Size2D;
 use layers::platform::surface::NativeGraphicsMetadata;
 use layers::layers::LayerBufferSet;
 use std::fmt::{Formatter, Debug};
 use std::fmt;
+use std::sync::mpsc::Sender;
+use util::opts::PixelFormat;
 
 use constellation_msg::PipelineId;
 
@@ -60,13 +64,102 @@ impl LayerId {
     }
 }
 
+/// What a layer holds, which decides how the compositor treats it.
+#[derive(Clone, PartialEq, Eq, Copy, Debug)]
+pub enum LayerKind {
+    /// Page content, put together with the other layers as usual.
+    Content,
+    /// A mask, which multiplies the colors of what is under it. The painter paints masks into
+    /// the layer under them, so the compositor never gets them.
+    Mask,
+    /// A scrollbar, which is kept above the other layers of its pipeline.
+    Scrollbar,
+    /// Something drawn over the page for debugging, which mouse events go through.
+    Overlay,
+}
+
+/// A per-pipeline ID for a scroll frame, the box whose content a scroll layer scrolls, that
+/// should be stable across reflows.
+#[derive(Clone, PartialEq, Eq, Copy, Debug)]
+pub struct ScrollId(pub usize);
+
+/// How a layer that scrolls its own content scrolls.
+#[derive(Clone, Copy)]
+pub struct ScrollMetadata {
+    pub id: ScrollId,
+    /// The size of all of the content that can be scrolled into view, in pixels.
+    pub content_size: Size2D<i32>,
+    /// The part of the layer that the content is seen through, in pixels from the top left of
+    /// the layer.
+    pub viewport: Rect<i32>,
+    /// How far the content can be scrolled up and to the left, in pixels. This is zero unless the
+    /// content reaches out of the top or left of the viewport, and negative if it does.
+    pub min_scroll_offset: Point2D<i32>,
+    /// How far the content can be scrolled down and to the right, in pixels.
+    pub max_scroll_offset: Point2D<i32>,
+    /// What happens when the content is scrolled past its ends.
+    pub overscroll: OverscrollBehavior,
+}
+
+impl ScrollMetadata {
+    /// Returns true if scrolling by `delta` past the ends of the content should scroll the layers
+    /// that this one is in instead, along the axis that `delta` mostly goes along.
+    pub fn chains_scroll(&self, delta: &Point2D<f32>) -> bool {
+        if delta.x * delta.x > delta.y * delta.y {
+            self.overscroll.x == OverscrollMode::Auto
+        } else {
+            self.overscroll.y == OverscrollMode::Auto
+        }
+    }
+}
+
+/// What happens along each axis when the content of a scroll layer is scrolled past its ends, as
+/// `overscroll-behavior` decides.
+#[derive(Clone, PartialEq, Eq, Copy, Debug)]
+pub struct OverscrollBehavior {
+    pub x: OverscrollMode,
+    pub y: OverscrollMode,
+}
+
+impl OverscrollBehavior {
+    pub fn auto() -> OverscrollBehavior {
+        OverscrollBehavior {
+            x: OverscrollMode::Auto,
+            y: OverscrollMode::Auto,
+        }
+    }
+}
+
+/// What happens along one axis when the content of a scroll layer is scrolled past its ends.
+#[derive(Clone, PartialEq, Eq, Copy, Debug)]
+pub enum OverscrollMode {
+    /// The layers that the scroll layer is in scroll instead, and the scroll layer shows that it
+    /// has reached its end if none of them can.
+    Auto,
+    /// The scroll layer shows that it has reached its end, by rubber-banding or a glow, and no
+    /// other layer scrolls.
+    Contain,
+    /// Nothing happens.
+    None,
+}
+
+impl OverscrollMode {
+    /// Returns true if the scroll layer shows that it has reached its end.
+    pub fn shows_end(&self) -> bool {
+        *self != OverscrollMode::None
+    }
+}
+
 /// The scrolling policy of a layer.
-#[derive(Clone, PartialEq, Eq, Copy)]
+#[derive(Clone, PartialEq, Eq, Copy, Debug)]
 pub enum ScrollPolicy {
     /// These layers scroll when the parent receives a scrolling message.
     Scrollable,
     /// These layers do not scroll when the parent receives a scrolling message.
     FixedPosition,
+    /// Like `FixedPosition`, but these layers are drawn behind the base layer of their pipeline.
+    /// They hold backgrounds with `background-attachment: fixed`.
+    FixedBackground,
 }
 
 /// All layer-specific information that the painting task sends to the compositor other than the
@@ -81,6 +174,107 @@ pub struct LayerMetadata {
     pub background_color: Color,
     /// The scrolling policy of this layer.
     pub scroll_policy: ScrollPolicy,
+    /// How the layer scrolls its own content, if it does.
+    pub scroll: Option<ScrollMetadata>,
+    /// What the layer holds.
+    pub kind: LayerKind,
+    /// True if the layer paints fully opaque pixels over all of itself.
+    pub is_opaque: bool,
+}
+
+/// An animation of the transform or opacity of a layer, which the compositor runs by itself
+/// between the frames that layout builds, so that it stays smooth while layout is busy.
+#[derive(Clone)]
+pub struct LayerAnimation {
+    pub layer_id: LayerId,
+    /// When the animation starts, in seconds as returned by `time::precise_time_s()`.
+    pub start_time: f64,
+    /// When the animation ends, in the same seconds.
+    pub end_time: f64,
+    pub timing_function: AnimationTimingFunction,
+    pub keyframes: LayerKeyframes,
+}
+
+/// How far through its keyframes an animation is at each point in its time.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnimationTimingFunction {
+    Linear,
+    /// A cubic Bézier curve from (0, 0) to (1, 1) with the given control points, as
+    /// `cubic-bezier()` gives.
+    CubicBezier(Point2D<f64>, Point2D<f64>),
+}
+
+/// The values that an animation goes through, each at an offset from 0 to 1 through it, in order
+/// of their offsets.
+#[derive(Clone)]
+pub enum LayerKeyframes {
+    /// Transforms applied to the layer on top of how it was painted, relative to its top left.
+    Transform(Vec<(f32, Matrix2D<f32>)>),
+    /// Opacities that the layer is composited with, on top of the opacity it was painted with.
+    Opacity(Vec<(f32, f32)>),
+}
+
+/// A GPU texture that something other than the painter draws into, such as the decoder of a video
+/// or a WebGL canvas, and that the compositor draws into the window as it is, without the pixels
+/// being read back for painting.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ExternalTextureId(pub usize);
+
+/// A frame drawn into an external texture, which its producer hands to the compositor.
+pub struct ExternalTextureFrame {
+    /// The GL name of the texture. It has to belong to a context that shares objects with the
+    /// one that the compositor draws with.
+    pub texture: u32,
+    /// The size of the texture in pixels.
+    pub size: Size2D<i32>,
+    /// A number that tells the frames of the texture apart.
+    pub frame_number: u64,
+    /// Sent `frame_number` once the compositor is done drawing the frame, after a later frame
+    /// has replaced it. Until then, the producer must not draw into the texture again.
+    pub release_chan: Sender<u64>,
+}
+
+impl ExternalTextureFrame {
+    /// Gives the frame back to its producer.
+    pub fn release(self) {
+        let _ = self.release_chan.send(self.frame_number);
+    }
+}
+
+/// Where to find a surface of pixels in POSIX shared memory.
+#[derive(Clone)]
+pub struct SharedSurfaceHandle {
+    /// The name of the shared memory object.
+    pub name: String,
+    pub size: Size2D<i32>,
+    pub format: PixelFormat,
+    /// The number of bytes from the start of one row of pixels to the start of the next.
+    pub stride: i32,
+}
+
+/// A tile painted into shared memory, for an embedder or a compositor in another process.
+#[derive(Clone)]
+pub struct SharedTile {
+    /// The area of the layer that the tile shows, in page coordinates.
+    pub page_rect: Rect<f32>,
+    /// The area of the layer that the tile shows, in device pixels.
+    pub screen_rect: Rect<usize>,
+    /// The number of device pixels per px that the tile was painted at.
+    pub resolution: f32,
+    pub surface: SharedSurfaceHandle,
+}
+
+/// The part of a page in view, painted in one piece for the compositor to put straight into the
+/// window, for pages simple enough not to need tiles.
+pub struct DirectFrame {
+    /// The area of the page that the frame shows, in page coordinates.
+    pub page_rect: Rect<f32>,
+    /// The size of the frame in device pixels.
+    pub size: Size2D<i32>,
+    /// The number of device pixels per px that the frame was painted at.
+    pub resolution: f32,
+    /// The premultiplied BGRA pixels, in rows from the top.
+    pub pixels: Vec<u8>,
 }
 
 /// The interface used by the painter to acquire draw targets for each paint frame and
@@ -101,8 +295,44 @@ pub trait PaintListener {
                               epoch: Epoch,
                               replies: Vec<(LayerId, Box<LayerBufferSet>)>);
 
+    /// Sends tiles painted into shared memory for the given layers, when painting with
+    /// `-Z shared-memory-tiles`.
+    fn assign_shared_tiles(&mut self,
+                           pipeline_id: PipelineId,
+                           epoch: Epoch,
+                           tiles: Vec<(LayerId, Vec<SharedTile>)>);
+
+    /// Tells the compositor whether the display list of the given pipeline at the given epoch
+    /// is simple enough to be painted straight into the window, in which case the compositor may
+    /// ask for whole frames instead of tiles.
+    fn set_direct_painting(&mut self, pipeline_id: PipelineId, epoch: Epoch, direct: bool);
+
+    /// Sends a frame painted for the compositor to put straight into the window.
+    fn assign_direct_frame(&mut self, pipeline_id: PipelineId, epoch: Epoch, frame: DirectFrame);
+
     fn paint_msg_discarded(&mut self);
     fn set_paint_state(&mut self, PipelineId, PaintState);
+
+    /// Asks the compositor to have the given area of a layer repainted at `time`, in nanoseconds
+    /// as returned by `time::precise_time_ns()`. The painter uses this when an animated image in
+    /// that area is due to show its next frame.
+    fn schedule_frame_repaint(&mut self,
+                              pipeline_id: PipelineId,
+                              epoch: Epoch,
+                              layer_id: LayerId,
+                              rect: Rect<f32>,
+                              time: u64);
+
+    /// Gives the compositor the animations of the layers of the given pipeline, in place of those
+    /// that it was given before. Animations that were given before keep running as they were.
+    fn set_layer_animations(&mut self, pipeline_id: PipelineId, animations: Vec<LayerAnimation>);
+
+    /// Tells the compositor where in the layers of the given pipeline to draw external textures,
+    /// in place of what it was told before. Rects are in px from the top left of each layer.
+    fn set_external_textures(&mut self,
+                             pipeline_id: PipelineId,
+                             epoch: Epoch,
+                             textures: Vec<(LayerId, Vec<(ExternalTextureId, Rect<f32>)>)>);
 }
 
 /// The interface used by the script task to tell the compositor to update its ready state,
@@ -5,6 +5,7 @@
 //! The high-level interface from script to constellation. Using this abstract interface helps
 //! reduce coupling between these two components.
 
+use geom::point::Point2D;
 use geom::rect::Rect;
 use geom::size::TypedSize2D;
 use geom::scale_factor::ScaleFactor;
@@ -233,7 +234,28 @@ pub enum Msg {
     /// Requests that the constellation retrieve the current contents of the clipboard
     GetClipboardContents(Sender<String>),
     // Dispatch a webdriver command
-    WebDriverCommand(PipelineId, WebDriverScriptCommand)
+    WebDriverCommand(PipelineId, WebDriverScriptCommand),
+    /// Hands a mouse event that hit an iframe over to the pipeline of the document in it.
+    ForwardMouseEvent(PipelineId, SubpageId, IframeMouseEvent),
+}
+
+/// The mouse button involved in the event.
+#[derive(Clone, Debug)]
+pub enum MouseButton {
+    /// The left mouse button.
+    Left,
+    /// The middle mouse button.
+    Middle,
+    /// The right mouse button.
+    Right,
+}
+
+/// A mouse event that hit an iframe, at a point in the coordinates of the document in it.
+pub enum IframeMouseEvent {
+    Click(MouseButton, Point2D<f32>),
+    MouseDown(MouseButton, Point2D<f32>),
+    MouseUp(MouseButton, Point2D<f32>),
+    MouseMove(Point2D<f32>),
 }
 
 // https://developer.mozilla.org/en-US/docs/Web/API/Using_the_Browser_API#Events
@@ -3,13 +3,14 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use collections::borrow::ToOwned;
-use net_traits::image::base::{Image, load_from_memory};
+use net_traits::image::base::{DecodedImages, Image, LazyImage, LazyImageCache, MAX_DECODED_SIZE};
+use net_traits::image::base::load_from_memory;
 use net_traits::image_cache_task::{ImageState, ImageCacheTask, ImageCacheChan, ImageCacheCommand, ImageCacheResult};
 use net_traits::load_whole_resource;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::mem;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{channel, Sender, Receiver, Select};
 use util::resource_files::resources_dir_path;
 use util::task::spawn_named;
@@ -51,13 +52,15 @@ impl PendingLoad {
 /// Represents an image that has completed loading.
 /// Images that fail to load (due to network or decode
 /// failure) are still stored here, so that they aren't
-/// fetched again.
+/// fetched again. Their encoded data is kept, and their
+/// pixels only for as long as the decoded images fit in
+/// the cache.
 struct CompletedLoad {
-    image: Option<Arc<Image>>,
+    image: Option<LazyImage>,
 }
 
 impl CompletedLoad {
-    fn new(image: Option<Arc<Image>>) -> CompletedLoad {
+    fn new(image: Option<LazyImage>) -> CompletedLoad {
         CompletedLoad {
             image: image,
         }
@@ -134,12 +137,16 @@ struct ImageCache {
     completed_loads: HashMap<Url, CompletedLoad>,
 
     // The placeholder image used when an image fails to load
-    placeholder_image: Option<Arc<Image>>,
+    placeholder_image: Option<LazyImage>,
+
+    // The decoded pixels of the images that have finished loading
+    decoded_images: LazyImageCache,
 }
 
 /// Message that the decoder worker threads send to main image cache task.
 struct DecoderMsg {
     url: Url,
+    bytes: Vec<u8>,
     image: Option<Image>,
 }
 
@@ -211,22 +218,30 @@ impl ImageCache {
                 self.request_image(url, result_chan, responder);
             }
             ImageCacheCommand::GetImageIfAvailable(url, consumer) => {
-                let result = match self.completed_loads.get(&url) {
-                    Some(completed_load) => {
-                        completed_load.image.clone().ok_or(ImageState::LoadError)
-                    }
-                    None => {
-                        let pending_load = self.pending_loads.get(&url);
-                        Err(pending_load.map_or(ImageState::NotRequested, |_| ImageState::Pending))
-                    }
-                };
+                let result = self.get_lazy_image_if_available(&url).and_then(|image| {
+                    image.decode().ok_or(ImageState::LoadError)
+                });
                 consumer.send(result).unwrap();
             }
+            ImageCacheCommand::GetLazyImageIfAvailable(url, consumer) => {
+                consumer.send(self.get_lazy_image_if_available(&url)).unwrap();
+            }
         };
 
         None
     }
 
+    // Get an image that has completed loading, or the state of its load
+    fn get_lazy_image_if_available(&self, url: &Url) -> Result<LazyImage, ImageState> {
+        match self.completed_loads.get(url) {
+            Some(completed_load) => completed_load.image.clone().ok_or(ImageState::LoadError),
+            None => {
+                let pending_load = self.pending_loads.get(url);
+                Err(pending_load.map_or(ImageState::NotRequested, |_| ImageState::Pending))
+            }
+        }
+    }
+
     // Handle progress messages from the resource task
     fn handle_progress(&mut self, msg: ResourceLoadInfo) {
         match msg.action {
@@ -249,6 +264,7 @@ impl ImageCache {
                             let image = load_from_memory(&bytes);
                             let msg = DecoderMsg {
                                 url: url,
+                                bytes: bytes,
                                 image: image
                             };
                             sender.send(msg).unwrap();
@@ -265,19 +281,24 @@ impl ImageCache {
 
     // Handle a message from one of the decoder worker threads
     fn handle_decoder(&mut self, msg: DecoderMsg) {
-        let image = msg.image.map(Arc::new);
-        self.complete_load(msg.url, image);
+        let DecoderMsg { url, bytes, image } = msg;
+        let decoded_images = &self.decoded_images;
+        let image = image.map(|image| {
+            LazyImage::from_image(bytes, Arc::new(image), decoded_images)
+        });
+        self.complete_load(url, image);
     }
 
     // Change state of a url from pending -> loaded.
-    fn complete_load(&mut self, url: Url, image: Option<Arc<Image>>) {
+    fn complete_load(&mut self, url: Url, image: Option<LazyImage>) {
         let pending_load = self.pending_loads.remove(&url).unwrap();
 
-        let completed_load = CompletedLoad::new(image.clone());
+        let decoded_image = image.as_ref().and_then(|image| image.decode());
+        let completed_load = CompletedLoad::new(image);
         self.completed_loads.insert(url, completed_load);
 
         for listener in pending_load.listeners.into_iter() {
-            listener.notify(image.clone());
+            listener.notify(decoded_image.clone());
         }
     }
 
@@ -289,7 +310,9 @@ impl ImageCache {
         match self.completed_loads.get(&url) {
             Some(completed_load) => {
                 // It's already completed, return a notify straight away
-                image_listener.notify(completed_load.image.clone());
+                image_listener.notify(completed_load.image.as_ref().and_then(|image| {
+                    image.decode()
+                }));
             }
             None => {
                 // Check if the load is already pending
@@ -326,6 +349,7 @@ pub fn new_image_cache_task(resource_task: ResourceTask) -> ImageCacheTask {
     let (decoder_sender, decoder_receiver) = channel();
 
     spawn_named("ImageCacheThread".to_owned(), move || {
+        let decoded_images = Arc::new(Mutex::new(DecodedImages::new(MAX_DECODED_SIZE)));
 
         // Preload the placeholder image, used when images fail to load.
         let mut placeholder_url = resources_dir_path();
@@ -338,7 +362,8 @@ pub fn new_image_cache_task(resource_task: ResourceTask) -> ImageCacheTask {
                 None
             }
             Ok((_, image_data)) => {
-                Some(Arc::new(load_from_memory(&image_data).unwrap()))
+                let image = Arc::new(load_from_memory(&image_data).unwrap());
+                Some(LazyImage::from_image(image_data, image, &decoded_images))
             }
         };
 
@@ -353,6 +378,7 @@ pub fn new_image_cache_task(resource_task: ResourceTask) -> ImageCacheTask {
             completed_loads: HashMap::new(),
             resource_task: resource_task,
             placeholder_image: placeholder_image,
+            decoded_images: decoded_images,
         };
 
         cache.run();
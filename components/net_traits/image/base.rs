@@ -2,14 +2,76 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use image::color_profile::{ColorProfile, jpeg_icc_profile, png_icc_profile};
+use image::gif;
+use image::jpeg;
 use png;
 use stb_image::image as stb_image2;
+use std::collections::HashMap;
 use std::iter::range_step;
+use std::sync::{Arc, Mutex, Weak};
+use time::precise_time_ns;
+use util::cache::SizedLRUCache;
 use util::vec::byte_swap;
 
-// FIXME: Images must not be copied every frame. Instead we should atomically
-// reference count them.
-pub type Image = png::Image;
+/// A decoded image.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: png::PixelsByColorType,
+    /// True if every pixel of the image, and of all its frames if it is animated, is fully
+    /// opaque, which is worked out once when the image is made. Greyscale images without alpha
+    /// are painted as masks, so they never are.
+    pub is_opaque: bool,
+    /// The frames of the image if it is animated. The pixels above are those of its first frame.
+    pub animation: Option<ImageFrames>,
+    /// The EXIF `Orientation` of the image, which says how it is turned to be shown upright. The
+    /// pixels above are as stored, and images without EXIF data have the upright value of 1.
+    pub exif_orientation: u16,
+}
+
+/// One frame of an animated image.
+#[derive(Clone)]
+pub struct ImageFrame {
+    pub image: Arc<Image>,
+    /// How long the frame is shown, in milliseconds.
+    pub delay: u32,
+}
+
+/// The frames of an animated image, such as an animated GIF.
+#[derive(Clone)]
+pub struct ImageFrames {
+    pub frames: Arc<Vec<ImageFrame>>,
+    /// Whether the animation loops forever instead of stopping at its last frame.
+    pub repeat: bool,
+    /// The time at which the image was decoded, which is when its animation starts, in
+    /// nanoseconds as returned by `time::precise_time_ns()`. Every element that shows the image
+    /// shows the same frame.
+    pub decode_time: u64,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, pixels: png::PixelsByColorType) -> Image {
+        let is_opaque = match pixels {
+            png::PixelsByColorType::RGB8(_) => true,
+            png::PixelsByColorType::K8(_) => false,
+            png::PixelsByColorType::RGBA8(ref pixels) => {
+                range_step(3, pixels.len(), 4).all(|index| pixels[index] == 0xff)
+            }
+            png::PixelsByColorType::KA8(ref pixels) => {
+                range_step(1, pixels.len(), 2).all(|index| pixels[index] == 0xff)
+            }
+        };
+        Image {
+            width: width,
+            height: height,
+            pixels: pixels,
+            is_opaque: is_opaque,
+            animation: None,
+            exif_orientation: 1,
+        }
+    }
+}
 
 // TODO(pcwalton): Speed up with SIMD, or better yet, find some way to not do this.
 fn byte_swap_and_premultiply(data: &mut [u8]) {
@@ -33,6 +95,19 @@ pub fn load_from_memory(buffer: &[u8]) -> Option<Image> {
     if png::is_png(buffer) {
         match png::load_png_from_memory(buffer) {
             Ok(mut png_image) => {
+                if let Some(profile) = png_icc_profile(buffer) {
+                    if let Some(profile) = ColorProfile::parse(&profile) {
+                        match png_image.pixels {
+                            png::PixelsByColorType::RGB8(ref mut data) => {
+                                profile.convert_rgb_to_srgb(data)
+                            }
+                            png::PixelsByColorType::RGBA8(ref mut data) => {
+                                profile.convert_rgba_to_srgb(data)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
                 match png_image.pixels {
                     png::PixelsByColorType::RGB8(ref mut data) => byte_swap(data),
                     png::PixelsByColorType::RGBA8(ref mut data) => {
@@ -40,7 +115,7 @@ pub fn load_from_memory(buffer: &[u8]) -> Option<Image> {
                     }
                     _ => {}
                 }
-                Some(png_image)
+                Some(Image::new(png_image.width, png_image.height, png_image.pixels))
             }
             Err(_err) => None,
         }
@@ -52,17 +127,28 @@ pub fn load_from_memory(buffer: &[u8]) -> Option<Image> {
         match stb_image2::load_from_memory_with_depth(buffer, FORCE_DEPTH, true) {
             stb_image2::LoadResult::ImageU8(mut image) => {
                 assert!(image.depth == 4);
+                if let Some(profile) = jpeg_icc_profile(buffer) {
+                    if let Some(profile) = ColorProfile::parse(&profile) {
+                        profile.convert_rgba_to_srgb(&mut image.data);
+                    }
+                }
                 // handle gif separately because the alpha-channel has to be premultiplied
                 if is_gif(buffer) {
                     byte_swap_and_premultiply(&mut image.data);
                 } else {
                     byte_swap(&mut image.data);
                 }
-                Some(png::Image {
-                    width: image.width as u32,
-                    height: image.height as u32,
-                    pixels: png::PixelsByColorType::RGBA8(image.data)
-                })
+                let mut image = Image::new(image.width as u32,
+                                           image.height as u32,
+                                           png::PixelsByColorType::RGBA8(image.data));
+                image.exif_orientation = jpeg::orientation(buffer).unwrap_or(1);
+                if is_gif(buffer) {
+                    image.animation = gif_frames(buffer);
+                    if let Some(ref animation) = image.animation {
+                        image.is_opaque = animation.frames.iter().all(|frame| frame.image.is_opaque)
+                    }
+                }
+                Some(image)
             }
             stb_image2::LoadResult::ImageF32(_image) => {
                 error!("HDR images not implemented");
@@ -76,6 +162,186 @@ pub fn load_from_memory(buffer: &[u8]) -> Option<Image> {
     }
 }
 
+/// How many bytes of decoded pixels a `LazyImageCache` usually keeps before it drops those of the
+/// least recently used images, which are decoded again if they are needed after that.
+pub static MAX_DECODED_SIZE: usize = 64 * 1024 * 1024;
+
+/// An encoded image that is decoded when its pixels are needed. The decoded image is kept in the
+/// cache that the image was made with, which clones share, until the cache grows too large, so
+/// that images that aren't painted don't keep their pixels in memory.
+#[derive(Clone)]
+pub struct LazyImage {
+    /// The width of the image in pixels.
+    pub width: u32,
+    /// The height of the image in pixels.
+    pub height: u32,
+    /// True if every pixel of the image is known to be fully opaque.
+    pub is_opaque: bool,
+    /// Whether the image is known to be animated. Its frames are kept with its decoded pixels,
+    /// so that they count toward the size of the cache.
+    pub is_animated: bool,
+    data: Arc<Vec<u8>>,
+    /// Identifies the image in its cache.
+    id: usize,
+    cache: LazyImageCache,
+}
+
+/// The decoded images of lazy images, as the threads that decode them share them.
+pub type LazyImageCache = Arc<Mutex<DecodedImages>>;
+
+/// The decoded images of lazy images, and those that were dropped but may still be in use.
+pub struct DecodedImages {
+    /// The decoded images by id, or `None` for data that couldn't be decoded, bounded by the
+    /// bytes of their pixels.
+    images: SizedLRUCache<usize, Option<Arc<Image>>>,
+    /// The images that were dropped from the cache, which are used again instead of being decoded
+    /// again for as long as something else holds them.
+    evicted_images: HashMap<usize, Weak<Image>>,
+    next_id: usize,
+}
+
+impl DecodedImages {
+    pub fn new(max_size: usize) -> DecodedImages {
+        DecodedImages {
+            images: SizedLRUCache::new(max_size),
+            evicted_images: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Returns the number of bytes of pixels in the cache.
+    pub fn size(&self) -> usize {
+        self.images.size()
+    }
+
+    /// Returns the number of images that were dropped from the cache and are still in use.
+    pub fn evicted_image_count(&self) -> usize {
+        self.evicted_images.len()
+    }
+
+    /// Keeps a decoded image, dropping the least recently used images if the cache grows too
+    /// large.
+    fn insert(&mut self, id: usize, image: Option<Arc<Image>>) {
+        self.evicted_images.remove(&id);
+        let size = size_of(&image);
+        for (id, image) in self.images.insert(id, image, size).into_iter() {
+            if let Some(ref image) = image {
+                self.evicted_images.insert(id, image.downgrade());
+            }
+        }
+
+        // Forget the dropped images that nothing holds any more.
+        let dead_ids: Vec<usize> = self.evicted_images.iter().filter(|&(_, image)| {
+            image.upgrade().is_none()
+        }).map(|(&id, _)| id).collect();
+        for id in dead_ids.iter() {
+            self.evicted_images.remove(id);
+        }
+    }
+}
+
+impl LazyImage {
+    /// Creates an image that decodes `data` when its pixels are first needed.
+    pub fn new(data: Vec<u8>, width: u32, height: u32, cache: &LazyImageCache) -> LazyImage {
+        let id = {
+            let mut cache = cache.lock().unwrap();
+            cache.next_id += 1;
+            cache.next_id
+        };
+        LazyImage {
+            width: width,
+            height: height,
+            is_opaque: false,
+            is_animated: false,
+            data: Arc::new(data),
+            id: id,
+            cache: cache.clone(),
+        }
+    }
+
+    /// Creates an image from `data` that has already been decoded into `image`, which the cache
+    /// keeps until it grows too large.
+    pub fn from_image(data: Vec<u8>, image: Arc<Image>, cache: &LazyImageCache) -> LazyImage {
+        let mut lazy_image = LazyImage::new(data, image.width, image.height, cache);
+        lazy_image.is_opaque = image.is_opaque;
+        lazy_image.is_animated = image.animation.is_some();
+        cache.lock().unwrap().insert(lazy_image.id, Some(image));
+        lazy_image
+    }
+
+    /// Returns the frames of the image if it is animated, decoding it if it isn't in the cache.
+    pub fn animation(&self) -> Option<ImageFrames> {
+        if !self.is_animated {
+            return None
+        }
+        self.decode().and_then(|image| image.animation.clone())
+    }
+
+    /// Returns the encoded data of the image.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    /// Returns the decoded image, decoding it if it isn't in the cache. Returns `None` if the
+    /// data can't be decoded. The cache isn't locked while the image is decoded, so that threads
+    /// that need other images don't wait for it.
+    pub fn decode(&self) -> Option<Arc<Image>> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(image) = cache.images.find(&self.id) {
+                return image
+            }
+            let evicted_image = cache.evicted_images.remove(&self.id).and_then(|image| {
+                image.upgrade()
+            });
+            if let Some(image) = evicted_image {
+                cache.insert(self.id, Some(image.clone()));
+                return Some(image)
+            }
+        }
+
+        let image = load_from_memory(&self.data[..]).map(Arc::new);
+        self.cache.lock().unwrap().insert(self.id, image.clone());
+        image
+    }
+}
+
+/// Returns the number of bytes of pixels that a decoded image and its frames take up.
+fn size_of(image: &Option<Arc<Image>>) -> usize {
+    match *image {
+        Some(ref image) => {
+            let frame_count = image.animation.as_ref().map_or(0, |animation| {
+                animation.frames.len()
+            });
+            image.width as usize * image.height as usize * 4 * (1 + frame_count)
+        }
+        None => 0,
+    }
+}
+
+/// Decodes the frames of a GIF, or returns `None` if it isn't animated.
+///
+/// TODO: Decode the frames of animated PNGs too.
+fn gif_frames(buffer: &[u8]) -> Option<ImageFrames> {
+    let animation = match gif::decode_animation(buffer) {
+        Some(animation) => animation,
+        None => return None,
+    };
+    let (width, height) = (animation.width, animation.height);
+    let frames = animation.frames.into_iter().map(|mut frame| {
+        byte_swap_and_premultiply(&mut frame.pixels);
+        ImageFrame {
+            image: Arc::new(Image::new(width, height, png::PixelsByColorType::RGBA8(frame.pixels))),
+            delay: frame.delay,
+        }
+    }).collect();
+    Some(ImageFrames {
+        frames: Arc::new(frames),
+        repeat: animation.repeat,
+        decode_time: precise_time_ns(),
+    })
+}
+
 fn is_gif(buffer: &[u8]) -> bool {
     match buffer {
         [b'G',b'I',b'F',b'8', n, b'a', ..] if n == b'7' || n == b'9' => true,
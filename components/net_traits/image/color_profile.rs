@@ -0,0 +1,345 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Conversion of images with embedded ICC color profiles to sRGB.
+//!
+//! Only RGB matrix/TRC profiles are supported: three primaries in the D50 profile connection
+//! space and a tone reproduction curve per channel. These cover the profiles that cameras and
+//! image editors embed in practice (Display P3, Adobe RGB, ProPhoto and the like). Pixels are
+//! converted once when the image is decoded, so the converted data is what the image cache keeps
+//! and what every display item draws from.
+
+use flate2::read::ZlibDecoder;
+use image::jpeg;
+use std::io::Read;
+use std::iter::range_step;
+use std::num::Float;
+
+/// The number of entries in the table that encodes linear values to sRGB.
+static ENCODE_TABLE_SIZE: usize = 4096;
+
+/// The largest profile we are willing to inflate out of a PNG `iCCP` chunk.
+static MAX_PNG_PROFILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The matrix from the D50 XYZ connection space to linear sRGB, with Bradford adaptation.
+static XYZ_D50_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+    [ 3.1338561, -1.6168667, -0.4906146],
+    [-0.9787684,  1.9161415,  0.0334540],
+    [ 0.0719453, -0.2289914,  1.4052427],
+];
+
+/// An RGB color profile reduced to what is needed to convert 8-bit pixels to sRGB.
+pub struct ColorProfile {
+    /// The linear value of each 8-bit input value, per channel.
+    linearize: [[f32; 256]; 3],
+    /// The matrix from linear profile RGB to linear sRGB.
+    to_linear_srgb: [[f32; 3]; 3],
+}
+
+impl ColorProfile {
+    /// Parses an ICC profile. Returns `None` for profiles that aren't RGB matrix/TRC profiles.
+    pub fn parse(data: &[u8]) -> Option<ColorProfile> {
+        // The color space of the data and the profile connection space.
+        if read_u32(data, 16) != Some(tag(b"RGB ")) || read_u32(data, 20) != Some(tag(b"XYZ ")) {
+            return None
+        }
+
+        let primaries = [find_tag(data, tag(b"rXYZ")).and_then(|tag| read_xyz(data, tag)),
+                         find_tag(data, tag(b"gXYZ")).and_then(|tag| read_xyz(data, tag)),
+                         find_tag(data, tag(b"bXYZ")).and_then(|tag| read_xyz(data, tag))];
+        let curves = [find_tag(data, tag(b"rTRC")).and_then(|tag| read_curve(data, tag)),
+                      find_tag(data, tag(b"gTRC")).and_then(|tag| read_curve(data, tag)),
+                      find_tag(data, tag(b"bTRC")).and_then(|tag| read_curve(data, tag))];
+
+        // The primaries are the columns of the matrix from linear RGB to XYZ.
+        let mut to_xyz = [[0.0; 3]; 3];
+        for (channel, primary) in primaries.iter().enumerate() {
+            match *primary {
+                Some(xyz) => {
+                    for row in 0..3 {
+                        to_xyz[row][channel] = xyz[row];
+                    }
+                }
+                None => return None,
+            }
+        }
+
+        let mut linearize = [[0.0; 256]; 3];
+        for (channel, curve) in curves.iter().enumerate() {
+            match *curve {
+                Some(ref curve) => {
+                    for value in 0..256 {
+                        linearize[channel][value] = curve.evaluate(value as f32 / 255.0);
+                    }
+                }
+                None => return None,
+            }
+        }
+
+        Some(ColorProfile {
+            linearize: linearize,
+            to_linear_srgb: multiply(&XYZ_D50_TO_LINEAR_SRGB, &to_xyz),
+        })
+    }
+
+    /// Converts unpremultiplied RGBA pixels in place from this profile to sRGB.
+    pub fn convert_rgba_to_srgb(&self, data: &mut [u8]) {
+        self.convert_to_srgb(data, 4)
+    }
+
+    /// Converts RGB pixels in place from this profile to sRGB.
+    pub fn convert_rgb_to_srgb(&self, data: &mut [u8]) {
+        self.convert_to_srgb(data, 3)
+    }
+
+    fn convert_to_srgb(&self, data: &mut [u8], bytes_per_pixel: usize) {
+        let encode: Vec<u8> = (0..ENCODE_TABLE_SIZE).map(|index| {
+            let linear = index as f32 / (ENCODE_TABLE_SIZE - 1) as f32;
+            let encoded = if linear <= 0.0031308 {
+                linear * 12.92
+            } else {
+                1.055 * linear.powf(1.0 / 2.4) - 0.055
+            };
+            (encoded * 255.0 + 0.5) as u8
+        }).collect();
+
+        let matrix = &self.to_linear_srgb;
+        let end = data.len() - data.len() % bytes_per_pixel;
+        for i in range_step(0, end, bytes_per_pixel) {
+            let rgb = [self.linearize[0][data[i] as usize],
+                       self.linearize[1][data[i + 1] as usize],
+                       self.linearize[2][data[i + 2] as usize]];
+            for row in 0..3 {
+                let linear = matrix[row][0] * rgb[0] + matrix[row][1] * rgb[1] +
+                    matrix[row][2] * rgb[2];
+                let clamped = if linear < 0.0 { 0.0 } else if linear > 1.0 { 1.0 } else { linear };
+                data[i + row] = encode[(clamped * (ENCODE_TABLE_SIZE - 1) as f32) as usize];
+            }
+        }
+    }
+}
+
+/// Collects the ICC profile embedded in the `APP2` segments of a JPEG file, if any.
+pub fn jpeg_icc_profile(buffer: &[u8]) -> Option<Vec<u8>> {
+    static ICC_MARKER: &'static [u8] = b"ICC_PROFILE\0";
+    static APP2: u8 = 0xe2;
+
+    // The profile may be split over several segments, each with its sequence number.
+    let mut chunks: Vec<(u8, &[u8])> = vec!();
+    for segment in jpeg::segments(buffer, APP2).into_iter() {
+        if segment.len() > ICC_MARKER.len() + 2 && &segment[..ICC_MARKER.len()] == ICC_MARKER {
+            chunks.push((segment[ICC_MARKER.len()], &segment[ICC_MARKER.len() + 2..]));
+        }
+    }
+
+    if chunks.is_empty() {
+        return None
+    }
+    chunks.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut profile = vec!();
+    for &(_, chunk) in chunks.iter() {
+        profile.extend(chunk.iter().cloned());
+    }
+    Some(profile)
+}
+
+/// Inflates the ICC profile in the `iCCP` chunk of a PNG file, if any.
+pub fn png_icc_profile(buffer: &[u8]) -> Option<Vec<u8>> {
+    static SIGNATURE_LENGTH: usize = 8;
+
+    // Each chunk is its length, its type, its data and a CRC. The profile has to come before the
+    // image data.
+    let mut offset = SIGNATURE_LENGTH;
+    loop {
+        let (length, kind) = match (read_u32(buffer, offset), read_u32(buffer, offset + 4)) {
+            (Some(length), Some(kind)) => (length as usize, kind),
+            _ => return None,
+        };
+        let start = offset + 8;
+        if length > buffer.len() - start {
+            return None
+        }
+        let data = &buffer[start..start + length];
+        if kind == tag(b"IDAT") || kind == tag(b"IEND") {
+            return None
+        }
+        if kind == tag(b"iCCP") {
+            // A profile name of 1 to 79 bytes, a null separator, the compression method (only 0,
+            // zlib, is defined) and the compressed profile.
+            let name_length = match data.iter().position(|&byte| byte == 0) {
+                Some(name_length) if name_length < data.len() - 1 => name_length,
+                _ => return None,
+            };
+            if data[name_length + 1] != 0 {
+                return None
+            }
+            let decoder = ZlibDecoder::new(&data[name_length + 2..]);
+            let mut profile = vec!();
+            return match decoder.take(MAX_PNG_PROFILE_SIZE).read_to_end(&mut profile) {
+                Ok(_) => Some(profile),
+                Err(_) => None,
+            }
+        }
+        offset = start + length + 4;
+    }
+}
+
+/// A tone reproduction curve, which maps encoded channel values to linear ones.
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// The `g, a, b, c, d, e, f` parameters of an ICC parametric curve.
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    fn evaluate(&self, x: f32) -> f32 {
+        match *self {
+            Curve::Gamma(gamma) => x.powf(gamma),
+            Curve::Table(ref table) => {
+                let position = x * (table.len() - 1) as f32;
+                let index = position.floor() as usize;
+                if index + 1 >= table.len() {
+                    return table[table.len() - 1]
+                }
+                let fraction = position - index as f32;
+                table[index] + (table[index + 1] - table[index]) * fraction
+            }
+            Curve::Parametric(ref p) => {
+                // (aX + b)^g + e for X >= d, and cX + f otherwise.
+                if x >= p[4] {
+                    let base = p[1] * x + p[2];
+                    (if base > 0.0 { base.powf(p[0]) } else { 0.0 }) + p[5]
+                } else {
+                    p[3] * x + p[6]
+                }
+            }
+        }
+    }
+}
+
+fn tag(name: &[u8; 4]) -> u32 {
+    (name[0] as u32) << 24 | (name[1] as u32) << 16 | (name[2] as u32) << 8 | name[3] as u32
+}
+
+/// Returns the offset of the tag's data.
+fn find_tag(data: &[u8], signature: u32) -> Option<usize> {
+    let count = match read_u32(data, 128) {
+        Some(count) => count as usize,
+        None => return None,
+    };
+    // The tag table has to fit in the profile; don't trust the count before checking that.
+    if count > (data.len() - 132) / 12 {
+        return None
+    }
+    for index in 0..count {
+        let entry = 132 + index * 12;
+        if read_u32(data, entry) == Some(signature) {
+            return read_u32(data, entry + 4).map(|offset| offset as usize)
+        }
+    }
+    None
+}
+
+fn read_xyz(data: &[u8], offset: usize) -> Option<[f32; 3]> {
+    if read_u32(data, offset) != Some(tag(b"XYZ ")) {
+        return None
+    }
+    match (read_s15_fixed16(data, offset + 8),
+           read_s15_fixed16(data, offset + 12),
+           read_s15_fixed16(data, offset + 16)) {
+        (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+        _ => None,
+    }
+}
+
+fn read_curve(data: &[u8], offset: usize) -> Option<Curve> {
+    let signature = read_u32(data, offset);
+    if signature == Some(tag(b"curv")) {
+        let count = match read_u32(data, offset + 8) {
+            Some(count) => count as usize,
+            None => return None,
+        };
+        match count {
+            0 => Some(Curve::Gamma(1.0)),
+            // A single entry is a gamma exponent in u8Fixed8Number format.
+            1 => read_u16(data, offset + 12).map(|gamma| Curve::Gamma(gamma as f32 / 256.0)),
+            // The entries have to fit in the profile before we allocate room for them.
+            _ if offset + 12 > data.len() || count > (data.len() - offset - 12) / 2 => None,
+            _ => {
+                let mut table = Vec::with_capacity(count);
+                for index in 0..count {
+                    match read_u16(data, offset + 12 + index * 2) {
+                        Some(value) => table.push(value as f32 / 65535.0),
+                        None => return None,
+                    }
+                }
+                Some(Curve::Table(table))
+            }
+        }
+    } else if signature == Some(tag(b"para")) {
+        // Each function type uses a prefix of the parameters; the others keep the values that
+        // make them have no effect.
+        let parameter_count = match read_u16(data, offset + 8) {
+            Some(0) => 1,
+            Some(1) => 3,
+            Some(2) => 4,
+            Some(3) => 5,
+            Some(4) => 7,
+            _ => return None,
+        };
+        let mut parameters = [1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for index in 0..parameter_count {
+            match read_s15_fixed16(data, offset + 12 + index * 4) {
+                Some(value) => parameters[index] = value,
+                None => return None,
+            }
+        }
+        let (g, a, b, c, d) = (parameters[0], parameters[1], parameters[2], parameters[3],
+                               parameters[4]);
+        let parameters = match parameter_count {
+            // Y = X^g.
+            1 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            // Y = (aX + b)^g for X >= -b/a, and 0 otherwise.
+            3 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+            // Y = (aX + b)^g + c for X >= -b/a, and c otherwise.
+            4 => [g, a, b, 0.0, -b / a, c, c],
+            // Y = (aX + b)^g for X >= d, and cX otherwise.
+            5 => [g, a, b, c, d, 0.0, 0.0],
+            _ => parameters,
+        };
+        Some(Curve::Parametric(parameters))
+    } else {
+        None
+    }
+}
+
+fn multiply(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut result = [[0.0; 3]; 3];
+    for row in 0..3 {
+        for column in 0..3 {
+            result[row][column] = (0..3).fold(0.0, |sum, k| sum + a[row][k] * b[k][column]);
+        }
+    }
+    result
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    if offset + 2 > data.len() {
+        return None
+    }
+    Some((data[offset] as u16) << 8 | data[offset + 1] as u16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    if offset + 4 > data.len() {
+        return None
+    }
+    Some((data[offset] as u32) << 24 | (data[offset + 1] as u32) << 16 |
+         (data[offset + 2] as u32) << 8 | data[offset + 3] as u32)
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Option<f32> {
+    read_u32(data, offset).map(|value| value as i32 as f32 / 65536.0)
+}
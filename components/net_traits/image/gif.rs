@@ -0,0 +1,361 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Decoding of the frames of animated GIFs.
+//!
+//! stb_image only decodes the first frame of a GIF, which is all that still images have. For
+//! GIFs with more frames, this decodes each of them and composites it onto the frames before it
+//! as its disposal method says, so that every frame comes out as the whole picture.
+
+use std::cmp::{max, min};
+use std::iter;
+
+/// The largest number of codes in the LZW table of a GIF, which uses codes of up to 12 bits.
+static MAX_CODES: usize = 4096;
+
+/// The most bytes of pixels that the frames of an animation are decoded into, and the most
+/// frames it has. Animations past either only show their first frame, which stb_image decodes.
+static MAX_ANIMATION_SIZE: usize = 64 * 1024 * 1024;
+static MAX_FRAMES: usize = 1000;
+
+/// The disposal methods of frames, from their graphic control extension, which say what becomes
+/// of the frame before the next one is drawn.
+static DISPOSE_TO_BACKGROUND: u8 = 2;
+static DISPOSE_TO_PREVIOUS: u8 = 3;
+
+/// The frames of an animated GIF.
+pub struct GifAnimation {
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<GifFrame>,
+    /// Whether the animation loops, however many times its application extension asks for.
+    pub repeat: bool,
+}
+
+/// A frame of an animated GIF, as the whole picture.
+pub struct GifFrame {
+    /// The pixels of the frame, as unpremultiplied RGBA.
+    pub pixels: Vec<u8>,
+    /// How long the frame is shown, in milliseconds.
+    pub delay: u32,
+}
+
+/// The graphic control extension that applies to the next image of a GIF.
+#[derive(Clone, Copy)]
+struct GraphicControl {
+    disposal: u8,
+    /// The delay in hundredths of a second.
+    delay: u16,
+    transparent_index: Option<u8>,
+}
+
+impl GraphicControl {
+    fn new() -> GraphicControl {
+        GraphicControl {
+            disposal: 0,
+            delay: 0,
+            transparent_index: None,
+        }
+    }
+}
+
+/// Reads the GIF in `data` and returns its frames, or `None` if it has fewer than two, isn't a
+/// GIF, is too broken to show more than one frame of or is too large to keep every frame of.
+/// Frames are decoded until the data ends or stops making sense, and are clipped to the logical
+/// screen.
+pub fn decode_animation(data: &[u8]) -> Option<GifAnimation> {
+    if data.len() < 13 || &data[0..3] != b"GIF" {
+        return None
+    }
+    let width = read_u16(data, 6) as usize;
+    let height = read_u16(data, 8) as usize;
+    let flags = data[10];
+    let mut offset = 13;
+    let global_color_table = if flags & 0x80 != 0 {
+        let length = 3 << ((flags & 0x07) + 1);
+        if offset + length > data.len() {
+            return None
+        }
+        offset += length;
+        Some(&data[offset - length..offset])
+    } else {
+        None
+    };
+
+    let frame_size = width * height * 4;
+    if frame_size == 0 || frame_size > MAX_ANIMATION_SIZE / 2 {
+        return None
+    }
+    let mut canvas = vec![0; frame_size];
+    let mut frames = Vec::new();
+    let mut repeat = false;
+    let mut control = GraphicControl::new();
+    while offset < data.len() {
+        match data[offset] {
+            // An extension.
+            0x21 if offset + 2 < data.len() => {
+                let label = data[offset + 1];
+                let block = &data[offset + 2..];
+                if label == 0xf9 && block.len() >= 6 && block[0] >= 4 {
+                    control = GraphicControl {
+                        disposal: (block[1] >> 2) & 0x07,
+                        delay: read_u16(block, 2),
+                        transparent_index: if block[1] & 0x01 != 0 {
+                            Some(block[4])
+                        } else {
+                            None
+                        },
+                    }
+                } else if label == 0xff && block.len() >= 12 && &block[1..12] == b"NETSCAPE2.0" {
+                    repeat = true
+                }
+                offset = skip_sub_blocks(data, offset + 2)
+            }
+
+            // An image.
+            0x2c if offset + 10 <= data.len() => {
+                if frames.len() == MAX_FRAMES ||
+                        (frames.len() + 1) * frame_size > MAX_ANIMATION_SIZE {
+                    return None
+                }
+                let left = read_u16(data, offset + 1) as usize;
+                let top = read_u16(data, offset + 3) as usize;
+                let frame_width = read_u16(data, offset + 5) as usize;
+                let frame_height = read_u16(data, offset + 7) as usize;
+                let flags = data[offset + 9];
+                offset += 10;
+                let color_table = if flags & 0x80 != 0 {
+                    let length = 3 << ((flags & 0x07) + 1);
+                    if offset + length > data.len() {
+                        break
+                    }
+                    offset += length;
+                    Some(&data[offset - length..offset])
+                } else {
+                    global_color_table
+                };
+                if offset >= data.len() {
+                    break
+                }
+
+                // Only the rows that can be on the screen are decoded, except in interlaced images,
+                // whose rows come out of order.
+                let interlaced = flags & 0x40 != 0;
+                let decoded_rows = if interlaced {
+                    frame_height
+                } else {
+                    min(frame_height, if top < height { height - top } else { 0 })
+                };
+                if frame_width * decoded_rows > MAX_ANIMATION_SIZE {
+                    return None
+                }
+
+                let min_code_size = data[offset];
+                let mut compressed = Vec::new();
+                offset = read_sub_blocks(data, offset + 1, &mut compressed);
+                let indices = match decompress(&compressed[..],
+                                                min_code_size,
+                                                frame_width * decoded_rows) {
+                    Some(indices) => indices,
+                    None => break,
+                };
+
+                let previous_canvas = if control.disposal == DISPOSE_TO_PREVIOUS {
+                    Some(canvas.clone())
+                } else {
+                    None
+                };
+                if let Some(color_table) = color_table {
+                    let visible_width = if left < width { width - left } else { 0 };
+                    // Images without pixels have no rows.
+                    for (index, row) in indices.chunks(max(frame_width, 1)).enumerate() {
+                        let y = top + if interlaced {
+                            interlaced_row(index, frame_height)
+                        } else {
+                            index
+                        };
+                        if y >= height {
+                            continue
+                        }
+                        for (x, &color_index) in row.iter().take(visible_width).enumerate() {
+                            let x = left + x;
+                            let color = color_index as usize * 3;
+                            if Some(color_index) == control.transparent_index ||
+                                    color + 3 > color_table.len() {
+                                continue
+                            }
+                            let pixel = &mut canvas[(y * width + x) * 4..(y * width + x + 1) * 4];
+                            pixel[0] = color_table[color];
+                            pixel[1] = color_table[color + 1];
+                            pixel[2] = color_table[color + 2];
+                            pixel[3] = 0xff;
+                        }
+                    }
+                }
+                frames.push(GifFrame {
+                    pixels: canvas.clone(),
+                    delay: control.delay as u32 * 10,
+                });
+
+                // Dispose of the frame before the next one is drawn.
+                if let Some(previous_canvas) = previous_canvas {
+                    canvas = previous_canvas
+                } else if control.disposal == DISPOSE_TO_BACKGROUND {
+                    for y in top..min(top + frame_height, height) {
+                        for x in left..min(left + frame_width, width) {
+                            for channel in 0..4 {
+                                canvas[(y * width + x) * 4 + channel] = 0
+                            }
+                        }
+                    }
+                }
+                control = GraphicControl::new()
+            }
+
+            // The trailer, or something that doesn't belong.
+            _ => break,
+        }
+    }
+
+    if frames.len() < 2 {
+        return None
+    }
+    Some(GifAnimation {
+        width: width as u32,
+        height: height as u32,
+        frames: frames,
+        repeat: repeat,
+    })
+}
+
+/// Returns the row of an interlaced image that its `index`th row of data goes in. Interlaced
+/// images store every eighth row from the first, then every eighth from the fifth, then every
+/// fourth from the third, then every other one from the second.
+fn interlaced_row(index: usize, height: usize) -> usize {
+    let passes = [(0, 8), (4, 8), (2, 4), (1, 2)];
+    let mut index = index;
+    for &(start, step) in passes.iter() {
+        let rows = (height + step - 1 - start) / step;
+        if index < rows {
+            return start + index * step
+        }
+        index -= rows
+    }
+    height
+}
+
+/// Decompresses the LZW data of an image into `pixel_count` color indices. Returns `None` if
+/// the data uses codes that it hasn't defined. Images whose data ends early are left with
+/// indices of zero.
+fn decompress(data: &[u8], min_code_size: u8, pixel_count: usize) -> Option<Vec<u8>> {
+    if min_code_size < 1 || min_code_size > 11 {
+        return None
+    }
+    let clear_code = 1 << min_code_size;
+    let end_code = clear_code + 1;
+
+    // Each code stands for the string of its prefix followed by its suffix.
+    let mut prefixes = vec![0u16; MAX_CODES];
+    let mut suffixes = vec![0u8; MAX_CODES];
+    let mut first_bytes = vec![0u8; MAX_CODES];
+    for code in 0..clear_code {
+        suffixes[code] = code as u8;
+        first_bytes[code] = code as u8;
+    }
+
+    let mut result = Vec::with_capacity(pixel_count);
+    let mut string = Vec::new();
+    let mut code_size = min_code_size as usize + 1;
+    let mut next_code = end_code + 1;
+    let mut previous_code: Option<usize> = None;
+    let (mut bits, mut bit_count, mut position) = (0u32, 0, 0);
+    while result.len() < pixel_count {
+        while bit_count < code_size && position < data.len() {
+            bits |= (data[position] as u32) << bit_count;
+            bit_count += 8;
+            position += 1;
+        }
+        if bit_count < code_size {
+            break
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear_code {
+            code_size = min_code_size as usize + 1;
+            next_code = end_code + 1;
+            previous_code = None;
+            continue
+        }
+        if code == end_code {
+            break
+        }
+
+        let previous = match previous_code {
+            None if code < clear_code => {
+                result.push(code as u8);
+                previous_code = Some(code);
+                continue
+            }
+            None => return None,
+            Some(previous) => previous,
+        };
+        let first_byte = if code < next_code {
+            first_bytes[code]
+        } else if code == next_code {
+            first_bytes[previous]
+        } else {
+            return None
+        };
+        if next_code < MAX_CODES {
+            prefixes[next_code] = previous as u16;
+            suffixes[next_code] = first_byte;
+            first_bytes[next_code] = first_bytes[previous];
+            next_code += 1;
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1
+            }
+        }
+
+        // Write the string of the code, which is built from its end.
+        string.clear();
+        let mut string_code = code;
+        while string_code > end_code {
+            string.push(suffixes[string_code]);
+            string_code = prefixes[string_code] as usize
+        }
+        string.push(suffixes[string_code]);
+        result.extend(string.iter().rev().cloned());
+        previous_code = Some(code)
+    }
+
+    result.truncate(pixel_count);
+    let length = result.len();
+    result.extend(iter::repeat(0).take(pixel_count - length));
+    Some(result)
+}
+
+/// Appends the data of the sub-blocks that start at `offset` to `result`, and returns the offset
+/// after them.
+fn read_sub_blocks(data: &[u8], mut offset: usize, result: &mut Vec<u8>) -> usize {
+    while offset < data.len() && data[offset] != 0 {
+        let end = min(offset + 1 + data[offset] as usize, data.len());
+        result.push_all(&data[offset + 1..end]);
+        offset = end
+    }
+    offset + 1
+}
+
+/// Returns the offset after the sub-blocks that start at `offset`.
+fn skip_sub_blocks(data: &[u8], mut offset: usize) -> usize {
+    while offset < data.len() && data[offset] != 0 {
+        offset += 1 + data[offset] as usize
+    }
+    offset + 1
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
@@ -0,0 +1,101 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The metadata that JPEG files keep in their application segments, before the image data.
+
+/// The segment that EXIF data is kept in.
+static APP1: u8 = 0xe1;
+static START_OF_SCAN: u8 = 0xda;
+
+/// The EXIF tag of the orientation of the image.
+static ORIENTATION_TAG: u16 = 0x0112;
+
+/// Returns the contents of the segments with the given marker in a JPEG file, in order. Buffers
+/// that aren't JPEG files have none.
+pub fn segments(buffer: &[u8], marker: u8) -> Vec<&[u8]> {
+    let mut result = vec!();
+    if buffer.len() < 4 || buffer[0] != 0xff || buffer[1] != 0xd8 {
+        return result
+    }
+
+    let mut offset = 2;
+    while offset + 4 <= buffer.len() && buffer[offset] == 0xff {
+        let segment_marker = buffer[offset + 1];
+        if segment_marker == START_OF_SCAN {
+            break
+        }
+        let length = (buffer[offset + 2] as usize) << 8 | buffer[offset + 3] as usize;
+        let end = offset + 2 + length;
+        if length < 2 || end > buffer.len() {
+            break
+        }
+        if segment_marker == marker {
+            result.push(&buffer[offset + 4..end])
+        }
+        offset = end;
+    }
+    result
+}
+
+/// Returns the value of the EXIF `Orientation` tag of a JPEG file, which says how the image is
+/// turned to be shown upright, or `None` if the file doesn't have one.
+pub fn orientation(buffer: &[u8]) -> Option<u16> {
+    static EXIF_HEADER: &'static [u8] = b"Exif\0\0";
+
+    for segment in segments(buffer, APP1).into_iter() {
+        if segment.len() < EXIF_HEADER.len() || &segment[..EXIF_HEADER.len()] != EXIF_HEADER {
+            continue
+        }
+
+        // The EXIF data is a TIFF file, whose first directory describes the image.
+        let tiff = &segment[EXIF_HEADER.len()..];
+        if tiff.len() < 2 {
+            continue
+        }
+        let big_endian = match (tiff[0], tiff[1]) {
+            (b'M', b'M') => true,
+            (b'I', b'I') => false,
+            _ => continue,
+        };
+        if read_u16(tiff, 2, big_endian) != Some(42) {
+            continue
+        }
+        let directory = match read_u32(tiff, 4, big_endian) {
+            Some(directory) => directory as usize,
+            None => continue,
+        };
+        let entry_count = match read_u16(tiff, directory, big_endian) {
+            Some(entry_count) => entry_count as usize,
+            None => continue,
+        };
+        for entry in 0..entry_count {
+            let entry_offset = directory + 2 + entry * 12;
+            if read_u16(tiff, entry_offset, big_endian) == Some(ORIENTATION_TAG) {
+                // The value is a short at the start of the four bytes of the entry's value.
+                return read_u16(tiff, entry_offset + 8, big_endian)
+            }
+        }
+    }
+    None
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    if offset + 2 > data.len() {
+        return None
+    }
+    let (first, second) = (data[offset] as u16, data[offset + 1] as u16);
+    Some(if big_endian {
+        first << 8 | second
+    } else {
+        second << 8 | first
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    match (read_u16(data, offset, big_endian), read_u16(data, offset + 2, big_endian)) {
+        (Some(first), Some(second)) if big_endian => Some((first as u32) << 16 | second as u32),
+        (Some(first), Some(second)) => Some((second as u32) << 16 | first as u32),
+        _ => None,
+    }
+}
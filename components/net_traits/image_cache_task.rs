@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use image::base::Image;
+use image::base::{Image, LazyImage};
 use url::Url;
 use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender};
@@ -47,6 +47,10 @@ pub enum ImageCacheCommand {
     /// layout / paint task.
     GetImageIfAvailable(Url, Sender<Result<Arc<Image>, ImageState>>),
 
+    /// Synchronously get an image from the cache without decoding it, so that it is only decoded
+    /// if it is painted.
+    GetLazyImageIfAvailable(Url, Sender<Result<LazyImage, ImageState>>),
+
     /// Clients must wait for a response before shutting down the ResourceTask
     Exit(Sender<()>),
 }
@@ -85,6 +89,14 @@ impl ImageCacheTask {
         receiver.recv().unwrap()
     }
 
+    /// Get an image without decoding it. See ImageCacheCommand::GetLazyImageIfAvailable.
+    pub fn get_lazy_image_if_available(&self, url: Url) -> Result<LazyImage, ImageState> {
+        let (sender, receiver) = channel();
+        let msg = ImageCacheCommand::GetLazyImageIfAvailable(url, sender);
+        self.chan.send(msg).unwrap();
+        receiver.recv().unwrap()
+    }
+
     /// Shutdown the image cache task.
     pub fn exit(&self) {
         let (response_chan, response_port) = channel();
@@ -2,17 +2,21 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+#![feature(alloc)]
 #![feature(box_syntax)]
 #![feature(collections)]
 #![feature(core)]
+#![feature(io)]
 #![feature(rustc_private)]
 
+extern crate flate2;
 extern crate geom;
 extern crate hyper;
 #[macro_use]
 extern crate log;
 extern crate png;
 extern crate stb_image;
+extern crate time;
 extern crate url;
 extern crate util;
 
@@ -35,6 +39,9 @@ pub mod storage_task;
 /// caching is involved) and as a result it must live in here.
 pub mod image {
     pub mod base;
+    pub mod color_profile;
+    pub mod gif;
+    pub mod jpeg;
 }
 
 #[derive(Clone)]
@@ -5,10 +5,13 @@
 //! Timing functions.
 
 use collections::BTreeMap;
-use profile_traits::time::{ProfilerCategory, ProfilerChan, ProfilerMsg, TimerMetadata};
+use profile_traits::time::{ProfilerCategory, ProfilerChan, ProfilerMsg, TimelineMarker};
+use profile_traits::time::TimerMetadata;
 use std::borrow::ToOwned;
 use std::cmp::Ordering;
 use std::f64;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::old_io::timer::sleep;
 use std::iter::AdditiveIterator;
 use std::num::Float;
@@ -56,32 +59,56 @@ impl Formattable for ProfilerCategory {
             ProfilerCategory::LayoutShaping |
             ProfilerCategory::LayoutDamagePropagate |
             ProfilerCategory::PaintingPerTile |
+            ProfilerCategory::PaintingDisplayListReceipt |
+            ProfilerCategory::PaintingUpload |
             ProfilerCategory::PaintingPrepBuff => "+ ",
             ProfilerCategory::LayoutParallelWarmup |
             ProfilerCategory::LayoutSelectorMatch |
-            ProfilerCategory::LayoutTreeBuilder => "| + ",
+            ProfilerCategory::LayoutTreeBuilder |
+            ProfilerCategory::PaintingOptimization |
+            ProfilerCategory::PaintingBackgroundsAndBorders |
+            ProfilerCategory::PaintingNegativeZIndexDescendants |
+            ProfilerCategory::PaintingBlockBackgroundsAndBorders |
+            ProfilerCategory::PaintingFloats |
+            ProfilerCategory::PaintingContent |
+            ProfilerCategory::PaintingPositionedDescendants |
+            ProfilerCategory::PaintingOutlines => "| + ",
             _ => ""
         };
-        let name = match *self {
-            ProfilerCategory::Compositing => "Compositing",
-            ProfilerCategory::LayoutPerform => "Layout",
-            ProfilerCategory::LayoutStyleRecalc => "Style Recalc",
-            ProfilerCategory::LayoutRestyleDamagePropagation => "Restyle Damage Propagation",
-            ProfilerCategory::LayoutNonIncrementalReset => "Non-incremental reset (temporary)",
-            ProfilerCategory::LayoutSelectorMatch => "Selector Matching",
-            ProfilerCategory::LayoutTreeBuilder => "Tree Building",
-            ProfilerCategory::LayoutDamagePropagate => "Damage Propagation",
-            ProfilerCategory::LayoutGeneratedContent => "Generated Content Resolution",
-            ProfilerCategory::LayoutMain => "Primary Layout Pass",
-            ProfilerCategory::LayoutParallelWarmup => "Parallel Warmup",
-            ProfilerCategory::LayoutShaping => "Shaping",
-            ProfilerCategory::LayoutDispListBuild => "Display List Construction",
-            ProfilerCategory::PaintingPerTile => "Painting Per Tile",
-            ProfilerCategory::PaintingPrepBuff => "Buffer Prep",
-            ProfilerCategory::Painting => "Painting",
-            ProfilerCategory::ImageDecoding => "Image Decoding",
-        };
-        format!("{}{}", padding, name)
+        format!("{}{}", padding, category_name(self))
+    }
+}
+
+/// Returns the name of the category, as it is shown in printouts and timelines.
+fn category_name(category: &ProfilerCategory) -> &'static str {
+    match *category {
+        ProfilerCategory::Compositing => "Compositing",
+        ProfilerCategory::LayoutPerform => "Layout",
+        ProfilerCategory::LayoutStyleRecalc => "Style Recalc",
+        ProfilerCategory::LayoutRestyleDamagePropagation => "Restyle Damage Propagation",
+        ProfilerCategory::LayoutNonIncrementalReset => "Non-incremental reset (temporary)",
+        ProfilerCategory::LayoutSelectorMatch => "Selector Matching",
+        ProfilerCategory::LayoutTreeBuilder => "Tree Building",
+        ProfilerCategory::LayoutDamagePropagate => "Damage Propagation",
+        ProfilerCategory::LayoutGeneratedContent => "Generated Content Resolution",
+        ProfilerCategory::LayoutMain => "Primary Layout Pass",
+        ProfilerCategory::LayoutParallelWarmup => "Parallel Warmup",
+        ProfilerCategory::LayoutShaping => "Shaping",
+        ProfilerCategory::LayoutDispListBuild => "Display List Construction",
+        ProfilerCategory::PaintingPerTile => "Painting Per Tile",
+        ProfilerCategory::PaintingDisplayListReceipt => "Display List Receipt",
+        ProfilerCategory::PaintingOptimization => "Display List Optimization",
+        ProfilerCategory::PaintingBackgroundsAndBorders => "Backgrounds and Borders",
+        ProfilerCategory::PaintingNegativeZIndexDescendants => "Negative Z-index Descendants",
+        ProfilerCategory::PaintingBlockBackgroundsAndBorders => "Block Backgrounds and Borders",
+        ProfilerCategory::PaintingFloats => "Floats",
+        ProfilerCategory::PaintingContent => "Content",
+        ProfilerCategory::PaintingPositionedDescendants => "Positioned Descendants",
+        ProfilerCategory::PaintingOutlines => "Outlines",
+        ProfilerCategory::PaintingUpload => "Surface Upload",
+        ProfilerCategory::PaintingPrepBuff => "Buffer Prep",
+        ProfilerCategory::Painting => "Painting",
+        ProfilerCategory::ImageDecoding => "Image Decoding",
     }
 }
 
@@ -92,30 +119,28 @@ pub struct Profiler {
     pub port: Receiver<ProfilerMsg>,
     buckets: ProfilerBuckets,
     pub last_msg: Option<ProfilerMsg>,
+    /// The markers that have been reported, and the file to write them to on exit, if a timeline
+    /// is being kept.
+    timeline: Option<(String, Vec<TimelineMarker>)>,
 }
 
 impl Profiler {
-    pub fn create(period: Option<f64>) -> ProfilerChan {
+    pub fn create(period: Option<f64>, timeline_file: Option<String>) -> ProfilerChan {
         let (chan, port) = channel();
-        match period {
-            Some(period) => {
-                let period = Duration::milliseconds((period * 1000f64) as i64);
-                let chan = chan.clone();
-                spawn_named("Time profiler timer".to_owned(), move || {
-                    loop {
-                        sleep(period);
-                        if chan.send(ProfilerMsg::Print).is_err() {
-                            break;
-                        }
+        if let Some(period) = period {
+            let period = Duration::milliseconds((period * 1000f64) as i64);
+            let chan = chan.clone();
+            spawn_named("Time profiler timer".to_owned(), move || {
+                loop {
+                    sleep(period);
+                    if chan.send(ProfilerMsg::Print).is_err() {
+                        break;
                     }
-                });
-                // Spawn the time profiler.
-                spawn_named("Time profiler".to_owned(), move || {
-                    let mut profiler = Profiler::new(port);
-                    profiler.start();
-                });
-            }
-            None => {
+                }
+            });
+        }
+        match (period, timeline_file) {
+            (None, None) => {
                 // No-op to handle messages when the time profiler is inactive.
                 spawn_named("Time profiler".to_owned(), move || {
                     loop {
@@ -126,6 +151,14 @@ impl Profiler {
                     }
                 });
             }
+            (_, timeline_file) => {
+                // Spawn the time profiler.
+                spawn_named("Time profiler".to_owned(), move || {
+                    let mut profiler = Profiler::new(port);
+                    profiler.timeline = timeline_file.map(|file| (file, Vec::new()));
+                    profiler.start();
+                });
+            }
         }
 
         ProfilerChan(chan)
@@ -136,6 +169,7 @@ impl Profiler {
             port: port,
             buckets: BTreeMap::new(),
             last_msg: None,
+            timeline: None,
         }
     }
 
@@ -165,12 +199,22 @@ impl Profiler {
     fn handle_msg(&mut self, msg: ProfilerMsg) -> bool {
         match msg.clone() {
             ProfilerMsg::Time(k, t) => self.find_or_insert(k, t),
+            ProfilerMsg::Marker(marker) => {
+                let ms = (marker.end_time - marker.start_time) as f64 / 1000000f64;
+                self.find_or_insert((marker.category.clone(), None), ms);
+                if let Some((_, ref mut markers)) = self.timeline {
+                    markers.push(marker)
+                }
+            }
             ProfilerMsg::Print => match self.last_msg {
                 // only print if more data has arrived since the last printout
-                Some(ProfilerMsg::Time(..)) => self.print_buckets(),
+                Some(ProfilerMsg::Time(..)) | Some(ProfilerMsg::Marker(..)) => self.print_buckets(),
                 _ => ()
             },
-            ProfilerMsg::Exit => return false,
+            ProfilerMsg::Exit => {
+                self.write_timeline();
+                return false
+            }
         };
         self.last_msg = Some(msg);
         true
@@ -204,6 +248,60 @@ impl Profiler {
     }
 }
 
+impl Profiler {
+    /// Writes the markers to the timeline file as complete events in the trace event format, with
+    /// times in microseconds.
+    fn write_timeline(&mut self) {
+        let (path, markers) = match self.timeline.take() {
+            Some(timeline) => timeline,
+            None => return,
+        };
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(error) => {
+                println!("couldn't create the profiler timeline {}: {}", path, error);
+                return
+            }
+        };
+
+        let mut writer = BufWriter::new(file);
+        let mut result = writer.write_all(b"{\"traceEvents\":[\n");
+        for (index, marker) in markers.iter().enumerate() {
+            result = result.and_then(|_| {
+                write!(&mut writer,
+                       "{}{{\"name\":\"{}\",\"cat\":\"paint\",\"ph\":\"X\",\"pid\":0,\
+                        \"tid\":{},\"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"subject\":\"{}\"}}}}\n",
+                       if index == 0 { "" } else { "," },
+                       category_name(&marker.category),
+                       marker.thread,
+                       marker.start_time as f64 / 1000f64,
+                       (marker.end_time - marker.start_time) as f64 / 1000f64,
+                       escape_json(&marker.subject))
+            });
+        }
+        result = result.and_then(|_| writer.write_all(b"]}\n"));
+        if let Err(error) = result {
+            println!("couldn't write the profiler timeline {}: {}", path, error);
+        }
+    }
+}
+
+/// Escapes the characters that can't be in a JSON string as they are.
+fn escape_json(string: &str) -> String {
+    let mut escaped = String::new();
+    for character in string.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if (character as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
 pub fn time<T, F>(msg: &str, callback: F) -> T
     where F: Fn() -> T
 {
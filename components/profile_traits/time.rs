@@ -30,6 +30,8 @@ impl ProfilerChan {
 pub enum ProfilerMsg {
     /// Normal message used for reporting time
     Time((ProfilerCategory, Option<TimerMetadata>), f64),
+    /// Reports a span of time for the timeline, which also counts toward the time of its category.
+    Marker(TimelineMarker),
     /// Message used to force print the profiling metrics
     Print,
     /// Tells the profiler to shut down.
@@ -53,11 +55,35 @@ pub enum ProfilerCategory {
     LayoutShaping,
     LayoutDispListBuild,
     PaintingPerTile,
+    PaintingDisplayListReceipt,
+    PaintingOptimization,
+    PaintingBackgroundsAndBorders,
+    PaintingNegativeZIndexDescendants,
+    PaintingBlockBackgroundsAndBorders,
+    PaintingFloats,
+    PaintingContent,
+    PaintingPositionedDescendants,
+    PaintingOutlines,
+    PaintingUpload,
     PaintingPrepBuff,
     Painting,
     ImageDecoding,
 }
 
+/// A span of time that some phase of work took, for a timeline of where the time of a task went.
+#[derive(Clone)]
+pub struct TimelineMarker {
+    pub category: ProfilerCategory,
+    /// What the work was done for, such as the tile or the frame that was painted.
+    pub subject: String,
+    /// The thread that did the work, as returned by `util::tid::tid()`.
+    pub thread: usize,
+    /// When the work started and ended, as returned by `time::precise_time_ns()`. Both are the
+    /// same for events that take no time, such as the arrival of a message.
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
 #[derive(Eq, PartialEq)]
 pub enum TimerMetadataFrameType {
     RootWindow,
@@ -65,11 +65,12 @@ use layout_interface::{HitTestResponse, MouseOverResponse};
 use msg::compositor_msg::ScriptListener;
 use msg::constellation_msg::Msg as ConstellationMsg;
 use msg::constellation_msg::{ConstellationChan, FocusType, Key, KeyState, KeyModifiers, MozBrowserEvent};
+use msg::constellation_msg::{IframeMouseEvent, SubpageId};
 use msg::constellation_msg::{SUPER, ALT, SHIFT, CONTROL};
 use net_traits::CookieSource::NonHTTP;
 use net_traits::ControlMsg::{SetCookiesForUrl, GetCookiesForUrl};
 use script_task::Runnable;
-use script_traits::{MouseButton, UntrustedNodeAddress};
+use script_traits::MouseButton;
 use util::opts;
 use util::str::{DOMString, split_html_space_chars};
 use layout_interface::{ReflowGoal, ReflowQueryType};
@@ -129,6 +130,9 @@ pub struct Document {
     /// https://html.spec.whatwg.org/multipage/#concept-n-noscript
     /// True if scripting is enabled for all scripts in this document
     scripting_enabled: Cell<bool>,
+    /// Increased by one every time that the content of the document changes, so that layout can
+    /// tell whether the nodes in its display list may no longer be there.
+    dom_generation: Cell<u32>,
 }
 
 impl DocumentDerived for EventTarget {
@@ -205,12 +209,16 @@ pub trait DocumentHelpers<'a> {
     fn set_encoding_name(self, name: DOMString);
     fn content_changed(self, node: JSRef<Node>, damage: NodeDamage);
     fn content_and_heritage_changed(self, node: JSRef<Node>, damage: NodeDamage);
+    /// Returns how many times the content of the document has changed.
+    fn dom_generation(self) -> u32;
     fn unregister_named_element(self, to_unregister: JSRef<Element>, id: Atom);
     fn register_named_element(self, element: JSRef<Element>, id: Atom);
     fn load_anchor_href(self, href: DOMString);
     fn find_fragment_node(self, fragid: DOMString) -> Option<Temporary<Element>>;
-    fn hit_test(self, point: &Point2D<f32>) -> Option<UntrustedNodeAddress>;
-    fn get_nodes_under_mouse(self, point: &Point2D<f32>) -> Vec<UntrustedNodeAddress>;
+    fn hit_test(self, point: &Point2D<f32>) -> Option<HitTestResponse>;
+    fn get_nodes_under_mouse(self, point: &Point2D<f32>) -> MouseOverResponse;
+    /// Hands a mouse event that hit an iframe over to the document in it.
+    fn forward_mouse_event(self, subpage_id: SubpageId, event: IframeMouseEvent);
     fn set_ready_state(self, state: DocumentReadyState);
     fn get_focused_element(self) -> Option<Temporary<Element>>;
     fn is_scripting_enabled(self) -> bool;
@@ -296,15 +304,21 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
     }
 
     fn content_changed(self, node: JSRef<Node>, damage: NodeDamage) {
+        self.dom_generation.set(self.dom_generation.get() + 1);
         node.dirty(damage);
     }
 
     fn content_and_heritage_changed(self, node: JSRef<Node>, damage: NodeDamage) {
         debug!("content_and_heritage_changed on {}", node.debug_str());
+        self.dom_generation.set(self.dom_generation.get() + 1);
         node.force_dirty_ancestors(damage);
         node.dirty(damage);
     }
 
+    fn dom_generation(self) -> u32 {
+        self.dom_generation.get()
+    }
+
     /// Remove any existing association between the provided id and any elements in this document.
     fn unregister_named_element(self,
                                 to_unregister: JSRef<Element>,
@@ -393,7 +407,7 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
         })
     }
 
-    fn hit_test(self, point: &Point2D<f32>) -> Option<UntrustedNodeAddress> {
+    fn hit_test(self, point: &Point2D<f32>) -> Option<HitTestResponse> {
         let root = self.GetDocumentElement().root();
         let root = match root.r() {
             Some(root) => root,
@@ -401,30 +415,40 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
         };
         let root = NodeCast::from_ref(root);
         let win = self.window.root();
-        let address = match win.r().layout().hit_test(root.to_trusted_node_address(), *point) {
-            Ok(HitTestResponse(node_address)) => Some(node_address),
+        match win.r().layout().hit_test(root.to_trusted_node_address(),
+                                        *point,
+                                        self.dom_generation()) {
+            Ok(response) => Some(response),
             Err(()) => {
                 debug!("layout query error");
                 None
             }
-        };
-        address
+        }
     }
 
-    fn get_nodes_under_mouse(self, point: &Point2D<f32>) -> Vec<UntrustedNodeAddress> {
+    fn get_nodes_under_mouse(self, point: &Point2D<f32>) -> MouseOverResponse {
         let root = self.GetDocumentElement().root();
         let root = match root.r() {
             Some(root) => root,
-            None => return vec!(),
+            None => return MouseOverResponse(vec!(), None),
         };
         let root: JSRef<Node> = NodeCast::from_ref(root);
         let win = self.window.root();
-        match win.r().layout().mouse_over(root.to_trusted_node_address(), *point) {
-            Ok(MouseOverResponse(node_address)) => node_address,
-            Err(()) => vec!(),
+        match win.r().layout().mouse_over(root.to_trusted_node_address(),
+                                          *point,
+                                          self.dom_generation()) {
+            Ok(response) => response,
+            Err(()) => MouseOverResponse(vec!(), None),
         }
     }
 
+    fn forward_mouse_event(self, subpage_id: SubpageId, event: IframeMouseEvent) {
+        let window = self.window.root();
+        let ConstellationChan(ref chan) = window.r().constellation_chan();
+        let event = ConstellationMsg::ForwardMouseEvent(window.r().pipeline(), subpage_id, event);
+        chan.send(event).unwrap();
+    }
+
     // https://html.spec.whatwg.org/multipage/#current-document-readiness
     fn set_ready_state(self, state: DocumentReadyState) {
         self.ready_state.set(state);
@@ -513,7 +537,7 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
     }
 
     fn handle_mouse_event(self, js_runtime: *mut JSRuntime,
-                          _button: MouseButton, point: Point2D<f32>,
+                          button: MouseButton, point: Point2D<f32>,
                           mouse_event_type: MouseEventType) {
         let mouse_event_type_string = match mouse_event_type {
             MouseEventType::Click => "click".to_owned(),
@@ -521,13 +545,23 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
             MouseEventType::MouseDown => "mousedown".to_owned(),
         };
         debug!("{}: at {:?}", mouse_event_type_string, point);
-        let node = match self.hit_test(&point) {
-            Some(node_address) => {
-                debug!("node address is {:?}", node_address.0);
-                node::from_untrusted_node_address(js_runtime, node_address)
-            },
+        let (node_address, iframe_hit) = match self.hit_test(&point) {
+            Some(HitTestResponse(node_address, iframe_hit)) => (node_address, iframe_hit),
             None => return,
-        }.root();
+        };
+
+        // The event belongs to the document in the iframe that it hit.
+        if let Some((subpage_id, point)) = iframe_hit {
+            let event = match mouse_event_type {
+                MouseEventType::Click => IframeMouseEvent::Click(button, point),
+                MouseEventType::MouseDown => IframeMouseEvent::MouseDown(button, point),
+                MouseEventType::MouseUp => IframeMouseEvent::MouseUp(button, point),
+            };
+            return self.forward_mouse_event(subpage_id, event)
+        }
+
+        debug!("node address is {:?}", node_address.0);
+        let node = node::from_untrusted_node_address(js_runtime, node_address).root();
 
         let el = match ElementCast::to_ref(node.r()) {
             Some(el) => Temporary::from_rooted(el),
@@ -590,7 +624,8 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
                                point: Point2D<f32>,
                                prev_mouse_over_targets: &mut RootedVec<JS<Node>>) {
         // Build a list of elements that are currently under the mouse.
-        let mouse_over_addresses = self.get_nodes_under_mouse(&point);
+        let MouseOverResponse(mouse_over_addresses, iframe_hit) =
+            self.get_nodes_under_mouse(&point);
         let mut mouse_over_targets: RootedVec<JS<Node>> = RootedVec::new();
         for node_address in mouse_over_addresses.iter() {
             let node = node::from_untrusted_node_address(js_runtime, *node_address);
@@ -620,8 +655,11 @@ impl<'a> DocumentHelpers<'a> for JSRef<'a, Document> {
             }
         }
 
-        // Send mousemove event to topmost target
-        if mouse_over_addresses.len() > 0 {
+        // Send mousemove event to topmost target, which is the document in the iframe if an
+        // iframe is on top.
+        if let Some((subpage_id, point)) = iframe_hit {
+            self.forward_mouse_event(subpage_id, IframeMouseEvent::MouseMove(point));
+        } else if mouse_over_addresses.len() > 0 {
             let top_most_node =
                 node::from_untrusted_node_address(js_runtime, mouse_over_addresses[0]).root();
 
@@ -870,6 +908,7 @@ impl Document {
             focused: Default::default(),
             current_script: Default::default(),
             scripting_enabled: Cell::new(true),
+            dom_generation: Cell::new(0),
         }
     }
 
@@ -81,6 +81,7 @@ pub enum ReflowReason {
     DOMContentLoaded,
     DocumentLoaded,
     ImageLoaded,
+    WebFontLoaded,
 }
 
 #[dom_struct]
@@ -606,6 +607,7 @@ impl<'a> WindowHelpers for JSRef<'a, Window> {
             script_chan: self.control_chan.clone(),
             script_join_chan: join_chan,
             id: last_reflow_id.get(),
+            dom_generation: document.r().dom_generation(),
             query_type: query_type,
         };
 
@@ -960,6 +962,7 @@ fn debug_reflow_events(goal: &ReflowGoal, query_type: &ReflowQueryType, reason:
         ReflowReason::DOMContentLoaded => "\tDOMContentLoaded",
         ReflowReason::DocumentLoaded => "\tDocumentLoaded",
         ReflowReason::ImageLoaded => "\tImageLoaded",
+        ReflowReason::WebFontLoaded => "\tWebFontLoaded",
     });
 
     println!("{}", debug_msg);
@@ -11,7 +11,7 @@ use dom::node::LayoutData;
 use geom::point::Point2D;
 use geom::rect::Rect;
 use libc::uintptr_t;
-use msg::constellation_msg::{PipelineExitType, WindowSizeData};
+use msg::constellation_msg::{PipelineExitType, SubpageId, WindowSizeData};
 use profile_traits::mem::{Reporter, ReportsChan};
 use script_traits::{ScriptControlChan, OpaqueScriptLayoutChannel, UntrustedNodeAddress};
 use std::any::Any;
@@ -77,15 +77,23 @@ pub trait LayoutRPC {
     fn content_box(&self) -> ContentBoxResponse;
     /// Requests the dimensions of all the content boxes, as in the `getClientRects()` call.
     fn content_boxes(&self) -> ContentBoxesResponse;
-    /// Requests the node containing the point of interest
-    fn hit_test(&self, node: TrustedNodeAddress, point: Point2D<f32>) -> Result<HitTestResponse, ()>;
-    fn mouse_over(&self, node: TrustedNodeAddress, point: Point2D<f32>) -> Result<MouseOverResponse, ()>;
+    /// Requests the node containing the point of interest. `dom_generation` is how many times the
+    /// document has changed; if it has changed since the display list was built, the nodes in it
+    /// may be gone, so nothing is found.
+    fn hit_test(&self, node: TrustedNodeAddress, point: Point2D<f32>, dom_generation: u32)
+                -> Result<HitTestResponse, ()>;
+    fn mouse_over(&self, node: TrustedNodeAddress, point: Point2D<f32>, dom_generation: u32)
+                  -> Result<MouseOverResponse, ()>;
 }
 
 pub struct ContentBoxResponse(pub Rect<Au>);
 pub struct ContentBoxesResponse(pub Vec<Rect<Au>>);
-pub struct HitTestResponse(pub UntrustedNodeAddress);
-pub struct MouseOverResponse(pub Vec<UntrustedNodeAddress>);
+/// The topmost node at the point and, if that is an iframe, the subpage that it shows and the
+/// point in the coordinates of the document in it.
+pub struct HitTestResponse(pub UntrustedNodeAddress, pub Option<(SubpageId, Point2D<f32>)>);
+/// The nodes at the point, topmost first, and the iframe that is topmost as in `HitTestResponse`.
+pub struct MouseOverResponse(pub Vec<UntrustedNodeAddress>,
+                             pub Option<(SubpageId, Point2D<f32>)>);
 
 /// Why we're doing reflow.
 #[derive(PartialEq, Copy, Debug)]
@@ -126,6 +134,8 @@ pub struct ScriptReflow {
     pub script_join_chan: Sender<()>,
     /// Unique identifier
     pub id: u32,
+    /// How many times the document has changed, as of this reflow.
+    pub dom_generation: u32,
     /// The type of query if any to perform during this reflow.
     pub query_type: ReflowQueryType,
 }
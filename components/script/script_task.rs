@@ -712,6 +712,8 @@ impl ScriptTask {
                 panic!("should have handled ExitPipeline already"),
             ConstellationControlMsg::GetTitle(pipeline_id) =>
                 self.handle_get_title_msg(pipeline_id),
+            ConstellationControlMsg::WebFontLoaded(pipeline_id) =>
+                self.handle_web_font_loaded(pipeline_id),
             ConstellationControlMsg::Freeze(pipeline_id) =>
                 self.handle_freeze_msg(pipeline_id),
             ConstellationControlMsg::Thaw(pipeline_id) =>
@@ -1001,6 +1003,13 @@ impl ScriptTask {
         document.r().send_title_to_compositor();
     }
 
+    /// Handles a web font becoming available by laying the page out again, since its text may
+    /// have been shaped with a fallback font.
+    fn handle_web_font_loaded(&self, pipeline_id: PipelineId) {
+        let page = get_page(&self.root_page(), pipeline_id);
+        self.force_reflow(&*page, ReflowReason::WebFontLoaded);
+    }
+
     /// Handles a request to exit the script task and shut down layout.
     /// Returns true if the script task should shut down and false otherwise.
     fn handle_exit_pipeline_msg(&self, id: PipelineId, exit_type: PipelineExitType) -> bool {
@@ -33,6 +33,8 @@ use webdriver_traits::WebDriverScriptCommand;
 use geom::point::Point2D;
 use geom::rect::Rect;
 
+pub use msg::constellation_msg::MouseButton;
+
 /// The address of a node. Layout sends these back. They must be validated via
 /// `from_untrusted_node_address` before they can be used, because we do not trust layout.
 #[allow(raw_pointer_derive)]
@@ -69,6 +71,8 @@ pub enum ConstellationControlMsg {
     SendEvent(PipelineId, CompositorEvent),
     /// Notifies script that reflow is finished.
     ReflowComplete(PipelineId, u32),
+    /// Notifies script that a web font has loaded, so that text must be laid out again.
+    WebFontLoaded(PipelineId),
     /// Notifies script of the viewport.
     Viewport(PipelineId, Rect<f32>),
     /// Requests that the script task immediately send the constellation the title of a pipeline.
@@ -89,17 +93,6 @@ pub enum ConstellationControlMsg {
     WebDriverCommand(PipelineId, WebDriverScriptCommand)
 }
 
-/// The mouse button involved in the event.
-#[derive(Clone, Debug)]
-pub enum MouseButton {
-    /// The left mouse button.
-    Left,
-    /// The middle mouse button.
-    Middle,
-    /// The right mouse button.
-    Right,
-}
-
 /// Events from the compositor that the script task needs to know about
 pub enum CompositorEvent {
     /// The window was resized.
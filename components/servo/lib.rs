@@ -95,7 +95,9 @@ impl Browser  {
         // to deliver the message.
         let (compositor_proxy, compositor_receiver) =
             WindowMethods::create_compositor_channel(&window);
-        let time_profiler_chan = profile_time::Profiler::create(opts.time_profiler_period);
+        let time_profiler_chan =
+            profile_time::Profiler::create(opts.time_profiler_period,
+                                           opts.profiler_timeline_file.clone());
         let mem_profiler_chan = profile_mem::Profiler::create(opts.mem_profiler_period);
         let devtools_chan = opts.devtools_port.map(|port| {
             devtools::start_server(port)
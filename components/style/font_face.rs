@@ -22,16 +22,40 @@ pub struct UrlSource {
     pub format_hints: Vec<String>,
 }
 
+/// A range of code points, inclusive at both ends, as specified by the `unicode-range`
+/// descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnicodeRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl UnicodeRange {
+    #[inline]
+    pub fn contains(&self, character: char) -> bool {
+        let code_point = character as u32;
+        code_point >= self.start && code_point <= self.end
+    }
+}
+
+/// Returns true if a face with the given `unicode-range` may be used for the given character. An
+/// empty list means that the descriptor was absent, so the face covers everything.
+pub fn unicode_range_contains(unicode_range: &[UnicodeRange], character: char) -> bool {
+    unicode_range.is_empty() || unicode_range.iter().any(|range| range.contains(character))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct FontFaceRule {
     pub family: Atom,
     pub sources: Vec<Source>,
+    pub unicode_range: Vec<UnicodeRange>,
 }
 
 pub fn parse_font_face_block(context: &ParserContext, input: &mut Parser)
                              -> Result<FontFaceRule, ()> {
     let mut family = None;
     let mut src = None;
+    let mut unicode_range = vec![];
     let mut iter = DeclarationListParser::new(input, FontFaceRuleParser { context: context });
     while let Some(declaration) = iter.next() {
         match declaration {
@@ -47,6 +71,9 @@ pub fn parse_font_face_block(context: &ParserContext, input: &mut Parser)
             Ok(FontFaceDescriptorDeclaration::Src(value)) => {
                 src = Some(value);
             }
+            Ok(FontFaceDescriptorDeclaration::UnicodeRange(value)) => {
+                unicode_range = value;
+            }
         }
     }
     match (family, src) {
@@ -54,6 +81,7 @@ pub fn parse_font_face_block(context: &ParserContext, input: &mut Parser)
             Ok(FontFaceRule {
                 family: family,
                 sources: src,
+                unicode_range: unicode_range,
             })
         }
         _ => Err(())
@@ -63,6 +91,7 @@ pub fn parse_font_face_block(context: &ParserContext, input: &mut Parser)
 enum FontFaceDescriptorDeclaration {
     Family(Atom),
     Src(Vec<Source>),
+    UnicodeRange(Vec<UnicodeRange>),
 }
 
 
@@ -91,6 +120,10 @@ impl<'a, 'b> DeclarationParser for FontFaceRuleParser<'a, 'b> {
                 Ok(FontFaceDescriptorDeclaration::Src(try!(input.parse_comma_separated(|input| {
                     parse_one_src(self.context, input)
                 }))))
+            },
+            "unicode-range" => {
+                Ok(FontFaceDescriptorDeclaration::UnicodeRange(try!(
+                            input.parse_comma_separated(parse_one_unicode_range))))
             }
             _ => Err(())
         }
@@ -104,6 +137,17 @@ fn parse_one_non_generic_family_name(input: &mut Parser) -> Result<Atom, ()> {
     }
 }
 
+fn parse_one_unicode_range(input: &mut Parser) -> Result<UnicodeRange, ()> {
+    match try!(input.next()) {
+        Token::UnicodeRange(start, end) if start <= end => {
+            Ok(UnicodeRange {
+                start: start,
+                end: end,
+            })
+        }
+        _ => Err(())
+    }
+}
 
 fn parse_one_src(context: &ParserContext, input: &mut Parser) -> Result<Source, ()> {
     let url = match input.next() {
@@ -1293,7 +1293,7 @@ pub mod longhands {
             }
     </%self:longhand>
 
-    ${single_keyword("background-repeat", "repeat repeat-x repeat-y no-repeat")}
+    ${single_keyword("background-repeat", "repeat repeat-x repeat-y no-repeat space round")}
 
     ${single_keyword("background-attachment", "scroll fixed")}
 
@@ -1926,20 +1926,29 @@ pub mod longhands {
         }
     </%self:longhand>
 
+    ${single_keyword("text-decoration-style", "solid double dotted dashed wavy")}
+
     ${switch_to_style_struct("InheritedText")}
 
     <%self:longhand name="-servo-text-decorations-in-effect"
-                    derived_from="display text-decoration">
+                    derived_from="display text-decoration text-decoration-style">
         use cssparser::RGBA;
         use values::computed::ComputedValueAsSpecified;
 
         impl ComputedValueAsSpecified for SpecifiedValue {}
 
+        /// How a line is drawn, which comes from the element that the line decorates.
+        #[derive(Clone, PartialEq, Copy, Debug)]
+        pub struct Decoration {
+            pub color: RGBA,
+            pub style: super::text_decoration_style::computed_value::T,
+        }
+
         #[derive(Clone, PartialEq, Copy, Debug)]
         pub struct SpecifiedValue {
-            pub underline: Option<RGBA>,
-            pub overline: Option<RGBA>,
-            pub line_through: Option<RGBA>,
+            pub underline: Option<Decoration>,
+            pub overline: Option<Decoration>,
+            pub line_through: Option<Decoration>,
         }
 
         pub mod computed_value {
@@ -1955,9 +1964,12 @@ pub mod longhands {
             }
         }
 
-        fn maybe(flag: bool, context: &computed::Context) -> Option<RGBA> {
+        fn maybe(flag: bool, context: &computed::Context) -> Option<Decoration> {
             if flag {
-                Some(context.color)
+                Some(Decoration {
+                    color: context.color,
+                    style: context.text_decoration_style,
+                })
             } else {
                 None
             }
@@ -1998,6 +2010,14 @@ pub mod longhands {
             derive(context)
         }
 
+        #[inline]
+        pub fn derive_from_text_decoration_style(
+                _: super::text_decoration_style::computed_value::T,
+                context: &computed::Context)
+                -> computed_value::T {
+            derive(context)
+        }
+
         #[inline]
         pub fn derive_from_display(_: super::display::computed_value::T,
                                    context: &computed::Context)
@@ -2008,11 +2028,251 @@ pub mod longhands {
 
     ${single_keyword("white-space", "normal pre nowrap")}
 
+    ${single_keyword("text-decoration-skip-ink", "auto none")}
+
+    ${single_keyword("ruby-position", "over under")}
+
+    // http://dev.w3.org/csswg/css-text-decor-3/#emphasis-marks
+    ${predefined_type("text-emphasis-color", "CSSColor", "::cssparser::Color::CurrentColor")}
+
+    <%self:longhand name="text-emphasis-style">
+        use text_writer::{self, TextWriter};
+        use values::computed::ComputedValueAsSpecified;
+
+        use cssparser::{ToCss, Token};
+        use std::borrow::IntoCow;
+
+        pub use self::computed_value::T as SpecifiedValue;
+
+        pub mod computed_value {
+            #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+            pub enum Shape {
+                Dot,
+                Circle,
+                DoubleCircle,
+                Triangle,
+                Sesame,
+            }
+
+            #[derive(Clone, PartialEq, Debug)]
+            pub enum T {
+                None,
+                /// Filled (`true`) or open marks of the given shape, or of the shape that suits
+                /// the writing mode if none was given.
+                Shape(bool, Option<Shape>),
+                /// Marks of the given string.
+                String(String),
+            }
+
+            impl T {
+                /// Returns the text of each mark, if there are marks. Marks without a shape are
+                /// circles in horizontal text and sesame dots in vertical text.
+                pub fn mark(&self, vertical: bool) -> Option<String> {
+                    let (filled, shape) = match *self {
+                        T::None => return None,
+                        T::String(ref string) => {
+                            return string.chars().next().map(|mark| mark.to_string())
+                        }
+                        T::Shape(filled, Some(shape)) => (filled, shape),
+                        T::Shape(filled, None) if vertical => (filled, Shape::Sesame),
+                        T::Shape(filled, None) => (filled, Shape::Circle),
+                    };
+                    let mark = match (shape, filled) {
+                        (Shape::Dot, true) => '\u{2022}',
+                        (Shape::Dot, false) => '\u{25e6}',
+                        (Shape::Circle, true) => '\u{25cf}',
+                        (Shape::Circle, false) => '\u{25cb}',
+                        (Shape::DoubleCircle, true) => '\u{25c9}',
+                        (Shape::DoubleCircle, false) => '\u{25ce}',
+                        (Shape::Triangle, true) => '\u{25b2}',
+                        (Shape::Triangle, false) => '\u{25b3}',
+                        (Shape::Sesame, true) => '\u{fe45}',
+                        (Shape::Sesame, false) => '\u{fe46}',
+                    };
+                    Some(mark.to_string())
+                }
+            }
+        }
+
+        impl ComputedValueAsSpecified for SpecifiedValue {}
+
+        impl ToCss for SpecifiedValue {
+            fn to_css<W>(&self, dest: &mut W) -> text_writer::Result where W: TextWriter {
+                use self::computed_value::Shape;
+                match *self {
+                    SpecifiedValue::None => dest.write_str("none"),
+                    SpecifiedValue::String(ref string) => {
+                        Token::QuotedString((**string).into_cow()).to_css(dest)
+                    }
+                    SpecifiedValue::Shape(filled, shape) => {
+                        try!(dest.write_str(if filled { "filled" } else { "open" }));
+                        match shape {
+                            Some(Shape::Dot) => dest.write_str(" dot"),
+                            Some(Shape::Circle) => dest.write_str(" circle"),
+                            Some(Shape::DoubleCircle) => dest.write_str(" double-circle"),
+                            Some(Shape::Triangle) => dest.write_str(" triangle"),
+                            Some(Shape::Sesame) => dest.write_str(" sesame"),
+                            None => Ok(()),
+                        }
+                    }
+                }
+            }
+        }
+
+        #[inline]
+        pub fn get_initial_value() -> computed_value::T {
+            computed_value::T::None
+        }
+
+        /// none | [ filled | open ] || [ dot | circle | double-circle | triangle | sesame ] |
+        /// <string>
+        pub fn parse(_: &ParserContext, input: &mut Parser) -> Result<SpecifiedValue,()> {
+            use self::computed_value::Shape;
+            if input.try(|input| input.expect_ident_matching("none")).is_ok() {
+                return Ok(SpecifiedValue::None)
+            }
+            if let Ok(string) = input.try(|input| input.expect_string()) {
+                return Ok(SpecifiedValue::String(string.into_owned()))
+            }
+
+            let (mut filled, mut shape) = (None, None);
+            while let Ok(ident) = input.try(|input| input.expect_ident()) {
+                let (new_filled, new_shape) = match_ignore_ascii_case! { ident,
+                    "filled" => (Some(true), None),
+                    "open" => (Some(false), None),
+                    "dot" => (None, Some(Shape::Dot)),
+                    "circle" => (None, Some(Shape::Circle)),
+                    "double-circle" => (None, Some(Shape::DoubleCircle)),
+                    "triangle" => (None, Some(Shape::Triangle)),
+                    "sesame" => (None, Some(Shape::Sesame))
+                    _ => return Err(())
+                };
+                if (new_filled.is_some() && filled.is_some()) ||
+                        (new_shape.is_some() && shape.is_some()) {
+                    return Err(())
+                }
+                filled = filled.or(new_filled);
+                shape = shape.or(new_shape);
+            }
+            if filled.is_none() && shape.is_none() {
+                return Err(())
+            }
+            Ok(SpecifiedValue::Shape(filled.unwrap_or(true), shape))
+        }
+    </%self:longhand>
+
+    <%self:longhand name="text-emphasis-position">
+        use text_writer::{self, TextWriter};
+        use values::computed::ComputedValueAsSpecified;
+
+        use cssparser::ToCss;
+
+        pub use self::computed_value::T as SpecifiedValue;
+
+        pub mod computed_value {
+            /// Where emphasis marks go: over or under the text in horizontal writing modes, and
+            /// to its right or left in vertical ones.
+            #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+            pub struct T {
+                pub over: bool,
+                pub right: bool,
+            }
+        }
+
+        impl ComputedValueAsSpecified for SpecifiedValue {}
+
+        impl ToCss for SpecifiedValue {
+            fn to_css<W>(&self, dest: &mut W) -> text_writer::Result where W: TextWriter {
+                try!(dest.write_str(if self.over { "over" } else { "under" }));
+                dest.write_str(if self.right { " right" } else { " left" })
+            }
+        }
+
+        #[inline]
+        pub fn get_initial_value() -> computed_value::T {
+            computed_value::T {
+                over: true,
+                right: true,
+            }
+        }
+
+        /// [ over | under ] && [ right | left ]
+        pub fn parse(_: &ParserContext, input: &mut Parser) -> Result<SpecifiedValue,()> {
+            let (mut over, mut right) = (None, None);
+            for _ in 0..2 {
+                // Whether the keyword is `over` or `under` rather than `right` or `left`, and
+                // whether it is the first of its pair.
+                let ident = try!(input.expect_ident());
+                let (is_over_or_under, value) = match_ignore_ascii_case! { ident,
+                    "over" => (true, true),
+                    "under" => (true, false),
+                    "right" => (false, true),
+                    "left" => (false, false)
+                    _ => return Err(())
+                };
+                let keyword = if is_over_or_under { &mut over } else { &mut right };
+                if keyword.is_some() {
+                    return Err(())
+                }
+                *keyword = Some(value)
+            }
+            Ok(SpecifiedValue {
+                over: over.unwrap(),
+                right: right.unwrap(),
+            })
+        }
+    </%self:longhand>
+
     // TODO(pcwalton): `full-width`
     ${single_keyword("text-transform", "none capitalize uppercase lowercase")}
 
     ${single_keyword("text-rendering", "auto optimizespeed optimizelegibility geometricprecision")}
 
+    // TODO: Support `<length>` values per CSS-TEXT § 4.2.
+    <%self:longhand name="tab-size">
+        use values::computed::{ToComputedValue, Context};
+        use cssparser::ToCss;
+        use text_writer::{self, TextWriter};
+
+        #[derive(Clone, Copy, PartialEq)]
+        pub struct SpecifiedValue(pub u32);
+
+        impl ToCss for SpecifiedValue {
+            fn to_css<W>(&self, dest: &mut W) -> text_writer::Result where W: TextWriter {
+                write!(dest, "{}", self.0)
+            }
+        }
+
+        pub mod computed_value {
+            /// The width of a tab, in multiples of the advance of a space.
+            pub type T = u32;
+        }
+
+        #[inline]
+        pub fn get_initial_value() -> computed_value::T {
+            8
+        }
+
+        impl ToComputedValue for SpecifiedValue {
+            type ComputedValue = computed_value::T;
+
+            #[inline]
+            fn to_computed_value(&self, _context: &Context) -> computed_value::T {
+                self.0
+            }
+        }
+
+        pub fn parse(_context: &ParserContext, input: &mut Parser) -> Result<SpecifiedValue, ()> {
+            use std::u32;
+            let count = try!(input.expect_integer());
+            // Negative values are invalid.
+            if count < 0 || count > (u32::MAX as i64) {
+                return Err(())
+            }
+            Ok(SpecifiedValue(count as u32))
+        }
+    </%self:longhand>
+
     // CSS 2.1, Section 17 - Tables
     ${new_style_struct("Table", is_inherited=False)}
 
@@ -2118,7 +2378,13 @@ pub mod longhands {
 
     // FIXME(SimonSapin): Add 'mixed' and 'upright' (needs vertical text support)
     // FIXME(SimonSapin): initial (first) value should be 'mixed', when that's implemented
-    ${single_keyword("text-orientation", "sideways sideways-left sideways-right", experimental=True)}
+    ${single_keyword("text-orientation", "sideways sideways-left sideways-right mixed", experimental=True)}
+
+    // CSS Image Values and Replaced Content Module Level 3
+    // http://dev.w3.org/csswg/css-images-3/#the-image-orientation
+    ${switch_to_style_struct("InheritedBox")}
+
+    ${single_keyword("image-orientation", "from-image none")}
 
     // CSS Basic User Interface Module Level 3
     // http://dev.w3.org/csswg/css-ui/
@@ -2783,7 +3049,7 @@ pub mod longhands {
             }
         }
 
-        fn parse_one_text_shadow(input: &mut Parser) -> Result<SpecifiedTextShadow,()> {
+        pub fn parse_one_text_shadow(input: &mut Parser) -> Result<SpecifiedTextShadow,()> {
             use util::geometry::Au;
             let mut lengths = [specified::Length::Absolute(Au(0)); 3];
             let mut lengths_parsed = false;
@@ -2858,27 +3124,32 @@ pub mod longhands {
         use values::computed::{Context, ToComputedValue};
         use values::specified::{Angle, Length};
         use values::CSSFloat;
-        use cssparser::ToCss;
+        use cssparser::{self, ToCss};
         use text_writer::{self, TextWriter};
+        use super::text_shadow::{self, SpecifiedTextShadow};
+        use url::Url;
 
         #[derive(Clone, PartialEq)]
         pub struct SpecifiedValue(Vec<SpecifiedFilter>);
 
-        // TODO(pcwalton): `drop-shadow`
         #[derive(Clone, PartialEq, Debug)]
         pub enum SpecifiedFilter {
             Blur(Length),
             Brightness(CSSFloat),
             Contrast(CSSFloat),
+            DropShadow(SpecifiedTextShadow),
             Grayscale(CSSFloat),
             HueRotate(Angle),
             Invert(CSSFloat),
             Opacity(CSSFloat),
             Saturate(CSSFloat),
             Sepia(CSSFloat),
+            Url(Url),
         }
 
         pub mod computed_value {
+            use cssparser::RGBA;
+            use url::Url;
             use util::geometry::Au;
             use values::CSSFloat;
             use values::specified::{Angle};
@@ -2888,12 +3159,24 @@ pub mod longhands {
                 Blur(Au),
                 Brightness(CSSFloat),
                 Contrast(CSSFloat),
+                DropShadow(DropShadow),
                 Grayscale(CSSFloat),
                 HueRotate(Angle),
                 Invert(CSSFloat),
                 Opacity(CSSFloat),
                 Saturate(CSSFloat),
                 Sepia(CSSFloat),
+                /// An SVG `<filter>` element.
+                Url(Url),
+            }
+
+            /// The shadow of `drop-shadow()`, with `currentColor` resolved.
+            #[derive(Clone, Copy, PartialEq, Debug)]
+            pub struct DropShadow {
+                pub offset_x: Au,
+                pub offset_y: Au,
+                pub blur_radius: Au,
+                pub color: RGBA,
             }
 
             #[derive(Clone, PartialEq, Debug)]
@@ -2963,6 +3246,11 @@ pub mod longhands {
                     }
                     SpecifiedFilter::Brightness(value) => try!(write!(dest, "brightness({})", value)),
                     SpecifiedFilter::Contrast(value) => try!(write!(dest, "contrast({})", value)),
+                    SpecifiedFilter::DropShadow(ref shadow) => {
+                        try!(dest.write_str("drop-shadow("));
+                        try!(shadow.to_css(dest));
+                        try!(dest.write_str(")"));
+                    }
                     SpecifiedFilter::Grayscale(value) => try!(write!(dest, "grayscale({})", value)),
                     SpecifiedFilter::HueRotate(value) => {
                         try!(dest.write_str("hue-rotate("));
@@ -2973,6 +3261,11 @@ pub mod longhands {
                     SpecifiedFilter::Opacity(value) => try!(write!(dest, "opacity({})", value)),
                     SpecifiedFilter::Saturate(value) => try!(write!(dest, "saturate({})", value)),
                     SpecifiedFilter::Sepia(value) => try!(write!(dest, "sepia({})", value)),
+                    SpecifiedFilter::Url(ref url) => {
+                        try!(dest.write_str("url(\""));
+                        try!(write!(&mut cssparser::CssStringWriter::new(dest), "{}", url));
+                        try!(dest.write_str("\")"));
+                    }
                 }
                 Ok(())
             }
@@ -2983,18 +3276,21 @@ pub mod longhands {
             computed_value::T::new(Vec::new())
         }
 
-        pub fn parse(_context: &ParserContext, input: &mut Parser) -> Result<SpecifiedValue, ()> {
+        pub fn parse(context: &ParserContext, input: &mut Parser) -> Result<SpecifiedValue, ()> {
             let mut filters = Vec::new();
             if input.try(|input| input.expect_ident_matching("none")).is_ok() {
                 return Ok(SpecifiedValue(filters))
             }
             loop {
-                if let Ok(function_name) = input.try(|input| input.expect_function()) {
+                if let Ok(url) = input.try(|input| input.expect_url()) {
+                    filters.push(SpecifiedFilter::Url(context.parse_url(&*url)))
+                } else if let Ok(function_name) = input.try(|input| input.expect_function()) {
                     filters.push(try!(input.parse_nested_block(|input| {
                         match_ignore_ascii_case! { function_name,
                             "blur" => specified::Length::parse_non_negative(input).map(SpecifiedFilter::Blur),
                             "brightness" => parse_factor(input).map(SpecifiedFilter::Brightness),
                             "contrast" => parse_factor(input).map(SpecifiedFilter::Contrast),
+                            "drop-shadow" => text_shadow::parse_one_text_shadow(input).map(SpecifiedFilter::DropShadow),
                             "grayscale" => parse_factor(input).map(SpecifiedFilter::Grayscale),
                             "hue-rotate" => Angle::parse(input).map(SpecifiedFilter::HueRotate),
                             "invert" => parse_factor(input).map(SpecifiedFilter::Invert),
@@ -3030,12 +3326,28 @@ pub mod longhands {
                         &SpecifiedFilter::Blur(factor) => computed_value::Filter::Blur(factor.to_computed_value(context)),
                         &SpecifiedFilter::Brightness(factor) => computed_value::Filter::Brightness(factor),
                         &SpecifiedFilter::Contrast(factor) => computed_value::Filter::Contrast(factor),
+                        &SpecifiedFilter::DropShadow(ref shadow) => {
+                            let color = match shadow.color {
+                                Some(ref color) => color.parsed,
+                                None => cssparser::Color::CurrentColor,
+                            };
+                            computed_value::Filter::DropShadow(computed_value::DropShadow {
+                                offset_x: shadow.offset_x.to_computed_value(context),
+                                offset_y: shadow.offset_y.to_computed_value(context),
+                                blur_radius: shadow.blur_radius.to_computed_value(context),
+                                color: match color {
+                                    cssparser::Color::RGBA(rgba) => rgba,
+                                    cssparser::Color::CurrentColor => context.color,
+                                },
+                            })
+                        }
                         &SpecifiedFilter::Grayscale(factor) => computed_value::Filter::Grayscale(factor),
                         &SpecifiedFilter::HueRotate(factor) => computed_value::Filter::HueRotate(factor),
                         &SpecifiedFilter::Invert(factor) => computed_value::Filter::Invert(factor),
                         &SpecifiedFilter::Opacity(factor) => computed_value::Filter::Opacity(factor),
                         &SpecifiedFilter::Saturate(factor) => computed_value::Filter::Saturate(factor),
                         &SpecifiedFilter::Sepia(factor) => computed_value::Filter::Sepia(factor),
+                        &SpecifiedFilter::Url(ref url) => computed_value::Filter::Url(url.clone()),
                     }
                 }).collect() }
             }
@@ -3058,12 +3370,19 @@ pub mod longhands {
 
             use std::num::Float;
             use std::ops::Mul;
+            use util::geometry::Au;
 
+            /// A transform of 3D space that keeps parallel lines parallel, so that it can keep
+            /// its translation as lengths and percentages. It transforms row vectors, so its
+            /// translation is in `m41`, `m42` and `m43`.
             #[derive(Clone, Copy, Debug, PartialEq)]
             pub struct ComputedMatrix {
-                pub m11: CSSFloat, pub m12: CSSFloat,
-                pub m21: CSSFloat, pub m22: CSSFloat,
-                pub m31: computed::LengthAndPercentage, pub m32: computed::LengthAndPercentage,
+                pub m11: CSSFloat, pub m12: CSSFloat, pub m13: CSSFloat,
+                pub m21: CSSFloat, pub m22: CSSFloat, pub m23: CSSFloat,
+                pub m31: CSSFloat, pub m32: CSSFloat, pub m33: CSSFloat,
+                pub m41: computed::LengthAndPercentage,
+                pub m42: computed::LengthAndPercentage,
+                pub m43: computed::LengthAndPercentage,
             }
 
             impl Mul<ComputedMatrix> for ComputedMatrix {
@@ -3071,70 +3390,108 @@ pub mod longhands {
 
                 fn mul(self, other: ComputedMatrix) -> ComputedMatrix {
                     ComputedMatrix {
-                        m11: self.m11*other.m11 + self.m12*other.m21,
-                        m12: self.m11*other.m12 + self.m12*other.m22,
-                        m21: self.m21*other.m11 + self.m22*other.m21,
-                        m22: self.m21*other.m12 + self.m22*other.m22,
-                        m31: self.m31.clone()*other.m11 + self.m32.clone()*other.m21 + other.m31,
-                        m32: self.m31*other.m12 + self.m32*other.m22 + other.m32,
+                        m11: self.m11*other.m11 + self.m12*other.m21 + self.m13*other.m31,
+                        m12: self.m11*other.m12 + self.m12*other.m22 + self.m13*other.m32,
+                        m13: self.m11*other.m13 + self.m12*other.m23 + self.m13*other.m33,
+                        m21: self.m21*other.m11 + self.m22*other.m21 + self.m23*other.m31,
+                        m22: self.m21*other.m12 + self.m22*other.m22 + self.m23*other.m32,
+                        m23: self.m21*other.m13 + self.m22*other.m23 + self.m23*other.m33,
+                        m31: self.m31*other.m11 + self.m32*other.m21 + self.m33*other.m31,
+                        m32: self.m31*other.m12 + self.m32*other.m22 + self.m33*other.m32,
+                        m33: self.m31*other.m13 + self.m32*other.m23 + self.m33*other.m33,
+                        m41: self.m41*other.m11 + self.m42*other.m21 + self.m43*other.m31 +
+                            other.m41,
+                        m42: self.m41*other.m12 + self.m42*other.m22 + self.m43*other.m32 +
+                            other.m42,
+                        m43: self.m41*other.m13 + self.m42*other.m23 + self.m43*other.m33 +
+                            other.m43,
                     }
                 }
             }
 
             impl ComputedMatrix {
+                /// Returns the matrix that transforms by the given 3×3 matrix and doesn't
+                /// translate.
                 #[inline]
-                fn new(m11: CSSFloat,
-                       m12: CSSFloat,
-                       m21: CSSFloat,
-                       m22: CSSFloat,
-                       m31: computed::LengthAndPercentage,
-                       m32: computed::LengthAndPercentage)
-                       -> ComputedMatrix {
+                fn linear(m11: CSSFloat, m12: CSSFloat, m13: CSSFloat,
+                          m21: CSSFloat, m22: CSSFloat, m23: CSSFloat,
+                          m31: CSSFloat, m32: CSSFloat, m33: CSSFloat)
+                          -> ComputedMatrix {
                     ComputedMatrix {
-                        m11: m11, m12: m12,
-                        m21: m21, m22: m22,
-                        m31: m31, m32: m32,
+                        m11: m11, m12: m12, m13: m13,
+                        m21: m21, m22: m22, m23: m23,
+                        m31: m31, m32: m32, m33: m33,
+                        m41: computed::LengthAndPercentage::zero(),
+                        m42: computed::LengthAndPercentage::zero(),
+                        m43: computed::LengthAndPercentage::zero(),
                     }
                 }
 
                 #[inline]
                 pub fn identity() -> ComputedMatrix {
-                    ComputedMatrix {
-                        m11: 1.0, m12: 0.0,
-                        m21: 0.0, m22: 1.0,
-                        m31: computed::LengthAndPercentage::zero(),
-                        m32: computed::LengthAndPercentage::zero(),
-                    }
+                    ComputedMatrix::linear(1.0, 0.0, 0.0,
+                                           0.0, 1.0, 0.0,
+                                           0.0, 0.0, 1.0)
                 }
 
-                pub fn scale(&mut self, sx: CSSFloat, sy: CSSFloat) {
-                    *self = ComputedMatrix::new(sx, 0.0,
-                                                0.0, sy,
-                                                computed::LengthAndPercentage::zero(),
-                                                computed::LengthAndPercentage::zero()) *
-                        (*self).clone()
+                pub fn scale(&mut self, sx: CSSFloat, sy: CSSFloat, sz: CSSFloat) {
+                    *self = ComputedMatrix::linear(sx, 0.0, 0.0,
+                                                   0.0, sy, 0.0,
+                                                   0.0, 0.0, sz) * (*self).clone()
                 }
 
                 pub fn skew(&mut self, sx: CSSFloat, sy: CSSFloat) {
-                    *self = ComputedMatrix::new(1.0, sx,
-                                                sy, 1.0,
-                                                computed::LengthAndPercentage::zero(),
-                                                computed::LengthAndPercentage::zero()) *
-                        (*self).clone()
+                    *self = ComputedMatrix::linear(1.0, sx, 0.0,
+                                                   sy, 1.0, 0.0,
+                                                   0.0, 0.0, 1.0) * (*self).clone()
                 }
 
                 pub fn translate(&mut self,
                                  tx: computed::LengthAndPercentage,
-                                 ty: computed::LengthAndPercentage) {
-                    *self = ComputedMatrix::new(1.0, 0.0, 0.0, 1.0, tx, ty) * (*self).clone()
+                                 ty: computed::LengthAndPercentage,
+                                 tz: Au) {
+                    let mut translation = ComputedMatrix::identity();
+                    translation.m41 = tx;
+                    translation.m42 = ty;
+                    translation.m43 = computed::LengthAndPercentage {
+                        length: tz,
+                        percentage: 0.0,
+                    };
+                    *self = translation * (*self).clone()
                 }
 
                 pub fn rotate(&mut self, theta: CSSFloat) {
-                    *self = ComputedMatrix::new(theta.cos(), -theta.sin(),
-                                                theta.sin(), theta.cos(),
-                                                computed::LengthAndPercentage::zero(),
-                                                computed::LengthAndPercentage::zero()) *
-                        (*self).clone()
+                    *self = ComputedMatrix::linear(theta.cos(), -theta.sin(), 0.0,
+                                                   theta.sin(), theta.cos(), 0.0,
+                                                   0.0, 0.0, 1.0) * (*self).clone()
+                }
+
+                /// Rotates clockwise by `theta` radians around the axis (`x`, `y`, `z`), as
+                /// `rotate3d()` does. Does nothing if the axis has no direction.
+                pub fn rotate_3d(&mut self,
+                                 x: CSSFloat,
+                                 y: CSSFloat,
+                                 z: CSSFloat,
+                                 theta: CSSFloat) {
+                    let length = (x * x + y * y + z * z).sqrt();
+                    if length == 0.0 || !length.is_finite() {
+                        return
+                    }
+                    let (x, y, z) = (x / length, y / length, z / length);
+                    let sc = (theta / 2.0).sin() * (theta / 2.0).cos();
+                    let sq = (theta / 2.0).sin() * (theta / 2.0).sin();
+                    // The transpose of the matrix in CSS-TRANSFORMS-2 § 13.1, which transforms
+                    // column vectors.
+                    *self = ComputedMatrix::linear(
+                        1.0 - 2.0 * (y * y + z * z) * sq,
+                        2.0 * (x * y * sq + z * sc),
+                        2.0 * (x * z * sq - y * sc),
+                        2.0 * (x * y * sq - z * sc),
+                        1.0 - 2.0 * (x * x + z * z) * sq,
+                        2.0 * (y * z * sq + x * sc),
+                        2.0 * (x * z * sq + y * sc),
+                        2.0 * (y * z * sq - x * sc),
+                        1.0 - 2.0 * (x * x + y * y) * sq) * (*self).clone()
                 }
             }
 
@@ -3207,12 +3564,25 @@ pub mod longhands {
             Ok((first, second))
         }
 
+        fn parse_three_floats(input: &mut Parser) -> Result<(CSSFloat, CSSFloat, CSSFloat),()> {
+            let first = try!(input.expect_number());
+            try!(input.expect_comma());
+            let second = try!(input.expect_number());
+            try!(input.expect_comma());
+            let third = try!(input.expect_number());
+            Ok((first, second, third))
+        }
+
         #[derive(Clone, Debug, PartialEq)]
         enum SpecifiedOperation {
             Matrix(SpecifiedMatrix),
-            Translate(specified::LengthAndPercentage, specified::LengthAndPercentage),
-            Scale(CSSFloat, CSSFloat),
+            Translate(specified::LengthAndPercentage,
+                      specified::LengthAndPercentage,
+                      specified::Length),
+            Scale(CSSFloat, CSSFloat, CSSFloat),
             Rotate(specified::Angle),
+            /// A rotation around the axis of the given direction.
+            Rotate3D(CSSFloat, CSSFloat, CSSFloat, specified::Angle),
             Skew(CSSFloat, CSSFloat),
         }
 
@@ -3282,7 +3652,19 @@ pub mod longhands {
                     "translate" => {
                         try!(input.parse_nested_block(|input| {
                             let (tx, ty) = try!(parse_two_lengths_or_percentages(input));
-                            result.push(SpecifiedOperation::Translate(tx, ty));
+                            let tz = specified::Length::Absolute(Au(0));
+                            result.push(SpecifiedOperation::Translate(tx, ty, tz));
+                            Ok(())
+                        }))
+                    },
+                    "translate3d" => {
+                        try!(input.parse_nested_block(|input| {
+                            let tx = try!(specified::LengthAndPercentage::parse(input));
+                            try!(input.expect_comma());
+                            let ty = try!(specified::LengthAndPercentage::parse(input));
+                            try!(input.expect_comma());
+                            let tz = try!(specified::Length::parse(input));
+                            result.push(SpecifiedOperation::Translate(tx, ty, tz));
                             Ok(())
                         }))
                     },
@@ -3292,7 +3674,8 @@ pub mod longhands {
                             result.push(SpecifiedOperation::Translate(
                                 specified::LengthAndPercentage::from_length_or_percentage(
                                     &tx),
-                                specified::LengthAndPercentage::zero()));
+                                specified::LengthAndPercentage::zero(),
+                                specified::Length::Absolute(Au(0))));
                             Ok(())
                         }))
                     },
@@ -3302,38 +3685,86 @@ pub mod longhands {
                             result.push(SpecifiedOperation::Translate(
                                 specified::LengthAndPercentage::zero(),
                                 specified::LengthAndPercentage::from_length_or_percentage(
-                                    &ty)));
+                                    &ty),
+                                specified::Length::Absolute(Au(0))));
+                            Ok(())
+                        }))
+                    },
+                    "translatez" => {
+                        try!(input.parse_nested_block(|input| {
+                            let tz = try!(specified::Length::parse(input));
+                            result.push(SpecifiedOperation::Translate(
+                                specified::LengthAndPercentage::zero(),
+                                specified::LengthAndPercentage::zero(),
+                                tz));
                             Ok(())
                         }))
                     },
                     "scale" => {
                         try!(input.parse_nested_block(|input| {
                             let (sx, sy) = try!(parse_two_floats(input));
-                            result.push(SpecifiedOperation::Scale(sx, sy));
+                            result.push(SpecifiedOperation::Scale(sx, sy, 1.0));
+                            Ok(())
+                        }))
+                    },
+                    "scale3d" => {
+                        try!(input.parse_nested_block(|input| {
+                            let (sx, sy, sz) = try!(parse_three_floats(input));
+                            result.push(SpecifiedOperation::Scale(sx, sy, sz));
                             Ok(())
                         }))
                     },
                     "scalex" => {
                         try!(input.parse_nested_block(|input| {
                             let sx = try!(input.expect_number());
-                            result.push(SpecifiedOperation::Scale(sx, 1.0));
+                            result.push(SpecifiedOperation::Scale(sx, 1.0, 1.0));
                             Ok(())
                         }))
                     },
                     "scaley" => {
                         try!(input.parse_nested_block(|input| {
                             let sy = try!(input.expect_number());
-                            result.push(SpecifiedOperation::Scale(1.0, sy));
+                            result.push(SpecifiedOperation::Scale(1.0, sy, 1.0));
+                            Ok(())
+                        }))
+                    },
+                    "scalez" => {
+                        try!(input.parse_nested_block(|input| {
+                            let sz = try!(input.expect_number());
+                            result.push(SpecifiedOperation::Scale(1.0, 1.0, sz));
                             Ok(())
                         }))
                     },
-                    "rotate" => {
+                    "rotate" | "rotatez" => {
                         try!(input.parse_nested_block(|input| {
                             let theta = try!(specified::Angle::parse(input));
                             result.push(SpecifiedOperation::Rotate(theta));
                             Ok(())
                         }))
                     },
+                    "rotatex" => {
+                        try!(input.parse_nested_block(|input| {
+                            let theta = try!(specified::Angle::parse(input));
+                            result.push(SpecifiedOperation::Rotate3D(1.0, 0.0, 0.0, theta));
+                            Ok(())
+                        }))
+                    },
+                    "rotatey" => {
+                        try!(input.parse_nested_block(|input| {
+                            let theta = try!(specified::Angle::parse(input));
+                            result.push(SpecifiedOperation::Rotate3D(0.0, 1.0, 0.0, theta));
+                            Ok(())
+                        }))
+                    },
+                    "rotate3d" => {
+                        try!(input.parse_nested_block(|input| {
+                            let (x, y, z) = try!(parse_three_floats(input));
+                            try!(input.expect_comma());
+                            let theta = try!(specified::Angle::parse(input));
+                            result.push(SpecifiedOperation::Rotate3D(x, y, z, theta));
+                            Ok(())
+                        }))
+                    },
                     "skew" => {
                         try!(input.parse_nested_block(|input| {
                             let (sx, sy) = try!(parse_two_floats(input));
@@ -3380,22 +3811,28 @@ pub mod longhands {
                     match *operation {
                         SpecifiedOperation::Matrix(ref matrix) => {
                             result = computed_value::ComputedMatrix {
-                                m11: matrix.m11, m12: matrix.m12,
-                                m21: matrix.m21, m22: matrix.m22,
-                                m31: matrix.m31.to_computed_value(context),
-                                m32: matrix.m32.to_computed_value(context),
+                                m11: matrix.m11, m12: matrix.m12, m13: 0.0,
+                                m21: matrix.m21, m22: matrix.m22, m23: 0.0,
+                                m31: 0.0, m32: 0.0, m33: 1.0,
+                                m41: matrix.m31.to_computed_value(context),
+                                m42: matrix.m32.to_computed_value(context),
+                                m43: computed::LengthAndPercentage::zero(),
                             } * result
                         }
-                        SpecifiedOperation::Translate(ref tx, ref ty) => {
+                        SpecifiedOperation::Translate(ref tx, ref ty, ref tz) => {
                             result.translate(tx.to_computed_value(context),
-                                             ty.to_computed_value(context))
+                                             ty.to_computed_value(context),
+                                             tz.to_computed_value(context))
                         }
-                        SpecifiedOperation::Scale(sx, sy) => {
-                            result.scale(sx, sy)
+                        SpecifiedOperation::Scale(sx, sy, sz) => {
+                            result.scale(sx, sy, sz)
                         }
                         SpecifiedOperation::Rotate(ref theta) => {
                             result.rotate(f64::consts::PI_2 - theta.radians());
                         }
+                        SpecifiedOperation::Rotate3D(x, y, z, ref theta) => {
+                            result.rotate_3d(x, y, z, theta.radians());
+                        }
                         SpecifiedOperation::Skew(sx, sy) => {
                             result.skew(sx, sy)
                         }
@@ -3524,6 +3961,60 @@ pub mod longhands {
         }
     </%self:longhand>
 
+    ${single_keyword("transform-style", "flat preserve-3d")}
+
+    <%self:longhand name="perspective">
+        use values::computed::{ToComputedValue, Context};
+        use cssparser::ToCss;
+        use text_writer::{self, TextWriter};
+
+        #[derive(Clone, Copy, PartialEq)]
+        pub enum SpecifiedValue {
+            None,
+            Specified(specified::Length),
+        }
+
+        impl ToCss for SpecifiedValue {
+            fn to_css<W>(&self, dest: &mut W) -> text_writer::Result where W: TextWriter {
+                match *self {
+                    SpecifiedValue::None => dest.write_str("none"),
+                    SpecifiedValue::Specified(l) => l.to_css(dest),
+                }
+            }
+        }
+
+        pub mod computed_value {
+            use util::geometry::Au;
+            /// The distance from the viewer to the plane z = 0, or `None` for no perspective.
+            pub type T = Option<Au>;
+        }
+
+        #[inline]
+        pub fn get_initial_value() -> computed_value::T {
+            None
+        }
+
+        impl ToComputedValue for SpecifiedValue {
+            type ComputedValue = computed_value::T;
+
+            #[inline]
+            fn to_computed_value(&self, context: &Context) -> computed_value::T {
+                match *self {
+                    SpecifiedValue::None => None,
+                    SpecifiedValue::Specified(l) => Some(l.to_computed_value(context))
+                }
+            }
+        }
+
+        pub fn parse(_context: &ParserContext, input: &mut Parser) -> Result<SpecifiedValue, ()> {
+            if input.try(|input| input.expect_ident_matching("none")).is_ok() {
+                Ok(SpecifiedValue::None)
+            } else {
+                specified::Length::parse_non_negative(input).map(SpecifiedValue::Specified)
+            }
+        }
+    </%self:longhand>
+
     ${single_keyword("mix-blend-mode",
                      """normal multiply screen overlay darken lighten color-dodge
                         color-burn hard-light soft-light difference exclusion hue
@@ -5240,7 +5731,9 @@ pub fn get_writing_mode(inheritedbox_style: &style_structs::InheritedBox) -> Wri
         },
     }
     match inheritedbox_style.text_orientation {
-        computed_values::text_orientation::T::sideways_right => {},
+        // Under `mixed`, the characters that are not kept upright are set sideways right.
+        computed_values::text_orientation::T::sideways_right |
+        computed_values::text_orientation::T::mixed => {},
         computed_values::text_orientation::T::sideways_left => {
             flags.insert(logical_geometry::FLAG_VERTICAL_LR);
         },
@@ -5417,6 +5910,7 @@ pub fn cascade(viewport_size: Size2D<Au>,
             display: longhands::display::get_initial_value(),
             color: inherited_style.get_color().color,
             text_decoration: longhands::text_decoration::get_initial_value(),
+            text_decoration_style: longhands::text_decoration_style::get_initial_value(),
             overflow_x: longhands::overflow_x::get_initial_value(),
             overflow_y: longhands::overflow_y::get_initial_value(),
             positioned: false,
@@ -5495,6 +5989,10 @@ pub fn cascade(viewport_size: Size2D<Au>,
                 PropertyDeclaration::TextDecoration(ref value) => {
                     context.text_decoration = get_specified!(get_text, text_decoration, value);
                 }
+                PropertyDeclaration::TextDecorationStyle(ref value) => {
+                    context.text_decoration_style =
+                        get_specified!(get_text, text_decoration_style, value);
+                }
                 % for side in ["top", "right", "bottom", "left"]:
                     PropertyDeclaration::Border${side.capitalize()}Style(ref value) => {
                         context.border_${side}_present =
@@ -5744,11 +6242,13 @@ pub fn longhands_from_shorthand(shorthand: &str) -> Option<Vec<String>> {
     }
 }
 
-/// Corresponds to the fields in `gfx::font_template::FontTemplateDescriptor`.
+/// Corresponds to the fields in `gfx::font_template::FontTemplateDescriptor`, which are all
+/// compared when fonts are looked up, plus the family.
 fn compute_font_hash(font: &mut style_structs::Font) {
     let mut hasher: FnvHasher = Default::default();
     hasher.write_u16(font.font_weight as u16);
     font.font_stretch.hash(&mut hasher);
+    font.font_style.hash(&mut hasher);
     font.font_family.hash(&mut hasher);
     font.hash = hasher.finish()
 }
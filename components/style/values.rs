@@ -97,7 +97,7 @@ pub mod specified {
     use std::num::{NumCast, ToPrimitive};
     use std::ops::{Add, Mul};
     use url::Url;
-    use cssparser::{self, Token, Parser, ToCss, CssStringWriter};
+    use cssparser::{self, Token, Parser, ToCss, CssStringWriter, Delimiter};
     use geom::size::Size2D;
     use parser::ParserContext;
     use text_writer::{self, TextWriter};
@@ -699,6 +699,7 @@ pub mod specified {
     pub enum Image {
         Url(Url),
         LinearGradient(LinearGradient),
+        Paint(PaintImage),
     }
 
     impl fmt::Debug for Image {
@@ -714,7 +715,8 @@ pub mod specified {
                     try!(dest.write_str("\")"));
                     Ok(())
                 }
-                &Image::LinearGradient(ref gradient) => gradient.to_css(dest)
+                &Image::LinearGradient(ref gradient) => gradient.to_css(dest),
+                &Image::Paint(ref paint_image) => paint_image.to_css(dest),
             }
         }
     }
@@ -730,6 +732,10 @@ pub mod specified {
                         "linear-gradient" => {
                             Ok(Image::LinearGradient(try!(
                                 input.parse_nested_block(LinearGradient::parse_function))))
+                        },
+                        "paint" => {
+                            Ok(Image::Paint(try!(
+                                input.parse_nested_block(PaintImage::parse_function))))
                         }
                         _ => Err(())
                     }
@@ -739,6 +745,56 @@ pub mod specified {
         }
     }
 
+    /// An image drawn by the image generator registered under `name`, as
+    /// `paint(name, arguments…)` gives it. This is both the specified and the computed value.
+    #[derive(Clone, PartialEq)]
+    pub struct PaintImage {
+        pub name: String,
+        /// The arguments that the generator is given, each as the CSS it was written as.
+        pub arguments: Vec<String>,
+    }
+
+    impl fmt::Debug for PaintImage {
+        #[inline] fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.fmt_to_css(f) }
+    }
+
+    impl ToCss for PaintImage {
+        fn to_css<W>(&self, dest: &mut W) -> text_writer::Result where W: TextWriter {
+            try!(dest.write_str("paint("));
+            try!(dest.write_str(&self.name));
+            for argument in self.arguments.iter() {
+                try!(dest.write_str(", "));
+                try!(dest.write_str(argument));
+            }
+            try!(dest.write_char(')'));
+            Ok(())
+        }
+    }
+
+    impl PaintImage {
+        /// Parses the inside of `paint()`: the name of the generator, then each argument after a
+        /// comma.
+        pub fn parse_function(input: &mut Parser) -> Result<PaintImage, ()> {
+            let name = try!(input.expect_ident()).into_owned();
+            let mut arguments = Vec::new();
+            while input.try(|input| input.expect_comma()).is_ok() {
+                let argument = try!(input.parse_until_before(Delimiter::Comma, |input| {
+                    let start_position = input.position();
+                    while input.next().is_ok() {}
+                    Ok(input.slice_from(start_position).trim().to_owned())
+                }));
+                if argument.is_empty() {
+                    return Err(())
+                }
+                arguments.push(argument)
+            }
+            Ok(PaintImage {
+                name: name,
+                arguments: arguments,
+            })
+        }
+    }
+
     /// Specified values for a CSS linear gradient.
     #[derive(Clone, PartialEq)]
     pub struct LinearGradient {
@@ -952,7 +1008,7 @@ pub mod specified {
 }
 
 pub mod computed {
-    pub use super::specified::{BorderStyle, Time};
+    pub use super::specified::{BorderStyle, PaintImage, Time};
     use super::specified::{AngleOrCorner};
     use super::{specified, CSSFloat};
     pub use cssparser::Color as CSSColor;
@@ -972,6 +1028,7 @@ pub mod computed {
         pub inherited_height: longhands::height::computed_value::T,
         pub color: longhands::color::computed_value::T,
         pub text_decoration: longhands::text_decoration::computed_value::T,
+        pub text_decoration_style: longhands::text_decoration_style::computed_value::T,
         pub font_size: longhands::font_size::computed_value::T,
         pub root_font_size: longhands::font_size::computed_value::T,
         pub display: longhands::display::computed_value::T,
@@ -1200,6 +1257,7 @@ pub mod computed {
                 specified::Image::LinearGradient(ref linear_gradient) => {
                     Image::LinearGradient(linear_gradient.to_computed_value(context))
                 }
+                specified::Image::Paint(ref paint_image) => Image::Paint(paint_image.clone()),
             }
         }
     }
@@ -1210,6 +1268,7 @@ pub mod computed {
     pub enum Image {
         Url(Url),
         LinearGradient(LinearGradient),
+        Paint(PaintImage),
     }
 
     impl fmt::Debug for Image {
@@ -1217,6 +1276,7 @@ pub mod computed {
             match self {
                 &Image::Url(ref url) => write!(f, "url(\"{}\")", url),
                 &Image::LinearGradient(ref grad) => write!(f, "linear-gradient({:?})", grad),
+                &Image::Paint(ref paint_image) => write!(f, "{:?}", paint_image),
             }
         }
     }
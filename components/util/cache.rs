@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::hash_state::DefaultState;
 use rand::Rng;
@@ -111,6 +111,114 @@ impl<K: Clone + PartialEq, V: Clone> LRUCache<K,V> {
     }
 }
 
+/// A least-recently-used cache bounded by the total size of its entries rather than by their
+/// number, as given by whoever inserts them. Lookups and insertions don't scan the entries.
+pub struct SizedLRUCache<K, V> {
+    entries: HashMap<K, SizedEntry<V>>,
+    /// The keys of the entries by when they were last used, oldest first.
+    recency: BTreeMap<u64, K>,
+    size: usize,
+    max_size: usize,
+    /// Counts the lookups and insertions, to order the entries by when they were last used.
+    clock: u64,
+}
+
+struct SizedEntry<V> {
+    value: V,
+    size: usize,
+    last_used: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> SizedLRUCache<K, V> {
+    pub fn new(max_size: usize) -> SizedLRUCache<K, V> {
+        SizedLRUCache {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            size: 0,
+            max_size: max_size,
+            clock: 0,
+        }
+    }
+
+    /// Returns the total size of the entries.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Looks up an entry and marks it as the most recently used.
+    pub fn find(&mut self, key: &K) -> Option<V> {
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        self.clock += 1;
+        let key = self.recency.remove(&entry.last_used).unwrap();
+        self.recency.insert(self.clock, key);
+        entry.last_used = self.clock;
+        Some(entry.value.clone())
+    }
+
+    /// Adds an entry of the given size, replacing any entry with the same key, then drops the
+    /// least recently used entries until the cache is within its bound. The new entry is dropped
+    /// as well if it is larger than the bound by itself. Returns the dropped entries.
+    pub fn insert(&mut self, key: K, value: V, size: usize) -> Vec<(K, V)> {
+        self.remove(&key);
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, SizedEntry {
+            value: value,
+            size: size,
+            last_used: self.clock,
+        });
+        self.size += size;
+
+        let mut evicted = vec!();
+        while self.size > self.max_size {
+            let oldest = match self.recency.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            let key = self.recency.remove(&oldest).unwrap();
+            let entry = self.entries.remove(&key).unwrap();
+            self.size -= entry.size;
+            evicted.push((key, entry.value));
+        }
+        evicted
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = match self.entries.remove(key) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        self.recency.remove(&entry.last_used);
+        self.size -= entry.size;
+        Some(entry.value)
+    }
+
+    /// Drops the entries whose keys match the predicate.
+    pub fn remove_matching<F>(&mut self, predicate: F) where F: Fn(&K) -> bool {
+        let keys: Vec<K> = self.entries.keys().filter(|key| predicate(*key)).cloned().collect();
+        for key in keys.iter() {
+            self.remove(key);
+        }
+    }
+
+    pub fn evict_all(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.size = 0;
+    }
+}
+
 pub struct SimpleHashCache<K,V> {
     entries: Vec<Option<(K,V)>>,
     k0: u64,
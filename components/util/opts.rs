@@ -11,6 +11,8 @@ use geom::scale_factor::ScaleFactor;
 use geom::size::TypedSize2D;
 use layers::geometry::DevicePixel;
 use getopts;
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
 use std::collections::HashSet;
 use std::cmp;
 use std::env;
@@ -18,6 +20,7 @@ use std::io::{self, Write};
 use std::mem;
 use std::ptr;
 use std::rt;
+use std::usize;
 
 /// Global flags for Servo, currently set on the command line.
 #[derive(Clone)]
@@ -30,13 +33,61 @@ pub struct Opts {
     /// Note that painting is sequentialized when using GPU painting.
     pub paint_threads: usize,
 
+    /// The size of the stacks of paint threads in bytes, or `None` for the default
+    /// (`--paint-stack-size`).
+    pub paint_thread_stack_size: Option<usize>,
+
+    /// The CPUs that paint threads are kept to, one per thread in turn, or none to let them run on
+    /// any (`--paint-cpus`). Only Linux supports this.
+    pub paint_thread_cpus: Vec<usize>,
+
     /// True to use GPU painting via Skia-GL, false to use CPU painting via Skia (`-g`). Note that
     /// compositing is always done on the GPU.
     pub gpu_painting: bool,
 
+    /// True to draw solid colors, images, simple borders and linear gradients as batches of
+    /// instanced quads when painting on the GPU, instead of through Skia-GL.
+    pub gpu_quad_batching: bool,
+
+    /// True to paint tiles into shared memory for the embedder to read, instead of into native
+    /// surfaces for the compositor. Only CPU painting can do this.
+    pub shared_memory_tiles: bool,
+
+    /// True to always paint pages into tiles for the compositor, even those simple enough to be
+    /// painted straight into the window.
+    pub disable_direct_painting: bool,
+
+    /// True to first paint the tiles that scrolling brings into view at a quarter of the
+    /// resolution, so that fast scrolls show blurry content instead of nothing until they are
+    /// painted at full resolution. Only CPU painting into native surfaces does this.
+    pub low_res_prepaint: bool,
+
+    /// The format of the pixels of tiles painted into shared memory (`--pixel-format`).
+    pub pixel_format: PixelFormat,
+
+    /// The color space that tiles are painted in, which the compositor shows them in
+    /// (`--color-space`). Colors in other spaces are converted to it as they are painted.
+    pub color_space: ColorSpace,
+
     /// The maximum size of each tile in pixels (`-s`).
     pub tile_size: usize,
 
+    /// How tiles are divided up for painting (`--tiling`).
+    pub tiling_strategy: TilingStrategy,
+
+    /// The largest area, in square px, of the layers that the painter may squash back into the
+    /// layers they are in (`--layer-squash-area`). 0 to keep all of them.
+    pub layer_squash_max_area: usize,
+
+    /// The largest part of the area of a layer, from 0 to 1, that other layers may overlap for it
+    /// to be squashed (`--layer-squash-overlap`).
+    pub layer_squash_max_overlap: f32,
+
+    /// How far past each side of the part of each scroll layer in view its display port reaches,
+    /// as a fraction of the height of that part (`--display-port-margin`). Only tiles in display
+    /// ports are painted.
+    pub display_port_margin: f32,
+
     /// The ratio of device pixels per px at the default scale. If unspecified, will use the
     /// platform default setting.
     pub device_pixels_per_px: Option<ScaleFactor<ScreenPx, DevicePixel, f32>>,
@@ -45,6 +96,10 @@ pub struct Opts {
     /// cause it to produce output on that interval (`-p`).
     pub time_profiler_period: Option<f64>,
 
+    /// A file to write a timeline of the phases of painting to when Servo exits, in the trace
+    /// event format that `chrome://tracing` reads (`--profiler-timeline`).
+    pub profiler_timeline_file: Option<String>,
+
     /// `None` to disable the memory profiler or `Some` with an interval in seconds to enable it
     /// and cause it to produce output on that interval (`-m`).
     pub mem_profiler_period: Option<f64>,
@@ -79,6 +134,23 @@ pub struct Opts {
     /// debugging purposes (`--show-debug-borders`).
     pub show_debug_borders: bool,
 
+    /// True if the compositor should outline each layer and tile in colors over the page and
+    /// label each layer with its ID (`-Z show-layer-borders`).
+    pub show_debug_layer_borders: bool,
+
+    /// True if the painter should show frames per second and the number of display items, the
+    /// number of layers and the memory of the display list over the page (`-Z show-hud`).
+    pub show_hud: bool,
+
+    /// True if each display item should be painted as a fill in a color that comes from its node,
+    /// with an outline, instead of as itself, to show where items are and which are painted over
+    /// which (`-Z show-item-boundaries`).
+    pub show_item_boundaries: bool,
+
+    /// True if each display item should be labeled with the number of its place in the order
+    /// that items are painted in (`-Z show-paint-order`).
+    pub show_paint_order: bool,
+
     /// True if we should show borders on all fragments for debugging purposes
     /// (`--show-debug-fragment-borders`).
     pub show_debug_fragment_borders: bool,
@@ -89,6 +161,10 @@ pub struct Opts {
     /// True if we should paint borders around flows based on which thread painted them.
     pub show_debug_parallel_layout: bool,
 
+    /// True if characters missing from the font should be painted as boxes containing their code
+    /// points in hexadecimal.
+    pub show_hexboxes: bool,
+
     /// If set with --disable-text-aa, disable antialiasing on fonts. This is primarily useful for reftests
     /// where pixel perfect results are required when using fonts such as the Ahem
     /// font for layout tests.
@@ -127,6 +203,35 @@ pub struct Opts {
     /// Dumps the display list after optimization (post layout, at painting time).
     pub dump_display_list_optimized: bool,
 
+    /// A directory to write each display list that is painted to as JSON, in numbered files,
+    /// instead of printing it (`--dump-display-list`). Optimized display lists are written there
+    /// too if they are dumped.
+    pub dump_display_list_dir: Option<String>,
+
+    /// How many stacking contexts deep printed display lists are shown
+    /// (`--dump-display-list-depth`).
+    pub dump_display_list_depth: usize,
+
+    /// A file of hashes of the pixels of tiles to check each painted tile against, which tiles that
+    /// aren't in it yet are added to (`--tile-checksums`). The pixels and the display list of each
+    /// tile that differs are written next to it.
+    pub tile_checksums_file: Option<String>,
+
+    /// True to print the tree of layers that the painter paints each display list into.
+    pub dump_layer_tree: bool,
+
+    /// True if the time that painting each display item takes should be added up by kind of item
+    /// and by node, and the most expensive ones printed for each display list.
+    pub paint_item_costs: bool,
+
+    /// How many milliseconds a tile may take to paint before it is reported along with the items
+    /// and stacking contexts that took the most of the time (`--paint-budget`).
+    pub paint_budget: Option<f64>,
+
+    /// The kinds of painting work that are skipped, so that pages paint without them
+    /// (`--skip-painting`). The painter can be told to skip others while it runs.
+    pub skipped_painting_work: Vec<PaintingWork>,
+
     /// Emits notifications when there is a relayout.
     pub relayout_event: bool,
 
@@ -141,6 +246,199 @@ pub struct Opts {
 
     /// Whether Style Sharing Cache is used
     pub disable_share_style_cache: bool,
+
+    /// How strongly glyph outlines are hinted (`--font-hinting`).
+    pub font_hinting: FontHinting,
+
+    /// Whether the platform's automatic hinter is used instead of the font's own hinting
+    /// instructions (`--font-autohint`).
+    pub font_autohint: FontAutohint,
+
+    /// The families that generic font families map to for the characters of particular scripts
+    /// (`--script-font`), in addition to the default family of each generic family.
+    pub script_fonts: Vec<ScriptFont>,
+}
+
+/// How painted pixels are stored. All of them hold premultiplied colors.
+///
+/// Azure only paints with 8 bits per channel, so tiles in the deeper formats are painted with the
+/// software backend, which keeps colors as floats while it paints.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    /// A byte for each of blue, green, red and alpha, in that order. Colors are clamped to sRGB.
+    Bgra8,
+    /// Red, green, blue and alpha in 10, 10, 10 and 2 bits of a little-endian 32-bit word, from
+    /// the least significant bit up. Colors are clamped to sRGB.
+    Rgb10A2,
+    /// A little-endian half float for each of red, green, blue and alpha, in that order. Colors
+    /// outside sRGB, and brighter than its white, are kept.
+    Rgba16F,
+}
+
+impl PixelFormat {
+    fn from_str(string: &str) -> Option<PixelFormat> {
+        match string {
+            "bgra8" => Some(PixelFormat::Bgra8),
+            "rgb10a2" => Some(PixelFormat::Rgb10A2),
+            "rgba16f" => Some(PixelFormat::Rgba16F),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            PixelFormat::Bgra8 | PixelFormat::Rgb10A2 => 4,
+            PixelFormat::Rgba16F => 8,
+        }
+    }
+}
+
+/// A color space that colors are given in and that surfaces are painted in. Both use the sRGB
+/// transfer function; they differ in their primaries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    Srgb,
+    /// The wider gamut of Display P3, which covers colors that sRGB can only show clamped.
+    DisplayP3,
+}
+
+impl ColorSpace {
+    fn from_str(string: &str) -> Option<ColorSpace> {
+        match string {
+            "srgb" => Some(ColorSpace::Srgb),
+            "display-p3" => Some(ColorSpace::DisplayP3),
+            _ => None,
+        }
+    }
+}
+
+/// How the painter divides up the tiles that it is asked for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TilingStrategy {
+    /// Each tile is painted by itself.
+    Squares,
+    /// The tiles in each row of a layer are painted together, in one pass over the display list,
+    /// as a strip as wide as the row, and then cut up. This saves going over the display list
+    /// for each tile of wide layers, at the cost of a larger surface. Only CPU painting into
+    /// native surfaces does this; the others paint squares.
+    Strips,
+}
+
+impl TilingStrategy {
+    fn from_str(string: &str) -> Option<TilingStrategy> {
+        match string {
+            "squares" => Some(TilingStrategy::Squares),
+            "strips" => Some(TilingStrategy::Strips),
+            _ => None,
+        }
+    }
+}
+
+/// A kind of painting work that can be switched off, to find out which kind makes painting slow.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PaintingWork {
+    SolidColors,
+    Text,
+    Images,
+    Borders,
+    Gradients,
+    Lines,
+    BoxShadows,
+    /// The filters of stacking contexts, such as blurs.
+    Filters,
+}
+
+impl PaintingWork {
+    pub fn from_str(string: &str) -> Option<PaintingWork> {
+        match string {
+            "solid-colors" => Some(PaintingWork::SolidColors),
+            "text" => Some(PaintingWork::Text),
+            "images" => Some(PaintingWork::Images),
+            "borders" => Some(PaintingWork::Borders),
+            "gradients" => Some(PaintingWork::Gradients),
+            "lines" => Some(PaintingWork::Lines),
+            "box-shadows" => Some(PaintingWork::BoxShadows),
+            "filters" => Some(PaintingWork::Filters),
+            _ => None,
+        }
+    }
+}
+
+/// How strongly glyph outlines are fitted to the pixel grid.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum FontHinting {
+    /// Outlines are used as designed. Text is smooth but may look blurry at small sizes.
+    None,
+    /// Outlines are fitted vertically only, which preserves glyph shapes and advances.
+    Slight,
+    /// Outlines are fitted along both axes. Text is crisp but glyph shapes may be distorted.
+    Full,
+}
+
+impl FontHinting {
+    fn from_str(string: &str) -> Option<FontHinting> {
+        match string {
+            "none" => Some(FontHinting::None),
+            "slight" => Some(FontHinting::Slight),
+            "full" => Some(FontHinting::Full),
+            _ => None,
+        }
+    }
+}
+
+/// Whether to prefer the automatic hinter over the hinting instructions embedded in a font.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum FontAutohint {
+    /// Let the platform decide, which usually means using the font's own instructions if present.
+    Default,
+    /// Always use the automatic hinter.
+    Force,
+    /// Never use the automatic hinter.
+    Disable,
+}
+
+impl FontAutohint {
+    fn from_str(string: &str) -> Option<FontAutohint> {
+        match string {
+            "default" => Some(FontAutohint::Default),
+            "force" => Some(FontAutohint::Force),
+            "disable" => Some(FontAutohint::Disable),
+            _ => None,
+        }
+    }
+}
+
+/// A default family for a generic font family and a script, given as
+/// `<script>:<generic family>=<family>`, for example `han:serif=Noto Serif CJK SC`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScriptFont {
+    /// The lowercase name of the script, such as `han` or `cyrillic`.
+    pub script: String,
+    /// The generic family, such as `serif` or `sans-serif`.
+    pub generic_family: String,
+    /// The family to use for the script when the generic family is requested.
+    pub family: String,
+}
+
+impl ScriptFont {
+    fn from_str(string: &str) -> Option<ScriptFont> {
+        let (key, family) = match string.find('=') {
+            Some(index) => (&string[..index], &string[index + 1..]),
+            None => return None,
+        };
+        let (script, generic_family) = match key.find(':') {
+            Some(index) => (&key[..index], &key[index + 1..]),
+            None => return None,
+        };
+        if script.is_empty() || generic_family.is_empty() || family.is_empty() {
+            return None
+        }
+        Some(ScriptFont {
+            script: script.to_ascii_lowercase(),
+            generic_family: generic_family.to_ascii_lowercase(),
+            family: family.to_owned(),
+        })
+    }
 }
 
 fn print_usage(app: &str, opts: &[getopts::OptGroup]) {
@@ -158,14 +456,26 @@ pub fn print_debug_usage(app: &str)  {
     print_option("bubble-widths", "Bubble intrinsic widths separately like other engines.");
     print_option("disable-text-aa", "Disable antialiasing of rendered text.");
     print_option("dump-flow-tree", "Print the flow tree after each layout.");
+    print_option("disable-direct-painting", "Paint simple pages into tiles like the others.");
     print_option("dump-display-list", "Print the display list after each layout.");
     print_option("dump-display-list-optimized", "Print optimized display list (at paint time).");
+    print_option("dump-layer-tree", "Print the layers each display list is painted into.");
+    print_option("gpu-quad-batching", "Paint simple display items as batched quads with -g.");
+    print_option("low-res-prepaint", "Paint tiles scrolled into view at low resolution first.");
+    print_option("paint-item-costs", "Print the display items that take the longest to paint.");
     print_option("relayout-event", "Print notifications when there is a relayout.");
     print_option("profile-tasks", "Instrument each task, writing the output to a file.");
+    print_option("shared-memory-tiles", "Paint tiles into shared memory for the embedder.");
     print_option("show-compositor-borders", "Paint borders along layer and tile boundaries.");
+    print_option("show-layer-borders", "Outline layers and tiles in colors, with layer IDs.");
+    print_option("show-hud", "Show painting statistics over the top left of the page.");
+    print_option("show-item-boundaries",
+                 "Paint each display item as a fill in a color for its node, with an outline.");
+    print_option("show-paint-order", "Label each display item with its place in the paint order.");
     print_option("show-fragment-borders", "Paint borders along fragment boundaries.");
     print_option("show-parallel-paint", "Overlay tiles with colors showing which thread painted them.");
     print_option("show-parallel-layout", "Mark which thread laid each flow out with colors.");
+    print_option("show-hexboxes", "Paint the code points of characters missing from fonts.");
     print_option("trace-layout", "Write layout trace to an external file for debugging.");
     print_option("validate-display-list-geometry",
                  "Display an error when display list geometry escapes overflow region.");
@@ -194,10 +504,23 @@ pub fn default_opts() -> Opts {
     Opts {
         url: String::new(),
         paint_threads: 1,
+        paint_thread_stack_size: None,
+        paint_thread_cpus: Vec::new(),
         gpu_painting: false,
+        gpu_quad_batching: false,
+        shared_memory_tiles: false,
+        disable_direct_painting: false,
+        low_res_prepaint: false,
+        pixel_format: PixelFormat::Bgra8,
+        color_space: ColorSpace::Srgb,
         tile_size: 512,
+        tiling_strategy: TilingStrategy::Squares,
+        layer_squash_max_area: 16384,
+        layer_squash_max_overlap: 0.0,
+        display_port_margin: 0.25,
         device_pixels_per_px: None,
         time_profiler_period: None,
+        profiler_timeline_file: None,
         mem_profiler_period: None,
         enable_experimental: false,
         layout_threads: 1,
@@ -209,9 +532,14 @@ pub fn default_opts() -> Opts {
         hard_fail: true,
         bubble_inline_sizes_separately: false,
         show_debug_borders: false,
+        show_debug_layer_borders: false,
+        show_hud: false,
+        show_item_boundaries: false,
+        show_paint_order: false,
         show_debug_fragment_borders: false,
         show_debug_parallel_paint: false,
         show_debug_parallel_layout: false,
+        show_hexboxes: false,
         enable_text_antialiasing: false,
         trace_layout: false,
         devtools_port: None,
@@ -221,12 +549,22 @@ pub fn default_opts() -> Opts {
         dump_flow_tree: false,
         dump_display_list: false,
         dump_display_list_optimized: false,
+        dump_display_list_dir: None,
+        dump_display_list_depth: usize::MAX,
+        tile_checksums_file: None,
+        dump_layer_tree: false,
+        paint_item_costs: false,
+        paint_budget: None,
+        skipped_painting_work: Vec::new(),
         relayout_event: false,
         validate_display_list_geometry: false,
         profile_tasks: false,
         resources_path: None,
         sniff_mime_types: false,
         disable_share_style_cache: false,
+        font_hinting: FontHinting::Full,
+        font_autohint: FontAutohint::Default,
+        script_fonts: vec!(),
     }
 }
 
@@ -239,10 +577,38 @@ pub fn from_cmdline_args(args: &[String]) -> bool {
         getopts::optflag("g", "gpu", "GPU painting"),
         getopts::optopt("o", "output", "Output file", "output.png"),
         getopts::optopt("s", "size", "Size of tiles", "512"),
+        getopts::optopt("", "tiling", "Paint tiles by themselves or in strips", "squares|strips"),
         getopts::optopt("", "device-pixel-ratio", "Device pixels per px", ""),
+        getopts::optopt("", "layer-squash-area",
+                        "Largest area of layers to squash, in square px, or 0 to squash none",
+                        "16384"),
+        getopts::optopt("", "layer-squash-overlap",
+                        "Largest part of a layer that other layers may cover for it to be squashed",
+                        "0"),
+        getopts::optopt("", "paint-budget",
+                        "Report tiles that take longer than this many milliseconds to paint",
+                        "16"),
+        getopts::optopt("", "skip-painting",
+                        "A comma-separated list of kinds of painting work to skip",
+                        "solid-colors,text,images,borders,gradients,lines,box-shadows,filters"),
+        getopts::optopt("", "display-port-margin",
+                        "How far past what is in view to paint, as a fraction of its height",
+                        "0.25"),
         getopts::optflag("e", "experimental", "Enable experimental web features"),
         getopts::optopt("t", "threads", "Number of paint threads", "1"),
+        getopts::optopt("", "paint-stack-size", "Size of the stacks of paint threads in bytes", ""),
+        getopts::optopt("", "paint-cpus", "A comma-separated list of CPUs to keep paint threads to",
+                        "0,1"),
         getopts::optflagopt("p", "profile", "Profiler flag and output interval", "10"),
+        getopts::optopt("", "profiler-timeline", "Write a timeline of paint phases to a file",
+                        "timeline.json"),
+        getopts::optopt("", "dump-display-list",
+                        "Write display lists as JSON to numbered files in a directory", "dir"),
+        getopts::optopt("", "dump-display-list-depth",
+                        "How many stacking contexts deep to print display lists", "4"),
+        getopts::optopt("", "tile-checksums",
+                        "Check painted tiles against a file of hashes, and dump those that differ",
+                        "checksums.txt"),
         getopts::optflagopt("m", "memory-profile", "Memory profiler flag and output interval", "10"),
         getopts::optflag("x", "exit", "Exit after load flag"),
         getopts::optopt("y", "layout-threads", "Number of threads to use for layout", "1"),
@@ -260,6 +626,13 @@ pub fn from_cmdline_args(args: &[String]) -> bool {
         getopts::optopt("r", "render-api", "Set the rendering API to use", "gl|mesa"),
         getopts::optopt("", "resources-path", "Path to find static resources", "/home/servo/resources"),
         getopts::optflag("", "sniff-mime-types" , "Enable MIME sniffing"),
+        getopts::optopt("", "font-hinting", "Glyph hinting level", "none|slight|full"),
+        getopts::optopt("", "pixel-format", "Format of tiles painted into shared memory",
+                        "bgra8|rgb10a2|rgba16f"),
+        getopts::optopt("", "color-space", "Color space to paint tiles in", "srgb|display-p3"),
+        getopts::optopt("", "font-autohint", "Automatic hinter preference", "default|force|disable"),
+        getopts::optmulti("", "script-font", "Default family of a generic family for a script",
+                          "han:serif=Noto Serif CJK SC"),
     );
 
     let opt_match = match getopts::getopts(args, &opts) {
@@ -301,6 +674,53 @@ pub fn from_cmdline_args(args: &[String]) -> bool {
         None => 512,
     };
 
+    let tiling_strategy = match opt_match.opt_str("tiling") {
+        Some(strategy_str) => match TilingStrategy::from_str(&strategy_str) {
+            Some(strategy) => strategy,
+            None => {
+                args_fail(&format!("unknown tiling strategy: {}", strategy_str));
+                return false;
+            }
+        },
+        None => TilingStrategy::Squares,
+    };
+
+    let layer_squash_max_area: usize = match opt_match.opt_str("layer-squash-area") {
+        Some(area_str) => area_str.parse().unwrap(),
+        None => 16384,
+    };
+
+    let layer_squash_max_overlap: f32 = match opt_match.opt_str("layer-squash-overlap") {
+        Some(overlap_str) => overlap_str.parse().unwrap(),
+        None => 0.0,
+    };
+
+    let paint_budget = opt_match.opt_str("paint-budget").map(|budget_str| {
+        budget_str.parse().unwrap()
+    });
+
+    let dump_display_list_depth = opt_match.opt_str("dump-display-list-depth").map(|depth_str| {
+        depth_str.parse().unwrap()
+    }).unwrap_or(usize::MAX);
+
+    let mut skipped_painting_work = Vec::new();
+    if let Some(work_str) = opt_match.opt_str("skip-painting") {
+        for name in work_str.split(',') {
+            match PaintingWork::from_str(name) {
+                Some(work) => skipped_painting_work.push(work),
+                None => {
+                    args_fail(&format!("unknown kind of painting work: {}", name));
+                    return false;
+                }
+            }
+        }
+    }
+
+    let display_port_margin: f32 = match opt_match.opt_str("display-port-margin") {
+        Some(margin_str) => margin_str.parse().unwrap(),
+        None => 0.25,
+    };
+
     let device_pixels_per_px = opt_match.opt_str("device-pixel-ratio").map(|dppx_str|
         ScaleFactor::new(dppx_str.parse().unwrap())
     );
@@ -310,6 +730,29 @@ pub fn from_cmdline_args(args: &[String]) -> bool {
         None => cmp::max(rt::default_sched_threads() * 3 / 4, 1),
     };
 
+    let paint_thread_stack_size = match opt_match.opt_str("paint-stack-size") {
+        Some(size_str) => match size_str.parse() {
+            Ok(size) => Some(size),
+            Err(_) => {
+                args_fail(&format!("invalid paint thread stack size: {}", size_str));
+                return false;
+            }
+        },
+        None => None,
+    };
+    let mut paint_thread_cpus = Vec::new();
+    if let Some(cpus_str) = opt_match.opt_str("paint-cpus") {
+        for cpu_str in cpus_str.split(',') {
+            match cpu_str.parse() {
+                Ok(cpu) => paint_thread_cpus.push(cpu),
+                Err(_) => {
+                    args_fail(&format!("invalid CPU for paint threads: {}", cpu_str));
+                    return false;
+                }
+            }
+        }
+    }
+
     // If only the flag is present, default to a 5 second period for both profilers.
     let time_profiler_period = opt_match.opt_default("p", "5").map(|period| {
         period.parse().unwrap()
@@ -354,13 +797,81 @@ pub fn from_cmdline_args(args: &[String]) -> bool {
         }
     };
 
+    let font_hinting = match opt_match.opt_str("font-hinting") {
+        Some(hinting_str) => match FontHinting::from_str(&hinting_str) {
+            Some(hinting) => hinting,
+            None => {
+                args_fail(&format!("unknown font hinting level: {}", hinting_str));
+                return false;
+            }
+        },
+        None => FontHinting::Full,
+    };
+
+    let font_autohint = match opt_match.opt_str("font-autohint") {
+        Some(autohint_str) => match FontAutohint::from_str(&autohint_str) {
+            Some(autohint) => autohint,
+            None => {
+                args_fail(&format!("unknown font autohint preference: {}", autohint_str));
+                return false;
+            }
+        },
+        None => FontAutohint::Default,
+    };
+
+    let pixel_format = match opt_match.opt_str("pixel-format") {
+        Some(format_str) => match PixelFormat::from_str(&format_str) {
+            Some(format) => format,
+            None => {
+                args_fail(&format!("unknown pixel format: {}", format_str));
+                return false;
+            }
+        },
+        None => PixelFormat::Bgra8,
+    };
+
+    let color_space = match opt_match.opt_str("color-space") {
+        Some(space_str) => match ColorSpace::from_str(&space_str) {
+            Some(space) => space,
+            None => {
+                args_fail(&format!("unknown color space: {}", space_str));
+                return false;
+            }
+        },
+        None => ColorSpace::Srgb,
+    };
+
+    let mut script_fonts = vec!();
+    for script_font_str in opt_match.opt_strs("script-font").iter() {
+        match ScriptFont::from_str(script_font_str) {
+            Some(script_font) => script_fonts.push(script_font),
+            None => {
+                args_fail(&format!("invalid script font: {}", script_font_str));
+                return false;
+            }
+        }
+    }
+
     let opts = Opts {
         url: url,
         paint_threads: paint_threads,
+        paint_thread_stack_size: paint_thread_stack_size,
+        paint_thread_cpus: paint_thread_cpus,
         gpu_painting: gpu_painting,
+        gpu_quad_batching: debug_options.contains(&"gpu-quad-batching"),
+        shared_memory_tiles: !gpu_painting && debug_options.contains(&"shared-memory-tiles"),
+        disable_direct_painting: debug_options.contains(&"disable-direct-painting"),
+        low_res_prepaint: debug_options.contains(&"low-res-prepaint"),
+        pixel_format: pixel_format,
+        color_space: color_space,
         tile_size: tile_size,
+        tiling_strategy: tiling_strategy,
+        layer_squash_max_area: layer_squash_max_area,
+        layer_squash_max_overlap: layer_squash_max_overlap,
+        display_port_margin: display_port_margin,
         device_pixels_per_px: device_pixels_per_px,
         time_profiler_period: time_profiler_period,
+        profiler_timeline_file: opt_match.opt_str("profiler-timeline"),
         mem_profiler_period: mem_profiler_period,
         enable_experimental: opt_match.opt_present("e"),
         layout_threads: layout_threads,
@@ -378,18 +889,33 @@ pub fn from_cmdline_args(args: &[String]) -> bool {
         initial_window_size: initial_window_size,
         user_agent: opt_match.opt_str("u"),
         show_debug_borders: debug_options.contains(&"show-compositor-borders"),
+        show_debug_layer_borders: debug_options.contains(&"show-layer-borders"),
+        show_hud: debug_options.contains(&"show-hud"),
+        show_item_boundaries: debug_options.contains(&"show-item-boundaries"),
+        show_paint_order: debug_options.contains(&"show-paint-order"),
         show_debug_fragment_borders: debug_options.contains(&"show-fragment-borders"),
         show_debug_parallel_paint: debug_options.contains(&"show-parallel-paint"),
         show_debug_parallel_layout: debug_options.contains(&"show-parallel-layout"),
+        show_hexboxes: debug_options.contains(&"show-hexboxes"),
         enable_text_antialiasing: !debug_options.contains(&"disable-text-aa"),
         dump_flow_tree: debug_options.contains(&"dump-flow-tree"),
         dump_display_list: debug_options.contains(&"dump-display-list"),
         dump_display_list_optimized: debug_options.contains(&"dump-display-list-optimized"),
+        dump_display_list_dir: opt_match.opt_str("dump-display-list"),
+        dump_display_list_depth: dump_display_list_depth,
+        tile_checksums_file: opt_match.opt_str("tile-checksums"),
+        dump_layer_tree: debug_options.contains(&"dump-layer-tree"),
+        paint_item_costs: debug_options.contains(&"paint-item-costs"),
+        paint_budget: paint_budget,
+        skipped_painting_work: skipped_painting_work,
         relayout_event: debug_options.contains(&"relayout-event"),
         validate_display_list_geometry: debug_options.contains(&"validate-display-list-geometry"),
         resources_path: opt_match.opt_str("resources-path"),
         sniff_mime_types: opt_match.opt_present("sniff-mime-types"),
         disable_share_style_cache: debug_options.contains(&"disable-share-style-cache"),
+        font_hinting: font_hinting,
+        font_autohint: font_autohint,
+        script_fonts: script_fonts,
     };
 
     set_opts(opts);
@@ -17,6 +17,47 @@ pub fn spawn_named<F>(name: String, f: F)
     }).unwrap();
 }
 
+/// Like `spawn_named`, but gives the thread a stack of the given size in bytes, if one is given.
+pub fn spawn_named_with_stack_size<F>(name: String, stack_size: Option<usize>, f: F)
+    where F: FnOnce() + Send + 'static
+{
+    let mut builder = thread::Builder::new().name(name);
+    if let Some(stack_size) = stack_size {
+        builder = builder.stack_size(stack_size)
+    }
+    builder.spawn(move || {
+        f()
+    }).unwrap();
+}
+
+/// Keeps the calling thread to the given CPU. Returns false if that isn't possible, as on
+/// platforms other than Linux.
+#[cfg(target_os="linux")]
+pub fn set_cpu_affinity(cpu: usize) -> bool {
+    use libc::{c_int, c_ulong, size_t};
+    use std::mem;
+
+    extern {
+        fn sched_setaffinity(pid: c_int, cpusetsize: size_t, mask: *const c_ulong) -> c_int;
+    }
+
+    // As large as the `cpu_set_t` of glibc on 64-bit platforms.
+    let mut mask = [0 as c_ulong; 1024 / 64];
+    let bits = mem::size_of::<c_ulong>() * 8;
+    if cpu >= mask.len() * bits {
+        return false
+    }
+    mask[cpu / bits] |= 1 << (cpu % bits);
+    unsafe {
+        sched_setaffinity(0, mem::size_of_val(&mask) as size_t, mask.as_ptr()) == 0
+    }
+}
+
+#[cfg(not(target_os="linux"))]
+pub fn set_cpu_affinity(_: usize) -> bool {
+    false
+}
+
 /// Arrange to send a particular message to a channel if the task fails.
 pub fn spawn_named_with_send_on_failure<F, T>(name: &'static str,
                                               state: task_state::TaskState,
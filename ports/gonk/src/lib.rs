@@ -97,7 +97,9 @@ impl Browser {
         // to deliver the message.
         let (compositor_proxy, compositor_receiver) =
             WindowMethods::create_compositor_channel(&window);
-        let time_profiler_chan = time::Profiler::create(opts.time_profiler_period);
+        let time_profiler_chan =
+            time::Profiler::create(opts.time_profiler_period,
+                                   opts.profiler_timeline_file.clone());
         let mem_profiler_chan = mem::Profiler::create(opts.mem_profiler_period);
         let devtools_chan = opts.devtools_port.map(|port| {
             devtools::start_server(port)
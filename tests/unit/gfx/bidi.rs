@@ -0,0 +1,51 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use gfx::text::bidi::{resolve_levels, split_at_level_changes};
+use gfx::text::glyph::CharIndex;
+use util::range::Range;
+
+#[test]
+fn test_left_to_right_text_keeps_the_paragraph_level() {
+    assert_eq!(resolve_levels("abc 123", 0), vec!(0, 0, 0, 0, 0, 0, 0));
+}
+
+#[test]
+fn test_latin_text_and_numbers_in_right_to_left_paragraphs_are_left_to_right() {
+    // "שלום abc 12"
+    let levels = resolve_levels("\u{5e9}\u{5dc}\u{5d5}\u{5dd} abc 12", 1);
+    assert_eq!(levels, vec!(1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2));
+}
+
+#[test]
+fn test_hebrew_and_numbers_in_left_to_right_paragraphs() {
+    // "a שם 12": the space between the Hebrew word and the number is right-to-left, and the
+    // number is one level deeper.
+    let levels = resolve_levels("a \u{5e9}\u{5dd} 12", 0);
+    assert_eq!(levels, vec!(0, 0, 1, 1, 1, 2, 2));
+}
+
+#[test]
+fn test_numbers_after_arabic_letters_are_arabic_numbers() {
+    let levels = resolve_levels("\u{627}12", 0);
+    assert_eq!(levels, vec!(1, 2, 2));
+}
+
+#[test]
+fn test_marks_take_the_level_of_the_character_before_them() {
+    let levels = resolve_levels("\u{5d0}\u{5b0}a\u{301}", 0);
+    assert_eq!(levels, vec!(1, 1, 0, 0));
+}
+
+#[test]
+fn test_ranges_split_at_level_changes() {
+    let levels = [1, 1, 2, 2, 2, 1];
+    let parts = split_at_level_changes(&Range::new(CharIndex(1), CharIndex(5)), &levels);
+    let parts: Vec<_> = parts.iter().map(|&(range, level)| {
+        (range.begin(), range.length(), level)
+    }).collect();
+    assert_eq!(parts, vec!((CharIndex(1), CharIndex(1), 1),
+                           (CharIndex(2), CharIndex(3), 2),
+                           (CharIndex(5), CharIndex(1), 1)));
+}
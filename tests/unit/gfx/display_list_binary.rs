@@ -0,0 +1,441 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use azure::azure_hl::GradientStop;
+use geom::matrix::{self, Matrix4};
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use gfx::color;
+use gfx::display_list::binary::{DecodeError, decode_display_list, decode_stacking_context};
+use gfx::display_list::binary::{encode_display_list, encode_stacking_context};
+use gfx::display_list::{BaseDisplayItem, BorderDisplayItem, BorderRadii, BoxShadowClipMode};
+use gfx::display_list::{BoxShadowDisplayItem, ClippingRegion, CursorRegion, DisplayItem};
+use gfx::display_list::{DisplayItemMetadata, DisplayList, DisplayListSection};
+use gfx::display_list::{EmphasisMarks, ExternalTextureDisplayItem, GradientDisplayItem};
+use gfx::display_list::{IframeDisplayItem, ImageAnimation, ImageDisplayItem, ImageFrame};
+use gfx::display_list::{ImageOrientation, ImageRepeat, ImageSource, LineDisplayItem, OpaqueNode};
+use gfx::display_list::{RadialGradientDisplayItem, RubyAnnotation, RubyPosition};
+use gfx::display_list::{SolidColorDisplayItem, StackingContext, TextDecoration};
+use gfx::display_list::{TextDecorationLine, TextDecorationStyle, TextDisplayItem};
+use gfx::display_list::{TextOrientation, TextShadow};
+use gfx::font::{FontMetrics, FontVariation, FontVariations, SYNTHETIC_BOLD_FONT_FLAG};
+use gfx::font_instance::FontInstanceKey;
+use gfx::image_generator::GeneratedImage;
+use gfx::paint_task::PaintLayer;
+use gfx::platform::font_template::FontTemplateData;
+use gfx::text::glyph::{CharIndex, GlyphData, GlyphStore, GlyphStoreData};
+use gfx::text::text_run::{GlyphRun, TextRun};
+use msg::compositor_msg::{ExternalTextureId, LayerId, ScrollPolicy};
+use msg::constellation_msg::{PipelineId, SubpageId};
+use net_traits::image::base::{DecodedImages, Image, LazyImage, MAX_DECODED_SIZE};
+use png::PixelsByColorType;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use style::computed_values::{border_style, filter, image_rendering, mix_blend_mode};
+use util::cursor::Cursor;
+use util::geometry::Au;
+use util::opts::{FontAutohint, FontHinting};
+use util::range::Range;
+
+fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect<Au> {
+    Rect(Point2D(Au(x), Au(y)), Size2D(Au(width), Au(height)))
+}
+
+fn base() -> BaseDisplayItem {
+    let metadata = DisplayItemMetadata {
+        node: OpaqueNode(7),
+        pointing: Some(Cursor::TextCursor),
+        tag: 3,
+    };
+    let clip = ClippingRegion::from_rect(&rect(0, 0, 600, 600))
+        .intersect_with_rounded_rect(&rect(60, 60, 300, 300), &BorderRadii::all_same(Au(30)));
+    let mut base = BaseDisplayItem::new(rect(60, 120, 240, 180), metadata, clip);
+    base.cursor_regions.push(CursorRegion {
+        rect: rect(60, 120, 60, 60),
+        cursor: Cursor::PointerCursor,
+    });
+    base
+}
+
+/// A run of three characters: one simple glyph, two detailed glyphs for one character, and a
+/// character without a glyph of its own.
+fn text_run() -> Arc<Box<TextRun>> {
+    let mut glyphs = GlyphStore::new(3, false);
+    glyphs.add_glyph_for_char_index(CharIndex(0),
+                                    Some('f'),
+                                    &GlyphData::new(1, Au(600), None, false, true, true));
+    glyphs.add_glyphs_for_char_index(CharIndex(1), &[
+        GlyphData::new(2, Au(500), Some(Point2D(Au(10), Au(-5))), false, true, true),
+        GlyphData::new(3, Au(400), None, false, true, false),
+    ]);
+    glyphs.add_nonglyph_for_char_index(CharIndex(2), false, false);
+    glyphs.finalize_changes();
+
+    let mut glyph_descents = HashMap::new();
+    glyph_descents.insert(2, Au(120));
+    Arc::new(box TextRun {
+        text: Arc::new("fix".to_owned()),
+        font_template: Arc::new(FontTemplateData::new("test-font", Some(vec![0, 1, 2, 3]))),
+        font_key: FontInstanceKey {
+            identifier: "test-font".to_owned(),
+            pt_size: Au(720),
+            variations: FontVariations {
+                named_instance: Some(1),
+                axes: vec![FontVariation {
+                    tag: 0x77676874,
+                    value: 700,
+                }],
+            },
+            hinting: FontHinting::Slight,
+            autohint: FontAutohint::Force,
+        },
+        actual_pt_size: Au(720),
+        font_metrics: FontMetrics {
+            underline_size: Au(60),
+            underline_offset: Au(-120),
+            strikeout_size: Au(60),
+            strikeout_offset: Au(300),
+            leading: Au(0),
+            x_height: Au(480),
+            em_size: Au(960),
+            ascent: Au(720),
+            descent: Au(240),
+            max_advance: Au(600),
+            average_advance: Au(480),
+            line_gap: Au(60),
+        },
+        synthesis: SYNTHETIC_BOLD_FONT_FLAG,
+        glyphs: Arc::new(vec![GlyphRun {
+            glyph_store: Arc::new(glyphs),
+            range: Range::new(CharIndex(0), CharIndex(3)),
+            upright: false,
+        }]),
+        glyph_descents: Arc::new(glyph_descents),
+        bidi_level: 0,
+    })
+}
+
+fn image(width: u32, height: u32) -> Arc<Image> {
+    let pixels = (0..width * height * 4).map(|value| value as u8).collect();
+    Arc::new(Image::new(width, height, PixelsByColorType::RGBA8(pixels)))
+}
+
+fn text_item(text_run: Arc<Box<TextRun>>) -> DisplayItem {
+    DisplayItem::TextClass(box TextDisplayItem {
+        base: base(),
+        text_run: text_run.clone(),
+        range: Range::new(CharIndex(0), CharIndex(3)),
+        text_color: color::rgba(0.0, 0.0, 1.0, 1.0),
+        baseline_origin: Point2D(Au(60), Au(240)),
+        orientation: TextOrientation::SidewaysRight,
+        blur_radius: Au(0),
+        shadows: vec![TextShadow {
+            offset: Point2D(Au(60), Au(60)),
+            blur_radius: Au(120),
+            color: color::rgba(0.0, 0.0, 0.0, 0.5),
+        }],
+        decorations: vec![TextDecoration {
+            line: TextDecorationLine::Underline,
+            style: TextDecorationStyle::Wavy,
+            color: color::rgba(1.0, 0.0, 0.0, 1.0),
+            skip_ink: true,
+        }],
+        ruby_annotation: Some(RubyAnnotation {
+            text_run: text_run.clone(),
+            range: Range::new(CharIndex(1), CharIndex(2)),
+            text_color: color::rgba(0.0, 1.0, 0.0, 1.0),
+            position: RubyPosition::Under,
+        }),
+        emphasis_marks: Some(EmphasisMarks {
+            text_run: text_run,
+            range: Range::new(CharIndex(0), CharIndex(1)),
+            text_color: color::rgba(1.0, 0.0, 1.0, 1.0),
+            position: RubyPosition::Over,
+        }),
+    })
+}
+
+fn image_item(source: ImageSource, animation: Option<ImageAnimation>) -> DisplayItem {
+    DisplayItem::ImageClass(box ImageDisplayItem {
+        base: base(),
+        image: source,
+        source_rect: Some(Rect(Point2D(1.0, 2.0), Size2D(3.0, 4.0))),
+        stretch_size: Size2D(Au(120), Au(60)),
+        horizontal_repeat: ImageRepeat::Round,
+        vertical_repeat: ImageRepeat::Space,
+        image_rendering: image_rendering::T::Pixelated,
+        animation: animation,
+        orientation: ImageOrientation::from_exif(7),
+        is_opaque: false,
+    })
+}
+
+/// Returns one item of each kind.
+fn every_kind_of_item() -> Vec<DisplayItem> {
+    let frames = Arc::new(vec![
+        ImageFrame {
+            image: image(2, 3),
+            delay: 50,
+        },
+        ImageFrame {
+            image: image(2, 3),
+            delay: 0,
+        },
+    ]);
+    let stops = vec![
+        GradientStop {
+            offset: 0.0,
+            color: color::rgba(1.0, 1.0, 1.0, 1.0),
+        },
+        GradientStop {
+            offset: 0.75,
+            color: color::rgba(0.0, 0.5, 0.0, 0.25),
+        },
+    ];
+    let lazy_images = Arc::new(Mutex::new(DecodedImages::new(MAX_DECODED_SIZE)));
+    vec![
+        DisplayItem::SolidColorClass(box SolidColorDisplayItem {
+            base: base(),
+            color: color::rgba(0.25, 0.5, 0.75, 1.0),
+        }),
+        text_item(text_run()),
+        image_item(ImageSource::Decoded(image(2, 3)),
+                   Some(ImageAnimation {
+                       frames: frames,
+                       start_time: 1_000_000,
+                       repeat: true,
+                   })),
+        image_item(ImageSource::Lazy(LazyImage::new(vec![137, 80, 78, 71], 16, 8, &lazy_images)),
+                   None),
+        image_item(ImageSource::Generated(GeneratedImage {
+                       name: "checkers".to_owned(),
+                       arguments: vec!["4px".to_owned(), "red".to_owned()],
+                   }),
+                   None),
+        DisplayItem::BorderClass(box BorderDisplayItem {
+            base: base(),
+            border_widths: SideOffsets2D::new(Au(60), Au(120), Au(180), Au(240)),
+            color: SideOffsets2D::new_all_same(color::rgba(0.0, 0.0, 0.0, 1.0)),
+            style: SideOffsets2D::new(border_style::T::solid,
+                                      border_style::T::dashed,
+                                      border_style::T::dotted,
+                                      border_style::T::double),
+            radius: BorderRadii {
+                top_left: Au(10),
+                top_right: Au(20),
+                bottom_right: Au(30),
+                bottom_left: Au(40),
+            },
+        }),
+        DisplayItem::GradientClass(box GradientDisplayItem {
+            base: base(),
+            start_point: Point2D(Au(0), Au(0)),
+            end_point: Point2D(Au(240), Au(180)),
+            stops: stops.clone(),
+            repeating: true,
+        }),
+        DisplayItem::RadialGradientClass(box RadialGradientDisplayItem {
+            base: base(),
+            center: Point2D(Au(120), Au(90)),
+            radius: Size2D(Au(120), Au(60)),
+            stops: stops,
+        }),
+        DisplayItem::LineClass(box LineDisplayItem {
+            base: base(),
+            color: color::rgba(0.5, 0.5, 0.5, 1.0),
+            style: border_style::T::dashed,
+        }),
+        DisplayItem::BoxShadowClass(box BoxShadowDisplayItem {
+            base: base(),
+            box_bounds: rect(60, 60, 120, 120),
+            offset: Point2D(Au(30), Au(-30)),
+            color: color::rgba(0.0, 0.0, 0.0, 0.5),
+            blur_radius: Au(60),
+            spread_radius: Au(-30),
+            clip_mode: BoxShadowClipMode::Inset,
+        }),
+        DisplayItem::ExternalTextureClass(box ExternalTextureDisplayItem {
+            base: base(),
+            texture: ExternalTextureId(5),
+        }),
+        DisplayItem::IframeClass(box IframeDisplayItem {
+            base: base(),
+            pipeline_id: PipelineId(2),
+            subpage_id: SubpageId(3),
+        }),
+    ]
+}
+
+fn display_list_of(items: Vec<DisplayItem>) -> DisplayList {
+    let mut display_list = DisplayList::new();
+    display_list.get_mut(DisplayListSection::Content).extend(items.into_iter());
+    display_list
+}
+
+/// A stacking context with a child in a layer of its own, which has a child of its own.
+fn nested_stacking_contexts() -> StackingContext {
+    let mut grandchild_list = display_list_of(every_kind_of_item());
+    grandchild_list.get_mut(DisplayListSection::Outlines).push(text_item(text_run()));
+    let grandchild = StackingContext::new(box grandchild_list,
+                                          &rect(0, 0, 600, 600),
+                                          &rect(-60, -60, 720, 720),
+                                          2,
+                                          &Matrix4::new(1.0, 0.0, 0.0, 0.0,
+                                                        0.0, 1.0, 0.0, 0.0,
+                                                        0.0, 0.0, 1.0, 0.0,
+                                                        10.0, 20.0, 0.0, 1.0),
+                                          &matrix::identity(),
+                                          true,
+                                          filter::T::new(vec![filter::Filter::Blur(Au(60))]),
+                                          mix_blend_mode::T::multiply,
+                                          None,
+                                          2.0);
+
+    let mut child_list = display_list_of(every_kind_of_item());
+    child_list.children.push(Arc::new(grandchild));
+    let layer = PaintLayer::new(LayerId(4, 1),
+                                color::rgba(1.0, 1.0, 1.0, 1.0),
+                                ScrollPolicy::FixedPosition);
+    let child = StackingContext::new(box child_list,
+                                     &rect(60, 60, 600, 600),
+                                     &rect(0, 0, 600, 600),
+                                     -1,
+                                     &matrix::identity(),
+                                     &matrix::identity(),
+                                     false,
+                                     filter::T::new(Vec::new()),
+                                     mix_blend_mode::T::normal,
+                                     Some(Arc::new(layer)),
+                                     1.0);
+
+    let mut root_list = display_list_of(every_kind_of_item());
+    root_list.get_mut(DisplayListSection::BackgroundAndBorders)
+             .push(every_kind_of_item().remove(0));
+    root_list.children.push(Arc::new(child));
+    StackingContext::new(box root_list,
+                         &rect(0, 0, 1200, 1200),
+                         &rect(0, 0, 1200, 1200),
+                         0,
+                         &matrix::identity(),
+                         &matrix::identity(),
+                         false,
+                         filter::T::new(Vec::new()),
+                         mix_blend_mode::T::normal,
+                         None,
+                         1.0)
+}
+
+// Values have no equality of their own, so a decoded value is compared with the original by
+// encoding it again: the codec writes every field, so the bytes are the same only if the values
+// are.
+
+#[test]
+fn test_every_kind_of_item_round_trips() {
+    for item in every_kind_of_item().into_iter() {
+        let bytes = encode_display_list(&display_list_of(vec![item]));
+        let decoded = decode_display_list(&bytes).unwrap();
+        assert_eq!(decoded.get(DisplayListSection::Content).len(), 1);
+        assert_eq!(encode_display_list(&decoded), bytes);
+    }
+}
+
+#[test]
+fn test_nested_stacking_contexts_round_trip() {
+    let bytes = encode_stacking_context(&nested_stacking_contexts());
+    let decoded = decode_stacking_context(&bytes).unwrap();
+    assert_eq!(encode_stacking_context(&decoded), bytes);
+
+    let child = &decoded.display_list.children[0];
+    assert_eq!(child.z_index, -1);
+    assert_eq!(child.layer.as_ref().unwrap().id, LayerId(4, 1));
+    let grandchild = &child.display_list.children[0];
+    assert_eq!(grandchild.z_index, 2);
+    assert!(grandchild.preserves_3d);
+    assert_eq!(grandchild.device_pixels_per_px, 2.0);
+    assert_eq!(grandchild.display_list.get(DisplayListSection::Outlines).len(), 1);
+}
+
+#[test]
+fn test_shared_values_stay_shared() {
+    let text_run = text_run();
+    let bytes = encode_display_list(&display_list_of(vec![text_item(text_run.clone()),
+                                                          text_item(text_run)]));
+    let decoded = decode_display_list(&bytes).unwrap();
+    let items = decoded.get(DisplayListSection::Content);
+    match (&items[0], &items[1]) {
+        (&DisplayItem::TextClass(ref first), &DisplayItem::TextClass(ref second)) => {
+            assert!(&*first.text_run as *const _ == &*second.text_run as *const _);
+            let annotation = first.ruby_annotation.as_ref().unwrap();
+            assert!(&*first.text_run as *const _ == &*annotation.text_run as *const _);
+            let marks = first.emphasis_marks.as_ref().unwrap();
+            assert!(&*first.text_run as *const _ == &*marks.text_run as *const _);
+        }
+        _ => panic!("expected two text items"),
+    }
+}
+
+#[test]
+fn test_truncated_input_is_rejected() {
+    let bytes = encode_stacking_context(&nested_stacking_contexts());
+    for length in 0..bytes.len() {
+        assert!(decode_stacking_context(&bytes[..length]).is_err());
+    }
+}
+
+#[test]
+fn test_trailing_bytes_are_rejected() {
+    let mut bytes = encode_display_list(&display_list_of(every_kind_of_item()));
+    bytes.push(0);
+    assert_eq!(decode_display_list(&bytes).err(), Some(DecodeError::WrongLength));
+}
+
+#[test]
+fn test_corrupt_input_is_rejected_or_decoded_without_panicking() {
+    let bytes = encode_display_list(&display_list_of(every_kind_of_item()));
+    for index in 0..bytes.len() {
+        for &value in [0x00, 0x7f, 0xff].iter() {
+            let mut corrupt = bytes.clone();
+            corrupt[index] = value;
+            if let Ok(display_list) = decode_display_list(&corrupt) {
+                encode_display_list(&display_list);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_text_ranges_outside_the_run_are_rejected() {
+    let mut item = text_item(text_run());
+    if let DisplayItem::TextClass(ref mut text) = item {
+        text.range = Range::new(CharIndex(2), CharIndex(2));
+    }
+    let bytes = encode_display_list(&display_list_of(vec![item]));
+    assert_eq!(decode_display_list(&bytes).err(), Some(DecodeError::InvalidValue));
+}
+
+#[test]
+fn test_glyph_stores_with_indices_out_of_bounds_are_rejected() {
+    let data = || text_run().glyphs[0].glyph_store.to_data();
+    assert!(GlyphStore::from_data(data()).is_some());
+
+    let mut lookup_past_the_characters = data();
+    lookup_past_the_characters.detail_lookup[0].0 = CharIndex(3);
+    assert!(GlyphStore::from_data(lookup_past_the_characters).is_none());
+
+    let mut lookup_past_the_glyphs = data();
+    lookup_past_the_glyphs.detail_lookup[0].1 = 1;
+    assert!(GlyphStore::from_data(lookup_past_the_glyphs).is_none());
+
+    let mut missing_glyphs = data();
+    missing_glyphs.detailed_glyphs.pop();
+    assert!(GlyphStore::from_data(missing_glyphs).is_none());
+
+    let missing_lookup = GlyphStoreData {
+        detail_lookup: Vec::new(),
+        ..data()
+    };
+    assert!(GlyphStore::from_data(missing_lookup).is_none());
+}
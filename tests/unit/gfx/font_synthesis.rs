@@ -0,0 +1,30 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use gfx::font::{FontSynthesisFlags, SYNTHETIC_BOLD_FONT_FLAG, SYNTHETIC_OBLIQUE_FONT_FLAG};
+use gfx::font::{WDTH_AXIS_TAG, WGHT_AXIS_TAG};
+use gfx::font_template::FontTemplateDescriptor;
+use style::computed_values::{font_stretch, font_weight};
+
+fn bold_italic() -> FontTemplateDescriptor {
+    FontTemplateDescriptor::new(font_weight::T::Weight700, font_stretch::T::normal, true)
+}
+
+#[test]
+fn test_for_face_synthesizes_what_the_face_lacks() {
+    let flags = FontSynthesisFlags::for_face(&bold_italic(), false, false, &[]);
+    assert!(flags == SYNTHETIC_BOLD_FONT_FLAG | SYNTHETIC_OBLIQUE_FONT_FLAG);
+    assert!(FontSynthesisFlags::for_face(&bold_italic(), true, true, &[]).is_empty());
+}
+
+#[test]
+fn test_for_face_does_not_embolden_a_varied_weight() {
+    // A variable face declares its default weight, but its `wght` axis was set to the one
+    // requested.
+    let flags = FontSynthesisFlags::for_face(&bold_italic(), false, true, &[WGHT_AXIS_TAG]);
+    assert!(flags.is_empty());
+
+    let flags = FontSynthesisFlags::for_face(&bold_italic(), false, true, &[WDTH_AXIS_TAG]);
+    assert!(flags == SYNTHETIC_BOLD_FONT_FLAG);
+}
@@ -0,0 +1,34 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use gfx::font::{FontHandleMethods, FontTableMethods, FontTableTag};
+use gfx::platform::font::FontHandle;
+use gfx::platform::font_context::FontContextHandle;
+use gfx::platform::font_template::FontTemplateData;
+use std::slice;
+use std::sync::Arc;
+
+static HEAD: FontTableTag = 0x68656164;
+
+fn font_handle() -> FontHandle {
+    let template = FontTemplateData::new("../net/parsable_mime/unknown/true_type.ttf", None);
+    FontHandleMethods::new_from_template(&FontContextHandle::new(), Arc::new(template), None)
+        .unwrap()
+}
+
+#[test]
+fn test_get_table_for_tag_loads_the_table() {
+    let table = font_handle().get_table_for_tag(HEAD).unwrap();
+    table.with_buffer(|buffer, length| {
+        // The `head` table is 54 bytes long, and has a magic number at offset 12.
+        assert!(length >= 54);
+        let data = unsafe { slice::from_raw_parts(buffer, length) };
+        assert_eq!(&data[12..16], &[0x5f, 0x0f, 0x3c, 0xf5]);
+    });
+}
+
+#[test]
+fn test_get_table_for_tag_of_missing_table() {
+    assert!(font_handle().get_table_for_tag(0x7a7a7a7a).is_none());
+}
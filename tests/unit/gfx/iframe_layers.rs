@@ -0,0 +1,116 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::matrix;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::color;
+use gfx::display_list::{BaseDisplayItem, ClippingRegion, DisplayItem, DisplayItemMetadata};
+use gfx::display_list::{DisplayList, DisplayListSection, IframeDisplayItem, OpaqueNode};
+use gfx::display_list::{SolidColorDisplayItem, StackingContext};
+use msg::constellation_msg::{PipelineId, SubpageId};
+use std::sync::Arc;
+use style::computed_values::{filter, mix_blend_mode};
+use util::geometry::Au;
+
+fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect<Au> {
+    Rect(Point2D(Au(x), Au(y)), Size2D(Au(width), Au(height)))
+}
+
+fn base(node: usize) -> BaseDisplayItem {
+    let metadata = DisplayItemMetadata {
+        node: OpaqueNode(node),
+        pointing: None,
+        tag: 0,
+    };
+    BaseDisplayItem::new(rect(0, 0, 300, 300), metadata, ClippingRegion::max())
+}
+
+fn solid_color(node: usize) -> DisplayItem {
+    DisplayItem::SolidColorClass(box SolidColorDisplayItem {
+        base: base(node),
+        color: color::rgba(0.0, 0.0, 1.0, 1.0),
+    })
+}
+
+fn iframe(node: usize) -> DisplayItem {
+    DisplayItem::IframeClass(box IframeDisplayItem {
+        base: base(node),
+        pipeline_id: PipelineId(0),
+        subpage_id: SubpageId(1),
+    })
+}
+
+fn stacking_context(display_list: DisplayList, z_index: i32) -> StackingContext {
+    StackingContext::new(box display_list,
+                         &rect(0, 0, 600, 600),
+                         &rect(0, 0, 600, 600),
+                         z_index,
+                         &matrix::identity(),
+                         &matrix::identity(),
+                         false,
+                         filter::T::new(Vec::new()),
+                         mix_blend_mode::T::normal,
+                         None,
+                         1.0)
+}
+
+/// The nodes of the items of a display list, in the order that they are painted in.
+fn nodes(display_list: &DisplayList) -> Vec<usize> {
+    display_list.iter().map(|item| item.base().metadata.node.id() as usize).collect()
+}
+
+#[test]
+fn test_items_after_iframe_go_in_layer_above_it() {
+    // The document in the iframe is composited over the layer it is in, so what is painted over
+    // the iframe can't be painted into that layer.
+    let mut display_list = DisplayList::new();
+    display_list.get_mut(DisplayListSection::BackgroundAndBorders).push(solid_color(1));
+    display_list.get_mut(DisplayListSection::Content).push(solid_color(2));
+    display_list.get_mut(DisplayListSection::Content).push(iframe(3));
+    display_list.get_mut(DisplayListSection::Content).push(solid_color(4));
+    display_list.get_mut(DisplayListSection::Outlines).push(solid_color(5));
+    let mut kid_list = DisplayList::new();
+    kid_list.get_mut(DisplayListSection::Content).push(solid_color(6));
+    display_list.children.push(Arc::new(stacking_context(kid_list, 1)));
+
+    let parent = stacking_context(display_list, 0);
+    assert_eq!(nodes(&parent.display_list), vec![1, 2, 3]);
+    assert_eq!(parent.display_list.children.len(), 1);
+    let overlay = &parent.display_list.children[0];
+    assert_eq!(overlay.layer.as_ref().unwrap().id.0, 3);
+    assert_eq!(overlay.bounds, rect(0, 0, 600, 600));
+    assert_eq!(nodes(&overlay.display_list), vec![4, 5]);
+    assert_eq!(overlay.display_list.children.len(), 1);
+    assert_eq!(nodes(&overlay.display_list.children[0].display_list), vec![6]);
+
+    // Making the stacking context again from the split display list doesn't split it again.
+    let children = parent.display_list.children.clone();
+    let again = stacking_context(parent.display_list.clone_with_children(children), 0);
+    assert_eq!(again.display_list.children.len(), 1);
+    assert_eq!(nodes(&again.display_list.children[0].display_list), vec![4, 5]);
+}
+
+#[test]
+fn test_items_after_iframe_in_child_go_in_layer_above_it() {
+    // An iframe in a stacking context that is painted into its parent splits the parent too.
+    let mut kid_list = DisplayList::new();
+    kid_list.get_mut(DisplayListSection::Content).push(iframe(1));
+    kid_list.get_mut(DisplayListSection::Content).push(solid_color(2));
+    let mut display_list = DisplayList::new();
+    display_list.get_mut(DisplayListSection::Content).push(solid_color(3));
+    display_list.get_mut(DisplayListSection::Outlines).push(solid_color(4));
+    display_list.children.push(Arc::new(stacking_context(kid_list, 0)));
+
+    let parent = stacking_context(display_list, 0);
+    assert_eq!(nodes(&parent.display_list), vec![3]);
+    assert_eq!(parent.display_list.children.len(), 2);
+    let kid = &parent.display_list.children[0];
+    let kid_overlay = &kid.display_list.children[0];
+    assert_eq!(nodes(&kid_overlay.display_list), vec![2]);
+    let overlay = &parent.display_list.children[1];
+    assert_eq!(nodes(&overlay.display_list), vec![4]);
+    assert!(overlay.layer.as_ref().unwrap().id != kid_overlay.layer.as_ref().unwrap().id);
+}
@@ -0,0 +1,127 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use gfx::image_generator::{self, GeneratedImage, ImageGenerator, ImageGeneratorRegistry};
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Draws an image filled with the value of its first argument, and counts how many it draws.
+struct FillGenerator {
+    count: Arc<AtomicUsize>,
+}
+
+impl ImageGenerator for FillGenerator {
+    fn generate(&self, width: u32, height: u32, arguments: &[String]) -> Option<Image> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let value = match arguments.first() {
+            Some(argument) => argument.parse().unwrap(),
+            None => return None,
+        };
+        let pixels = vec![value; (width * height * 4) as usize];
+        Some(Image::new(width, height, PixelsByColorType::RGBA8(pixels)))
+    }
+}
+
+fn registry_with_fill(count: &Arc<AtomicUsize>) -> Mutex<ImageGeneratorRegistry> {
+    let mut registry = ImageGeneratorRegistry::new();
+    registry.register("fill".to_owned(), box FillGenerator {
+        count: count.clone(),
+    });
+    Mutex::new(registry)
+}
+
+fn fill(arguments: &[&str]) -> GeneratedImage {
+    GeneratedImage {
+        name: "fill".to_owned(),
+        arguments: arguments.iter().map(|argument| argument.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_generated_images_are_cached() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let registry = registry_with_fill(&count);
+
+    let image = image_generator::generate(&registry, &fill(&["255"]), 4, 2).unwrap();
+    assert_eq!((image.width, image.height), (4, 2));
+    let again = image_generator::generate(&registry, &fill(&["255"]), 4, 2).unwrap();
+    assert!(&*image as *const Image == &*again as *const Image);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // Another size or other arguments are drawn again.
+    image_generator::generate(&registry, &fill(&["255"]), 8, 2).unwrap();
+    image_generator::generate(&registry, &fill(&["0"]), 4, 2).unwrap();
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+
+    // So is nothing, once.
+    assert!(image_generator::generate(&registry, &fill(&[]), 4, 2).is_none());
+    assert!(image_generator::generate(&registry, &fill(&[]), 4, 2).is_none());
+    assert_eq!(count.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_generators_are_registered_for_every_paint_task() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let generated_image = GeneratedImage {
+        name: "registered-fill".to_owned(),
+        arguments: vec!["255".to_owned()],
+    };
+    assert!(image_generator::generate(&*image_generator::registry(), &generated_image, 4, 2)
+                .is_none());
+
+    image_generator::register("registered-fill".to_owned(), box FillGenerator {
+        count: count.clone(),
+    });
+    assert!(image_generator::generate(&*image_generator::registry(), &generated_image, 4, 2)
+                .is_some());
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_registering_a_generator_again_drops_its_images() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let registry = registry_with_fill(&count);
+    image_generator::generate(&registry, &fill(&["255"]), 4, 2).unwrap();
+
+    let other_count = Arc::new(AtomicUsize::new(0));
+    registry.lock().unwrap().register("fill".to_owned(), box FillGenerator {
+        count: other_count.clone(),
+    });
+    image_generator::generate(&registry, &fill(&["255"]), 4, 2).unwrap();
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert_eq!(other_count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_unknown_generators_and_empty_sizes_draw_nothing() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let registry = registry_with_fill(&count);
+    let unknown = GeneratedImage {
+        name: "unknown".to_owned(),
+        arguments: vec![],
+    };
+    assert!(image_generator::generate(&registry, &unknown, 4, 2).is_none());
+    assert!(image_generator::generate(&registry, &fill(&["255"]), 0, 2).is_none());
+    assert_eq!(count.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_least_recently_used_images_are_dropped() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let registry = registry_with_fill(&count);
+
+    // Each image is 16MB, and the cache keeps 32MB.
+    image_generator::generate(&registry, &fill(&["1"]), 2048, 2048).unwrap();
+    image_generator::generate(&registry, &fill(&["2"]), 2048, 2048).unwrap();
+    image_generator::generate(&registry, &fill(&["1"]), 2048, 2048).unwrap();
+    image_generator::generate(&registry, &fill(&["3"]), 2048, 2048).unwrap();
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+
+    image_generator::generate(&registry, &fill(&["1"]), 2048, 2048).unwrap();
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+    image_generator::generate(&registry, &fill(&["2"]), 2048, 2048).unwrap();
+    assert_eq!(count.load(Ordering::SeqCst), 4);
+}
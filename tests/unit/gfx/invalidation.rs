@@ -0,0 +1,259 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::matrix::{self, Matrix4};
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::color;
+use gfx::display_list::invalidation::changed_rects;
+use gfx::display_list::{BaseDisplayItem, ClippingRegion, DisplayItem, DisplayItemMetadata};
+use gfx::display_list::{DisplayList, DisplayListSection, OpaqueNode, RubyAnnotation};
+use gfx::display_list::{RubyPosition, SolidColorDisplayItem, StackingContext, TextDisplayItem};
+use gfx::display_list::TextOrientation;
+use gfx::font::{FontMetrics, FontSynthesisFlags, FontVariations};
+use gfx::font_instance::FontInstanceKey;
+use gfx::paint_task::PaintLayer;
+use gfx::platform::font_template::FontTemplateData;
+use gfx::text::glyph::{CharIndex, GlyphData, GlyphStore};
+use gfx::text::text_run::{GlyphRun, TextRun};
+use msg::compositor_msg::{LayerId, ScrollPolicy};
+use std::collections::HashMap;
+use std::sync::Arc;
+use style::computed_values::{filter, mix_blend_mode};
+use util::geometry::Au;
+use util::opts::{FontAutohint, FontHinting};
+use util::range::Range;
+
+fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect<Au> {
+    Rect(Point2D(Au(x), Au(y)), Size2D(Au(width), Au(height)))
+}
+
+fn base(bounds: Rect<Au>) -> BaseDisplayItem {
+    let metadata = DisplayItemMetadata {
+        node: OpaqueNode(1),
+        pointing: None,
+        tag: 0,
+    };
+    BaseDisplayItem::new(bounds, metadata, ClippingRegion::max())
+}
+
+fn solid_color(bounds: Rect<Au>, red: f32) -> DisplayItem {
+    DisplayItem::SolidColorClass(box SolidColorDisplayItem {
+        base: base(bounds),
+        color: color::rgba(red, 0.0, 0.0, 1.0),
+    })
+}
+
+/// A run with one glyph for each character, of the given advance. Each call makes a new run, as
+/// each reflow does.
+fn text_run(text: &str, advance: i32) -> Arc<Box<TextRun>> {
+    let length = text.chars().count();
+    let mut glyphs = GlyphStore::new(length, false);
+    for (index, character) in text.chars().enumerate() {
+        glyphs.add_glyph_for_char_index(CharIndex(index as isize),
+                                        Some(character),
+                                        &GlyphData::new(character as u32,
+                                                        Au(advance),
+                                                        None,
+                                                        false,
+                                                        true,
+                                                        true));
+    }
+    glyphs.finalize_changes();
+
+    Arc::new(box TextRun {
+        text: Arc::new(text.to_owned()),
+        font_template: Arc::new(FontTemplateData::new("test-font", Some(vec![0, 1, 2, 3]))),
+        font_key: FontInstanceKey {
+            identifier: "test-font".to_owned(),
+            pt_size: Au(720),
+            variations: FontVariations::new(),
+            hinting: FontHinting::Full,
+            autohint: FontAutohint::Default,
+        },
+        actual_pt_size: Au(720),
+        font_metrics: FontMetrics {
+            underline_size: Au(60),
+            underline_offset: Au(-120),
+            strikeout_size: Au(60),
+            strikeout_offset: Au(300),
+            leading: Au(0),
+            x_height: Au(480),
+            em_size: Au(960),
+            ascent: Au(720),
+            descent: Au(240),
+            max_advance: Au(600),
+            average_advance: Au(480),
+            line_gap: Au(60),
+        },
+        synthesis: FontSynthesisFlags::empty(),
+        glyphs: Arc::new(vec![GlyphRun {
+            glyph_store: Arc::new(glyphs),
+            range: Range::new(CharIndex(0), CharIndex(length as isize)),
+            upright: false,
+        }]),
+        glyph_descents: Arc::new(HashMap::new()),
+        bidi_level: 0,
+    })
+}
+
+fn text(bounds: Rect<Au>, text_run: Arc<Box<TextRun>>) -> DisplayItem {
+    let length = text_run.char_len();
+    DisplayItem::TextClass(box TextDisplayItem {
+        base: base(bounds),
+        text_run: text_run,
+        range: Range::new(CharIndex(0), length),
+        text_color: color::rgba(0.0, 0.0, 0.0, 1.0),
+        baseline_origin: Point2D(bounds.origin.x, bounds.origin.y + Au(720)),
+        orientation: TextOrientation::Upright,
+        blur_radius: Au(0),
+        shadows: Vec::new(),
+        decorations: Vec::new(),
+        ruby_annotation: None,
+        emphasis_marks: None,
+    })
+}
+
+fn stacking_context(items: Vec<DisplayItem>,
+                    children: Vec<StackingContext>,
+                    bounds: Rect<Au>,
+                    transform: Matrix4<f32>,
+                    layer: Option<PaintLayer>)
+                    -> StackingContext {
+    let mut display_list = DisplayList::new();
+    display_list.get_mut(DisplayListSection::Content).extend(items.into_iter());
+    display_list.children.extend(children.into_iter().map(Arc::new));
+    StackingContext::new(box display_list,
+                         &bounds,
+                         &Rect(Point2D(Au(0), Au(0)), bounds.size),
+                         0,
+                         &transform,
+                         &matrix::identity(),
+                         false,
+                         filter::T::new(Vec::new()),
+                         mix_blend_mode::T::normal,
+                         layer.map(Arc::new),
+                         1.0)
+}
+
+fn layer(id: usize) -> PaintLayer {
+    PaintLayer::new(LayerId(id, 0), color::rgba(1.0, 1.0, 1.0, 1.0), ScrollPolicy::Scrollable)
+}
+
+/// A page in one layer, with the given items and a child without a layer of its own at `child`.
+fn page(items: Vec<DisplayItem>, child: Rect<Au>, transform: Matrix4<f32>) -> StackingContext {
+    let child = stacking_context(vec![solid_color(rect(0, 0, 600, 600), 0.5)],
+                                 vec![],
+                                 child,
+                                 transform,
+                                 None);
+    stacking_context(items, vec![child], rect(0, 0, 6000, 6000), matrix::identity(), Some(layer(1)))
+}
+
+fn page_with(items: Vec<DisplayItem>) -> StackingContext {
+    page(items, rect(3000, 3000, 600, 600), matrix::identity())
+}
+
+#[test]
+fn test_nothing_changes() {
+    let old = page_with(vec![solid_color(rect(0, 0, 600, 600), 1.0),
+                             text(rect(0, 600, 1800, 960), text_run("abc", 600))]);
+    let new = page_with(vec![solid_color(rect(0, 0, 600, 600), 1.0),
+                             text(rect(0, 600, 1800, 960), text_run("abc", 600))]);
+    assert!(changed_rects(&old, &new).unwrap().is_empty());
+}
+
+#[test]
+fn test_changed_item() {
+    let old = page_with(vec![solid_color(rect(0, 0, 600, 600), 1.0),
+                             solid_color(rect(600, 0, 600, 600), 1.0)]);
+    let new = page_with(vec![solid_color(rect(0, 0, 600, 600), 1.0),
+                             solid_color(rect(1200, 0, 600, 600), 1.0)]);
+    assert_eq!(changed_rects(&old, &new).unwrap(),
+               vec![(LayerId(1, 0), vec![rect(600, 0, 600, 600), rect(1200, 0, 600, 600)])]);
+}
+
+#[test]
+fn test_added_and_removed_items() {
+    let old = page_with(vec![solid_color(rect(0, 0, 600, 600), 1.0)]);
+    let new = page_with(vec![solid_color(rect(0, 0, 600, 600), 1.0),
+                             solid_color(rect(0, 600, 600, 600), 1.0)]);
+    assert_eq!(changed_rects(&old, &new).unwrap(),
+               vec![(LayerId(1, 0), vec![rect(0, 600, 600, 600)])]);
+    assert_eq!(changed_rects(&new, &old).unwrap(),
+               vec![(LayerId(1, 0), vec![rect(0, 600, 600, 600)])]);
+}
+
+#[test]
+fn test_text_is_compared_by_content() {
+    let bounds = rect(0, 0, 1800, 960);
+    let old = page_with(vec![text(bounds, text_run("abc", 600))]);
+
+    // The runs are new, but paint the same.
+    let same = page_with(vec![text(bounds, text_run("abc", 600))]);
+    assert!(changed_rects(&old, &same).unwrap().is_empty());
+
+    let other_text = page_with(vec![text(bounds, text_run("abd", 600))]);
+    assert_eq!(changed_rects(&old, &other_text).unwrap(),
+               vec![(LayerId(1, 0), vec![bounds, bounds])]);
+
+    // The same text spaced out, as `letter-spacing` does.
+    let other_glyphs = page_with(vec![text(bounds, text_run("abc", 660))]);
+    assert_eq!(changed_rects(&old, &other_glyphs).unwrap(),
+               vec![(LayerId(1, 0), vec![bounds, bounds])]);
+}
+
+#[test]
+fn test_ruby_annotation_is_invalidated() {
+    let bounds = rect(0, 960, 1800, 960);
+    let old = page_with(vec![text(bounds, text_run("abc", 600))]);
+
+    let mut annotated = text(bounds, text_run("abc", 600));
+    if let DisplayItem::TextClass(ref mut text_item) = annotated {
+        text_item.ruby_annotation = Some(RubyAnnotation {
+            text_run: text_run("xy", 300),
+            range: Range::new(CharIndex(0), CharIndex(2)),
+            text_color: color::rgba(0.0, 0.0, 0.0, 1.0),
+            position: RubyPosition::Over,
+        })
+    }
+    let new = page_with(vec![annotated]);
+
+    // The annotation is centered over the text, its descent on the ascent of the text.
+    assert_eq!(changed_rects(&old, &new).unwrap(),
+               vec![(LayerId(1, 0), vec![bounds, rect(0, 0, 1800, 1920)])]);
+}
+
+#[test]
+fn test_moved_child() {
+    let old = page(vec![], rect(3000, 3000, 600, 600), matrix::identity());
+    let new = page(vec![], rect(3600, 3000, 600, 600), matrix::identity());
+    assert_eq!(changed_rects(&old, &new).unwrap(),
+               vec![(LayerId(1, 0), vec![rect(3000, 3000, 600, 600), rect(3600, 3000, 600, 600)])]);
+}
+
+#[test]
+fn test_moved_transformed_child_changes_everything() {
+    let scale = Matrix4::new(2.0, 0.0, 0.0, 0.0,
+                             0.0, 2.0, 0.0, 0.0,
+                             0.0, 0.0, 1.0, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+    let old = page(vec![], rect(3000, 3000, 600, 600), scale);
+    let new = page(vec![], rect(3600, 3000, 600, 600), scale);
+    assert!(changed_rects(&old, &new).is_none());
+
+    // Left where it was, it changes nothing.
+    let same = page(vec![], rect(3000, 3000, 600, 600), scale);
+    assert!(changed_rects(&old, &same).unwrap().is_empty());
+}
+
+#[test]
+fn test_changed_layer_changes_everything() {
+    let old = stacking_context(vec![], vec![], rect(0, 0, 600, 600), matrix::identity(),
+                               Some(layer(1)));
+    let new = stacking_context(vec![], vec![], rect(0, 0, 600, 600), matrix::identity(),
+                               Some(layer(2)));
+    assert!(changed_rects(&old, &new).is_none());
+}
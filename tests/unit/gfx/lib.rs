@@ -2,6 +2,30 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+#![cfg_attr(test, feature(box_syntax))]
+
+extern crate azure;
+extern crate geom;
 extern crate gfx;
+extern crate layers;
+extern crate msg;
+extern crate net_traits;
+extern crate png;
+extern crate style;
+extern crate util;
 
+#[cfg(test)] mod bidi;
+#[cfg(test)] mod display_list_binary;
+#[cfg(test)] mod font_synthesis;
+#[cfg(all(test, target_os = "linux"))] mod font_table;
+#[cfg(test)] mod iframe_layers;
+#[cfg(test)] mod image_generator;
+#[cfg(test)] mod invalidation;
+#[cfg(test)] mod mipmap_cache;
+#[cfg(test)] mod opaque_layers;
+#[cfg(test)] mod pixel_snapping;
+#[cfg(test)] mod software_backend;
+#[cfg(test)] mod text_decoration;
 #[cfg(test)] mod text_util;
+#[cfg(test)] mod tile_queue;
+#[cfg(test)] mod transform3d;
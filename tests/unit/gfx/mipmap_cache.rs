@@ -0,0 +1,36 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use gfx::mipmap_cache::MipmapCache;
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::sync::Arc;
+
+fn image(width: u32, height: u32) -> Arc<Image> {
+    let pixels = vec![0xff; (width * height * 4) as usize];
+    Arc::new(Image::new(width, height, PixelsByColorType::RGBA8(pixels)))
+}
+
+#[test]
+fn test_mipmap_cache_is_bounded_by_bytes() {
+    // Room for the first two levels of an 8×8 image, which take 64 and 16 bytes.
+    let mut cache = MipmapCache::new(80);
+    let first = image(8, 8);
+    let first_quarter = cache.find_or_create(&first, 2);
+    assert_eq!((first_quarter.width, first_quarter.height), (2, 2));
+    assert_eq!(cache.size(), 80);
+
+    // The first level of another image pushes out the least recently used level.
+    let second = image(8, 8);
+    cache.find_or_create(&second, 1);
+    assert_eq!(cache.size(), 80);
+    let first_quarter_again = cache.find_or_create(&first, 2);
+    assert_eq!(&*first_quarter as *const Image, &*first_quarter_again as *const Image);
+
+    // A level larger than the whole cache isn't kept.
+    let large = image(32, 32);
+    let half = cache.find_or_create(&large, 1);
+    assert_eq!((half.width, half.height), (16, 16));
+    assert!(cache.size() <= 80);
+}
@@ -0,0 +1,77 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::matrix;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::color;
+use gfx::display_list::{BaseDisplayItem, ClippingRegion, DisplayItem, DisplayItemMetadata};
+use gfx::display_list::{DisplayList, DisplayListSection, ImageDisplayItem, ImageOrientation};
+use gfx::display_list::{ImageRepeat, ImageSource, OpaqueNode, StackingContext};
+use gfx::paint_task::PaintLayer;
+use msg::compositor_msg::{LayerId, ScrollPolicy};
+use net_traits::image::base::Image;
+use png::PixelsByColorType;
+use std::sync::Arc;
+use style::computed_values::{filter, image_rendering, mix_blend_mode};
+use util::geometry::Au;
+
+fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect<Au> {
+    Rect(Point2D(Au(x), Au(y)), Size2D(Au(width), Au(height)))
+}
+
+fn image_item(bounds: Rect<Au>, pixels: Vec<u8>) -> DisplayItem {
+    let metadata = DisplayItemMetadata {
+        node: OpaqueNode(1),
+        pointing: None,
+        tag: 0,
+    };
+    let image = Image::new(1, 1, PixelsByColorType::RGBA8(pixels));
+    DisplayItem::ImageClass(box ImageDisplayItem {
+        base: BaseDisplayItem::new(bounds, metadata, ClippingRegion::max()),
+        is_opaque: image.is_opaque,
+        image: ImageSource::Decoded(Arc::new(image)),
+        source_rect: None,
+        stretch_size: bounds.size,
+        horizontal_repeat: ImageRepeat::NoRepeat,
+        vertical_repeat: ImageRepeat::NoRepeat,
+        image_rendering: image_rendering::T::Auto,
+        animation: None,
+        orientation: ImageOrientation::identity(),
+    })
+}
+
+/// A stacking context of the given items in a layer with a transparent background.
+fn layer_with(items: Vec<DisplayItem>) -> StackingContext {
+    let mut display_list = DisplayList::new();
+    display_list.get_mut(DisplayListSection::Content).extend(items.into_iter());
+    let layer = PaintLayer::new(LayerId(1, 0),
+                                color::rgba(0.0, 0.0, 0.0, 0.0),
+                                ScrollPolicy::Scrollable);
+    StackingContext::new(box display_list,
+                         &rect(0, 0, 600, 600),
+                         &rect(0, 0, 600, 600),
+                         0,
+                         &matrix::identity(),
+                         &matrix::identity(),
+                         false,
+                         filter::T::new(Vec::new()),
+                         mix_blend_mode::T::normal,
+                         Some(Arc::new(layer)),
+                         1.0)
+}
+
+#[test]
+fn test_layer_covered_by_opaque_image_is_opaque() {
+    // The compositor leaves out the tiles of the layers under an opaque layer.
+    let opaque = layer_with(vec![image_item(rect(0, 0, 600, 600), vec![1, 2, 3, 0xff])]);
+    assert!(opaque.layer.unwrap().is_opaque);
+
+    let translucent = layer_with(vec![image_item(rect(0, 0, 600, 600), vec![1, 2, 3, 0x80])]);
+    assert!(!translucent.layer.unwrap().is_opaque);
+
+    let partly_covered = layer_with(vec![image_item(rect(0, 0, 300, 600), vec![1, 2, 3, 0xff])]);
+    assert!(!partly_covered.layer.unwrap().is_opaque);
+}
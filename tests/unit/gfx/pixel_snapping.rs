@@ -0,0 +1,47 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::matrix;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::display_list::{ClippingRegion, DisplayList, StackingContext};
+use style::computed_values::{filter, mix_blend_mode};
+use util::geometry::Au;
+
+#[test]
+fn test_snapping_a_clip_moves_its_edges_to_device_pixels() {
+    let clip = ClippingRegion::from_rect(&Rect(Point2D(Au(20), Au(100)),
+                                               Size2D(Au(80), Au(60))));
+    assert_eq!(clip.snap_to_pixels(1.0).main,
+               Rect(Point2D(Au(0), Au(120)), Size2D(Au(120), Au(60))));
+    assert_eq!(clip.snap_to_pixels(2.0).main,
+               Rect(Point2D(Au(30), Au(90)), Size2D(Au(60), Au(60))));
+}
+
+#[test]
+fn test_snapping_a_clip_that_clips_nothing_keeps_it() {
+    assert_eq!(ClippingRegion::max().snap_to_pixels(1.0), ClippingRegion::max());
+}
+
+#[test]
+fn test_stacking_context_origins_are_snapped_to_device_pixels() {
+    // A third of a px off, which is between device pixels at one and two device pixels per px.
+    let bounds = Rect(Point2D(Au(20), Au(80)), Size2D(Au(600), Au(600)));
+    let snapped_origin = |device_pixels_per_px: f32| {
+        StackingContext::new(box DisplayList::new(),
+                             &bounds,
+                             &Rect(Point2D(Au(0), Au(0)), bounds.size),
+                             0,
+                             &matrix::identity(),
+                             &matrix::identity(),
+                             false,
+                             filter::T::new(Vec::new()),
+                             mix_blend_mode::T::normal,
+                             None,
+                             device_pixels_per_px).bounds.origin
+    };
+    assert_eq!(snapped_origin(1.0), Point2D(Au(0), Au(60)));
+    assert_eq!(snapped_origin(2.0), Point2D(Au(30), Au(90)));
+}
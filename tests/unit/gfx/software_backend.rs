@@ -0,0 +1,187 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::matrix;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::side_offsets::SideOffsets2D;
+use geom::size::Size2D;
+use gfx::backends::software::SoftwareBackend;
+use gfx::color;
+use gfx::display_list::{BaseDisplayItem, BorderDisplayItem, BorderRadii, ClippingRegion};
+use gfx::display_list::{DisplayItem, DisplayItemMetadata, DisplayList, DisplayListSection};
+use gfx::display_list::{OpaqueNode, SolidColorDisplayItem, StackingContext, TextDisplayItem};
+use gfx::display_list::TextOrientation;
+use gfx::font::{FontMetrics, FontSynthesisFlags, FontVariations};
+use gfx::font_cache_task::FontCacheTask;
+use gfx::font_instance::FontInstanceKey;
+use gfx::headless;
+use gfx::platform::font_template::FontTemplateData;
+use gfx::text::glyph::{CharIndex, GlyphData, GlyphStore};
+use gfx::text::text_run::{GlyphRun, TextRun};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use style::computed_values::{border_style, filter, mix_blend_mode};
+use util::geometry::Au;
+use util::opts::{FontAutohint, FontHinting};
+use util::range::Range;
+
+/// The glyph of 'H' in the test font. Its outline is two stems, from 80 to 236 and from 498 to
+/// 654 thousandths of an em across, joined by a bar from 294 to 428 up, and it is 693 high.
+static GLYPH_H: u32 = 43;
+
+fn rect(x: isize, y: isize, width: isize, height: isize) -> Rect<Au> {
+    Rect(Point2D(Au::from_px(x), Au::from_px(y)), Size2D(Au::from_px(width), Au::from_px(height)))
+}
+
+fn base(bounds: Rect<Au>) -> BaseDisplayItem {
+    let metadata = DisplayItemMetadata {
+        node: OpaqueNode(1),
+        pointing: None,
+        tag: 0,
+    };
+    BaseDisplayItem::new(bounds, metadata, ClippingRegion::max())
+}
+
+fn stacking_context(items: Vec<DisplayItem>, bounds: Rect<Au>) -> StackingContext {
+    let mut display_list = DisplayList::new();
+    display_list.get_mut(DisplayListSection::Content).extend(items.into_iter());
+    StackingContext::new(box display_list,
+                         &bounds,
+                         &bounds,
+                         0,
+                         &matrix::identity(),
+                         &matrix::identity(),
+                         false,
+                         filter::T::new(Vec::new()),
+                         mix_blend_mode::T::normal,
+                         None,
+                         1.0)
+}
+
+/// Paints the stacking context with the software backend, and returns its pixels as
+/// premultiplied BGRA.
+fn paint_with_software_backend(root: &StackingContext, size: isize) -> Vec<u8> {
+    let mut backend = SoftwareBackend::new(size as usize, size as usize);
+    headless::paint_page(root, &rect(0, 0, size, size), 0, &mut backend);
+    backend.to_bgra()
+}
+
+#[test]
+fn test_paints_the_same_pixels_as_azure() {
+    let solid_color = |bounds: Rect<Au>, color| {
+        DisplayItem::SolidColorClass(box SolidColorDisplayItem {
+            base: base(bounds),
+            color: color,
+        })
+    };
+    let border = DisplayItem::BorderClass(box BorderDisplayItem {
+        base: base(rect(10, 10, 20, 20)),
+        border_widths: SideOffsets2D::new_all_same(Au::from_px(2)),
+        color: SideOffsets2D::new_all_same(color::rgba(0.0, 0.0, 1.0, 1.0)),
+        style: SideOffsets2D::new_all_same(border_style::T::solid),
+        radius: BorderRadii::all_same(Au(0)),
+    });
+    let root = stacking_context(vec![solid_color(rect(0, 0, 40, 40), color::white()),
+                                     solid_color(rect(5, 5, 20, 20),
+                                                 color::rgba(1.0, 0.0, 0.0, 0.5)),
+                                     border],
+                                rect(0, 0, 40, 40));
+
+    // Web fonts are loaded through the resource task, and there are none here.
+    let (resource_task, _) = channel();
+    let azure_pixels = headless::render_display_list(&root,
+                                                     &rect(0, 0, 40, 40),
+                                                     1.0,
+                                                     FontCacheTask::new(resource_task));
+    let software_pixels = paint_with_software_backend(&root, 40);
+
+    // Everything is opaque, so premultiplying doesn't change the colors.
+    assert_eq!(azure_pixels.len(), software_pixels.len());
+    for (rgba, bgra) in azure_pixels.chunks(4).zip(software_pixels.chunks(4)) {
+        for &(azure, software) in [(rgba[0], bgra[2]),
+                                   (rgba[1], bgra[1]),
+                                   (rgba[2], bgra[0]),
+                                   (rgba[3], bgra[3])].iter() {
+            assert!((azure as i32 - software as i32).abs() <= 1,
+                    "Azure painted {:?}, the software backend {:?}", rgba, bgra);
+        }
+    }
+}
+
+// Elsewhere, fonts loaded by path don't keep their file.
+#[cfg(target_os = "linux")]
+#[test]
+fn test_draws_text_from_glyph_outlines() {
+    let mut glyphs = GlyphStore::new(1, false);
+    glyphs.add_glyph_for_char_index(CharIndex(0),
+                                    Some('H'),
+                                    &GlyphData::new(GLYPH_H,
+                                                    Au::from_px(70),
+                                                    None,
+                                                    false,
+                                                    true,
+                                                    true));
+    glyphs.finalize_changes();
+    let template = FontTemplateData::new("../net/parsable_mime/unknown/true_type.ttf", None);
+    let text_run = Arc::new(box TextRun {
+        text: Arc::new("H".to_owned()),
+        font_template: Arc::new(template),
+        font_key: FontInstanceKey {
+            identifier: "../net/parsable_mime/unknown/true_type.ttf".to_owned(),
+            pt_size: Au::from_px(100),
+            variations: FontVariations::new(),
+            hinting: FontHinting::Full,
+            autohint: FontAutohint::Default,
+        },
+        actual_pt_size: Au::from_px(100),
+        font_metrics: FontMetrics {
+            underline_size: Au::from_px(5),
+            underline_offset: Au::from_px(-10),
+            strikeout_size: Au::from_px(5),
+            strikeout_offset: Au::from_px(30),
+            leading: Au(0),
+            x_height: Au::from_px(50),
+            em_size: Au::from_px(100),
+            ascent: Au::from_px(90),
+            descent: Au::from_px(10),
+            max_advance: Au::from_px(100),
+            average_advance: Au::from_px(70),
+            line_gap: Au::from_px(10),
+        },
+        synthesis: FontSynthesisFlags::empty(),
+        glyphs: Arc::new(vec![GlyphRun {
+            glyph_store: Arc::new(glyphs),
+            range: Range::new(CharIndex(0), CharIndex(1)),
+            upright: false,
+        }]),
+        glyph_descents: Arc::new(HashMap::new()),
+        bidi_level: 0,
+    });
+    let text = DisplayItem::TextClass(box TextDisplayItem {
+        base: base(rect(0, 0, 100, 100)),
+        text_run: text_run,
+        range: Range::new(CharIndex(0), CharIndex(1)),
+        text_color: color::black(),
+        baseline_origin: Point2D(Au(0), Au::from_px(100)),
+        orientation: TextOrientation::Upright,
+        blur_radius: Au(0),
+        shadows: Vec::new(),
+        decorations: Vec::new(),
+        ruby_annotation: None,
+        emphasis_marks: None,
+    });
+    let pixels = paint_with_software_backend(&stacking_context(vec![text], rect(0, 0, 100, 100)),
+                                             100);
+
+    let alpha = |x: usize, y: usize| pixels[(y * 100 + x) * 4 + 3];
+    // The left stem and the bar are covered.
+    assert_eq!(alpha(15, 50), 255);
+    assert_eq!(alpha(36, 64), 255);
+    // Between the stems, above the bar, and around the glyph, nothing is.
+    assert_eq!(alpha(36, 45), 0);
+    assert_eq!(alpha(15, 20), 0);
+    assert_eq!(alpha(80, 50), 0);
+}
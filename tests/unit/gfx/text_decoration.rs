@@ -0,0 +1,199 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::color;
+use gfx::display_list::{BaseDisplayItem, ClippingRegion, DisplayItemMetadata, EmphasisMarks};
+use gfx::display_list::{OpaqueNode, RubyPosition, TextDecoration, TextDecorationLine};
+use gfx::display_list::{TextDecorationStyle, TextDisplayItem, TextOrientation, skip_intervals};
+use gfx::font::{FontMetrics, FontSynthesisFlags, FontVariations};
+use gfx::font_instance::FontInstanceKey;
+use gfx::platform::font_template::FontTemplateData;
+use gfx::text::glyph::{CharIndex, GlyphData, GlyphStore};
+use gfx::text::text_run::{GlyphRun, TextRun};
+use std::collections::HashMap;
+use std::sync::Arc;
+use util::geometry::Au;
+use util::opts::{FontAutohint, FontHinting};
+use util::range::Range;
+
+fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect<Au> {
+    Rect(Point2D(Au(x), Au(y)), Size2D(Au(width), Au(height)))
+}
+
+/// A run with one glyph, 600 wide, for each character. The ink of the glyphs for `p` and `g`
+/// reaches 200 below the baseline, well past the underline.
+fn text_run(text: &str) -> TextRun {
+    let length = text.chars().count();
+    let mut glyphs = GlyphStore::new(length, false);
+    let mut glyph_descents = HashMap::new();
+    for (index, character) in text.chars().enumerate() {
+        glyphs.add_glyph_for_char_index(CharIndex(index as isize),
+                                        Some(character),
+                                        &GlyphData::new(character as u32,
+                                                        Au(600),
+                                                        None,
+                                                        false,
+                                                        true,
+                                                        true));
+        if character == 'p' || character == 'g' {
+            glyph_descents.insert(character as u32, Au(200));
+        }
+    }
+    glyphs.finalize_changes();
+
+    TextRun {
+        text: Arc::new(text.to_owned()),
+        font_template: Arc::new(FontTemplateData::new("test-font", Some(vec![0, 1, 2, 3]))),
+        font_key: FontInstanceKey {
+            identifier: "test-font".to_owned(),
+            pt_size: Au(720),
+            variations: FontVariations::new(),
+            hinting: FontHinting::Full,
+            autohint: FontAutohint::Default,
+        },
+        actual_pt_size: Au(720),
+        font_metrics: FontMetrics {
+            underline_size: Au(60),
+            underline_offset: Au(-120),
+            strikeout_size: Au(60),
+            strikeout_offset: Au(300),
+            leading: Au(0),
+            x_height: Au(480),
+            em_size: Au(960),
+            ascent: Au(720),
+            descent: Au(240),
+            max_advance: Au(600),
+            average_advance: Au(480),
+            line_gap: Au(60),
+        },
+        synthesis: FontSynthesisFlags::empty(),
+        glyphs: Arc::new(vec![GlyphRun {
+            glyph_store: Arc::new(glyphs),
+            range: Range::new(CharIndex(0), CharIndex(length as isize)),
+            upright: false,
+        }]),
+        glyph_descents: Arc::new(glyph_descents),
+        bidi_level: 0,
+    }
+}
+
+/// A text item at the origin that paints `text_run(text)`.
+fn text_item(text: &str) -> TextDisplayItem {
+    let length = text.chars().count();
+    let metadata = DisplayItemMetadata {
+        node: OpaqueNode(1),
+        pointing: None,
+        tag: 0,
+    };
+    TextDisplayItem {
+        base: BaseDisplayItem::new(rect(0, 0, 600 * length as i32, 960),
+                                   metadata,
+                                   ClippingRegion::max()),
+        text_run: Arc::new(box text_run(text)),
+        range: Range::new(CharIndex(0), CharIndex(length as isize)),
+        text_color: color::rgba(0.0, 0.0, 0.0, 1.0),
+        baseline_origin: Point2D(Au(0), Au(720)),
+        orientation: TextOrientation::Upright,
+        blur_radius: Au(0),
+        shadows: Vec::new(),
+        decorations: Vec::new(),
+        ruby_annotation: None,
+        emphasis_marks: None,
+    }
+}
+
+fn underline(skip_ink: bool) -> TextDecoration {
+    TextDecoration {
+        line: TextDecorationLine::Underline,
+        style: TextDecorationStyle::Solid,
+        color: color::rgba(0.0, 0.0, 0.0, 1.0),
+        skip_ink: skip_ink,
+    }
+}
+
+#[test]
+fn test_descender_intervals() {
+    let run = text_run("pag");
+    let range = Range::new(CharIndex(0), CharIndex(3));
+    assert_eq!(run.descender_intervals(&range, Au(60)),
+               vec![(Au(0), Au(600)), (Au(1200), Au(1800))]);
+
+    // Descenders that end above the line don't cross it.
+    assert_eq!(run.descender_intervals(&range, Au(300)), Vec::<(Au, Au)>::new());
+
+    // Intervals are relative to the start of the range.
+    let range = Range::new(CharIndex(1), CharIndex(3));
+    assert_eq!(run.descender_intervals(&range, Au(60)), vec![(Au(600), Au(1200))]);
+}
+
+#[test]
+fn test_underline_skips_descenders() {
+    // The underline breaks a stroke's thickness away from each side of `p` and `g`.
+    let item = text_item("pag");
+    assert_eq!(item.decoration_rects(&underline(true)), vec![rect(660, 840, 480, 60)]);
+    assert_eq!(item.decoration_rects(&underline(false)), vec![rect(0, 840, 1800, 60)]);
+}
+
+#[test]
+fn test_underline_without_descenders_is_whole() {
+    assert_eq!(text_item("aaa").decoration_rects(&underline(true)),
+               vec![rect(0, 840, 1800, 60)]);
+}
+
+#[test]
+fn test_skip_intervals_without_gaps() {
+    assert_eq!(skip_intervals(Au(0), Au(100), &[]), vec![(Au(0), Au(100))]);
+}
+
+#[test]
+fn test_skip_intervals_breaks_around_gaps() {
+    let gaps = [(Au(10), Au(20)), (Au(50), Au(60))];
+    assert_eq!(skip_intervals(Au(0), Au(100), &gaps),
+               vec![(Au(0), Au(10)), (Au(20), Au(50)), (Au(60), Au(100))]);
+}
+
+#[test]
+fn test_skip_intervals_sorts_and_merges_gaps() {
+    // Right-to-left text yields its descenders from right to left.
+    let gaps = [(Au(50), Au(70)), (Au(10), Au(30)), (Au(25), Au(40))];
+    assert_eq!(skip_intervals(Au(0), Au(100), &gaps),
+               vec![(Au(0), Au(10)), (Au(40), Au(50)), (Au(70), Au(100))]);
+}
+
+#[test]
+fn test_skip_intervals_clips_gaps_to_the_line() {
+    let gaps = [(Au(-10), Au(10)), (Au(90), Au(110))];
+    assert_eq!(skip_intervals(Au(0), Au(100), &gaps), vec![(Au(10), Au(90))]);
+}
+
+#[test]
+fn test_skip_intervals_with_the_line_in_a_gap() {
+    assert_eq!(skip_intervals(Au(20), Au(40), &[(Au(0), Au(100))]), Vec::<(Au, Au)>::new());
+}
+
+#[test]
+fn test_emphasis_marks_skip_whitespace() {
+    let marks = EmphasisMarks {
+        text_run: Arc::new(box text_run("x")),
+        range: Range::new(CharIndex(0), CharIndex(1)),
+        text_color: color::rgba(0.0, 0.0, 0.0, 1.0),
+        position: RubyPosition::Over,
+    };
+    let base_run = text_run("a b");
+    let offsets = marks.baseline_offsets(&base_run, &Range::new(CharIndex(0), CharIndex(3)));
+    let offsets: Vec<_> = offsets.iter().map(|offset| (offset.x, offset.y)).collect();
+    // The marks sit on the ascent of the base text, their descent touching it.
+    assert_eq!(offsets, vec!((Au(0), Au(-960)), (Au(1200), Au(-960))));
+
+    let marks = EmphasisMarks {
+        position: RubyPosition::Under,
+        ..marks
+    };
+    let offsets = marks.baseline_offsets(&base_run, &Range::new(CharIndex(2), CharIndex(1)));
+    let offsets: Vec<_> = offsets.iter().map(|offset| (offset.x, offset.y)).collect();
+    assert_eq!(offsets, vec!((Au(0), Au(960))));
+}
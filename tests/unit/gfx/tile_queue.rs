@@ -0,0 +1,86 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::tile_queue::TileQueue;
+use layers::content_age::ContentAge;
+use layers::layers::BufferRequest;
+
+/// The 256px tile in the given column and row of the layer, at a scale of 1.
+fn tile(column: usize, row: usize) -> BufferRequest {
+    BufferRequest::new(Rect(Point2D(column * 256, row * 256), Size2D(256, 256)),
+                       Rect(Point2D(column as f32 * 256.0, row as f32 * 256.0),
+                            Size2D(256.0, 256.0)),
+                       ContentAge::new())
+}
+
+/// The columns and rows of the tiles in the order that the queue hands them out.
+fn order(mut queue: TileQueue) -> Vec<(usize, usize)> {
+    let mut order = Vec::new();
+    while let Some(tile) = queue.pop() {
+        order.push((tile.screen_rect.origin.x / 256, tile.screen_rect.origin.y / 256));
+    }
+    order
+}
+
+/// A viewport over the first two columns of the first two rows.
+fn viewport() -> Rect<f32> {
+    Rect(Point2D(0.0, 0.0), Size2D(512.0, 512.0))
+}
+
+#[test]
+fn test_tiles_in_view_come_first() {
+    let tiles = vec![tile(0, 2), tile(0, 0), tile(3, 3), tile(1, 1)];
+    let queue = TileQueue::new(tiles, &viewport(), &Point2D(0.0, 0.0));
+    assert_eq!(order(queue), vec![(0, 0), (1, 1), (0, 2), (3, 3)]);
+}
+
+#[test]
+fn test_tiles_in_the_scroll_direction_come_first() {
+    // Scrolling down, the rows below go first, both in view and out of it.
+    let tiles = vec![tile(0, 0), tile(0, 1), tile(0, 2), tile(0, 3)];
+    let queue = TileQueue::new(tiles, &viewport(), &Point2D(0.0, 1.0));
+    assert_eq!(order(queue), vec![(0, 1), (0, 0), (0, 3), (0, 2)]);
+
+    // Scrolling left, the columns to the left go first.
+    let tiles = vec![tile(0, 0), tile(1, 0), tile(2, 0), tile(3, 0)];
+    let queue = TileQueue::new(tiles, &viewport(), &Point2D(-1.0, 0.0));
+    assert_eq!(order(queue), vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+}
+
+#[test]
+fn test_ties_keep_the_order_asked_for() {
+    // Without scrolling, or across it, the tiles stay in the order they were asked for in.
+    let tiles = vec![tile(1, 0), tile(0, 1), tile(0, 0), tile(1, 1)];
+    let queue = TileQueue::new(tiles, &viewport(), &Point2D(0.0, 0.0));
+    assert_eq!(order(queue), vec![(1, 0), (0, 1), (0, 0), (1, 1)]);
+
+    let tiles = vec![tile(1, 0), tile(0, 0), tile(1, 1), tile(0, 1)];
+    let queue = TileQueue::new(tiles, &viewport(), &Point2D(0.0, 1.0));
+    assert_eq!(order(queue), vec![(1, 1), (0, 1), (1, 0), (0, 0)]);
+}
+
+#[test]
+fn test_pop_strip_takes_the_row_from_left_to_right() {
+    let tiles = vec![tile(2, 0), tile(0, 1), tile(1, 0), tile(1, 1), tile(0, 0)];
+    let mut queue = TileQueue::new(tiles, &viewport(), &Point2D(0.0, 1.0));
+
+    let strip: Vec<usize> = queue.pop_strip().unwrap().iter().map(|tile| {
+        assert_eq!(tile.screen_rect.origin.y, 256);
+        tile.screen_rect.origin.x / 256
+    }).collect();
+    assert_eq!(strip, vec![0, 1]);
+
+    // Tiles out of view go along with the row of the first tile.
+    let strip: Vec<usize> = queue.pop_strip().unwrap().iter().map(|tile| {
+        assert_eq!(tile.screen_rect.origin.y, 0);
+        tile.screen_rect.origin.x / 256
+    }).collect();
+    assert_eq!(strip, vec![0, 1, 2]);
+
+    assert!(queue.pop_strip().is_none());
+    assert!(queue.is_empty());
+}
@@ -0,0 +1,126 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use geom::matrix::{self, Matrix4};
+use geom::matrix2d::Matrix2D;
+use geom::point::Point2D;
+use geom::rect::Rect;
+use geom::size::Size2D;
+use gfx::display_list::transform3d;
+use std::num::Float;
+
+fn assert_near(actual: &Point2D<f32>, expected: &Point2D<f32>) {
+    assert!((actual.x - expected.x).abs() < 0.01 && (actual.y - expected.y).abs() < 0.01,
+            "expected {:?}, got {:?}", expected, actual);
+}
+
+fn apply(transform: &Matrix2D<f32>, point: &Point2D<f32>) -> Point2D<f32> {
+    Point2D(point.x * transform.m11 + point.y * transform.m21 + transform.m31,
+            point.x * transform.m12 + point.y * transform.m22 + transform.m32)
+}
+
+/// A plane turned 60° around the y axis and seen from 500px away, so that its right side is nearer
+/// to the viewer than its left side, and is behind the viewer from x = 1000 / √3 on.
+fn tilted() -> Matrix4<f32> {
+    let (sin, cos) = (3.0.sqrt() / 2.0, 0.5);
+    let rotation = Matrix4::new(cos, 0.0, sin, 0.0,
+                                0.0, 1.0, 0.0, 0.0,
+                                -sin, 0.0, cos, 0.0,
+                                0.0, 0.0, 0.0, 1.0);
+    transform3d::perspective(500.0, &Point2D(0.0, 0.0)).mul(&rotation)
+}
+
+#[test]
+fn test_unproject_point_undoes_project_point() {
+    let transform = tilted().translate(30.0, -20.0, 0.0);
+    for point in [Point2D(0.0, 0.0), Point2D(100.0, 50.0), Point2D(-200.0, 300.0)].iter() {
+        let projected = transform3d::project_point(&transform, point).unwrap();
+        assert_near(&transform3d::unproject_point(&transform, &projected).unwrap(), point);
+    }
+}
+
+#[test]
+fn test_unproject_point_of_affine_transform() {
+    let scale = Matrix4::new(2.0, 0.0, 0.0, 0.0,
+                             0.0, 4.0, 0.0, 0.0,
+                             0.0, 0.0, 1.0, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+    let transform = matrix::identity().translate(10.0, 20.0, 0.0).mul(&scale);
+    assert_near(&transform3d::unproject_point(&transform, &Point2D(30.0, 100.0)).unwrap(),
+                &Point2D(10.0, 20.0));
+}
+
+#[test]
+fn test_unproject_point_behind_the_viewer() {
+    // What is seen left of the vanishing point, at x = -500 / √3, is the part of the plane
+    // behind the viewer, which can't be seen.
+    let transform = tilted();
+    assert!(transform3d::project_point(&transform, &Point2D(1000.0, 0.0)).is_none());
+    assert!(transform3d::unproject_point(&transform, &Point2D(-400.0, 0.0)).is_none());
+}
+
+#[test]
+fn test_unproject_point_of_flattening_transform() {
+    // A plane seen edge on is a line, so the points on it can't be told apart.
+    let transform = Matrix4::new(0.0, 0.0, 1.0, 0.0,
+                                 0.0, 1.0, 0.0, 0.0,
+                                 1.0, 0.0, 0.0, 0.0,
+                                 0.0, 0.0, 0.0, 1.0);
+    assert!(transform3d::unproject_point(&transform, &Point2D(0.0, 10.0)).is_none());
+}
+
+#[test]
+fn test_to_2d_of_affine_transform_is_exact() {
+    let transform = Matrix4::new(2.0, 0.0, 0.0, 0.0,
+                                 0.0, 3.0, 0.0, 0.0,
+                                 0.0, 0.0, 5.0, 0.0,
+                                 10.0, 20.0, 0.0, 1.0);
+    let transform_2d = transform3d::to_2d(&transform, &Point2D(1000.0, 1000.0)).unwrap();
+    for point in [Point2D(0.0, 0.0), Point2D(7.0, -3.0)].iter() {
+        assert_near(&apply(&transform_2d, point),
+                    &transform3d::project_point(&transform, point).unwrap());
+    }
+}
+
+#[test]
+fn test_to_2d_agrees_with_perspective_around_the_point() {
+    let transform = tilted();
+    let around = Point2D(50.0, 40.0);
+    let transform_2d = transform3d::to_2d(&transform, &around).unwrap();
+    assert_near(&apply(&transform_2d, &around),
+                &transform3d::project_point(&transform, &around).unwrap());
+
+    // Near the point, it strays from the projection by much less than it moves.
+    let nearby = Point2D(50.5, 40.5);
+    let exact = transform3d::project_point(&transform, &nearby).unwrap();
+    let approximate = apply(&transform_2d, &nearby);
+    assert!((exact.x - approximate.x).abs() < 0.01 && (exact.y - approximate.y).abs() < 0.01);
+}
+
+#[test]
+fn test_to_2d_behind_the_viewer() {
+    assert!(transform3d::to_2d(&tilted(), &Point2D(1000.0, 0.0)).is_none());
+}
+
+#[test]
+fn test_to_2d_within_a_rect_partly_behind_the_viewer() {
+    // The centre of the rect is behind the viewer, but its left part isn't, so the transform is
+    // the one around the middle of that part.
+    let transform = tilted();
+    let rect = Rect(Point2D(0.0, 0.0), Size2D(2000.0, 100.0));
+    let transform_2d = transform3d::to_2d_within(&transform, &rect).unwrap();
+    let middle = Point2D(500.0 / 3.0.sqrt(), 50.0);
+    assert_near(&apply(&transform_2d, &middle),
+                &transform3d::project_point(&transform, &middle).unwrap());
+
+    let behind = Rect(Point2D(1000.0, 0.0), Size2D(1000.0, 100.0));
+    assert!(transform3d::to_2d_within(&transform, &behind).is_none());
+}
+
+#[test]
+fn test_perspective_leaves_the_plane_alone() {
+    let transform = transform3d::perspective(300.0, &Point2D(50.0, 50.0));
+    assert_near(&transform3d::project_point(&transform, &Point2D(20.0, 80.0)).unwrap(),
+                &Point2D(20.0, 80.0));
+}
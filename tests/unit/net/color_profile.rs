@@ -0,0 +1,122 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net_traits::image::color_profile::{ColorProfile, png_icc_profile};
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend([(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+                .iter().cloned());
+}
+
+fn write(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    for (destination, &byte) in data[offset..].iter_mut().zip(bytes.iter()) {
+        *destination = byte;
+    }
+}
+
+/// An RGB profile with the sRGB primaries and a linear table for each curve, whose `curv` tags
+/// claim `curve_count` entries.
+fn profile(curve_count: u32) -> Vec<u8> {
+    let mut data = vec![0; 128];
+    write(&mut data, 16, b"RGB ");
+    write(&mut data, 20, b"XYZ ");
+
+    let primaries = [[0x6fa2, 0x38f5, 0x0390], [0x6299, 0xb785, 0x18da], [0x24a0, 0x0f84, 0xb6cf]];
+    let xyz_size = 20;
+    let curve_offset = 132 + 6 * 12 + 3 * xyz_size;
+    push_u32(&mut data, 6);
+    for (index, name) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+        data.extend(name.iter().cloned());
+        push_u32(&mut data, (132 + 6 * 12 + index * xyz_size) as u32);
+        push_u32(&mut data, xyz_size as u32);
+    }
+    for name in [b"rTRC", b"gTRC", b"bTRC"].iter() {
+        data.extend(name.iter().cloned());
+        push_u32(&mut data, curve_offset as u32);
+        push_u32(&mut data, 16);
+    }
+    for primary in primaries.iter() {
+        data.extend(b"XYZ \0\0\0\0".iter().cloned());
+        for &value in primary.iter() {
+            push_u32(&mut data, value);
+        }
+    }
+    data.extend(b"curv\0\0\0\0".iter().cloned());
+    push_u32(&mut data, curve_count);
+    data.extend([0x00, 0x00, 0xff, 0xff].iter().cloned());
+    data
+}
+
+#[test]
+fn test_parse_matrix_trc_profile() {
+    let profile = ColorProfile::parse(&profile(2)).unwrap();
+    // The primaries are sRGB's, so pixels come out about the same as they went in.
+    let mut pixels = vec![0, 0x80, 0xff, 0xff];
+    profile.convert_rgba_to_srgb(&mut pixels);
+    assert_eq!(pixels[3], 0xff);
+    for (&converted, &original) in pixels.iter().zip([0, 0x80, 0xff].iter()) {
+        assert!((converted as i32 - original as i32).abs() <= 2);
+    }
+}
+
+#[test]
+fn test_reject_truncated_profile() {
+    let data = profile(2);
+    assert!(ColorProfile::parse(&data[..data.len() - 2]).is_none());
+    assert!(ColorProfile::parse(&data[..140]).is_none());
+}
+
+#[test]
+fn test_reject_oversized_counts() {
+    // A curve that claims more entries than the profile holds.
+    assert!(ColorProfile::parse(&profile(0xffffffff)).is_none());
+
+    // A tag table that claims more tags than the profile holds.
+    let mut data = profile(2);
+    write(&mut data, 128, &[0xff, 0xff, 0xff, 0xff]);
+    assert!(ColorProfile::parse(&data).is_none());
+}
+
+/// Wraps `data` in a zlib stream of one stored block.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let length = data.len() as u16;
+    let mut stream = vec![0x78, 0x01, 0x01, length as u8, (length >> 8) as u8,
+                          !length as u8, (!length >> 8) as u8];
+    stream.extend(data.iter().cloned());
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    push_u32(&mut stream, b << 16 | a);
+    stream
+}
+
+fn png_with_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for &(kind, data) in [(kind, data), (b"IEND" as &[u8], &[] as &[u8])].iter() {
+        push_u32(&mut png, data.len() as u32);
+        png.extend(kind.iter().cloned());
+        png.extend(data.iter().cloned());
+        // The CRC isn't checked.
+        push_u32(&mut png, 0);
+    }
+    png
+}
+
+#[test]
+fn test_png_icc_profile() {
+    let profile = profile(2);
+    let mut chunk = b"icc\0\0".to_vec();
+    chunk.extend(zlib_stored(&profile).into_iter());
+    assert_eq!(png_icc_profile(&png_with_chunk(b"iCCP", &chunk)), Some(profile));
+
+    assert_eq!(png_icc_profile(&png_with_chunk(b"tEXt", b"icc\0\0")), None);
+    // An unknown compression method.
+    assert_eq!(png_icc_profile(&png_with_chunk(b"iCCP", b"icc\0\x01")), None);
+    // A chunk that is longer than the file.
+    let mut truncated = png_with_chunk(b"iCCP", &chunk);
+    truncated.truncate(20);
+    assert_eq!(png_icc_profile(&truncated), None);
+}
@@ -0,0 +1,176 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use net_traits::image::base::{DecodedImages, Image, LazyImage, load_from_memory};
+use net_traits::image::gif;
+use net_traits::image::jpeg;
+use png::PixelsByColorType;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_image_without_alpha_is_opaque() {
+    let image = Image::new(2, 1, PixelsByColorType::RGB8(vec![0; 6]));
+    assert!(image.is_opaque);
+}
+
+#[test]
+fn test_greyscale_image_is_not_opaque() {
+    // Greyscale images are painted as masks.
+    let image = Image::new(2, 1, PixelsByColorType::K8(vec![0xff; 2]));
+    assert!(!image.is_opaque);
+}
+
+#[test]
+fn test_image_with_alpha_is_opaque_if_every_pixel_is() {
+    let opaque = Image::new(2, 1, PixelsByColorType::RGBA8(vec![0, 0, 0, 0xff, 1, 2, 3, 0xff]));
+    assert!(opaque.is_opaque);
+    let translucent = Image::new(2, 1, PixelsByColorType::RGBA8(vec![0, 0, 0, 0xff, 0, 0, 0, 0]));
+    assert!(!translucent.is_opaque);
+    let greyscale = Image::new(1, 1, PixelsByColorType::KA8(vec![0x40, 0xfe]));
+    assert!(!greyscale.is_opaque);
+}
+
+/// A GIF of two pixels in two frames that loop: both pixels red for 50ms, then the right one blue
+/// for 200ms, from a second image that covers only it.
+static ANIMATED_GIF: &'static [u8] = &[
+    b'G', b'I', b'F', b'8', b'9', b'a', 0x02, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00,
+    // The global color table: red and blue.
+    0xff, 0x00, 0x00, 0x00, 0x00, 0xff,
+    0x21, 0xff, 0x0b, b'N', b'E', b'T', b'S', b'C', b'A', b'P', b'E', b'2', b'.', b'0',
+    0x03, 0x01, 0x00, 0x00, 0x00,
+    0x21, 0xf9, 0x04, 0x00, 0x05, 0x00, 0x00, 0x00,
+    0x2c, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x04, 0x0a, 0x00,
+    0x21, 0xf9, 0x04, 0x00, 0x14, 0x00, 0x00, 0x00,
+    0x2c, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x4c, 0x01, 0x00,
+    0x3b,
+];
+
+#[test]
+fn test_animated_gif_has_frames() {
+    let image = load_from_memory(ANIMATED_GIF).unwrap();
+    let animation = image.animation.unwrap();
+    assert!(animation.repeat);
+    assert_eq!(animation.frames.len(), 2);
+
+    // Frames are premultiplied BGRA, like other decoded images.
+    let red = [0x00, 0x00, 0xff, 0xff];
+    let blue = [0xff, 0x00, 0x00, 0xff];
+    let expected_frames = [(50, [red, red]), (200, [red, blue])];
+    for (frame, &(delay, ref pixels)) in animation.frames.iter().zip(expected_frames.iter()) {
+        assert_eq!(frame.delay, delay);
+        assert_eq!((frame.image.width, frame.image.height), (2, 1));
+        match frame.image.pixels {
+            PixelsByColorType::RGBA8(ref data) => {
+                assert_eq!(&data[..4], &pixels[0][..]);
+                assert_eq!(&data[4..], &pixels[1][..]);
+            }
+            _ => panic!("expected RGBA pixels"),
+        }
+    }
+}
+
+#[test]
+fn test_gif_with_one_frame_is_still() {
+    // The GIF up to its first frame.
+    let mut still_gif = ANIMATED_GIF[..61].to_vec();
+    still_gif.push(0x3b);
+    let image = load_from_memory(&still_gif[..]).unwrap();
+    assert!(image.animation.is_none());
+}
+
+#[test]
+fn test_gif_frames_are_clipped_to_the_screen() {
+    // The second image is as large as a GIF image can be, but only its first pixel is on the
+    // screen, so only its first row is decoded.
+    let mut gif = ANIMATED_GIF.to_vec();
+    for byte in gif[74..78].iter_mut() {
+        *byte = 0xff
+    }
+    let animation = gif::decode_animation(&gif[..]).unwrap();
+    assert_eq!(animation.frames.len(), 2);
+    assert_eq!(&animation.frames[1].pixels[4..], &[0x00, 0x00, 0xff, 0xff][..]);
+}
+
+#[test]
+fn test_gifs_too_large_to_animate_are_still() {
+    let mut huge_screen = ANIMATED_GIF.to_vec();
+    for byte in huge_screen[6..10].iter_mut() {
+        *byte = 0xff
+    }
+    assert!(gif::decode_animation(&huge_screen[..]).is_none());
+
+    // The second frame, over and over.
+    let mut many_frames = ANIMATED_GIF[..69].to_vec();
+    for _ in 0..1000 {
+        many_frames.extend(ANIMATED_GIF[61..84].iter().cloned());
+    }
+    many_frames.push(0x3b);
+    assert!(gif::decode_animation(&many_frames[..]).is_none());
+    assert_eq!(gif::decode_animation(&many_frames[..69 + 998 * 23]).unwrap().frames.len(), 999);
+}
+
+#[test]
+fn test_lazy_images_are_decoded_again_once_dropped_from_the_cache() {
+    // The cache has room for the pixels of one of the images, which are 2×1, and of its two
+    // frames.
+    let cache = Arc::new(Mutex::new(DecodedImages::new(24)));
+    let first = LazyImage::new(ANIMATED_GIF.to_vec(), 2, 1, &cache);
+    let second = LazyImage::new(ANIMATED_GIF.to_vec(), 2, 1, &cache);
+
+    let first_image = first.decode().unwrap();
+    assert_eq!(cache.lock().unwrap().size(), 24);
+    let second_image = second.decode().unwrap();
+    assert_eq!(cache.lock().unwrap().size(), 24);
+
+    // The first image was dropped from the cache, but is still in use, so it is found again
+    // instead of being decoded again.
+    let first_image_again = first.decode().unwrap();
+    assert_eq!(&*first_image as *const Image, &*first_image_again as *const Image);
+    assert_eq!(cache.lock().unwrap().size(), 24);
+
+    // Nothing holds the second image once it is dropped, so it is decoded again.
+    drop(second_image);
+    let second_image = second.decode().unwrap();
+    assert_eq!((second_image.width, second_image.height), (2, 1));
+    assert_eq!(cache.lock().unwrap().size(), 24);
+
+    // Dropped images are forgotten once nothing holds them.
+    assert_eq!(cache.lock().unwrap().evicted_image_count(), 1);
+    drop(first_image);
+    drop(first_image_again);
+    let third = LazyImage::new(ANIMATED_GIF.to_vec(), 2, 1, &cache);
+    third.decode().unwrap();
+    assert_eq!(cache.lock().unwrap().evicted_image_count(), 1);
+}
+
+/// The start of a JPEG file whose EXIF data says that it is turned a quarter clockwise, with the
+/// TIFF data in big-endian byte order.
+static JPEG_WITH_EXIF_ORIENTATION: &'static [u8] = &[
+    0xff, 0xd8,
+    // The EXIF segment, with the TIFF header and a directory of one entry.
+    0xff, 0xe1, 0x00, 0x22, b'E', b'x', b'i', b'f', 0x00, 0x00,
+    b'M', b'M', 0x00, 0x2a, 0x00, 0x00, 0x00, 0x08,
+    0x00, 0x01,
+    0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+    // The start of the image data.
+    0xff, 0xda, 0x00, 0x02,
+];
+
+#[test]
+fn test_jpeg_orientation_is_read_from_exif() {
+    assert_eq!(jpeg::orientation(JPEG_WITH_EXIF_ORIENTATION), Some(6));
+
+    // This photo's EXIF data is in little-endian byte order, and says that it is upright.
+    let test_jpeg = include_bytes!("test.jpeg");
+    assert_eq!(jpeg::orientation(test_jpeg), Some(1));
+    assert_eq!(load_from_memory(test_jpeg).unwrap().exif_orientation, 1);
+}
+
+#[test]
+fn test_jpeg_without_exif_has_no_orientation() {
+    // A JPEG file that goes straight to its image data.
+    let without_exif = [0xff, 0xd8, 0xff, 0xda, 0x00, 0x02];
+    assert_eq!(jpeg::orientation(&without_exif[..]), None);
+}
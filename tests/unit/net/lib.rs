@@ -6,10 +6,13 @@
 
 extern crate net;
 extern crate net_traits;
+extern crate png;
 extern crate url;
 extern crate util;
 
+#[cfg(test)] mod color_profile;
 #[cfg(test)] mod cookie;
 #[cfg(test)] mod data_loader;
+#[cfg(test)] mod image;
 #[cfg(test)] mod mime_classifier;
 #[cfg(test)] mod resource_task;
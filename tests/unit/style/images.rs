@@ -0,0 +1,42 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use cssparser::{Parser, ToCss};
+use style::parser::ParserContext;
+use style::stylesheets::Origin;
+use style::values::specified::{Image, PaintImage};
+use url::Url;
+
+fn parse(css: &str) -> Result<Image, ()> {
+    let url = Url::parse("http://localhost").unwrap();
+    let context = ParserContext::new(Origin::Author, &url);
+    let mut parser = Parser::new(css);
+    Image::parse(&context, &mut parser).and_then(|image| {
+        parser.expect_exhausted().map(|()| image)
+    })
+}
+
+#[test]
+fn test_paint_image() {
+    let image = parse("paint(checkers, 4px, rgb(0, 0, 0) , \"a, b\")").unwrap();
+    assert_eq!(image, Image::Paint(PaintImage {
+        name: "checkers".to_owned(),
+        arguments: vec!["4px".to_owned(), "rgb(0, 0, 0)".to_owned(), "\"a, b\"".to_owned()],
+    }));
+    assert_eq!(image.to_css_string(), "paint(checkers, 4px, rgb(0, 0, 0), \"a, b\")");
+
+    assert_eq!(parse("paint(checkers)").unwrap(), Image::Paint(PaintImage {
+        name: "checkers".to_owned(),
+        arguments: vec![],
+    }));
+}
+
+#[test]
+fn test_malformed_paint_image() {
+    assert!(parse("paint()").is_err());
+    assert!(parse("paint(4px)").is_err());
+    assert!(parse("paint(checkers 4px)").is_err());
+    assert!(parse("paint(checkers, )").is_err());
+    assert!(parse("paint(checkers, 4px,)").is_err());
+}
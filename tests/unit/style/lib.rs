@@ -14,6 +14,7 @@ extern crate url;
 extern crate util;
 
 
+#[cfg(test)] mod images;
 #[cfg(test)] mod stylesheets;
 #[cfg(test)] mod media_queries;
 
@@ -3,7 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::cell::Cell;
-use util::cache::{HashCache, LRUCache};
+use util::cache::{HashCache, LRUCache, SizedLRUCache};
 
 #[test]
 fn test_hashcache() {
@@ -51,3 +51,33 @@ fn test_lru_cache() {
     assert!(cache.find(&3).is_none()); // (4, 1) (no change)
     assert!(cache.find(&4).is_some()); // (1, 4)
 }
+
+#[test]
+fn test_sized_lru_cache() {
+    let mut cache: SizedLRUCache<usize, &str> = SizedLRUCache::new(10);
+    assert!(cache.insert(1, "one", 4).is_empty());
+    assert!(cache.insert(2, "two", 4).is_empty());
+    assert_eq!(cache.size(), 8);
+
+    // Using 1 makes 2 the least recently used, so it is dropped to make room for 3.
+    assert_eq!(cache.find(&1), Some("one"));
+    assert_eq!(cache.insert(3, "three", 4), vec!((2, "two")));
+    assert_eq!(cache.size(), 8);
+    assert!(cache.find(&2).is_none());
+
+    // Replacing an entry replaces its size as well.
+    assert!(cache.insert(3, "three", 2).is_empty());
+    assert_eq!(cache.size(), 6);
+
+    // An entry larger than the bound doesn't stay, and takes everything else with it.
+    assert_eq!(cache.insert(4, "four", 11).len(), 3);
+    assert_eq!(cache.len(), 0);
+    assert_eq!(cache.size(), 0);
+
+    cache.insert(5, "five", 1);
+    cache.insert(6, "six", 1);
+    cache.remove_matching(|key| *key == 5);
+    assert!(!cache.contains_key(&5));
+    assert!(cache.contains_key(&6));
+    assert_eq!(cache.size(), 1);
+}